@@ -0,0 +1,197 @@
+//! Conversions of file metadata and directory entries into the structures
+//! handed out to user space.
+//!
+//! The layout of `struct stat` differs between architectures: x86_64 has its
+//! own, with a 64-bit `st_nlink` placed before `st_mode`, while riscv64,
+//! loongarch64 and aarch64 share the asm-generic one. The offsets below are
+//! those of the kernel ABI, and are checked at compile time against the
+//! bindings in `linux_raw_sys` for the target architecture, so that a
+//! mismatch breaks the build rather than a single architecture at runtime.
+
+use core::{mem::offset_of, time::Duration};
+
+use axfs_ng_vfs::NodeType;
+use linux_raw_sys::general::{STATX_BASIC_STATS, linux_dirent64, stat, statx, statx_timestamp};
+
+use super::Kstat;
+
+macro_rules! check_layout {
+    ($ty:ty, size = $size:expr $(, $field:ident = $offset:expr)* $(,)?) => {
+        const _: () = {
+            assert!(size_of::<$ty>() == $size);
+            $(assert!(offset_of!($ty, $field) == $offset);)*
+        };
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+check_layout!(
+    stat,
+    size = 144,
+    st_dev = 0,
+    st_ino = 8,
+    st_nlink = 16,
+    st_mode = 24,
+    st_uid = 28,
+    st_gid = 32,
+    st_rdev = 40,
+    st_size = 48,
+    st_blksize = 56,
+    st_blocks = 64,
+    st_atime = 72,
+    st_atime_nsec = 80,
+    st_mtime = 88,
+    st_mtime_nsec = 96,
+    st_ctime = 104,
+    st_ctime_nsec = 112,
+);
+
+#[cfg(any(
+    target_arch = "riscv64",
+    target_arch = "loongarch64",
+    target_arch = "aarch64"
+))]
+check_layout!(
+    stat,
+    size = 128,
+    st_dev = 0,
+    st_ino = 8,
+    st_mode = 16,
+    st_nlink = 20,
+    st_uid = 24,
+    st_gid = 28,
+    st_rdev = 32,
+    st_size = 48,
+    st_blksize = 56,
+    st_blocks = 64,
+    st_atime = 72,
+    st_atime_nsec = 80,
+    st_mtime = 88,
+    st_mtime_nsec = 96,
+    st_ctime = 104,
+    st_ctime_nsec = 112,
+);
+
+check_layout!(
+    statx,
+    size = 256,
+    stx_mask = 0,
+    stx_blksize = 4,
+    stx_attributes = 8,
+    stx_nlink = 16,
+    stx_uid = 20,
+    stx_gid = 24,
+    stx_mode = 28,
+    stx_ino = 32,
+    stx_size = 40,
+    stx_blocks = 48,
+    stx_atime = 64,
+    stx_ctime = 96,
+    stx_mtime = 112,
+    stx_rdev_major = 128,
+    stx_rdev_minor = 132,
+    stx_dev_major = 136,
+    stx_dev_minor = 140,
+);
+
+check_layout!(
+    linux_dirent64,
+    size = 24,
+    d_ino = 0,
+    d_off = 8,
+    d_reclen = 16,
+    d_type = 18,
+    d_name = 19,
+);
+
+impl From<Kstat> for stat {
+    fn from(value: Kstat) -> Self {
+        // SAFETY: valid for stat
+        let mut stat: stat = unsafe { core::mem::zeroed() };
+        stat.st_dev = value.dev as _;
+        stat.st_ino = value.ino as _;
+        stat.st_nlink = value.nlink as _;
+        stat.st_mode = value.mode as _;
+        stat.st_uid = value.uid as _;
+        stat.st_gid = value.gid as _;
+        stat.st_size = value.size as _;
+        stat.st_blksize = value.blksize as _;
+        stat.st_blocks = value.blocks as _;
+        stat.st_rdev = value.rdev.0 as _;
+
+        stat.st_atime = value.atime.as_secs() as _;
+        stat.st_atime_nsec = value.atime.subsec_nanos() as _;
+        stat.st_mtime = value.mtime.as_secs() as _;
+        stat.st_mtime_nsec = value.mtime.subsec_nanos() as _;
+        stat.st_ctime = value.ctime.as_secs() as _;
+        stat.st_ctime_nsec = value.ctime.subsec_nanos() as _;
+
+        stat
+    }
+}
+
+impl From<Kstat> for statx {
+    fn from(value: Kstat) -> Self {
+        // SAFETY: valid for statx
+        let mut statx: statx = unsafe { core::mem::zeroed() };
+        statx.stx_mask = STATX_BASIC_STATS;
+        statx.stx_blksize = value.blksize as _;
+        statx.stx_nlink = value.nlink as _;
+        statx.stx_uid = value.uid as _;
+        statx.stx_gid = value.gid as _;
+        statx.stx_mode = value.mode as _;
+        statx.stx_ino = value.ino as _;
+        statx.stx_size = value.size as _;
+        statx.stx_blocks = value.blocks as _;
+        statx.stx_rdev_major = value.rdev.major();
+        statx.stx_rdev_minor = value.rdev.minor();
+
+        fn time_to_statx(time: &Duration) -> statx_timestamp {
+            statx_timestamp {
+                tv_sec: time.as_secs() as _,
+                tv_nsec: time.subsec_nanos() as _,
+                __reserved: 0,
+            }
+        }
+        statx.stx_atime = time_to_statx(&value.atime);
+        statx.stx_ctime = time_to_statx(&value.ctime);
+        statx.stx_mtime = time_to_statx(&value.mtime);
+
+        statx.stx_dev_major = (value.dev >> 32) as _;
+        statx.stx_dev_minor = value.dev as _;
+
+        statx
+    }
+}
+
+/// Writes a `linux_dirent64` record for an entry at the start of `buf`.
+///
+/// Returns the length of the record, padded to the alignment of the
+/// structure, or `None` if it does not fit.
+pub fn write_dirent64(
+    buf: &mut [u8],
+    ino: u64,
+    off: i64,
+    ty: NodeType,
+    name: &[u8],
+) -> Option<usize> {
+    const NAME_OFFSET: usize = offset_of!(linux_dirent64, d_name);
+
+    let len = (NAME_OFFSET + name.len() + 1).next_multiple_of(align_of::<linux_dirent64>());
+    let record = buf.get_mut(..len)?;
+    record.fill(0);
+
+    let mut put = |offset: usize, bytes: &[u8]| {
+        record[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(offset_of!(linux_dirent64, d_ino), &ino.to_ne_bytes());
+    put(offset_of!(linux_dirent64, d_off), &off.to_ne_bytes());
+    put(
+        offset_of!(linux_dirent64, d_reclen),
+        &(len as u16).to_ne_bytes(),
+    );
+    put(offset_of!(linux_dirent64, d_type), &[ty as u8]);
+    put(NAME_OFFSET, name);
+
+    Some(len)
+}
@@ -9,7 +9,7 @@ use axerrno::LinuxError;
 use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
 use axtask::future::Poller;
 
-use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
+use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut, is_nowait};
 
 pub struct EventFd {
     count: AtomicU64,
@@ -102,7 +102,7 @@ impl FileLike for EventFd {
     }
 
     fn nonblocking(&self) -> bool {
-        self.non_blocking.load(Ordering::Acquire)
+        self.non_blocking.load(Ordering::Acquire) || is_nowait()
     }
 
     fn set_nonblocking(&self, non_blocking: bool) -> axio::Result {
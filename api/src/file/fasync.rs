@@ -0,0 +1,146 @@
+//! Signal-driven I/O: `O_ASYNC` and `F_SETOWN`.
+//!
+//! The state belongs to the open file description, which is the
+//! [`FileLike`] shared by duplicated descriptors, so it is kept in a table
+//! keyed by that object rather than in every file type. Once `O_ASYNC` is set,
+//! a kernel task waits on the file and sends `SIGIO` to the owner every time
+//! it is woken up with the file readable or writable, much like Linux does on
+//! every arrival of data.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use axio::IoEvents;
+use axtask::future::{block_on, timeout_opt};
+use kspin::SpinNoIrq;
+use starry_core::task::{send_signal_to_process, send_signal_to_process_group, spawn_background};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use super::FileLike;
+
+/// How often a watcher checks whether its file is still open, as nothing
+/// wakes it up once the file is gone.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Fasync {
+    file: Weak<dyn FileLike>,
+    /// A process if positive, a process group if negative.
+    owner: AtomicI32,
+    enabled: AtomicBool,
+    watching: AtomicBool,
+}
+
+static TABLE: SpinNoIrq<BTreeMap<usize, Arc<Fasync>>> = SpinNoIrq::new(BTreeMap::new());
+
+fn key(file: &Arc<dyn FileLike>) -> usize {
+    Arc::as_ptr(file) as *const () as usize
+}
+
+fn lookup(file: &Arc<dyn FileLike>) -> Option<Arc<Fasync>> {
+    let state = TABLE.lock().get(&key(file)).cloned()?;
+    // The address may have been reused by another file.
+    state
+        .file
+        .upgrade()
+        .is_some_and(|it| Arc::ptr_eq(&it, file))
+        .then_some(state)
+}
+
+fn get_or_insert(file: &Arc<dyn FileLike>) -> Arc<Fasync> {
+    if let Some(state) = lookup(file) {
+        return state;
+    }
+    let state = Arc::new(Fasync {
+        file: Arc::downgrade(file),
+        owner: AtomicI32::new(0),
+        enabled: AtomicBool::new(false),
+        watching: AtomicBool::new(false),
+    });
+    let mut table = TABLE.lock();
+    table.retain(|_, it| it.file.strong_count() > 0);
+    table.insert(key(file), state.clone());
+    state
+}
+
+/// Returns the owner set with `F_SETOWN`: a process if positive, a process
+/// group if negative, and nobody if 0.
+pub fn owner(file: &Arc<dyn FileLike>) -> i32 {
+    lookup(file).map_or(0, |it| it.owner.load(Ordering::Acquire))
+}
+
+/// Sets the owner receiving `SIGIO`, as for `F_SETOWN`.
+pub fn set_owner(file: &Arc<dyn FileLike>, owner: i32) {
+    get_or_insert(file).owner.store(owner, Ordering::Release);
+}
+
+/// Returns whether `O_ASYNC` is set on the file.
+pub fn is_async(file: &Arc<dyn FileLike>) -> bool {
+    lookup(file).is_some_and(|it| it.enabled.load(Ordering::Acquire))
+}
+
+/// Sets or clears `O_ASYNC` on the file.
+pub fn set_async(file: &Arc<dyn FileLike>, enabled: bool) {
+    if !enabled {
+        if let Some(state) = lookup(file) {
+            state.enabled.store(false, Ordering::Release);
+        }
+        return;
+    }
+    let state = get_or_insert(file);
+    state.enabled.store(true, Ordering::Release);
+    if !state.watching.swap(true, Ordering::AcqRel) {
+        spawn_background(move || watch(state), "fasync".into());
+    }
+}
+
+fn watch(state: Arc<Fasync>) {
+    loop {
+        let mut registered = false;
+        let woken = block_on(timeout_opt(
+            poll_fn(|cx| {
+                if registered {
+                    return Poll::Ready(());
+                }
+                if let Some(file) = state.file.upgrade() {
+                    file.register(cx, IoEvents::IN | IoEvents::OUT);
+                }
+                registered = true;
+                Poll::Pending
+            }),
+            Some(RECHECK_INTERVAL),
+        ));
+
+        let Some(file) = state.file.upgrade() else {
+            break;
+        };
+        if !state.enabled.load(Ordering::Acquire) {
+            break;
+        }
+        if woken.is_some() && file.poll().intersects(IoEvents::IN | IoEvents::OUT) {
+            let owner = state.owner.load(Ordering::Acquire);
+            let sig = Some(SignalInfo::new_kernel(Signo::SIGIO));
+            let _ = match owner {
+                0 => Ok(()),
+                pid if pid > 0 => send_signal_to_process(pid as Pid, sig),
+                pgid => send_signal_to_process_group(-pgid as Pid, sig),
+            };
+        }
+    }
+    state.watching.store(false, Ordering::Release);
+    // Set again while stopping.
+    if state.enabled.load(Ordering::Acquire)
+        && state.file.strong_count() > 0
+        && !state.watching.swap(true, Ordering::AcqRel)
+    {
+        spawn_background(move || watch(state), "fasync".into());
+    }
+}
@@ -0,0 +1,157 @@
+//! BSD `flock` locks.
+//!
+//! A lock belongs to the open file description it was taken through, so it is
+//! shared by the descriptors `dup` and `fork` make of it, and goes away when
+//! the last of them is closed.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Poll,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
+use axio::PollSet;
+use axsync::Mutex;
+use axtask::future::try_block_on;
+use lazy_static::lazy_static;
+use linux_raw_sys::general::{LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN};
+
+use super::fs::{NodeKey, node_key};
+
+#[derive(Default)]
+struct LockState {
+    exclusive: Option<u64>,
+    shared: BTreeSet<u64>,
+}
+
+impl LockState {
+    fn is_empty(&self) -> bool {
+        self.exclusive.is_none() && self.shared.is_empty()
+    }
+}
+
+/// Locks of the nodes currently locked.
+static LOCKS: Mutex<BTreeMap<NodeKey, LockState>> = Mutex::new(BTreeMap::new());
+
+lazy_static! {
+    /// Woken whenever a lock is released.
+    static ref POLL_UNLOCK: PollSet = PollSet::new();
+}
+
+static NEXT_OWNER: AtomicU64 = AtomicU64::new(1);
+
+/// The `flock` lock an open file description holds, if any.
+///
+/// It is released when dropped along with the file description.
+pub struct FileLock {
+    owner: u64,
+    held: Mutex<Option<NodeKey>>,
+}
+
+impl FileLock {
+    pub fn new() -> Self {
+        Self {
+            owner: NEXT_OWNER.fetch_add(1, Ordering::Relaxed),
+            held: Mutex::new(None),
+        }
+    }
+
+    fn try_lock(&self, key: NodeKey, exclusive: bool) -> bool {
+        let mut locks = LOCKS.lock();
+        let state = locks.entry(key).or_default();
+        if exclusive {
+            if !state.is_empty() {
+                return false;
+            }
+            state.exclusive = Some(self.owner);
+        } else {
+            if state.exclusive.is_some() {
+                return false;
+            }
+            state.shared.insert(self.owner);
+        }
+        *self.held.lock() = Some(key);
+        true
+    }
+
+    /// Takes a shared or exclusive lock on `loc`, replacing the one already
+    /// held.
+    ///
+    /// As on Linux, converting a lock is not atomic: the old one is released
+    /// before waiting for the new one.
+    fn lock(&self, loc: &Location, exclusive: bool, nonblocking: bool) -> LinuxResult<()> {
+        let key = node_key(loc)?;
+        if *self.held.lock() == Some(key) {
+            let locks = LOCKS.lock();
+            let state = &locks[&key];
+            if exclusive == (state.exclusive == Some(self.owner)) {
+                return Ok(());
+            }
+        }
+        self.unlock();
+
+        if self.try_lock(key, exclusive) {
+            return Ok(());
+        }
+        if nonblocking {
+            return Err(LinuxError::EWOULDBLOCK);
+        }
+        match try_block_on(poll_fn(|cx| {
+            if self.try_lock(key, exclusive) {
+                return Poll::Ready(Ok(()));
+            }
+            POLL_UNLOCK.register(cx.waker());
+            if self.try_lock(key, exclusive) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })) {
+            Ok(Some(())) => Ok(()),
+            Ok(None) => Err(LinuxError::EINTR),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Applies a `flock` operation on `loc`.
+    pub fn flock(&self, loc: &Location, operation: u32) -> LinuxResult<()> {
+        let nonblocking = operation & LOCK_NB != 0;
+        match operation & !LOCK_NB {
+            LOCK_SH => self.lock(loc, false, nonblocking),
+            LOCK_EX => self.lock(loc, true, nonblocking),
+            LOCK_UN => {
+                self.unlock();
+                Ok(())
+            }
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+
+    /// Releases the lock held, if any.
+    fn unlock(&self) {
+        let Some(key) = self.held.lock().take() else {
+            return;
+        };
+        let mut locks = LOCKS.lock();
+        if let Some(state) = locks.get_mut(&key) {
+            if state.exclusive == Some(self.owner) {
+                state.exclusive = None;
+            }
+            state.shared.remove(&self.owner);
+            if state.is_empty() {
+                locks.remove(&key);
+            }
+        }
+        drop(locks);
+        POLL_UNLOCK.wake();
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        self.unlock();
+    }
+}
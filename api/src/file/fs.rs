@@ -1,4 +1,4 @@
-use alloc::{borrow::Cow, string::ToString, sync::Arc};
+use alloc::{borrow::Cow, collections::BTreeSet, string::ToString, sync::Arc, vec};
 use core::{
     any::Any,
     ffi::c_int,
@@ -8,21 +8,45 @@ use core::{
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{Location, Metadata, NodeFlags};
-use axio::{IoEvents, Pollable};
+use axfs_ng::{FileBackend, FileFlags, FsContext};
+use axfs_ng_vfs::{FilesystemOps, Location, Metadata, NodeFlags, NodeType};
+use axhal::time::monotonic_time;
+use axio::{Buf, IoEvents, Pollable, Seek, SeekFrom};
 use axsync::Mutex;
-use axtask::future::Poller;
-use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
-
-use super::{FileLike, Kstat, get_file_like};
-use crate::file::{SealedBuf, SealedBufMut};
+use axtask::{current, future::Poller};
+use kspin::SpinNoIrq;
+use linux_raw_sys::{
+    general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW, RLIMIT_FSIZE},
+    ioctl::{
+        FIBMAP, FIEMAP_EXTENT_LAST, FIEMAP_EXTENT_UNKNOWN, FIEMAP_FLAG_SYNC, FIEMAP_FLAGS_COMPAT,
+        FIFREEZE, FITHAW, FITRIM, FS_IOC_FIEMAP,
+    },
+};
+use starry_core::{
+    resources::RLIM_INFINITY,
+    task::{AsThread, fs_context, send_signal_to_thread},
+    vfs::SimpleFile,
+};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+use starry_vm::{VmMutPtr, VmPtr};
+
+use super::{FileLike, Kstat, flock::FileLock, get_file_like, is_nowait};
+use crate::{
+    file::{SealedBuf, SealedBufMut},
+    io::{TakeBuf, UserBuf, UserBufMut},
+    vfs::{
+        self, freeze,
+        stats::{self, VfsOp, track_io},
+    },
+};
 
 pub fn with_fs<R>(
     dirfd: c_int,
     f: impl FnOnce(&mut FsContext) -> LinuxResult<R>,
 ) -> LinuxResult<R> {
-    let mut fs = FS_CONTEXT.lock();
+    let fs = fs_context();
+    let mut fs = fs.lock();
     if dirfd == AT_FDCWD {
         f(&mut fs)
     } else {
@@ -31,6 +55,22 @@ pub fn with_fs<R>(
     }
 }
 
+/// Returns where the magic link at `path`, like `/proc/self/fd/3`, leads, or
+/// `None` if `path` does not name one, in which case the path it reads as is
+/// to be followed instead.
+///
+/// Only the last component of `path` is taken as a magic link; one further up
+/// is followed as a plain symbolic link.
+pub fn resolve_magic_link(fs: &FsContext, path: &str) -> Option<LinuxResult<Location>> {
+    let mut components = path.trim_end_matches('/').rsplit('/');
+    let name = components.next().unwrap_or_default();
+    if !(matches!(name, "exe" | "cwd" | "root") || components.next() == Some("fd")) {
+        return None;
+    }
+    let link = fs.resolve_no_follow(path).ok()?;
+    link.entry().downcast::<SimpleFile>().ok()?.link_target()
+}
+
 pub enum ResolveAtResult {
     File(Location),
     Other(Arc<dyn FileLike>),
@@ -61,7 +101,8 @@ pub fn resolve_at(dirfd: c_int, path: Option<&str>, flags: u32) -> LinuxResult<R
             let file_like = get_file_like(dirfd)?;
             let f = file_like.clone().into_any();
             Ok(if let Some(file) = f.downcast_ref::<File>() {
-                ResolveAtResult::File(file.inner().backend()?.location().clone())
+                // Also for `O_PATH` files, which have no backend.
+                ResolveAtResult::File(file.inner().location().clone())
             } else if let Some(dir) = f.downcast_ref::<Directory>() {
                 ResolveAtResult::File(dir.inner().clone())
             } else {
@@ -69,12 +110,16 @@ pub fn resolve_at(dirfd: c_int, path: Option<&str>, flags: u32) -> LinuxResult<R
             })
         }
         Some(path) => with_fs(dirfd, |fs| {
-            if flags & AT_SYMLINK_NOFOLLOW != 0 {
+            let start = monotonic_time();
+            let loc = if flags & AT_SYMLINK_NOFOLLOW != 0 {
                 fs.resolve_no_follow(path)
+            } else if let Some(target) = resolve_magic_link(fs, path) {
+                target
             } else {
                 fs.resolve(path)
-            }
-            .map(ResolveAtResult::File)
+            }?;
+            stats::record(&loc, VfsOp::Lookup, 0, start);
+            Ok(ResolveAtResult::File(loc))
         }),
     }
 }
@@ -100,10 +145,113 @@ pub fn metadata_to_kstat(metadata: &Metadata) -> Kstat {
     }
 }
 
+fn file_size_limit() -> u64 {
+    current().as_thread().proc_data.rlim.read()[RLIMIT_FSIZE].current
+}
+
+/// Raises `SIGXFSZ` for going past `RLIMIT_FSIZE`.
+fn file_too_large() -> LinuxError {
+    let tid = current().id().as_u64() as Pid;
+    let _ = send_signal_to_thread(None, tid, Some(SignalInfo::new_kernel(Signo::SIGXFSZ)));
+    LinuxError::EFBIG
+}
+
+/// Returns how many of `len` bytes written to `loc` at `offset` fit in
+/// `RLIMIT_FSIZE`, failing with `EFBIG` if none does.
+///
+/// The pages they cover are then allocated on the in-memory filesystems, so
+/// that filling holes fails with `ENOSPC` past the size limit.
+pub fn limit_write(loc: &Location, offset: u64, len: usize) -> LinuxResult<usize> {
+    if len == 0 || loc.node_type() != NodeType::RegularFile {
+        return Ok(len);
+    }
+    let limit = file_size_limit();
+    let len = if limit == RLIM_INFINITY || offset.saturating_add(len as u64) <= limit {
+        len
+    } else if offset >= limit {
+        return Err(file_too_large());
+    } else {
+        (limit - offset) as usize
+    };
+    vfs::allocate(loc, offset, offset.saturating_add(len as u64))?;
+    Ok(len)
+}
+
+/// Checks that `loc` may be resized to `size` bytes under `RLIMIT_FSIZE`.
+pub fn check_file_size(loc: &Location, size: u64) -> LinuxResult<()> {
+    if size > file_size_limit() && size > loc.len()? {
+        return Err(file_too_large());
+    }
+    Ok(())
+}
+
+/// `struct fiemap`, the header of a `FS_IOC_FIEMAP` request.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Fiemap {
+    fm_start: u64,
+    fm_length: u64,
+    fm_flags: u32,
+    fm_mapped_extents: u32,
+    fm_extent_count: u32,
+    fm_reserved: u32,
+}
+
+/// `struct fiemap_extent`, which follows the header.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct FiemapExtent {
+    fe_logical: u64,
+    fe_physical: u64,
+    fe_length: u64,
+    fe_reserved64: [u64; 2],
+    fe_flags: u32,
+    fe_reserved: [u32; 3],
+}
+
+/// `struct fstrim_range`, the argument of `FITRIM`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FstrimRange {
+    start: u64,
+    len: u64,
+    minlen: u64,
+}
+
+/// Handles `FITRIM`, which discards the free space of the filesystem `loc`
+/// is on and reports how much in `len`.
+fn fitrim(loc: &Location, arg: usize) -> LinuxResult<usize> {
+    let ptr = arg as *mut FstrimRange;
+    // FIXME: AnyBitPattern
+    let mut range = unsafe { ptr.cast_const().vm_read_uninit()?.assume_init() };
+    let _write = freeze::start_write(loc)?;
+    let end = range.start.saturating_add(range.len);
+    range.len = vfs::trim(loc, range.start, end, range.minlen)?;
+    ptr.vm_write(range)?;
+    Ok(0)
+}
+
+/// Write-back errors nobody has been told about yet, by device and inode.
+///
+/// The dirty data of a file may be written back after its last descriptor is
+/// closed, with nobody to report a failure to. The error is kept here and
+/// returned as `EIO` by the next `fsync` of the file, as Linux does.
+static WRITEBACK_ERRORS: SpinNoIrq<BTreeSet<(u64, u64)>> = SpinNoIrq::new(BTreeSet::new());
+
+fn inode_key(loc: &Location) -> (u64, u64) {
+    (loc.mountpoint().device() as u64, loc.entry().inode())
+}
+
+/// Records that writing back `loc` failed.
+pub fn record_writeback_error(loc: &Location) {
+    WRITEBACK_ERRORS.lock().insert(inode_key(loc));
+}
+
 /// File wrapper for `axfs::fops::File`.
 pub struct File {
     inner: axfs_ng::File,
     nonblock: AtomicBool,
+    lock: FileLock,
 }
 
 impl File {
@@ -111,6 +259,7 @@ impl File {
         Self {
             inner,
             nonblock: AtomicBool::new(false),
+            lock: FileLock::new(),
         }
     }
 
@@ -118,12 +267,161 @@ impl File {
         &self.inner
     }
 
+    /// Writes the file back, failing with `EIO` if an earlier write-back of
+    /// it failed unnoticed.
+    pub fn sync(&self, data_only: bool) -> LinuxResult<()> {
+        let result = self.inner.sync(data_only);
+        if WRITEBACK_ERRORS
+            .lock()
+            .remove(&inode_key(self.inner.location()))
+        {
+            return Err(LinuxError::EIO);
+        }
+        result
+    }
+
+    /// Applies a `flock` operation.
+    pub fn flock(&self, operation: u32) -> LinuxResult<()> {
+        self.lock.flock(self.inner.location(), operation)
+    }
+
     fn is_blocking(&self) -> bool {
         self.inner.location().flags().contains(NodeFlags::BLOCKING)
     }
+
+    /// Whether the file is a block device, which is read and written
+    /// without a page cache, so that large transfers may go straight to the
+    /// user pages.
+    fn is_direct_block(&self) -> bool {
+        self.inner.location().node_type() == NodeType::BlockDevice
+            && matches!(self.inner.backend(), Ok(FileBackend::Direct(_)))
+    }
+
+    /// Reads into `dst` at `offset` in place, see
+    /// [`UserBufMut::fill_pinned`]. Returns `None` if the data is to be
+    /// copied instead.
+    pub fn read_pinned_at(
+        &self,
+        dst: &mut UserBufMut,
+        mut offset: u64,
+    ) -> Option<LinuxResult<usize>> {
+        if !self.is_direct_block() {
+            return None;
+        }
+        dst.fill_pinned(|chunk| {
+            let read = self.inner.read_at(&mut &mut *chunk, offset)?;
+            offset += read as u64;
+            Ok(read)
+        })
+    }
+
+    /// Writes `src` at `offset` in place, see [`UserBuf::consume_pinned`].
+    /// Returns `None` if the data is to be copied instead.
+    pub fn write_pinned_at(
+        &self,
+        src: &mut UserBuf,
+        mut offset: u64,
+    ) -> Option<LinuxResult<usize>> {
+        if !self.is_direct_block() {
+            return None;
+        }
+        src.consume_pinned(|chunk| {
+            let written = self.inner.write_at(&mut &*chunk, offset)?;
+            offset += written as u64;
+            Ok(written)
+        })
+    }
+
+    /// Shortens `src` to what may be written, as [`limit_write`] does.
+    fn write_limit(&self, src: &SealedBuf) -> LinuxResult<usize> {
+        let len = src.remaining();
+        if len == 0 {
+            return Ok(len);
+        }
+        let loc = self.inner.location();
+        let offset = if self.inner.access(FileFlags::APPEND).is_ok() {
+            loc.len()?
+        } else {
+            self.inner.seek(SeekFrom::Current(0))?
+        };
+        limit_write(loc, offset, len)
+    }
+
+    /// Handles `FS_IOC_FIEMAP`.
+    ///
+    /// The filesystems do not expose where the data is stored, so extents
+    /// have an unknown location, the way Linux reports data that has not been
+    /// allocated yet. The in-memory filesystems report their holes; on the
+    /// others, the whole file is one extent.
+    fn fiemap(&self, arg: usize) -> LinuxResult<usize> {
+        let ptr = arg as *mut Fiemap;
+        // FIXME: AnyBitPattern
+        let mut fiemap = unsafe { ptr.cast_const().vm_read_uninit()?.assume_init() };
+        let unsupported = fiemap.fm_flags & !FIEMAP_FLAGS_COMPAT;
+        if unsupported != 0 {
+            fiemap.fm_flags = unsupported;
+            ptr.vm_write(fiemap)?;
+            return Err(LinuxError::EBADR);
+        }
+        if fiemap.fm_length == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+
+        if fiemap.fm_flags & FIEMAP_FLAG_SYNC != 0 {
+            self.inner.sync(true)?;
+        }
+        let loc = self.inner.location();
+        let extents = match vfs::extents(loc)? {
+            Some(extents) => extents,
+            None => {
+                let size = loc.len()?;
+                if size > 0 { vec![0..size] } else { vec![] }
+            }
+        };
+        let start = fiemap.fm_start;
+        let end = start.saturating_add(fiemap.fm_length);
+        let out = ptr.wrapping_add(1) as *mut FiemapExtent;
+        let last = extents.len().saturating_sub(1);
+        fiemap.fm_mapped_extents = 0;
+        for (i, range) in extents.iter().enumerate() {
+            if range.end <= start || range.start >= end {
+                continue;
+            }
+            // With no room for extents, they are only counted.
+            if fiemap.fm_extent_count > 0 {
+                if fiemap.fm_mapped_extents == fiemap.fm_extent_count {
+                    break;
+                }
+                let mut flags = FIEMAP_EXTENT_UNKNOWN;
+                if i == last {
+                    flags |= FIEMAP_EXTENT_LAST;
+                }
+                let extent = FiemapExtent {
+                    fe_logical: range.start,
+                    fe_length: range.end - range.start,
+                    fe_flags: flags,
+                    ..Default::default()
+                };
+                out.wrapping_add(fiemap.fm_mapped_extents as usize)
+                    .vm_write(extent)?;
+            }
+            fiemap.fm_mapped_extents += 1;
+        }
+        ptr.vm_write(fiemap)?;
+        Ok(0)
+    }
 }
 
-fn path_for(loc: &Location) -> Cow<'static, str> {
+/// Identifies a node by its filesystem and inode number.
+pub(super) type NodeKey = (usize, u64);
+
+pub(super) fn node_key(loc: &Location) -> LinuxResult<NodeKey> {
+    let fs: &dyn FilesystemOps = &**loc.filesystem();
+    let fs = fs as *const dyn FilesystemOps as *const () as usize;
+    Ok((fs, loc.metadata()?.inode))
+}
+
+pub fn path_for(loc: &Location) -> Cow<'static, str> {
     loc.absolute_path()
         .map_or_else(|_| "<error>".into(), |f| Cow::Owned(f.to_string()))
 }
@@ -131,24 +429,43 @@ fn path_for(loc: &Location) -> Cow<'static, str> {
 impl FileLike for File {
     fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
         let inner = self.inner();
-        if likely(self.is_blocking()) {
-            inner.read(dst)
-        } else {
-            Poller::new(self, IoEvents::IN)
-                .non_blocking(self.nonblocking())
-                .poll(|| inner.read(dst))
+        if self.is_direct_block()
+            && let SealedBufMut::Bytes(bytes) = dst
+            && let Some(read) = bytes.fill_pinned(|chunk| inner.read(&mut &mut *chunk))
+        {
+            return read;
         }
+        track_io(inner.location(), VfsOp::Read, || {
+            if likely(self.is_blocking()) {
+                inner.read(dst)
+            } else {
+                Poller::new(self, IoEvents::IN)
+                    .non_blocking(self.nonblocking())
+                    .poll(|| inner.read(dst))
+            }
+        })
     }
 
     fn write(&self, src: &mut SealedBuf) -> LinuxResult<usize> {
         let inner = self.inner();
-        if likely(self.is_blocking()) {
-            inner.write(src)
-        } else {
-            Poller::new(self, IoEvents::OUT)
-                .non_blocking(self.nonblocking())
-                .poll(|| inner.write(src))
+        let limit = self.write_limit(src)?;
+        if self.is_direct_block()
+            && limit == src.remaining()
+            && let SealedBuf::Bytes(bytes) = src
+            && let Some(written) = bytes.consume_pinned(|chunk| inner.write(&mut &*chunk))
+        {
+            return written;
         }
+        let mut src = TakeBuf::new(src, limit);
+        track_io(inner.location(), VfsOp::Write, || {
+            if likely(self.is_blocking()) {
+                inner.write(&mut src)
+            } else {
+                Poller::new(self, IoEvents::OUT)
+                    .non_blocking(self.nonblocking())
+                    .poll(|| inner.write(&mut src))
+            }
+        })
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
@@ -160,7 +477,15 @@ impl FileLike for File {
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
-        self.inner().backend()?.location().ioctl(cmd, arg)
+        match cmd {
+            FS_IOC_FIEMAP => self.fiemap(arg),
+            // Block numbers are not known either.
+            FIBMAP => Err(LinuxError::EINVAL),
+            FITRIM => fitrim(self.inner().location(), arg),
+            FIFREEZE => freeze::freeze(self.inner().location()).map(|()| 0),
+            FITHAW => freeze::thaw(self.inner().location()).map(|()| 0),
+            _ => self.inner().backend()?.location().ioctl(cmd, arg),
+        }
     }
 
     fn set_nonblocking(&self, flag: bool) -> LinuxResult {
@@ -169,7 +494,7 @@ impl FileLike for File {
     }
 
     fn nonblocking(&self) -> bool {
-        self.nonblock.load(Ordering::Acquire)
+        self.nonblock.load(Ordering::Acquire) || is_nowait()
     }
 
     fn path(&self) -> Cow<str> {
@@ -205,6 +530,7 @@ impl Pollable for File {
 pub struct Directory {
     inner: Location,
     pub offset: Mutex<u64>,
+    lock: FileLock,
 }
 
 impl Directory {
@@ -212,6 +538,7 @@ impl Directory {
         Self {
             inner,
             offset: Mutex::new(0),
+            lock: FileLock::new(),
         }
     }
 
@@ -219,6 +546,11 @@ impl Directory {
     pub fn inner(&self) -> &Location {
         &self.inner
     }
+
+    /// Applies a `flock` operation.
+    pub fn flock(&self, operation: u32) -> LinuxResult<()> {
+        self.lock.flock(&self.inner, operation)
+    }
 }
 
 impl FileLike for Directory {
@@ -238,6 +570,15 @@ impl FileLike for Directory {
         path_for(&self.inner)
     }
 
+    fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
+        match cmd {
+            FITRIM => fitrim(&self.inner, arg),
+            FIFREEZE => freeze::freeze(&self.inner).map(|()| 0),
+            FITHAW => freeze::thaw(&self.inner).map(|()| 0),
+            _ => Err(LinuxError::ENOTTY),
+        }
+    }
+
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
         self
     }
@@ -1,32 +1,52 @@
+mod abi;
 pub mod epoll;
 pub mod event;
+pub mod fasync;
+mod flock;
 mod fs;
+pub mod mqueue;
 mod net;
+mod netlink;
 mod pidfd;
 mod pipe;
-
-use alloc::{borrow::Cow, sync::Arc};
-use core::{any::Any, ffi::c_int, time::Duration};
+pub mod ratelimit;
+
+use alloc::{borrow::Cow, string::ToString, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    ffi::c_int,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, OpenOptions};
-use axfs_ng_vfs::DeviceId;
+use axfs_ng::OpenOptions;
+use axfs_ng_vfs::{DeviceId, NodePermission};
 use axio::{Buf, BufMut, Pollable, Read, Write};
+use axnet::Shutdown;
 use axtask::current;
 use flatten_objects::FlattenObjects;
 use inherit_methods_macro::inherit_methods;
-use linux_raw_sys::general::{RLIMIT_NOFILE, stat, statx, statx_timestamp};
+use linux_raw_sys::general::{FASYNC, O_NONBLOCK, O_RDWR, O_WRONLY, RLIMIT_NOFILE};
 use spin::RwLock;
-use starry_core::{resources::AX_FILE_LIMIT, task::AsThread};
-use starry_vm::{VmBytes, VmBytesMut};
+use starry_core::{
+    resources::AX_FILE_LIMIT,
+    sysctl,
+    task::{AsThread, defer_idle_work, fs_context},
+};
 
 pub use self::{
-    fs::{Directory, File, ResolveAtResult, metadata_to_kstat, resolve_at, with_fs},
+    abi::write_dirent64,
+    fs::{
+        Directory, File, ResolveAtResult, check_file_size, limit_write, metadata_to_kstat,
+        path_for, record_writeback_error, resolve_at, resolve_magic_link, with_fs,
+    },
     net::Socket,
+    netlink::NetlinkSocket,
     pidfd::PidFd,
     pipe::Pipe,
 };
-use crate::io::IoVectorBufIo;
+use crate::io::{IoVectorBufIo, UserBuf, UserBufMut};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Kstat {
@@ -65,69 +85,9 @@ impl Default for Kstat {
     }
 }
 
-impl From<Kstat> for stat {
-    fn from(value: Kstat) -> Self {
-        // SAFETY: valid for stat
-        let mut stat: stat = unsafe { core::mem::zeroed() };
-        stat.st_dev = value.dev as _;
-        stat.st_ino = value.ino as _;
-        stat.st_nlink = value.nlink as _;
-        stat.st_mode = value.mode as _;
-        stat.st_uid = value.uid as _;
-        stat.st_gid = value.gid as _;
-        stat.st_size = value.size as _;
-        stat.st_blksize = value.blksize as _;
-        stat.st_blocks = value.blocks as _;
-        stat.st_rdev = value.rdev.0 as _;
-
-        stat.st_atime = value.atime.as_secs() as _;
-        stat.st_atime_nsec = value.atime.subsec_nanos() as _;
-        stat.st_mtime = value.mtime.as_secs() as _;
-        stat.st_mtime_nsec = value.mtime.subsec_nanos() as _;
-        stat.st_ctime = value.ctime.as_secs() as _;
-        stat.st_ctime_nsec = value.ctime.subsec_nanos() as _;
-
-        stat
-    }
-}
-
-impl From<Kstat> for statx {
-    fn from(value: Kstat) -> Self {
-        // SAFETY: valid for statx
-        let mut statx: statx = unsafe { core::mem::zeroed() };
-        statx.stx_blksize = value.blksize as _;
-        statx.stx_attributes = value.mode as _;
-        statx.stx_nlink = value.nlink as _;
-        statx.stx_uid = value.uid as _;
-        statx.stx_gid = value.gid as _;
-        statx.stx_mode = value.mode as _;
-        statx.stx_ino = value.ino as _;
-        statx.stx_size = value.size as _;
-        statx.stx_blocks = value.blocks as _;
-        statx.stx_rdev_major = value.rdev.major();
-        statx.stx_rdev_minor = value.rdev.minor();
-
-        fn time_to_statx(time: &Duration) -> statx_timestamp {
-            statx_timestamp {
-                tv_sec: time.as_secs() as _,
-                tv_nsec: time.subsec_nanos() as _,
-                __reserved: 0,
-            }
-        }
-        statx.stx_atime = time_to_statx(&value.atime);
-        statx.stx_ctime = time_to_statx(&value.ctime);
-        statx.stx_mtime = time_to_statx(&value.mtime);
-
-        statx.stx_dev_major = (value.dev >> 32) as _;
-        statx.stx_dev_minor = value.dev as _;
-
-        statx
-    }
-}
-
 pub enum SealedBuf<'a> {
     Slice(&'a [u8]),
-    Bytes(VmBytes),
+    Bytes(UserBuf),
     IoVec(IoVectorBufIo),
 }
 
@@ -137,8 +97,8 @@ impl<'a> From<&'a [u8]> for SealedBuf<'a> {
     }
 }
 
-impl<'a> From<VmBytes> for SealedBuf<'a> {
-    fn from(value: VmBytes) -> Self {
+impl<'a> From<UserBuf> for SealedBuf<'a> {
+    fn from(value: UserBuf) -> Self {
         Self::Bytes(value)
     }
 }
@@ -149,6 +109,17 @@ impl<'a> From<IoVectorBufIo> for SealedBuf<'a> {
     }
 }
 
+impl SealedBuf<'_> {
+    /// Whether a user buffer ended early because of a fault.
+    pub fn faulted(&self) -> bool {
+        match self {
+            SealedBuf::Slice(_) => false,
+            SealedBuf::Bytes(bytes) => bytes.faulted(),
+            SealedBuf::IoVec(io_vec) => io_vec.faulted(),
+        }
+    }
+}
+
 #[inherit_methods]
 impl Read for SealedBuf<'_> {
     fn read(&mut self, buf: &mut [u8]) -> LinuxResult<usize> {
@@ -180,7 +151,7 @@ impl Buf for SealedBuf<'_> {
 
 pub enum SealedBufMut<'a> {
     Slice(&'a mut [u8]),
-    Bytes(VmBytesMut),
+    Bytes(UserBufMut),
     IoVec(IoVectorBufIo),
 }
 
@@ -190,8 +161,8 @@ impl<'a> From<&'a mut [u8]> for SealedBufMut<'a> {
     }
 }
 
-impl<'a> From<VmBytesMut> for SealedBufMut<'a> {
-    fn from(value: VmBytesMut) -> Self {
+impl<'a> From<UserBufMut> for SealedBufMut<'a> {
+    fn from(value: UserBufMut) -> Self {
         Self::Bytes(value)
     }
 }
@@ -202,6 +173,17 @@ impl<'a> From<IoVectorBufIo> for SealedBufMut<'a> {
     }
 }
 
+impl SealedBufMut<'_> {
+    /// Whether a user buffer ended early because of a fault.
+    pub fn faulted(&self) -> bool {
+        match self {
+            SealedBufMut::Slice(_) => false,
+            SealedBufMut::Bytes(bytes) => bytes.faulted(),
+            SealedBufMut::IoVec(io_vec) => io_vec.faulted(),
+        }
+    }
+}
+
 impl Write for SealedBufMut<'_> {
     fn write(&mut self, buf: &[u8]) -> LinuxResult<usize> {
         match self {
@@ -250,7 +232,7 @@ pub trait FileLike: Pollable + Send + Sync {
     }
 
     fn nonblocking(&self) -> bool {
-        false
+        is_nowait()
     }
 
     fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
@@ -281,9 +263,43 @@ pub struct FileDescriptor {
     pub cloexec: bool,
 }
 
+/// A file descriptor table.
+///
+/// Copies share the descriptors until either side changes them, which makes
+/// forking a process with many open files cheap. The first change after a
+/// fork pays for the copy instead, once.
+#[derive(Clone)]
+pub struct FdTable(Arc<FlattenObjects<FileDescriptor, AX_FILE_LIMIT>>);
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self(Arc::new(FlattenObjects::new()))
+    }
+}
+
+impl Deref for FdTable {
+    type Target = FlattenObjects<FileDescriptor, AX_FILE_LIMIT>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for FdTable {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
 scope_local::scope_local! {
     /// The current file descriptor table.
-    pub static FD_TABLE: Arc<RwLock<FlattenObjects<FileDescriptor, AX_FILE_LIMIT>>> = Arc::default();
+    pub static FD_TABLE: Arc<RwLock<FdTable>> = Arc::default();
+}
+
+/// Registers the `fs.*` sysctls: `fs.nr_open`, the most files a process can
+/// have open.
+pub fn register_sysctls() {
+    sysctl::register("fs.nr_open", || AX_FILE_LIMIT.to_string());
 }
 
 /// Get a file-like object by `fd`.
@@ -295,6 +311,44 @@ pub fn get_file_like(fd: c_int) -> LinuxResult<Arc<dyn FileLike>> {
         .ok_or(LinuxError::EBADF)
 }
 
+/// Runs `f` with every file treated as nonblocking by the current thread, as
+/// if opened with `O_NONBLOCK`, for `RWF_NOWAIT`.
+pub fn nowait<R>(f: impl FnOnce() -> R) -> R {
+    let curr = current();
+    let thr = curr.as_thread();
+    let outer = thr.set_nowait(true);
+    let result = f();
+    thr.set_nowait(outer);
+    result
+}
+
+/// Whether the current thread treats every file as nonblocking, in
+/// [`nowait`].
+pub fn is_nowait() -> bool {
+    current().try_as_thread().is_some_and(|thr| thr.nowait())
+}
+
+/// Returns the file status flags of `f`, as `F_GETFL` reports them.
+pub fn status_flags(f: &Arc<dyn FileLike>) -> LinuxResult<u32> {
+    let mut ret = 0;
+    if f.nonblocking() {
+        ret |= O_NONBLOCK;
+    }
+    if fasync::is_async(f) {
+        ret |= FASYNC;
+    }
+
+    let perm = NodePermission::from_bits_truncate(f.stat()?.mode as _);
+    if perm.contains(NodePermission::OWNER_WRITE) {
+        if perm.contains(NodePermission::OWNER_READ) {
+            ret |= O_RDWR;
+        } else {
+            ret |= O_WRONLY;
+        }
+    }
+    Ok(ret)
+}
+
 /// Add a file to the file descriptor table.
 pub fn add_file_like(f: Arc<dyn FileLike>, cloexec: bool) -> LinuxResult<c_int> {
     let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
@@ -313,38 +367,83 @@ pub fn close_file_like(fd: c_int) -> LinuxResult {
         .remove(fd as usize)
         .ok_or(LinuxError::EBADF)?;
     debug!("close_file_like <= count: {}", Arc::strong_count(&f.inner));
+    if Arc::strong_count(&f.inner) == 1
+        && let Ok(socket) = f.inner.into_any().downcast::<Socket>()
+        && let Err(err) = socket.linger_on_close()
+    {
+        // The descriptor is closed regardless, as on Linux.
+        warn!("Failed to linger on fd {}: {:?}", fd, err);
+    }
     Ok(())
 }
 
-pub fn add_stdio(fd_table: &mut FlattenObjects<FileDescriptor, AX_FILE_LIMIT>) -> LinuxResult<()> {
+/// Closes every file descriptor of the exiting process.
+///
+/// Nothing happens if the table is still shared with another process. Files
+/// whose last reference goes away here are released eagerly: regular files
+/// have their dirty pages written back and sockets are shut down, so peers
+/// observe EOF right away instead of whenever the process is reaped.
+pub fn close_all_files() {
+    if Arc::strong_count(&FD_TABLE) > 1 {
+        return;
+    }
+    let files = {
+        let mut table = FD_TABLE.write();
+        let ids = table.ids().collect::<Vec<_>>();
+        ids.into_iter()
+            .filter_map(|fd| table.remove(fd))
+            .collect::<Vec<_>>()
+    };
+    for f in files {
+        if Arc::strong_count(&f.inner) > 1 {
+            continue;
+        }
+        let any = f.inner.into_any();
+        if let Ok(file) = any.clone().downcast::<File>() {
+            // Nobody waits for the writeback.
+            defer_idle_work(move || {
+                if let Err(err) = file.inner().sync(true) {
+                    warn!("Failed to flush {}: {:?}", file.path(), err);
+                    record_writeback_error(file.inner().location());
+                }
+            });
+        } else if let Some(socket) = any.downcast_ref::<Socket>() {
+            let _ = socket.linger_on_close();
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Opens the standard streams of init on `/dev/console`.
+///
+/// Without a usable console, they are opened on `/dev/null` instead so that
+/// init still starts with descriptors 0 to 2 taken. None of them is closed on
+/// exec.
+pub fn add_stdio(fd_table: &mut FdTable) -> LinuxResult<()> {
     assert_eq!(fd_table.count(), 0);
-    let cx = FS_CONTEXT.lock();
-    let open = |options: &mut OpenOptions| {
-        LinuxResult::Ok(Arc::new(File::new(
-            options.open(&cx, "/dev/console")?.into_file()?,
-        )))
+    let cx = fs_context();
+    let cx = cx.lock();
+    let open = |path: &str, options: &mut OpenOptions| {
+        LinuxResult::Ok(Arc::new(File::new(options.open(&cx, path)?.into_file()?)))
+    };
+    let open_pair = |path: &str| {
+        let tty_in = open(path, OpenOptions::new().read(true).write(false))?;
+        let tty_out = open(path, OpenOptions::new().read(false).write(true))?;
+        LinuxResult::Ok((tty_in, tty_out))
     };
 
-    let tty_in = open(OpenOptions::new().read(true).write(false))?;
-    let tty_out = open(OpenOptions::new().read(false).write(true))?;
-    fd_table
-        .add(FileDescriptor {
-            inner: tty_in,
-            cloexec: false,
-        })
-        .map_err(|_| LinuxError::EMFILE)?;
-    fd_table
-        .add(FileDescriptor {
-            inner: tty_out.clone(),
-            cloexec: false,
-        })
-        .map_err(|_| LinuxError::EMFILE)?;
-    fd_table
-        .add(FileDescriptor {
-            inner: tty_out,
-            cloexec: false,
-        })
-        .map_err(|_| LinuxError::EMFILE)?;
+    let (tty_in, tty_out) = open_pair("/dev/console").or_else(|err| {
+        warn!("Failed to open /dev/console: {:?}, using /dev/null", err);
+        open_pair("/dev/null")
+    })?;
+    for inner in [tty_in, tty_out.clone(), tty_out] {
+        fd_table
+            .add(FileDescriptor {
+                inner,
+                cloexec: false,
+            })
+            .map_err(|_| LinuxError::EMFILE)?;
+    }
 
     Ok(())
 }
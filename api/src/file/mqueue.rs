@@ -0,0 +1,451 @@
+//! POSIX message queues.
+//!
+//! Queues live in a single table, named without the leading slash, and are
+//! shown by every mount of the mqueue filesystem. An unlinked queue goes away
+//! with its last descriptor.
+
+use alloc::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::TimeValue;
+use axio::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use axtask::{current, future::Poller};
+use linux_raw_sys::general::{NAME_MAX, S_IFREG, SI_MESGQ, sigval};
+use starry_core::task::{AsThread, get_process_data, send_signal_to_process};
+use starry_process::Pid;
+use starry_signal::{SignalInfo, Signo};
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut, is_nowait};
+
+/// The largest priority plus one.
+pub const MQ_PRIO_MAX: u32 = 32768;
+/// Default capacity of a queue created without attributes.
+pub const DEFAULT_MAXMSG: usize = 10;
+/// Default message size of a queue created without attributes.
+pub const DEFAULT_MSGSIZE: usize = 8192;
+/// Limits on the attributes, those Linux enforces even for privileged users.
+const HARD_MAXMSG: usize = 65536;
+const HARD_MSGSIZE: usize = 16 << 20;
+
+/// `struct mq_attr`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MqAttr {
+    pub mq_flags: i64,
+    pub mq_maxmsg: i64,
+    pub mq_msgsize: i64,
+    pub mq_curmsgs: i64,
+    pub reserved: [i64; 4],
+}
+
+/// Who is told about a message arriving at an empty queue, as registered
+/// with `mq_notify`.
+pub struct Notification {
+    pub pid: Pid,
+    /// The signal to send, or `None` for `SIGEV_NONE`.
+    pub signo: Option<Signo>,
+    pub value: sigval,
+}
+
+// SAFETY: `value` is only handed back to the process that registered it.
+unsafe impl Send for Notification {}
+
+struct Message {
+    priority: u32,
+    data: Vec<u8>,
+}
+
+struct Queue {
+    /// Ordered by priority, highest first, then by arrival.
+    messages: VecDeque<Message>,
+    notification: Option<Notification>,
+}
+
+/// A message queue.
+pub struct MessageQueue {
+    maxmsg: usize,
+    msgsize: usize,
+    mode: u32,
+    queue: Mutex<Queue>,
+    /// Receivers waiting for a message, which take precedence over the
+    /// notification.
+    receivers: AtomicUsize,
+    poll_rx: PollSet,
+    poll_tx: PollSet,
+}
+
+static QUEUES: Mutex<BTreeMap<String, Arc<MessageQueue>>> = Mutex::new(BTreeMap::new());
+
+fn check_name(name: &str) -> LinuxResult<()> {
+    if name.is_empty() {
+        Err(LinuxError::ENOENT)
+    } else if name.len() > NAME_MAX as usize {
+        Err(LinuxError::ENAMETOOLONG)
+    } else if name.contains('/') || name == "." || name == ".." {
+        Err(LinuxError::EACCES)
+    } else {
+        Ok(())
+    }
+}
+
+/// Opens the queue `name` for reading and writing as `(readable, writable)`
+/// ask, creating it with `attr` (or the defaults) and `mode` if `create` is
+/// set and it does not exist yet.
+///
+/// An existing queue may only be opened for what its mode allows.
+pub fn open(
+    name: &str,
+    create: bool,
+    exclusive: bool,
+    mode: u32,
+    attr: Option<MqAttr>,
+    (readable, writable): (bool, bool),
+) -> LinuxResult<Arc<MessageQueue>> {
+    check_name(name)?;
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.get(name) {
+        if create && exclusive {
+            return Err(LinuxError::EEXIST);
+        }
+        if (readable && queue.mode & 0o400 == 0) || (writable && queue.mode & 0o200 == 0) {
+            return Err(LinuxError::EACCES);
+        }
+        return Ok(queue.clone());
+    }
+    if !create {
+        return Err(LinuxError::ENOENT);
+    }
+
+    let (maxmsg, msgsize) = match attr {
+        Some(attr) => {
+            if attr.mq_maxmsg <= 0 || attr.mq_msgsize <= 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            (attr.mq_maxmsg as usize, attr.mq_msgsize as usize)
+        }
+        None => (DEFAULT_MAXMSG, DEFAULT_MSGSIZE),
+    };
+    if maxmsg > HARD_MAXMSG || msgsize > HARD_MSGSIZE {
+        return Err(LinuxError::EINVAL);
+    }
+    let queue = Arc::new(MessageQueue {
+        maxmsg,
+        msgsize,
+        mode: mode & 0o777,
+        queue: Mutex::new(Queue {
+            messages: VecDeque::new(),
+            notification: None,
+        }),
+        receivers: AtomicUsize::new(0),
+        poll_rx: PollSet::new(),
+        poll_tx: PollSet::new(),
+    });
+    queues.insert(name.into(), queue.clone());
+    Ok(queue)
+}
+
+/// Removes the queue `name`, which stays usable through open descriptors.
+pub fn unlink(name: &str) -> LinuxResult<()> {
+    check_name(name)?;
+    QUEUES
+        .lock()
+        .remove(name)
+        .map(|_| ())
+        .ok_or(LinuxError::ENOENT)
+}
+
+/// Returns the queue `name`.
+pub fn lookup(name: &str) -> Option<Arc<MessageQueue>> {
+    QUEUES.lock().get(name).cloned()
+}
+
+/// Returns the names of all queues.
+pub fn names() -> Vec<String> {
+    QUEUES.lock().keys().cloned().collect()
+}
+
+impl MessageQueue {
+    /// Returns the attributes, with `mq_flags` left to the caller.
+    pub fn attr(&self) -> MqAttr {
+        MqAttr {
+            mq_maxmsg: self.maxmsg as _,
+            mq_msgsize: self.msgsize as _,
+            mq_curmsgs: self.queue.lock().messages.len() as _,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the size limit of a message.
+    pub fn msgsize(&self) -> usize {
+        self.msgsize
+    }
+
+    /// Returns the permission bits given at creation.
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    /// Returns the line shown by the queue's file in the mqueue filesystem.
+    pub fn status(&self) -> String {
+        let queue = self.queue.lock();
+        let size = queue.messages.iter().map(|it| it.data.len()).sum::<usize>();
+        let (notify, signo, pid) = match &queue.notification {
+            Some(it) => (
+                if it.signo.is_some() { 0 } else { 1 },
+                it.signo.map_or(0, |it| it as u32),
+                it.pid,
+            ),
+            None => (0, 0, 0),
+        };
+        format!("QSIZE:{size:<10} NOTIFY:{notify:<5} SIGNO:{signo:<5} NOTIFY_PID:{pid:<6}\n")
+    }
+
+    /// Registers `notification`, or removes the registration of the calling
+    /// process if it is `None`.
+    pub fn set_notification(&self, notification: Option<Notification>) -> LinuxResult<()> {
+        let pid = current().as_thread().proc_data.proc.pid();
+        let mut queue = self.queue.lock();
+        // A registration outlives neither its process nor its delivery.
+        if queue
+            .notification
+            .as_ref()
+            .is_some_and(|it| get_process_data(it.pid).is_err())
+        {
+            queue.notification = None;
+        }
+        match notification {
+            Some(notification) => {
+                if queue.notification.is_some() {
+                    return Err(LinuxError::EBUSY);
+                }
+                queue.notification = Some(notification);
+            }
+            None => {
+                if queue.notification.as_ref().is_some_and(|it| it.pid == pid) {
+                    queue.notification = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn notify(&self, notification: Notification) {
+        let Some(signo) = notification.signo else {
+            return;
+        };
+        let sender = current().as_thread().proc_data.proc.pid();
+        let mut sig = SignalInfo::new_user(signo, SI_MESGQ, sender);
+        unsafe {
+            sig.0
+                .__bindgen_anon_1
+                .__bindgen_anon_1
+                ._sifields
+                ._rt
+                ._sigval = notification.value;
+        }
+        let _ = send_signal_to_process(notification.pid, Some(sig));
+    }
+
+    fn send(
+        &self,
+        data: &[u8],
+        priority: u32,
+        non_blocking: bool,
+        timeout: Option<TimeValue>,
+    ) -> LinuxResult<()> {
+        if data.len() > self.msgsize {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        if priority >= MQ_PRIO_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        Poller::new(self, IoEvents::OUT)
+            .non_blocking(non_blocking)
+            .timeout(timeout)
+            .poll(|| {
+                let mut queue = self.queue.lock();
+                if queue.messages.len() >= self.maxmsg {
+                    return Err(LinuxError::EAGAIN);
+                }
+                let index = queue
+                    .messages
+                    .iter()
+                    .position(|it| it.priority < priority)
+                    .unwrap_or(queue.messages.len());
+                queue.messages.insert(
+                    index,
+                    Message {
+                        priority,
+                        data: data.to_vec(),
+                    },
+                );
+                let notification =
+                    if queue.messages.len() == 1 && self.receivers.load(Ordering::Acquire) == 0 {
+                        queue.notification.take()
+                    } else {
+                        None
+                    };
+                drop(queue);
+                self.poll_rx.wake();
+                if let Some(notification) = notification {
+                    self.notify(notification);
+                }
+                Ok(())
+            })
+    }
+
+    fn receive(
+        &self,
+        buf: &mut [u8],
+        non_blocking: bool,
+        timeout: Option<TimeValue>,
+    ) -> LinuxResult<(usize, u32)> {
+        if buf.len() < self.msgsize {
+            return Err(LinuxError::EMSGSIZE);
+        }
+        let waiting = usize::from(!non_blocking);
+        self.receivers.fetch_add(waiting, Ordering::AcqRel);
+        let result = Poller::new(self, IoEvents::IN)
+            .non_blocking(non_blocking)
+            .timeout(timeout)
+            .poll(|| {
+                let message = self
+                    .queue
+                    .lock()
+                    .messages
+                    .pop_front()
+                    .ok_or(LinuxError::EAGAIN)?;
+                self.poll_tx.wake();
+                buf[..message.data.len()].copy_from_slice(&message.data);
+                Ok((message.data.len(), message.priority))
+            });
+        self.receivers.fetch_sub(waiting, Ordering::AcqRel);
+        result
+    }
+}
+
+impl Pollable for MessageQueue {
+    fn poll(&self) -> IoEvents {
+        let len = self.queue.lock().messages.len();
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, len > 0);
+        events.set(IoEvents::OUT, len < self.maxmsg);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+        if events.contains(IoEvents::OUT) {
+            self.poll_tx.register(context.waker());
+        }
+    }
+}
+
+/// A message queue descriptor, as returned by `mq_open`.
+pub struct MessageQueueFile {
+    name: String,
+    queue: Arc<MessageQueue>,
+    readable: bool,
+    writable: bool,
+    non_blocking: AtomicBool,
+}
+
+impl MessageQueueFile {
+    pub fn new(name: &str, queue: Arc<MessageQueue>, readable: bool, writable: bool) -> Self {
+        Self {
+            name: name.into(),
+            queue,
+            readable,
+            writable,
+            non_blocking: AtomicBool::new(false),
+        }
+    }
+
+    pub fn queue(&self) -> &Arc<MessageQueue> {
+        &self.queue
+    }
+
+    /// Sends a message, waiting for room until `timeout` unless the
+    /// descriptor is non-blocking.
+    pub fn send(&self, data: &[u8], priority: u32, timeout: Option<TimeValue>) -> LinuxResult<()> {
+        if !self.writable {
+            return Err(LinuxError::EBADF);
+        }
+        self.queue.send(data, priority, self.nonblocking(), timeout)
+    }
+
+    /// Receives the oldest message of the highest priority into `buf`,
+    /// returning its length and priority.
+    pub fn receive(&self, buf: &mut [u8], timeout: Option<TimeValue>) -> LinuxResult<(usize, u32)> {
+        if !self.readable {
+            return Err(LinuxError::EBADF);
+        }
+        self.queue.receive(buf, self.nonblocking(), timeout)
+    }
+}
+
+impl FileLike for MessageQueueFile {
+    fn read(&self, _dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFREG | self.queue.mode(),
+            ..Default::default()
+        })
+    }
+
+    fn path(&self) -> Cow<str> {
+        format!("/dev/mqueue/{}", self.name).into()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.non_blocking.store(nonblocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire) || is_nowait()
+    }
+}
+
+impl Pollable for MessageQueueFile {
+    fn poll(&self) -> IoEvents {
+        let mut events = self.queue.poll();
+        if !self.readable {
+            events.remove(IoEvents::IN);
+        }
+        if !self.writable {
+            events.remove(IoEvents::OUT);
+        }
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        self.queue.register(context, events);
+    }
+}
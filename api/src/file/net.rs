@@ -1,24 +1,322 @@
+mod loopback;
+
 use alloc::{borrow::Cow, format, sync::Arc};
-use core::{ffi::c_int, ops::Deref, task::Context};
+use core::{
+    ffi::c_int,
+    ops::Deref,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::Context,
+    time::Duration,
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axio::{IoEvents, Pollable};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable};
 use axnet::{
-    SocketOps,
+    RecvFlags, RecvOptions, SendOptions, Shutdown, SocketAddrEx, SocketOps,
     options::{Configurable, GetSocketOption, SetSocketOption},
 };
+use axsync::Mutex;
+use axtask::future::Poller;
+use kspin::SpinNoIrq;
 use linux_raw_sys::general::S_IFSOCK;
 
-use super::{FileLike, Kstat};
+use self::loopback::{FastPath, Stream};
+use super::{FileLike, Kstat, is_nowait};
 use crate::file::{SealedBuf, SealedBufMut, get_file_like};
 
-pub struct Socket(pub axnet::Socket);
+pub struct Socket {
+    inner: axnet::Socket,
+    /// `SO_LINGER`: how long closing the socket waits for the peer, if at all.
+    linger: SpinNoIrq<Option<Duration>>,
+    /// `TCP_QUICKACK`. Only the peer would notice it, so it is just kept.
+    quick_ack: AtomicBool,
+    /// Whether a non-blocking `connect` is still in progress.
+    connecting: AtomicBool,
+    /// The pending error reported by `SO_ERROR`, e.g. of a failed `connect`.
+    error: SpinNoIrq<Option<LinuxError>>,
+    /// The loopback fast path, once the connection is paired. See
+    /// [`loopback`].
+    fast: SpinNoIrq<Option<FastPath>>,
+    /// Whether the socket is a loopback client that may still be paired.
+    pairing: AtomicBool,
+    /// How much was sent through the stack, and received through it since
+    /// the pairing, which tells where the fast path takes over.
+    stack_sent: AtomicU64,
+    stack_received: AtomicU64,
+    /// Held while sending or receiving, so that the connection is not paired
+    /// in the middle of a transfer through the stack.
+    sending: Mutex<()>,
+    receiving: Mutex<()>,
+    /// Woken when the connection is paired, for those waiting on the stack.
+    poll_pair: PollSet,
+}
+
+impl Socket {
+    pub fn new(inner: axnet::Socket) -> Self {
+        Self {
+            inner,
+            linger: SpinNoIrq::new(None),
+            quick_ack: AtomicBool::new(false),
+            connecting: AtomicBool::new(false),
+            error: SpinNoIrq::new(None),
+            fast: SpinNoIrq::new(None),
+            pairing: AtomicBool::new(false),
+            stack_sent: AtomicU64::new(0),
+            stack_received: AtomicU64::new(0),
+            sending: Mutex::new(()),
+            receiving: Mutex::new(()),
+            poll_pair: PollSet::new(),
+        }
+    }
+
+    /// Notes that the socket is about to connect to `addr`, which may let
+    /// the connection take the loopback fast path once it is accepted.
+    pub fn prepare_connect(self: &Arc<Self>, addr: &SocketAddrEx) {
+        loopback::connecting(self, addr);
+    }
+
+    /// Pairs a socket just returned by `accept` with its client for the
+    /// loopback fast path, if the client is a socket of this kernel.
+    pub fn pair_loopback(&self) {
+        loopback::pair(self);
+    }
+
+    /// Connects to `addr`.
+    ///
+    /// A non-blocking socket fails with `EINPROGRESS` and connects in the
+    /// background. It turns writable once done, and `SO_ERROR` or another
+    /// `connect` then tell whether it succeeded.
+    pub fn connect(&self, addr: SocketAddrEx) -> LinuxResult<()> {
+        if self.connecting.load(Ordering::Acquire) {
+            return match self.poll_connect() {
+                None => Err(LinuxError::EALREADY),
+                Some(Ok(())) => Err(LinuxError::EISCONN),
+                Some(Err(err)) => {
+                    self.error.lock().take();
+                    Err(err)
+                }
+            };
+        }
+        match self.inner.connect(addr) {
+            Err(LinuxError::EAGAIN | LinuxError::EINPROGRESS) => {
+                self.connecting.store(true, Ordering::Release);
+                Err(LinuxError::EINPROGRESS)
+            }
+            result => result,
+        }
+    }
+
+    /// Checks on a connection set up in the background, returning `None`
+    /// while it is still in progress.
+    fn poll_connect(&self) -> Option<LinuxResult<()>> {
+        if !self.connecting.load(Ordering::Acquire) {
+            return None;
+        }
+        let events = self.inner.poll();
+        let result = if events.contains(IoEvents::ERR)
+            || (events.contains(IoEvents::HUP) && !events.contains(IoEvents::OUT))
+        {
+            let mut code = 0;
+            let _ = self.inner.get_option(GetSocketOption::Error(&mut code));
+            let err = LinuxError::try_from(code).unwrap_or(LinuxError::ECONNREFUSED);
+            *self.error.lock() = Some(err);
+            Err(err)
+        } else if events.contains(IoEvents::OUT) {
+            Ok(())
+        } else {
+            return None;
+        };
+        self.connecting.store(false, Ordering::Release);
+        Some(result)
+    }
+
+    /// Returns and clears the pending error, for `SO_ERROR`.
+    pub fn take_error(&self) -> LinuxResult<i32> {
+        self.poll_connect();
+        if let Some(err) = self.error.lock().take() {
+            return Ok(err.code());
+        }
+        let mut code = 0;
+        self.inner.get_option(GetSocketOption::Error(&mut code))?;
+        Ok(code)
+    }
+
+    fn fast(&self) -> Option<FastPath> {
+        self.fast.lock().clone()
+    }
+
+    /// Returns the timeout set with `option`, which is zero for none.
+    fn timeout(
+        &self,
+        option: impl FnOnce(&mut Duration) -> GetSocketOption<'_>,
+    ) -> Option<Duration> {
+        let mut timeout = Duration::ZERO;
+        let _ = self.inner.get_option(option(&mut timeout));
+        (!timeout.is_zero()).then_some(timeout)
+    }
+
+    /// Sends data as [`SocketOps::send`] does, except that `SO_SNDTIMEO`
+    /// running out fails with `EAGAIN` as on Linux.
+    pub fn send(&self, src: &mut impl Buf, options: SendOptions) -> LinuxResult<usize> {
+        let _sending = self.sending.lock();
+        if let Some(fast) = self.fast() {
+            return self.send_fast(&fast.tx, src);
+        }
+        let sent = self.inner.send(src, options).map_err(timeout_to_again)?;
+        self.stack_sent.fetch_add(sent as u64, Ordering::AcqRel);
+        Ok(sent)
+    }
+
+    /// Receives data as [`SocketOps::recv`] does, except that `SO_RCVTIMEO`
+    /// running out fails with `EAGAIN` as on Linux.
+    pub fn recv(&self, dst: &mut impl BufMut, options: RecvOptions) -> LinuxResult<usize> {
+        if self.pairing.load(Ordering::Acquire) {
+            // Wait here rather than in the stack, where a reader would stop
+            // the pairing and then miss what comes through the fast path.
+            Poller::new(self, IoEvents::IN)
+                .non_blocking(self.nonblocking())
+                .timeout(self.timeout(GetSocketOption::ReceiveTimeout))
+                .poll(|| {
+                    let ready = IoEvents::IN | IoEvents::HUP | IoEvents::ERR;
+                    if self.fast().is_some() || self.inner.poll().intersects(ready) {
+                        Ok(())
+                    } else {
+                        Err(LinuxError::EAGAIN)
+                    }
+                })
+                .map_err(timeout_to_again)?;
+        }
+        let _receiving = self.receiving.lock();
+        match self.fast() {
+            Some(fast) => self.recv_fast(&fast.rx, dst, options),
+            None => self.inner.recv(dst, options).map_err(timeout_to_again),
+        }
+    }
+
+    fn send_fast(&self, tx: &Stream, src: &mut impl Buf) -> LinuxResult<usize> {
+        let non_blocking = self.nonblocking();
+        let mut total = 0;
+        let result = Poller::new(self, IoEvents::OUT)
+            .non_blocking(non_blocking)
+            .timeout(self.timeout(GetSocketOption::SendTimeout))
+            .poll(|| {
+                if src.remaining() == 0 {
+                    return Ok(());
+                }
+                total += tx.write(&mut *src)?;
+                if src.remaining() == 0 || non_blocking {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            });
+        match result {
+            Ok(()) => Ok(total),
+            Err(_) if total > 0 => Ok(total),
+            Err(err) => Err(timeout_to_again(err)),
+        }
+    }
+
+    fn recv_fast(
+        &self,
+        rx: &Stream,
+        dst: &mut impl BufMut,
+        options: RecvOptions,
+    ) -> LinuxResult<usize> {
+        let peek = options.flags.contains(RecvFlags::PEEK);
+        // What the peer sent before the pairing comes first.
+        if self.stack_received.load(Ordering::Acquire) < rx.boundary() {
+            let received = self.inner.recv(dst, options).map_err(timeout_to_again)?;
+            if !peek {
+                self.stack_received
+                    .fetch_add(received as u64, Ordering::AcqRel);
+            }
+            return Ok(received);
+        }
+        if let Some(from) = options.from {
+            *from = self.inner.peer_addr()?;
+        }
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .timeout(self.timeout(GetSocketOption::ReceiveTimeout))
+            .poll(|| rx.read(&mut *dst, peek))
+            .map_err(timeout_to_again)
+    }
+
+    /// Shuts down the connection as [`SocketOps::shutdown`] does, on the
+    /// loopback fast path too.
+    pub fn shutdown(&self, how: Shutdown) -> LinuxResult<()> {
+        if let Some(fast) = self.fast() {
+            if matches!(how, Shutdown::Write | Shutdown::Both) {
+                fast.tx.close();
+            }
+            if matches!(how, Shutdown::Read | Shutdown::Both) {
+                fast.rx.close();
+            }
+        }
+        self.inner.shutdown(how)
+    }
+
+    /// Returns the `SO_LINGER` timeout, or `None` if lingering is off.
+    pub fn linger(&self) -> Option<Duration> {
+        *self.linger.lock()
+    }
+
+    pub fn set_linger(&self, linger: Option<Duration>) {
+        *self.linger.lock() = linger;
+    }
+
+    pub fn quick_ack(&self) -> bool {
+        self.quick_ack.load(Ordering::Relaxed)
+    }
+
+    pub fn set_quick_ack(&self, quick_ack: bool) {
+        self.quick_ack.store(quick_ack, Ordering::Relaxed);
+    }
+
+    /// Closes the socket on its last `close`, honoring `SO_LINGER`.
+    ///
+    /// With a zero timeout the connection is torn down at once. Otherwise the
+    /// end of the stream is sent and the caller waits, up to the timeout,
+    /// until the peer has read everything and closed its side too. The stack
+    /// does not tell when the peer acknowledges the data, so this is the
+    /// closest sign that it got it.
+    pub fn linger_on_close(&self) -> LinuxResult<()> {
+        let Some(linger) = self.linger() else {
+            return Ok(());
+        };
+        if linger.is_zero() {
+            return self.shutdown(Shutdown::Both);
+        }
+        self.shutdown(Shutdown::Write)?;
+        let result = Poller::new(self, IoEvents::IN | IoEvents::HUP)
+            .timeout(Some(linger))
+            .poll(|| {
+                if self.poll().contains(IoEvents::HUP) {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            });
+        match result {
+            Err(LinuxError::ETIMEDOUT) => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn timeout_to_again(err: LinuxError) -> LinuxError {
+    match err {
+        LinuxError::ETIMEDOUT => LinuxError::EAGAIN,
+        err => err,
+    }
+}
 
 impl Deref for Socket {
     type Target = axnet::Socket;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.inner
     }
 }
 
@@ -48,14 +346,18 @@ impl FileLike for Socket {
         let mut result = false;
         self.get_option(GetSocketOption::NonBlocking(&mut result))
             .unwrap();
-        result
+        result || is_nowait()
     }
 
     fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult<()> {
-        self.0
+        self.inner
             .set_option(SetSocketOption::NonBlocking(&nonblocking))
     }
 
+    fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
+        crate::netif::ioctl(cmd, arg)
+    }
+
     fn path(&self) -> Cow<str> {
         format!("socket:[{}]", self as *const _ as usize).into()
     }
@@ -70,12 +372,42 @@ impl FileLike for Socket {
             .map_err(|_| LinuxError::ENOTSOCK)
     }
 }
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Some(fast) = self.fast.lock().take() {
+            fast.tx.close();
+            fast.rx.abandon();
+        }
+    }
+}
+
 impl Pollable for Socket {
     fn poll(&self) -> IoEvents {
-        self.0.poll()
+        if let Some(fast) = self.fast() {
+            let mut events = fast.rx.poll_read() | fast.tx.poll_write();
+            if self.stack_received.load(Ordering::Acquire) < fast.rx.boundary() {
+                events |= self.inner.poll() & IoEvents::IN;
+            }
+            events.set(IoEvents::HUP, fast.rx.is_closed() && fast.tx.is_closed());
+            return events;
+        }
+        let mut events = self.inner.poll();
+        self.poll_connect();
+        if self.error.lock().is_some() {
+            // A failed connection wakes up whoever waits to write.
+            events |= IoEvents::ERR | IoEvents::OUT;
+        }
+        events
     }
 
     fn register(&self, context: &mut Context<'_>, events: IoEvents) {
-        self.0.register(context, events);
+        self.inner.register(context, events);
+        self.poll_pair.register(context.waker());
+        // Both directions, as either end closing may be a hangup.
+        if let Some(fast) = self.fast() {
+            fast.rx.register_read(context.waker());
+            fast.tx.register_write(context.waker());
+        }
     }
 }
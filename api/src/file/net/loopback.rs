@@ -0,0 +1,275 @@
+//! A fast path for TCP connections over the loopback interface.
+//!
+//! When both ends of a connection to a loopback address are sockets of this
+//! kernel, the stream need not go through the network stack: what one end
+//! sends is copied straight into a buffer the other end reads from. The
+//! stack still sets the connection up, keeps its addresses and options and
+//! tears it down; only the data bypasses it.
+//!
+//! The ends are paired when the server accepts the connection. What the
+//! client sent through the stack before that is read first, so the stream
+//! keeps its order. A client that is in the middle of a transfer through the
+//! stack at that moment stays on the stack.
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    task::Waker,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::{Buf, BufMut, IoEvents, PollSet};
+use axnet::{
+    SocketAddrEx, SocketOps,
+    options::{Configurable, GetSocketOption},
+};
+use axsync::Mutex;
+use ringbuf::{
+    HeapRb,
+    traits::{Consumer, Observer, Producer},
+};
+
+use super::Socket;
+
+/// The bounds of the buffer of a direction, which is as large as the receive
+/// buffer of its reader.
+const MIN_BUFFER_SIZE: usize = 4096;
+const MAX_BUFFER_SIZE: usize = 4 << 20;
+
+/// One direction of a paired connection.
+pub struct Stream {
+    buffer: Mutex<HeapRb<u8>>,
+    /// How much the writer had sent through the stack before the pairing,
+    /// which the reader takes from there first.
+    boundary: u64,
+    /// The writer sends no more, which reads as the end of the stream once
+    /// the buffer is drained.
+    closed: AtomicBool,
+    /// The reader is gone, which makes writing fail with `EPIPE`.
+    abandoned: AtomicBool,
+    poll_rx: PollSet,
+    poll_tx: PollSet,
+}
+
+impl Stream {
+    fn new(capacity: usize, boundary: u64) -> Self {
+        Self {
+            buffer: Mutex::new(HeapRb::new(capacity)),
+            boundary,
+            closed: AtomicBool::new(false),
+            abandoned: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+            poll_tx: PollSet::new(),
+        }
+    }
+
+    /// How much the reader is to receive through the stack before reading
+    /// from the buffer.
+    pub fn boundary(&self) -> u64 {
+        self.boundary
+    }
+
+    /// Reads into `dst`, leaving the data in the buffer if `peek` is set.
+    ///
+    /// Fails with `EAGAIN` while there is nothing to read and the writer may
+    /// still send something.
+    pub fn read(&self, dst: &mut impl BufMut, peek: bool) -> LinuxResult<usize> {
+        if dst.remaining_mut() == 0 {
+            return Ok(0);
+        }
+        let mut buffer = self.buffer.lock();
+        let read = {
+            let (left, right) = buffer.as_slices();
+            let mut taken = 0;
+            dst.fill(|chunk| {
+                let copied = copy_out(left, right, taken, chunk);
+                taken += copied;
+                Ok(copied)
+            })?
+        };
+        if read > 0 {
+            if !peek {
+                // SAFETY: `read` bytes were just copied out of the buffer.
+                unsafe { buffer.advance_read_index(read) };
+                drop(buffer);
+                self.poll_tx.wake();
+            }
+            Ok(read)
+        } else if self.closed.load(Ordering::Acquire) {
+            Ok(0)
+        } else {
+            Err(LinuxError::EAGAIN)
+        }
+    }
+
+    /// Writes as much of `src` as there is room for.
+    ///
+    /// Fails with `EPIPE` once the reader is gone, and with `EAGAIN` while
+    /// the buffer is full.
+    pub fn write(&self, src: &mut impl Buf) -> LinuxResult<usize> {
+        if self.abandoned.load(Ordering::Acquire) {
+            return Err(LinuxError::EPIPE);
+        }
+        let mut buffer = self.buffer.lock();
+        let written = src.consume(|chunk| Ok(buffer.push_slice(chunk)))?;
+        drop(buffer);
+        if written > 0 {
+            self.poll_rx.wake();
+            Ok(written)
+        } else {
+            Err(LinuxError::EAGAIN)
+        }
+    }
+
+    /// Ends the stream, for `SHUT_WR` or the writer going away.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.poll_rx.wake();
+    }
+
+    /// Drops the reader, for it going away.
+    pub fn abandon(&self) {
+        self.abandoned.store(true, Ordering::Release);
+        self.poll_tx.wake();
+    }
+
+    /// Whether the stream is closed, i.e. the writer sends no more.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// The events of reading from the stream.
+    pub fn poll_read(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(
+            IoEvents::IN,
+            self.buffer.lock().occupied_len() > 0 || self.is_closed(),
+        );
+        events
+    }
+
+    /// The events of writing to the stream.
+    pub fn poll_write(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(
+            IoEvents::OUT,
+            self.buffer.lock().vacant_len() > 0 || self.abandoned.load(Ordering::Acquire),
+        );
+        events
+    }
+
+    pub fn register_read(&self, waker: &Waker) {
+        self.poll_rx.register(waker);
+    }
+
+    pub fn register_write(&self, waker: &Waker) {
+        self.poll_tx.register(waker);
+    }
+}
+
+/// Copies what follows the first `skip` bytes of `left` and then `right`
+/// into `chunk`, returning how much.
+fn copy_out(left: &[u8], right: &[u8], skip: usize, chunk: &mut [u8]) -> usize {
+    let (first, second) = match left.get(skip..) {
+        Some(rest) => (rest, right),
+        None => (&[][..], right.get(skip - left.len()..).unwrap_or_default()),
+    };
+    let len = chunk.len().min(first.len());
+    chunk[..len].copy_from_slice(&first[..len]);
+    let more = (chunk.len() - len).min(second.len());
+    chunk[len..len + more].copy_from_slice(&second[..more]);
+    len + more
+}
+
+/// The two directions of a paired connection, as one end sees them.
+#[derive(Clone)]
+pub struct FastPath {
+    pub tx: Arc<Stream>,
+    pub rx: Arc<Stream>,
+}
+
+/// Clients connecting to a loopback address, whose connections may not have
+/// been accepted yet.
+static CONNECTING: Mutex<Vec<Weak<Socket>>> = Mutex::new(Vec::new());
+
+fn is_loopback(addr: &SocketAddrEx) -> bool {
+    matches!(addr, SocketAddrEx::Ip(addr) if addr.ip().is_loopback())
+}
+
+fn same_addr(a: &SocketAddrEx, b: &SocketAddrEx) -> bool {
+    matches!((a, b), (SocketAddrEx::Ip(a), SocketAddrEx::Ip(b)) if a == b)
+}
+
+/// Notes that `socket` is connecting to `addr`, so that [`pair`] can find it
+/// once the server accepts the connection.
+///
+/// Until then, a blocking `recv` on it waits outside the stack, so that it
+/// does not keep the connection from being paired.
+pub fn connecting(socket: &Arc<Socket>, addr: &SocketAddrEx) {
+    let inner: &axnet::Socket = socket;
+    if !matches!(inner, axnet::Socket::Tcp(_)) || !is_loopback(addr) {
+        return;
+    }
+    socket.pairing.store(true, Ordering::Release);
+    let socket = Arc::downgrade(socket);
+    let mut connecting = CONNECTING.lock();
+    connecting.retain(|it| it.strong_count() > 0);
+    if !connecting.iter().any(|it| it.ptr_eq(&socket)) {
+        connecting.push(socket);
+    }
+}
+
+/// Returns the size of the buffer that `reader` receives through.
+fn buffer_size(reader: &Socket) -> usize {
+    let mut size = 0;
+    let _ = reader.get_option(GetSocketOption::ReceiveBuffer(&mut size));
+    size.clamp(MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)
+}
+
+/// Pairs `accepted`, just accepted by a listening socket, with its client if
+/// that is a socket of this kernel.
+pub fn pair(accepted: &Socket) {
+    let (Ok(local), Ok(peer)) = (accepted.local_addr(), accepted.peer_addr()) else {
+        return;
+    };
+    if !is_loopback(&local) {
+        return;
+    }
+    let client = {
+        let mut connecting = CONNECTING.lock();
+        connecting.retain(|it| it.strong_count() > 0);
+        let index = connecting.iter().position(|it| {
+            it.upgrade().is_some_and(|client| {
+                let (Ok(client_local), Ok(client_peer)) = (client.local_addr(), client.peer_addr())
+                else {
+                    return false;
+                };
+                same_addr(&client_local, &peer) && same_addr(&client_peer, &local)
+            })
+        });
+        match index.and_then(|index| connecting.swap_remove(index).upgrade()) {
+            Some(client) => client,
+            None => return,
+        }
+    };
+
+    let Some(_sending) = client.sending.try_lock() else {
+        return;
+    };
+    let Some(_receiving) = client.receiving.try_lock() else {
+        return;
+    };
+    let boundary = client.stack_sent.load(Ordering::Acquire);
+    let up = Arc::new(Stream::new(buffer_size(accepted), boundary));
+    let down = Arc::new(Stream::new(buffer_size(&client), 0));
+    *accepted.fast.lock() = Some(FastPath {
+        tx: down.clone(),
+        rx: up.clone(),
+    });
+    *client.fast.lock() = Some(FastPath { tx: up, rx: down });
+    client.pairing.store(false, Ordering::Release);
+    client.poll_pair.wake();
+}
@@ -0,0 +1,409 @@
+//! `AF_NETLINK` sockets of the `NETLINK_ROUTE` family.
+//!
+//! Only the requests used to enumerate the interfaces and their addresses,
+//! `RTM_GETLINK` and `RTM_GETADDR`, are answered, from [`crate::netif`]. The
+//! replies to a request are made as it is sent and queued as one datagram.
+
+use alloc::{borrow::Cow, collections::VecDeque, format, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Context,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::{Buf, BufMut, IoEvents, PollSet, Pollable};
+use axtask::{current, future::Poller};
+use kspin::SpinNoIrq;
+use linux_raw_sys::{
+    general::S_IFSOCK,
+    net::{
+        AF_INET, AF_NETLINK, AF_UNSPEC, IF_OPER_UNKNOWN, IF_OPER_UP,
+        net_device_flags::IFF_BROADCAST,
+    },
+    netlink::{
+        IFA_ADDRESS, IFA_BROADCAST, IFA_F_PERMANENT, IFA_LABEL, IFA_LOCAL, IFLA_ADDRESS,
+        IFLA_BROADCAST, IFLA_IFNAME, IFLA_MTU, IFLA_OPERSTATE, IFLA_TXQLEN, NLM_F_ACK, NLM_F_DUMP,
+        NLM_F_MULTI, NLM_F_REQUEST, NLMSG_ALIGNTO, NLMSG_DONE, NLMSG_ERROR, RTM_GETADDR,
+        RTM_GETLINK, RTM_NEWADDR, RTM_NEWLINK, ifaddrmsg, ifinfomsg, nlmsghdr, rt_scope_t, rtattr,
+        sockaddr_nl,
+    },
+};
+use starry_core::task::AsThread;
+
+use super::{FileLike, Kstat, SealedBuf, SealedBufMut, is_nowait};
+use crate::netif::{Interface, interfaces};
+
+/// The port IDs in use.
+static PORTS: SpinNoIrq<Vec<u32>> = SpinNoIrq::new(Vec::new());
+
+fn align(len: usize) -> usize {
+    len.next_multiple_of(NLMSG_ALIGNTO as usize)
+}
+
+fn push<T>(buf: &mut Vec<u8>, value: &T) {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    buf.extend_from_slice(bytes);
+    buf.resize(align(buf.len()), 0);
+}
+
+fn push_attr(buf: &mut Vec<u8>, ty: u32, data: &[u8]) {
+    let attr = rtattr {
+        rta_len: (size_of::<rtattr>() + data.len()) as _,
+        rta_type: ty as _,
+    };
+    push(buf, &attr);
+    buf.extend_from_slice(data);
+    buf.resize(align(buf.len()), 0);
+}
+
+fn name_attr(name: &str) -> Vec<u8> {
+    let mut data = Vec::from(name.as_bytes());
+    data.push(0);
+    data
+}
+
+/// Builds the reply to a request.
+struct Reply {
+    buf: Vec<u8>,
+    seq: u32,
+    port: u32,
+}
+
+impl Reply {
+    fn message(&mut self, ty: u32, flags: u32, body: impl FnOnce(&mut Vec<u8>)) {
+        let start = self.buf.len();
+        let hdr = nlmsghdr {
+            nlmsg_len: 0,
+            nlmsg_type: ty as _,
+            nlmsg_flags: flags as _,
+            nlmsg_seq: self.seq,
+            nlmsg_pid: self.port,
+        };
+        push(&mut self.buf, &hdr);
+        body(&mut self.buf);
+        let len = (self.buf.len() - start) as u32;
+        self.buf[start..start + size_of::<u32>()].copy_from_slice(&len.to_ne_bytes());
+    }
+
+    /// Adds an `NLMSG_ERROR` message, which is an acknowledgement if `error`
+    /// is zero.
+    fn error(&mut self, error: i32, request: &nlmsghdr) {
+        self.message(NLMSG_ERROR, 0, |buf| {
+            push(buf, &error);
+            push(buf, request);
+        });
+    }
+
+    fn done(&mut self) {
+        self.message(NLMSG_DONE, NLM_F_MULTI, |buf| push(buf, &0i32));
+    }
+
+    fn link(&mut self, iface: &Interface, flags: u32) {
+        self.message(RTM_NEWLINK as _, flags, |buf| {
+            push(
+                buf,
+                &ifinfomsg {
+                    ifi_family: AF_UNSPEC as _,
+                    __ifi_pad: 0,
+                    ifi_type: iface.hw_type,
+                    ifi_index: iface.index as _,
+                    ifi_flags: iface.flags,
+                    ifi_change: 0,
+                },
+            );
+            let (operstate, broadcast) = if iface.flags & IFF_BROADCAST as u32 != 0 {
+                (IF_OPER_UP, [0xff; 6])
+            } else {
+                (IF_OPER_UNKNOWN, [0; 6])
+            };
+            push_attr(buf, IFLA_IFNAME as _, &name_attr(&iface.name));
+            push_attr(buf, IFLA_MTU as _, &iface.mtu.to_ne_bytes());
+            push_attr(buf, IFLA_TXQLEN as _, &1000u32.to_ne_bytes());
+            push_attr(buf, IFLA_OPERSTATE as _, &[operstate as u8]);
+            push_attr(buf, IFLA_ADDRESS as _, &iface.mac);
+            push_attr(buf, IFLA_BROADCAST as _, &broadcast);
+        });
+    }
+
+    fn addr(&mut self, iface: &Interface) {
+        self.message(RTM_NEWADDR as _, NLM_F_MULTI, |buf| {
+            let scope = if iface.addr.is_loopback() {
+                rt_scope_t::RT_SCOPE_HOST
+            } else {
+                rt_scope_t::RT_SCOPE_UNIVERSE
+            };
+            push(
+                buf,
+                &ifaddrmsg {
+                    ifa_family: AF_INET as _,
+                    ifa_prefixlen: iface.prefix_len,
+                    ifa_flags: IFA_F_PERMANENT as _,
+                    ifa_scope: scope as _,
+                    ifa_index: iface.index,
+                },
+            );
+            push_attr(buf, IFA_ADDRESS as _, &iface.addr.octets());
+            push_attr(buf, IFA_LOCAL as _, &iface.addr.octets());
+            if let Some(broadcast) = iface.broadcast() {
+                push_attr(buf, IFA_BROADCAST as _, &broadcast.octets());
+            }
+            push_attr(buf, IFA_LABEL as _, &name_attr(&iface.name));
+        });
+    }
+
+    /// Answers `request`, whose payload is `body`.
+    fn answer(&mut self, request: &nlmsghdr, body: &[u8]) {
+        let flags = request.nlmsg_flags as u32;
+        let dump = flags & NLM_F_DUMP == NLM_F_DUMP;
+        let result = match request.nlmsg_type as u32 {
+            ty if ty == RTM_GETLINK as u32 && dump => {
+                for iface in interfaces() {
+                    self.link(&iface, NLM_F_MULTI);
+                }
+                self.done();
+                Ok(())
+            }
+            ty if ty == RTM_GETLINK as u32 => {
+                if body.len() < size_of::<ifinfomsg>() {
+                    Err(LinuxError::EINVAL)
+                } else {
+                    let msg = unsafe { ptr::read_unaligned(body.as_ptr() as *const ifinfomsg) };
+                    match interfaces()
+                        .iter()
+                        .find(|iface| iface.index as i32 == msg.ifi_index)
+                    {
+                        Some(iface) => {
+                            self.link(iface, 0);
+                            Ok(())
+                        }
+                        None => Err(LinuxError::ENODEV),
+                    }
+                }
+            }
+            ty if ty == RTM_GETADDR as u32 && dump => {
+                // Only IPv4 addresses are known.
+                let family = body.first().copied().unwrap_or(0) as u32;
+                if family == AF_UNSPEC || family == AF_INET {
+                    for iface in interfaces().iter().filter(|it| it.has_addr()) {
+                        self.addr(iface);
+                    }
+                }
+                self.done();
+                Ok(())
+            }
+            ty => {
+                debug!("netlink: unsupported request type {ty}");
+                Err(LinuxError::EOPNOTSUPP)
+            }
+        };
+        match result {
+            Err(err) => self.error(-err.code(), request),
+            Ok(()) if flags & NLM_F_ACK != 0 && !dump => self.error(0, request),
+            Ok(()) => {}
+        }
+    }
+}
+
+pub struct NetlinkSocket {
+    /// The port ID, zero until the socket is bound.
+    port: AtomicU32,
+    /// The replies not read yet, one per datagram.
+    replies: SpinNoIrq<VecDeque<Vec<u8>>>,
+    non_blocking: AtomicBool,
+    poll_rx: PollSet,
+}
+
+impl NetlinkSocket {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            port: AtomicU32::new(0),
+            replies: SpinNoIrq::new(VecDeque::new()),
+            non_blocking: AtomicBool::new(false),
+            poll_rx: PollSet::new(),
+        }
+    }
+
+    /// Binds the socket to the port of `addr`, or picks one if it is zero.
+    pub fn bind(&self, addr: &sockaddr_nl) -> LinuxResult<()> {
+        // Multicast groups are accepted, but nothing is ever sent to them.
+        if self.port.load(Ordering::Acquire) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        self.bind_port(addr.nl_pid)
+    }
+
+    fn bind_port(&self, port: u32) -> LinuxResult<()> {
+        let mut ports = PORTS.lock();
+        let port = if port != 0 {
+            if ports.contains(&port) {
+                return Err(LinuxError::EADDRINUSE);
+            }
+            port
+        } else {
+            // Like Linux, try the PID first, then negative numbers.
+            let pid = current().as_thread().proc_data.proc.pid();
+            let mut port = pid;
+            let mut next = u32::MAX - 4095;
+            while ports.contains(&port) {
+                port = next;
+                next -= 1;
+            }
+            port
+        };
+        ports.push(port);
+        self.port.store(port, Ordering::Release);
+        Ok(())
+    }
+
+    fn port(&self) -> LinuxResult<u32> {
+        if self.port.load(Ordering::Acquire) == 0 {
+            self.bind_port(0)?;
+        }
+        Ok(self.port.load(Ordering::Acquire))
+    }
+
+    /// Returns the address of the socket, binding it first if it is not.
+    pub fn local_addr(&self) -> LinuxResult<sockaddr_nl> {
+        Ok(sockaddr_nl {
+            nl_family: AF_NETLINK as _,
+            nl_pad: 0,
+            nl_pid: self.port()?,
+            nl_groups: 0,
+        })
+    }
+
+    /// Handles the requests in a datagram sent to the kernel.
+    pub fn send(&self, src: &mut impl Buf) -> LinuxResult<usize> {
+        let mut data = Vec::with_capacity(src.remaining());
+        src.consume(|chunk| {
+            data.extend_from_slice(chunk);
+            Ok(chunk.len())
+        })?;
+        let port = self.port()?;
+
+        let mut offset = 0;
+        while data.len() - offset >= size_of::<nlmsghdr>() {
+            let hdr = unsafe { ptr::read_unaligned(data[offset..].as_ptr() as *const nlmsghdr) };
+            let len = hdr.nlmsg_len as usize;
+            if len < size_of::<nlmsghdr>() || len > data.len() - offset {
+                break;
+            }
+            if hdr.nlmsg_flags as u32 & NLM_F_REQUEST != 0 {
+                let mut reply = Reply {
+                    buf: Vec::new(),
+                    seq: hdr.nlmsg_seq,
+                    port,
+                };
+                reply.answer(&hdr, &data[offset + size_of::<nlmsghdr>()..offset + len]);
+                if !reply.buf.is_empty() {
+                    self.replies.lock().push_back(reply.buf);
+                    self.poll_rx.wake();
+                }
+            }
+            offset += align(len);
+        }
+        Ok(data.len())
+    }
+
+    /// Receives a datagram, returning its full length and whether it did not
+    /// fit in `dst`.
+    pub fn recv(&self, dst: &mut impl BufMut, peek: bool) -> LinuxResult<(usize, bool)> {
+        Poller::new(self, IoEvents::IN)
+            .non_blocking(self.nonblocking())
+            .poll(|| {
+                let mut replies = self.replies.lock();
+                let Some(reply) = replies.front() else {
+                    return Err(LinuxError::EAGAIN);
+                };
+                let mut copied = 0;
+                dst.fill(|chunk| {
+                    let len = chunk.len().min(reply.len() - copied);
+                    chunk[..len].copy_from_slice(&reply[copied..copied + len]);
+                    copied += len;
+                    Ok(len)
+                })?;
+                let result = (reply.len(), copied < reply.len());
+                if !peek {
+                    replies.pop_front();
+                }
+                Ok(result)
+            })
+    }
+
+    /// Returns the address replies come from, the kernel's.
+    pub fn peer_addr(&self) -> sockaddr_nl {
+        sockaddr_nl {
+            nl_family: AF_NETLINK as _,
+            nl_pad: 0,
+            nl_pid: 0,
+            nl_groups: 0,
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        let port = *self.port.get_mut();
+        if port != 0 {
+            PORTS.lock().retain(|it| *it != port);
+        }
+    }
+}
+
+impl FileLike for NetlinkSocket {
+    fn read(&self, dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        let capacity = dst.remaining_mut();
+        self.recv(dst, false).map(|(len, _)| len.min(capacity))
+    }
+
+    fn write(&self, src: &mut SealedBuf) -> LinuxResult<usize> {
+        self.send(src)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat {
+            mode: S_IFSOCK | 0o777u32,
+            blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.non_blocking.load(Ordering::Acquire) || is_nowait()
+    }
+
+    fn set_nonblocking(&self, non_blocking: bool) -> LinuxResult {
+        self.non_blocking.store(non_blocking, Ordering::Release);
+        Ok(())
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
+        crate::netif::ioctl(cmd, arg)
+    }
+
+    fn path(&self) -> Cow<str> {
+        format!("socket:[{}]", self as *const _ as usize).into()
+    }
+}
+
+impl Pollable for NetlinkSocket {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.replies.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_rx.register(context.waker());
+        }
+    }
+}
@@ -7,21 +7,28 @@ use core::task::Context;
 use axerrno::{LinuxError, LinuxResult};
 use axio::{IoEvents, PollSet, Pollable};
 use starry_core::task::ProcessData;
+use starry_process::Pid;
 
 use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
 
 pub struct PidFd {
+    pid: Pid,
     proc_data: Weak<ProcessData>,
     exit_event: Arc<PollSet>,
 }
 impl PidFd {
     pub fn new(proc_data: &Arc<ProcessData>) -> Self {
         Self {
+            pid: proc_data.proc.pid(),
             proc_data: Arc::downgrade(proc_data),
             exit_event: proc_data.exit_event.clone(),
         }
     }
 
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
     pub fn process_data(&self) -> LinuxResult<Arc<ProcessData>> {
         self.proc_data.upgrade().ok_or(LinuxError::ESRCH)
     }
@@ -1,16 +1,25 @@
-use alloc::{borrow::Cow, format, sync::Arc};
+use alloc::{
+    borrow::Cow,
+    collections::BTreeMap,
+    format,
+    sync::{Arc, Weak},
+};
 use core::{
     any::Any,
     mem,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     task::Context,
 };
 
 use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
 use axio::{Buf, BufMut, IoEvents, PollSet, Pollable, Read, Write};
 use axsync::Mutex;
 use axtask::{current, future::Poller};
-use linux_raw_sys::{general::S_IFIFO, ioctl::FIONREAD};
+use linux_raw_sys::{
+    general::{O_ACCMODE, O_NONBLOCK, O_RDONLY, O_WRONLY, S_IFIFO},
+    ioctl::FIONREAD,
+};
 use memory_addr::PAGE_SIZE_4K;
 use ringbuf::{
     HeapRb,
@@ -20,60 +29,171 @@ use starry_core::task::{AsThread, send_signal_to_process};
 use starry_signal::{SignalInfo, Signo};
 use starry_vm::VmMutPtr;
 
-use super::{FileLike, Kstat};
+use super::{
+    FileLike, Kstat,
+    fs::{NodeKey, node_key, path_for},
+    is_nowait, metadata_to_kstat,
+};
 use crate::file::{SealedBuf, SealedBufMut};
 
 const RING_BUFFER_INIT_SIZE: usize = 65536; // 64 KiB
 
 struct Shared {
     buffer: Mutex<HeapRb<u8>>,
+    readers: AtomicUsize,
+    writers: AtomicUsize,
     poll_rx: PollSet,
     poll_tx: PollSet,
     poll_close: PollSet,
+    /// Woken when a FIFO is opened, for those waiting for the other end.
+    poll_open: PollSet,
+}
+
+impl Shared {
+    fn new() -> Self {
+        Self {
+            buffer: Mutex::new(HeapRb::new(RING_BUFFER_INIT_SIZE)),
+            readers: AtomicUsize::new(0),
+            writers: AtomicUsize::new(0),
+            poll_rx: PollSet::new(),
+            poll_tx: PollSet::new(),
+            poll_close: PollSet::new(),
+            poll_open: PollSet::new(),
+        }
+    }
+}
+
+/// Buffers of the FIFOs currently open, which go away with their last end as
+/// on Linux.
+static FIFOS: Mutex<BTreeMap<NodeKey, Weak<Shared>>> = Mutex::new(BTreeMap::new());
+
+/// Waits for the other end of a FIFO to be opened.
+struct FifoPeer<'a>(&'a Shared);
+
+impl Pollable for FifoPeer<'_> {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, context: &mut Context<'_>, _events: IoEvents) {
+        self.0.poll_open.register(context.waker());
+    }
 }
 
 pub struct Pipe {
-    read_side: bool,
+    readable: bool,
+    writable: bool,
     shared: Arc<Shared>,
     non_blocking: AtomicBool,
+    /// The node a FIFO was opened from, `None` for anonymous pipes.
+    fifo: Option<Location>,
 }
 impl Drop for Pipe {
     fn drop(&mut self) {
+        if self.readable {
+            self.shared.readers.fetch_sub(1, Ordering::AcqRel);
+        }
+        if self.writable {
+            self.shared.writers.fetch_sub(1, Ordering::AcqRel);
+        }
         self.shared.poll_close.wake();
     }
 }
 
 impl Pipe {
-    pub fn new() -> (Pipe, Pipe) {
-        let shared = Arc::new(Shared {
-            buffer: Mutex::new(HeapRb::new(RING_BUFFER_INIT_SIZE)),
-            poll_rx: PollSet::new(),
-            poll_tx: PollSet::new(),
-            poll_close: PollSet::new(),
-        });
-        let read_end = Pipe {
-            read_side: true,
-            shared: shared.clone(),
-            non_blocking: AtomicBool::new(false),
-        };
-        let write_end = Pipe {
-            read_side: false,
+    fn new_end(
+        shared: Arc<Shared>,
+        readable: bool,
+        writable: bool,
+        fifo: Option<Location>,
+    ) -> Pipe {
+        if readable {
+            shared.readers.fetch_add(1, Ordering::AcqRel);
+        }
+        if writable {
+            shared.writers.fetch_add(1, Ordering::AcqRel);
+        }
+        shared.poll_open.wake();
+        Pipe {
+            readable,
+            writable,
             shared,
             non_blocking: AtomicBool::new(false),
-        };
+            fifo,
+        }
+    }
+
+    pub fn new() -> (Pipe, Pipe) {
+        let shared = Arc::new(Shared::new());
+        let read_end = Pipe::new_end(shared.clone(), true, false, None);
+        let write_end = Pipe::new_end(shared, false, true, None);
         (read_end, write_end)
     }
 
+    /// Opens the FIFO at `loc` with the access mode in `flags`.
+    ///
+    /// Opening only one end blocks until the other end is opened as well,
+    /// unless `O_NONBLOCK` is given, in which case opening for reading
+    /// succeeds right away and opening for writing fails with `ENXIO`.
+    pub fn open_fifo(loc: &Location, flags: u32) -> LinuxResult<Pipe> {
+        let key = node_key(loc)?;
+        let shared = {
+            let mut fifos = FIFOS.lock();
+            fifos.retain(|_, it| it.strong_count() > 0);
+            match fifos.get(&key).and_then(Weak::upgrade) {
+                Some(shared) => shared,
+                None => {
+                    let shared = Arc::new(Shared::new());
+                    fifos.insert(key, Arc::downgrade(&shared));
+                    shared
+                }
+            }
+        };
+
+        let non_blocking = flags & O_NONBLOCK != 0;
+        let (readable, writable) = match flags & O_ACCMODE {
+            O_RDONLY => (true, false),
+            O_WRONLY => (false, true),
+            _ => (true, true),
+        };
+        if !readable && non_blocking && shared.readers.load(Ordering::Acquire) == 0 {
+            return Err(LinuxError::ENXIO);
+        }
+        let pipe = Pipe::new_end(shared, readable, writable, Some(loc.clone()));
+        if readable != writable && !non_blocking {
+            let peers = if readable {
+                &pipe.shared.writers
+            } else {
+                &pipe.shared.readers
+            };
+            Poller::new(&FifoPeer(&pipe.shared), IoEvents::IN).poll(|| {
+                if peers.load(Ordering::Acquire) > 0 {
+                    Ok(())
+                } else {
+                    Err(LinuxError::EAGAIN)
+                }
+            })?;
+        }
+        Ok(pipe)
+    }
+
     pub const fn is_read(&self) -> bool {
-        self.read_side
+        self.readable
     }
 
     pub const fn is_write(&self) -> bool {
-        !self.read_side
+        self.writable
+    }
+
+    /// Whether all write ends are closed, which reading reports as EOF.
+    fn no_writers(&self) -> bool {
+        self.shared.writers.load(Ordering::Acquire) == 0
     }
 
-    pub fn closed(&self) -> bool {
-        Arc::strong_count(&self.shared) == 1
+    /// Whether all read ends are closed, which makes writing fail with
+    /// `EPIPE`.
+    fn no_readers(&self) -> bool {
+        self.shared.readers.load(Ordering::Acquire) == 0
     }
 
     pub fn capacity(&self) -> usize {
@@ -132,7 +252,7 @@ impl FileLike for Pipe {
                 if read > 0 {
                     self.shared.poll_tx.wake();
                     Ok(read)
-                } else if self.closed() {
+                } else if self.no_writers() {
                     Ok(0)
                 } else {
                     Err(LinuxError::EAGAIN)
@@ -154,7 +274,7 @@ impl FileLike for Pipe {
         Poller::new(self, IoEvents::OUT)
             .non_blocking(non_blocking)
             .poll(|| {
-                if self.closed() {
+                if self.no_readers() {
                     raise_pipe();
                     return Err(LinuxError::EPIPE);
                 }
@@ -181,6 +301,9 @@ impl FileLike for Pipe {
     }
 
     fn stat(&self) -> LinuxResult<Kstat> {
+        if let Some(loc) = &self.fifo {
+            return Ok(metadata_to_kstat(&loc.metadata()?));
+        }
         Ok(Kstat {
             mode: S_IFIFO | if self.is_read() { 0o444 } else { 0o222 },
             ..Default::default()
@@ -188,6 +311,9 @@ impl FileLike for Pipe {
     }
 
     fn path(&self) -> Cow<str> {
+        if let Some(loc) = &self.fifo {
+            return path_for(loc);
+        }
         format!("pipe:[{}]", self as *const _ as usize).into()
     }
 
@@ -201,7 +327,7 @@ impl FileLike for Pipe {
     }
 
     fn nonblocking(&self) -> bool {
-        self.non_blocking.load(Ordering::Acquire)
+        self.non_blocking.load(Ordering::Acquire) || is_nowait()
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
@@ -219,10 +345,11 @@ impl Pollable for Pipe {
     fn poll(&self) -> IoEvents {
         let mut events = IoEvents::empty();
         let buf = self.shared.buffer.lock();
-        if self.read_side {
+        if self.readable {
             events.set(IoEvents::IN, buf.occupied_len() > 0);
-            events.set(IoEvents::HUP, self.closed());
-        } else {
+            events.set(IoEvents::HUP, self.no_writers());
+        }
+        if self.writable {
             events.set(IoEvents::OUT, buf.vacant_len() > 0);
         }
         events
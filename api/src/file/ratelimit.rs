@@ -0,0 +1,213 @@
+//! Per-file rate limiting, for experiments with differentiated service.
+//!
+//! A token bucket is attached to an open file description with
+//! [`FIOSETRATE`], one each for reads and writes. Like the state of
+//! [`fasync`](super::fasync), it is kept in a table keyed by the
+//! [`FileLike`], so that it is shared by duplicated descriptors and works for
+//! every kind of file.
+//!
+//! A transfer is charged to the bucket once it is done, which may leave the
+//! bucket in debt; the next transfer waits until the debt is paid back, or
+//! fails with `EAGAIN` on a non-blocking file. The bucket fills up to its
+//! burst size while the file is idle.
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
+use core::{
+    ffi::c_int,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::monotonic_time;
+use axtask::future::{block_on_interruptible, sleep};
+use bytemuck::AnyBitPattern;
+use kspin::SpinNoIrq;
+use starry_core::task::{WaitChannel, wait_on};
+use starry_vm::{VmMutPtr, VmPtr};
+
+use super::{FileLike, get_file_like};
+
+/// `_IOW('Q', 1, struct fd_rate_limit)`: sets the limits of a file, or
+/// removes them if both rates are 0.
+pub const FIOSETRATE: u32 = 0x4018_5101;
+/// `_IOR('Q', 2, struct fd_rate_limit)`: gets the limits of a file.
+pub const FIOGETRATE: u32 = 0x8018_5102;
+
+/// The argument of [`FIOSETRATE`] and [`FIOGETRATE`].
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, AnyBitPattern)]
+pub struct RateLimitArg {
+    /// Bytes per second read, or 0 for no limit.
+    read_bps: u64,
+    /// Bytes per second written, or 0 for no limit.
+    write_bps: u64,
+    /// The most bytes transferred at full speed after the file was idle,
+    /// or 0 for a second's worth.
+    burst: u64,
+}
+
+/// The direction of a transfer.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Read,
+    Write,
+}
+
+struct Bucket {
+    /// Bytes per second.
+    rate: u64,
+    burst: u64,
+    /// The bytes that may be transferred, negative while in debt.
+    tokens: i64,
+    last_refill: Duration,
+}
+
+impl Bucket {
+    fn new(rate: u64, burst: u64) -> Self {
+        // The tokens are counted in an i64.
+        let burst = if burst == 0 { rate } else { burst }.min(i64::MAX as u64);
+        Self {
+            rate,
+            burst,
+            tokens: burst as i64,
+            last_refill: monotonic_time(),
+        }
+    }
+
+    fn refill(&mut self, now: Duration) {
+        let elapsed = now.saturating_sub(self.last_refill);
+        let earned = elapsed.as_nanos().saturating_mul(self.rate as u128) / 1_000_000_000;
+        let tokens = self.tokens as i128 + earned.min(self.burst as u128) as i128;
+        self.tokens = tokens.min(self.burst as i128) as i64;
+        self.last_refill = now;
+    }
+
+    /// Returns how long it takes to pay back the debt, if there is any.
+    fn debt(&mut self) -> Option<Duration> {
+        self.refill(monotonic_time());
+        (self.tokens < 0).then(|| {
+            let nanos = self.tokens.unsigned_abs() as u128 * 1_000_000_000 / self.rate as u128;
+            Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX))
+        })
+    }
+}
+
+struct RateLimit {
+    file: Weak<dyn FileLike>,
+    read: SpinNoIrq<Option<Bucket>>,
+    write: SpinNoIrq<Option<Bucket>>,
+}
+
+impl RateLimit {
+    fn bucket(&self, dir: Direction) -> &SpinNoIrq<Option<Bucket>> {
+        match dir {
+            Direction::Read => &self.read,
+            Direction::Write => &self.write,
+        }
+    }
+}
+
+static TABLE: SpinNoIrq<BTreeMap<usize, Arc<RateLimit>>> = SpinNoIrq::new(BTreeMap::new());
+/// Whether a limit was ever set, which spares the other transfers the
+/// lookup.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn key(file: &Arc<dyn FileLike>) -> usize {
+    Arc::as_ptr(file) as *const () as usize
+}
+
+fn lookup(file: &Arc<dyn FileLike>) -> Option<Arc<RateLimit>> {
+    let state = TABLE.lock().get(&key(file)).cloned()?;
+    // The address may have been reused by another file.
+    state
+        .file
+        .upgrade()
+        .is_some_and(|it| Arc::ptr_eq(&it, file))
+        .then_some(state)
+}
+
+/// Handles [`FIOSETRATE`] and [`FIOGETRATE`] on `file`.
+pub fn ioctl(file: &Arc<dyn FileLike>, cmd: u32, arg: usize) -> LinuxResult<usize> {
+    match cmd {
+        FIOSETRATE => {
+            let limit = (arg as *const RateLimitArg).vm_read()?;
+            let bucket = |rate| (rate != 0).then(|| Bucket::new(rate, limit.burst));
+            let mut table = TABLE.lock();
+            table.retain(|_, it| it.file.strong_count() > 0);
+            if limit.read_bps == 0 && limit.write_bps == 0 {
+                table.remove(&key(file));
+            } else {
+                let state = RateLimit {
+                    file: Arc::downgrade(file),
+                    read: SpinNoIrq::new(bucket(limit.read_bps)),
+                    write: SpinNoIrq::new(bucket(limit.write_bps)),
+                };
+                table.insert(key(file), Arc::new(state));
+                ACTIVE.store(true, Ordering::Release);
+            }
+        }
+        FIOGETRATE => {
+            let mut limit = RateLimitArg::default();
+            if let Some(state) = lookup(file) {
+                for dir in [Direction::Read, Direction::Write] {
+                    if let Some(bucket) = &*state.bucket(dir).lock() {
+                        limit.burst = bucket.burst;
+                        match dir {
+                            Direction::Read => limit.read_bps = bucket.rate,
+                            Direction::Write => limit.write_bps = bucket.rate,
+                        }
+                    }
+                }
+            }
+            (arg as *mut RateLimitArg).vm_write(limit)?;
+        }
+        _ => return Err(LinuxError::ENOTTY),
+    }
+    Ok(0)
+}
+
+/// Runs a transfer `f` on `fd` in direction `dir` within the limits of the
+/// file, if it has any.
+pub fn limit(
+    fd: c_int,
+    dir: Direction,
+    f: impl FnOnce() -> LinuxResult<usize>,
+) -> LinuxResult<usize> {
+    if !ACTIVE.load(Ordering::Acquire) {
+        return f();
+    }
+    let Some(state) = get_file_like(fd).ok().as_ref().and_then(lookup) else {
+        return f();
+    };
+    let bucket = state.bucket(dir);
+    if bucket.lock().is_none() {
+        return f();
+    }
+
+    loop {
+        let Some(delay) = bucket.lock().as_mut().and_then(Bucket::debt) else {
+            break;
+        };
+        if state.file.upgrade().is_some_and(|file| file.nonblocking()) {
+            return Err(LinuxError::EAGAIN);
+        }
+        let _wait = wait_on(WaitChannel::Sleep("ratelimit"));
+        block_on_interruptible(async {
+            sleep(delay).await;
+            Ok(())
+        })?;
+    }
+    let result = f();
+    if let Ok(n) = result
+        && let Some(bucket) = bucket.lock().as_mut()
+    {
+        bucket.tokens = bucket
+            .tokens
+            .saturating_sub(n.try_into().unwrap_or(i64::MAX));
+    }
+    result
+}
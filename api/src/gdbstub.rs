@@ -0,0 +1,492 @@
+//! A minimal GDB stub speaking the remote serial protocol over the second
+//! UART of the RK3588.
+//!
+//! The stub takes over when a user thread traps on a breakpoint or finishes a
+//! single step while a debugger is attached, and before init executes its
+//! first instruction if the kernel command line contains `gdbwait`. Only the
+//! stopped thread waits for commands; threads on other CPUs keep running, and
+//! one that stops while the stub serves another waits its turn.
+//!
+//! Registers, memory and software breakpoints of the stopped process are
+//! supported. Kernel memory can be read and written, but breakpoints can only
+//! be set in user space: a `brk` in kernel code would be taken by the kernel
+//! trap handler, which does not know about the stub.
+
+#[cfg(not(target_arch = "aarch64"))]
+compile_error!("the GDB stub only supports aarch64");
+
+use alloc::{collections::BTreeMap, format, vec::Vec};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::{
+    context::TrapFrame,
+    mem::phys_to_virt,
+    paging::MappingFlags,
+    uspace::{ExceptionKind, UserContext},
+};
+use axtask::current;
+use kspin::{SpinNoIrq, SpinNoPreempt};
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use starry_core::{cmdline, task::AsThread};
+use starry_vm::{vm_load, vm_write_slice};
+
+/// UART3, the first UART after the debug console (UART2).
+const UART_BASE: usize = 0xfeb6_0000;
+/// The UART input clock, in Hz.
+const UART_CLOCK: u32 = 24_000_000;
+const UART_BAUD: u32 = 115_200;
+
+const UART_RBR: usize = 0x00;
+const UART_THR: usize = 0x00;
+const UART_DLL: usize = 0x00;
+const UART_DLH: usize = 0x04;
+const UART_FCR: usize = 0x08;
+const UART_LCR: usize = 0x0c;
+const UART_LSR: usize = 0x14;
+
+const LSR_DATA_READY: u32 = 1 << 0;
+const LSR_THR_EMPTY: u32 = 1 << 5;
+
+/// `brk #0`
+const BRK_INSN: u32 = 0xd420_0000;
+
+/// The SS bit of SPSR, set to step a single instruction after `eret`.
+const SPSR_SS: u64 = 1 << 21;
+/// The SS bit of MDSCR_EL1, enabling software step.
+const MDSCR_SS: u64 = 1 << 0;
+
+/// User addresses are below this, kernel addresses at or above.
+const USER_END: usize = 1 << 48;
+
+/// Signal number reported for every stop, `SIGTRAP`.
+const STOP_SIGNAL: u8 = 5;
+
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+/// The thread being single-stepped, or 0.
+static STEPPING: AtomicU64 = AtomicU64::new(0);
+static UART_READY: AtomicBool = AtomicBool::new(false);
+static GDBWAIT_DONE: AtomicBool = AtomicBool::new(false);
+
+/// Inserted breakpoints, mapping addresses to the original instructions.
+static BREAKPOINTS: SpinNoIrq<BTreeMap<usize, u32>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Held while a stopped thread talks to the debugger.
+static SERVING: SpinNoPreempt<()> = SpinNoPreempt::new(());
+
+fn uart_reg(offset: usize) -> *mut u32 {
+    phys_to_virt(PhysAddr::from(UART_BASE + offset)).as_mut_ptr() as *mut u32
+}
+
+fn uart_read(offset: usize) -> u32 {
+    // SAFETY: UART registers are mapped as device memory
+    unsafe { uart_reg(offset).read_volatile() }
+}
+
+fn uart_write(offset: usize, value: u32) {
+    // SAFETY: UART registers are mapped as device memory
+    unsafe { uart_reg(offset).write_volatile(value) }
+}
+
+/// Programs the UART for 8N1 at [`UART_BAUD`]. The pins are expected to be
+/// muxed by the firmware.
+fn uart_init() {
+    if UART_READY.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let divisor = UART_CLOCK / (16 * UART_BAUD);
+    uart_write(UART_LCR, 0x83);
+    uart_write(UART_DLL, divisor & 0xff);
+    uart_write(UART_DLH, divisor >> 8);
+    uart_write(UART_LCR, 0x03);
+    uart_write(UART_FCR, 0x07);
+}
+
+fn getc() -> u8 {
+    while uart_read(UART_LSR) & LSR_DATA_READY == 0 {
+        spin_loop();
+    }
+    uart_read(UART_RBR) as u8
+}
+
+fn putc(ch: u8) {
+    while uart_read(UART_LSR) & LSR_THR_EMPTY == 0 {
+        spin_loop();
+    }
+    uart_write(UART_THR, ch as u32);
+}
+
+fn hex_digit(ch: u8) -> Option<u8> {
+    (ch as char).to_digit(16).map(|it| it as u8)
+}
+
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() {
+        return None;
+    }
+    s.iter().try_fold(0usize, |acc, ch| {
+        acc.checked_mul(16)?.checked_add(hex_digit(*ch)? as usize)
+    })
+}
+
+fn decode_hex(s: &[u8]) -> Option<Vec<u8>> {
+    s.chunks(2)
+        .map(|it| match it {
+            [hi, lo] => Some((hex_digit(*hi)? << 4) | hex_digit(*lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn encode_hex(out: &mut Vec<u8>, bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for byte in bytes {
+        out.push(DIGITS[(byte >> 4) as usize]);
+        out.push(DIGITS[(byte & 0xf) as usize]);
+    }
+}
+
+/// Receives a packet, acknowledging it once its checksum matches.
+fn read_packet(buf: &mut Vec<u8>) {
+    loop {
+        while getc() != b'$' {}
+        buf.clear();
+        let mut sum = 0u8;
+        loop {
+            match getc() {
+                b'#' => break,
+                ch => {
+                    sum = sum.wrapping_add(ch);
+                    buf.push(ch);
+                }
+            }
+        }
+        let expected = [getc(), getc()];
+        if parse_hex(&expected) == Some(sum as usize) {
+            putc(b'+');
+            return;
+        }
+        putc(b'-');
+    }
+}
+
+/// Sends a packet, retransmitting it until the debugger acknowledges it.
+fn write_packet(data: &[u8]) {
+    let sum = data.iter().fold(0u8, |acc, ch| acc.wrapping_add(*ch));
+    let mut trailer = Vec::with_capacity(2);
+    encode_hex(&mut trailer, &[sum]);
+    loop {
+        putc(b'$');
+        data.iter().copied().for_each(putc);
+        putc(b'#');
+        trailer.iter().copied().for_each(putc);
+        if getc() == b'+' {
+            return;
+        }
+    }
+}
+
+/// Returns the value and size in bytes of register `n`, numbered as in the
+/// `org.gnu.gdb.aarch64.core` feature.
+fn read_register(tf: &TrapFrame, n: usize) -> Option<(u64, usize)> {
+    match n {
+        0..=30 => Some((tf.r[n], 8)),
+        31 => Some((tf.usp, 8)),
+        32 => Some((tf.elr, 8)),
+        33 => Some((tf.spsr, 4)),
+        _ => None,
+    }
+}
+
+fn write_register(tf: &mut TrapFrame, n: usize, value: u64) -> Option<()> {
+    match n {
+        0..=30 => tf.r[n] = value,
+        31 => tf.usp = value,
+        32 => tf.elr = value,
+        33 => tf.spsr = value,
+        _ => return None,
+    }
+    Some(())
+}
+
+const NUM_REGS: usize = 34;
+
+fn read_memory(addr: usize, len: usize) -> Option<Vec<u8>> {
+    if addr < USER_END {
+        return vm_load(addr as *const u8, len).ok();
+    }
+    let mut buf = alloc::vec![0; len];
+    // SAFETY: the debugger is trusted with kernel memory
+    unsafe { core::ptr::copy_nonoverlapping(addr as *const u8, buf.as_mut_ptr(), len) };
+    Some(buf)
+}
+
+fn flush_icache() {
+    // SAFETY: only invalidates caches
+    unsafe { asm!("dsb ish", "ic iallu", "dsb ish", "isb") };
+}
+
+/// Writes to user memory, including read-only text, as needed to insert
+/// breakpoints.
+///
+/// The pages are made writable for the duration of the write, and faulted in
+/// for writing first so that copy-on-write pages, e.g. those of the page
+/// cache, are copied instead of being modified for everyone.
+fn write_user_memory(addr: usize, data: &[u8]) -> LinuxResult<()> {
+    if vm_write_slice(addr as *mut u8, data).is_ok() {
+        return Ok(());
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let aspace = &proc_data.aspace;
+    let start = VirtAddr::from(addr).align_down_4k();
+    let end = VirtAddr::from(addr + data.len()).align_up_4k();
+    let flags = {
+        let mut aspace = aspace.lock();
+        let flags = aspace.find_area(start).ok_or(LinuxError::EFAULT)?.flags();
+        proc_data
+            .zero_pages
+            .read()
+            .strip(&mut aspace, start, end - start)?;
+        aspace.protect(start, end - start, flags | MappingFlags::WRITE)?;
+        for page in (start.as_usize()..end.as_usize()).step_by(PAGE_SIZE_4K) {
+            aspace.handle_page_fault(VirtAddr::from(page), MappingFlags::WRITE);
+        }
+        flags
+    };
+    let result = vm_write_slice(addr as *mut u8, data);
+    aspace.lock().protect(start, end - start, flags)?;
+    result.map_err(Into::into)
+}
+
+fn write_memory(addr: usize, data: &[u8]) -> LinuxResult<()> {
+    if addr < USER_END {
+        write_user_memory(addr, data)?;
+    } else {
+        // SAFETY: the debugger is trusted with kernel memory
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len()) };
+    }
+    flush_icache();
+    Ok(())
+}
+
+fn insert_breakpoint(addr: usize) -> LinuxResult<()> {
+    if addr >= USER_END || addr % 4 != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.contains_key(&addr) {
+        return Ok(());
+    }
+    let orig = read_memory(addr, 4).ok_or(LinuxError::EFAULT)?;
+    write_memory(addr, &BRK_INSN.to_le_bytes())?;
+    breakpoints.insert(addr, u32::from_le_bytes(orig.try_into().unwrap()));
+    Ok(())
+}
+
+fn remove_breakpoint(addr: usize) -> LinuxResult<()> {
+    let orig = BREAKPOINTS.lock().remove(&addr).ok_or(LinuxError::ENOENT)?;
+    write_memory(addr, &orig.to_le_bytes())
+}
+
+fn remove_all_breakpoints() {
+    let addrs = BREAKPOINTS.lock().keys().copied().collect::<Vec<_>>();
+    for addr in addrs {
+        let _ = remove_breakpoint(addr);
+    }
+}
+
+/// Arms or disarms software step for the return to user space.
+fn set_stepping(tf: &mut TrapFrame, step: bool) {
+    let tid = if step { current().id().as_u64() } else { 0 };
+    STEPPING.store(tid, Ordering::Release);
+    let mut mdscr: u64;
+    // SAFETY: only changes the debug configuration of this CPU
+    unsafe {
+        // Debug exceptions are masked while the OS lock is set, as it is
+        // after reset.
+        asm!("msr oslar_el1, xzr");
+        asm!("mrs {}, mdscr_el1", out(reg) mdscr);
+    }
+    if step {
+        mdscr |= MDSCR_SS;
+        tf.spsr |= SPSR_SS;
+    } else {
+        mdscr &= !MDSCR_SS;
+        tf.spsr &= !SPSR_SS;
+    }
+    // SAFETY: see above
+    unsafe { asm!("msr mdscr_el1, {}", "isb", in(reg) mdscr) };
+}
+
+fn error_reply(err: LinuxError) -> Vec<u8> {
+    format!("E{:02x}", err.code() as u8).into_bytes()
+}
+
+fn result_reply(result: LinuxResult<()>) -> Vec<u8> {
+    match result {
+        Ok(()) => b"OK".to_vec(),
+        Err(err) => error_reply(err),
+    }
+}
+
+/// Parses `addr,len` or `addr,kind`.
+fn parse_pair(args: &[u8]) -> Option<(usize, usize)> {
+    let (a, b) = args.split_at(args.iter().position(|it| *it == b',')?);
+    Some((parse_hex(a)?, parse_hex(&b[1..])?))
+}
+
+/// Runs the command loop until the debugger resumes the stopped thread.
+fn serve(tf: &mut TrapFrame) {
+    let _serving = SERVING.lock();
+    let tid = current().id().as_u64();
+    set_stepping(tf, false);
+    write_packet(format!("T{STOP_SIGNAL:02x}thread:{tid:x};").as_bytes());
+
+    let mut packet = Vec::new();
+    loop {
+        read_packet(&mut packet);
+        let (&cmd, args) = match packet.split_first() {
+            Some(it) => it,
+            None => continue,
+        };
+        let reply = match cmd {
+            b'?' => format!("S{STOP_SIGNAL:02x}").into_bytes(),
+            b'g' => {
+                let mut reply = Vec::new();
+                for n in 0..NUM_REGS {
+                    let (value, size) = read_register(tf, n).unwrap();
+                    encode_hex(&mut reply, &value.to_le_bytes()[..size]);
+                }
+                reply
+            }
+            b'G' => {
+                let Some(bytes) = decode_hex(args) else {
+                    write_packet(&error_reply(LinuxError::EINVAL));
+                    continue;
+                };
+                let mut bytes = bytes.as_slice();
+                for n in 0..NUM_REGS {
+                    let size = read_register(tf, n).unwrap().1;
+                    if bytes.len() < size {
+                        break;
+                    }
+                    let mut value = [0; 8];
+                    value[..size].copy_from_slice(&bytes[..size]);
+                    write_register(tf, n, u64::from_le_bytes(value));
+                    bytes = &bytes[size..];
+                }
+                b"OK".to_vec()
+            }
+            b'p' => match parse_hex(args).and_then(|n| read_register(tf, n)) {
+                Some((value, size)) => {
+                    let mut reply = Vec::new();
+                    encode_hex(&mut reply, &value.to_le_bytes()[..size]);
+                    reply
+                }
+                None => error_reply(LinuxError::EINVAL),
+            },
+            b'P' => {
+                let parsed = args
+                    .iter()
+                    .position(|it| *it == b'=')
+                    .and_then(|eq| Some((parse_hex(&args[..eq])?, decode_hex(&args[eq + 1..])?)));
+                match parsed {
+                    Some((n, bytes)) if bytes.len() <= 8 => {
+                        let mut value = [0; 8];
+                        value[..bytes.len()].copy_from_slice(&bytes);
+                        match write_register(tf, n, u64::from_le_bytes(value)) {
+                            Some(()) => b"OK".to_vec(),
+                            None => error_reply(LinuxError::EINVAL),
+                        }
+                    }
+                    _ => error_reply(LinuxError::EINVAL),
+                }
+            }
+            b'm' => match parse_pair(args).and_then(|(addr, len)| read_memory(addr, len)) {
+                Some(data) => {
+                    let mut reply = Vec::new();
+                    encode_hex(&mut reply, &data);
+                    reply
+                }
+                None => error_reply(LinuxError::EFAULT),
+            },
+            b'M' => {
+                let parsed = args.iter().position(|it| *it == b':').and_then(|colon| {
+                    let (addr, len) = parse_pair(&args[..colon])?;
+                    let data = decode_hex(&args[colon + 1..])?;
+                    (data.len() == len).then_some((addr, data))
+                });
+                match parsed {
+                    Some((addr, data)) => result_reply(write_memory(addr, &data)),
+                    None => error_reply(LinuxError::EINVAL),
+                }
+            }
+            b'Z' | b'z' if args.starts_with(b"0,") => match parse_pair(&args[2..]) {
+                Some((addr, _kind)) if cmd == b'Z' => result_reply(insert_breakpoint(addr)),
+                Some((addr, _kind)) => result_reply(remove_breakpoint(addr)),
+                None => error_reply(LinuxError::EINVAL),
+            },
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    tf.elr = addr as u64;
+                }
+                set_stepping(tf, cmd == b's');
+                return;
+            }
+            b'D' | b'k' => {
+                remove_all_breakpoints();
+                ATTACHED.store(false, Ordering::Release);
+                if cmd == b'D' {
+                    write_packet(b"OK");
+                }
+                return;
+            }
+            b'H' | b'T' => b"OK".to_vec(),
+            b'q' if args.starts_with(b"Supported") => b"PacketSize=1000".to_vec(),
+            b'q' if args == b"Attached" => b"1".to_vec(),
+            b'q' if args == b"C" => format!("QC{tid:x}").into_bytes(),
+            b'q' if args == b"fThreadInfo" => format!("m{tid:x}").into_bytes(),
+            b'q' if args == b"sThreadInfo" => b"l".to_vec(),
+            _ => Vec::new(),
+        };
+        write_packet(&reply);
+    }
+}
+
+/// Hands a trap in user space over to the debugger, if one is attached.
+///
+/// Returns `true` if the trap was a breakpoint or single step handled by the
+/// stub, in which case no signal is to be delivered.
+pub fn handle_trap(uctx: &mut UserContext, kind: ExceptionKind) -> bool {
+    if !ATTACHED.load(Ordering::Acquire) {
+        return false;
+    }
+    let tid = current().id().as_u64();
+    let stepped = STEPPING
+        .compare_exchange(tid, 0, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok();
+    if !stepped && !matches!(kind, ExceptionKind::Breakpoint) {
+        return false;
+    }
+    serve(uctx);
+    true
+}
+
+/// Waits for the debugger before the first user instruction is executed, if
+/// requested with `gdbwait` on the command line.
+pub fn on_user_entry(uctx: &mut UserContext) {
+    let requested = cmdline::args().any(|it| it == "gdbwait");
+    if !requested || GDBWAIT_DONE.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    uart_init();
+    info!("Waiting for GDB on UART3");
+    ATTACHED.store(true, Ordering::Release);
+    serve(uctx);
+}
@@ -1,10 +1,200 @@
 use core::mem::{self, MaybeUninit};
 
 use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
 use axio::{Buf, BufMut, Read, Write};
 use bytemuck::AnyBitPattern;
+use memory_addr::{PAGE_SIZE_4K, VirtAddr};
 use starry_vm::{VmPtr, vm_read_slice, vm_write_slice};
 
+use crate::mm::with_user_pages;
+
+/// Transfers smaller than this are copied through a kernel buffer even when
+/// they could go straight to the user pages, as pinning them costs more.
+const PIN_THRESHOLD: usize = 64 * 1024;
+
+/// Whether a transfer of `len` bytes at `ptr` is to be done in place in the
+/// user pages: it must be large, and aligned to sectors like `O_DIRECT` I/O.
+fn should_pin(ptr: usize, len: usize) -> bool {
+    len >= PIN_THRESHOLD && ptr % 512 == 0 && len % 512 == 0
+}
+
+/// Copies user memory at `src` to `dst` a page at a time, and returns how
+/// much was copied before the first page that faulted.
+fn copy_from_user(src: *const u8, dst: &mut [u8]) -> usize {
+    let mut copied = 0;
+    while copied < dst.len() {
+        let ptr = src.wrapping_add(copied);
+        let len = (PAGE_SIZE_4K - ptr as usize % PAGE_SIZE_4K).min(dst.len() - copied);
+        // SAFETY: `u8` and `MaybeUninit<u8>` have the same layout
+        let chunk = unsafe {
+            mem::transmute::<&mut [u8], &mut [MaybeUninit<u8>]>(&mut dst[copied..copied + len])
+        };
+        if vm_read_slice(ptr, chunk).is_err() {
+            break;
+        }
+        copied += len;
+    }
+    copied
+}
+
+/// Copies `src` to user memory at `dst` a page at a time, and returns how
+/// much was copied before the first page that faulted.
+fn copy_to_user(dst: *mut u8, src: &[u8]) -> usize {
+    let mut copied = 0;
+    while copied < src.len() {
+        let ptr = dst.wrapping_add(copied);
+        let len = (PAGE_SIZE_4K - ptr as usize % PAGE_SIZE_4K).min(src.len() - copied);
+        if vm_write_slice(ptr, &src[copied..copied + len]).is_err() {
+            break;
+        }
+        copied += len;
+    }
+    copied
+}
+
+/// Returns the result of a transfer to or from a user buffer that may have
+/// faulted.
+///
+/// As on Linux, a fault cuts the transfer short rather than failing it, so
+/// it is only an error if nothing was transferred.
+pub fn partial_transfer(result: LinuxResult<usize>, faulted: bool) -> LinuxResult<usize> {
+    match result {
+        Ok(0) if faulted => Err(LinuxError::EFAULT),
+        result => result,
+    }
+}
+
+/// A user buffer to take data from, which ends at the first page that
+/// faults. See [`partial_transfer`].
+pub struct UserBuf {
+    ptr: *const u8,
+    len: usize,
+    faulted: bool,
+}
+
+impl UserBuf {
+    pub fn new(ptr: *const u8, len: usize) -> Self {
+        Self {
+            ptr,
+            len,
+            faulted: false,
+        }
+    }
+
+    /// Whether the buffer ended early because of a fault.
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Lets `f` take the data straight from the user pages, see
+    /// [`with_user_pages`]. Returns `None` if the buffer is not worth
+    /// pinning or cannot be pinned, so that it is to be copied instead.
+    pub fn consume_pinned(
+        &mut self,
+        mut f: impl FnMut(&[u8]) -> LinuxResult<usize>,
+    ) -> Option<LinuxResult<usize>> {
+        if !should_pin(self.ptr as usize, self.len) {
+            return None;
+        }
+        let start = VirtAddr::from_ptr_of(self.ptr);
+        let result = with_user_pages(start, self.len, MappingFlags::READ, |chunk| f(chunk))?;
+        if let Ok(consumed) = result {
+            self.ptr = self.ptr.wrapping_add(consumed);
+            self.len -= consumed;
+        }
+        Some(result)
+    }
+}
+
+impl Read for UserBuf {
+    fn read(&mut self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let len = buf.len().min(self.len);
+        let read = copy_from_user(self.ptr, &mut buf[..len]);
+        if read < len {
+            self.faulted = true;
+            self.len = 0;
+        } else {
+            self.len -= read;
+        }
+        self.ptr = self.ptr.wrapping_add(read);
+        Ok(read)
+    }
+}
+
+impl Buf for UserBuf {
+    fn remaining(&self) -> usize {
+        self.len
+    }
+}
+
+/// A user buffer to put data into, which ends at the first page that
+/// faults. See [`partial_transfer`].
+pub struct UserBufMut {
+    ptr: *mut u8,
+    len: usize,
+    faulted: bool,
+}
+
+impl UserBufMut {
+    pub fn new(ptr: *mut u8, len: usize) -> Self {
+        Self {
+            ptr,
+            len,
+            faulted: false,
+        }
+    }
+
+    /// Whether the buffer ended early because of a fault.
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Lets `f` put the data straight into the user pages, see
+    /// [`with_user_pages`]. Returns `None` if the buffer is not worth
+    /// pinning or cannot be pinned, so that it is to be copied instead.
+    pub fn fill_pinned(
+        &mut self,
+        f: impl FnMut(&mut [u8]) -> LinuxResult<usize>,
+    ) -> Option<LinuxResult<usize>> {
+        if !should_pin(self.ptr as usize, self.len) {
+            return None;
+        }
+        let start = VirtAddr::from_mut_ptr_of(self.ptr);
+        let result = with_user_pages(start, self.len, MappingFlags::WRITE, f)?;
+        if let Ok(filled) = result {
+            self.ptr = self.ptr.wrapping_add(filled);
+            self.len -= filled;
+        }
+        Some(result)
+    }
+}
+
+impl Write for UserBufMut {
+    fn write(&mut self, buf: &[u8]) -> LinuxResult<usize> {
+        let len = buf.len().min(self.len);
+        let written = copy_to_user(self.ptr, &buf[..len]);
+        if written < len {
+            self.faulted = true;
+            self.len = 0;
+        } else {
+            self.len -= written;
+        }
+        self.ptr = self.ptr.wrapping_add(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> LinuxResult {
+        Ok(())
+    }
+}
+
+impl BufMut for UserBufMut {
+    fn remaining_mut(&self) -> usize {
+        self.len
+    }
+}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, AnyBitPattern)]
 pub struct IoVec {
@@ -78,17 +268,33 @@ impl IoVectorBuf {
             inner: self,
             start: 0,
             offset: 0,
+            faulted: false,
         }
     }
 }
 
+/// An I/O vector as a buffer, which ends at the first page that faults. See
+/// [`partial_transfer`].
 pub struct IoVectorBufIo {
     inner: IoVectorBuf,
     start: usize,
     offset: usize,
+    faulted: bool,
 }
 
 impl IoVectorBufIo {
+    /// Whether the buffer ended early because of a fault.
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+
+    /// Ends the buffer after a fault.
+    fn fault(&mut self) {
+        self.faulted = true;
+        self.inner.len = 0;
+        self.start = self.inner.iovcnt;
+    }
+
     fn skip_empty(&mut self) -> LinuxResult<()> {
         while self.start < self.inner.iovcnt {
             let iov = self.inner.iovs.wrapping_add(self.start).vm_read()?;
@@ -115,12 +321,17 @@ impl Read for IoVectorBufIo {
             if len == 0 {
                 break;
             }
-            vm_read_slice(iov.iov_base.wrapping_add(self.offset), unsafe {
-                mem::transmute::<&mut [u8], &mut [MaybeUninit<u8>]>(&mut buf[count..count + len])
-            })?;
+            let read = copy_from_user(
+                iov.iov_base.wrapping_add(self.offset),
+                &mut buf[count..count + len],
+            );
+            count += read;
+            if read < len {
+                self.fault();
+                break;
+            }
             self.offset += len;
             self.inner.len -= len;
-            count += len;
         }
         Ok(count)
     }
@@ -145,13 +356,17 @@ impl Write for IoVectorBufIo {
             if len == 0 {
                 break;
             }
-            vm_write_slice(
+            let written = copy_to_user(
                 iov.iov_base.wrapping_add(self.offset),
                 &buf[count..count + len],
-            )?;
+            );
+            count += written;
+            if written < len {
+                self.fault();
+                break;
+            }
             self.offset += len;
             self.inner.len -= len;
-            count += len;
         }
         Ok(count)
     }
@@ -166,3 +381,43 @@ impl BufMut for IoVectorBufIo {
         self.inner.len
     }
 }
+
+/// A [`Buf`] that yields at most `limit` bytes of another one.
+pub struct TakeBuf<'a, B> {
+    inner: &'a mut B,
+    limit: usize,
+}
+
+impl<'a, B> TakeBuf<'a, B> {
+    pub fn new(inner: &'a mut B, limit: usize) -> Self {
+        Self { inner, limit }
+    }
+}
+
+impl<B: Buf + Read> Read for TakeBuf<'_, B> {
+    fn read(&mut self, buf: &mut [u8]) -> LinuxResult<usize> {
+        let len = buf.len().min(self.limit);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.limit -= read;
+        Ok(read)
+    }
+}
+
+impl<B: Buf + Read> Buf for TakeBuf<'_, B> {
+    fn remaining(&self) -> usize {
+        self.inner.remaining().min(self.limit)
+    }
+
+    fn consume(&mut self, mut f: impl FnMut(&[u8]) -> LinuxResult<usize>) -> LinuxResult<usize> {
+        let limit = &mut self.limit;
+        self.inner.consume(|chunk| {
+            let len = chunk.len().min(*limit);
+            if len == 0 {
+                return Ok(0);
+            }
+            let consumed = f(&chunk[..len])?;
+            *limit -= consumed;
+            Ok(consumed)
+        })
+    }
+}
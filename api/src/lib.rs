@@ -12,8 +12,14 @@ extern crate axlog;
 extern crate alloc;
 
 pub mod file;
+#[cfg(feature = "gdbstub")]
+pub mod gdbstub;
 pub mod io;
+pub mod logging;
 pub mod mm;
+pub mod netif;
+#[cfg(feature = "profile")]
+pub mod profile;
 pub mod signal;
 pub mod socket;
 pub mod syscall;
@@ -24,17 +30,36 @@ pub mod vfs;
 
 /// Initialize.
 pub fn init() {
-    if axconfig::plat::CPU_NUM > 1 {
-        panic!("SMP is not supported");
-    }
+    logging::init();
+
+    #[cfg(feature = "cpu-topology")]
+    starry_core::task::set_cpu_capacity(vfs::cpu_capacity());
+
+    info!("Initialize sysctls...");
+    starry_core::sysctl::init();
+    logging::register_sysctl();
+    file::register_sysctls();
+    syscall::register_sysctls();
+
     info!("Initialize VFS...");
     vfs::mount_all().expect("Failed to mount vfs");
 
+    info!("Initialize vDSO...");
+    starry_core::mm::vdso::init();
+
     info!("Initialize /proc/interrupts...");
     axtask::register_timer_callback(|_| {
         time::inc_irq_cnt();
+        time::account_tick();
+        starry_core::mm::vdso::update();
     });
 
+    #[cfg(feature = "profile")]
+    {
+        info!("Initialize /proc/profile...");
+        profile::init();
+    }
+
     info!("Initialize alarm...");
     starry_core::time::spawn_alarm_task();
 }
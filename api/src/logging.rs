@@ -0,0 +1,462 @@
+//! Control over the kernel log: its output, its level, set through
+//! `/proc/sys/kernel/printk`, rate limiting of repeated warnings, and the ring
+//! buffer read through `/dev/kmsg` and `syslog`.
+//!
+//! The messages of the `log` macros are not written to the console by the
+//! CPU logging them, which would make it wait for the UART and for the other
+//! CPUs logging. Every CPU appends them to a buffer of its own instead, which
+//! only it writes to, and a background task copies the buffers out to the
+//! console. Errors are written out at once, along with what came before them
+//! on the same CPU, so that they are seen even if the system goes down right
+//! after. A CPU whose buffer is full drops messages, and the flush reports how
+//! many. Every message is also kept in the ring buffer, where `dmesg` finds it.
+
+use alloc::{
+    collections::VecDeque,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{
+    cell::UnsafeCell,
+    fmt::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+    time::Duration,
+};
+
+use axerrno::LinuxError;
+use axhal::{percpu::this_cpu_id, time::monotonic_time};
+use axio::PollSet;
+use kernel_guard::NoPreemptIrqSave;
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use starry_core::{sysctl, task::spawn_background};
+
+/// The size of the log buffer of every CPU.
+const CPU_LOG_LEN: usize = 16 * 1024;
+/// How often the background task writes the buffers out.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The log output of a CPU that has not been written to the console yet.
+///
+/// Only the CPU itself appends to it, with interrupts disabled, and only the
+/// holder of [`FLUSH`] takes from it, so the two positions are all the
+/// synchronization needed.
+struct CpuLog {
+    buf: UnsafeCell<[u8; CPU_LOG_LEN]>,
+    /// How many bytes were appended, ever.
+    head: AtomicUsize,
+    /// How many bytes were written out, ever.
+    tail: AtomicUsize,
+    /// How many messages were dropped since the last flush.
+    dropped: AtomicUsize,
+}
+
+// SAFETY: the bytes between `tail` and `head` are only read, and the others
+// only written by the CPU owning the buffer, see above.
+unsafe impl Sync for CpuLog {}
+
+impl CpuLog {
+    const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new([0; CPU_LOG_LEN]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends `msg` if it fits, to be called on the owning CPU with
+    /// interrupts disabled.
+    fn push(&self, msg: &[u8]) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if CPU_LOG_LEN - (head - tail) < msg.len() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let buf = self.buf.get() as *mut u8;
+        for (i, &byte) in msg.iter().enumerate() {
+            // SAFETY: the byte is free, and nobody else appends.
+            unsafe { buf.add((head + i) % CPU_LOG_LEN).write(byte) };
+        }
+        self.head.store(head + msg.len(), Ordering::Release);
+    }
+
+    /// Writes what was appended to the console, to be called holding
+    /// [`FLUSH`].
+    fn flush(&self) {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        if head == tail {
+            return;
+        }
+        // SAFETY: the bytes from `tail` to `head` are not written to until
+        // `tail` moves past them.
+        let buf = unsafe { &*self.buf.get() };
+        let (start, end) = (tail % CPU_LOG_LEN, head % CPU_LOG_LEN);
+        if start < end {
+            axhal::console::write_bytes(&buf[start..end]);
+        } else {
+            axhal::console::write_bytes(&buf[start..]);
+            axhal::console::write_bytes(&buf[..end]);
+        }
+        self.tail.store(head, Ordering::Release);
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let msg = format!("[log] {dropped} messages dropped\n");
+            axhal::console::write_bytes(msg.as_bytes());
+        }
+    }
+}
+
+static CPU_LOGS: [CpuLog; axconfig::plat::CPU_NUM] =
+    [const { CpuLog::new() }; axconfig::plat::CPU_NUM];
+
+/// Held while writing the buffers out, which keeps the output of a CPU in
+/// order.
+static FLUSH: SpinNoIrq<()> = SpinNoIrq::new(());
+
+/// Writes the buffers of all CPUs to the console.
+fn flush_all() {
+    let _flush = FLUSH.lock();
+    for log in &CPU_LOGS {
+        log.flush();
+    }
+}
+
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = monotonic_time();
+        let tid = axtask::current_may_uninit().map_or(0, |curr| curr.id().as_u64());
+        let mut msg = String::new();
+        let _ = writeln!(
+            msg,
+            "[{:4}.{:06} {}:{} {:5} {}] {}",
+            now.as_secs(),
+            now.subsec_micros(),
+            this_cpu_id(),
+            tid,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        {
+            let _guard = NoPreemptIrqSave::new();
+            CPU_LOGS[this_cpu_id()].push(msg.as_bytes());
+        }
+        add_record(syslog_level(record.level()), &record.args().to_string());
+        if record.level() == Level::Error {
+            flush_all();
+        }
+    }
+
+    fn flush(&self) {
+        flush_all();
+    }
+}
+
+static LOGGER: Logger = Logger;
+
+/// Takes the output of the `log` macros over from axlog, and starts the task
+/// writing it out.
+pub fn init() {
+    axlog::set_logger(&LOGGER);
+    spawn_background(
+        || {
+            loop {
+                axtask::sleep(FLUSH_INTERVAL);
+                flush_all();
+            }
+        },
+        "log-flush".into(),
+    );
+}
+
+/// The `console_loglevel` of Linux for `level`: messages less severe than it
+/// are shown. `trace` has no Linux level, so it gets the one after `debug`.
+fn console_loglevel_of(level: LevelFilter) -> u8 {
+    match level {
+        LevelFilter::Off => 1,
+        LevelFilter::Error => 4,
+        LevelFilter::Warn => 5,
+        LevelFilter::Info => 7,
+        LevelFilter::Debug => 8,
+        LevelFilter::Trace => 9,
+    }
+}
+
+/// Returns the `console_loglevel` matching the current log level.
+pub fn console_loglevel() -> u8 {
+    console_loglevel_of(log::max_level())
+}
+
+/// Sets the log level to show the messages less severe than `level`, a Linux
+/// `console_loglevel`.
+pub fn set_console_loglevel(level: u8) {
+    let filter = match level {
+        0..=3 => LevelFilter::Off,
+        4 => LevelFilter::Error,
+        5 | 6 => LevelFilter::Warn,
+        7 => LevelFilter::Info,
+        8 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    info!("Log level set to {}", filter);
+    log::set_max_level(filter);
+}
+
+/// Registers `kernel.printk`, of which only the first level, the one of the
+/// console, can be changed.
+pub fn register_sysctl() {
+    sysctl::register_writable(
+        "kernel.printk",
+        || format!("{}\t4\t1\t7", console_loglevel()),
+        |value| {
+            if let Some(level) = value.split_ascii_whitespace().next() {
+                set_console_loglevel(level.parse().map_err(|_| LinuxError::EINVAL)?);
+            }
+            Ok(())
+        },
+    );
+}
+
+struct RateLimitState {
+    /// When the current interval began.
+    begin: Option<Duration>,
+    printed: u32,
+    missed: u32,
+}
+
+/// Limits how often a message is logged, like `printk_ratelimited` of Linux:
+/// at most [`RateLimit::BURST`] times every [`RateLimit::INTERVAL`].
+pub struct RateLimit {
+    name: &'static str,
+    state: SpinNoIrq<RateLimitState>,
+}
+
+impl RateLimit {
+    const BURST: u32 = 10;
+    const INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Creates a limit, whose suppressed messages are reported under `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: SpinNoIrq::new(RateLimitState {
+                begin: None,
+                printed: 0,
+                missed: 0,
+            }),
+        }
+    }
+
+    /// Whether the message may be logged now.
+    pub fn allow(&self) -> bool {
+        let now = monotonic_time();
+        let mut state = self.state.lock();
+        let mut missed = 0;
+        if state
+            .begin
+            .is_none_or(|begin| now.saturating_sub(begin) >= Self::INTERVAL)
+        {
+            missed = state.missed;
+            *state = RateLimitState {
+                begin: Some(now),
+                printed: 0,
+                missed: 0,
+            };
+        }
+        let allowed = state.printed < Self::BURST;
+        if allowed {
+            state.printed += 1;
+        } else {
+            state.missed += 1;
+        }
+        drop(state);
+        if missed > 0 {
+            warn!("{}: {} callbacks suppressed", self.name, missed);
+        }
+        allowed
+    }
+}
+
+/// The size of the ring buffer, counting the text of the records.
+pub const LOG_BUF_LEN: usize = 128 * 1024;
+
+/// A message in the ring buffer.
+#[derive(Clone)]
+pub struct LogRecord {
+    /// The sequence number, which increases by one with every record.
+    pub seq: u64,
+    /// The syslog facility and level, `facility << 3 | level`.
+    pub prio: u8,
+    /// When the record was added, since boot.
+    pub time: Duration,
+    /// The message, without a trailing newline.
+    pub text: String,
+}
+
+impl LogRecord {
+    /// Formats the record the way `syslog(2)` returns it.
+    pub fn to_syslog(&self) -> String {
+        alloc::format!(
+            "<{}>[{:5}.{:06}] {}\n",
+            self.prio,
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.text
+        )
+    }
+}
+
+struct LogBuffer {
+    records: VecDeque<LogRecord>,
+    /// The sequence number of the next record.
+    next_seq: u64,
+    /// The first record `syslog` reads, moved by clearing.
+    clear_seq: u64,
+    /// The first record not yet consumed by `SYSLOG_ACTION_READ`.
+    syslog_seq: u64,
+    len: usize,
+}
+
+static LOG_BUFFER: SpinNoIrq<LogBuffer> = SpinNoIrq::new(LogBuffer {
+    records: VecDeque::new(),
+    next_seq: 0,
+    clear_seq: 0,
+    syslog_seq: 0,
+    len: 0,
+});
+
+lazy_static! {
+    static ref LOG_POLL: PollSet = PollSet::new();
+}
+
+/// The syslog level of a log level.
+fn syslog_level(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Adds a record with the syslog priority `prio` to the ring buffer,
+/// dropping the oldest records to make room.
+pub fn add_record(prio: u8, text: &str) {
+    let text = text.trim_end_matches('\n');
+    let mut buf = LOG_BUFFER.lock();
+    while buf.len + text.len() > LOG_BUF_LEN
+        && let Some(old) = buf.records.pop_front()
+    {
+        buf.len -= old.text.len();
+    }
+    let seq = buf.next_seq;
+    buf.next_seq += 1;
+    buf.len += text.len();
+    buf.records.push_back(LogRecord {
+        seq,
+        prio,
+        time: monotonic_time(),
+        text: text.to_string(),
+    });
+    drop(buf);
+    LOG_POLL.wake();
+}
+
+/// Returns the record numbered `seq`, `Err` with the first record still kept
+/// if it has been dropped, or `Ok(None)` if there is no such record yet.
+pub fn read_record(seq: u64) -> Result<Option<LogRecord>, u64> {
+    let buf = LOG_BUFFER.lock();
+    let first = buf.records.front().map_or(buf.next_seq, |it| it.seq);
+    if seq < first {
+        return Err(first);
+    }
+    Ok(buf.records.get((seq - first) as usize).cloned())
+}
+
+/// Returns the sequence numbers of the first record kept, the first one after
+/// the last clear and the next one.
+pub fn record_range() -> (u64, u64, u64) {
+    let buf = LOG_BUFFER.lock();
+    let first = buf.records.front().map_or(buf.next_seq, |it| it.seq);
+    (first, buf.clear_seq.max(first), buf.next_seq)
+}
+
+/// Makes `syslog` skip the records up to now.
+pub fn clear_records() {
+    let mut buf = LOG_BUFFER.lock();
+    buf.clear_seq = buf.next_seq;
+}
+
+/// Returns the newest records since the last clear, as many as fit into
+/// `len` bytes, for `SYSLOG_ACTION_READ_ALL`.
+pub fn read_all(len: usize) -> String {
+    let buf = LOG_BUFFER.lock();
+    let lines = buf
+        .records
+        .iter()
+        .filter(|it| it.seq >= buf.clear_seq)
+        .map(LogRecord::to_syslog)
+        .collect::<Vec<_>>();
+    drop(buf);
+    let mut total = 0;
+    let fit = lines
+        .iter()
+        .rev()
+        .take_while(|it| {
+            total += it.len();
+            total <= len
+        })
+        .count();
+    lines[lines.len() - fit..].concat()
+}
+
+/// Takes the records that `SYSLOG_ACTION_READ` has not consumed yet, as long
+/// as their text fits into `len` bytes.
+pub fn take_unread(len: usize) -> String {
+    let mut buf = LOG_BUFFER.lock();
+    let first = buf.records.front().map_or(buf.next_seq, |it| it.seq);
+    let start = buf.syslog_seq.max(first);
+    let mut out = String::new();
+    let mut seq = start;
+    for record in buf.records.range((start - first) as usize..) {
+        let text = record.to_syslog();
+        if out.len() + text.len() > len {
+            break;
+        }
+        out.push_str(&text);
+        seq += 1;
+    }
+    buf.syslog_seq = seq;
+    out
+}
+
+/// Returns the number of bytes `SYSLOG_ACTION_READ` has yet to consume, which
+/// is 0 if and only if there are no such records.
+pub fn unread_len() -> usize {
+    let buf = LOG_BUFFER.lock();
+    buf.records
+        .iter()
+        .filter(|it| it.seq >= buf.syslog_seq)
+        .map(|it| it.to_syslog().len())
+        .sum()
+}
+
+/// Registers `waker` to be woken up when a record is added.
+pub fn register_record_waker(waker: &Waker) {
+    LOG_POLL.register(waker);
+}
@@ -1,15 +1,24 @@
-use alloc::string::String;
-use core::{alloc::Layout, ffi::c_char, hint::unlikely, mem::transmute, ptr, slice, str};
+use alloc::{string::String, vec::Vec};
+use core::{
+    alloc::Layout,
+    ffi::c_char,
+    hint::unlikely,
+    mem::transmute,
+    ptr, slice, str,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
+    mem::phys_to_virt,
     paging::MappingFlags,
     trap::{PAGE_FAULT, register_trap_handler},
 };
+use axmm::backend::Backend;
 use axtask::current;
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
-    mm::{access_user_memory, is_accessing_user_memory},
+    mm::{access_user_memory, is_accessing_user_memory, resident_pages},
     task::AsThread,
 };
 use starry_vm::vm_load_until_nul;
@@ -21,7 +30,8 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
 
     if !aspace.can_access_range(start, layout.size(), access_flags) {
         return Err(LinuxError::EFAULT);
@@ -29,11 +39,100 @@ fn check_region(start: VirtAddr, layout: Layout, access_flags: MappingFlags) ->
 
     let page_start = start.align_down_4k();
     let page_end = (start + layout.size()).align_up_4k();
-    aspace.populate_area(page_start, page_end - page_start, access_flags)?;
+    let size = page_end - page_start;
+    let resident = resident_pages(&aspace, page_start, size);
+    aspace.populate_area(page_start, size, access_flags)?;
+    proc_data.add_rss(resident_pages(&aspace, page_start, size) - resident);
 
     Ok(())
 }
 
+/// Runs `f` on the `len` bytes of user memory at `start` through the kernel
+/// mapping of their pages, one physically contiguous run at a time, so that
+/// a transfer goes straight into or out of them rather than through a
+/// bounce buffer.
+///
+/// The pages are faulted in for `access_flags` first, and stay pinned while
+/// `f` runs, so that they are not unmapped or shared with a forked child in
+/// the meantime, but the address space is not locked and other threads keep
+/// faulting in their pages. Stops at the first run that `f` does not use up,
+/// and returns how much it used.
+///
+/// Returns `None` if the memory cannot be pinned, e.g. because it is not
+/// all accessible or maps device memory, for the caller to copy it instead.
+pub fn with_user_pages(
+    start: VirtAddr,
+    len: usize,
+    access_flags: MappingFlags,
+    mut f: impl FnMut(&mut [u8]) -> LinuxResult<usize>,
+) -> Option<LinuxResult<usize>> {
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
+    if !aspace.can_access_range(start, len, access_flags) {
+        return None;
+    }
+    let end = start + len;
+    let mut addr = start;
+    while addr < end {
+        let area = aspace.find_area(addr)?;
+        if matches!(area.backend(), Backend::Linear(_)) {
+            return None;
+        }
+        addr = area.end();
+    }
+
+    let page_start = start.align_down_4k();
+    let size = end.align_up_4k() - page_start;
+    let resident = resident_pages(&aspace, page_start, size);
+    aspace.populate_area(page_start, size, access_flags).ok()?;
+    proc_data.add_rss(resident_pages(&aspace, page_start, size) - resident);
+
+    let mut runs = Vec::new();
+    let mut mapped = 0;
+    while mapped < len {
+        let vaddr = start + mapped;
+        let Ok((paddr, _, page_size)) = aspace.page_table().query(vaddr) else {
+            break;
+        };
+        let page_size = page_size as usize;
+        let mut run = (page_size - vaddr.as_usize() % page_size).min(len - mapped);
+        while mapped + run < len
+            && aspace
+                .page_table()
+                .query(vaddr + run)
+                .is_ok_and(|(next, ..)| next == paddr + run)
+        {
+            run = (run + PAGE_SIZE_4K).min(len - mapped);
+        }
+        runs.push((paddr, run));
+        mapped += run;
+    }
+    // Left to a copy if nothing can be transferred.
+    if runs.is_empty() {
+        return None;
+    }
+    let _pin = proc_data.pins.read().pin(start, mapped);
+    drop(aspace);
+
+    let mut done = 0;
+    for (paddr, run) in runs {
+        // SAFETY: the run is mapped for `access_flags` and pinned, so its
+        // pages are neither freed nor reused until `f` returns.
+        let chunk = unsafe { slice::from_raw_parts_mut(phys_to_virt(paddr).as_mut_ptr(), run) };
+        let used = match f(chunk) {
+            Ok(used) => used,
+            Err(_) if done > 0 => break,
+            Err(err) => return Some(Err(err)),
+        };
+        done += used;
+        if used < run {
+            break;
+        }
+    }
+    Some(Ok(done))
+}
+
 fn check_null_terminated<T: PartialEq + Default>(
     start: VirtAddr,
     access_flags: MappingFlags,
@@ -245,10 +344,7 @@ fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
         return false;
     };
 
-    thr.proc_data
-        .aspace
-        .lock()
-        .handle_page_fault(vaddr, access_flags)
+    thr.proc_data.handle_page_fault(vaddr, access_flags)
 }
 
 pub fn vm_load_string(ptr: *const c_char) -> LinuxResult<String> {
@@ -256,3 +352,18 @@ pub fn vm_load_string(ptr: *const c_char) -> LinuxResult<String> {
     let bytes = vm_load_until_nul(ptr as *const u8)?;
     String::from_utf8(bytes).map_err(|_| LinuxError::EILSEQ)
 }
+
+/// Atomically updates the `u32` at `ptr` in user memory with `f`, like
+/// [`AtomicU32::fetch_update`] does.
+///
+/// The page is faulted in for writing beforehand, so that the update itself
+/// does not need to take a page fault.
+pub fn vm_update_u32(
+    ptr: *mut u32,
+    f: impl FnMut(u32) -> Option<u32>,
+) -> LinuxResult<Result<u32, u32>> {
+    let word: &AtomicU32 = UserPtr::<AtomicU32>::from(ptr as usize).get_as_mut()?;
+    Ok(access_user_memory(|| {
+        word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, f)
+    }))
+}
@@ -0,0 +1,212 @@
+//! The network interfaces, as reported by netlink and the `SIOCGIF*` ioctls.
+//!
+//! Besides the loopback device, these are the Ethernet devices the stack
+//! drives, named `eth0`, `eth1` and so on in the order it found them. Only
+//! `eth0` has an address, the one the stack is configured with at build time
+//! through `AX_IP`, if any.
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::net::Ipv4Addr;
+
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::{
+    if_arp::{ARPHRD_ETHER, ARPHRD_LOOPBACK, ifconf},
+    ioctl::{
+        SIOCGIFADDR, SIOCGIFBRDADDR, SIOCGIFCONF, SIOCGIFFLAGS, SIOCGIFHWADDR, SIOCGIFINDEX,
+        SIOCGIFMTU, SIOCGIFNAME, SIOCGIFNETMASK,
+    },
+    net::{
+        AF_INET, IFNAMSIZ,
+        net_device_flags::{IFF_BROADCAST, IFF_LOOPBACK, IFF_LOWER_UP, IFF_RUNNING, IFF_UP},
+    },
+};
+use starry_vm::{VmMutPtr, VmPtr};
+
+/// The prefix length of the address of `eth0`, which the stack assumes too.
+const ETH_PREFIX_LEN: u8 = 24;
+
+/// A network interface.
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    /// The `IFF_*` flags.
+    pub flags: u32,
+    /// The `ARPHRD_*` hardware type.
+    pub hw_type: u16,
+    pub mtu: u32,
+    pub mac: [u8; 6],
+    /// The IPv4 address, or `UNSPECIFIED` if there is none.
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+impl Interface {
+    pub fn has_addr(&self) -> bool {
+        !self.addr.is_unspecified()
+    }
+
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from_bits(
+            u32::MAX
+                .checked_shl(32 - self.prefix_len as u32)
+                .unwrap_or(0),
+        )
+    }
+
+    /// Returns the broadcast address, if the interface has one.
+    pub fn broadcast(&self) -> Option<Ipv4Addr> {
+        (self.flags & IFF_BROADCAST as u32 != 0 && self.has_addr())
+            .then(|| Ipv4Addr::from_bits(self.addr.to_bits() | !self.netmask().to_bits()))
+    }
+}
+
+/// Lists the network interfaces, by index.
+pub fn interfaces() -> Vec<Interface> {
+    let running = (IFF_UP as u32) | (IFF_RUNNING as u32) | (IFF_LOWER_UP as u32);
+    let mut interfaces = vec![Interface {
+        index: 1,
+        name: "lo".into(),
+        flags: running | IFF_LOOPBACK as u32,
+        hw_type: ARPHRD_LOOPBACK as u16,
+        mtu: 65536,
+        mac: [0; 6],
+        addr: Ipv4Addr::LOCALHOST,
+        prefix_len: 8,
+    }];
+    let eth0_addr = option_env!("AX_IP").and_then(|ip| ip.parse().ok());
+    for (i, dev) in axnet::devices().into_iter().enumerate() {
+        let addr = eth0_addr.filter(|_| i == 0);
+        interfaces.push(Interface {
+            index: i as u32 + 2,
+            name: format!("eth{i}"),
+            flags: running | IFF_BROADCAST as u32,
+            hw_type: ARPHRD_ETHER as u16,
+            mtu: dev.mtu as u32,
+            mac: dev.mac,
+            addr: addr.unwrap_or(Ipv4Addr::UNSPECIFIED),
+            prefix_len: if addr.is_some() { ETH_PREFIX_LEN } else { 0 },
+        });
+    }
+    interfaces
+}
+
+/// `struct ifreq`, with its union as bytes. The one of `linux_raw_sys` is too
+/// large, as its `sockaddr` is a `sockaddr_storage`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct IfReq {
+    name: [u8; IFNAMSIZ as usize],
+    data: [u8; 24],
+}
+
+impl IfReq {
+    fn new(iface: &Interface) -> Self {
+        let mut req = Self {
+            name: [0; IFNAMSIZ as usize],
+            data: [0; 24],
+        };
+        req.name[..iface.name.len()].copy_from_slice(iface.name.as_bytes());
+        req
+    }
+
+    fn name(&self) -> &[u8] {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(self.name.len());
+        &self.name[..len]
+    }
+
+    fn set_int(&mut self, value: i32) {
+        self.data[..4].copy_from_slice(&value.to_ne_bytes());
+    }
+
+    /// Sets a `sockaddr` of family `family`, whose data starts with `data`.
+    fn set_addr(&mut self, family: u16, data: &[u8]) {
+        self.data[..2].copy_from_slice(&family.to_ne_bytes());
+        self.data[2..2 + data.len()].copy_from_slice(data);
+    }
+
+    /// Sets a `sockaddr_in` with address `addr` and port zero.
+    fn set_inet(&mut self, addr: Ipv4Addr) {
+        let mut data = [0; 6];
+        data[2..].copy_from_slice(&addr.octets());
+        self.set_addr(AF_INET as u16, &data);
+    }
+}
+
+/// Handles the `SIOCGIF*` ioctls, which any socket accepts.
+pub fn ioctl(cmd: u32, arg: usize) -> LinuxResult<usize> {
+    if cmd == SIOCGIFCONF {
+        // FIXME: AnyBitPattern
+        let mut conf = unsafe { (arg as *const ifconf).vm_read_uninit()?.assume_init() };
+        // Only interfaces with an address are listed, as on Linux.
+        let interfaces = interfaces()
+            .into_iter()
+            .filter(Interface::has_addr)
+            .collect::<Vec<_>>();
+        let buf = unsafe { conf.ifc_ifcu.ifcu_req } as *mut IfReq;
+        if buf.is_null() {
+            // Only the size needed is asked for.
+            conf.ifc_len = (interfaces.len() * size_of::<IfReq>()) as _;
+        } else {
+            let count = (conf.ifc_len.max(0) as usize / size_of::<IfReq>()).min(interfaces.len());
+            for (i, iface) in interfaces[..count].iter().enumerate() {
+                let mut req = IfReq::new(iface);
+                req.set_inet(iface.addr);
+                buf.wrapping_add(i).vm_write(req)?;
+            }
+            conf.ifc_len = (count * size_of::<IfReq>()) as _;
+        }
+        (arg as *mut ifconf).vm_write(conf)?;
+        return Ok(0);
+    }
+
+    if !matches!(
+        cmd,
+        SIOCGIFNAME
+            | SIOCGIFINDEX
+            | SIOCGIFFLAGS
+            | SIOCGIFADDR
+            | SIOCGIFNETMASK
+            | SIOCGIFBRDADDR
+            | SIOCGIFMTU
+            | SIOCGIFHWADDR
+    ) {
+        return Err(LinuxError::ENOTTY);
+    }
+    // FIXME: AnyBitPattern
+    let mut req = unsafe { (arg as *const IfReq).vm_read_uninit()?.assume_init() };
+    let iface = interfaces()
+        .into_iter()
+        .find(|iface| {
+            if cmd == SIOCGIFNAME {
+                let index = i32::from_ne_bytes(req.data[..4].try_into().unwrap());
+                iface.index as i32 == index
+            } else {
+                req.name() == iface.name.as_bytes()
+            }
+        })
+        .ok_or(LinuxError::ENODEV)?;
+    if matches!(cmd, SIOCGIFADDR | SIOCGIFNETMASK | SIOCGIFBRDADDR) && !iface.has_addr() {
+        return Err(LinuxError::EADDRNOTAVAIL);
+    }
+    match cmd {
+        SIOCGIFNAME => req.name = IfReq::new(&iface).name,
+        SIOCGIFINDEX => req.set_int(iface.index as _),
+        SIOCGIFFLAGS => {
+            // `ifr_flags` is a short, so the flags from `IFF_LOWER_UP` on are
+            // left out.
+            req.data[..2].copy_from_slice(&(iface.flags as u16).to_ne_bytes());
+        }
+        SIOCGIFADDR => req.set_inet(iface.addr),
+        SIOCGIFNETMASK => req.set_inet(iface.netmask()),
+        SIOCGIFBRDADDR => req.set_inet(iface.broadcast().unwrap_or(Ipv4Addr::UNSPECIFIED)),
+        SIOCGIFMTU => req.set_int(iface.mtu as _),
+        SIOCGIFHWADDR => req.set_addr(iface.hw_type, &iface.mac),
+        _ => unreachable!(),
+    }
+    (arg as *mut IfReq).vm_write(req)?;
+    Ok(0)
+}
@@ -0,0 +1,173 @@
+//! A sampling profiler, exposed as `/proc/profile`.
+//!
+//! Every timer tick records the PC it interrupted, in kernel or in user space,
+//! rounded down to a bucket of [`BUCKET_SIZE`] bytes. Reading `/proc/profile`
+//! lists the buckets in the folded format of `flamegraph.pl`, hottest first:
+//!
+//! ```text
+//! kernel;0xffff000040a1b2c0 42
+//! user;busybox;0x4a5b0 12
+//! ```
+//!
+//! The addresses are meant to be symbolized on the host. Writing anything to
+//! the file clears the samples.
+
+#[cfg(not(any(
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+)))]
+compile_error!("the interrupted PC cannot be sampled on this architecture");
+
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use core::{arch::asm, fmt::Write};
+
+use axtask::current;
+use kspin::SpinNoIrq;
+use starry_core::task::{AsThread, get_process_data};
+use starry_process::Pid;
+
+/// The granularity of the samples, in bytes.
+const BUCKET_SIZE: usize = 16;
+/// How many distinct buckets are kept. Further ones are only counted.
+const SLOTS: usize = 8192;
+
+#[derive(Clone, Copy, Default)]
+struct Slot {
+    /// The sampled process, or 0 for the kernel.
+    pid: Pid,
+    bucket: usize,
+    /// Zero if the slot is free.
+    count: u32,
+}
+
+struct Profile {
+    slots: Box<[Slot]>,
+    dropped: u64,
+}
+
+impl Profile {
+    fn record(&mut self, pid: Pid, bucket: usize) {
+        let hash = (bucket / BUCKET_SIZE) ^ (pid as usize).wrapping_mul(0x9e37_79b9);
+        for i in 0..SLOTS {
+            let slot = &mut self.slots[(hash + i) % SLOTS];
+            if slot.count == 0 {
+                *slot = Slot {
+                    pid,
+                    bucket,
+                    count: 1,
+                };
+                return;
+            }
+            if slot.pid == pid && slot.bucket == bucket {
+                slot.count = slot.count.saturating_add(1);
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+}
+
+/// Allocated up front by [`init`], since ticks cannot allocate.
+static PROFILE: SpinNoIrq<Option<Profile>> = SpinNoIrq::new(None);
+
+/// Returns the PC the current interrupt came from, and whether it is in user
+/// space.
+///
+/// This must be called while handling the interrupt, before anything could
+/// take another exception and overwrite the registers.
+fn interrupted_pc() -> (usize, bool) {
+    let (pc, status): (usize, usize);
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        asm!("mrs {}, elr_el1", "mrs {}, spsr_el1", out(reg) pc, out(reg) status);
+        // M[3:0] is EL0t.
+        (pc, status & 0xf == 0)
+    }
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        asm!("csrr {}, sepc", "csrr {}, sstatus", out(reg) pc, out(reg) status);
+        // SPP is clear.
+        (pc, status & (1 << 8) == 0)
+    }
+    #[cfg(target_arch = "loongarch64")]
+    unsafe {
+        asm!("csrrd {}, 0x6", "csrrd {}, 0x1", out(reg) pc, out(reg) status);
+        // PPLV is 3 in ERA and PRMD.
+        (pc, status & 0x3 == 3)
+    }
+}
+
+/// Takes a sample. Called on every timer tick.
+pub fn sample() {
+    let (pc, user) = interrupted_pc();
+    let pid = if user {
+        match current().try_as_thread() {
+            Some(thr) => thr.proc_data.proc.pid(),
+            None => return,
+        }
+    } else {
+        0
+    };
+    if let Some(profile) = PROFILE.lock().as_mut() {
+        profile.record(pid, pc & !(BUCKET_SIZE - 1));
+    }
+}
+
+/// Clears the samples.
+pub fn reset() {
+    if let Some(profile) = PROFILE.lock().as_mut() {
+        profile.slots.fill(Slot::default());
+        profile.dropped = 0;
+    }
+}
+
+fn process_name(pid: Pid) -> String {
+    match get_process_data(pid) {
+        Ok(data) => {
+            let exe_path = data.exe_path.read();
+            String::from(exe_path.rsplit('/').next().unwrap_or_default())
+        }
+        Err(_) => format!("[{pid}]"),
+    }
+}
+
+/// Renders the samples for `/proc/profile`.
+pub fn render() -> String {
+    let (mut samples, dropped) = match PROFILE.lock().as_ref() {
+        Some(profile) => (
+            profile
+                .slots
+                .iter()
+                .filter(|slot| slot.count > 0)
+                .copied()
+                .collect::<Vec<_>>(),
+            profile.dropped,
+        ),
+        None => return String::new(),
+    };
+    samples.sort_unstable_by(|a, b| b.count.cmp(&a.count));
+
+    let mut output = String::new();
+    for slot in samples {
+        if slot.pid == 0 {
+            let _ = writeln!(output, "kernel;{:#x} {}", slot.bucket, slot.count);
+        } else {
+            let name = process_name(slot.pid);
+            let _ = writeln!(output, "user;{name};{:#x} {}", slot.bucket, slot.count);
+        }
+    }
+    if dropped > 0 {
+        let _ = writeln!(output, "[dropped] {dropped}");
+    }
+    output
+}
+
+/// Allocates the sample buckets and starts sampling.
+pub fn init() {
+    *PROFILE.lock() = Some(Profile {
+        slots: alloc::vec![Slot::default(); SLOTS].into_boxed_slice(),
+        dropped: 0,
+    });
+    axtask::register_timer_callback(|_| sample());
+}
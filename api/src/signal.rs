@@ -1,12 +1,16 @@
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
 
 use axerrno::LinuxResult;
 use axhal::context::TrapFrame;
-use axtask::current;
+use axtask::{current, future::block_on};
 use starry_core::task::{AsThread, Thread};
 use starry_signal::{SignalOSAction, SignalSet};
 
-use crate::task::do_exit;
+use crate::task::{do_exit, dump_fatal_signal};
 
 pub fn check_signals(thr: &Thread, tf: &mut TrapFrame, restore_blocked: Option<SignalSet>) -> bool {
     let Some((sig, os_action)) = thr.signal.check_signals(tf, restore_blocked) else {
@@ -24,14 +28,15 @@ pub fn check_signals(thr: &Thread, tf: &mut TrapFrame, restore_blocked: Option<S
         }
         SignalOSAction::CoreDump => {
             // TODO: implement core dump
+            dump_fatal_signal(thr, tf, signo);
             do_exit(128 + signo as i32, true);
         }
         SignalOSAction::Stop => {
-            // TODO: implement stop
-            do_exit(1, true);
+            thr.proc_data.stop(signo);
+            wait_while_stopped(thr);
         }
         SignalOSAction::Continue => {
-            // TODO: implement continue
+            // The process has already been resumed when the signal was sent.
         }
         SignalOSAction::Handler => {
             // do nothing
@@ -40,6 +45,25 @@ pub fn check_signals(thr: &Thread, tf: &mut TrapFrame, restore_blocked: Option<S
     true
 }
 
+/// Blocks the current thread as long as its process is stopped by a job
+/// control signal.
+pub fn wait_while_stopped(thr: &Thread) {
+    if !thr.proc_data.is_stopped() {
+        return;
+    }
+    block_on(poll_fn(|cx| {
+        if !thr.proc_data.is_stopped() {
+            return Poll::Ready(());
+        }
+        thr.proc_data.cont_event.register(cx.waker());
+        if thr.proc_data.is_stopped() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }));
+}
+
 static BLOCK_NEXT_SIGNAL_CHECK: AtomicBool = AtomicBool::new(false);
 
 pub fn block_next_signal() {
@@ -9,9 +9,12 @@ use core::{
 
 use axerrno::{LinuxError, LinuxResult};
 use axnet::{SocketAddrEx, unix::UnixSocketAddr};
-use linux_raw_sys::net::{
-    __kernel_sa_family_t, AF_INET, AF_INET6, AF_UNIX, in_addr, in6_addr, sockaddr, sockaddr_in,
-    sockaddr_in6, socklen_t,
+use linux_raw_sys::{
+    net::{
+        __kernel_sa_family_t, AF_INET, AF_INET6, AF_NETLINK, AF_UNIX, in_addr, in6_addr, sockaddr,
+        sockaddr_in, sockaddr_in6, socklen_t,
+    },
+    netlink::sockaddr_nl,
 };
 
 use crate::mm::{UserConstPtr, UserPtr};
@@ -215,3 +218,24 @@ impl SocketAddrExt for SocketAddrEx {
         AF_INET as u16
     }
 }
+
+impl SocketAddrExt for sockaddr_nl {
+    fn read_from_user(addr: UserConstPtr<sockaddr>, addrlen: socklen_t) -> LinuxResult<Self> {
+        if (addrlen as usize) < size_of::<sockaddr_nl>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let addr_nl = *addr.cast::<sockaddr_nl>().get_as_ref()?;
+        if addr_nl.nl_family as u32 != AF_NETLINK {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(addr_nl)
+    }
+
+    fn write_to_user(&self, addr: UserPtr<sockaddr>, addrlen: &mut socklen_t) -> LinuxResult<()> {
+        fill_addr(addr, addrlen, unsafe { cast_to_slice(self) })
+    }
+
+    fn family(&self) -> u16 {
+        AF_NETLINK as u16
+    }
+}
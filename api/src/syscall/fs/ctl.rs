@@ -1,40 +1,69 @@
 use alloc::{ffi::CString, vec, vec::Vec};
 use core::{
     ffi::{c_char, c_int},
-    mem::offset_of,
     time::Duration,
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FsContext};
-use axfs_ng_vfs::{MetadataUpdate, NodePermission, NodeType, path::Path};
-use axhal::time::wall_time;
+use axfs_ng::FsContext;
+use axfs_ng_vfs::{DeviceId, Metadata, MetadataUpdate, NodePermission, NodeType, path::Path};
+use axhal::time::{monotonic_time, wall_time};
 use axtask::current;
 use linux_raw_sys::{
     general::*,
-    ioctl::{FIONBIO, TIOCGWINSZ},
+    ioctl::{FIOASYNC, FIOCLEX, FIONBIO, FIONCLEX, TIOCGWINSZ},
 };
-use starry_core::task::AsThread;
+use starry_core::task::{AsThread, fs_context};
 use starry_vm::{VmPtr, vm_write_slice};
 
 use crate::{
-    file::{Directory, FileLike, get_file_like, resolve_at, with_fs},
+    file::{
+        Directory, FD_TABLE, File, FileLike, fasync, get_file_like,
+        ratelimit::{self, FIOGETRATE, FIOSETRATE},
+        resolve_at, with_fs, write_dirent64,
+    },
+    logging::RateLimit,
     mm::vm_load_string,
+    syscall::sys::{sys_getegid, sys_geteuid},
     time::TimeValueLike,
+    vfs::{
+        create_device_node,
+        freeze::{self, WriteGuard},
+        stats::{self, VfsOp},
+    },
 };
 
+static UNKNOWN_IOCTL: RateLimit = RateLimit::new("sys_ioctl");
+
 /// The ioctl() system call manipulates the underlying device parameters
 /// of special files.
 pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> LinuxResult<isize> {
     debug!("sys_ioctl <= fd: {}, cmd: {}, arg: {}", fd, cmd, arg);
     let f = get_file_like(fd)?;
-    if cmd == FIONBIO {
-        let val = (arg as *const u8).vm_read()?;
-        if val != 0 && val != 1 {
-            return Err(LinuxError::EINVAL);
+    match cmd {
+        FIONBIO => {
+            let val = (arg as *const u8).vm_read()?;
+            if val != 0 && val != 1 {
+                return Err(LinuxError::EINVAL);
+            }
+            f.set_nonblocking(val != 0)?;
+            return Ok(0);
         }
-        f.set_nonblocking(val != 0)?;
-        return Ok(0);
+        FIOCLEX | FIONCLEX => {
+            FD_TABLE
+                .write()
+                .get_mut(fd as _)
+                .ok_or(LinuxError::EBADF)?
+                .cloexec = cmd == FIOCLEX;
+            return Ok(0);
+        }
+        FIOASYNC => {
+            let val = (arg as *const c_int).vm_read()?;
+            fasync::set_async(&f, val != 0);
+            return Ok(0);
+        }
+        FIOSETRATE | FIOGETRATE => return Ok(ratelimit::ioctl(&f, cmd, arg)? as isize),
+        _ => {}
     }
     f.ioctl(cmd, arg)
         .map(|result| result as isize)
@@ -45,7 +74,9 @@ pub fn sys_ioctl(fd: i32, cmd: u32, arg: usize) -> LinuxResult<isize> {
                 if cmd == TIOCGWINSZ {
                     return;
                 }
-                warn!("Unsupported ioctl command: {cmd} for fd: {fd}");
+                if UNKNOWN_IOCTL.allow() {
+                    warn!("Unsupported ioctl command: {cmd} for fd: {fd}");
+                }
             }
         })
 }
@@ -54,7 +85,8 @@ pub fn sys_chdir(path: *const c_char) -> LinuxResult<isize> {
     let path = vm_load_string(path)?;
     debug!("sys_chdir <= path: {}", path);
 
-    let mut fs = FS_CONTEXT.lock();
+    let fs = fs_context();
+    let mut fs = fs.lock();
     let entry = fs.resolve(path)?;
     fs.set_current_dir(entry)?;
     Ok(0)
@@ -64,7 +96,7 @@ pub fn sys_fchdir(dirfd: i32) -> LinuxResult<isize> {
     debug!("sys_fchdir <= dirfd: {}", dirfd);
 
     let entry = with_fs(dirfd, |fs| Ok(fs.current_dir().clone()))?;
-    FS_CONTEXT.lock().set_current_dir(entry)?;
+    fs_context().lock().set_current_dir(entry)?;
     Ok(0)
 }
 
@@ -77,7 +109,8 @@ pub fn sys_chroot(path: *const c_char) -> LinuxResult<isize> {
     let path = vm_load_string(path)?;
     debug!("sys_chroot <= path: {}", path);
 
-    let mut fs = FS_CONTEXT.lock();
+    let fs = fs_context();
+    let mut fs = fs.lock();
     let loc = fs.resolve(path)?;
     if loc.node_type() != NodeType::Directory {
         return Err(LinuxError::ENOTDIR);
@@ -93,15 +126,67 @@ pub fn sys_mkdirat(dirfd: i32, path: *const c_char, mode: u32) -> LinuxResult<is
         dirfd, path, mode
     );
 
-    let mode = mode & !current().as_thread().proc_data.umask();
+    let mode = mode & !current().as_thread().umask();
     let mode = NodePermission::from_bits_truncate(mode as u16);
 
+    let _write = start_dir_write(dirfd, &path)?;
     with_fs(dirfd, |fs| {
         fs.create_dir(path, mode)?;
         Ok(0)
     })
 }
 
+pub fn sys_mknodat(dirfd: i32, path: *const c_char, mode: u32, dev: u32) -> LinuxResult<isize> {
+    let path = vm_load_string(path)?;
+    debug!(
+        "sys_mknodat <= dirfd: {}, path: {}, mode: {:#o}, dev: {:#x}",
+        dirfd, path, mode, dev
+    );
+
+    let node_type = match mode & S_IFMT {
+        0 | S_IFREG => NodeType::RegularFile,
+        S_IFIFO => NodeType::Fifo,
+        S_IFSOCK => NodeType::Socket,
+        S_IFCHR => NodeType::CharacterDevice,
+        S_IFBLK => NodeType::BlockDevice,
+        _ => return Err(LinuxError::EINVAL),
+    };
+    let mode = mode & !S_IFMT & !current().as_thread().umask();
+    let mode = NodePermission::from_bits_truncate(mode as u16);
+
+    let start = monotonic_time();
+    let (dir, name) = with_fs(dirfd, |fs| fs.resolve_nonexistent(Path::new(&path)))?;
+    let _write = freeze::start_write(&dir)?;
+    if matches!(node_type, NodeType::CharacterDevice | NodeType::BlockDevice) {
+        // The device is looked up when the node is opened, as on Linux.
+        create_device_node(&dir, name, node_type, mode, decode_dev(dev))?;
+    } else {
+        dir.create(name, node_type, mode)?;
+    }
+    stats::record(&dir, VfsOp::Create, 0, start);
+    Ok(0)
+}
+
+/// Waits until the directory holding `path` may be changed, as its
+/// filesystem may be frozen. The guard is taken outside [`with_fs`], so that
+/// the waiting task does not hold the filesystem context.
+fn start_dir_write(dirfd: c_int, path: &str) -> LinuxResult<WriteGuard> {
+    let (dir, _) = with_fs(dirfd, |fs| fs.resolve_parent(Path::new(path)))?;
+    freeze::start_write(&dir)
+}
+
+/// Decodes a device number in the encoding of `new_encode_dev`.
+fn decode_dev(dev: u32) -> DeviceId {
+    let major = (dev & 0xfff00) >> 8;
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xfff00);
+    DeviceId::new(major, minor)
+}
+
+#[cfg(target_arch = "x86_64")]
+pub fn sys_mknod(path: *const c_char, mode: u32, dev: u32) -> LinuxResult<isize> {
+    sys_mknodat(AT_FDCWD, path, mode, dev)
+}
+
 // Directory buffer for getdents64 syscall
 struct DirBuffer {
     buf: Vec<u8>,
@@ -116,38 +201,14 @@ impl DirBuffer {
         }
     }
 
-    fn remaining_space(&self) -> usize {
-        self.buf.len().saturating_sub(self.offset)
-    }
-
     fn write_entry(&mut self, d_ino: u64, d_off: i64, d_type: NodeType, name: &[u8]) -> bool {
-        const NAME_OFFSET: usize = offset_of!(linux_dirent64, d_name);
-
-        let len = NAME_OFFSET + name.len() + 1;
-        // alignment
-        let len = len.next_multiple_of(align_of::<linux_dirent64>());
-        if self.remaining_space() < len {
-            return false;
-        }
-
-        // FIXME: safety
-        unsafe {
-            let entry_ptr = self.buf.as_mut_ptr().add(self.offset);
-            entry_ptr.cast::<linux_dirent64>().write(linux_dirent64 {
-                d_ino,
-                d_off,
-                d_reclen: len as _,
-                d_type: d_type as _,
-                d_name: Default::default(),
-            });
-
-            let name_ptr = entry_ptr.add(NAME_OFFSET);
-            name_ptr.copy_from_nonoverlapping(name.as_ptr(), name.len());
-            name_ptr.add(name.len()).write(0);
+        match write_dirent64(&mut self.buf[self.offset..], d_ino, d_off, d_type, name) {
+            Some(len) => {
+                self.offset += len;
+                true
+            }
+            None => false,
         }
-
-        self.offset += len;
-        true
     }
 }
 
@@ -211,6 +272,7 @@ pub fn sys_linkat(
     }
     let (new_dir, new_name) =
         with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    let _write = freeze::start_write(&new_dir)?;
 
     new_dir.link(new_name, &old)?;
     Ok(0)
@@ -234,6 +296,7 @@ pub fn sys_unlinkat(dirfd: i32, path: *const c_char, flags: usize) -> LinuxResul
         dirfd, path, flags
     );
 
+    let _write = start_dir_write(dirfd, &path)?;
     with_fs(dirfd, |fs| {
         if flags == AT_REMOVEDIR as _ {
             fs.remove_dir(path)?;
@@ -260,7 +323,7 @@ pub fn sys_getcwd(buf: *mut u8, size: isize) -> LinuxResult<isize> {
         return Ok(0);
     }
 
-    let cwd = FS_CONTEXT.lock().current_dir().absolute_path()?;
+    let cwd = fs_context().lock().current_dir().absolute_path()?;
     debug!("sys_getcwd => cwd: {}", cwd);
 
     let cwd = CString::new(cwd.as_str()).map_err(|_| LinuxError::EINVAL)?;
@@ -292,6 +355,7 @@ pub fn sys_symlinkat(
         target, new_dirfd, linkpath
     );
 
+    let _write = start_dir_write(new_dirfd, &linkpath)?;
     with_fs(new_dirfd, |fs| {
         fs.symlink(target, linkpath)?;
         Ok(0)
@@ -333,10 +397,29 @@ pub fn sys_lchown(path: *const c_char, uid: u32, gid: u32) -> LinuxResult<isize>
     sys_fchownat(AT_FDCWD, path, uid, gid, AT_SYMLINK_NOFOLLOW)
 }
 
+/// Fails with `EBADF` for an `O_PATH` file, which `fchmod` and `fchown`
+/// refuse, unlike their `*at` forms with `AT_EMPTY_PATH`.
+fn check_not_path(fd: i32) -> LinuxResult<()> {
+    match File::from_fd(fd) {
+        Ok(file) if file.inner().is_path() => Err(LinuxError::EBADF),
+        _ => Ok(()),
+    }
+}
+
 pub fn sys_fchown(fd: i32, uid: i32, gid: i32) -> LinuxResult<isize> {
+    check_not_path(fd)?;
     sys_fchownat(fd, core::ptr::null(), uid, gid, AT_EMPTY_PATH)
 }
 
+/// Fails with `EPERM` unless the caller owns the file of `meta` or is root.
+fn check_owner(meta: &Metadata) -> LinuxResult<()> {
+    let euid = sys_geteuid()? as u32;
+    if euid != 0 && euid != meta.uid {
+        return Err(LinuxError::EPERM);
+    }
+    Ok(())
+}
+
 pub fn sys_fchownat(
     dirfd: i32,
     path: *const c_char,
@@ -349,6 +432,17 @@ pub fn sys_fchownat(
         .into_file()
         .ok_or(LinuxError::EBADF)?;
     let meta = loc.metadata()?;
+    let uid = if uid == -1 { meta.uid } else { uid as _ };
+    let gid = if gid == -1 { meta.gid } else { gid as _ };
+    // Only root gives a file away, and the owner may only change its group
+    // to its own.
+    if (uid != meta.uid || (gid != meta.gid && gid != sys_getegid()? as u32)) && sys_geteuid()? != 0
+    {
+        return Err(LinuxError::EPERM);
+    }
+    if gid != meta.gid {
+        check_owner(&meta)?;
+    }
 
     let mut mode = meta.mode;
     // chown always clears the setuid bits
@@ -358,8 +452,6 @@ pub fn sys_fchownat(
         mode.remove(NodePermission::SET_GID);
     }
 
-    let uid = if uid == -1 { meta.uid } else { uid as _ };
-    let gid = if gid == -1 { meta.gid } else { gid as _ };
     loc.update_metadata(MetadataUpdate {
         owner: Some((uid, gid)),
         mode: Some(mode),
@@ -374,18 +466,24 @@ pub fn sys_chmod(path: *const c_char, mode: u32) -> LinuxResult<isize> {
 }
 
 pub fn sys_fchmod(fd: i32, mode: u32) -> LinuxResult<isize> {
+    check_not_path(fd)?;
     sys_fchmodat(fd, core::ptr::null(), mode, AT_EMPTY_PATH)
 }
 
 pub fn sys_fchmodat(dirfd: i32, path: *const c_char, mode: u32, flags: u32) -> LinuxResult<isize> {
     let path = path.nullable().map(vm_load_string).transpose()?;
-    resolve_at(dirfd, path.as_deref(), flags)?
+    let loc = resolve_at(dirfd, path.as_deref(), flags)?
         .into_file()
-        .ok_or(LinuxError::EBADF)?
-        .update_metadata(MetadataUpdate {
-            mode: Some(NodePermission::from_bits_truncate(mode as u16)),
-            ..Default::default()
-        })?;
+        .ok_or(LinuxError::EBADF)?;
+    // Reached with `AT_SYMLINK_NOFOLLOW`, or through an `O_PATH` file of it.
+    if loc.node_type() == NodeType::Symlink {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    check_owner(&loc.metadata()?)?;
+    loc.update_metadata(MetadataUpdate {
+        mode: Some(NodePermission::from_bits_truncate(mode as u16)),
+        ..Default::default()
+    })?;
     Ok(0)
 }
 
@@ -456,6 +554,9 @@ pub fn sys_utimensat(
     times: *const [timespec; 2],
     mut flags: u32,
 ) -> LinuxResult<isize> {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
     if path.is_null() {
         flags |= AT_EMPTY_PATH;
     }
@@ -517,6 +618,7 @@ pub fn sys_renameat2(
     let (old_dir, old_name) = with_fs(old_dirfd, |fs| fs.resolve_parent(Path::new(&old_path)))?;
     let (new_dir, new_name) =
         with_fs(new_dirfd, |fs| fs.resolve_nonexistent(Path::new(&new_path)))?;
+    let _write = freeze::start_write(&old_dir)?;
 
     old_dir.rename(&old_name, &new_dir, new_name)?;
     Ok(0)
@@ -1,26 +1,37 @@
-use alloc::{format, string::ToString, sync::Arc};
+use alloc::{string::ToString, sync::Arc};
 use core::{
+    any::Any,
     ffi::{c_char, c_int},
     mem,
-    ops::{Deref, DerefMut},
+    ops::DerefMut,
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FileBackend, OpenOptions, OpenResult};
-use axfs_ng_vfs::{DirEntry, FileNode, Location, NodePermission, NodeType, Reference};
+use axfs_ng::{CachedFile, FileBackend, FileFlags, FsContext, OpenOptions, OpenResult};
+use axfs_ng_vfs::{DirEntry, FileNode, Location, NodeFlags, NodePermission, NodeType, Reference};
+use axhal::time::monotonic_time;
 use axtask::current;
 use bitflags::bitflags;
 use linux_raw_sys::general::*;
-use starry_core::{task::AsThread, vfs::Device};
+use starry_core::{
+    resources::AX_FILE_LIMIT,
+    task::{AsThread, fs_context},
+    vfs::{Device, find_device},
+};
 
+#[cfg(feature = "input")]
+use crate::vfs::dev::{event::EventDev, uinput::Uinput};
 use crate::{
     file::{
-        Directory, FD_TABLE, File, FileLike, Pipe, add_file_like, close_file_like, get_file_like,
-        with_fs,
+        Directory, FD_TABLE, File, FileDescriptor, FileLike, Pipe, add_file_like, close_file_like,
+        fasync, get_file_like, resolve_magic_link, status_flags, with_fs,
     },
     mm::{UserPtr, vm_load_string},
     syscall::sys::{sys_getegid, sys_geteuid},
-    vfs::dev::tty,
+    vfs::{
+        dev::{kmsg::Kmsg, tty, uring_lite::UringLite},
+        stats::{self, VfsOp},
+    },
 };
 
 /// Convert open flags to [`OpenOptions`].
@@ -60,9 +71,141 @@ fn flags_to_options(flags: c_int, mode: __kernel_mode_t, (uid, gid): (u32, u32))
     options
 }
 
+/// Redirects a device node created with `mknod` outside of devfs to the
+/// device it refers to.
+fn open_device_node(file: &axfs_ng::File) -> LinuxResult<axfs_ng::File> {
+    let loc = file.location();
+    let metadata = loc.metadata()?;
+    let device = find_device(metadata.node_type, metadata.rdev).ok_or(LinuxError::ENXIO)?;
+    let entry = DirEntry::new_file(
+        FileNode::new(device),
+        metadata.node_type,
+        Reference::new(
+            loc.parent().map(|it| it.entry().clone()),
+            loc.name().to_string(),
+        ),
+    );
+    let loc = Location::new(loc.mountpoint().clone(), entry);
+    Ok(axfs_ng::File::new(FileBackend::Direct(loc), file.flags()))
+}
+
+/// Opens the target of a magic link like `/proc/self/exe` if `path` names
+/// one, instead of resolving the path the link reads as. See
+/// [`resolve_magic_link`].
+fn open_magic_link(
+    fs: &FsContext,
+    path: &str,
+    options: &OpenOptions,
+    flags: u32,
+) -> LinuxResult<Option<OpenResult>> {
+    if flags & O_NOFOLLOW != 0 {
+        return Ok(None);
+    }
+    let Some(target) = resolve_magic_link(fs, path) else {
+        return Ok(None);
+    };
+    let target = target?;
+
+    if target.node_type() == NodeType::Directory {
+        return options.open(&fs.with_current_dir(target)?, ".").map(Some);
+    }
+    if flags & O_DIRECTORY != 0 {
+        return Err(LinuxError::ENOTDIR);
+    }
+    // Still linked: open it by name to get the usual checks and handling.
+    if let Some(parent) = target.parent() {
+        let inode = target.metadata()?.inode;
+        let fs = fs.with_current_dir(parent)?;
+        if fs
+            .resolve_no_follow(target.name())
+            .and_then(|it| it.metadata())
+            .is_ok_and(|it| it.inode == inode)
+        {
+            return options.open(&fs, target.name()).map(Some);
+        }
+    }
+
+    // Unlinked, so only the open file is left. Its mode still limits what
+    // it can be opened for, as it would have by name.
+    let mut file_flags = match flags & 0b11 {
+        O_RDONLY => FileFlags::READ,
+        O_WRONLY => FileFlags::WRITE,
+        _ => FileFlags::READ | FileFlags::WRITE,
+    };
+    let mode = target.metadata()?.mode;
+    if (file_flags.contains(FileFlags::READ) && !mode.contains(NodePermission::OWNER_READ))
+        || (file_flags.contains(FileFlags::WRITE) && !mode.contains(NodePermission::OWNER_WRITE))
+    {
+        return Err(LinuxError::EACCES);
+    }
+    if flags & O_APPEND != 0 {
+        file_flags |= FileFlags::APPEND;
+    }
+    let backend = if target.node_type() == NodeType::RegularFile
+        && !target.flags().contains(NodeFlags::NON_CACHEABLE)
+    {
+        FileBackend::Cached(CachedFile::get_or_create(target))
+    } else {
+        FileBackend::Direct(target)
+    };
+    let file = axfs_ng::File::new(backend, file_flags);
+    if flags & O_TRUNC != 0 && file_flags.contains(FileFlags::WRITE) {
+        file.access(FileFlags::WRITE)?.set_len(0)?;
+    }
+    Ok(Some(OpenResult::File(file)))
+}
+
+/// Returns `file` opened on `device` instead, a node of its own for the open
+/// file at the same place.
+fn reopen_on(file: &axfs_ng::File, device: Arc<Device>) -> axfs_ng::File {
+    let loc = file.location();
+    let entry = DirEntry::new_file(
+        FileNode::new(device),
+        NodeType::CharacterDevice,
+        Reference::new(
+            loc.parent().map(|it| it.entry().clone()),
+            loc.name().to_string(),
+        ),
+    );
+    let loc = Location::new(loc.mountpoint().clone(), entry);
+    axfs_ng::File::new(FileBackend::Direct(loc), file.flags())
+}
+
+/// Returns a device of its own for a file opened on the device `inner`, for
+/// devices that keep state per open file.
+fn open_instance(inner: &dyn Any) -> Option<Arc<Device>> {
+    #[cfg(feature = "input")]
+    if let Some(evdev) = inner.downcast_ref::<EventDev>() {
+        // Each open file of an input device gets its own events,
+        return Some(evdev.open());
+    } else if let Some(uinput) = inner.downcast_ref::<Uinput>() {
+        // each one of /dev/uinput its own device to create,
+        return Some(uinput.open());
+    }
+    if let Some(kmsg) = inner.downcast_ref::<Kmsg>() {
+        // each one of /dev/kmsg its own position in the log,
+        Some(kmsg.open())
+    } else if let Some(uring) = inner.downcast_ref::<UringLite>() {
+        // and each one of /dev/uring_lite its own ring
+        Some(uring.open())
+    } else {
+        None
+    }
+}
+
 fn add_to_fd(result: OpenResult, flags: u32) -> LinuxResult<i32> {
     let f: Arc<dyn FileLike> = match result {
+        OpenResult::File(file) if file.location().node_type() == NodeType::Fifo => {
+            Arc::new(Pipe::open_fifo(file.location(), flags)?)
+        }
         OpenResult::File(mut file) => {
+            if matches!(
+                file.location().node_type(),
+                NodeType::CharacterDevice | NodeType::BlockDevice
+            ) && file.location().entry().downcast::<Device>().is_err()
+            {
+                file = open_device_node(&file)?;
+            }
             // /dev/xx handling
             if let Ok(device) = file.location().entry().downcast::<Device>() {
                 let inner = device.inner().as_any();
@@ -70,7 +213,7 @@ fn add_to_fd(result: OpenResult, flags: u32) -> LinuxResult<i32> {
                     // Opening /dev/ptmx creates a new pseudo-terminal
                     let (master, pty_number) = ptmx.create_pty()?;
                     // TODO: this is cursed
-                    let pts = FS_CONTEXT.lock().resolve("/dev/pts")?;
+                    let pts = fs_context().lock().resolve("/dev/pts")?;
                     let entry = DirEntry::new_file(
                         FileNode::new(master),
                         NodeType::CharacterDevice,
@@ -87,15 +230,13 @@ fn add_to_fd(result: OpenResult, flags: u32) -> LinuxResult<i32> {
                         .session()
                         .terminal()
                         .ok_or(LinuxError::ENOENT)?;
-                    let path = if term.is::<tty::NTtyDriver>() {
-                        "/dev/console".to_string()
-                    } else if let Some(pts) = term.downcast_ref::<tty::PtyDriver>() {
-                        format!("/dev/pts/{}", pts.pty_number())
-                    } else {
-                        panic!("unknown terminal type")
-                    };
-                    let loc = FS_CONTEXT.lock().resolve(&path)?;
+                    let path = tty::terminal_path(&*term).expect("unknown terminal type");
+                    let loc = fs_context().lock().resolve(&path)?;
                     file = axfs_ng::File::new(FileBackend::Direct(loc), file.flags());
+                } else if let Some(instance) = open_instance(inner) {
+                    file = reopen_on(&file, instance);
+                } else if flags & O_NOCTTY == 0 {
+                    tty::acquire_on_open(device.inner().as_ref());
                 }
             }
             Arc::new(File::new(file))
@@ -126,12 +267,26 @@ pub fn sys_openat(
         dirfd, path, flags, mode
     );
 
-    let mode = mode & !current().as_thread().proc_data.umask();
+    let mode = mode & !current().as_thread().umask();
 
     let options = flags_to_options(flags, mode, (sys_geteuid()? as _, sys_getegid()? as _));
-    with_fs(dirfd, |fs| options.open(fs, path))
-        .and_then(|it| add_to_fd(it, flags as _))
-        .map(|fd| fd as isize)
+    let start = monotonic_time();
+    let result = with_fs(dirfd, |fs| {
+        match open_magic_link(fs, &path, &options, flags as _)? {
+            Some(result) => Ok(result),
+            None => options.open(fs, path),
+        }
+    })?;
+    let op = if flags as u32 & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+        VfsOp::Create
+    } else {
+        VfsOp::Lookup
+    };
+    match &result {
+        OpenResult::File(file) => stats::record(file.location(), op, 0, start),
+        OpenResult::Dir(dir) => stats::record(dir, op, 0, start),
+    }
+    add_to_fd(result, flags as _).map(|fd| fd as isize)
 }
 
 /// Open a file by `filename` and insert it into the file descriptor table.
@@ -167,12 +322,11 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> LinuxResult<isize>
         first, last, flags
     );
     if flags.contains(CloseRangeFlags::UNSHARE) {
-        // TODO: optimize
         let curr = current();
         let mut scope = curr.as_thread().proc_data.scope.write();
         let mut guard = FD_TABLE.scope_mut(&mut scope);
         let old_files = mem::take(guard.deref_mut());
-        old_files.write().clone_from(old_files.read().deref());
+        guard.write().clone_from(&old_files.read());
     }
 
     let cloexec = flags.contains(CloseRangeFlags::CLOEXEC);
@@ -192,15 +346,33 @@ pub fn sys_close_range(first: i32, last: i32, flags: u32) -> LinuxResult<isize>
     Ok(0)
 }
 
-fn dup_fd(old_fd: c_int, cloexec: bool) -> LinuxResult<isize> {
-    let f = get_file_like(old_fd)?;
-    let new_fd = add_file_like(f, cloexec)?;
+/// Duplicates `old_fd` to the lowest free descriptor not below `min_fd`.
+///
+/// Both descriptors refer to the same open file description, so they share
+/// the file offset and status flags, but not the close-on-exec flag.
+fn dup_fd(old_fd: c_int, min_fd: usize, cloexec: bool) -> LinuxResult<isize> {
+    let max_nofile = current().as_thread().proc_data.rlim.read()[RLIMIT_NOFILE].current;
+    let max_nofile = max_nofile.min(AX_FILE_LIMIT as u64) as usize;
+    if min_fd >= max_nofile {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut fd_table = FD_TABLE.write();
+    let f = fd_table
+        .get(old_fd as _)
+        .map(|it| it.inner.clone())
+        .ok_or(LinuxError::EBADF)?;
+    let new_fd = (min_fd..max_nofile)
+        .find(|fd| !fd_table.is_assigned(*fd))
+        .ok_or(LinuxError::EMFILE)?;
+    fd_table
+        .add_at(new_fd, FileDescriptor { inner: f, cloexec })
+        .map_err(|_| LinuxError::EMFILE)?;
     Ok(new_fd as _)
 }
 
 pub fn sys_dup(old_fd: c_int) -> LinuxResult<isize> {
     debug!("sys_dup <= {}", old_fd);
-    dup_fd(old_fd, false)
+    dup_fd(old_fd, 0, false)
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -249,8 +421,8 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
     debug!("sys_fcntl <= fd: {} cmd: {} arg: {}", fd, cmd, arg);
 
     match cmd as u32 {
-        F_DUPFD => dup_fd(fd, false),
-        F_DUPFD_CLOEXEC => dup_fd(fd, true),
+        F_DUPFD => dup_fd(fd, arg, false),
+        F_DUPFD_CLOEXEC => dup_fd(fd, arg, true),
         F_SETLK | F_SETLKW => Ok(0),
         F_OFD_SETLK | F_OFD_SETLKW => Ok(0),
         F_GETLK | F_OFD_GETLK => {
@@ -259,28 +431,12 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
             Ok(0)
         }
         F_SETFL => {
-            get_file_like(fd)?.set_nonblocking(arg & (O_NONBLOCK as usize) > 0)?;
-            Ok(0)
-        }
-        F_GETFL => {
             let f = get_file_like(fd)?;
-
-            let mut ret = 0;
-            if f.nonblocking() {
-                ret |= O_NONBLOCK;
-            }
-
-            let perm = NodePermission::from_bits_truncate(f.stat()?.mode as _);
-            if perm.contains(NodePermission::OWNER_WRITE) {
-                if perm.contains(NodePermission::OWNER_READ) {
-                    ret |= O_RDWR;
-                } else {
-                    ret |= O_WRONLY;
-                }
-            }
-
-            Ok(ret as _)
+            f.set_nonblocking(arg & (O_NONBLOCK as usize) > 0)?;
+            fasync::set_async(&f, arg & (FASYNC as usize) > 0);
+            Ok(0)
         }
+        F_GETFL => Ok(status_flags(&get_file_like(fd)?)? as _),
         F_GETFD => {
             let cloexec = FD_TABLE
                 .read()
@@ -298,6 +454,11 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
                 .cloexec = cloexec;
             Ok(0)
         }
+        F_SETOWN => {
+            fasync::set_owner(&get_file_like(fd)?, arg as i32);
+            Ok(0)
+        }
+        F_GETOWN => Ok(fasync::owner(&get_file_like(fd)?) as _),
         F_GETPIPE_SZ => {
             let pipe = Pipe::from_fd(fd)?;
             Ok(pipe.capacity() as _)
@@ -316,6 +477,12 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> LinuxResult<isize> {
 
 pub fn sys_flock(fd: c_int, operation: c_int) -> LinuxResult<isize> {
     debug!("flock <= fd: {}, operation: {}", fd, operation);
-    // TODO: flock
+    let f = get_file_like(fd)?.into_any();
+    let operation = operation as u32;
+    if let Ok(file) = f.clone().downcast::<File>() {
+        file.flock(operation)?;
+    } else if let Ok(dir) = f.downcast::<Directory>() {
+        dir.flock(operation)?;
+    }
     Ok(0)
 }
@@ -5,17 +5,34 @@ use core::{
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, FileFlags, OpenOptions};
-use axio::{IoEvents, Pollable, Seek, SeekFrom};
+use axfs_ng::{FileFlags, OpenOptions};
+use axio::{Buf, IoEvents, Pollable, Seek, SeekFrom};
 use axtask::current;
-use linux_raw_sys::general::__kernel_off_t;
-use starry_vm::{VmBytes, VmBytesMut, VmMutPtr, VmPtr};
+use linux_raw_sys::general::{
+    __kernel_off_t, FALLOC_FL_KEEP_SIZE, FALLOC_FL_PUNCH_HOLE, RWF_APPEND, RWF_DSYNC, RWF_HIPRI,
+    RWF_NOWAIT, RWF_SYNC,
+};
+use starry_core::{
+    task::{AsThread, WaitChannel, fs_context, wait_on},
+    vfs::Device,
+};
+use starry_vm::{VmMutPtr, VmPtr};
 use syscalls::Sysno;
 
 use crate::{
-    file::{File, FileLike, Pipe, SealedBuf, SealedBufMut, get_file_like},
-    io::{IoVec, IoVectorBuf},
+    file::{
+        File, FileLike, Pipe, SealedBuf, SealedBufMut, Socket, check_file_size, get_file_like,
+        limit_write, nowait,
+        ratelimit::{self, Direction},
+    },
+    io::{IoVec, IoVectorBuf, TakeBuf, UserBuf, UserBufMut, partial_transfer},
     mm::UserConstPtr,
+    vfs::{
+        self,
+        dev::kmsg::Kmsg,
+        freeze,
+        stats::{VfsOp, track_io},
+    },
 };
 
 struct DummyFd;
@@ -58,19 +75,39 @@ pub fn sys_dummy_fd(sysno: Sysno) -> LinuxResult<isize> {
     DummyFd.add_to_fd_table(false).map(|fd| fd as isize)
 }
 
+/// Accounts a read syscall in the I/O counters of the current process.
+fn account_read(result: LinuxResult<usize>) -> LinuxResult<isize> {
+    let read = *result.as_ref().unwrap_or(&0);
+    current().as_thread().proc_data.io.add_read(read);
+    result.map(|n| n as _)
+}
+
+/// Accounts a write syscall in the I/O counters of the current process.
+fn account_write(result: LinuxResult<usize>) -> LinuxResult<isize> {
+    let written = *result.as_ref().unwrap_or(&0);
+    current().as_thread().proc_data.io.add_write(written);
+    result.map(|n| n as _)
+}
+
 /// Read data from the file indicated by `fd`.
 ///
 /// Return the read size if success.
 pub fn sys_read(fd: i32, buf: *mut u8, len: usize) -> LinuxResult<isize> {
     debug!("sys_read <= fd: {}, buf: {:p}, len: {}", fd, buf, len);
-    Ok(get_file_like(fd)?.read(&mut VmBytesMut::new(buf, len).into())? as _)
+    let _wait = wait_on(WaitChannel::File("read", fd));
+    let f = get_file_like(fd)?;
+    let mut dst: SealedBufMut = UserBufMut::new(buf, len).into();
+    let read = ratelimit::limit(fd, Direction::Read, || f.read(&mut dst));
+    account_read(partial_transfer(read, dst.faulted()))
 }
 
 pub fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: usize) -> LinuxResult<isize> {
     debug!("sys_readv <= fd: {}, iovcnt: {}", fd, iovcnt);
+    let _wait = wait_on(WaitChannel::File("readv", fd));
     let f = get_file_like(fd)?;
-    f.read(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into())
-        .map(|n| n as _)
+    let mut dst: SealedBufMut = IoVectorBuf::new(iov, iovcnt)?.into_io().into();
+    let read = ratelimit::limit(fd, Direction::Read, || f.read(&mut dst));
+    account_read(partial_transfer(read, dst.faulted()))
 }
 
 /// Write data to the file indicated by `fd`.
@@ -78,25 +115,37 @@ pub fn sys_readv(fd: i32, iov: *const IoVec, iovcnt: usize) -> LinuxResult<isize
 /// Return the written size if success.
 pub fn sys_write(fd: i32, buf: *mut u8, len: usize) -> LinuxResult<isize> {
     debug!("sys_write <= fd: {}, buf: {:p}, len: {}", fd, buf, len);
-    Ok(get_file_like(fd)?.write(&mut VmBytes::new(buf, len).into())? as _)
+    let _wait = wait_on(WaitChannel::File("write", fd));
+    let f = get_file_like(fd)?;
+    let mut src: SealedBuf = UserBuf::new(buf, len).into();
+    let written = ratelimit::limit(fd, Direction::Write, || f.write(&mut src));
+    account_write(partial_transfer(written, src.faulted()))
 }
 
 pub fn sys_writev(fd: i32, iov: *const IoVec, iovcnt: usize) -> LinuxResult<isize> {
     debug!("sys_writev <= fd: {}, iovcnt: {}", fd, iovcnt);
+    let _wait = wait_on(WaitChannel::File("writev", fd));
     let f = get_file_like(fd)?;
-    f.write(&mut IoVectorBuf::new(iov, iovcnt)?.into_io().into())
-        .map(|n| n as _)
+    let mut src: SealedBuf = IoVectorBuf::new(iov, iovcnt)?.into_io().into();
+    let written = ratelimit::limit(fd, Direction::Write, || f.write(&mut src));
+    account_write(partial_transfer(written, src.faulted()))
 }
 
 pub fn sys_lseek(fd: c_int, offset: __kernel_off_t, whence: c_int) -> LinuxResult<isize> {
     debug!("sys_lseek <= {} {} {}", fd, offset, whence);
+    let f = File::from_fd(fd)?;
+    if let Ok(device) = f.inner().location().entry().downcast::<Device>()
+        && let Some(kmsg) = device.inner().as_any().downcast_ref::<Kmsg>()
+    {
+        return Ok(kmsg.seek(offset, whence as _)? as _);
+    }
     let pos = match whence {
         0 => SeekFrom::Start(offset as _),
         1 => SeekFrom::Current(offset as _),
         2 => SeekFrom::End(offset as _),
         _ => return Err(LinuxError::EINVAL),
     };
-    let off = File::from_fd(fd)?.inner().seek(pos)?;
+    let off = f.inner().seek(pos)?;
     Ok(off as _)
 }
 
@@ -108,16 +157,20 @@ pub fn sys_truncate(path: UserConstPtr<c_char>, length: __kernel_off_t) -> Linux
     }
     let file = OpenOptions::new()
         .write(true)
-        .open(&FS_CONTEXT.lock(), path)?
+        .open(&fs_context().lock(), path)?
         .into_file()?;
-    file.access(FileFlags::WRITE)?.set_len(length as _)?;
+    let file = file.access(FileFlags::WRITE)?;
+    check_file_size(file.location(), length as _)?;
+    vfs::truncate(file.location(), || file.set_len(length as _))?;
     Ok(0)
 }
 
 pub fn sys_ftruncate(fd: c_int, length: __kernel_off_t) -> LinuxResult<isize> {
     debug!("sys_ftruncate <= {} {}", fd, length);
     let f = File::from_fd(fd)?;
-    f.inner().access(FileFlags::WRITE)?.set_len(length as _)?;
+    let file = f.inner().access(FileFlags::WRITE)?;
+    check_file_size(file.location(), length as _)?;
+    vfs::truncate(file.location(), || file.set_len(length as _))?;
     Ok(0)
 }
 
@@ -131,27 +184,45 @@ pub fn sys_fallocate(
         "sys_fallocate <= fd: {}, mode: {}, offset: {}, len: {}",
         fd, mode, offset, len
     );
-    if mode != 0 {
+    if offset < 0 || len <= 0 {
         return Err(LinuxError::EINVAL);
     }
+    if mode & !(FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE) != 0 {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
     let f = File::from_fd(fd)?;
     let inner = f.inner();
     let file = inner.access(FileFlags::WRITE)?;
-    file.set_len(file.location().len()?.max(offset as u64 + len as u64))?;
+    let loc = file.location();
+    let end = (offset as u64)
+        .checked_add(len as u64)
+        .ok_or(LinuxError::EFBIG)?;
+    if mode & FALLOC_FL_PUNCH_HOLE != 0 {
+        // Punching a hole never changes the size, which must be said.
+        if mode & FALLOC_FL_KEEP_SIZE == 0 {
+            return Err(LinuxError::EOPNOTSUPP);
+        }
+        vfs::punch_hole(inner.backend()?, offset as _, end)?;
+        return Ok(0);
+    }
+    check_file_size(loc, loc.len()?.max(end))?;
+    vfs::allocate(loc, offset as _, end)?;
+    if mode & FALLOC_FL_KEEP_SIZE == 0 && end > loc.len()? {
+        // The pages are already allocated.
+        vfs::truncate(loc, || file.set_len(end))?;
+    }
     Ok(0)
 }
 
 pub fn sys_fsync(fd: c_int) -> LinuxResult<isize> {
     debug!("sys_fsync <= {}", fd);
-    let f = File::from_fd(fd)?;
-    f.inner().sync(false)?;
+    File::from_fd(fd)?.sync(false)?;
     Ok(0)
 }
 
 pub fn sys_fdatasync(fd: c_int) -> LinuxResult<isize> {
     debug!("sys_fdatasync <= {}", fd);
-    let f = File::from_fd(fd)?;
-    f.inner().sync(true)?;
+    File::from_fd(fd)?.sync(true)?;
     Ok(0)
 }
 
@@ -184,10 +255,14 @@ pub fn sys_pread64(
     if offset < 0 {
         return Err(LinuxError::EINVAL);
     }
-    let read = f
-        .inner()
-        .read_at(&mut VmBytesMut::new(buf, len), offset as _)?;
-    Ok(read as _)
+    let mut dst = UserBufMut::new(buf, len);
+    let read = ratelimit::limit(fd, Direction::Read, || {
+        track_io(f.inner().location(), VfsOp::Read, || {
+            f.read_pinned_at(&mut dst, offset as _)
+                .unwrap_or_else(|| f.inner().read_at(&mut dst, offset as _))
+        })
+    });
+    account_read(partial_transfer(read, dst.faulted()))
 }
 
 pub fn sys_pwrite64(
@@ -196,14 +271,22 @@ pub fn sys_pwrite64(
     len: usize,
     offset: __kernel_off_t,
 ) -> LinuxResult<isize> {
+    let f = File::from_fd(fd)?;
+    if offset < 0 {
+        return Err(LinuxError::EINVAL);
+    }
     if len == 0 {
         return Ok(0);
     }
-    let f = File::from_fd(fd)?;
-    let write = f
-        .inner()
-        .write_at(&mut VmBytes::new(buf, len), offset as _)?;
-    Ok(write as _)
+    let len = limit_write(f.inner().location(), offset as _, len)?;
+    let mut src = UserBuf::new(buf, len);
+    let write = ratelimit::limit(fd, Direction::Write, || {
+        track_io(f.inner().location(), VfsOp::Write, || {
+            f.write_pinned_at(&mut src, offset as _)
+                .unwrap_or_else(|| f.inner().write_at(&mut src, offset as _))
+        })
+    });
+    account_write(partial_transfer(write, src.faulted()))
 }
 
 pub fn sys_preadv(
@@ -224,21 +307,94 @@ pub fn sys_pwritev(
     sys_pwritev2(fd, iov, iovcnt, offset, 0)
 }
 
+bitflags::bitflags! {
+    /// Flags for `preadv2` and `pwritev2`.
+    #[derive(Debug, Clone, Copy)]
+    struct RwFlags: u32 {
+        /// Poll for completion. There is nothing to poll here, so it has no
+        /// effect.
+        const HIPRI = RWF_HIPRI;
+        /// Write with `O_DSYNC` semantics.
+        const DSYNC = RWF_DSYNC;
+        /// Write with `O_SYNC` semantics.
+        const SYNC = RWF_SYNC;
+        /// Fail with `EAGAIN` instead of blocking.
+        const NOWAIT = RWF_NOWAIT;
+        /// Write at the end of the file, whatever the offset.
+        const APPEND = RWF_APPEND;
+    }
+}
+
+impl RwFlags {
+    fn parse(flags: u32) -> LinuxResult<Self> {
+        Self::from_bits(flags).ok_or(LinuxError::EOPNOTSUPP)
+    }
+
+    /// Runs the transfer `f` on `fd`, which fails with `EAGAIN` instead of
+    /// blocking if `RWF_NOWAIT` is set.
+    ///
+    /// `fd` is then treated as nonblocking while `f` runs. A socket, where
+    /// the network stack decides whether to block, must also be ready for
+    /// `events` beforehand. Regular files never block this way: the page
+    /// cache cannot tell which pages it holds, so a read that misses it
+    /// still goes to the disk.
+    fn transfer(
+        self,
+        fd: c_int,
+        events: IoEvents,
+        f: impl FnOnce() -> LinuxResult<isize>,
+    ) -> LinuxResult<isize> {
+        if !self.contains(Self::NOWAIT) {
+            return f();
+        }
+        if let Ok(socket) = Socket::from_fd(fd)
+            && !socket.poll().intersects(events)
+        {
+            return Err(LinuxError::EAGAIN);
+        }
+        nowait(f)
+    }
+
+    /// Flushes what was written to `fd` if `RWF_DSYNC` or `RWF_SYNC` is set.
+    fn sync_written(self, fd: c_int) -> LinuxResult<()> {
+        if self.intersects(Self::DSYNC | Self::SYNC)
+            && let Ok(f) = File::from_fd(fd)
+        {
+            f.sync(!self.contains(Self::SYNC))?;
+        }
+        Ok(())
+    }
+}
+
 pub fn sys_preadv2(
     fd: c_int,
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
         "sys_preadv2 <= fd: {}, iovcnt: {}, offset: {}, flags: {}",
-        fd, iovcnt, offset, _flags
+        fd, iovcnt, offset, flags
     );
-    let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let flags = RwFlags::parse(flags)?;
+    flags.transfer(fd, IoEvents::IN, || {
+        // An offset of -1 means the current file offset, which is then updated.
+        if offset == -1 {
+            return sys_readv(fd, iov, iovcnt);
+        }
+        if offset < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let f = File::from_fd(fd)?;
+        let mut buf = IoVectorBuf::new(iov, iovcnt)?.into_io();
+        let read = ratelimit::limit(fd, Direction::Read, || {
+            track_io(f.inner().location(), VfsOp::Read, || {
+                f.inner().read_at(&mut buf, offset as _)
+            })
+        });
+        account_read(partial_transfer(read, buf.faulted()))
+    })
 }
 
 pub fn sys_pwritev2(
@@ -246,16 +402,46 @@ pub fn sys_pwritev2(
     iov: *const IoVec,
     iovcnt: usize,
     offset: __kernel_off_t,
-    _flags: u32,
+    flags: u32,
 ) -> LinuxResult<isize> {
     debug!(
         "sys_pwritev2 <= fd: {}, iovcnt: {}, offset: {}, flags: {}",
-        fd, iovcnt, offset, _flags
+        fd, iovcnt, offset, flags
     );
-    let f = File::from_fd(fd)?;
-    f.inner()
-        .read_at(&mut IoVectorBuf::new(iov, iovcnt)?.into_io(), offset as _)
-        .map(|n| n as _)
+    let flags = RwFlags::parse(flags)?;
+    flags.transfer(fd, IoEvents::OUT, || {
+        if offset == -1 {
+            // Appending moves the file offset to the end, as on Linux.
+            if flags.contains(RwFlags::APPEND)
+                && let Ok(f) = File::from_fd(fd)
+            {
+                f.inner().seek(SeekFrom::End(0))?;
+            }
+            let written = sys_writev(fd, iov, iovcnt)?;
+            flags.sync_written(fd)?;
+            return Ok(written);
+        }
+        if offset < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let f = File::from_fd(fd)?;
+        let offset = if flags.contains(RwFlags::APPEND) {
+            f.inner().location().len()?
+        } else {
+            offset as u64
+        };
+        let mut buf = IoVectorBuf::new(iov, iovcnt)?.into_io();
+        let limit = limit_write(f.inner().location(), offset as _, buf.remaining())?;
+        let written = ratelimit::limit(fd, Direction::Write, || {
+            track_io(f.inner().location(), VfsOp::Write, || {
+                f.inner()
+                    .write_at(&mut TakeBuf::new(&mut buf, limit), offset as _)
+            })
+        });
+        let written = account_write(partial_transfer(written, buf.faulted()))?;
+        flags.sync_written(fd)?;
+        Ok(written)
+    })
 }
 
 enum SendFile {
@@ -289,6 +475,8 @@ impl SendFile {
             SendFile::Direct(file) => file.write(&mut buf.into()),
             SendFile::Offset(file, offset) => {
                 let off = offset.vm_read()?;
+                buf = &buf[..limit_write(file.inner().location(), off, buf.len())?];
+                let _write = freeze::start_write(file.inner().location())?;
                 let bytes_written = file.inner().write_at(&mut buf, off)?;
                 offset.vm_write(off + bytes_written as u64)?;
                 Ok(bytes_written)
@@ -2,8 +2,9 @@ use alloc::format;
 use core::ffi::c_char;
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{FS_CONTEXT, OpenOptions};
+use axfs_ng::OpenOptions;
 use linux_raw_sys::general::MFD_CLOEXEC;
+use starry_core::task::fs_context;
 
 use crate::{
     file::{File, FileLike},
@@ -16,7 +17,7 @@ pub fn sys_memfd_create(_name: UserConstPtr<c_char>, flags: u32) -> LinuxResult<
     // This is cursed
     for id in 0..0xffff {
         let name = format!("/tmp/memfd-{id:04x}");
-        let fs = FS_CONTEXT.lock().clone();
+        let fs = fs_context().lock().clone();
         if fs.resolve(&name).is_err() {
             let file = OpenOptions::new()
                 .read(true)
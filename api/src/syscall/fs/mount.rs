@@ -1,41 +1,102 @@
+use alloc::{string::ToString, sync::Arc};
 use core::ffi::{c_char, c_void};
 
-use axerrno::LinuxResult;
-use axfs_ng::FS_CONTEXT;
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::NodeType;
+use linux_raw_sys::general::{MS_RDONLY, TMPFS_MAGIC};
+use starry_core::task::fs_context;
 
-use crate::{mm::vm_load_string, vfs::MemoryFs};
+use crate::{
+    mm::vm_load_string,
+    vfs::{
+        Device, DeviceOps, FatFs, MemoryFs,
+        mount::{MOUNT_TABLE, Mount},
+        new_mqueuefs,
+    },
+};
 
 pub fn sys_mount(
     source: *const c_char,
     target: *const c_char,
     fs_type: *const c_char,
-    _flags: i32,
-    _data: *const c_void,
+    flags: i32,
+    data: *const c_void,
 ) -> LinuxResult<isize> {
     let source = vm_load_string(source)?;
     let target = vm_load_string(target)?;
     let fs_type = vm_load_string(fs_type)?;
     debug!(
-        "sys_mount <= source: {:?}, target: {:?}, fs_type: {:?}",
-        source, target, fs_type
+        "sys_mount <= source: {:?}, target: {:?}, fs_type: {:?}, flags: {:#x}",
+        source, target, fs_type, flags
     );
 
-    if fs_type != "tmpfs" {
-        return Err(axerrno::LinuxError::ENODEV);
-    }
-
-    let fs = MemoryFs::new();
+    let mut read_only = flags as u32 & MS_RDONLY != 0;
+    let fs = match fs_type.as_str() {
+        "tmpfs" => {
+            let mut size = None;
+            if !data.is_null() {
+                let data = vm_load_string(data as *const c_char)?;
+                for option in data.split(',') {
+                    if let Some(value) = option.strip_prefix("size=") {
+                        size = Some(parse_size(value).ok_or(LinuxError::EINVAL)?);
+                    }
+                }
+            }
+            MemoryFs::new_with(TMPFS_MAGIC, size)
+        }
+        "vfat" | "msdos" => {
+            let device = block_device(&source)?;
+            if !read_only && device.read_only() {
+                warn!("{} is write-protected, mounting read-only", source);
+                read_only = true;
+            }
+            FatFs::new(device, read_only)?
+        }
+        "mqueue" => new_mqueuefs(),
+        _ => return Err(LinuxError::ENODEV),
+    };
 
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.mount(&fs)?;
+    let cx = fs_context();
+    let cx = cx.lock();
+    cx.resolve(&target)?.mount(&fs)?;
+    let options = if read_only { "ro" } else { "rw" };
+    let mount = Mount::new(source, fs_type, options, cx.resolve(&target)?)?;
+    MOUNT_TABLE.write().add(mount);
 
     Ok(0)
 }
 
+/// Returns the block device at `path`, e.g. a loop device with an image
+/// attached, to mount a filesystem from.
+fn block_device(path: &str) -> LinuxResult<Arc<dyn DeviceOps>> {
+    let loc = fs_context().lock().resolve(path)?;
+    if loc.metadata()?.node_type != NodeType::BlockDevice {
+        return Err(LinuxError::ENOTBLK);
+    }
+    let device = loc
+        .entry()
+        .downcast::<Device>()
+        .map_err(|_| LinuxError::ENOTBLK)?;
+    Ok(device.inner().clone())
+}
+
+/// Parses a tmpfs size option such as `64m`.
+fn parse_size(value: &str) -> Option<u64> {
+    let (digits, shift) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 10),
+        b'm' | b'M' => (&value[..value.len() - 1], 20),
+        b'g' | b'G' => (&value[..value.len() - 1], 30),
+        _ => (value, 0),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
 pub fn sys_umount2(target: *const c_char, _flags: i32) -> LinuxResult<isize> {
     let target = vm_load_string(target)?;
     debug!("sys_umount2 <= target: {:?}", target);
-    let target = FS_CONTEXT.lock().resolve(target)?;
-    target.unmount()?;
+    let target = fs_context().lock().resolve(target)?;
+    MOUNT_TABLE
+        .write()
+        .remove(&target.absolute_path()?.to_string())?;
     Ok(0)
 }
@@ -1,5 +1,5 @@
 use axerrno::{LinuxError, LinuxResult};
-use starry_core::task::{get_process_data, send_signal_to_process};
+use starry_core::task::{current_pid_ns, get_process_data, send_signal_to_process};
 use starry_signal::SignalInfo;
 
 use crate::{
@@ -14,7 +14,7 @@ pub fn sys_pidfd_open(pid: u32, flags: u32) -> LinuxResult<isize> {
         return Err(LinuxError::EINVAL);
     }
 
-    let task = get_process_data(pid)?;
+    let task = get_process_data(current_pid_ns().global_pid(pid)?)?;
     let fd = PidFd::new(&task);
 
     fd.add_to_fd_table(true).map(|fd| fd as _)
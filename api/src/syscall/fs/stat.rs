@@ -1,16 +1,17 @@
 use core::ffi::{c_char, c_int};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::FS_CONTEXT;
 use axfs_ng_vfs::{Location, NodePermission};
 use linux_raw_sys::general::{
     __kernel_fsid_t, AT_EMPTY_PATH, R_OK, W_OK, X_OK, stat, statfs, statx,
 };
+use starry_core::task::fs_context;
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
     file::{File, FileLike, resolve_at},
     mm::vm_load_string,
+    vfs::mount::MOUNT_TABLE,
 };
 
 /// Get the file metadata by `path` and write into `statbuf`.
@@ -163,7 +164,11 @@ fn statfs(loc: &Location) -> LinuxResult<statfs> {
     };
     result.f_namelen = stat.name_length as _;
     result.f_frsize = stat.fragment_size as _;
-    result.f_flags = stat.mount_flags as _;
+    let flags = MOUNT_TABLE
+        .read()
+        .find(loc)
+        .map_or(0, |mount| mount.statfs_flags());
+    result.f_flags = (stat.mount_flags as u32 | flags) as _;
     Ok(result)
 }
 
@@ -172,7 +177,7 @@ pub fn sys_statfs(path: *const c_char, buf: *mut statfs) -> LinuxResult<isize> {
     debug!("sys_statfs <= path: {:?}", path);
 
     buf.vm_write(statfs(
-        &FS_CONTEXT
+        &fs_context()
             .lock()
             .resolve(path)?
             .mountpoint()
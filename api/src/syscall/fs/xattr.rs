@@ -0,0 +1,175 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::ffi::c_char;
+
+use axerrno::{LinuxError, LinuxResult};
+use linux_raw_sys::general::{AT_EMPTY_PATH, AT_FDCWD, AT_SYMLINK_NOFOLLOW};
+use starry_core::vfs::{XattrFlags, XattrNode};
+use starry_vm::{vm_load, vm_write_slice};
+
+use crate::{file::resolve_at, mm::vm_load_string, vfs::xattr_node};
+
+/// Resolves the node at `path` for the `*xattr` syscalls, or the symlink
+/// itself rather than its target for the `l*xattr` ones.
+fn xattr_node_at(
+    path: *const c_char,
+    follow: bool,
+) -> LinuxResult<Arc<dyn XattrNode + Send + Sync>> {
+    let path = vm_load_string(path)?;
+    let flags = if follow { 0 } else { AT_SYMLINK_NOFOLLOW };
+    let loc = resolve_at(AT_FDCWD, Some(&path), flags)?;
+    xattr_node(&loc.into_file().ok_or(LinuxError::EOPNOTSUPP)?)
+}
+
+/// Resolves the node opened as `fd` for the `f*xattr` syscalls.
+fn xattr_node_of(fd: i32) -> LinuxResult<Arc<dyn XattrNode + Send + Sync>> {
+    let loc = resolve_at(fd, None, AT_EMPTY_PATH)?;
+    xattr_node(&loc.into_file().ok_or(LinuxError::EOPNOTSUPP)?)
+}
+
+/// Copies `data` to the user buffer `buf` of `size` bytes, or only returns
+/// the size needed if `size` is 0.
+fn write_xattr_buf(data: &[u8], buf: *mut u8, size: usize) -> LinuxResult<isize> {
+    if size == 0 {
+        return Ok(data.len() as _);
+    }
+    if data.len() > size {
+        return Err(LinuxError::ERANGE);
+    }
+    vm_write_slice(buf, data)?;
+    Ok(data.len() as _)
+}
+
+fn do_setxattr(
+    node: Arc<dyn XattrNode + Send + Sync>,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let name = vm_load_string(name)?;
+    let flags = XattrFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    debug!(
+        "setxattr <= name: {:?}, size: {}, flags: {:?}",
+        name, size, flags
+    );
+    let value = if size == 0 {
+        Vec::new()
+    } else {
+        vm_load(value, size)?
+    };
+    node.xattrs().set(&name, &value, flags)?;
+    Ok(0)
+}
+
+fn do_getxattr(
+    node: Arc<dyn XattrNode + Send + Sync>,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    let name = vm_load_string(name)?;
+    debug!("getxattr <= name: {:?}, size: {}", name, size);
+    write_xattr_buf(&node.xattrs().get(&name)?, value, size)
+}
+
+fn do_listxattr(
+    node: Arc<dyn XattrNode + Send + Sync>,
+    list: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    write_xattr_buf(&node.xattrs().list(), list, size)
+}
+
+fn do_removexattr(
+    node: Arc<dyn XattrNode + Send + Sync>,
+    name: *const c_char,
+) -> LinuxResult<isize> {
+    let name = vm_load_string(name)?;
+    debug!("removexattr <= name: {:?}", name);
+    node.xattrs().remove(&name)?;
+    Ok(0)
+}
+
+pub fn sys_setxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let node = xattr_node_at(path, true)?;
+    do_setxattr(node, name, value, size, flags)
+}
+
+pub fn sys_lsetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let node = xattr_node_at(path, false)?;
+    do_setxattr(node, name, value, size, flags)
+}
+
+pub fn sys_fsetxattr(
+    fd: i32,
+    name: *const c_char,
+    value: *const u8,
+    size: usize,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let node = xattr_node_of(fd)?;
+    do_setxattr(node, name, value, size, flags)
+}
+
+pub fn sys_getxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    do_getxattr(xattr_node_at(path, true)?, name, value, size)
+}
+
+pub fn sys_lgetxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    do_getxattr(xattr_node_at(path, false)?, name, value, size)
+}
+
+pub fn sys_fgetxattr(
+    fd: i32,
+    name: *const c_char,
+    value: *mut u8,
+    size: usize,
+) -> LinuxResult<isize> {
+    do_getxattr(xattr_node_of(fd)?, name, value, size)
+}
+
+pub fn sys_listxattr(path: *const c_char, list: *mut u8, size: usize) -> LinuxResult<isize> {
+    do_listxattr(xattr_node_at(path, true)?, list, size)
+}
+
+pub fn sys_llistxattr(path: *const c_char, list: *mut u8, size: usize) -> LinuxResult<isize> {
+    do_listxattr(xattr_node_at(path, false)?, list, size)
+}
+
+pub fn sys_flistxattr(fd: i32, list: *mut u8, size: usize) -> LinuxResult<isize> {
+    do_listxattr(xattr_node_of(fd)?, list, size)
+}
+
+pub fn sys_removexattr(path: *const c_char, name: *const c_char) -> LinuxResult<isize> {
+    do_removexattr(xattr_node_at(path, true)?, name)
+}
+
+pub fn sys_lremovexattr(path: *const c_char, name: *const c_char) -> LinuxResult<isize> {
+    do_removexattr(xattr_node_at(path, false)?, name)
+}
+
+pub fn sys_fremovexattr(fd: i32, name: *const c_char) -> LinuxResult<isize> {
+    do_removexattr(xattr_node_of(fd)?, name)
+}
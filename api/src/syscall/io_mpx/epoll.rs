@@ -7,6 +7,7 @@ use bitflags::bitflags;
 use linux_raw_sys::general::{
     EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD, epoll_event, timespec,
 };
+use starry_core::task::{WaitChannel, wait_on};
 use starry_signal::SignalSet;
 
 use crate::{
@@ -88,6 +89,7 @@ fn do_epoll_wait(
         "sys_epoll_wait <= epfd: {}, maxevents: {}, timeout: {:?}",
         epfd, maxevents, timeout
     );
+    let _wait = wait_on(WaitChannel::File("epoll_wait", epfd));
 
     let epoll = Epoll::from_fd(epfd)?;
 
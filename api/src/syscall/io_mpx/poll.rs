@@ -5,6 +5,7 @@ use axhal::time::TimeValue;
 use axio::IoEvents;
 use axtask::future::Poller;
 use linux_raw_sys::general::{POLLNVAL, pollfd, timespec};
+use starry_core::task::{WaitChannel, wait_on};
 use starry_signal::SignalSet;
 
 use super::FdPollSet;
@@ -22,6 +23,7 @@ fn do_poll(
     sigmask: Option<SignalSet>,
 ) -> LinuxResult<isize> {
     debug!("do_poll fds={:?} timeout={:?}", poll_fds, timeout);
+    let _wait = wait_on(WaitChannel::Syscall("poll"));
 
     let mut res = 0isize;
     let mut fds = Vec::with_capacity(poll_fds.len());
@@ -9,6 +9,7 @@ use linux_raw_sys::{
     general::*,
     select_macros::{FD_ISSET, FD_SET, FD_ZERO},
 };
+use starry_core::task::{WaitChannel, wait_on};
 use starry_signal::SignalSet;
 
 use super::FdPollSet;
@@ -73,6 +74,7 @@ fn do_select(
         "sys_select <= nfds: {} sets: [read: {:?}, write: {:?}, except: {:?}] timeout: {:?}",
         nfds, read_set, write_set, except_set, timeout
     );
+    let _wait = wait_on(WaitChannel::Syscall("select"));
 
     let fd_table = FD_TABLE.read();
     let fd_bitmap = read_set.0 | write_set.0 | except_set.0;
@@ -0,0 +1,166 @@
+use alloc::{sync::Arc, vec};
+use core::ffi::{c_char, c_int};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{TimeValue, wall_time};
+use axtask::current;
+use linux_raw_sys::general::{
+    O_ACCMODE, O_CREAT, O_EXCL, O_NONBLOCK, O_RDONLY, O_WRONLY, SIGEV_NONE, SIGEV_SIGNAL, sigevent,
+    timespec,
+};
+use starry_core::task::AsThread;
+use starry_signal::Signo;
+use starry_vm::{VmMutPtr, VmPtr, vm_load, vm_write_slice};
+
+use crate::{
+    file::{
+        FileLike, add_file_like,
+        mqueue::{self, MessageQueueFile, MqAttr, Notification},
+    },
+    mm::vm_load_string,
+    time::TimeValueLike,
+};
+
+/// Reads an absolute `CLOCK_REALTIME` timeout and returns the time left.
+fn read_timeout(timeout: *const timespec) -> LinuxResult<Option<TimeValue>> {
+    let Some(ts) = timeout.nullable() else {
+        return Ok(None);
+    };
+    let ts = unsafe { ts.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
+    Ok(Some(ts.saturating_sub(wall_time())))
+}
+
+pub fn sys_mq_open(
+    name: *const c_char,
+    flags: c_int,
+    mode: u32,
+    attr: *const MqAttr,
+) -> LinuxResult<isize> {
+    let name = vm_load_string(name)?;
+    debug!(
+        "sys_mq_open <= name: {:?}, flags: {:#o}, mode: {:#o}",
+        name, flags, mode
+    );
+
+    let flags = flags as u32;
+    let attr = match attr.nullable() {
+        Some(attr) if flags & O_CREAT != 0 => Some(unsafe { attr.vm_read_uninit()?.assume_init() }),
+        _ => None,
+    };
+    let mode = mode & !current().as_thread().umask();
+    let (readable, writable) = match flags & O_ACCMODE {
+        O_RDONLY => (true, false),
+        O_WRONLY => (false, true),
+        _ => (true, true),
+    };
+    let queue = mqueue::open(
+        &name,
+        flags & O_CREAT != 0,
+        flags & O_EXCL != 0,
+        mode,
+        attr,
+        (readable, writable),
+    )?;
+    let file = MessageQueueFile::new(&name, queue, readable, writable);
+    file.set_nonblocking(flags & O_NONBLOCK != 0)?;
+    add_file_like(Arc::new(file), true).map(|fd| fd as isize)
+}
+
+pub fn sys_mq_unlink(name: *const c_char) -> LinuxResult<isize> {
+    let name = vm_load_string(name)?;
+    debug!("sys_mq_unlink <= name: {:?}", name);
+    mqueue::unlink(&name)?;
+    Ok(0)
+}
+
+pub fn sys_mq_timedsend(
+    mqdes: c_int,
+    msg: *const u8,
+    len: usize,
+    priority: u32,
+    timeout: *const timespec,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_mq_timedsend <= mqdes: {}, len: {}, priority: {}",
+        mqdes, len, priority
+    );
+    let file = MessageQueueFile::from_fd(mqdes).map_err(|_| LinuxError::EBADF)?;
+    // Before copying in what may be a huge buffer.
+    if len > file.queue().msgsize() {
+        return Err(LinuxError::EMSGSIZE);
+    }
+    let data = vm_load(msg, len)?;
+    file.send(&data, priority, read_timeout(timeout)?)?;
+    Ok(0)
+}
+
+pub fn sys_mq_timedreceive(
+    mqdes: c_int,
+    msg: *mut u8,
+    len: usize,
+    priority: *mut u32,
+    timeout: *const timespec,
+) -> LinuxResult<isize> {
+    debug!("sys_mq_timedreceive <= mqdes: {}, len: {}", mqdes, len);
+    let file = MessageQueueFile::from_fd(mqdes).map_err(|_| LinuxError::EBADF)?;
+    let mut buf = vec![0; len.min(file.queue().attr().mq_msgsize as usize)];
+    let (read, prio) = file.receive(&mut buf, read_timeout(timeout)?)?;
+    vm_write_slice(msg, &buf[..read])?;
+    if let Some(priority) = priority.nullable() {
+        priority.vm_write(prio)?;
+    }
+    Ok(read as isize)
+}
+
+pub fn sys_mq_notify(mqdes: c_int, notification: *const sigevent) -> LinuxResult<isize> {
+    debug!("sys_mq_notify <= mqdes: {}", mqdes);
+    let file = MessageQueueFile::from_fd(mqdes).map_err(|_| LinuxError::EBADF)?;
+    let notification = match notification.nullable() {
+        Some(ptr) => {
+            let event = unsafe { ptr.vm_read_uninit()?.assume_init() };
+            let signo = match event.sigev_notify as u32 {
+                SIGEV_NONE => None,
+                SIGEV_SIGNAL => Some(
+                    u8::try_from(event.sigev_signo)
+                        .ok()
+                        .and_then(Signo::from_repr)
+                        .ok_or(LinuxError::EINVAL)?,
+                ),
+                // `SIGEV_THREAD` needs netlink sockets.
+                _ => return Err(LinuxError::EINVAL),
+            };
+            Some(Notification {
+                pid: current().as_thread().proc_data.proc.pid(),
+                signo,
+                value: event.sigev_value,
+            })
+        }
+        None => None,
+    };
+    file.queue().set_notification(notification)?;
+    Ok(0)
+}
+
+pub fn sys_mq_getsetattr(
+    mqdes: c_int,
+    new_attr: *const MqAttr,
+    old_attr: *mut MqAttr,
+) -> LinuxResult<isize> {
+    debug!("sys_mq_getsetattr <= mqdes: {}", mqdes);
+    let file = MessageQueueFile::from_fd(mqdes).map_err(|_| LinuxError::EBADF)?;
+    let mut attr = file.queue().attr();
+    if file.nonblocking() {
+        attr.mq_flags = O_NONBLOCK as _;
+    }
+    if let Some(new_attr) = new_attr.nullable() {
+        let new_attr = unsafe { new_attr.vm_read_uninit()?.assume_init() };
+        if new_attr.mq_flags & !(O_NONBLOCK as i64) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        file.set_nonblocking(new_attr.mq_flags != 0)?;
+    }
+    if let Some(old_attr) = old_attr.nullable() {
+        old_attr.vm_write(attr)?;
+    }
+    Ok(0)
+}
@@ -1,4 +1,4 @@
-use alloc::sync::Arc;
+use alloc::{format, sync::Arc};
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{
@@ -11,7 +11,8 @@ use axtask::current;
 use linux_raw_sys::general::*;
 use memory_addr::{PAGE_SIZE_4K, VirtAddr, VirtAddrRange};
 use starry_core::{
-    shm::{SHM_MANAGER, ShmInner, ShmidDs},
+    mm::resident_pages,
+    shm::{SHM_MANAGER, ShmInner, ShmidDs, track_shared_pages},
     task::AsThread,
 };
 
@@ -149,10 +150,15 @@ pub fn sys_shmat(shmid: i32, addr: usize, shmflg: u32) -> LinuxResult<isize> {
         let pages = Arc::new(SharedPages::new(length, PageSize::Size4K)?);
         let backend = Backend::new_shared(start_addr, pages.clone());
         aspace.map(start_addr, length, mapping_flags, false, backend)?;
+        track_shared_pages(&pages, length);
 
         shm_inner.map_to_phys(pages);
     }
 
+    proc_data.mapping_names.lock().insert(
+        start_addr.as_usize(),
+        format!("/SYSV{:08x} (deleted)", shm_inner.key()),
+    );
     shm_inner.attach_process(pid, va_range);
     Ok(start_addr.as_usize() as isize)
 }
@@ -219,8 +225,10 @@ pub fn sys_shmdt(shmaddr: usize) -> LinuxResult<isize> {
     let mut shm_inner = shm_inner.lock();
     let va_range = shm_inner.get_addr_range(pid).ok_or(LinuxError::EINVAL)?;
 
-    let mut aspace = proc_data.aspace.lock();
+    let mut aspace = proc_data.lock_unpinned(va_range.start, va_range.size());
+    proc_data.sub_rss(resident_pages(&aspace, va_range.start, va_range.size()));
     aspace.unmap(va_range.start, va_range.size())?;
+    proc_data.remove_mapping_names(va_range.start.as_usize(), va_range.size());
 
     let mut shm_manager = SHM_MANAGER.lock();
     shm_manager.remove_shmaddr(pid, shmaddr);
@@ -0,0 +1,119 @@
+//! The legacy x86_64 entry points: `int 0x80` and the vsyscall page.
+//!
+//! `int 0x80` has no gate for user space, so it faults where it stands. The
+//! fault is taken for the 32-bit syscall ABI when the instruction under `rip`
+//! is `int 0x80`: the i386 syscall number is translated, and the arguments are
+//! moved from `ebx`, `ecx`, `edx`, `esi`, `edi` and `ebp` to the registers of
+//! the 64-bit ABI. Only syscalls whose arguments have the same layout for both
+//! ABIs are translated, as the 32-bit structures are not converted.
+//!
+//! The vsyscall page is not mapped, as in the `xonly` mode of Linux: a call
+//! into it faults on the fetch, and the fault is emulated by doing the
+//! syscall and returning to the caller.
+
+use axerrno::LinuxError;
+use axhal::{context::TrapFrame, time::wall_time};
+use memory_addr::VirtAddr;
+use starry_vm::{VmMutPtr, VmPtr, vm_read_slice};
+use syscalls::Sysno;
+
+use super::handle_syscall;
+
+/// The encoding of `int 0x80`.
+const INT80: [u8; 2] = [0xcd, 0x80];
+
+/// The address of the vsyscall page.
+const VSYSCALL_ADDR: usize = 0xffff_ffff_ff60_0000;
+
+/// The i386 syscalls taken by `int 0x80`, by number.
+const I386_SYSCALLS: &[(u32, Sysno)] = &[
+    (1, Sysno::exit),
+    (3, Sysno::read),
+    (4, Sysno::write),
+    (5, Sysno::open),
+    (6, Sysno::close),
+    (10, Sysno::unlink),
+    (12, Sysno::chdir),
+    (20, Sysno::getpid),
+    (24, Sysno::getuid),
+    (33, Sysno::access),
+    (37, Sysno::kill),
+    (39, Sysno::mkdir),
+    (40, Sysno::rmdir),
+    (41, Sysno::dup),
+    (42, Sysno::pipe),
+    (45, Sysno::brk),
+    (47, Sysno::getgid),
+    (49, Sysno::geteuid),
+    (50, Sysno::getegid),
+    (54, Sysno::ioctl),
+    (63, Sysno::dup2),
+    (64, Sysno::getppid),
+    (91, Sysno::munmap),
+    (122, Sysno::uname),
+    (125, Sysno::mprotect),
+    (158, Sysno::sched_yield),
+    (183, Sysno::getcwd),
+    (224, Sysno::gettid),
+    (252, Sysno::exit_group),
+];
+
+/// Handles the fault of an `int 0x80` at `rip`, returning whether it was one.
+pub fn handle_int80(tf: &mut TrapFrame) -> bool {
+    let mut insn = [0; 2];
+    if vm_read_slice(tf.rip as *const u8, &mut insn).is_err() || insn != INT80 {
+        return false;
+    }
+    let number = tf.rax as u32;
+    match I386_SYSCALLS.iter().find(|(it, _)| *it == number) {
+        Some((_, sysno)) => {
+            let saved = (tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9);
+            let args = [tf.rbx, tf.rcx, tf.rdx, tf.rsi, tf.rdi, tf.rbp].map(|it| it as u32 as u64);
+            tf.rax = sysno.id() as u64;
+            [tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9] = args;
+            handle_syscall(tf);
+            (tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9) = saved;
+        }
+        None => {
+            warn!("Unsupported i386 syscall: {}", number);
+            tf.rax = -LinuxError::ENOSYS.code() as u64;
+        }
+    }
+    tf.rip += INT80.len() as u64;
+    true
+}
+
+/// Emulates a call into the vsyscall page faulting at `addr`, returning
+/// whether it was one.
+pub fn emulate_vsyscall(tf: &mut TrapFrame, addr: VirtAddr) -> bool {
+    let addr = addr.as_usize();
+    if tf.rip as usize != addr || addr & !0xfff != VSYSCALL_ADDR {
+        return false;
+    }
+    let Ok(ret_addr) = (tf.rsp as *const u64).vm_read() else {
+        return false;
+    };
+    match addr - VSYSCALL_ADDR {
+        0x000 => {
+            tf.rax = Sysno::gettimeofday.id() as u64;
+            handle_syscall(tf);
+        }
+        0x400 => {
+            let now = wall_time().as_secs();
+            let tloc = tf.rdi as *mut u64;
+            tf.rax = now;
+            if !tloc.is_null() && tloc.vm_write(now).is_err() {
+                tf.rax = -LinuxError::EFAULT.code() as u64;
+            }
+        }
+        0x800 => {
+            tf.rax = Sysno::getcpu.id() as u64;
+            handle_syscall(tf);
+        }
+        // Only the start of each entry may be called.
+        _ => return false,
+    }
+    tf.rip = ret_addr;
+    tf.rsp += 8;
+    true
+}
@@ -1,7 +1,10 @@
 use axerrno::LinuxResult;
 use axtask::current;
+use memory_addr::align_up_4k;
 use starry_core::task::AsThread;
 
+use super::mmap::check_address_space;
+
 pub fn sys_brk(addr: usize) -> LinuxResult<isize> {
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
@@ -9,6 +12,22 @@ pub fn sys_brk(addr: usize) -> LinuxResult<isize> {
     let heap_bottom = proc_data.get_heap_bottom() as usize;
     if addr != 0 && addr >= heap_bottom && addr <= heap_bottom + starry_core::config::USER_HEAP_SIZE
     {
+        let old_end = align_up_4k(proc_data.get_heap_top());
+        let new_end = align_up_4k(addr);
+        if new_end > old_end {
+            let aspace = proc_data.aspace.lock();
+            if check_address_space(proc_data, &aspace, new_end - old_end).is_err()
+                || proc_data
+                    .commit
+                    .read()
+                    .charge(old_end, new_end - old_end)
+                    .is_err()
+            {
+                return Ok(return_val);
+            }
+        } else {
+            proc_data.commit.read().release(new_end, old_end - new_end);
+        }
         proc_data.set_heap_top(addr);
         return_val = addr as isize;
     }
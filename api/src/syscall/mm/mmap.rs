@@ -1,14 +1,20 @@
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
 use axerrno::{LinuxError, LinuxResult};
 use axfs_ng::FileBackend;
 use axhal::paging::{MappingFlags, PageSize};
-use axmm::backend::{Backend, SharedPages};
+use axmm::{
+    AddrSpace,
+    backend::{Backend, SharedPages},
+};
 use axtask::current;
 use linux_raw_sys::general::*;
 use memory_addr::{MemoryAddr, VirtAddr, VirtAddrRange, align_up_4k};
 use starry_core::{
-    task::AsThread,
+    mm::{OvercommitPolicy, overcommit_policy, resident_pages},
+    resources::RLIM_INFINITY,
+    shm::track_shared_pages,
+    task::{AsThread, ProcessData},
     vfs::{Device, DeviceMmap},
 };
 use starry_vm::{vm_load, vm_write_slice};
@@ -81,12 +87,61 @@ bitflags::bitflags! {
         const HUGE_1GB = MAP_HUGETLB | MAP_HUGE_1GB;
         /// Deprecated flag
         const DENYWRITE = MAP_DENYWRITE;
+        /// Put the mapping in the low 2 GiB of the address space.
+        #[cfg(target_arch = "x86_64")]
+        const LOW_32BIT = MAP_32BIT;
 
         /// Mask for type of mapping
         const TYPE = MAP_TYPE;
     }
 }
 
+/// The end of the low part of the address space that `MAP_32BIT` and
+/// [`ProcessData::low_code`] are about.
+const LOW_REGION_END: usize = 0x8000_0000;
+
+/// Returns the range to look for room for a new mapping in.
+fn search_range(
+    proc_data: &ProcessData,
+    aspace: &AddrSpace,
+    flags: MmapFlags,
+    prot: MmapProt,
+) -> VirtAddrRange {
+    #[cfg(target_arch = "x86_64")]
+    let low = flags.contains(MmapFlags::LOW_32BIT);
+    #[cfg(not(target_arch = "x86_64"))]
+    let low = {
+        let _ = flags;
+        false
+    };
+    let split = VirtAddr::from(LOW_REGION_END).clamp(aspace.base(), aspace.end());
+    if low || (proc_data.low_code() && prot.contains(MmapProt::EXEC)) {
+        VirtAddrRange::new(aspace.base(), split)
+    } else if proc_data.low_code() {
+        VirtAddrRange::new(split, aspace.end())
+    } else {
+        VirtAddrRange::new(aspace.base(), aspace.end())
+    }
+}
+
+/// Checks that `len` more bytes may be mapped into `aspace` under
+/// `RLIMIT_AS`.
+pub(super) fn check_address_space(
+    proc_data: &ProcessData,
+    aspace: &AddrSpace,
+    len: usize,
+) -> LinuxResult<()> {
+    let limit = proc_data.rlim.read()[RLIMIT_AS].current;
+    if limit == RLIM_INFINITY {
+        return Ok(());
+    }
+    let mapped = aspace.areas().map(|area| area.size()).sum::<usize>();
+    if (mapped + len) as u64 > limit {
+        return Err(LinuxError::ENOMEM);
+    }
+    Ok(())
+}
+
 pub fn sys_mmap(
     addr: usize,
     length: usize,
@@ -100,7 +155,7 @@ pub fn sys_mmap(
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
     let permission_flags = MmapProt::from_bits_truncate(prot);
     // TODO: check illegal flags for mmap
     let map_flags = match MmapFlags::from_bits(flags) {
@@ -148,26 +203,41 @@ pub fn sys_mmap(
     let end = (addr + length).align_up(page_size);
     let mut length = end - start;
 
+    let replace =
+        map_flags.contains(MmapFlags::FIXED) && !map_flags.contains(MmapFlags::FIXED_NOREPLACE);
+    // What a fixed mapping replaces must not be in use by a transfer.
+    let mut aspace = if replace {
+        proc_data.lock_unpinned(VirtAddr::from(start), length)
+    } else {
+        proc_data.aspace.lock()
+    };
     let start = if map_flags.intersects(MmapFlags::FIXED | MmapFlags::FIXED_NOREPLACE) {
         let dst_addr = VirtAddr::from(start);
-        if !map_flags.contains(MmapFlags::FIXED_NOREPLACE) {
+        if replace {
+            proc_data.sub_rss(resident_pages(&aspace, dst_addr, length));
             aspace.unmap(dst_addr, length)?;
+            proc_data.commit.read().release(dst_addr.as_usize(), length);
+            proc_data
+                .zero_pages
+                .read()
+                .remove(dst_addr.as_usize(), length);
+            proc_data.remove_mapping_names(dst_addr.as_usize(), length);
         }
         dst_addr
     } else {
+        let range = search_range(proc_data, &aspace, map_flags, permission_flags);
+        let hint = if start == 0 {
+            proc_data.get_mmap_base()
+        } else {
+            start
+        };
+        let hint = VirtAddr::from(hint).clamp(range.start, range.end);
         aspace
-            .find_free_area(
-                VirtAddr::from(start),
-                length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
-            )
-            .or(aspace.find_free_area(
-                aspace.base(),
-                length,
-                VirtAddrRange::new(aspace.base(), aspace.end()),
-            ))
+            .find_free_area(hint, length, range)
+            .or(aspace.find_free_area(range.start, length, range))
             .ok_or(LinuxError::ENOMEM)?
     };
+    check_address_space(proc_data, &aspace, length)?;
 
     let file = if fd > 0 {
         Some(File::from_fd(fd)?)
@@ -175,6 +245,20 @@ pub fn sys_mmap(
         None
     };
 
+    // Private writable and shared anonymous memory may need to be backed by
+    // fresh pages, so it is charged to the commit.
+    let reserve =
+        !map_flags.contains(MmapFlags::NORESERVE) || overcommit_policy() == OvercommitPolicy::Never;
+    let accountable = reserve
+        && page_size == PageSize::Size4K
+        && match map_type {
+            MmapFlags::PRIVATE => permission_flags.contains(MmapProt::WRITE),
+            _ => file.is_none(),
+        };
+    // Private anonymous memory maps the zero page until it is written to.
+    let anonymous =
+        map_type == MmapFlags::PRIVATE && file.is_none() && page_size == PageSize::Size4K;
+
     let backend = match map_type {
         MmapFlags::SHARED | MmapFlags::SHARED_VALIDATE => {
             if let Some(file) = file {
@@ -225,7 +309,13 @@ pub fn sys_mmap(
                     }
                 }
             } else {
-                Backend::new_shared(start, Arc::new(SharedPages::new(length, PageSize::Size4K)?))
+                let pages = Arc::new(SharedPages::new(length, PageSize::Size4K)?);
+                track_shared_pages(&pages, length);
+                proc_data
+                    .mapping_names
+                    .lock()
+                    .insert(start.as_usize(), "/dev/zero (deleted)".into());
+                Backend::new_shared(start, pages)
             }
         }
         MmapFlags::PRIVATE => {
@@ -240,8 +330,24 @@ pub fn sys_mmap(
         _ => return Err(LinuxError::EINVAL),
     };
 
+    if accountable {
+        proc_data.commit.read().charge(start.as_usize(), length)?;
+    }
     let populate = map_flags.contains(MmapFlags::POPULATE);
-    aspace.map(start, length, permission_flags.into(), populate, backend)?;
+    let result = aspace.map(start, length, permission_flags.into(), populate, backend);
+    if result.is_err() && accountable {
+        proc_data.commit.read().release(start.as_usize(), length);
+    }
+    result?;
+    if populate {
+        proc_data.add_rss(resident_pages(&aspace, start, length));
+    }
+    if anonymous {
+        proc_data
+            .zero_pages
+            .read()
+            .add_anon(start.as_usize(), length);
+    }
 
     Ok(start.as_usize() as _)
 }
@@ -249,10 +355,15 @@ pub fn sys_mmap(
 pub fn sys_munmap(addr: usize, length: usize) -> LinuxResult<isize> {
     debug!("sys_munmap <= addr: {:#x}, length: {:x}", addr, length);
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
+    let mut aspace = proc_data.lock_unpinned(start_addr, length);
+    proc_data.sub_rss(resident_pages(&aspace, start_addr, length));
     aspace.unmap(start_addr, length)?;
+    proc_data.commit.read().release(addr, length);
+    proc_data.zero_pages.read().remove(addr, length);
+    proc_data.remove_mapping_names(addr, length);
     Ok(0)
 }
 
@@ -271,14 +382,67 @@ pub fn sys_mprotect(addr: usize, length: usize, prot: u32) -> LinuxResult<isize>
     }
 
     let curr = current();
-    let mut aspace = curr.as_thread().proc_data.aspace.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut aspace = proc_data.aspace.lock();
     let length = align_up_4k(length);
     let start_addr = VirtAddr::from(addr);
-    aspace.protect(start_addr, length, permission_flags.into())?;
+    // Zero pages must stay read-only whatever the protection is.
+    proc_data
+        .zero_pages
+        .read()
+        .strip(&mut aspace, start_addr, length)?;
+    let charged = if permission_flags.contains(MmapProt::WRITE) {
+        charge_writable(proc_data, &aspace, start_addr, length)?
+    } else {
+        Vec::new()
+    };
+    if let Err(err) = aspace.protect(start_addr, length, permission_flags.into()) {
+        let commit = proc_data.commit.read();
+        for (start, end) in charged {
+            commit.release(start, end - start);
+        }
+        return Err(err.into());
+    }
 
     Ok(0)
 }
 
+/// Charges private memory from `start` to `start + length` that is about to
+/// be made writable, as it may need fresh pages like a writable mapping.
+///
+/// Returns the ranges charged.
+fn charge_writable(
+    proc_data: &ProcessData,
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    length: usize,
+) -> LinuxResult<Vec<(usize, usize)>> {
+    let end = start + length;
+    let commit = proc_data.commit.read();
+    let mut charged = Vec::new();
+    for area in aspace.areas() {
+        if area.end() <= start
+            || area.start() >= end
+            || area.flags().contains(MappingFlags::WRITE)
+            || !matches!(area.backend(), Backend::Cow(_))
+        {
+            continue;
+        }
+        let from = area.start().max(start);
+        let to = area.end().min(end);
+        match commit.charge_uncommitted(from.as_usize(), to - from) {
+            Ok(ranges) => charged.extend(ranges),
+            Err(err) => {
+                for (start, end) in charged {
+                    commit.release(start, end - start);
+                }
+                return Err(err);
+            }
+        }
+    }
+    Ok(charged)
+}
+
 pub fn sys_mremap(addr: usize, old_size: usize, new_size: usize, flags: u32) -> LinuxResult<isize> {
     debug!(
         "sys_mremap <= addr: {:#x}, old_size: {:x}, new_size: {:x}, flags: {:#x}",
@@ -8,6 +8,11 @@ use crate::{
     mm::{UserConstPtr, UserPtr},
 };
 
+/// Rounds `len` up as `CMSG_ALIGN` does, to where the next header starts.
+pub fn cmsg_align(len: usize) -> usize {
+    len.next_multiple_of(size_of::<usize>())
+}
+
 pub enum CMsg {
     Rights { fds: Vec<Arc<dyn FileLike>> },
 }
@@ -80,8 +85,10 @@ impl<'a> CMsgBuilder<'a> {
 
         let cmsg_len = size_of::<cmsghdr>() + body_len;
         hdr.cmsg_len = cmsg_len;
-        self.hdr = UserPtr::from(hdr as *const _ as usize + cmsg_len);
-        *self.len += cmsg_len;
+        // The padding after the last message may not fit.
+        let advance = cmsg_align(cmsg_len).min(self.capacity - *self.len);
+        self.hdr = UserPtr::from(hdr as *const _ as usize + advance);
+        *self.len += advance;
         Ok(true)
     }
 }
@@ -1,22 +1,52 @@
 use alloc::{boxed::Box, vec::Vec};
 use core::net::Ipv4Addr;
 
-use axerrno::LinuxResult;
-use axio::{Buf, BufMut};
-use axnet::{CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx, SocketOps};
-use linux_raw_sys::net::{
-    MSG_PEEK, MSG_TRUNC, SCM_RIGHTS, SOL_SOCKET, cmsghdr, msghdr, sockaddr, socklen_t,
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::monotonic_time;
+use axio::{Buf, BufMut, IoEvents, Pollable};
+use axnet::{CMsgData, RecvFlags, RecvOptions, SendFlags, SendOptions, SocketAddrEx};
+use axtask::current;
+use linux_raw_sys::{
+    general::{UIO_MAXIOV, timespec},
+    net::{
+        MSG_CTRUNC, MSG_DONTWAIT, MSG_NOSIGNAL, MSG_PEEK, MSG_TRUNC, SCM_RIGHTS, SOL_SOCKET,
+        cmsghdr, mmsghdr, msghdr, sockaddr, socklen_t,
+    },
 };
+use starry_core::task::{AsThread, send_signal_to_process};
+use starry_signal::{SignalInfo, Signo};
 use starry_vm::{VmBytes, VmBytesMut};
 
 use crate::{
-    file::{FileLike, Socket, add_file_like},
+    file::{
+        FileLike, NetlinkSocket, Socket, add_file_like,
+        ratelimit::{self, Direction},
+    },
     io::{IoVec, IoVectorBuf},
     mm::{UserConstPtr, UserPtr},
     socket::SocketAddrExt,
-    syscall::net::{CMsg, CMsgBuilder},
+    syscall::net::{CMsg, CMsgBuilder, cmsg_align},
+    time::TimeValueLike,
 };
 
+/// Makes `recvmmsg` stop waiting once a message has been received. Missing
+/// from `linux_raw_sys`.
+const MSG_WAITFORONE: u32 = 0x10000;
+
+/// Fails with `EAGAIN` if `MSG_DONTWAIT` is given and the socket is not ready
+/// for `events`, so that a blocking socket does not block for this call.
+fn check_dontwait(socket: &impl FileLike, flags: u32, events: IoEvents) -> LinuxResult<()> {
+    if flags & MSG_DONTWAIT != 0
+        && !socket.nonblocking()
+        && !socket
+            .poll()
+            .intersects(events | IoEvents::ERR | IoEvents::HUP)
+    {
+        return Err(LinuxError::EAGAIN);
+    }
+    Ok(())
+}
+
 fn send_impl(
     fd: i32,
     mut src: impl Buf,
@@ -25,6 +55,11 @@ fn send_impl(
     addrlen: socklen_t,
     cmsg: Vec<CMsgData>,
 ) -> LinuxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        // Everything goes to the kernel, whatever the address.
+        return Ok(socket.send(&mut src)? as isize);
+    }
+
     let addr = if addr.is_null() || addrlen == 0 {
         None
     } else {
@@ -34,16 +69,25 @@ fn send_impl(
     debug!("sys_send <= fd: {}, flags: {}, addr: {:?}", fd, flags, addr);
 
     let socket = Socket::from_fd(fd)?;
-    let sent = socket.send(
-        &mut src,
-        SendOptions {
-            to: addr,
-            flags: SendFlags::default(),
-            cmsg,
-        },
-    )?;
+    check_dontwait(&*socket, flags, IoEvents::OUT)?;
+    let result = ratelimit::limit(fd, Direction::Write, || {
+        socket.send(
+            &mut src,
+            SendOptions {
+                to: addr,
+                flags: SendFlags::default(),
+                cmsg,
+            },
+        )
+    });
+    if result == Err(LinuxError::EPIPE) && flags & MSG_NOSIGNAL == 0 {
+        let _ = send_signal_to_process(
+            current().as_thread().proc_data.proc.pid(),
+            Some(SignalInfo::new_kernel(Signo::SIGPIPE)),
+        );
+    }
 
-    Ok(sent as isize)
+    Ok(result? as isize)
 }
 
 pub fn sys_sendto(
@@ -57,8 +101,7 @@ pub fn sys_sendto(
     send_impl(fd, VmBytes::new(buf, len), flags, addr, addrlen, Vec::new())
 }
 
-pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
-    let msg = msg.get_as_ref()?;
+fn sendmsg_impl(fd: i32, msg: &msghdr, flags: u32) -> LinuxResult<isize> {
     let mut cmsg = Vec::new();
     if !msg.msg_control.is_null() {
         let mut ptr = msg.msg_control as usize;
@@ -66,10 +109,10 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResul
         while ptr + size_of::<cmsghdr>() <= ptr_end {
             let hdr = UserConstPtr::<cmsghdr>::from(ptr).get_as_ref()?;
             if ptr_end - ptr < hdr.cmsg_len {
-                return Err(axerrno::LinuxError::EINVAL);
+                return Err(LinuxError::EINVAL);
             }
             cmsg.push(Box::new(CMsg::parse(hdr)?) as CMsgData);
-            ptr += hdr.cmsg_len;
+            ptr += cmsg_align(hdr.cmsg_len);
         }
     }
     send_impl(
@@ -82,6 +125,36 @@ pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResul
     )
 }
 
+pub fn sys_sendmsg(fd: i32, msg: UserConstPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    sendmsg_impl(fd, msg.get_as_ref()?, flags)
+}
+
+pub fn sys_sendmmsg(
+    fd: i32,
+    msgvec: UserPtr<mmsghdr>,
+    vlen: u32,
+    flags: u32,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_sendmmsg <= fd: {}, vlen: {}, flags: {}",
+        fd, vlen, flags
+    );
+    let msgvec = msgvec.get_as_mut_slice(vlen.min(UIO_MAXIOV) as usize)?;
+    let mut sent = 0;
+    for entry in msgvec {
+        match sendmsg_impl(fd, &entry.msg_hdr, flags) {
+            Ok(len) => entry.msg_len = len as _,
+            // The error is reported if nothing could be sent.
+            Err(err) if sent == 0 => return Err(err),
+            Err(_) => break,
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Receives a message, returning its length and the `MSG_*` flags to report
+/// in `msg_flags`.
 fn recv_impl(
     fd: i32,
     mut dst: impl BufMut,
@@ -89,10 +162,28 @@ fn recv_impl(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
     cmsg_builder: Option<CMsgBuilder>,
-) -> LinuxResult<isize> {
+) -> LinuxResult<(usize, u32)> {
     debug!("sys_recv <= fd: {}, flags: {}", fd, flags);
 
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        check_dontwait(&*socket, flags, IoEvents::IN)?;
+        let capacity = dst.remaining_mut();
+        let (len, truncated) = socket.recv(&mut dst, flags & MSG_PEEK != 0)?;
+        if !addr.is_null() {
+            socket
+                .peer_addr()
+                .write_to_user(addr, addrlen.get_as_mut()?)?;
+        }
+        let len = if flags & MSG_TRUNC != 0 {
+            len
+        } else {
+            len.min(capacity)
+        };
+        return Ok((len, if truncated { MSG_TRUNC } else { 0 }));
+    }
+
     let socket = Socket::from_fd(fd)?;
+    check_dontwait(&*socket, flags, IoEvents::IN)?;
     let mut recv_flags = RecvFlags::empty();
     if flags & MSG_PEEK != 0 {
         recv_flags |= RecvFlags::PEEK;
@@ -103,47 +194,61 @@ fn recv_impl(
 
     let mut cmsg = Vec::new();
 
+    let capacity = dst.remaining_mut();
     let mut remote_addr =
         (!addr.is_null()).then(|| SocketAddrEx::Ip((Ipv4Addr::UNSPECIFIED, 0).into()));
-    let recv = socket.recv(
-        &mut dst,
-        RecvOptions {
-            from: remote_addr.as_mut(),
-            flags: recv_flags,
-            cmsg: Some(&mut cmsg),
-        },
-    )?;
+    let recv = ratelimit::limit(fd, Direction::Read, || {
+        socket.recv(
+            &mut dst,
+            RecvOptions {
+                from: remote_addr.as_mut(),
+                flags: recv_flags,
+                cmsg: Some(&mut cmsg),
+            },
+        )
+    })?;
 
     if let Some(remote_addr) = remote_addr {
         remote_addr.write_to_user(addr, addrlen.get_as_mut()?)?;
     }
 
-    if let Some(mut builder) = cmsg_builder {
-        for cmsg in cmsg {
-            let Ok(cmsg) = cmsg.downcast::<CMsg>() else {
-                warn!("received unexpected cmsg");
-                continue;
-            };
-
-            let pushed = match *cmsg {
-                CMsg::Rights { fds } => builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
-                    let mut written = 0;
-                    for (f, chunk) in fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>())) {
-                        let fd = add_file_like(f, false)?;
-                        chunk.copy_from_slice(&fd.to_ne_bytes());
-                        written += size_of::<i32>();
-                    }
-                    Ok(written)
-                })?,
-            };
-            if !pushed {
-                break;
+    let mut msg_flags = 0;
+    if recv > capacity {
+        msg_flags |= MSG_TRUNC;
+    }
+    match cmsg_builder {
+        Some(mut builder) => {
+            for cmsg in cmsg {
+                let Ok(cmsg) = cmsg.downcast::<CMsg>() else {
+                    warn!("received unexpected cmsg");
+                    continue;
+                };
+
+                let pushed = match *cmsg {
+                    CMsg::Rights { fds } => builder.push(SOL_SOCKET, SCM_RIGHTS, |data| {
+                        let mut written = 0;
+                        for (f, chunk) in
+                            fds.into_iter().zip(data.chunks_exact_mut(size_of::<i32>()))
+                        {
+                            let fd = add_file_like(f, false)?;
+                            chunk.copy_from_slice(&fd.to_ne_bytes());
+                            written += size_of::<i32>();
+                        }
+                        Ok(written)
+                    })?,
+                };
+                if !pushed {
+                    msg_flags |= MSG_CTRUNC;
+                    break;
+                }
             }
         }
+        None if !cmsg.is_empty() => msg_flags |= MSG_CTRUNC,
+        None => {}
     }
 
     debug!("sys_recv => fd: {}, recv: {}", fd, recv);
-    Ok(recv as isize)
+    Ok((recv, msg_flags))
 }
 
 pub fn sys_recvfrom(
@@ -154,12 +259,12 @@ pub fn sys_recvfrom(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
-    recv_impl(fd, VmBytesMut::new(buf, len), flags, addr, addrlen, None)
+    let (recv, _) = recv_impl(fd, VmBytesMut::new(buf, len), flags, addr, addrlen, None)?;
+    Ok(recv as isize)
 }
 
-pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
-    let msg = msg.get_as_mut()?;
-    recv_impl(
+fn recvmsg_impl(fd: i32, msg: &mut msghdr, flags: u32) -> LinuxResult<isize> {
+    let (recv, msg_flags) = recv_impl(
         fd,
         IoVectorBuf::new(msg.msg_iov as *mut IoVec, msg.msg_iovlen)?.into_io(),
         flags,
@@ -171,5 +276,48 @@ pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isi
                 &mut msg.msg_controllen,
             )
         }),
-    )
+    )?;
+    msg.msg_flags = msg_flags as _;
+    Ok(recv as isize)
+}
+
+pub fn sys_recvmsg(fd: i32, msg: UserPtr<msghdr>, flags: u32) -> LinuxResult<isize> {
+    recvmsg_impl(fd, msg.get_as_mut()?, flags)
+}
+
+pub fn sys_recvmmsg(
+    fd: i32,
+    msgvec: UserPtr<mmsghdr>,
+    vlen: u32,
+    mut flags: u32,
+    timeout: UserConstPtr<timespec>,
+) -> LinuxResult<isize> {
+    debug!(
+        "sys_recvmmsg <= fd: {}, vlen: {}, flags: {}",
+        fd, vlen, flags
+    );
+    // As on Linux, the timeout is only checked after each message.
+    let deadline = if timeout.is_null() {
+        None
+    } else {
+        Some(monotonic_time() + timeout.get_as_ref()?.try_into_time_value()?)
+    };
+    let msgvec = msgvec.get_as_mut_slice(vlen.min(UIO_MAXIOV) as usize)?;
+    let mut received = 0;
+    for entry in msgvec {
+        match recvmsg_impl(fd, &mut entry.msg_hdr, flags) {
+            Ok(len) => entry.msg_len = len as _,
+            // The error is reported if nothing was received.
+            Err(err) if received == 0 => return Err(err),
+            Err(_) => break,
+        }
+        received += 1;
+        if flags & MSG_WAITFORONE != 0 {
+            flags |= MSG_DONTWAIT;
+        }
+        if deadline.is_some_and(|deadline| monotonic_time() >= deadline) {
+            break;
+        }
+    }
+    Ok(received)
 }
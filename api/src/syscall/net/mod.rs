@@ -4,4 +4,40 @@ mod name;
 mod opt;
 mod socket;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::LinuxError;
+use starry_core::sysctl;
+
 pub use self::{cmsg::*, io::*, name::*, opt::*, socket::*};
+
+static RMEM_MAX: AtomicUsize = AtomicUsize::new(212992);
+static WMEM_MAX: AtomicUsize = AtomicUsize::new(212992);
+
+/// The largest receive buffer `SO_RCVBUF` may ask for, `net.core.rmem_max`.
+pub fn rmem_max() -> usize {
+    RMEM_MAX.load(Ordering::Relaxed)
+}
+
+/// The largest send buffer `SO_SNDBUF` may ask for, `net.core.wmem_max`.
+pub fn wmem_max() -> usize {
+    WMEM_MAX.load(Ordering::Relaxed)
+}
+
+/// Registers the `net.*` sysctls, the limits of the socket buffers.
+pub fn register_sysctls() {
+    sysctl::register_int("net.core.rmem_max", rmem_max, |max| {
+        if max > i32::MAX as usize {
+            return Err(LinuxError::EINVAL);
+        }
+        RMEM_MAX.store(max, Ordering::Relaxed);
+        Ok(())
+    });
+    sysctl::register_int("net.core.wmem_max", wmem_max, |max| {
+        if max > i32::MAX as usize {
+            return Err(LinuxError::EINVAL);
+        }
+        WMEM_MAX.store(max, Ordering::Relaxed);
+        Ok(())
+    });
+}
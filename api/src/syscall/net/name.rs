@@ -3,7 +3,7 @@ use axnet::SocketOps;
 use linux_raw_sys::net::{sockaddr, socklen_t};
 
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, NetlinkSocket, Socket},
     mm::UserPtr,
     socket::SocketAddrExt,
 };
@@ -13,6 +13,12 @@ pub fn sys_getsockname(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        socket
+            .local_addr()?
+            .write_to_user(addr, addrlen.get_as_mut()?)?;
+        return Ok(0);
+    }
     let socket = Socket::from_fd(fd)?;
     let local_addr = socket.local_addr()?;
     debug!("sys_getsockname <= fd: {}, addr: {:?}", fd, local_addr);
@@ -26,6 +32,12 @@ pub fn sys_getpeername(
     addr: UserPtr<sockaddr>,
     addrlen: UserPtr<socklen_t>,
 ) -> LinuxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        socket
+            .peer_addr()
+            .write_to_user(addr, addrlen.get_as_mut()?)?;
+        return Ok(0);
+    }
     let socket = Socket::from_fd(fd)?;
     let peer_addr = socket.peer_addr()?;
     debug!("sys_getpeername <= fd: {}, addr: {:?}", fd, peer_addr);
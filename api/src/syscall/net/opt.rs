@@ -1,9 +1,15 @@
+use core::time::Duration;
+
 use axerrno::{LinuxError, LinuxResult};
 use axnet::options::{Configurable, GetSocketOption, SetSocketOption};
-use linux_raw_sys::net::socklen_t;
+use linux_raw_sys::net::{
+    SO_ERROR, SO_LINGER, SO_RCVBUF, SO_RCVBUFFORCE, SO_SNDBUF, SO_SNDBUFFORCE, SOL_NETLINK,
+    SOL_SOCKET, TCP_QUICKACK, linger, socklen_t,
+};
 
+use super::{rmem_max, wmem_max};
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, NetlinkSocket, Socket},
     mm::{UserConstPtr, UserPtr},
 };
 
@@ -11,6 +17,21 @@ const PROTO_TCP: u32 = linux_raw_sys::net::IPPROTO_TCP as u32;
 
 const PROTO_IP: u32 = linux_raw_sys::net::IPPROTO_IP as u32;
 
+/// The buffer size reported for netlink sockets, Linux's default.
+const NETLINK_BUFFER_SIZE: i32 = 212992;
+
+/// The smallest buffers a socket can be given, as in Linux.
+const SOCK_MIN_SNDBUF: usize = 4608;
+const SOCK_MIN_RCVBUF: usize = 2304;
+
+/// Checks that `TCP_*` options are only used on TCP sockets.
+fn check_tcp(socket: &Socket) -> LinuxResult<()> {
+    match &**socket {
+        axnet::Socket::Tcp(_) => Ok(()),
+        _ => Err(LinuxError::EOPNOTSUPP),
+    }
+}
+
 mod conv {
     use axerrno::{LinuxError, LinuxResult};
     use axnet::options::UnixCredentials;
@@ -83,7 +104,6 @@ macro_rules! call_dispatch {
         call_dispatch! {
             $dispatch, $pat,
             (SOL_SOCKET, SO_REUSEADDR) => ReuseAddress as IntBool,
-            (SOL_SOCKET, SO_ERROR) => Error,
             (SOL_SOCKET, SO_DONTROUTE) => DontRoute as IntBool,
             (SOL_SOCKET, SO_SNDBUF) => SendBuffer as Int<usize>,
             (SOL_SOCKET, SO_RCVBUF) => ReceiveBuffer as Int<usize>,
@@ -137,7 +157,39 @@ pub fn sys_getsockopt(
         val.cast().get_as_mut()
     }
 
+    if NetlinkSocket::from_fd(fd).is_ok() {
+        return match (level, optname) {
+            (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF) => {
+                *get::<i32>(optval, optlen)? = NETLINK_BUFFER_SIZE;
+                Ok(0)
+            }
+            _ => Err(LinuxError::ENOPROTOOPT),
+        };
+    }
+
     let socket = Socket::from_fd(fd)?;
+    // Options the stack knows nothing about, kept by the socket itself.
+    match (level, optname) {
+        (SOL_SOCKET, SO_ERROR) => {
+            *get::<i32>(optval, optlen)? = socket.take_error()?;
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_LINGER) => {
+            let timeout = socket.linger();
+            *get(optval, optlen)? = linger {
+                l_onoff: timeout.is_some() as _,
+                l_linger: timeout.map_or(0, |it| it.as_secs() as _),
+            };
+            return Ok(0);
+        }
+        (PROTO_TCP, TCP_QUICKACK) => {
+            check_tcp(&socket)?;
+            *get::<i32>(optval, optlen)? = socket.quick_ack() as _;
+            return Ok(0);
+        }
+        _ => {}
+    }
+
     macro_rules! dispatch {
         ($which:ident) => {
             socket.get_option(GetSocketOption::$which(get(optval, optlen)?))?;
@@ -176,7 +228,56 @@ pub fn sys_setsockopt(
         val.cast().get_as_ref()
     }
 
+    if NetlinkSocket::from_fd(fd).is_ok() {
+        // Replies are never dropped, so neither the buffer sizes nor the
+        // netlink options change anything.
+        return match (level, optname) {
+            (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF | SO_SNDBUFFORCE | SO_RCVBUFFORCE)
+            | (SOL_NETLINK, _) => Ok(0),
+            _ => Err(LinuxError::ENOPROTOOPT),
+        };
+    }
+
     let socket = Socket::from_fd(fd)?;
+    // Options the stack knows nothing about, kept by the socket itself.
+    match (level, optname) {
+        (SOL_SOCKET, SO_LINGER) => {
+            let val: &linger = get(optval, optlen)?;
+            let timeout = Duration::from_secs(val.l_linger.max(0) as u64);
+            socket.set_linger((val.l_onoff != 0).then_some(timeout));
+            return Ok(0);
+        }
+        (PROTO_TCP, TCP_QUICKACK) => {
+            check_tcp(&socket)?;
+            socket.set_quick_ack(*get::<i32>(optval, optlen)? != 0);
+            return Ok(0);
+        }
+        (SOL_SOCKET, SO_SNDBUF | SO_RCVBUF | SO_SNDBUFFORCE | SO_RCVBUFFORCE) => {
+            let val = *get::<i32>(optval, optlen)?;
+            let send = matches!(optname, SO_SNDBUF | SO_SNDBUFFORCE);
+            let (max, min) = if send {
+                (wmem_max(), SOCK_MIN_SNDBUF)
+            } else {
+                (rmem_max(), SOCK_MIN_RCVBUF)
+            };
+            // As in Linux, the size is capped by `net.core.[rw]mem_max`
+            // unless forced, and doubled to leave room for bookkeeping.
+            let size = if matches!(optname, SO_SNDBUFFORCE | SO_RCVBUFFORCE) {
+                val.max(0) as usize
+            } else {
+                (val as u32 as usize).min(max)
+            };
+            let size = size.saturating_mul(2).max(min);
+            socket.set_option(if send {
+                SetSocketOption::SendBuffer(&size)
+            } else {
+                SetSocketOption::ReceiveBuffer(&size)
+            })?;
+            return Ok(0);
+        }
+        _ => {}
+    }
+
     macro_rules! dispatch {
         ($which:ident) => {
             socket.set_option(SetSocketOption::$which(get(optval, optlen)?))?;
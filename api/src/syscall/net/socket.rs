@@ -1,3 +1,5 @@
+use alloc::sync::Arc;
+
 use axerrno::{LinuxError, LinuxResult};
 use axnet::{
     Shutdown, SocketAddrEx, SocketOps,
@@ -9,14 +11,15 @@ use axtask::current;
 use linux_raw_sys::{
     general::{O_CLOEXEC, O_NONBLOCK},
     net::{
-        AF_INET, AF_UNIX, IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR, SHUT_WR, SOCK_DGRAM,
-        SOCK_SEQPACKET, SOCK_STREAM, sockaddr, socklen_t,
+        AF_INET, AF_NETLINK, AF_UNIX, IPPROTO_TCP, IPPROTO_UDP, SHUT_RD, SHUT_RDWR, SHUT_WR,
+        SOCK_DGRAM, SOCK_RAW, SOCK_SEQPACKET, SOCK_STREAM, sockaddr, socklen_t,
     },
+    netlink::{NETLINK_ROUTE, sockaddr_nl},
 };
 use starry_core::task::AsThread;
 
 use crate::{
-    file::{FileLike, Socket},
+    file::{FileLike, NetlinkSocket, Socket, add_file_like},
     mm::{UserConstPtr, UserPtr},
     socket::SocketAddrExt,
 };
@@ -28,6 +31,19 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
     );
     let ty = raw_ty & 0xFF;
 
+    if domain == AF_NETLINK {
+        if ty != SOCK_RAW && ty != SOCK_DGRAM {
+            return Err(LinuxError::ESOCKTNOSUPPORT);
+        }
+        if proto != NETLINK_ROUTE {
+            return Err(LinuxError::EPROTONOSUPPORT);
+        }
+        let socket = NetlinkSocket::new();
+        socket.set_nonblocking(raw_ty & O_NONBLOCK != 0)?;
+        let cloexec = raw_ty & O_CLOEXEC != 0;
+        return socket.add_to_fd_table(cloexec).map(|fd| fd as isize);
+    }
+
     let pid = current().as_thread().proc_data.proc.pid();
     let socket = match (domain, ty) {
         (AF_INET, SOCK_STREAM) => {
@@ -52,7 +68,7 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
             return Err(LinuxError::EAFNOSUPPORT);
         }
     };
-    let socket = Socket(socket);
+    let socket = Socket::new(socket);
 
     if raw_ty & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
@@ -63,6 +79,11 @@ pub fn sys_socket(domain: u32, raw_ty: u32, proto: u32) -> LinuxResult<isize> {
 }
 
 pub fn sys_bind(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> LinuxResult<isize> {
+    if let Ok(socket) = NetlinkSocket::from_fd(fd) {
+        socket.bind(&sockaddr_nl::read_from_user(addr, addrlen)?)?;
+        return Ok(0);
+    }
+
     let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
     debug!("sys_bind <= fd: {}, addr: {:?}", fd, addr);
 
@@ -75,13 +96,9 @@ pub fn sys_connect(fd: i32, addr: UserConstPtr<sockaddr>, addrlen: u32) -> Linux
     let addr = SocketAddrEx::read_from_user(addr, addrlen)?;
     debug!("sys_connect <= fd: {}, addr: {:?}", fd, addr);
 
-    Socket::from_fd(fd)?.connect(addr).map_err(|e| {
-        if e == LinuxError::EAGAIN {
-            LinuxError::EINPROGRESS
-        } else {
-            e
-        }
-    })?;
+    let socket = Socket::from_fd(fd)?;
+    socket.prepare_connect(&addr);
+    socket.connect(addr)?;
 
     Ok(0)
 }
@@ -116,14 +133,16 @@ pub fn sys_accept4(
 
     let cloexec = flags & O_CLOEXEC != 0;
 
-    let socket = Socket::from_fd(fd)?;
-    let socket = Socket(socket.accept()?);
+    let listener = Socket::from_fd(fd)?;
+    let socket = Arc::new(Socket::new(listener.accept()?));
+    socket.set_linger(listener.linger());
     if flags & O_NONBLOCK != 0 {
         socket.set_nonblocking(true)?;
     }
+    socket.pair_loopback();
 
     let remote_addr = socket.local_addr()?;
-    let fd = socket.add_to_fd_table(cloexec).map(|fd| fd as isize)?;
+    let fd = add_file_like(socket, cloexec).map(|fd| fd as isize)?;
     debug!("sys_accept => fd: {}, addr: {:?}", fd, remote_addr);
 
     if !addr.is_null() {
@@ -177,8 +196,8 @@ pub fn sys_socketpair(
             return Err(LinuxError::ESOCKTNOSUPPORT);
         }
     };
-    let sock1 = Socket(axnet::Socket::Unix(sock1));
-    let sock2 = Socket(axnet::Socket::Unix(sock2));
+    let sock1 = Socket::new(axnet::Socket::Unix(sock1));
+    let sock2 = Socket::new(axnet::Socket::Unix(sock2));
 
     if raw_ty & O_NONBLOCK != 0 {
         sock1.set_nonblocking(true)?;
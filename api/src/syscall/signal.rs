@@ -11,11 +11,11 @@ use linux_raw_sys::general::{
     timespec,
 };
 use starry_core::task::{
-    AsThread, processes, send_signal_to_process, send_signal_to_process_group,
-    send_signal_to_thread,
+    AsThread, PidNamespace, WaitChannel, current_pid_ns, get_process_data, processes,
+    send_signal_to_process, send_signal_to_process_group, send_signal_to_thread, wait_on,
 };
 use starry_process::Pid;
-use starry_signal::{SignalInfo, SignalSet, SignalStack, Signo};
+use starry_signal::{SignalDisposition, SignalInfo, SignalSet, SignalStack, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
@@ -81,12 +81,17 @@ pub fn sys_rt_sigaction(
     }
 
     let curr = current();
-    let mut actions = curr.as_thread().proc_data.signal.actions.lock();
+    let proc_data = &curr.as_thread().proc_data;
+    let mut actions = proc_data.signal.actions.lock();
     if let Some(oldact) = oldact.nullable() {
         oldact.vm_write(actions[signo].clone().into())?;
     }
     if let Some(act) = act.nullable() {
-        let act = unsafe { act.vm_read_uninit()?.assume_init() }.into();
+        let act: kernel_sigaction = unsafe { act.vm_read_uninit()?.assume_init() };
+        if signo == Signo::SIGCHLD {
+            proc_data.set_sigchld_flags(act.sa_flags as _);
+        }
+        let act = act.into();
         debug!("sys_rt_sigaction <= signo: {:?}, act: {:?}", signo, act);
         actions[signo] = act;
     }
@@ -111,13 +116,33 @@ fn make_siginfo(signo: u32, code: i32) -> LinuxResult<Option<SignalInfo>> {
     )))
 }
 
+/// Whether a signal sent from inside `pid_ns` to the process `pid` is
+/// discarded, because `pid` is the init of the namespace and does not handle
+/// the signal.
+fn ignored_by_ns_init(pid_ns: &PidNamespace, pid: Pid, sig: Option<&SignalInfo>) -> bool {
+    let Some(sig) = sig else {
+        return false;
+    };
+    pid_ns.init_pid() == Some(pid)
+        && get_process_data(pid).is_ok_and(|data| {
+            matches!(
+                data.signal.actions.lock()[sig.signo()].disposition,
+                SignalDisposition::Default
+            )
+        })
+}
+
 pub fn sys_kill(pid: i32, signo: u32) -> LinuxResult<isize> {
     debug!("sys_kill: pid = {}, signo = {}", pid, signo);
     let sig = make_siginfo(signo, SI_USER as _)?;
+    let pid_ns = current_pid_ns();
 
     match pid {
         1.. => {
-            send_signal_to_process(pid as _, sig)?;
+            let pid = pid_ns.global_pid(pid as _)?;
+            if !ignored_by_ns_init(&pid_ns, pid, sig.as_ref()) {
+                send_signal_to_process(pid, sig)?;
+            }
         }
         0 => {
             let pgid = current().as_thread().proc_data.proc.group().pgid();
@@ -132,15 +157,20 @@ pub fn sys_kill(pid: i32, signo: u32) -> LinuxResult<isize> {
                     //    implementation-defined system processes.  Linux allows a process
                     //    to signal itself, but on Linux the call kill(-1,sig) does not
                     //    signal the calling process.
-                    if proc_data.proc.is_init() || proc_data.proc.pid() == curr_pid {
+                    let pid = proc_data.proc.pid();
+                    if proc_data.proc.is_init()
+                        || pid == curr_pid
+                        || pid_ns.local_pid(pid).is_none()
+                        || pid_ns.init_pid() == Some(pid)
+                    {
                         continue;
                     }
-                    let _ = send_signal_to_process(proc_data.proc.pid(), Some(sig.clone()));
+                    let _ = send_signal_to_process(pid, Some(sig.clone()));
                 }
             }
         }
         ..-1 => {
-            send_signal_to_process_group((-pid) as Pid, sig)?;
+            send_signal_to_process_group(pid_ns.global_pid((-pid) as Pid)?, sig)?;
         }
     }
     Ok(0)
@@ -148,13 +178,14 @@ pub fn sys_kill(pid: i32, signo: u32) -> LinuxResult<isize> {
 
 pub fn sys_tkill(tid: Pid, signo: u32) -> LinuxResult<isize> {
     let sig = make_siginfo(signo, SI_TKILL)?;
-    send_signal_to_thread(None, tid, sig)?;
+    send_signal_to_thread(None, current_pid_ns().global_pid(tid)?, sig)?;
     Ok(0)
 }
 
 pub fn sys_tgkill(tgid: Pid, tid: Pid, signo: u32) -> LinuxResult<isize> {
     let sig = make_siginfo(signo, SI_TKILL)?;
-    send_signal_to_thread(Some(tgid), tid, sig)?;
+    let pid_ns = current_pid_ns();
+    send_signal_to_thread(Some(pid_ns.global_pid(tgid)?), pid_ns.global_pid(tid)?, sig)?;
     Ok(0)
 }
 
@@ -186,6 +217,7 @@ pub fn sys_rt_sigqueueinfo(
 ) -> LinuxResult<isize> {
     check_sigset_size(sigsetsize)?;
 
+    let tgid = current_pid_ns().global_pid(tgid)?;
     let sig = make_queue_signal_info(tgid, signo, sig)?;
     send_signal_to_process(tgid, sig)?;
     Ok(0)
@@ -200,8 +232,10 @@ pub fn sys_rt_tgsigqueueinfo(
 ) -> LinuxResult<isize> {
     check_sigset_size(sigsetsize)?;
 
+    let pid_ns = current_pid_ns();
+    let tgid = pid_ns.global_pid(tgid)?;
     let sig = make_queue_signal_info(tgid, signo, sig)?;
-    send_signal_to_thread(Some(tgid), tid, sig)?;
+    send_signal_to_thread(Some(tgid), pid_ns.global_pid(tid)?, sig)?;
     Ok(0)
 }
 
@@ -254,6 +288,7 @@ pub fn sys_rt_sigtimedwait(
         }
     });
 
+    let _wait = wait_on(WaitChannel::Signal("rt_sigtimedwait"));
     let Some(sig) = block_on(timeout_opt(fut, timeout)) else {
         // Timeout
         signal.set_blocked(old_blocked);
@@ -286,6 +321,7 @@ pub fn sys_rt_sigsuspend(
 
     tf.set_retval(-LinuxError::EINTR.code() as usize);
 
+    let _wait = wait_on(WaitChannel::Signal("rt_sigsuspend"));
     block_on(poll_fn(|context| {
         if check_signals(thr, tf, Some(old_blocked)) {
             return Poll::Ready(());
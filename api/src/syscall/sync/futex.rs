@@ -1,18 +1,24 @@
-use core::sync::atomic::Ordering;
+use core::time::Duration;
 
 use axerrno::{LinuxError, LinuxResult};
+use axhal::time::{TimeValue, monotonic_time, wall_time};
 use axtask::current;
 use linux_raw_sys::general::{
-    FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_REQUEUE, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAKE,
-    FUTEX_WAKE_BITSET, robust_list_head, timespec,
+    FUTEX_BITSET_MATCH_ANY, FUTEX_CLOCK_REALTIME, FUTEX_CMD_MASK, FUTEX_CMP_REQUEUE, FUTEX_LOCK_PI,
+    FUTEX_LOCK_PI2, FUTEX_OP_ADD, FUTEX_OP_ANDN, FUTEX_OP_CMP_EQ, FUTEX_OP_CMP_GE, FUTEX_OP_CMP_GT,
+    FUTEX_OP_CMP_LE, FUTEX_OP_CMP_LT, FUTEX_OP_CMP_NE, FUTEX_OP_OPARG_SHIFT, FUTEX_OP_OR,
+    FUTEX_OP_SET, FUTEX_OP_XOR, FUTEX_OWNER_DIED, FUTEX_REQUEUE, FUTEX_TID_MASK, FUTEX_TRYLOCK_PI,
+    FUTEX_UNLOCK_PI, FUTEX_WAIT, FUTEX_WAIT_BITSET, FUTEX_WAITERS, FUTEX_WAKE, FUTEX_WAKE_BITSET,
+    FUTEX_WAKE_OP, robust_list_head, timespec,
 };
 use starry_core::{
-    futex::FutexKey,
-    task::{AsThread, get_task},
+    futex::{FutexKey, FutexTable},
+    task::{AsThread, WaitChannel, current_pid_ns, get_task, wait_on},
 };
+use starry_process::Pid;
 use starry_vm::{VmMutPtr, VmPtr};
 
-use crate::time::TimeValueLike;
+use crate::{mm::vm_update_u32, time::TimeValueLike};
 
 fn assert_unsigned(value: u32) -> LinuxResult<u32> {
     if (value as i32) < 0 {
@@ -22,6 +28,128 @@ fn assert_unsigned(value: u32) -> LinuxResult<u32> {
     }
 }
 
+/// Converts the timeout of a wait operation into the duration to wait for.
+///
+/// `FUTEX_WAIT` takes a relative timeout, while the other operations take an
+/// absolute one. `FUTEX_LOCK_PI` measures it against `CLOCK_REALTIME`, the
+/// others against `CLOCK_REALTIME` if `FUTEX_CLOCK_REALTIME` is set and
+/// `CLOCK_MONOTONIC` otherwise.
+fn futex_timeout(command: u32, futex_op: u32, ts: TimeValue) -> Duration {
+    if command == FUTEX_WAIT {
+        return ts;
+    }
+    let now = if command == FUTEX_LOCK_PI || futex_op & FUTEX_CLOCK_REALTIME != 0 {
+        wall_time()
+    } else {
+        monotonic_time()
+    };
+    ts.saturating_sub(now)
+}
+
+/// Returns the thread ID of the current task as stored in futex words.
+fn current_tid() -> u32 {
+    let tid = current().id().as_u64() as Pid;
+    current_pid_ns().local_pid(tid).unwrap_or_default()
+}
+
+/// Applies the operation encoded in `FUTEX_WAKE_OP`'s `value3` to `uaddr`,
+/// returning whether the old value passes the encoded comparison.
+fn futex_wake_op(uaddr: *mut u32, encoded: u32) -> LinuxResult<bool> {
+    let op = (encoded >> 28) & 0xf;
+    let cmp = (encoded >> 24) & 0xf;
+    // Both arguments are signed 12-bit numbers.
+    let mut oparg = ((encoded << 8) as i32 >> 20) as u32;
+    let cmparg = ((encoded << 20) as i32 >> 20) as u32;
+    if op & FUTEX_OP_OPARG_SHIFT != 0 {
+        oparg = 1 << (oparg & 31);
+    }
+    let apply: fn(u32, u32) -> u32 = match op & !FUTEX_OP_OPARG_SHIFT {
+        FUTEX_OP_SET => |_, arg| arg,
+        FUTEX_OP_ADD => u32::wrapping_add,
+        FUTEX_OP_OR => |old, arg| old | arg,
+        FUTEX_OP_ANDN => |old, arg| old & !arg,
+        FUTEX_OP_XOR => |old, arg| old ^ arg,
+        _ => return Err(LinuxError::ENOSYS),
+    };
+    let (Ok(old) | Err(old)) = vm_update_u32(uaddr, |old| Some(apply(old, oparg)))?;
+    let (old, cmparg) = (old as i32, cmparg as i32);
+    match cmp {
+        FUTEX_OP_CMP_EQ => Ok(old == cmparg),
+        FUTEX_OP_CMP_NE => Ok(old != cmparg),
+        FUTEX_OP_CMP_LT => Ok(old < cmparg),
+        FUTEX_OP_CMP_LE => Ok(old <= cmparg),
+        FUTEX_OP_CMP_GT => Ok(old > cmparg),
+        FUTEX_OP_CMP_GE => Ok(old >= cmparg),
+        _ => Err(LinuxError::ENOSYS),
+    }
+}
+
+/// Takes the priority-inheritance futex at `uaddr` for the current thread.
+///
+/// The scheduler has no notion of priority, so there is nobody to boost, and
+/// only the ownership protocol is left: the futex word holds the owner's TID,
+/// with `FUTEX_WAITERS` set while others wait in the kernel so that the owner
+/// unlocks through `FUTEX_UNLOCK_PI`. A released lock is not handed over, the
+/// woken waiter competes for it like everybody else.
+fn futex_lock_pi(
+    uaddr: *mut u32,
+    key: &FutexKey,
+    table: &FutexTable,
+    deadline: Option<impl Fn() -> Duration>,
+    trylock: bool,
+) -> LinuxResult<isize> {
+    let tid = current_tid();
+    let futex = table.get_or_insert(key);
+    loop {
+        let waiters = if futex.wq.is_empty() {
+            0
+        } else {
+            FUTEX_WAITERS
+        };
+        // A lock whose owner died is taken over, keeping `FUTEX_OWNER_DIED`
+        // for user space to notice.
+        let val = match vm_update_u32(uaddr, |val| {
+            (val & FUTEX_TID_MASK == 0).then_some(val & FUTEX_OWNER_DIED | waiters | tid)
+        })? {
+            Ok(_) => return Ok(0),
+            Err(val) => val,
+        };
+        let owner = val & FUTEX_TID_MASK;
+        if owner == tid {
+            return Err(LinuxError::EDEADLK);
+        }
+        if trylock {
+            return Err(LinuxError::EWOULDBLOCK);
+        }
+        if get_task(current_pid_ns().global_pid(owner)?).is_err() {
+            // The owner is gone without releasing the lock.
+            return Err(LinuxError::ESRCH);
+        }
+
+        let val = val | FUTEX_WAITERS;
+        if vm_update_u32(uaddr, |cur| (cur | FUTEX_WAITERS == val).then_some(val))?.is_err() {
+            continue;
+        }
+        let timeout = deadline.as_ref().map(|remaining| remaining());
+        futex.wq.wait_if(FUTEX_BITSET_MATCH_ANY, timeout, || {
+            uaddr.vm_read() == Ok(val)
+        })?;
+    }
+}
+
+/// Releases the priority-inheritance futex at `uaddr`, which the current
+/// thread must own, and wakes up a waiter.
+fn futex_unlock_pi(uaddr: *mut u32, key: &FutexKey, table: &FutexTable) -> LinuxResult<isize> {
+    let tid = current_tid();
+    if vm_update_u32(uaddr, |val| (val & FUTEX_TID_MASK == tid).then_some(0))?.is_err() {
+        return Err(LinuxError::EPERM);
+    }
+    if let Some(futex) = table.get(key) {
+        futex.wq.wake(1, FUTEX_BITSET_MATCH_ANY);
+    }
+    Ok(0)
+}
+
 pub fn sys_futex(
     uaddr: *const u32,
     futex_op: u32,
@@ -36,6 +164,7 @@ pub fn sys_futex(
     );
 
     let key = FutexKey::new_current(uaddr.addr());
+    let _wait = wait_on(WaitChannel::Futex(uaddr.addr()));
 
     let curr = current();
     let thr = curr.as_thread();
@@ -43,6 +172,22 @@ pub fn sys_futex(
     let futex_table = proc_data.futex_table_for(&key);
 
     let command = futex_op & (FUTEX_CMD_MASK as u32);
+    if futex_op & FUTEX_CLOCK_REALTIME != 0
+        && !matches!(command, FUTEX_WAIT | FUTEX_WAIT_BITSET | FUTEX_LOCK_PI2)
+    {
+        return Err(LinuxError::ENOSYS);
+    }
+    if matches!(command, FUTEX_WAIT_BITSET | FUTEX_WAKE_BITSET) && value3 == 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let read_timeout = || -> LinuxResult<Option<TimeValue>> {
+        let Some(ts) = timeout.nullable() else {
+            return Ok(None);
+        };
+        // FIXME: AnyBitPattern
+        let ts = unsafe { ts.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
+        Ok(Some(ts))
+    };
     match command {
         FUTEX_WAIT | FUTEX_WAIT_BITSET => {
             // Fast path
@@ -50,20 +195,14 @@ pub fn sys_futex(
                 return Err(LinuxError::EAGAIN);
             }
 
-            let timeout = if let Some(ts) = timeout.nullable() {
-                // FIXME: AnyBitPattern
-                let ts = unsafe { ts.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
-                Some(ts)
-            } else {
-                None
-            };
+            let timeout = read_timeout()?.map(|ts| futex_timeout(command, futex_op, ts));
 
             let futex = futex_table.get_or_insert(&key);
 
             let bitset = if command == FUTEX_WAIT_BITSET {
                 value3
             } else {
-                u32::MAX
+                FUTEX_BITSET_MATCH_ANY
             };
 
             if !futex
@@ -72,12 +211,7 @@ pub fn sys_futex(
             {
                 return Err(LinuxError::EAGAIN);
             }
-
-            if futex.owner_dead.swap(false, Ordering::SeqCst) {
-                Err(LinuxError::EOWNERDEAD)
-            } else {
-                Ok(0)
-            }
+            Ok(0)
         }
         FUTEX_WAKE | FUTEX_WAKE_BITSET => {
             let futex = futex_table.get(&key);
@@ -86,7 +220,7 @@ pub fn sys_futex(
                 let bitset = if command == FUTEX_WAKE_BITSET {
                     value3
                 } else {
-                    u32::MAX
+                    FUTEX_BITSET_MATCH_ANY
                 };
                 count = futex.wq.wake(value as _, bitset);
             }
@@ -105,15 +239,43 @@ pub fn sys_futex(
             let table2 = proc_data.futex_table_for(&key2);
             let futex2 = table2.get_or_insert(&key2);
 
+            // Waiters beyond the first `value` are moved to the second futex,
+            // to be woken up by whoever releases it.
             let mut count = 0;
             if let Some(futex) = futex {
-                count = futex.wq.wake(value as _, u32::MAX);
-                if count == value as usize {
-                    count += futex.wq.requeue(value2 as _, &futex2.wq) as usize;
-                }
+                count = futex.wq.wake(value as _, FUTEX_BITSET_MATCH_ANY);
+                count += futex.wq.requeue(value2 as _, &futex2.wq);
             }
             Ok(count as _)
         }
+        FUTEX_WAKE_OP => {
+            let value2 = timeout.addr() as u32;
+            let key2 = FutexKey::new_current(uaddr2.addr());
+            let table2 = proc_data.futex_table_for(&key2);
+
+            let wake2 = futex_wake_op(uaddr2, value3)?;
+            let mut count = 0;
+            if let Some(futex) = futex_table.get(&key) {
+                count += futex.wq.wake(value as _, FUTEX_BITSET_MATCH_ANY);
+            }
+            if wake2 && let Some(futex2) = table2.get(&key2) {
+                count += futex2.wq.wake(value2 as _, FUTEX_BITSET_MATCH_ANY);
+            }
+            axtask::yield_now();
+            Ok(count as _)
+        }
+        FUTEX_LOCK_PI | FUTEX_LOCK_PI2 | FUTEX_TRYLOCK_PI => {
+            let deadline = read_timeout()?;
+            let deadline = deadline.map(|ts| move || futex_timeout(command, futex_op, ts));
+            futex_lock_pi(
+                uaddr.cast_mut(),
+                &key,
+                &futex_table,
+                deadline,
+                command == FUTEX_TRYLOCK_PI,
+            )
+        }
+        FUTEX_UNLOCK_PI => futex_unlock_pi(uaddr.cast_mut(), &key, &futex_table),
         _ => Err(LinuxError::ENOSYS),
     }
 }
@@ -123,7 +285,7 @@ pub fn sys_get_robust_list(
     head: *mut *const robust_list_head,
     size: *mut usize,
 ) -> LinuxResult<isize> {
-    let task = get_task(tid)?;
+    let task = get_task(current_pid_ns().global_pid(tid)?)?;
     head.vm_write(task.as_thread().robust_list_head() as _)?;
     size.vm_write(size_of::<robust_list_head>())?;
 
@@ -1,15 +1,32 @@
 use alloc::vec;
-use core::ffi::c_char;
+use core::{
+    ffi::c_char,
+    future::poll_fn,
+    sync::atomic::{AtomicU8, Ordering},
+    task::Poll,
+};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::FS_CONTEXT;
+use axhal::time::monotonic_time;
+use axtask::future::block_on_interruptible;
 use linux_raw_sys::{
     general::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM},
     system::{new_utsname, sysinfo},
 };
-use starry_core::task::processes;
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::{
+    mm::total_pages,
+    shm::shared_memory_usage,
+    sysctl,
+    task::{fs_context, processes},
+};
 use starry_vm::{VmMutPtr, vm_write_slice};
 
+use crate::logging::{
+    LOG_BUF_LEN, clear_records, console_loglevel, read_all, register_record_waker,
+    set_console_loglevel, take_unread, unread_len,
+};
+
 pub fn sys_getuid() -> LinuxResult<isize> {
     Ok(0)
 }
@@ -59,31 +76,147 @@ const fn pad_str(info: &str) -> [c_char; 65] {
     data
 }
 
+const SYSNAME: &str = "Linux";
+const RELEASE: &str = "10.0.0";
+/// The initial host and domain names, changed through `/proc/sys/kernel`.
+const HOSTNAME: &str = "starry";
+const DOMAINNAME: &str = "https://github.com/Starry-Mix-THU/starry-mix";
+
 const UTSNAME: new_utsname = new_utsname {
-    sysname: pad_str("Linux"),
-    nodename: pad_str("starry"),
-    release: pad_str("10.0.0"),
-    version: pad_str("10.0.0"),
+    sysname: pad_str(SYSNAME),
+    nodename: pad_str(HOSTNAME),
+    release: pad_str(RELEASE),
+    version: pad_str(RELEASE),
     machine: pad_str("riscv64"),
-    domainname: pad_str("https://github.com/Starry-Mix-THU/starry-mix"),
+    domainname: pad_str(DOMAINNAME),
 };
 
+/// The longest host and domain names, as in `struct new_utsname`.
+const UTS_NAME_MAX: usize = 64;
+
+/// Registers the sysctls of `uname`: `kernel.hostname` and
+/// `kernel.domainname`, which can be changed, and `kernel.ostype` and
+/// `kernel.osrelease`.
+pub fn register_sysctls() {
+    sysctl::register_string_value("kernel.hostname", HOSTNAME, UTS_NAME_MAX);
+    sysctl::register_string_value("kernel.domainname", DOMAINNAME, UTS_NAME_MAX);
+    sysctl::register("kernel.ostype", || SYSNAME.into());
+    sysctl::register("kernel.osrelease", || RELEASE.into());
+}
+
 pub fn sys_uname(name: *mut new_utsname) -> LinuxResult<isize> {
-    name.vm_write(UTSNAME)?;
+    let mut uts = UTSNAME;
+    for (field, knob) in [
+        (&mut uts.nodename, "kernel.hostname"),
+        (&mut uts.domainname, "kernel.domainname"),
+    ] {
+        let value = sysctl::read(knob)?;
+        let len = value.len().min(UTS_NAME_MAX);
+        field.fill(0);
+        for (dst, src) in field.iter_mut().zip(&value.as_bytes()[..len]) {
+            *dst = *src as c_char;
+        }
+    }
+    name.vm_write(uts)?;
     Ok(0)
 }
 
 pub fn sys_sysinfo(info: *mut sysinfo) -> LinuxResult<isize> {
     // FIXME: Zeroable
     let mut kinfo: sysinfo = unsafe { core::mem::zeroed() };
+    kinfo.uptime = monotonic_time().as_secs() as _;
+    // The same totals as `/proc/meminfo`.
+    let allocator = axalloc::global_allocator();
+    kinfo.totalram = (total_pages() * PAGE_SIZE_4K) as _;
+    kinfo.freeram = (allocator.available_pages() * PAGE_SIZE_4K) as _;
+    kinfo.sharedram = shared_memory_usage() as _;
     kinfo.procs = processes().len() as _;
     kinfo.mem_unit = 1;
     info.vm_write(kinfo)?;
     Ok(0)
 }
 
-pub fn sys_syslog(_type: i32, _buf: *mut c_char, _len: usize) -> LinuxResult<isize> {
-    Ok(0)
+const SYSLOG_ACTION_CLOSE: i32 = 0;
+const SYSLOG_ACTION_OPEN: i32 = 1;
+const SYSLOG_ACTION_READ: i32 = 2;
+const SYSLOG_ACTION_READ_ALL: i32 = 3;
+const SYSLOG_ACTION_READ_CLEAR: i32 = 4;
+const SYSLOG_ACTION_CLEAR: i32 = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: i32 = 6;
+const SYSLOG_ACTION_CONSOLE_ON: i32 = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: i32 = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: i32 = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+/// The console log level saved by `SYSLOG_ACTION_CONSOLE_OFF`, or 0.
+static SAVED_CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(0);
+
+pub fn sys_syslog(ty: i32, buf: *mut c_char, len: i32) -> LinuxResult<isize> {
+    debug!("sys_syslog <= type: {}, buf: {:p}, len: {}", ty, buf, len);
+    let is_read = matches!(
+        ty,
+        SYSLOG_ACTION_READ | SYSLOG_ACTION_READ_ALL | SYSLOG_ACTION_READ_CLEAR
+    );
+    if is_read && (buf.is_null() || len < 0) {
+        return Err(LinuxError::EINVAL);
+    }
+    let text = match ty {
+        SYSLOG_ACTION_CLOSE | SYSLOG_ACTION_OPEN => return Ok(0),
+        SYSLOG_ACTION_READ => {
+            if len == 0 {
+                return Ok(0);
+            }
+            block_on_interruptible(poll_fn(|cx| {
+                if unread_len() == 0 {
+                    register_record_waker(cx.waker());
+                }
+                if unread_len() == 0 {
+                    Poll::Pending
+                } else {
+                    Poll::Ready(Ok(()))
+                }
+            }))?;
+            take_unread(len as _)
+        }
+        SYSLOG_ACTION_READ_ALL => read_all(len as _),
+        SYSLOG_ACTION_READ_CLEAR => {
+            let text = read_all(len as _);
+            clear_records();
+            text
+        }
+        SYSLOG_ACTION_CLEAR => {
+            clear_records();
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_OFF => {
+            let saved = SAVED_CONSOLE_LOGLEVEL.load(Ordering::Relaxed);
+            if saved == 0 {
+                SAVED_CONSOLE_LOGLEVEL.store(console_loglevel(), Ordering::Relaxed);
+                set_console_loglevel(1);
+            }
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_ON => {
+            let saved = SAVED_CONSOLE_LOGLEVEL.swap(0, Ordering::Relaxed);
+            if saved != 0 {
+                set_console_loglevel(saved);
+            }
+            return Ok(0);
+        }
+        SYSLOG_ACTION_CONSOLE_LEVEL => {
+            if !(1..=8).contains(&len) {
+                return Err(LinuxError::EINVAL);
+            }
+            set_console_loglevel(len as _);
+            SAVED_CONSOLE_LOGLEVEL.store(0, Ordering::Relaxed);
+            return Ok(0);
+        }
+        SYSLOG_ACTION_SIZE_UNREAD => return Ok(unread_len() as _),
+        SYSLOG_ACTION_SIZE_BUFFER => return Ok(LOG_BUF_LEN as _),
+        _ => return Err(LinuxError::EINVAL),
+    };
+    vm_write_slice(buf as *mut u8, text.as_bytes())?;
+    Ok(text.len() as _)
 }
 
 bitflags::bitflags! {
@@ -112,7 +245,7 @@ pub fn sys_getrandom(buf: *mut u8, len: usize, flags: u32) -> LinuxResult<isize>
         "/dev/urandom"
     };
 
-    let f = FS_CONTEXT.lock().resolve(path)?;
+    let f = fs_context().lock().resolve(path)?;
     let mut kbuf = vec![0; len];
     let len = f.entry().as_file()?.read_at(&mut kbuf, 0)?;
 
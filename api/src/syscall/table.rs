@@ -0,0 +1,174 @@
+//! The syscall table.
+//!
+//! The handlers are listed once for all architectures in `syscall_table!`,
+//! with a `cfg` only on the syscalls that exist on some of them, such as the
+//! legacy ones of x86_64. Such a `cfg` must only select architectures. From
+//! that list the macro generates the dispatcher,
+//! [`is_handled`](super::is_handled) and the list of entries, which the
+//! checks below use at compile time.
+//!
+//! The syscalls left out on purpose are listed in `UNSUPPORTED` for each
+//! architecture. They fail with `ENOSYS` quietly. Any other syscall that is
+//! missing from the table logs a warning, as it is probably an oversight.
+
+use syscalls::Sysno;
+
+/// Generates `dispatch`, which calls the handler of a syscall, `is_handled`
+/// and `ENTRIES`, from a table of `Sysno::name => handler` entries.
+///
+/// `$tf` and `$sysno` name the trap frame and the syscall number in the
+/// handlers.
+macro_rules! syscall_table {
+    ($tf:ident, $sysno:ident; $($(#[$meta:meta])* $($name:path)|+ => $handler:expr,)*) => {
+        /// The syscalls of every entry of the table on all architectures,
+        /// and whether the entry has a `cfg`.
+        const ENTRIES: &[(&[&str], bool)] = &[$((
+            &[$(stringify!($name)),+],
+            !(&[$(stringify!($meta)),*] as &[&str]).is_empty(),
+        ),)*];
+
+        /// Whether the syscall table has a handler for `sysno`.
+        pub const fn is_handled(sysno: Sysno) -> bool {
+            match sysno {
+                $($(#[$meta])* $($name)|+ => true,)*
+                _ => false,
+            }
+        }
+
+        fn dispatch($tf: &mut TrapFrame, $sysno: Sysno) -> axerrno::LinuxResult<isize> {
+            match $sysno {
+                $($(#[$meta])* $($name)|+ => $handler,)*
+                _ if table::is_unsupported($sysno) => Err(LinuxError::ENOSYS),
+                _ => {
+                    warn!("Unimplemented syscall: {}", $sysno);
+                    Err(LinuxError::ENOSYS)
+                }
+            }
+        }
+    };
+}
+
+/// The syscalls that all architectures leave out on purpose: loading
+/// modules, swap, quotas, keyrings, NUMA policies and other processes'
+/// memory.
+const GENERIC_UNSUPPORTED: &[Sysno] = &[
+    Sysno::acct,
+    Sysno::swapon,
+    Sysno::swapoff,
+    Sysno::kexec_load,
+    Sysno::init_module,
+    Sysno::finit_module,
+    Sysno::delete_module,
+    Sysno::quotactl,
+    Sysno::pivot_root,
+    Sysno::vhangup,
+    Sysno::add_key,
+    Sysno::request_key,
+    Sysno::keyctl,
+    Sysno::mbind,
+    Sysno::set_mempolicy,
+    Sysno::migrate_pages,
+    Sysno::move_pages,
+    Sysno::process_vm_readv,
+    Sysno::process_vm_writev,
+    Sysno::kcmp,
+    Sysno::name_to_handle_at,
+    Sysno::open_by_handle_at,
+];
+
+/// The syscalls left out on purpose on this architecture.
+#[cfg(target_arch = "x86_64")]
+const UNSUPPORTED: &[&[Sysno]] = &[
+    GENERIC_UNSUPPORTED,
+    &[Sysno::iopl, Sysno::ioperm, Sysno::modify_ldt, Sysno::uselib],
+];
+
+/// The syscalls left out on purpose on this architecture.
+#[cfg(not(target_arch = "x86_64"))]
+const UNSUPPORTED: &[&[Sysno]] = &[GENERIC_UNSUPPORTED];
+
+/// Whether `sysno` is left out on purpose.
+pub const fn is_unsupported(sysno: Sysno) -> bool {
+    let mut i = 0;
+    while i < UNSUPPORTED.len() {
+        let mut j = 0;
+        while j < UNSUPPORTED[i].len() {
+            if UNSUPPORTED[i][j].id() == sysno.id() {
+                return true;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    false
+}
+
+// A syscall is either handled or left out, not both.
+const _: () = {
+    let mut i = 0;
+    while i < UNSUPPORTED.len() {
+        let mut j = 0;
+        while j < UNSUPPORTED[i].len() {
+            assert!(
+                !super::is_handled(UNSUPPORTED[i][j]),
+                "a syscall listed as unsupported has a handler"
+            );
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+/// Whether `path`, a syscall as written in the table, such as
+/// `Sysno::mkdir`, names the syscall `name`.
+const fn names(path: &str, name: &str) -> bool {
+    let (path, name) = (path.as_bytes(), name.as_bytes());
+    if path.len() <= name.len() || path[path.len() - name.len() - 1] != b':' {
+        return false;
+    }
+    let start = path.len() - name.len();
+    let mut i = 0;
+    while i < name.len() {
+        if path[start + i] != name[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Looks up the syscall of this architecture that `path` names.
+const fn lookup(path: &str) -> Option<Sysno> {
+    let mut id = Sysno::first().id() as usize;
+    while id <= Sysno::last().id() as usize {
+        if let Some(sysno) = Sysno::new(id)
+            && names(path, sysno.name())
+        {
+            return Some(sysno);
+        }
+        id += 1;
+    }
+    None
+}
+
+// An entry with a `cfg` must be handled on every architecture that has its
+// syscall, unless the syscall is left out there on purpose. Each
+// architecture checks the entries of all of them against its own syscall
+// table, so one that is missing fails the build of that architecture.
+const _: () = {
+    let mut i = 0;
+    while i < super::ENTRIES.len() {
+        let (paths, configured) = super::ENTRIES[i];
+        let mut j = 0;
+        while configured && j < paths.len() {
+            if let Some(sysno) = lookup(paths[j]) {
+                assert!(
+                    super::is_handled(sysno) || is_unsupported(sysno),
+                    "a syscall handled on other architectures has no handler here"
+                );
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+};
@@ -1,18 +1,23 @@
 use alloc::sync::Arc;
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::FS_CONTEXT;
-use axhal::{context::TrapFrame, uspace::UserContext};
+use axhal::{context::TrapFrame, time::monotonic_time, uspace::UserContext};
 use axtask::{TaskExtProxy, current, spawn_task};
 use bitflags::bitflags;
 use kspin::SpinNoIrq;
 use linux_raw_sys::general::*;
+use memory_addr::PAGE_SIZE_4K;
+use spin::RwLock;
 use starry_core::{
-    mm::copy_from_kernel,
-    task::{AsThread, ProcessData, Thread, add_task_to_table},
+    mm::{ZeroPages, check_fork_memory, copy_from_kernel, count_fork},
+    task::{
+        AsThread, ProcessData, Thread, add_task_to_table, get_task, release_pid, start_cpus,
+        thread_count,
+    },
 };
 use starry_process::Pid;
-use starry_signal::Signo;
+use starry_signal::{SignalAction, SignalDisposition, Signo};
+use starry_vm::VmPtr;
 
 use crate::{
     file::{FD_TABLE, FileLike, PidFd},
@@ -21,7 +26,7 @@ use crate::{
 };
 
 bitflags! {
-    /// Options for use with [`sys_clone`].
+    /// Options for use with [`sys_clone`] and [`sys_clone3`].
     #[derive(Debug, Clone, Copy, Default)]
     struct CloneFlags: u32 {
         /// The calling process and the child process run in the same
@@ -51,7 +56,8 @@ bitflags! {
         /// The child is placed in the same thread group as the calling
         /// process.
         const THREAD = CLONE_THREAD;
-        /// The cloned child is started in a new mount namespace.
+        /// The cloned child is started in a new mount namespace, which is
+        /// not supported.
         const NEWNS = CLONE_NEWNS;
         /// The child and the calling process share a single list of System
         /// V semaphore adjustment values
@@ -85,6 +91,23 @@ bitflags! {
     }
 }
 
+/// Arguments shared by [`sys_clone`] and [`sys_clone3`].
+struct CloneArgs {
+    flags: CloneFlags,
+    exit_signal: u64,
+    /// The new stack pointer, or 0 to keep the current one.
+    stack: usize,
+    tls: usize,
+    parent_tid: usize,
+    child_tid: usize,
+    /// Where to store the pidfd for `CLONE_PIDFD`.
+    pidfd: usize,
+    /// Reset the signal handlers of the child to their defaults.
+    clear_sighand: bool,
+    /// The TID requested through `clone3`'s `set_tid`.
+    set_tid: Option<Pid>,
+}
+
 pub fn sys_clone(
     tf: &TrapFrame,
     flags: u32,
@@ -96,7 +119,128 @@ pub fn sys_clone(
 ) -> LinuxResult<isize> {
     const FLAG_MASK: u32 = 0xff;
     let exit_signal = flags & FLAG_MASK;
-    let mut flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
+    let flags = CloneFlags::from_bits_truncate(flags & !FLAG_MASK);
+    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
+        return Err(LinuxError::EINVAL);
+    }
+    do_clone(
+        tf,
+        CloneArgs {
+            flags,
+            exit_signal: exit_signal as u64,
+            stack,
+            tls,
+            parent_tid,
+            child_tid,
+            pidfd: parent_tid,
+            clear_sighand: false,
+            set_tid: None,
+        },
+    )
+}
+
+pub fn sys_clone3(tf: &TrapFrame, args: *const clone_args, size: usize) -> LinuxResult<isize> {
+    const WORDS: usize = size_of::<clone_args>() / size_of::<u64>();
+    if size < CLONE_ARGS_SIZE_VER0 as usize || size % size_of::<u64>() != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    if size > PAGE_SIZE_4K {
+        return Err(LinuxError::E2BIG);
+    }
+
+    // Older versions of the structure are shorter; newer ones must not use
+    // any field we do not know about.
+    let ptr = args as *const u64;
+    let mut words = [0u64; WORDS];
+    for i in 0..size / size_of::<u64>() {
+        let word = ptr.wrapping_add(i).vm_read()?;
+        match words.get_mut(i) {
+            Some(slot) => *slot = word,
+            None if word != 0 => return Err(LinuxError::E2BIG),
+            None => {}
+        }
+    }
+    let [
+        flags,
+        pidfd,
+        child_tid,
+        parent_tid,
+        exit_signal,
+        stack,
+        stack_size,
+        tls,
+        set_tid,
+        set_tid_size,
+        _cgroup,
+    ] = words;
+    debug!(
+        "sys_clone3 <= flags: {:#x}, exit_signal: {}, stack: {:#x}, stack_size: {:#x}",
+        flags, exit_signal, stack, stack_size
+    );
+
+    if flags & !(CloneFlags::all().bits() as u64 | CLONE_CLEAR_SIGHAND) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let clear_sighand = flags & CLONE_CLEAR_SIGHAND != 0;
+    let flags = CloneFlags::from_bits_truncate(flags as u32);
+    if clear_sighand && flags.contains(CloneFlags::SIGHAND) {
+        return Err(LinuxError::EINVAL);
+    }
+    if (stack == 0) != (stack_size == 0) {
+        return Err(LinuxError::EINVAL);
+    }
+    let stack_top = stack.checked_add(stack_size).ok_or(LinuxError::EINVAL)?;
+    if exit_signal != 0
+        && u8::try_from(exit_signal)
+            .ok()
+            .and_then(Signo::from_repr)
+            .is_none()
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let set_tid = match set_tid_size {
+        0 if set_tid != 0 => return Err(LinuxError::EINVAL),
+        0 => None,
+        // Only the ID in the innermost PID namespace can be chosen.
+        1 => {
+            let tid = (set_tid as usize as *const Pid).vm_read()?;
+            if tid as i32 <= 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            Some(tid)
+        }
+        _ => return Err(LinuxError::EINVAL),
+    };
+
+    do_clone(
+        tf,
+        CloneArgs {
+            flags,
+            exit_signal,
+            stack: stack_top as usize,
+            tls: tls as usize,
+            parent_tid: parent_tid as usize,
+            child_tid: child_tid as usize,
+            pidfd: pidfd as usize,
+            clear_sighand,
+            set_tid,
+        },
+    )
+}
+
+fn do_clone(tf: &TrapFrame, args: CloneArgs) -> LinuxResult<isize> {
+    let CloneArgs {
+        mut flags,
+        exit_signal,
+        stack,
+        tls,
+        parent_tid,
+        child_tid,
+        pidfd,
+        clear_sighand,
+        set_tid,
+    } = args;
     if flags.contains(CloneFlags::VFORK) {
         debug!("sys_clone: CLONE_VFORK slow path");
         flags.remove(CloneFlags::VM);
@@ -114,10 +258,18 @@ pub fn sys_clone(
     if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
         return Err(LinuxError::EINVAL);
     }
-    if flags.contains(CloneFlags::PIDFD | CloneFlags::PARENT_SETTID) {
+    // Mounts are attached to the directory tree all processes share, so a
+    // new mount namespace is accepted but shares its mounts with the others.
+    if flags.contains(CloneFlags::NEWNS | CloneFlags::FS) {
+        return Err(LinuxError::EINVAL);
+    }
+    if flags.contains(CloneFlags::NEWPID)
+        && flags.intersects(CloneFlags::THREAD | CloneFlags::PARENT)
+    {
         return Err(LinuxError::EINVAL);
     }
-    let exit_signal = Signo::from_repr(exit_signal as u8);
+    let exit_signal = u8::try_from(exit_signal).ok().and_then(Signo::from_repr);
+    let start = monotonic_time();
 
     let mut new_uctx = UserContext::from(*tf);
     if stack != 0 {
@@ -137,11 +289,45 @@ pub fn sys_clone(
     let curr = current();
     let old_proc_data = &curr.as_thread().proc_data;
 
+    // There is a single user, so all of the tasks count against the limit.
+    if thread_count() as u64 >= old_proc_data.rlim.read()[RLIMIT_NPROC].current {
+        return Err(LinuxError::EAGAIN);
+    }
+    check_fork_memory()?;
+
     let mut new_task = new_user_task(&curr.name(), new_uctx, set_child_tid);
 
+    let pid_ns = if flags.contains(CloneFlags::THREAD) {
+        old_proc_data.pid_ns.read().clone()
+    } else if flags.contains(CloneFlags::NEWPID) {
+        old_proc_data.children_pid_ns.read().new_child()?
+    } else {
+        old_proc_data.children_pid_ns.read().clone()
+    };
+    if let Some(set_tid) = set_tid
+        && pid_ns.global_pid(set_tid).and_then(get_task).is_ok()
+    {
+        return Err(LinuxError::EEXIST);
+    }
+
     let tid = new_task.id().as_u64() as Pid;
+    let local_tid = pid_ns.alloc(tid)?;
+    if let Some(set_tid) = set_tid
+        && set_tid != local_tid
+    {
+        // Task IDs are allocated by the scheduler and cannot be chosen.
+        warn!("sys_clone3: cannot create task with TID {set_tid}");
+        release_pid(tid);
+        return Err(LinuxError::EINVAL);
+    }
+    // The parent sees the child by its ID in the parent's namespace.
+    let parent_view_tid = old_proc_data
+        .pid_ns
+        .read()
+        .local_pid(tid)
+        .unwrap_or_default();
     if flags.contains(CloneFlags::PARENT_SETTID) {
-        *UserPtr::<Pid>::from(parent_tid).get_as_mut()? = tid;
+        *UserPtr::<Pid>::from(parent_tid).get_as_mut()? = parent_view_tid;
     }
 
     let new_proc_data = if flags.contains(CloneFlags::THREAD) {
@@ -157,13 +343,21 @@ pub fn sys_clone(
         }
         .fork(tid);
 
-        let aspace = if flags.contains(CloneFlags::VM) {
-            old_proc_data.aspace.clone()
+        let (aspace, zero_pages) = if flags.contains(CloneFlags::VM) {
+            (
+                old_proc_data.aspace.clone(),
+                old_proc_data.zero_pages.read().clone(),
+            )
         } else {
-            let mut aspace = old_proc_data.aspace.lock();
+            // Pages written by a transfer must not end up shared with the
+            // child.
+            let mut aspace = old_proc_data.lock_all_unpinned();
             let aspace = aspace.try_clone()?;
             copy_from_kernel(&mut aspace.lock())?;
-            aspace
+            // The copy maps the same zero pages.
+            let zero_pages = Arc::new(ZeroPages::default());
+            zero_pages.inherit(&old_proc_data.zero_pages.read());
+            (aspace, zero_pages)
         };
         new_task
             .ctx_mut()
@@ -172,7 +366,20 @@ pub fn sys_clone(
         let signal_actions = if flags.contains(CloneFlags::SIGHAND) {
             old_proc_data.signal.actions.clone()
         } else {
-            Arc::new(SpinNoIrq::new(old_proc_data.signal.actions.lock().clone()))
+            let mut actions = old_proc_data.signal.actions.lock().clone();
+            if clear_sighand {
+                for signo in (1..=64).filter_map(Signo::from_repr) {
+                    if !matches!(actions[signo].disposition, SignalDisposition::Ignore) {
+                        actions[signo] = SignalAction::default();
+                    }
+                }
+            }
+            Arc::new(SpinNoIrq::new(actions))
+        };
+        let fs = if flags.contains(CloneFlags::FS) {
+            curr.as_thread().fs()
+        } else {
+            curr.as_thread().fs().fork()
         };
         let proc_data = ProcessData::new(
             proc,
@@ -181,8 +388,28 @@ pub fn sys_clone(
             aspace,
             signal_actions,
             exit_signal,
+            fs,
         );
-        proc_data.set_umask(old_proc_data.umask());
+        *proc_data.exe.write() = old_proc_data.exe.read().clone();
+        *proc_data.environ.write() = old_proc_data.environ.read().clone();
+        if flags.contains(CloneFlags::VM) {
+            *proc_data.commit.write() = old_proc_data.commit.read().clone();
+            *proc_data.pins.write() = old_proc_data.pins.read().clone();
+        } else {
+            proc_data
+                .commit
+                .read()
+                .inherit(&old_proc_data.commit.read())?;
+        }
+        *proc_data.zero_pages.write() = zero_pages;
+        proc_data.inherit_rss(old_proc_data);
+        proc_data.set_mmap_base(old_proc_data.get_mmap_base());
+        proc_data.set_low_code(old_proc_data.low_code());
+        proc_data.set_sigchld_flags(old_proc_data.sigchld_flags());
+        *proc_data.mapping_names.lock() = old_proc_data.mapping_names.lock().clone();
+        *proc_data.rlim.write() = old_proc_data.rlim.read().clone();
+        *proc_data.pid_ns.write() = pid_ns.clone();
+        *proc_data.children_pid_ns.write() = pid_ns;
 
         {
             let mut scope = proc_data.scope.write();
@@ -194,15 +421,6 @@ pub fn sys_clone(
                     .write()
                     .clone_from(&FD_TABLE.read());
             }
-
-            if flags.contains(CloneFlags::FS) {
-                FS_CONTEXT.scope_mut(&mut scope).clone_from(&FS_CONTEXT);
-            } else {
-                FS_CONTEXT
-                    .scope_mut(&mut scope)
-                    .lock()
-                    .clone_from(&FS_CONTEXT.lock());
-            }
         }
 
         proc_data
@@ -211,20 +429,76 @@ pub fn sys_clone(
     new_proc_data.proc.add_thread(tid);
 
     if flags.contains(CloneFlags::PIDFD) {
-        let pidfd = PidFd::new(&new_proc_data);
-        *UserPtr::<i32>::from(parent_tid).get_as_mut()? = pidfd.add_to_fd_table(true)?;
+        let fd = PidFd::new(&new_proc_data).add_to_fd_table(true)?;
+        *UserPtr::<i32>::from(pidfd).get_as_mut()? = fd;
     }
 
     let thr = Thread::new(tid, new_proc_data);
+    if flags.contains(CloneFlags::THREAD) {
+        let fs = curr.as_thread().fs();
+        if !flags.contains(CloneFlags::FS) {
+            thr.set_fs(fs.fork());
+        } else if !Arc::ptr_eq(&fs, &thr.proc_data.fs) {
+            thr.set_fs(fs);
+        }
+    }
+    let sched = curr.as_thread().sched().fork();
+    thr.set_sched(sched);
     if flags.contains(CloneFlags::CHILD_CLEARTID) {
         thr.set_clear_child_tid(child_tid);
     }
     *new_task.task_ext_mut() = Some(unsafe { TaskExtProxy::from_impl(thr) });
 
+    // Start on the CPUs suiting the task, then let it go wherever the parent
+    // may.
+    let allowed = curr.cpumask();
+    new_task.set_cpumask(start_cpus(allowed, sched));
     let task = spawn_task(new_task);
+    task.set_cpumask(allowed);
     add_task_to_table(&task);
+    if !flags.contains(CloneFlags::THREAD) {
+        count_fork(monotonic_time() - start);
+    }
+
+    Ok(parent_view_tid as _)
+}
 
-    Ok(tid as _)
+pub fn sys_unshare(flags: u32) -> LinuxResult<isize> {
+    let flags = CloneFlags::from_bits(flags).ok_or(LinuxError::EINVAL)?;
+    debug!("sys_unshare <= flags: {:?}", flags);
+    // `CLONE_NEWNS` is accepted like in `clone`, without isolating mounts.
+    if !(CloneFlags::NEWPID
+        | CloneFlags::NEWNS
+        | CloneFlags::FILES
+        | CloneFlags::FS
+        | CloneFlags::SYSVSEM)
+        .contains(flags)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let curr = current();
+    let proc_data = &curr.as_thread().proc_data;
+    // Like in Linux, a new mount namespace implies a filesystem context of
+    // its own.
+    if flags.intersects(CloneFlags::FS | CloneFlags::NEWNS) {
+        curr.as_thread().set_fs(curr.as_thread().fs().fork());
+    }
+
+    if flags.contains(CloneFlags::NEWPID) {
+        // The caller stays where it is, and only its future children are
+        // moved into the new namespace.
+        let mut children_pid_ns = proc_data.children_pid_ns.write();
+        if !Arc::ptr_eq(&children_pid_ns, &proc_data.pid_ns.read()) {
+            return Err(LinuxError::EINVAL);
+        }
+        *children_pid_ns = children_pid_ns.new_child()?;
+    }
+    if flags.contains(CloneFlags::FILES) {
+        let files = Arc::new(RwLock::new(FD_TABLE.read().clone()));
+        *FD_TABLE.scope_mut(&mut proc_data.scope.write()) = files;
+    }
+    Ok(0)
 }
 
 #[cfg(target_arch = "x86_64")]
@@ -3,13 +3,20 @@ use core::ffi::c_char;
 use axerrno::{LinuxError, LinuxResult};
 use axtask::current;
 use linux_raw_sys::general::{__user_cap_data_struct, __user_cap_header_struct};
-use starry_core::task::{AsThread, get_process_data};
+use starry_core::task::{AsThread, current_pid_ns, get_process_data};
 use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
 
 use crate::mm::vm_load_string;
 
 const CAPABILITY_VERSION_3: u32 = 0x20080522;
 
+/// A `prctl` of Starry's own, for JIT runtimes: with a nonzero argument,
+/// executable mappings without a fixed address go in the low 2 GiB of the
+/// address space and the others above, so that generated code can reach
+/// other code with relative calls. Linux has no such option, hence a number
+/// far from its own ones. It is reset by `execve`.
+const PR_SET_LOW_CODE: u32 = 0x5354_0001;
+
 fn validate_cap_header(header_ptr: *mut __user_cap_header_struct) -> LinuxResult<()> {
     // FIXME: AnyBitPattern
     let mut header = unsafe { header_ptr.vm_read_uninit()?.assume_init() };
@@ -18,7 +25,7 @@ fn validate_cap_header(header_ptr: *mut __user_cap_header_struct) -> LinuxResult
         header_ptr.vm_write(header)?;
         return Err(LinuxError::EINVAL);
     }
-    let _ = get_process_data(header.pid as u32)?;
+    let _ = get_process_data(current_pid_ns().global_pid(header.pid as u32)?)?;
     Ok(())
 }
 
@@ -47,7 +54,7 @@ pub fn sys_capset(
 
 pub fn sys_umask(mask: u32) -> LinuxResult<isize> {
     let curr = current();
-    let old = curr.as_thread().proc_data.replace_umask(mask);
+    let old = curr.as_thread().replace_umask(mask);
     Ok(old as isize)
 }
 
@@ -101,6 +108,12 @@ pub fn sys_prctl(
             vm_write_slice(arg2 as _, &buf)?;
         }
         PR_SET_SECCOMP => {}
+        PR_SET_LOW_CODE => {
+            if arg2 > 1 {
+                return Err(LinuxError::EINVAL);
+            }
+            current().as_thread().proc_data.set_low_code(arg2 != 0);
+        }
         PR_MCE_KILL => {}
         PR_SET_MM_START_CODE
         | PR_SET_MM_END_CODE
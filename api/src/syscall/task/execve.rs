@@ -1,14 +1,61 @@
-use alloc::{string::ToString, sync::Arc, vec::Vec};
-use core::ffi::c_char;
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::ffi::{c_char, c_int};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::FS_CONTEXT;
+use axfs_ng_vfs::{Location, NodeType};
 use axhal::context::TrapFrame;
 use axtask::current;
-use starry_core::{mm::load_user_app, task::AsThread};
-use starry_vm::vm_load_until_nul;
+use linux_raw_sys::general::{ARG_MAX, AT_EMPTY_PATH, AT_SYMLINK_NOFOLLOW, RLIMIT_STACK};
+use memory_addr::PAGE_SIZE_4K;
+use starry_core::{
+    config::USER_STACK_SIZE,
+    mm::{aslr, load_user_app, load_user_app_at},
+    task::{AsThread, fs_context},
+};
+use starry_vm::{VmPtr, vm_load_until_nul};
 
-use crate::{file::FD_TABLE, mm::vm_load_string};
+use crate::{
+    file::{FD_TABLE, ResolveAtResult, resolve_at},
+    mm::vm_load_string,
+};
+
+/// The longest argument or environment string, `MAX_ARG_STRLEN` on Linux.
+const MAX_ARG_STRLEN: usize = 32 * PAGE_SIZE_4K;
+
+fn load_strings(ptr: *const *const c_char) -> LinuxResult<Vec<String>> {
+    vm_load_until_nul(ptr)?
+        .into_iter()
+        .map(vm_load_string)
+        .collect()
+}
+
+/// Checks that the arguments and environment fit on the new stack.
+///
+/// As on Linux, they may take a quarter of `RLIMIT_STACK`, and at least
+/// `ARG_MAX`. The stack does not grow beyond its fixed size though, so a
+/// larger limit does not allow more.
+fn check_arg_size(args: &[String], envs: &[String]) -> LinuxResult<()> {
+    let stack_limit = current().as_thread().proc_data.rlim.read()[RLIMIT_STACK].current;
+    let limit = (stack_limit / 4)
+        .min(USER_STACK_SIZE as u64 / 4)
+        .max(ARG_MAX as u64);
+
+    let mut size = 0;
+    for s in args.iter().chain(envs) {
+        if s.len() + 1 > MAX_ARG_STRLEN {
+            return Err(LinuxError::E2BIG);
+        }
+        size += s.len() + 1 + size_of::<usize>();
+    }
+    if size as u64 > limit {
+        return Err(LinuxError::E2BIG);
+    }
+    Ok(())
+}
 
 pub fn sys_execve(
     tf: &mut TrapFrame,
@@ -17,22 +64,70 @@ pub fn sys_execve(
     envp: *const *const c_char,
 ) -> LinuxResult<isize> {
     let path = vm_load_string(path)?;
-
-    let args = vm_load_until_nul(argv)?
-        .into_iter()
-        .map(vm_load_string)
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let envs = vm_load_until_nul(envp)?
-        .into_iter()
-        .map(vm_load_string)
-        .collect::<Result<Vec<_>, _>>()?;
+    let args = load_strings(argv)?;
+    let envs = load_strings(envp)?;
 
     debug!(
         "sys_execve <= path: {:?}, args: {:?}, envs: {:?}",
         path, args, envs
     );
 
+    do_execve(tf, path, None, args, envs)
+}
+
+/// Executes the program `path` refers to relative to `dirfd`, or the file
+/// `dirfd` itself with `AT_EMPTY_PATH`, which is how `fexecve` works.
+pub fn sys_execveat(
+    tf: &mut TrapFrame,
+    dirfd: c_int,
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+    flags: u32,
+) -> LinuxResult<isize> {
+    let path = path.nullable().map(vm_load_string).transpose()?;
+    let args = load_strings(argv)?;
+    let envs = load_strings(envp)?;
+
+    debug!(
+        "sys_execveat <= dirfd: {}, path: {:?}, args: {:?}, envs: {:?}, flags: {:#x}",
+        dirfd, path, args, envs, flags
+    );
+
+    if flags & !(AT_EMPTY_PATH | AT_SYMLINK_NOFOLLOW) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let loc = match resolve_at(dirfd, path.as_deref(), flags)? {
+        ResolveAtResult::File(loc) => loc,
+        ResolveAtResult::Other(_) => return Err(LinuxError::EACCES),
+    };
+    match loc.node_type() {
+        NodeType::RegularFile => {}
+        NodeType::Symlink => return Err(LinuxError::ELOOP),
+        _ => return Err(LinuxError::EACCES),
+    }
+
+    // The file is run as it is, even if it has no path any more.
+    do_execve(tf, path_of(&loc), Some(loc), args, envs)
+}
+
+/// Returns the path of `loc`, or just its name if it has none, e.g. once
+/// unlinked.
+fn path_of(loc: &Location) -> String {
+    loc.absolute_path()
+        .map_or_else(|_| loc.name().to_string(), |path| path.to_string())
+}
+
+/// Executes the program at `path`, or at `loc` if it is already resolved.
+fn do_execve(
+    tf: &mut TrapFrame,
+    path: String,
+    loc: Option<Location>,
+    args: Vec<String>,
+    envs: Vec<String>,
+) -> LinuxResult<isize> {
+    check_arg_size(&args, &envs)?;
+
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
 
@@ -42,18 +137,32 @@ pub fn sys_execve(
         return Err(LinuxError::EAGAIN);
     }
 
-    let mut aspace = proc_data.aspace.lock();
-    let (entry_point, user_stack_base) =
-        load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?;
+    let mut aspace = proc_data.lock_all_unpinned();
+    let (entry_point, user_stack_base, resident) = match &loc {
+        Some(loc) => load_user_app_at(&mut aspace, loc.clone(), &path, &args, &envs)?,
+        None => load_user_app(&mut aspace, Some(path.as_str()), &args, &envs)?,
+    };
     drop(aspace);
+    proc_data.reset_rss(resident);
+    proc_data.set_mmap_base(aslr::mmap_base());
+    proc_data.set_low_code(false);
+    proc_data.commit.read().clear();
+    proc_data.zero_pages.read().clear();
+    proc_data.mapping_names.lock().clear();
 
-    let loc = FS_CONTEXT.lock().resolve(&path)?;
+    let loc = match loc {
+        Some(loc) => loc,
+        None => fs_context().lock().resolve(&path)?,
+    };
     curr.set_name(loc.name());
 
-    *proc_data.exe_path.write() = loc.absolute_path()?.to_string();
+    *proc_data.exe_path.write() = path_of(&loc);
+    *proc_data.exe.write() = Some(loc);
     *proc_data.cmdline.write() = Arc::new(args);
+    *proc_data.environ.write() = Arc::new(envs);
 
     *proc_data.signal.actions.lock() = Default::default();
+    proc_data.set_sigchld_flags(0);
 
     // Close CLOEXEC file descriptors
     let mut fd_table = FD_TABLE.write();
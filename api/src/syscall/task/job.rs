@@ -1,10 +1,16 @@
 use axerrno::{LinuxError, LinuxResult};
 use axtask::current;
-use starry_core::task::{AsThread, get_process_data, get_process_group};
+use starry_core::task::{AsThread, current_pid_ns, get_process_data, get_process_group};
 use starry_process::Pid;
 
 pub fn sys_getsid(pid: Pid) -> LinuxResult<isize> {
-    Ok(get_process_data(pid)?.proc.group().session().sid() as _)
+    let pid_ns = current_pid_ns();
+    let sid = get_process_data(pid_ns.global_pid(pid)?)?
+        .proc
+        .group()
+        .session()
+        .sid();
+    Ok(pid_ns.local_pid(sid).unwrap_or_default() as _)
 }
 
 pub fn sys_setsid() -> LinuxResult<isize> {
@@ -14,23 +20,30 @@ pub fn sys_setsid() -> LinuxResult<isize> {
         return Err(LinuxError::EPERM);
     }
 
-    if let Some((session, _)) = proc.create_session() {
-        Ok(session.sid() as _)
+    let sid = if let Some((session, _)) = proc.create_session() {
+        session.sid()
     } else {
-        Ok(proc.pid() as _)
-    }
+        proc.pid()
+    };
+    Ok(current_pid_ns().local_pid(sid).unwrap_or_default() as _)
 }
 
 pub fn sys_getpgid(pid: Pid) -> LinuxResult<isize> {
-    Ok(get_process_data(pid)?.proc.group().pgid() as _)
+    let pid_ns = current_pid_ns();
+    let pgid = get_process_data(pid_ns.global_pid(pid)?)?
+        .proc
+        .group()
+        .pgid();
+    Ok(pid_ns.local_pid(pgid).unwrap_or_default() as _)
 }
 
 pub fn sys_setpgid(pid: Pid, pgid: Pid) -> LinuxResult<isize> {
-    let proc = &get_process_data(pid)?.proc;
+    let pid_ns = current_pid_ns();
+    let proc = &get_process_data(pid_ns.global_pid(pid)?)?.proc;
 
     if pgid == 0 {
         proc.create_group();
-    } else if !proc.move_to_group(&get_process_group(pgid)?) {
+    } else if !proc.move_to_group(&get_process_group(pid_ns.global_pid(pgid)?)?) {
         return Err(LinuxError::EPERM);
     }
 
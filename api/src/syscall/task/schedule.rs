@@ -1,14 +1,17 @@
 use axerrno::{LinuxError, LinuxResult};
 use axhal::time::TimeValue;
 use axtask::{
-    AxCpuMask, current,
+    AxCpuMask, AxTaskRef, current,
     future::{block_on_interruptible, sleep},
 };
 use linux_raw_sys::general::{
     __kernel_clockid_t, CLOCK_MONOTONIC, CLOCK_REALTIME, PRIO_PGRP, PRIO_PROCESS, PRIO_USER,
-    SCHED_RR, TIMER_ABSTIME, timespec,
+    SCHED_RESET_ON_FORK, TIMER_ABSTIME, timespec,
+};
+use starry_core::task::{
+    AsThread, SchedParams, SchedPolicy, WaitChannel, allowed_cpus, current_pid_ns,
+    get_process_data, get_process_group, get_task, wait_on,
 };
-use starry_core::task::{get_process_data, get_process_group};
 use starry_vm::{VmMutPtr, VmPtr, vm_load, vm_write_slice};
 
 use crate::time::TimeValueLike;
@@ -18,8 +21,19 @@ pub fn sys_sched_yield() -> LinuxResult<isize> {
     Ok(0)
 }
 
-fn sleep_impl(clock: impl Fn() -> TimeValue, dur: TimeValue) -> TimeValue {
+pub fn sys_getcpu(cpu: *mut u32, node: *mut u32) -> LinuxResult<isize> {
+    if let Some(cpu) = cpu.nullable() {
+        cpu.vm_write(axhal::percpu::this_cpu_id() as u32)?;
+    }
+    if let Some(node) = node.nullable() {
+        node.vm_write(0)?;
+    }
+    Ok(0)
+}
+
+fn sleep_impl(name: &'static str, clock: impl Fn() -> TimeValue, dur: TimeValue) -> TimeValue {
     debug!("sleep_impl <= {:?}", dur);
+    let _wait = wait_on(WaitChannel::Sleep(name));
 
     let start = clock();
 
@@ -39,7 +53,7 @@ pub fn sys_nanosleep(req: *const timespec, rem: *mut timespec) -> LinuxResult<is
     let req = unsafe { req.vm_read_uninit()?.assume_init() }.try_into_time_value()?;
     debug!("sys_nanosleep <= req: {:?}", req);
 
-    let actual = sleep_impl(axhal::time::monotonic_time, req);
+    let actual = sleep_impl("nanosleep", axhal::time::monotonic_time, req);
 
     if let Some(diff) = req.checked_sub(actual) {
         debug!("sys_nanosleep => rem: {:?}", diff);
@@ -79,7 +93,7 @@ pub fn sys_clock_nanosleep(
         req
     };
 
-    let actual = sleep_impl(clock, dur);
+    let actual = sleep_impl("clock_nanosleep", clock, dur);
 
     if let Some(diff) = dur.checked_sub(actual) {
         debug!("sys_clock_nanosleep => rem: {:?}", diff);
@@ -129,37 +143,107 @@ pub fn sys_sched_setaffinity(
         }
     }
 
+    let cpu_mask = allowed_cpus(cpu_mask);
+    if cpu_mask.is_empty() {
+        return Err(LinuxError::EINVAL);
+    }
+
     // TODO: support other threads
     axtask::set_current_affinity(cpu_mask);
 
     Ok(0)
 }
 
-pub fn sys_sched_getscheduler(_pid: i32) -> LinuxResult<isize> {
-    Ok(SCHED_RR as _)
+/// Finds the thread targeted by the `sched_*` syscalls.
+fn sched_target(pid: i32) -> LinuxResult<AxTaskRef> {
+    if pid < 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    get_task(current_pid_ns().global_pid(pid as _)?)
+}
+
+fn read_sched_priority(param: *const i32) -> LinuxResult<u32> {
+    let priority = param.nullable().ok_or(LinuxError::EINVAL)?.vm_read()?;
+    u32::try_from(priority).map_err(|_| LinuxError::EINVAL)
+}
+
+fn check_sched_priority(policy: SchedPolicy, priority: u32) -> LinuxResult<()> {
+    let (min, max) = policy.priority_range();
+    if !(min..=max).contains(&priority) {
+        return Err(LinuxError::EINVAL);
+    }
+    Ok(())
+}
+
+pub fn sys_sched_getscheduler(pid: i32) -> LinuxResult<isize> {
+    let params = sched_target(pid)?.as_thread().sched();
+    let mut policy = params.policy as u32;
+    if params.reset_on_fork {
+        policy |= SCHED_RESET_ON_FORK;
+    }
+    Ok(policy as _)
+}
+
+pub fn sys_sched_setscheduler(pid: i32, policy: u32, param: *const i32) -> LinuxResult<isize> {
+    debug!(
+        "sys_sched_setscheduler <= pid: {}, policy: {:#x}",
+        pid, policy
+    );
+    let task = sched_target(pid)?;
+    let reset_on_fork = policy & SCHED_RESET_ON_FORK != 0;
+    let policy = SchedPolicy::try_from(policy & !SCHED_RESET_ON_FORK)?;
+    let priority = read_sched_priority(param)?;
+    check_sched_priority(policy, priority)?;
+
+    task.as_thread().set_sched(SchedParams {
+        policy,
+        priority,
+        reset_on_fork,
+    });
+    Ok(0)
 }
 
-pub fn sys_sched_setscheduler(_pid: i32, _policy: i32, _param: *const ()) -> LinuxResult<isize> {
+pub fn sys_sched_getparam(pid: i32, param: *mut i32) -> LinuxResult<isize> {
+    let params = sched_target(pid)?.as_thread().sched();
+    param
+        .nullable()
+        .ok_or(LinuxError::EINVAL)?
+        .vm_write(params.priority as i32)?;
     Ok(0)
 }
 
-pub fn sys_sched_getparam(_pid: i32, _param: *mut ()) -> LinuxResult<isize> {
+pub fn sys_sched_setparam(pid: i32, param: *const i32) -> LinuxResult<isize> {
+    let task = sched_target(pid)?;
+    let thr = task.as_thread();
+    let priority = read_sched_priority(param)?;
+    let params = thr.sched();
+    check_sched_priority(params.policy, priority)?;
+
+    thr.set_sched(SchedParams { priority, ..params });
     Ok(0)
 }
 
+pub fn sys_sched_get_priority_max(policy: u32) -> LinuxResult<isize> {
+    Ok(SchedPolicy::try_from(policy)?.priority_range().1 as _)
+}
+
+pub fn sys_sched_get_priority_min(policy: u32) -> LinuxResult<isize> {
+    Ok(SchedPolicy::try_from(policy)?.priority_range().0 as _)
+}
+
 pub fn sys_getpriority(which: u32, who: u32) -> LinuxResult<isize> {
     debug!("sys_getpriority <= which: {}, who: {}", which, who);
 
     match which {
         PRIO_PROCESS => {
             if who != 0 {
-                let _proc = get_process_data(who)?;
+                let _proc = get_process_data(current_pid_ns().global_pid(who)?)?;
             }
             Ok(20)
         }
         PRIO_PGRP => {
             if who != 0 {
-                let _pg = get_process_group(who)?;
+                let _pg = get_process_group(current_pid_ns().global_pid(who)?)?;
             }
             Ok(20)
         }
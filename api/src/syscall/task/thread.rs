@@ -1,24 +1,28 @@
 use axerrno::{LinuxError, LinuxResult};
 use axtask::current;
 use num_enum::TryFromPrimitive;
-use starry_core::task::AsThread;
+use starry_core::task::{AsThread, current_pid_ns};
+use starry_process::Pid;
 
 pub fn sys_getpid() -> LinuxResult<isize> {
-    Ok(current().as_thread().proc_data.proc.pid() as _)
+    let pid = current().as_thread().proc_data.proc.pid();
+    Ok(current_pid_ns().local_pid(pid).unwrap_or_default() as _)
 }
 
 pub fn sys_getppid() -> LinuxResult<isize> {
-    current()
+    let parent = current()
         .as_thread()
         .proc_data
         .proc
         .parent()
-        .ok_or(LinuxError::ESRCH)
-        .map(|p| p.pid() as _)
+        .ok_or(LinuxError::ESRCH)?;
+    // The parent of the init of a PID namespace is outside of it.
+    Ok(current_pid_ns().local_pid(parent.pid()).unwrap_or_default() as _)
 }
 
 pub fn sys_gettid() -> LinuxResult<isize> {
-    Ok(current().id().as_u64() as _)
+    let tid = current().id().as_u64() as Pid;
+    Ok(current_pid_ns().local_pid(tid).unwrap_or_default() as _)
 }
 
 /// ARCH_PRCTL codes
@@ -49,7 +53,8 @@ enum ArchPrctlCode {
 pub fn sys_set_tid_address(clear_child_tid: usize) -> LinuxResult<isize> {
     let curr = current();
     curr.as_thread().set_clear_child_tid(clear_child_tid);
-    Ok(curr.id().as_u64() as isize)
+    let tid = curr.id().as_u64() as Pid;
+    Ok(current_pid_ns().local_pid(tid).unwrap_or_default() as _)
 }
 
 #[cfg(target_arch = "x86_64")]
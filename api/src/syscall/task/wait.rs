@@ -2,17 +2,25 @@ use alloc::vec::Vec;
 use core::{future::poll_fn, task::Poll};
 
 use axerrno::{LinuxError, LinuxResult};
-use axhal::context::TrapFrame;
+use axhal::{context::TrapFrame, time::TimeValue};
 use axtask::{current, future::try_block_on};
 use bitflags::bitflags;
 use linux_raw_sys::general::{
-    __WALL, __WCLONE, __WNOTHREAD, WCONTINUED, WEXITED, WNOHANG, WNOWAIT, WUNTRACED,
+    __WALL, __WCLONE, __WNOTHREAD, __sifields__bindgen_ty_4, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED,
+    CLD_KILLED, CLD_STOPPED, P_ALL, P_PGID, P_PID, P_PIDFD, SIGCHLD, SIGCONT, WCONTINUED, WEXITED,
+    WNOHANG, WNOWAIT, WUNTRACED, rusage, siginfo,
+};
+use starry_core::{
+    resources::ResourceUsage,
+    task::{AsThread, JobEvent, WaitChannel, current_pid_ns, get_process_data, wait_on},
 };
-use starry_core::task::AsThread;
 use starry_process::{Pid, Process};
 use starry_vm::{VmMutPtr, VmPtr};
 
-use crate::signal::check_signals;
+use crate::{
+    file::{FileLike, PidFd},
+    signal::check_signals,
+};
 
 bitflags! {
     #[derive(Debug)]
@@ -59,28 +67,17 @@ impl WaitPid {
     }
 }
 
-pub fn sys_waitpid(
+fn do_wait(
     tf: &mut TrapFrame,
-    pid: i32,
-    exit_code: *mut i32,
-    options: u32,
+    pid: WaitPid,
+    options: WaitOptions,
+    report: impl Fn(Pid, i32, ResourceUsage) -> LinuxResult<()>,
 ) -> LinuxResult<isize> {
-    let options = WaitOptions::from_bits_truncate(options);
-    info!("sys_waitpid <= pid: {:?}, options: {:?}", pid, options);
-
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
     let proc = &proc_data.proc;
-
-    let pid = if pid == -1 {
-        WaitPid::Any
-    } else if pid == 0 {
-        WaitPid::Pgid(proc.group().pgid())
-    } else if pid > 0 {
-        WaitPid::Pid(pid as _)
-    } else {
-        WaitPid::Pgid(-pid as _)
-    };
+    let pid_ns = proc_data.pid_ns.read().clone();
+    let local_pid = |child: &Process| pid_ns.local_pid(child.pid()).unwrap_or_default();
 
     // FIXME: add back support for WALL & WCLONE, since ProcessData may drop before
     // Process now.
@@ -94,14 +91,44 @@ pub fn sys_waitpid(
     }
 
     let check_children = || {
-        if let Some(child) = children.iter().find(|child| child.is_zombie()) {
-            if !options.contains(WaitOptions::WNOWAIT) {
-                child.free();
-            }
-            if let Some(exit_code) = exit_code.nullable() {
-                exit_code.vm_write(child.exit_code())?;
+        if options.contains(WaitOptions::WEXITED)
+            && let Some((child, pid, usage)) = children
+                .iter()
+                .filter(|child| child.is_zombie())
+                .find_map(|child| {
+                    let pid = local_pid(child);
+                    let usage = if options.contains(WaitOptions::WNOWAIT) {
+                        Some(proc_data.zombie_usage(child.pid()))
+                    } else {
+                        // `None` if the orphan reaper has freed it already.
+                        proc_data.reap_child(child)
+                    };
+                    Some((child, pid, usage?))
+                })
+        {
+            report(pid, child.exit_code(), usage)?;
+            Ok(pid as _)
+        } else if let Some((child, event)) = children.iter().find_map(|child| {
+            let event = get_process_data(child.pid()).ok()?.job_event()?;
+            let wanted = match event {
+                JobEvent::Stopped(_) => options.contains(WaitOptions::WUNTRACED),
+                JobEvent::Continued => options.contains(WaitOptions::WCONTINUED),
+            };
+            wanted.then_some((child, event))
+        }) {
+            report(
+                local_pid(child),
+                event.wait_status(),
+                ResourceUsage::default(),
+            )?;
+            if !options.contains(WaitOptions::WNOWAIT)
+                && let Ok(data) = get_process_data(child.pid())
+            {
+                data.clear_job_event();
             }
-            Ok(child.pid() as _)
+            Ok(local_pid(child) as _)
+        } else if !proc.children().iter().any(|child| pid.apply(child)) {
+            Err(LinuxError::ECHILD)
         } else if options.contains(WaitOptions::WNOHANG) {
             Ok(0)
         } else {
@@ -109,6 +136,7 @@ pub fn sys_waitpid(
         }
     };
 
+    let _wait = wait_on(WaitChannel::Syscall("wait"));
     let result = try_block_on(poll_fn(|cx| match check_children() {
         Ok(pid) => Poll::Ready(Ok(pid)),
         Err(LinuxError::EAGAIN) => {
@@ -132,3 +160,121 @@ pub fn sys_waitpid(
         Err(err) => Err(err),
     }
 }
+
+pub fn sys_waitpid(
+    tf: &mut TrapFrame,
+    pid: i32,
+    exit_code: *mut i32,
+    options: u32,
+    rusage: *mut rusage,
+) -> LinuxResult<isize> {
+    let options = WaitOptions::from_bits_truncate(options) | WaitOptions::WEXITED;
+    info!("sys_waitpid <= pid: {:?}, options: {:?}", pid, options);
+
+    let pid_ns = current_pid_ns();
+    let pid = if pid == -1 {
+        WaitPid::Any
+    } else if pid == 0 {
+        WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid())
+    } else if pid > 0 {
+        WaitPid::Pid(
+            pid_ns
+                .global_pid(pid as _)
+                .map_err(|_| LinuxError::ECHILD)?,
+        )
+    } else {
+        WaitPid::Pgid(
+            pid_ns
+                .global_pid(-pid as _)
+                .map_err(|_| LinuxError::ECHILD)?,
+        )
+    };
+
+    do_wait(tf, pid, options, |_, status, usage| {
+        if let Some(exit_code) = exit_code.nullable() {
+            exit_code.vm_write(status)?;
+        }
+        if let Some(rusage) = rusage.nullable() {
+            rusage.vm_write(usage.into())?;
+        }
+        Ok(())
+    })
+}
+
+/// Builds the `SIGCHLD` style [`siginfo`] reported by `waitid`.
+fn child_siginfo(pid: Pid, status: i32, usage: ResourceUsage) -> siginfo {
+    let (code, status) = if status == 0xffff {
+        (CLD_CONTINUED, SIGCONT as i32)
+    } else if status & 0xff == 0x7f {
+        (CLD_STOPPED, (status >> 8) & 0xff)
+    } else if status & 0x7f == 0 {
+        (CLD_EXITED, (status >> 8) & 0xff)
+    } else if status & 0x80 != 0 {
+        (CLD_DUMPED, status & 0x7f)
+    } else {
+        (CLD_KILLED, status & 0x7f)
+    };
+    // clock_t values are in USER_HZ (100) ticks.
+    let ticks = |tv: TimeValue| (tv.as_millis() / 10) as _;
+
+    // FIXME: Zeroable
+    let mut info: siginfo = unsafe { core::mem::zeroed() };
+    let fields = unsafe { &mut info.__bindgen_anon_1.__bindgen_anon_1 };
+    fields.si_signo = SIGCHLD as _;
+    fields.si_code = code as _;
+    fields._sifields._sigchld = __sifields__bindgen_ty_4 {
+        _pid: pid as _,
+        _uid: 0,
+        _status: status,
+        _utime: ticks(usage.utime),
+        _stime: ticks(usage.stime),
+    };
+    info
+}
+
+pub fn sys_waitid(
+    tf: &mut TrapFrame,
+    idtype: u32,
+    id: i32,
+    info: *mut siginfo,
+    options: u32,
+    rusage: *mut rusage,
+) -> LinuxResult<isize> {
+    let options = WaitOptions::from_bits(options).ok_or(LinuxError::EINVAL)?;
+    info!(
+        "sys_waitid <= idtype: {}, id: {}, options: {:?}",
+        idtype, id, options
+    );
+    if !options.intersects(WaitOptions::WEXITED | WaitOptions::WUNTRACED | WaitOptions::WCONTINUED)
+    {
+        return Err(LinuxError::EINVAL);
+    }
+
+    let pid_ns = current_pid_ns();
+    let global_pid = |id: i32| pid_ns.global_pid(id as _).map_err(|_| LinuxError::ECHILD);
+    let pid = match idtype {
+        P_ALL => WaitPid::Any,
+        P_PID if id > 0 => WaitPid::Pid(global_pid(id)?),
+        P_PGID if id == 0 => WaitPid::Pgid(current().as_thread().proc_data.proc.group().pgid()),
+        P_PGID if id > 0 => WaitPid::Pgid(global_pid(id)?),
+        P_PIDFD => WaitPid::Pid(PidFd::from_fd(id)?.pid()),
+        _ => return Err(LinuxError::EINVAL),
+    };
+
+    let pid = do_wait(tf, pid, options, |pid, status, usage| {
+        if let Some(info) = info.nullable() {
+            info.vm_write(child_siginfo(pid, status, usage))?;
+        }
+        if let Some(rusage) = rusage.nullable() {
+            rusage.vm_write(usage.into())?;
+        }
+        Ok(())
+    })?;
+    if pid == 0
+        && let Some(info) = info.nullable()
+    {
+        // WNOHANG and no child is waitable: si_pid must read as zero.
+        info.vm_write(unsafe { core::mem::zeroed() })?;
+    }
+    Ok(0)
+}
@@ -1,17 +1,20 @@
-use core::{ffi::c_long, sync::atomic::Ordering};
+use core::{ffi::c_long, mem, ops::DerefMut};
 
 use axerrno::{LinuxError, LinuxResult};
-use axhal::uspace::{ExceptionKind, ReturnReason, UserContext};
+use axhal::{
+    context::TrapFrame,
+    uspace::{ExceptionKind, ReturnReason, UserContext},
+};
 use axtask::{TaskInner, current};
 use bytemuck::AnyBitPattern;
-use linux_raw_sys::general::ROBUST_LIST_LIMIT;
+use linux_raw_sys::general::{FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS, ROBUST_LIST_LIMIT};
 use starry_core::{
     futex::FutexKey,
     mm::access_user_memory,
     shm::SHM_MANAGER,
     task::{
-        AsThread, get_process_data, get_task, send_signal_to_process, send_signal_to_thread,
-        set_timer_state,
+        AsThread, ProcessData, SchedPolicy, Thread, current_pid_ns, exit_process, get_process_data,
+        get_task, send_signal_to_process, send_signal_to_thread, set_timer_state,
     },
     time::TimerState,
 };
@@ -20,10 +23,12 @@ use starry_signal::{SignalInfo, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
-    signal::{check_signals, unblock_next_signal},
+    file::{FD_TABLE, close_all_files},
+    mm::vm_update_u32,
+    signal::{check_signals, unblock_next_signal, wait_while_stopped},
     syscall::handle_syscall,
+    vfs::dev::tty::release_terminal,
 };
-// use axhal::context::TrapFrame;
 
 /// Create a new user task.
 pub fn new_user_task(
@@ -36,13 +41,18 @@ pub fn new_user_task(
             let curr = axtask::current();
             access_user_memory(|| {
                 if let Some(tid) = set_child_tid {
-                    *tid = curr.id().as_u64() as Pid;
+                    *tid = current_pid_ns()
+                        .local_pid(curr.id().as_u64() as Pid)
+                        .unwrap_or_default();
                 }
             });
 
             info!("Enter user space: ip={:#x}, sp={:#x}", uctx.ip(), uctx.sp());
             info!("name: {:#?}", curr.name());
 
+            #[cfg(feature = "gdbstub")]
+            crate::gdbstub::on_user_entry(&mut uctx);
+
             let thr = curr.as_thread();
             while !thr.pending_exit() {
                 let _ = curr.get_stack_bottom();
@@ -57,19 +67,41 @@ pub fn new_user_task(
 
                 match reason {
                     ReturnReason::Syscall => handle_syscall(&mut uctx),
-                    ReturnReason::PageFault(addr, flags) => {
-                        if !thr.proc_data.aspace.lock().handle_page_fault(addr, flags) {
-                            info!(
-                                "{:?}: segmentation fault at {:#x} {:?}",
-                                thr.proc_data.proc, addr, flags
-                            );
-                            raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
-                                .expect("Failed to send SIGSEGV");
+                    ReturnReason::PageFault(addr, flags) => 'fault: {
+                        if thr.proc_data.handle_page_fault(addr, flags) {
+                            thr.count_page_fault();
+                            break 'fault;
                         }
+                        #[cfg(target_arch = "x86_64")]
+                        if crate::syscall::emulate_vsyscall(&mut uctx, addr) {
+                            break 'fault;
+                        }
+                        info!(
+                            "{}[{}]: segfault at {:#x} ip {:#x} sp {:#x} {:?}",
+                            curr.name(),
+                            curr.id().as_u64(),
+                            addr,
+                            uctx.ip(),
+                            uctx.sp(),
+                            flags
+                        );
+                        thr.set_fault_addr(addr.as_usize());
+                        raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV), &uctx)
+                            .expect("Failed to send SIGSEGV");
                     }
                     ReturnReason::Interrupt => {}
                     #[allow(unused_labels)]
                     ReturnReason::Exception(exc_info) => 'exc: {
+                        #[cfg(feature = "gdbstub")]
+                        if crate::gdbstub::handle_trap(&mut uctx, exc_info.kind()) {
+                            break 'exc;
+                        }
+                        #[cfg(target_arch = "x86_64")]
+                        if !matches!(exc_info.kind(), ExceptionKind::Breakpoint)
+                            && crate::syscall::handle_int80(&mut uctx)
+                        {
+                            break 'exc;
+                        }
                         // TODO: detailed handling
                         let signo = match exc_info.kind() {
                             ExceptionKind::Misaligned => {
@@ -83,12 +115,12 @@ pub fn new_user_task(
                             ExceptionKind::IllegalInstruction => Signo::SIGILL,
                             _ => Signo::SIGTRAP,
                         };
-                        raise_signal_fatal(SignalInfo::new_kernel(signo))
+                        raise_signal_fatal(SignalInfo::new_kernel(signo), &uctx)
                             .expect("Failed to send SIGTRAP");
                     }
                     r => {
                         warn!("Unexpected return reason: {:?}", r);
-                        raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV))
+                        raise_signal_fatal(SignalInfo::new_kernel(Signo::SIGSEGV), &uctx)
                             .expect("Failed to send SIGSEGV");
                     }
                 }
@@ -96,6 +128,13 @@ pub fn new_user_task(
                 if !unblock_next_signal() {
                     while check_signals(thr, &mut uctx, None) {}
                 }
+                // Another thread may have stopped the whole process.
+                wait_while_stopped(thr);
+                // Not an idle class, which the scheduler lacks, but it lets
+                // every other runnable task go first at each kernel entry.
+                if thr.sched().policy == SchedPolicy::Idle {
+                    axtask::yield_now();
+                }
 
                 set_timer_state(&curr, TimerState::User);
                 // Clear interrupt state
@@ -121,21 +160,44 @@ pub struct RobustListHead {
     pub list_op_pending: *mut RobustList,
 }
 
-fn handle_futex_death(entry: *mut RobustList, offset: i64) -> LinuxResult<()> {
-    let address = (entry as u64)
+/// Marks the robust futex at `entry + offset` as abandoned if the current
+/// thread owns it, waking up a waiter to take it over.
+///
+/// `pending` is for `list_op_pending`, the lock being taken or released when
+/// the thread died. A non-PI lock it already released may still have to wake
+/// up a waiter.
+fn handle_futex_death(
+    entry: *mut RobustList,
+    offset: i64,
+    tid: u32,
+    pending: bool,
+) -> LinuxResult<()> {
+    let pi = entry.addr() & 1 != 0;
+    let address = (entry.addr() as u64 & !1)
         .checked_add_signed(offset)
         .ok_or(LinuxError::EINVAL)?;
     let address: usize = address.try_into().map_err(|_| LinuxError::EINVAL)?;
-    let key = FutexKey::new_current(address);
+    let uaddr = address as *mut u32;
 
-    let curr = current();
-    let futex_table = curr.as_thread().proc_data.futex_table_for(&key);
-
-    let Some(futex) = futex_table.get(&key) else {
-        return Ok(());
+    let val = uaddr.vm_read()?;
+    let wake = if pending && !pi && val == 0 {
+        true
+    } else {
+        let Ok(val) = vm_update_u32(uaddr, |val| {
+            (val & FUTEX_TID_MASK == tid).then_some(val & FUTEX_WAITERS | FUTEX_OWNER_DIED)
+        })?
+        else {
+            return Ok(());
+        };
+        val & FUTEX_WAITERS != 0
     };
-    futex.owner_dead.store(true, Ordering::SeqCst);
-    futex.wq.wake(1, u32::MAX);
+    if wake {
+        let key = FutexKey::new_current(address);
+        let futex_table = current().as_thread().proc_data.futex_table_for(&key);
+        if let Some(futex) = futex_table.get(&key) {
+            futex.wq.wake(1, u32::MAX);
+        }
+    }
     Ok(())
 }
 
@@ -143,6 +205,9 @@ pub fn exit_robust_list(head: *const RobustListHead) -> LinuxResult<()> {
     // Reference: https://elixir.bootlin.com/linux/v6.13.6/source/kernel/futex/core.c#L777
 
     let mut limit = ROBUST_LIST_LIMIT;
+    let tid = current_pid_ns()
+        .local_pid(current().id().as_u64() as Pid)
+        .unwrap_or_default();
 
     let end_ptr = unsafe { &raw const (*head).list };
     let head = head.vm_read()?;
@@ -150,10 +215,11 @@ pub fn exit_robust_list(head: *const RobustListHead) -> LinuxResult<()> {
     let offset = head.futex_offset;
     let pending = head.list_op_pending;
 
-    while !core::ptr::eq(entry, end_ptr) {
-        let next_entry = entry.vm_read()?.next;
+    // The low bit of the pointers marks PI futexes.
+    while !core::ptr::eq(entry.map_addr(|addr| addr & !1), end_ptr) {
+        let next_entry = entry.map_addr(|addr| addr & !1).vm_read()?.next;
         if entry != pending {
-            handle_futex_death(entry, offset)?;
+            handle_futex_death(entry, offset, tid, false)?;
         }
         entry = next_entry;
 
@@ -163,6 +229,9 @@ pub fn exit_robust_list(head: *const RobustListHead) -> LinuxResult<()> {
         }
         axtask::yield_now();
     }
+    if !pending.is_null() {
+        handle_futex_death(pending, offset, tid, true)?;
+    }
 
     Ok(())
 }
@@ -191,13 +260,31 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
     }
 
     let process = &thr.proc_data.proc;
-    if process.exit_thread(curr.id().as_u64() as Pid, exit_code) {
-        process.exit();
+    let usage = thr.proc_data.add_exited_usage(thr.usage());
+    let tid = curr.id().as_u64() as Pid;
+    if process.exit_thread(tid, exit_code) {
+        let pid_ns = thr.proc_data.pid_ns.read().clone();
+        if pid_ns.init_pid() == Some(process.pid()) {
+            // The namespace dies with its init.
+            let sig = SignalInfo::new_kernel(Signo::SIGKILL);
+            for pid in pid_ns.kill() {
+                if pid != process.pid() {
+                    let _ = send_signal_to_process(pid, Some(sig.clone()));
+                }
+            }
+        }
+        close_all_files();
+        release_scope(&thr.proc_data);
+        exit_process(&thr.proc_data);
+        thr.proc_data.teardown();
+        release_terminal(process);
         if let Some(parent) = process.parent() {
             if let Some(signo) = thr.proc_data.exit_signal {
                 let _ = send_signal_to_process(parent.pid(), Some(SignalInfo::new_kernel(signo)));
             }
             if let Ok(data) = get_process_data(parent.pid()) {
+                let usage = usage.collate(thr.proc_data.children_usage());
+                data.set_zombie_usage(process.pid(), usage);
                 data.child_exit_event.wake();
             }
         }
@@ -215,8 +302,62 @@ pub fn do_exit(exit_code: i32, group_exit: bool) {
     thr.set_exit();
 }
 
+/// Releases the resources in the scope of a process whose last thread is
+/// exiting, so that they do not live as long as its data.
+///
+/// With the `track` feature, a file descriptor table that was not shared is
+/// checked to have no files left.
+fn release_scope(proc_data: &ProcessData) {
+    let mut scope = proc_data.scope.write();
+    let files = mem::take(FD_TABLE.scope_mut(&mut scope).deref_mut());
+    drop(scope);
+
+    #[cfg(feature = "track")]
+    if alloc::sync::Arc::strong_count(&files) == 1 {
+        let count = files.read().count();
+        assert_eq!(count, 0, "{:?} exited with files open", proc_data.proc);
+    }
+    drop(files);
+}
+
+/// Number of words of the user stack dumped by [`dump_fatal_signal`].
+const STACK_DUMP_WORDS: usize = 32;
+
+/// Prints diagnostics for a thread about to be killed by a fatal signal.
+///
+/// This includes the pid, the faulting address (if any), the registers and a
+/// short hexdump of the user stack. Reading the stack is best effort: the dump
+/// stops at the first inaccessible word.
+pub fn dump_fatal_signal(thr: &Thread, tf: &TrapFrame, signo: Signo) {
+    let proc = &thr.proc_data.proc;
+    let exe_path = thr.proc_data.exe_path.read().clone();
+    warn!(
+        "pid {} ({}) killed by {:?}, fault addr: {:#x}, ip: {:#x}, sp: {:#x}",
+        proc.pid(),
+        exe_path,
+        signo,
+        thr.fault_addr(),
+        tf.ip(),
+        tf.sp()
+    );
+    warn!("registers: {:#x?}", tf);
+
+    let sp = tf.sp() & !(size_of::<usize>() - 1);
+    for row in 0..STACK_DUMP_WORDS / 4 {
+        let addr = sp + row * 4 * size_of::<usize>();
+        let Ok(words) = (addr as *const [usize; 4]).vm_read() else {
+            warn!("{:#x}: <inaccessible>", addr);
+            break;
+        };
+        warn!(
+            "{:#x}: {:#018x} {:#018x} {:#018x} {:#018x}",
+            addr, words[0], words[1], words[2], words[3]
+        );
+    }
+}
+
 /// Sends a fatal signal to the current process.
-pub fn raise_signal_fatal(sig: SignalInfo) -> LinuxResult<()> {
+pub fn raise_signal_fatal(sig: SignalInfo, tf: &TrapFrame) -> LinuxResult<()> {
     let curr = current();
     let proc_data = &curr.as_thread().proc_data;
 
@@ -228,6 +369,7 @@ pub fn raise_signal_fatal(sig: SignalInfo) -> LinuxResult<()> {
         task.interrupt(proc_data.signal.can_restart(signo));
     } else {
         warn!("Fatal exit {signo:?}");
+        dump_fatal_signal(curr.as_thread(), tf, signo);
         // No task wants to handle the signal, abort the task
         do_exit(signo as i32, true);
     }
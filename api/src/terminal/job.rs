@@ -60,11 +60,25 @@ impl JobControl {
         Ok(())
     }
 
+    /// Returns the session the terminal controls, if any.
+    pub fn session(&self) -> Option<Arc<Session>> {
+        self.session.lock().upgrade()
+    }
+
     pub fn set_session(&self, session: &Arc<Session>) {
         let mut guard = self.session.lock();
         assert!(guard.upgrade().is_none());
         *guard = Arc::downgrade(session);
     }
+
+    /// Detaches the terminal from its session, returning the foreground
+    /// process group it had.
+    pub fn detach(&self) -> Option<Arc<ProcessGroup>> {
+        *self.session.lock() = Weak::new();
+        let foreground = core::mem::take(&mut *self.foreground.lock()).upgrade();
+        self.poll_fg.wake();
+        foreground
+    }
 }
 
 impl Pollable for JobControl {
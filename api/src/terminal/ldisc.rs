@@ -1,16 +1,20 @@
-use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use core::{
     future::poll_fn,
     ops::Range,
     sync::atomic::{AtomicBool, Ordering},
     task::{Context, Poll, Waker},
+    time::Duration,
 };
 
 use axerrno::{LinuxError, LinuxResult};
 use axio::{IoEvents, PollSet, Pollable};
 use axtask::future::{Poller, block_on};
+use kspin::SpinNoIrq;
 use linux_raw_sys::general::{
-    ECHOCTL, ECHOK, ICRNL, IGNCR, ISIG, VEOF, VERASE, VKILL, VMIN, VTIME,
+    ECHOCTL, ECHOE, ECHOK, ECHOKE, ECHONL, ICRNL, IGNCR, ISIG, IXANY, IXON, NOFLSH,
+    TIOCPKT_FLUSHREAD, TIOCPKT_FLUSHWRITE, VEOF, VERASE, VKILL, VLNEXT, VMIN, VREPRINT, VSTART,
+    VSTOP, VTIME, VWERASE,
 };
 use ringbuf::{
     CachingCons, CachingProd,
@@ -22,6 +26,9 @@ use starry_signal::SignalInfo;
 use crate::terminal::{Terminal, termios::Termios2};
 
 const BUF_SIZE: usize = 80;
+/// How much input pushed with `TIOCSTI` may wait to be processed, like the
+/// input buffer of Linux.
+const INJECTED_MAX: usize = 4096;
 
 type ReadBuf = Arc<ringbuf::StaticRb<u8, BUF_SIZE>>;
 
@@ -58,6 +65,27 @@ pub trait TtyRead: Send + Sync + 'static {
 }
 pub trait TtyWrite: Send + Sync + 'static {
     fn write(&self, buf: &[u8]);
+
+    /// Writes as much of `buf` as there is room for, failing with `EAGAIN` if
+    /// there is none. Devices that never fill up take it all.
+    fn try_write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        self.write(buf);
+        Ok(buf.len())
+    }
+
+    /// Whether [`TtyWrite::try_write`] would take anything.
+    fn writable(&self) -> bool {
+        true
+    }
+
+    /// Registers `waker` to be woken once there is room to write.
+    fn register_writable(&self, _waker: &Waker) {}
+
+    /// Switches the line to `baud`, returning the speed actually set, or
+    /// `None` if the device has no line speed.
+    fn set_speed(&self, _baud: u32) -> LinuxResult<Option<u32>> {
+        Ok(None)
+    }
 }
 
 struct InputReader<R, W> {
@@ -73,6 +101,10 @@ struct InputReader<R, W> {
     line_buf: Vec<u8>,
     line_read: Option<usize>,
     clear_line_buf: Arc<AtomicBool>,
+    /// Whether the next character is taken as is, after `VLNEXT`.
+    literal_next: bool,
+    /// Input pushed with `TIOCSTI`, taken before that of the device.
+    injected: Arc<SpinNoIrq<VecDeque<u8>>>,
 }
 impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
     pub fn poll(&mut self) -> bool {
@@ -80,7 +112,17 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
             self.line_buf.clear();
         }
         if self.read_range.is_empty() {
-            let read = self.reader.read(&mut self.read_buf);
+            let mut injected = self.injected.lock();
+            let read = if injected.is_empty() {
+                drop(injected);
+                self.reader.read(&mut self.read_buf)
+            } else {
+                let len = injected.len().min(BUF_SIZE);
+                for (dst, ch) in self.read_buf.iter_mut().zip(injected.drain(..len)) {
+                    *dst = ch;
+                }
+                len
+            };
             self.read_range = 0..read;
         }
         let term = self.terminal.load_termios();
@@ -95,6 +137,7 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
                 *offset += read;
                 if *offset == self.line_buf.len() {
                     self.line_read = None;
+                    self.line_buf.clear();
                 }
                 continue;
             }
@@ -104,6 +147,19 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
             let mut ch = self.read_buf[self.read_range.start];
             self.read_range.start += 1;
 
+            if core::mem::take(&mut self.literal_next) {
+                if term.echo() {
+                    self.echo_char(&term, ch);
+                }
+                if term.canonical() {
+                    self.line_buf.push(ch);
+                } else {
+                    self.buf_tx.try_push(ch).unwrap();
+                    sent += 1;
+                }
+                continue;
+            }
+
             if ch == b'\r' {
                 if term.has_iflag(IGNCR) {
                     continue;
@@ -113,72 +169,172 @@ impl<R: TtyRead, W: TtyWrite> InputReader<R, W> {
                 }
             }
 
-            self.check_send_signal(&term, ch);
-
-            if term.echo() {
-                self.output_char(&term, ch);
+            if self.check_send_signal(&term, ch) || self.check_flow_control(&term, ch) {
+                continue;
+            }
+            if term.contains_iexten() && ch == term.special_char(VLNEXT) {
+                self.literal_next = true;
+                continue;
             }
+
             if !term.canonical() {
+                if term.echo() {
+                    self.echo_char(&term, ch);
+                }
                 self.buf_tx.try_push(ch).unwrap();
                 sent += 1;
                 continue;
             }
+            self.edit_line(&term, ch);
+        }
 
-            // Canonical mode
-            if term.has_lflag(ECHOK) && ch == term.special_char(VKILL) {
-                self.line_buf.clear();
-                continue;
-            }
-            if ch == term.special_char(VERASE) {
-                self.line_buf.pop();
-                continue;
+        sent > 0
+    }
+
+    /// Sends the signal of `ch` to the foreground process group under
+    /// `ISIG`, returning whether it was such a character.
+    fn check_send_signal(&mut self, term: &Termios2, ch: u8) -> bool {
+        if !term.has_lflag(ISIG) {
+            return false;
+        }
+        let Some(signo) = term.signo_for(ch) else {
+            return false;
+        };
+        if !term.has_lflag(NOFLSH) {
+            self.line_buf.clear();
+            self.line_read = None;
+            self.terminal
+                .packet
+                .report(TIOCPKT_FLUSHREAD | TIOCPKT_FLUSHWRITE);
+        }
+        if term.echo() {
+            self.echo_char(term, ch);
+        }
+        if let Some(pg) = self.terminal.job_control.foreground() {
+            let sig = SignalInfo::new_kernel(signo);
+            if let Err(err) = send_signal_to_process_group(pg.pgid(), Some(sig)) {
+                warn!("Failed to send signal: {err:?}");
             }
+        }
+        true
+    }
 
-            if term.is_eol(ch) || ch == term.special_char(VEOF) {
-                if ch != term.special_char(VEOF) {
-                    self.line_buf.push(ch);
+    /// Stops or restarts the output on `VSTOP` and `VSTART` under `IXON`,
+    /// returning whether `ch` was one of them.
+    fn check_flow_control(&self, term: &Termios2, ch: u8) -> bool {
+        if !term.has_iflag(IXON) {
+            return false;
+        }
+        if ch == term.special_char(VSTOP) {
+            self.terminal.set_output_stopped(true);
+            return true;
+        }
+        if ch == term.special_char(VSTART) {
+            self.terminal.set_output_stopped(false);
+            return true;
+        }
+        if term.has_iflag(IXANY) {
+            self.terminal.set_output_stopped(false);
+        }
+        false
+    }
+
+    /// Edits the line being typed with `ch`, in canonical mode.
+    fn edit_line(&mut self, term: &Termios2, ch: u8) {
+        let echo = term.echo();
+        let iexten = term.contains_iexten();
+        if ch == term.special_char(VERASE) {
+            self.erase(term, 1, ch);
+        } else if iexten && ch == term.special_char(VWERASE) {
+            let word = self
+                .line_buf
+                .iter()
+                .rev()
+                .skip_while(|it| it.is_ascii_whitespace())
+                .take_while(|it| !it.is_ascii_whitespace())
+                .count();
+            let spaces = self
+                .line_buf
+                .iter()
+                .rev()
+                .take_while(|it| it.is_ascii_whitespace())
+                .count();
+            self.erase(term, spaces + word, ch);
+        } else if ch == term.special_char(VKILL) {
+            if term.has_lflag(ECHOK) && term.has_lflag(ECHOKE) {
+                self.erase(term, self.line_buf.len(), ch);
+            } else {
+                self.line_buf.clear();
+                if echo {
+                    self.echo_char(term, ch);
+                    if term.has_lflag(ECHOK) {
+                        self.writer.write(b"\n");
+                    }
                 }
-                if !self.line_buf.is_empty() {
-                    self.line_read = Some(0);
+            }
+        } else if iexten && ch == term.special_char(VREPRINT) {
+            if echo {
+                self.echo_char(term, ch);
+                self.writer.write(b"\n");
+                for &ch in &self.line_buf {
+                    self.echo_char(term, ch);
                 }
-                continue;
             }
-
-            if ch.is_ascii_graphic() {
-                self.line_buf.push(ch);
-                continue;
+        } else if ch == term.special_char(VEOF) {
+            // Ends the line without being part of it, so at the start of a
+            // line it reads as the end of the file, which is not supported.
+            if !self.line_buf.is_empty() {
+                self.line_read = Some(0);
+            }
+        } else {
+            if echo || (ch == b'\n' && term.has_lflag(ECHONL)) {
+                self.echo_char(term, ch);
+            }
+            self.line_buf.push(ch);
+            if term.is_eol(ch) {
+                self.line_read = Some(0);
             }
         }
-
-        sent > 0
     }
 
-    fn check_send_signal(&self, term: &Termios2, ch: u8) {
-        if !term.canonical() || !term.has_lflag(ISIG) {
+    /// Erases the last `count` characters of the line, `erase` being the
+    /// character that asked for it.
+    fn erase(&mut self, term: &Termios2, count: usize, erase: u8) {
+        let count = count.min(self.line_buf.len());
+        if count == 0 {
             return;
         }
-        if let Some(signo) = term.signo_for(ch)
-            && let Some(pg) = self.terminal.job_control.foreground()
-        {
-            let sig = SignalInfo::new_kernel(signo);
-            if let Err(err) = send_signal_to_process_group(pg.pgid(), Some(sig)) {
-                warn!("Failed to send signal: {err:?}");
+        let erased = self.line_buf.split_off(self.line_buf.len() - count);
+        if !term.echo() {
+            return;
+        }
+        if !term.has_lflag(ECHOE) {
+            self.echo_char(term, erase);
+            return;
+        }
+        for &ch in erased.iter().rev() {
+            // Control characters were shown as two, like `^A`.
+            let width = if ch.is_ascii_control() && ch != b'\t' && term.has_lflag(ECHOCTL) {
+                2
+            } else {
+                1
+            };
+            for _ in 0..width {
+                self.writer.write(b"\x08 \x08");
             }
         }
     }
 
-    fn output_char(&self, term: &Termios2, ch: u8) {
+    fn echo_char(&self, term: &Termios2, ch: u8) {
         match ch {
             b'\n' => self.writer.write(b"\n"),
             b'\r' => self.writer.write(b"\r\n"),
-            ch if ch == term.special_char(VERASE) => self.writer.write(b"\x08 \x08"),
-            ch if ch == b' ' || ch.is_ascii_graphic() => self.writer.write(&[ch]),
+            b'\t' => self.writer.write(b"\t"),
             ch if ch.is_ascii_control() && term.has_lflag(ECHOCTL) => {
-                self.writer.write(&[b'^', (ch + 0x40)]);
-            }
-            other => {
-                warn!("Ignored echo char: {:#x}", other);
+                // `^?` for DEL.
+                self.writer.write(&[b'^', ch ^ 0x40]);
             }
+            ch => self.writer.write(&[ch]),
         }
     }
 }
@@ -190,7 +346,10 @@ struct SimpleReader<R> {
 }
 impl<R: TtyRead> SimpleReader<R> {
     pub fn poll(&mut self) {
-        let read = self.reader.read(&mut self.read_buf);
+        // Only take what fits even if every byte is a newline, so that the
+        // writer is held back instead of the data being dropped.
+        let room = (self.buf_tx.vacant_len() / 2).min(BUF_SIZE);
+        let read = self.reader.read(&mut self.read_buf[..room]);
         for ch in &self.read_buf[..read] {
             if *ch == b'\n' {
                 let _ = self.buf_tx.try_push(b'\r');
@@ -211,6 +370,7 @@ pub struct LineDiscipline<R, W> {
     buf_rx: CachingCons<ReadBuf>,
     poll_tx: Arc<PollSet>,
     clear_line_buf: Arc<AtomicBool>,
+    injected: Arc<SpinNoIrq<VecDeque<u8>>>,
     processor: Processor<R, W>,
 }
 
@@ -234,6 +394,7 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
         let (buf_tx, buf_rx) = ReadBuf::default().split();
 
         let clear_line_buf = Arc::new(AtomicBool::new(false));
+        let injected = Arc::new(SpinNoIrq::new(VecDeque::new()));
         let mut reader = InputReader {
             terminal: terminal.clone(),
 
@@ -247,6 +408,8 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             line_buf: Vec::new(),
             line_read: None,
             clear_line_buf: clear_line_buf.clone(),
+            literal_next: false,
+            injected: injected.clone(),
         };
 
         let poll_tx = Arc::new(PollSet::new());
@@ -293,10 +456,24 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             buf_rx,
             poll_tx,
             clear_line_buf,
+            injected,
             processor,
         }
     }
 
+    /// Pushes `ch` as if it had been typed, for `TIOCSTI`. Like a full input
+    /// buffer, too much input waiting drops the character.
+    pub fn push_input(&self, ch: u8) {
+        let mut injected = self.injected.lock();
+        if injected.len() >= INJECTED_MAX {
+            return;
+        }
+        injected.push_back(ch);
+        drop(injected);
+        // Wakes up the task processing the input, if any.
+        self.poll_tx.wake();
+    }
+
     pub fn drain_input(&mut self) {
         self.buf_rx.clear();
         self.clear_line_buf.store(true, Ordering::Relaxed);
@@ -337,20 +514,22 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             };
         }
 
+        // In non-canonical mode, VMIN is how many bytes to wait for, and
+        // VTIME how long to wait in tenths of a second: for the first byte if
+        // VMIN is zero, or between bytes otherwise.
         let term = self.terminal.termios.lock().clone();
-        let vmin = if term.canonical() {
-            1
+        let (vmin, vtime) = if term.canonical() {
+            (1, None)
         } else {
-            let vtime = term.special_char(VTIME);
-            if vtime > 0 {
-                todo!();
-            }
-            term.special_char(VMIN) as usize
+            let vtime = term.special_char(VTIME) as u64;
+            (
+                term.special_char(VMIN) as usize,
+                (vtime > 0).then(|| Duration::from_millis(vtime * 100)),
+            )
         };
-
-        if buf.len() < vmin as usize {
-            return Err(LinuxError::EAGAIN);
-        }
+        let vmin = vmin.min(buf.len());
+        // With a timer, a read of no bytes still waits for one.
+        let target = vmin.max(vtime.is_some() as usize);
 
         let mut total_read = 0;
         let set = match &self.processor {
@@ -359,12 +538,33 @@ impl<R: TtyRead, W: TtyWrite> LineDiscipline<R, W> {
             _ => unreachable!(),
         };
         let pollable = WaitPollable(set);
-        Poller::new(&pollable, IoEvents::IN).poll(|| {
-            total_read += self.buf_rx.pop_slice(&mut buf[total_read..]);
-            self.poll_tx.wake();
-            (total_read >= vmin)
-                .then_some(total_read)
-                .ok_or(LinuxError::EAGAIN)
-        })
+        loop {
+            let before = total_read;
+            // The timer only starts with the first byte, unless VMIN is zero.
+            let timeout = if total_read > 0 || vmin == 0 {
+                vtime
+            } else {
+                None
+            };
+            let result = Poller::new(&pollable, IoEvents::IN)
+                .timeout(timeout)
+                .poll(|| {
+                    total_read += self.buf_rx.pop_slice(&mut buf[total_read..]);
+                    self.poll_tx.wake();
+                    if total_read >= target {
+                        Ok(true)
+                    } else if vtime.is_some() && total_read > before {
+                        // Restart the timer.
+                        Ok(false)
+                    } else {
+                        Err(LinuxError::EAGAIN)
+                    }
+                });
+            match result {
+                Ok(true) | Err(LinuxError::ETIMEDOUT) => return Ok(total_read),
+                Ok(false) => {}
+                Err(err) => return Err(err),
+            }
+        }
     }
 }
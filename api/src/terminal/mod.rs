@@ -1,17 +1,24 @@
 //! Terminal module.
 
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicU32;
+use core::{
+    sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering},
+    task::Waker,
+};
 
+use axio::PollSet;
 use bytemuck::AnyBitPattern;
 use kspin::SpinNoPreempt;
+use linux_raw_sys::general::{TIOCPKT_START, TIOCPKT_STOP};
+use starry_core::task::send_signal_to_process_group;
+use starry_signal::{SignalInfo, Signo};
 
 pub mod job;
 pub mod ldisc;
 pub mod termios;
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, AnyBitPattern)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, AnyBitPattern)]
 pub struct WindowSize {
     pub ws_row: u16,
     pub ws_col: u16,
@@ -19,11 +26,66 @@ pub struct WindowSize {
     pub ws_ypixel: u16,
 }
 
+/// Packet mode of a pty master, set with `TIOCPKT`.
+///
+/// Each read from the master then starts with a byte: `TIOCPKT_DATA` before
+/// the data, or `TIOCPKT_*` flags telling about changes on the slave, which
+/// are read alone.
+#[derive(Default)]
+pub struct PacketMode {
+    enabled: AtomicBool,
+    /// The changes not read yet.
+    status: AtomicU8,
+    poll_status: PollSet,
+}
+
+impl PacketMode {
+    pub fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.status.store(0, Ordering::Release);
+        self.enabled.store(enabled, Ordering::Release);
+        // A blocked read is to start over in the new mode.
+        self.poll_status.wake();
+    }
+
+    /// Records the `TIOCPKT_*` flags `status` for the master, if in packet
+    /// mode.
+    pub fn report(&self, status: u32) {
+        if self.enabled() {
+            self.status.fetch_or(status as u8, Ordering::AcqRel);
+            self.poll_status.wake();
+        }
+    }
+
+    /// Takes the changes not read yet, if any.
+    pub fn take_status(&self) -> Option<u8> {
+        match self.status.swap(0, Ordering::AcqRel) {
+            0 => None,
+            status => Some(status),
+        }
+    }
+
+    pub fn has_status(&self) -> bool {
+        self.status.load(Ordering::Acquire) != 0
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        self.poll_status.register(waker);
+    }
+}
+
 pub struct Terminal {
     pub job_control: job::JobControl,
     pub window_size: SpinNoPreempt<WindowSize>,
     pub termios: SpinNoPreempt<Arc<termios::Termios2>>,
     pub pty_number: AtomicU32,
+    pub packet: PacketMode,
+    /// Whether output is suspended, by `VSTOP` or `TCOOFF`.
+    output_stopped: AtomicBool,
+    poll_output: PollSet,
 }
 impl Default for Terminal {
     fn default() -> Self {
@@ -37,6 +99,9 @@ impl Default for Terminal {
             }),
             termios: SpinNoPreempt::new(Arc::new(termios::Termios2::default())),
             pty_number: AtomicU32::new(0),
+            packet: PacketMode::default(),
+            output_stopped: AtomicBool::new(false),
+            poll_output: PollSet::new(),
         }
     }
 }
@@ -44,4 +109,41 @@ impl Terminal {
     pub fn load_termios(&self) -> Arc<termios::Termios2> {
         self.termios.lock().clone()
     }
+
+    pub fn output_stopped(&self) -> bool {
+        self.output_stopped.load(Ordering::Acquire)
+    }
+
+    /// Suspends or resumes output, which writers wait for.
+    pub fn set_output_stopped(&self, stopped: bool) {
+        if self.output_stopped.swap(stopped, Ordering::AcqRel) == stopped {
+            return;
+        }
+        if stopped {
+            self.packet.report(TIOCPKT_STOP);
+        } else {
+            self.packet.report(TIOCPKT_START);
+            self.poll_output.wake();
+        }
+    }
+
+    /// Registers `waker` to be woken once output is resumed.
+    pub fn register_output(&self, waker: &Waker) {
+        self.poll_output.register(waker);
+    }
+
+    /// Sets the window size, sending SIGWINCH to the foreground process group
+    /// if it changed.
+    ///
+    /// Both sides of a pty share the terminal, so a resize through the master
+    /// reaches the programs running on the slave.
+    pub fn set_window_size(&self, size: WindowSize) {
+        if core::mem::replace(&mut *self.window_size.lock(), size) == size {
+            return;
+        }
+        if let Some(pg) = self.job_control.foreground() {
+            let sig = SignalInfo::new_kernel(Signo::SIGWINCH);
+            let _ = send_signal_to_process_group(pg.pgid(), Some(sig));
+        }
+    }
 }
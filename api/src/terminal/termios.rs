@@ -2,7 +2,12 @@ use core::ops::{Deref, DerefMut};
 
 use bytemuck::AnyBitPattern;
 use linux_raw_sys::general::{
-    speed_t, tcflag_t, B38400, CREAD, CS8, ECHO, ECHOCTL, ECHOE, ECHOK, ECHOKE, ICANON, ICRNL, IEXTEN, ISIG, IXON, ONLCR, OPOST, VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VQUIT, VREPRINT, VWERASE
+    B0, B50, B75, B110, B134, B150, B200, B300, B600, B1200, B1800, B2400, B4800, B9600, B19200,
+    B38400, B57600, B115200, B230400, B460800, B500000, B576000, B921600, B1000000, B1152000,
+    B1500000, B2000000, B2500000, B3000000, B3500000, B4000000, BOTHER, CBAUD, CIBAUD, CREAD, CS8,
+    ECHO, ECHOCTL, ECHOE, ECHOK, ECHOKE, IBSHIFT, ICANON, ICRNL, IEXTEN, ISIG, IXON, ONLCR, OPOST,
+    VDISCARD, VEOF, VEOL, VEOL2, VERASE, VINTR, VKILL, VLNEXT, VQUIT, VREPRINT, VSTART, VSTOP,
+    VSUSP, VWERASE, speed_t, tcflag_t,
 };
 use starry_signal::Signo;
 
@@ -37,6 +42,9 @@ impl Default for Termios {
             (VERASE, b'\x7f'),
             (VKILL, ctl(b'U')),
             (VEOF, ctl(b'D')),
+            (VSUSP, ctl(b'Z')),
+            (VSTART, ctl(b'Q')),
+            (VSTOP, ctl(b'S')),
             (VEOL, b'\0'),
             (VREPRINT, ctl(b'R')),
             (VDISCARD, ctl(b'O')),
@@ -100,11 +108,62 @@ impl Termios {
         Some(match ch {
             ch if ch == self.special_char(VINTR) => Signo::SIGINT,
             ch if ch == self.special_char(VQUIT) => Signo::SIGQUIT,
+            ch if ch == self.special_char(VSUSP) => Signo::SIGTSTP,
             _ => return None,
         })
     }
 }
 
+/// Line speeds of the standard `Bxxx` codes, in baud.
+const BAUD_RATES: [(tcflag_t, speed_t); 31] = [
+    (B0, 0),
+    (B50, 50),
+    (B75, 75),
+    (B110, 110),
+    (B134, 134),
+    (B150, 150),
+    (B200, 200),
+    (B300, 300),
+    (B600, 600),
+    (B1200, 1200),
+    (B1800, 1800),
+    (B2400, 2400),
+    (B4800, 4800),
+    (B9600, 9600),
+    (B19200, 19200),
+    (B38400, 38400),
+    (B57600, 57600),
+    (B115200, 115200),
+    (B230400, 230400),
+    (B460800, 460800),
+    (B500000, 500000),
+    (B576000, 576000),
+    (B921600, 921600),
+    (B1000000, 1000000),
+    (B1152000, 1152000),
+    (B1500000, 1500000),
+    (B2000000, 2000000),
+    (B2500000, 2500000),
+    (B3000000, 3000000),
+    (B3500000, 3500000),
+    (B4000000, 4000000),
+];
+
+fn code_to_baud(code: tcflag_t) -> speed_t {
+    BAUD_RATES
+        .iter()
+        .find(|(it, _)| *it == code)
+        .map_or(0, |(_, baud)| *baud)
+}
+
+/// Returns the standard code for `baud`, or `BOTHER` if there is none.
+fn baud_to_code(baud: speed_t) -> tcflag_t {
+    BAUD_RATES
+        .iter()
+        .find(|(_, it)| *it == baud)
+        .map_or(BOTHER, |(code, _)| *code)
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, AnyBitPattern)]
 pub struct Termios2 {
@@ -120,11 +179,61 @@ impl Default for Termios2 {
 }
 impl Termios2 {
     pub fn new(termios: Termios) -> Self {
-        Self {
+        let mut result = Self {
             termios,
-            c_ispeed: B38400,
-            c_ospeed: B38400,
+            c_ispeed: 0,
+            c_ospeed: 0,
+        };
+        result.encode_speeds();
+        result
+    }
+
+    /// Replaces the settings with those of the legacy `termios`, which has no
+    /// room for custom speeds: `BOTHER` keeps the ones currently in effect, as
+    /// on Linux.
+    pub fn with_termios(&self, termios: Termios) -> Self {
+        let mut result = Self { termios, ..*self };
+        result.encode_speeds();
+        result
+    }
+
+    /// Returns the output speed in baud.
+    pub fn output_speed(&self) -> speed_t {
+        match self.c_cflag & CBAUD {
+            BOTHER => self.c_ospeed,
+            code => code_to_baud(code),
+        }
+    }
+
+    /// Returns the input speed in baud, which follows the output speed unless
+    /// set on its own in `CIBAUD`.
+    pub fn input_speed(&self) -> speed_t {
+        match (self.c_cflag >> IBSHIFT) & CBAUD {
+            B0 => self.output_speed(),
+            BOTHER => self.c_ispeed,
+            code => code_to_baud(code),
+        }
+    }
+
+    /// Fills `c_ispeed` and `c_ospeed` with the speeds in effect, which is
+    /// what `TCGETS2` reports whatever the codes in `c_cflag` are.
+    pub fn encode_speeds(&mut self) {
+        self.c_ispeed = self.input_speed();
+        self.c_ospeed = self.output_speed();
+    }
+
+    /// Sets the speeds, using the standard codes where they exist and
+    /// `BOTHER` otherwise.
+    pub fn set_speeds(&mut self, ispeed: speed_t, ospeed: speed_t) {
+        let split = self.c_cflag & CIBAUD != 0 || ispeed != ospeed;
+        self.termios.c_cflag &= !CBAUD;
+        self.termios.c_cflag |= baud_to_code(ospeed);
+        if split {
+            self.termios.c_cflag &= !CIBAUD;
+            self.termios.c_cflag |= baud_to_code(ispeed) << IBSHIFT;
         }
+        self.c_ispeed = ispeed;
+        self.c_ospeed = ospeed;
     }
 }
 
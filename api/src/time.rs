@@ -1,11 +1,16 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 use axerrno::{LinuxError, LinuxResult};
-use axhal::time::TimeValue;
+use axhal::{
+    percpu::this_cpu_id,
+    time::{TimeValue, monotonic_time_nanos},
+};
+use axtask::current;
 use linux_raw_sys::general::{
     __kernel_old_timespec, __kernel_old_timeval, __kernel_sock_timeval, __kernel_timespec,
     timespec, timeval,
 };
+use starry_core::task::AsThread;
 
 /// A helper trait for converting from and to `TimeValue`.
 pub trait TimeValueLike {
@@ -140,3 +145,70 @@ pub(crate) fn inc_irq_cnt() {
 pub(crate) fn irq_cnt() -> usize {
     IRQ_CNT.load(Ordering::Relaxed)
 }
+
+/// The time a CPU has spent in each state, in nanoseconds, as sampled on
+/// timer ticks.
+pub(crate) struct CpuTimes {
+    pub user: AtomicU64,
+    pub system: AtomicU64,
+    pub idle: AtomicU64,
+    /// When the last tick was accounted.
+    last_tick: AtomicU64,
+    /// The task running at the last tick.
+    last_task: AtomicU64,
+}
+
+static CPU_TIMES: [CpuTimes; axconfig::plat::CPU_NUM] = [const {
+    CpuTimes {
+        user: AtomicU64::new(0),
+        system: AtomicU64::new(0),
+        idle: AtomicU64::new(0),
+        last_tick: AtomicU64::new(0),
+        last_task: AtomicU64::new(0),
+    }
+}; axconfig::plat::CPU_NUM];
+
+/// The number of ticks that found another task running than the previous
+/// one on the same CPU.
+static CONTEXT_SWITCHES: AtomicU64 = AtomicU64::new(0);
+
+/// Accounts the time since the last tick of this CPU to the state the CPU is
+/// in now.
+///
+/// The scheduler has no hook on switches, so they are counted when a tick
+/// finds another task than the last one, which misses the tasks that ran
+/// between two ticks.
+pub(crate) fn account_tick() {
+    let times = &CPU_TIMES[this_cpu_id()];
+    let now = monotonic_time_nanos();
+    let delta = now - times.last_tick.swap(now, Ordering::Relaxed);
+
+    let curr = current();
+    let counter = if curr.is_idle() {
+        &times.idle
+    } else if curr
+        .try_as_thread()
+        .and_then(|thr| thr.time.try_borrow().ok().map(|time| time.in_user()))
+        .unwrap_or(false)
+    {
+        &times.user
+    } else {
+        &times.system
+    };
+    counter.fetch_add(delta, Ordering::Relaxed);
+
+    let id = curr.id().as_u64();
+    if times.last_task.swap(id, Ordering::Relaxed) != id {
+        CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the accounted times of `cpu`.
+pub(crate) fn cpu_times(cpu: usize) -> &'static CpuTimes {
+    &CPU_TIMES[cpu]
+}
+
+/// Returns the number of context switches seen, see [`account_tick`].
+pub(crate) fn context_switches() -> u64 {
+    CONTEXT_SWITCHES.load(Ordering::Relaxed)
+}
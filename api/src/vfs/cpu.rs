@@ -0,0 +1,211 @@
+//! CPU topology of the RK3588, exposed as `/sys/devices/system/cpu`.
+//!
+//! The SoC has four Cortex-A55 cores in one cluster and four Cortex-A76 cores
+//! in two clusters of two, all in one package and sharing the L3 cache of the
+//! DynamIQ Shared Unit. Each CPU shows its `cpu_capacity` as on Linux, 530 for
+//! the little cores and 1024 for the big ones, together with its `topology`
+//! and `cache` directories. Only the CPUs the kernel runs on are listed.
+
+#[cfg(not(target_arch = "aarch64"))]
+compile_error!("the CPU topology is only known for the RK3588");
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::ops::Range;
+
+use axfs_ng_vfs::Filesystem;
+use linux_raw_sys::general::SYSFS_MAGIC;
+use starry_core::vfs::{DirMaker, DirMapping, SimpleDir, SimpleFile, SimpleFs};
+
+/// A cache level of a core.
+struct Cache {
+    level: u32,
+    ty: &'static str,
+    size_kb: u32,
+    ways: u32,
+    /// Whether all cores share it, otherwise it is private to the core.
+    shared: bool,
+}
+
+/// A kind of core.
+struct CoreType {
+    capacity: u32,
+    caches: &'static [Cache],
+}
+
+const fn private(level: u32, ty: &'static str, size_kb: u32, ways: u32) -> Cache {
+    Cache {
+        level,
+        ty,
+        size_kb,
+        ways,
+        shared: false,
+    }
+}
+
+const L3: Cache = Cache {
+    level: 3,
+    ty: "Unified",
+    size_kb: 3072,
+    ways: 12,
+    shared: true,
+};
+
+const CORTEX_A55: CoreType = CoreType {
+    capacity: 530,
+    caches: &[
+        private(1, "Data", 32, 4),
+        private(1, "Instruction", 32, 4),
+        private(2, "Unified", 128, 4),
+        L3,
+    ],
+};
+
+const CORTEX_A76: CoreType = CoreType {
+    capacity: 1024,
+    caches: &[
+        private(1, "Data", 64, 4),
+        private(1, "Instruction", 64, 4),
+        private(2, "Unified", 512, 8),
+        L3,
+    ],
+};
+
+const CACHE_LINE_SIZE: u32 = 64;
+
+/// The clusters, by their CPUs.
+static CLUSTERS: [(Range<usize>, &CoreType); 3] = [
+    (0..4, &CORTEX_A55),
+    (4..6, &CORTEX_A76),
+    (6..8, &CORTEX_A76),
+];
+const ALL_CPUS: Range<usize> = 0..8;
+
+const CPU_NUM: usize = axconfig::plat::CPU_NUM;
+
+/// Returns the index of the cluster of `cpu`, its CPUs and their type.
+fn cluster_of(cpu: usize) -> (usize, &'static Range<usize>, &'static CoreType) {
+    CLUSTERS
+        .iter()
+        .enumerate()
+        .find(|(_, (cpus, _))| cpus.contains(&cpu))
+        .map(|(id, (cpus, core))| (id, cpus, *core))
+        .expect("unknown CPU")
+}
+
+/// Returns the capacity of every online CPU.
+pub fn cpu_capacity() -> Vec<u32> {
+    (0..CPU_NUM).map(|cpu| cluster_of(cpu).2.capacity).collect()
+}
+
+/// Formats the CPUs in `cpus` that are online as a list like `4-5`.
+fn cpu_list(cpus: Range<usize>) -> String {
+    let cpus = cpus.start..cpus.end.min(CPU_NUM);
+    let list = if cpus.len() > 1 {
+        format!("{}-{}", cpus.start, cpus.end - 1)
+    } else {
+        format!("{}", cpus.start)
+    };
+    list + "\n"
+}
+
+/// Formats the CPUs in `cpus` that are online as a hexadecimal mask.
+fn cpu_map(cpus: Range<usize>) -> String {
+    let mask = cpus
+        .filter(|cpu| *cpu < CPU_NUM)
+        .fold(0u64, |mask, cpu| mask | (1 << cpu));
+    format!("{:0width$x}\n", mask, width = CPU_NUM.div_ceil(4))
+}
+
+fn static_file(fs: &Arc<SimpleFs>, content: String) -> Arc<SimpleFile> {
+    SimpleFile::new_regular(fs.clone(), move || Ok(content.clone()))
+}
+
+fn cache_dir(fs: &Arc<SimpleFs>, cpu: usize, cache: &Cache) -> DirMaker {
+    let shared = if cache.shared { ALL_CPUS } else { cpu..cpu + 1 };
+    let sets = cache.size_kb * 1024 / CACHE_LINE_SIZE / cache.ways;
+    let id = if cache.shared { 0 } else { cpu };
+
+    let mut dir = DirMapping::new();
+    let files = [
+        ("id", format!("{id}\n")),
+        ("level", format!("{}\n", cache.level)),
+        ("type", format!("{}\n", cache.ty)),
+        ("size", format!("{}K\n", cache.size_kb)),
+        ("coherency_line_size", format!("{CACHE_LINE_SIZE}\n")),
+        ("ways_of_associativity", format!("{}\n", cache.ways)),
+        ("number_of_sets", format!("{sets}\n")),
+        ("shared_cpu_list", cpu_list(shared.clone())),
+        ("shared_cpu_map", cpu_map(shared)),
+    ];
+    for (name, content) in files {
+        dir.add(name, static_file(fs, content));
+    }
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+fn cpu_dir(fs: &Arc<SimpleFs>, cpu: usize) -> DirMaker {
+    let (cluster_id, cluster, core) = cluster_of(cpu);
+
+    let mut topology = DirMapping::new();
+    let files = [
+        ("physical_package_id", String::from("0\n")),
+        ("cluster_id", format!("{cluster_id}\n")),
+        ("core_id", format!("{}\n", cpu - cluster.start)),
+        ("thread_siblings_list", cpu_list(cpu..cpu + 1)),
+        ("thread_siblings", cpu_map(cpu..cpu + 1)),
+        ("core_cpus_list", cpu_list(cpu..cpu + 1)),
+        ("core_cpus", cpu_map(cpu..cpu + 1)),
+        ("cluster_cpus_list", cpu_list(cluster.clone())),
+        ("cluster_cpus", cpu_map(cluster.clone())),
+        ("core_siblings_list", cpu_list(ALL_CPUS)),
+        ("core_siblings", cpu_map(ALL_CPUS)),
+        ("package_cpus_list", cpu_list(ALL_CPUS)),
+        ("package_cpus", cpu_map(ALL_CPUS)),
+    ];
+    for (name, content) in files {
+        topology.add(name, static_file(fs, content));
+    }
+
+    let mut cache = DirMapping::new();
+    for (index, info) in core.caches.iter().enumerate() {
+        cache.add(format!("index{index}"), cache_dir(fs, cpu, info));
+    }
+
+    let mut dir = DirMapping::new();
+    dir.add("online", static_file(fs, String::from("1\n")));
+    dir.add(
+        "cpu_capacity",
+        static_file(fs, format!("{}\n", core.capacity)),
+    );
+    dir.add(
+        "topology",
+        SimpleDir::new_maker(fs.clone(), Arc::new(topology)),
+    );
+    dir.add("cache", SimpleDir::new_maker(fs.clone(), Arc::new(cache)));
+    #[cfg(feature = "cpufreq")]
+    if cpu == 0 {
+        // This is mounted to the cpufreq filesystem in `mount_all`
+        dir.add(
+            "cpufreq",
+            SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
+        );
+    }
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir))
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+    for name in ["online", "possible", "present"] {
+        root.add(name, static_file(&fs, cpu_list(0..CPU_NUM)));
+    }
+    root.add("offline", static_file(&fs, String::from("\n")));
+    for cpu in 0..CPU_NUM {
+        root.add(format!("cpu{cpu}"), cpu_dir(&fs, cpu));
+    }
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
+
+/// Creates the `/sys/devices/system/cpu` directory.
+pub fn new_cpufs() -> Filesystem {
+    SimpleFs::new_with(String::from("sysfs"), SYSFS_MAGIC, builder)
+}
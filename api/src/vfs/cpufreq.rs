@@ -0,0 +1,285 @@
+//! CPU frequency scaling of the RK3588, exposed as
+//! `/sys/devices/system/cpu/cpu0/cpufreq`.
+//!
+//! The CPU clocks are owned by the trusted firmware and changed through SCMI
+//! requests over the SMC transport, the same way Linux does it, using the
+//! channel described by the `arm,scmi-smc` node of the device tree. Only the
+//! `userspace` governor is provided: the frequency changes when a new one is
+//! written to `scaling_setspeed`.
+
+#[cfg(not(target_arch = "aarch64"))]
+compile_error!("cpufreq is only supported on the RK3588");
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::arch::asm;
+
+use axerrno::AxError;
+use axfs_ng_vfs::{Filesystem, VfsError, VfsResult};
+use axhal::{mem::phys_to_virt, paging::MappingFlags};
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::SYSFS_MAGIC;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr};
+use spin::Once;
+use starry_core::{
+    cmdline::device_tree,
+    vfs::{DirMaker, DirMapping, RwFile, SimpleDir, SimpleFile, SimpleFileOperation, SimpleFs},
+};
+
+/// The SCMI channel to the firmware.
+struct ScmiChannel {
+    /// Function ID of the SMC transport.
+    smc_id: usize,
+    /// Shared memory of the channel.
+    shmem: PhysAddr,
+}
+
+/// The channel, found by [`new_cpufreqfs`].
+static SCMI: Once<ScmiChannel> = Once::new();
+
+const SHMEM_CHANNEL_STATUS: usize = 0x04;
+const SHMEM_FLAGS: usize = 0x10;
+const SHMEM_LENGTH: usize = 0x14;
+const SHMEM_MSG_HEADER: usize = 0x18;
+const SHMEM_PAYLOAD: usize = 0x1c;
+
+const CHANNEL_FREE: u32 = 1 << 0;
+const CHANNEL_ERROR: u32 = 1 << 1;
+
+const SCMI_PROTOCOL_CLOCK: u32 = 0x14;
+const CLOCK_RATE_SET: u32 = 0x5;
+const CLOCK_RATE_GET: u32 = 0x6;
+
+const SCMI_SUCCESS: i32 = 0;
+const SCMI_INVALID_PARAMETERS: i32 = -2;
+
+/// `SCMI_CLK_CPUL`, the clock of the Cortex-A55 cluster cpu0 belongs to.
+const CLOCK_CPUL: u32 = 0;
+
+/// Operating points of the Cortex-A55 cluster in kHz.
+///
+/// Only those running at the lowest voltage of the OPP table (675 mV) are
+/// listed, as higher ones need the regulator raised first, which is not done
+/// here.
+const FREQUENCIES: &[u32] = &[408_000, 600_000, 816_000, 1_008_000];
+
+/// Serializes the use of the shared memory.
+static CHANNEL: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn channel() -> &'static ScmiChannel {
+    SCMI.get()
+        .expect("cpufreq is only registered once the SCMI channel is found")
+}
+
+fn shmem(offset: usize) -> *mut u32 {
+    phys_to_virt(channel().shmem + offset).as_mut_ptr() as *mut u32
+}
+
+/// Reads the SCMI channel from the `arm,scmi-smc` node of the device tree.
+fn find_scmi_channel() -> Option<ScmiChannel> {
+    let fdt = device_tree()?;
+    let scmi = fdt.find_compatible(&["arm,scmi-smc"])?;
+    let smc_id = scmi.property("arm,smc-id")?.as_usize()?;
+    let phandle = scmi.property("shmem")?.as_usize()?;
+    let shmem = fdt.find_phandle(phandle as u32)?;
+    let offset = shmem.reg()?.next()?.starting_address as usize;
+    // The shared memory is a region of an SRAM node, whose children are
+    // addressed from the start of the SRAM through its `ranges`.
+    let base = fdt
+        .all_nodes()
+        .find(|node| {
+            node.children().any(|child| {
+                child.property("phandle").and_then(|it| it.as_usize()) == Some(phandle)
+            })
+        })
+        .filter(|parent| {
+            parent
+                .property("ranges")
+                .is_some_and(|it| !it.value.is_empty())
+        })
+        .and_then(|parent| parent.reg()?.next())
+        .map_or(0, |reg| reg.starting_address as usize);
+    Some(ScmiChannel {
+        smc_id,
+        shmem: PhysAddr::from(base + offset),
+    })
+}
+
+/// Sends a clock protocol message and waits for its response, whose payload
+/// after the status is copied to `response`.
+fn scmi_call(msg_id: u32, params: &[u32], response: &mut [u32]) -> VfsResult<()> {
+    let _guard = CHANNEL.lock();
+    // SAFETY: the shared memory is mapped by `new_cpufreqfs`, and the firmware
+    // only touches it during the SMC below
+    let status = unsafe {
+        shmem(SHMEM_CHANNEL_STATUS).write_volatile(0);
+        shmem(SHMEM_FLAGS).write_volatile(0);
+        shmem(SHMEM_LENGTH).write_volatile(4 + 4 * params.len() as u32);
+        shmem(SHMEM_MSG_HEADER).write_volatile(msg_id | (SCMI_PROTOCOL_CLOCK << 10));
+        for (i, param) in params.iter().enumerate() {
+            shmem(SHMEM_PAYLOAD + 4 * i).write_volatile(*param);
+        }
+        asm!("smc #0", inout("x0") channel().smc_id => _, clobber_abi("C"));
+        shmem(SHMEM_CHANNEL_STATUS).read_volatile()
+    };
+    if status & CHANNEL_FREE == 0 || status & CHANNEL_ERROR != 0 {
+        warn!("SCMI channel error, status {:#x}", status);
+        return Err(VfsError::EIO);
+    }
+
+    // SAFETY: see above
+    match unsafe { shmem(SHMEM_PAYLOAD).read_volatile() } as i32 {
+        SCMI_SUCCESS => {}
+        SCMI_INVALID_PARAMETERS => return Err(VfsError::EINVAL),
+        err => {
+            warn!("SCMI message {:#x} failed: {}", msg_id, err);
+            return Err(VfsError::EIO);
+        }
+    }
+    for (i, value) in response.iter_mut().enumerate() {
+        // SAFETY: see above
+        *value = unsafe { shmem(SHMEM_PAYLOAD + 4 * (i + 1)).read_volatile() };
+    }
+    Ok(())
+}
+
+/// Returns the current frequency in kHz.
+fn current_freq() -> VfsResult<u32> {
+    let mut rate = [0; 2];
+    scmi_call(CLOCK_RATE_GET, &[CLOCK_CPUL], &mut rate)?;
+    let hz = rate[0] as u64 | ((rate[1] as u64) << 32);
+    Ok((hz / 1000) as u32)
+}
+
+/// Switches to the lowest operating point at or above `khz`, like the
+/// `userspace` governor of Linux.
+fn set_freq(khz: u32) -> VfsResult<()> {
+    let khz = FREQUENCIES
+        .iter()
+        .copied()
+        .find(|it| *it >= khz)
+        .unwrap_or(*FREQUENCIES.last().unwrap());
+    let hz = khz as u64 * 1000;
+    scmi_call(
+        CLOCK_RATE_SET,
+        &[0, CLOCK_CPUL, hz as u32, (hz >> 32) as u32],
+        &mut [],
+    )?;
+    info!("cpu0 frequency set to {} kHz", khz);
+    Ok(())
+}
+
+fn parse_khz(data: &[u8]) -> VfsResult<u32> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|it| it.trim().parse().ok())
+        .ok_or(VfsError::EINVAL)
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let min = FREQUENCIES[0];
+    let max = *FREQUENCIES.last().unwrap();
+
+    let mut root = DirMapping::new();
+    for name in ["cpuinfo_min_freq", "scaling_min_freq"] {
+        root.add(
+            name,
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{min}\n"))),
+        );
+    }
+    for name in ["cpuinfo_max_freq", "scaling_max_freq"] {
+        root.add(
+            name,
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{max}\n"))),
+        );
+    }
+    for name in ["cpuinfo_cur_freq", "scaling_cur_freq"] {
+        root.add(
+            name,
+            SimpleFile::new_regular(fs.clone(), || Ok(format!("{}\n", current_freq()?))),
+        );
+    }
+    root.add(
+        "scaling_available_frequencies",
+        SimpleFile::new_regular(fs.clone(), || {
+            let freqs = FREQUENCIES
+                .iter()
+                .map(|it| format!("{it}"))
+                .collect::<Vec<_>>();
+            Ok(freqs.join(" ") + "\n")
+        }),
+    );
+    root.add(
+        "scaling_driver",
+        SimpleFile::new_regular(fs.clone(), || Ok("scmi\n")),
+    );
+    root.add(
+        "scaling_available_governors",
+        SimpleFile::new_regular(fs.clone(), || Ok("userspace\n")),
+    );
+    root.add(
+        "scaling_governor",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some("userspace\n")),
+                SimpleFileOperation::Write(data) => {
+                    if !data.is_empty() && data.trim_ascii() != b"userspace" {
+                        return Err(VfsError::EINVAL);
+                    }
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    root.add(
+        "scaling_setspeed",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => {
+                    Ok(Some(format!("{}\n", current_freq()?).into_bytes()))
+                }
+                SimpleFileOperation::Write(data) => {
+                    if !data.is_empty() {
+                        set_freq(parse_khz(data)?)?;
+                    }
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
+
+/// Creates the `cpufreq` directory of cpu0, or returns `None` if the SCMI
+/// channel cannot be used.
+pub fn new_cpufreqfs() -> Option<Filesystem> {
+    let Some(channel) = find_scmi_channel() else {
+        warn!("No SCMI channel in the device tree, cpufreq disabled");
+        return None;
+    };
+    let paddr = channel.shmem.align_down_4k();
+    match axmm::kernel_aspace().lock().map_linear(
+        phys_to_virt(paddr),
+        paddr,
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
+    ) {
+        // Already mapped if the platform lists it among its MMIO regions.
+        Ok(()) | Err(AxError::AlreadyExists) => {}
+        Err(err) => {
+            warn!(
+                "Failed to map SCMI shared memory: {:?}, cpufreq disabled",
+                err
+            );
+            return None;
+        }
+    }
+    SCMI.call_once(|| channel);
+    Some(SimpleFs::new_with(
+        String::from("sysfs"),
+        SYSFS_MAGIC,
+        builder,
+    ))
+}
@@ -0,0 +1,224 @@
+//! `/proc/cpuinfo`, in the format of Linux for each architecture.
+//!
+//! The identification and feature registers are read on the CPU doing the
+//! read and shown for every CPU. RISC-V has no such register readable from
+//! supervisor mode, so there the ISA is the one the kernel is built for.
+
+use alloc::{format, string::String, vec::Vec};
+use core::fmt::Write;
+
+/// Formats `/proc/cpuinfo`, with one entry per CPU, as
+/// `sysconf(_SC_NPROCESSORS_CONF)` counts them in some C libraries.
+pub fn render() -> String {
+    let info = arch_info();
+    let mut result = String::new();
+    for cpu in 0..axconfig::plat::CPU_NUM {
+        let _ = writeln!(result, "processor\t: {cpu}");
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        let _ = writeln!(result, "hart\t\t: {cpu}");
+        let _ = writeln!(result, "{info}");
+    }
+    result
+}
+
+/// Joins the names of the features whose bits are set in `value`.
+#[cfg(any(target_arch = "x86_64", target_arch = "loongarch64"))]
+fn features(value: u64, bits: &[(u32, &'static str)]) -> Vec<&'static str> {
+    bits.iter()
+        .filter(|(bit, _)| value & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+#[cfg(target_arch = "x86_64")]
+fn arch_info() -> String {
+    use core::arch::x86_64::__cpuid;
+
+    /// The features in `edx` and `ecx` of leaf 1.
+    const EDX_FEATURES: &[(u32, &str)] = &[
+        (0, "fpu"),
+        (4, "tsc"),
+        (5, "msr"),
+        (6, "pae"),
+        (8, "cx8"),
+        (9, "apic"),
+        (11, "sep"),
+        (15, "cmov"),
+        (16, "pat"),
+        (19, "clflush"),
+        (23, "mmx"),
+        (24, "fxsr"),
+        (25, "sse"),
+        (26, "sse2"),
+        (28, "ht"),
+    ];
+    const ECX_FEATURES: &[(u32, &str)] = &[
+        (0, "pni"),
+        (1, "pclmulqdq"),
+        (9, "ssse3"),
+        (12, "fma"),
+        (13, "cx16"),
+        (19, "sse4_1"),
+        (20, "sse4_2"),
+        (21, "x2apic"),
+        (22, "movbe"),
+        (23, "popcnt"),
+        (25, "aes"),
+        (26, "xsave"),
+        (28, "avx"),
+        (30, "rdrand"),
+        (31, "hypervisor"),
+    ];
+
+    #[allow(unused_unsafe)]
+    let cpuid = |leaf| unsafe { __cpuid(leaf) };
+    let regs_str = |regs: &[u32]| -> String {
+        let bytes = regs
+            .iter()
+            .flat_map(|it| it.to_le_bytes())
+            .collect::<Vec<_>>();
+        String::from_utf8_lossy(&bytes)
+            .trim_matches(['\0', ' '])
+            .into()
+    };
+
+    let leaf0 = cpuid(0);
+    let vendor = regs_str(&[leaf0.ebx, leaf0.edx, leaf0.ecx]);
+    let leaf1 = cpuid(1);
+    let base_family = (leaf1.eax >> 8) & 0xf;
+    let mut family = base_family;
+    let mut model = (leaf1.eax >> 4) & 0xf;
+    if base_family == 0xf {
+        family += (leaf1.eax >> 20) & 0xff;
+    }
+    if base_family == 0x6 || base_family == 0xf {
+        model |= ((leaf1.eax >> 16) & 0xf) << 4;
+    }
+    let name = if cpuid(0x8000_0000).eax >= 0x8000_0004 {
+        let regs = (0x8000_0002..=0x8000_0004)
+            .map(cpuid)
+            .flat_map(|it| [it.eax, it.ebx, it.ecx, it.edx])
+            .collect::<Vec<_>>();
+        regs_str(&regs)
+    } else {
+        String::from("unknown")
+    };
+    let mut flags = features(leaf1.edx as u64, EDX_FEATURES);
+    flags.extend(features(leaf1.ecx as u64, ECX_FEATURES));
+
+    format!(
+        "vendor_id\t: {vendor}\ncpu family\t: {family}\nmodel\t\t: {model}\nmodel name\t: \
+         {name}\nstepping\t: {}\nflags\t\t: {}\n",
+        leaf1.eax & 0xf,
+        flags.join(" ")
+    )
+}
+
+#[cfg(target_arch = "aarch64")]
+fn arch_info() -> String {
+    use core::arch::asm;
+
+    /// The features of `ID_AA64ISAR0_EL1`, by the lowest bit of their field
+    /// and the minimum value.
+    const ISAR0_FEATURES: &[(u32, u64, &str)] = &[
+        (4, 1, "aes"),
+        (4, 2, "pmull"),
+        (8, 1, "sha1"),
+        (12, 1, "sha2"),
+        (16, 1, "crc32"),
+        (20, 2, "atomics"),
+        (28, 1, "asimdrdm"),
+        (44, 1, "asimddp"),
+    ];
+
+    let (midr, pfr0, isar0, freq): (u64, u64, u64, u64);
+    // SAFETY: the identification registers are readable at EL1.
+    unsafe {
+        asm!("mrs {}, midr_el1", out(reg) midr);
+        asm!("mrs {}, id_aa64pfr0_el1", out(reg) pfr0);
+        asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0);
+        asm!("mrs {}, cntfrq_el0", out(reg) freq);
+    }
+    let field = |reg: u64, shift: u32| (reg >> shift) & 0xf;
+
+    let mut flags = Vec::new();
+    // A field of all ones means that the unit is missing.
+    if field(pfr0, 16) != 0xf {
+        flags.push("fp");
+    }
+    if field(pfr0, 20) != 0xf {
+        flags.push("asimd");
+    }
+    flags.extend(
+        ISAR0_FEATURES
+            .iter()
+            .filter(|(shift, min, _)| field(isar0, *shift) >= *min)
+            .map(|(.., name)| *name),
+    );
+
+    // Linux derives the BogoMIPS of arm64 from the frequency of the timer.
+    format!(
+        "BogoMIPS\t: {}.{:02}\nFeatures\t: {}\nCPU implementer\t: {:#04x}\nCPU architecture: \
+         8\nCPU variant\t: {:#x}\nCPU part\t: {:#05x}\nCPU revision\t: {}\n",
+        freq / 500_000,
+        freq / 5_000 % 100,
+        flags.join(" "),
+        (midr >> 24) & 0xff,
+        field(midr, 20),
+        (midr >> 4) & 0xfff,
+        field(midr, 0),
+    )
+}
+
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+fn arch_info() -> String {
+    let mut isa = String::from(if cfg!(target_arch = "riscv64") {
+        "rv64i"
+    } else {
+        "rv32i"
+    });
+    for (enabled, ext) in [
+        (cfg!(target_feature = "m"), 'm'),
+        (cfg!(target_feature = "a"), 'a'),
+        (cfg!(target_feature = "f"), 'f'),
+        (cfg!(target_feature = "d"), 'd'),
+        (cfg!(target_feature = "c"), 'c'),
+    ] {
+        if enabled {
+            isa.push(ext);
+        }
+    }
+    let mmu = if cfg!(target_arch = "riscv64") {
+        "sv39"
+    } else {
+        "sv32"
+    };
+    format!("isa\t\t: {isa}\nmmu\t\t: {mmu}\n")
+}
+
+#[cfg(target_arch = "loongarch64")]
+fn arch_info() -> String {
+    use core::arch::asm;
+
+    /// The features in words 1 and 2 of `cpucfg`.
+    const WORD1_FEATURES: &[(u32, &str)] = &[(20, "ual"), (25, "crc32")];
+    const WORD2_FEATURES: &[(u32, &str)] = &[(0, "fpu"), (6, "lsx"), (7, "lasx"), (22, "lam")];
+
+    let cpucfg = |word: usize| -> u64 {
+        let value: usize;
+        // SAFETY: `cpucfg` only reads the configuration of the CPU.
+        unsafe { asm!("cpucfg {}, {}", out(reg) value, in(reg) word) };
+        value as u64
+    };
+    let prid = cpucfg(0);
+    let mut flags = Vec::from(["cpucfg"]);
+    flags.extend(features(cpucfg(1), WORD1_FEATURES));
+    flags.extend(features(cpucfg(2), WORD2_FEATURES));
+
+    format!(
+        "CPU Family\t\t: Loongson-64bit\nPRID\t\t\t: {prid:#010x}\nCPU Revision\t\t: \
+         {:#04x}\nFeatures\t\t: {}\n",
+        prid & 0xff,
+        flags.join(" ")
+    )
+}
@@ -0,0 +1,803 @@
+//! A minimal DRM/KMS device, `/dev/dri/card0`, backed by the display.
+//!
+//! It has a single CRTC, encoder and connector, with the mode of the display,
+//! and only dumb buffers. Scanning out a framebuffer copies it to the display,
+//! which is what `SETCRTC`, `PAGE_FLIP` and `DIRTYFB` do.
+
+use alloc::{collections::VecDeque, format, vec::Vec};
+use core::{any::Any, slice, task::Context};
+
+use axalloc::global_allocator;
+use axdriver::prelude::DisplayDriverOps;
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+use axhal::{mem::virt_to_phys, time::monotonic_time};
+use axio::{IoEvents, PollSet, Pollable};
+use axmm::backend::Backend;
+use axsync::Mutex;
+use bytemuck::AnyBitPattern;
+use hashbrown::HashMap;
+use memory_addr::{PAGE_SIZE_4K, PhysAddrRange, VirtAddr, align_up_4k};
+use starry_core::{
+    task::processes,
+    vfs::{DeviceMmap, DeviceOps},
+};
+use starry_vm::{VmMutPtr, VmPtr, vm_write_slice};
+use zerocopy::{Immutable, IntoBytes};
+
+const DRM_IOCTL_VERSION: u32 = 0xc040_6400;
+const DRM_IOCTL_GEM_CLOSE: u32 = 0x4008_6409;
+const DRM_IOCTL_GET_CAP: u32 = 0xc010_640c;
+const DRM_IOCTL_SET_CLIENT_CAP: u32 = 0x4010_640d;
+const DRM_IOCTL_SET_MASTER: u32 = 0x641e;
+const DRM_IOCTL_DROP_MASTER: u32 = 0x641f;
+const DRM_IOCTL_MODE_GETRESOURCES: u32 = 0xc040_64a0;
+const DRM_IOCTL_MODE_GETCRTC: u32 = 0xc068_64a1;
+const DRM_IOCTL_MODE_SETCRTC: u32 = 0xc068_64a2;
+const DRM_IOCTL_MODE_GETENCODER: u32 = 0xc014_64a6;
+const DRM_IOCTL_MODE_GETCONNECTOR: u32 = 0xc050_64a7;
+const DRM_IOCTL_MODE_ADDFB: u32 = 0xc01c_64ae;
+const DRM_IOCTL_MODE_RMFB: u32 = 0xc004_64af;
+const DRM_IOCTL_MODE_PAGE_FLIP: u32 = 0xc018_64b0;
+const DRM_IOCTL_MODE_DIRTYFB: u32 = 0xc018_64b1;
+const DRM_IOCTL_MODE_CREATE_DUMB: u32 = 0xc020_64b2;
+const DRM_IOCTL_MODE_MAP_DUMB: u32 = 0xc010_64b3;
+const DRM_IOCTL_MODE_DESTROY_DUMB: u32 = 0xc004_64b4;
+const DRM_IOCTL_MODE_ADDFB2: u32 = 0xc068_64b8;
+
+const DRM_CAP_DUMB_BUFFER: u64 = 0x1;
+const DRM_CAP_VBLANK_HIGH_CRTC: u64 = 0x2;
+const DRM_CAP_DUMB_PREFERRED_DEPTH: u64 = 0x3;
+const DRM_CAP_DUMB_PREFER_SHADOW: u64 = 0x4;
+const DRM_CAP_PRIME: u64 = 0x5;
+const DRM_CAP_TIMESTAMP_MONOTONIC: u64 = 0x6;
+const DRM_CAP_ASYNC_PAGE_FLIP: u64 = 0x7;
+const DRM_CAP_CURSOR_WIDTH: u64 = 0x8;
+const DRM_CAP_CURSOR_HEIGHT: u64 = 0x9;
+const DRM_CAP_ADDFB2_MODIFIERS: u64 = 0x10;
+
+const DRM_MODE_PAGE_FLIP_EVENT: u32 = 0x01;
+const DRM_MODE_PAGE_FLIP_ASYNC: u32 = 0x02;
+const DRM_EVENT_FLIP_COMPLETE: u32 = 0x02;
+
+const DRM_MODE_CONNECTOR_VIRTUAL: u32 = 15;
+const DRM_MODE_ENCODER_VIRTUAL: u32 = 5;
+const DRM_MODE_CONNECTED: u32 = 1;
+const DRM_MODE_SUBPIXEL_UNKNOWN: u32 = 1;
+const DRM_MODE_TYPE_PREFERRED: u32 = 1 << 3;
+const DRM_MODE_TYPE_DRIVER: u32 = 1 << 6;
+
+const DRM_FORMAT_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+const DRM_FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+
+const CRTC_ID: u32 = 31;
+const ENCODER_ID: u32 = 32;
+const CONNECTOR_ID: u32 = 33;
+/// Framebuffers get IDs from here on, apart from the other objects.
+const FIRST_FB_ID: u32 = 64;
+
+/// How many screens the memory for dumb buffers can hold.
+const POOL_SCREENS: usize = 4;
+/// How many bytes of events may wait to be read, as on Linux.
+const EVENT_SPACE: usize = 4096;
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmVersion {
+    major: i32,
+    minor: i32,
+    patchlevel: i32,
+    name_len: u64,
+    name: u64,
+    date_len: u64,
+    date: u64,
+    desc_len: u64,
+    desc: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmGetCap {
+    capability: u64,
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeCardRes {
+    fb_id_ptr: u64,
+    crtc_id_ptr: u64,
+    connector_id_ptr: u64,
+    encoder_id_ptr: u64,
+    count_fbs: u32,
+    count_crtcs: u32,
+    count_connectors: u32,
+    count_encoders: u32,
+    min_width: u32,
+    max_width: u32,
+    min_height: u32,
+    max_height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeModeInfo {
+    clock: u32,
+    hdisplay: u16,
+    hsync_start: u16,
+    hsync_end: u16,
+    htotal: u16,
+    hskew: u16,
+    vdisplay: u16,
+    vsync_start: u16,
+    vsync_end: u16,
+    vtotal: u16,
+    vscan: u16,
+    vrefresh: u32,
+    flags: u32,
+    type_: u32,
+    name: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeCrtc {
+    set_connectors_ptr: u64,
+    count_connectors: u32,
+    crtc_id: u32,
+    fb_id: u32,
+    x: u32,
+    y: u32,
+    gamma_size: u32,
+    mode_valid: u32,
+    mode: DrmModeModeInfo,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeGetEncoder {
+    encoder_id: u32,
+    encoder_type: u32,
+    crtc_id: u32,
+    possible_crtcs: u32,
+    possible_clones: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeGetConnector {
+    encoders_ptr: u64,
+    modes_ptr: u64,
+    props_ptr: u64,
+    prop_values_ptr: u64,
+    count_modes: u32,
+    count_props: u32,
+    count_encoders: u32,
+    encoder_id: u32,
+    connector_id: u32,
+    connector_type: u32,
+    connector_type_id: u32,
+    connection: u32,
+    mm_width: u32,
+    mm_height: u32,
+    subpixel: u32,
+    pad: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeFbCmd {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    bpp: u32,
+    depth: u32,
+    handle: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeFbCmd2 {
+    fb_id: u32,
+    width: u32,
+    height: u32,
+    pixel_format: u32,
+    flags: u32,
+    handles: [u32; 4],
+    pitches: [u32; 4],
+    offsets: [u32; 4],
+    modifier: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeCrtcPageFlip {
+    crtc_id: u32,
+    fb_id: u32,
+    flags: u32,
+    reserved: u32,
+    user_data: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeFbDirtyCmd {
+    fb_id: u32,
+    flags: u32,
+    color: u32,
+    num_clips: u32,
+    clips_ptr: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeCreateDumb {
+    height: u32,
+    width: u32,
+    bpp: u32,
+    flags: u32,
+    handle: u32,
+    pitch: u32,
+    size: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct DrmModeMapDumb {
+    handle: u32,
+    pad: u32,
+    offset: u64,
+}
+
+/// `struct drm_event_vblank`.
+#[repr(C)]
+#[derive(Clone, Copy, IntoBytes, Immutable)]
+struct DrmEventVblank {
+    type_: u32,
+    length: u32,
+    user_data: u64,
+    tv_sec: u32,
+    tv_usec: u32,
+    sequence: u32,
+    crtc_id: u32,
+}
+
+/// The mode of the display.
+fn display_mode() -> DrmModeModeInfo {
+    let info = axdisplay::main_display().info();
+    let (width, height) = (info.width as u16, info.height as u16);
+    // Made-up blanking, as the display does not tell.
+    let (htotal, vtotal) = (width + 160, height + 35);
+    let mut name = [0; 32];
+    let text = format!("{width}x{height}");
+    name[..text.len()].copy_from_slice(text.as_bytes());
+    DrmModeModeInfo {
+        clock: htotal as u32 * vtotal as u32 * 60 / 1000,
+        hdisplay: width,
+        hsync_start: width + 48,
+        hsync_end: width + 80,
+        htotal,
+        hskew: 0,
+        vdisplay: height,
+        vsync_start: height + 3,
+        vsync_end: height + 9,
+        vtotal,
+        vscan: 0,
+        vrefresh: 60,
+        flags: 0,
+        type_: DRM_MODE_TYPE_PREFERRED | DRM_MODE_TYPE_DRIVER,
+        name,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct DumbBuffer {
+    /// Where it starts in the pool.
+    offset: usize,
+    size: usize,
+}
+
+/// Whether some process still maps part of `buffer`, in the pool at `pool`.
+///
+/// Mappings of the device are linear, so every one covers the contiguous
+/// physical memory its first page is at.
+fn is_mapped(pool: VirtAddr, buffer: &DumbBuffer) -> bool {
+    let start = virt_to_phys(pool) + buffer.offset;
+    let end = start + buffer.size;
+    processes().iter().any(|proc_data| {
+        let aspace = proc_data.aspace.lock();
+        aspace
+            .areas()
+            .filter(|area| matches!(area.backend(), Backend::Linear(_)))
+            .any(|area| {
+                aspace
+                    .page_table()
+                    .query(area.start())
+                    .is_ok_and(|(paddr, ..)| paddr < end && start < paddr + area.size())
+            })
+    })
+}
+
+struct Framebuffer {
+    handle: u32,
+    width: u32,
+    height: u32,
+    pitch: u32,
+    /// Where the pixels start in the buffer.
+    offset: u32,
+}
+
+struct DrmState {
+    /// The memory dumb buffers are allocated from, which is never freed, as
+    /// it may be mapped.
+    pool: Option<VirtAddr>,
+    buffers: HashMap<u32, DumbBuffer>,
+    /// Destroyed buffers whose memory is not reused until nobody maps it.
+    retired: Vec<DumbBuffer>,
+    next_handle: u32,
+    fbs: HashMap<u32, Framebuffer>,
+    next_fb_id: u32,
+    /// The framebuffer shown.
+    scanout: Option<u32>,
+    events: VecDeque<DrmEventVblank>,
+    sequence: u32,
+}
+
+impl DrmState {
+    fn pool_size() -> usize {
+        align_up_4k(axdisplay::main_display().info().fb_size) * POOL_SCREENS
+    }
+
+    /// Finds room for `size` bytes in the pool.
+    fn alloc(&mut self, size: usize) -> LinuxResult<usize> {
+        if self.pool.is_none() {
+            let pages = Self::pool_size() / PAGE_SIZE_4K;
+            let pool = global_allocator()
+                .alloc_pages(pages, PAGE_SIZE_4K)
+                .map_err(|_| LinuxError::ENOMEM)?;
+            self.pool = Some(VirtAddr::from(pool));
+        }
+        let mut used = self
+            .buffers
+            .values()
+            .chain(&self.retired)
+            .map(|it| (it.offset, it.offset + it.size))
+            .collect::<Vec<_>>();
+        used.sort_unstable();
+        let mut start = 0;
+        for (begin, end) in used {
+            if begin - start >= size {
+                break;
+            }
+            start = end;
+        }
+        if Self::pool_size() - start < size {
+            return Err(LinuxError::ENOMEM);
+        }
+        Ok(start)
+    }
+
+    /// Copies the framebuffer `fb_id` to the display, from `(x, y)` on.
+    fn show(&self, fb_id: u32, x: u32, y: u32) -> LinuxResult<()> {
+        let fb = self.fbs.get(&fb_id).ok_or(LinuxError::ENOENT)?;
+        if x >= fb.width || y >= fb.height {
+            return Err(LinuxError::EINVAL);
+        }
+        let (Some(pool), Some(buffer)) = (self.pool, self.buffers.get(&fb.handle)) else {
+            // The buffer is gone, so nothing changes.
+            return Ok(());
+        };
+        let display = axdisplay::main_display();
+        let info = display.info();
+        let line_length = info.fb_size / info.height as usize;
+        // SAFETY: the pool and the framebuffer of the display are allocated
+        // for the lifetime of the kernel, and checked against the sizes
+        // given when framebuffers are added
+        let (src, dst) = unsafe {
+            (
+                slice::from_raw_parts(pool.as_ptr().add(buffer.offset), buffer.size),
+                slice::from_raw_parts_mut(info.fb_base_vaddr as *mut u8, info.fb_size),
+            )
+        };
+        let width = (fb.width - x).min(info.width) as usize * 4;
+        let height = (fb.height - y).min(info.height) as usize;
+        let first = fb.offset as usize + y as usize * fb.pitch as usize + x as usize * 4;
+        for row in 0..height {
+            let start = first + row * fb.pitch as usize;
+            dst[row * line_length..][..width].copy_from_slice(&src[start..][..width]);
+        }
+        if let Err(err) = display.flush() {
+            warn!("Failed to refresh display: {err:?}");
+        }
+        Ok(())
+    }
+
+    fn add_fb(&mut self, fb: Framebuffer) -> LinuxResult<u32> {
+        let buffer = self.buffers.get(&fb.handle).ok_or(LinuxError::ENOENT)?;
+        let end = fb.offset as u64 + fb.pitch as u64 * fb.height as u64;
+        if fb.width == 0
+            || fb.height == 0
+            || (fb.pitch as u64) < fb.width as u64 * 4
+            || end > buffer.size as u64
+        {
+            return Err(LinuxError::EINVAL);
+        }
+        let id = self.next_fb_id;
+        self.next_fb_id += 1;
+        self.fbs.insert(id, fb);
+        Ok(id)
+    }
+}
+
+/// Copies `ids` to the user array `ptr` of `count` entries, if it is large
+/// enough, like DRM does.
+fn write_ids(ptr: u64, count: u32, ids: &[u32]) -> LinuxResult<()> {
+    if count as usize >= ids.len() && !ids.is_empty() {
+        vm_write_slice(ptr as *mut u32, ids)?;
+    }
+    Ok(())
+}
+
+/// Copies `value` to the user string `ptr` of `len` bytes, returning the
+/// length of `value`.
+fn write_string(ptr: u64, len: u64, value: &str) -> LinuxResult<u64> {
+    let copied = value.len().min(len as usize);
+    if copied > 0 {
+        vm_write_slice(ptr as *mut u8, &value.as_bytes()[..copied])?;
+    }
+    Ok(value.len() as u64)
+}
+
+pub struct Drm {
+    state: Mutex<DrmState>,
+    poll_event: PollSet,
+}
+
+impl Drm {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(DrmState {
+                pool: None,
+                buffers: HashMap::new(),
+                retired: Vec::new(),
+                next_handle: 1,
+                fbs: HashMap::new(),
+                next_fb_id: FIRST_FB_ID,
+                scanout: None,
+                events: VecDeque::new(),
+                sequence: 0,
+            }),
+            poll_event: PollSet::new(),
+        }
+    }
+
+    /// Makes the memory of destroyed buffers nobody maps anymore available
+    /// again.
+    ///
+    /// The state is not locked while looking at the address spaces, as
+    /// `mmap` locks them in the opposite order.
+    fn release_retired(&self) {
+        let (pool, retired) = {
+            let state = self.state.lock();
+            (state.pool, state.retired.clone())
+        };
+        let Some(pool) = pool else {
+            return;
+        };
+        let released = retired
+            .into_iter()
+            .filter(|it| !is_mapped(pool, it))
+            .collect::<Vec<_>>();
+        self.state
+            .lock()
+            .retired
+            .retain(|it| !released.contains(it));
+    }
+
+    fn get_cap(capability: u64) -> LinuxResult<u64> {
+        Ok(match capability {
+            DRM_CAP_DUMB_BUFFER | DRM_CAP_TIMESTAMP_MONOTONIC => 1,
+            DRM_CAP_DUMB_PREFERRED_DEPTH => 24,
+            DRM_CAP_CURSOR_WIDTH | DRM_CAP_CURSOR_HEIGHT => 64,
+            DRM_CAP_VBLANK_HIGH_CRTC
+            | DRM_CAP_DUMB_PREFER_SHADOW
+            | DRM_CAP_PRIME
+            | DRM_CAP_ASYNC_PAGE_FLIP
+            | DRM_CAP_ADDFB2_MODIFIERS => 0,
+            _ => return Err(LinuxError::EINVAL),
+        })
+    }
+}
+
+impl DeviceOps for Drm {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let mut state = self.state.lock();
+        let mut read = 0;
+        for out in buf.chunks_exact_mut(size_of::<DrmEventVblank>()) {
+            let Some(event) = state.events.pop_front() else {
+                break;
+            };
+            out.copy_from_slice(event.as_bytes());
+            read += out.len();
+        }
+        if read == 0 && !buf.is_empty() {
+            return Err(if state.events.is_empty() {
+                LinuxError::EAGAIN
+            } else {
+                LinuxError::EINVAL
+            });
+        }
+        Ok(read)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            DRM_IOCTL_VERSION => {
+                let mut version = (arg as *const DrmVersion).vm_read()?;
+                version.major = 1;
+                version.minor = 0;
+                version.patchlevel = 0;
+                version.name_len = write_string(version.name, version.name_len, "starry")?;
+                version.date_len = write_string(version.date, version.date_len, "0")?;
+                version.desc_len = write_string(version.desc, version.desc_len, "Starry display")?;
+                (arg as *mut DrmVersion).vm_write(version)?;
+            }
+            DRM_IOCTL_GET_CAP => {
+                let mut cap = (arg as *const DrmGetCap).vm_read()?;
+                cap.value = Self::get_cap(cap.capability)?;
+                (arg as *mut DrmGetCap).vm_write(cap)?;
+            }
+            // Neither universal planes nor atomic modesetting are there.
+            DRM_IOCTL_SET_CLIENT_CAP => return Err(LinuxError::EINVAL),
+            DRM_IOCTL_SET_MASTER | DRM_IOCTL_DROP_MASTER => {}
+            DRM_IOCTL_MODE_GETRESOURCES => {
+                let mut res = (arg as *const DrmModeCardRes).vm_read()?;
+                let fbs = self.state.lock().fbs.keys().copied().collect::<Vec<_>>();
+                write_ids(res.fb_id_ptr, res.count_fbs, &fbs)?;
+                write_ids(res.crtc_id_ptr, res.count_crtcs, &[CRTC_ID])?;
+                write_ids(res.connector_id_ptr, res.count_connectors, &[CONNECTOR_ID])?;
+                write_ids(res.encoder_id_ptr, res.count_encoders, &[ENCODER_ID])?;
+                let info = axdisplay::main_display().info();
+                res.count_fbs = fbs.len() as _;
+                res.count_crtcs = 1;
+                res.count_connectors = 1;
+                res.count_encoders = 1;
+                (res.min_width, res.max_width) = (1, info.width);
+                (res.min_height, res.max_height) = (1, info.height);
+                (arg as *mut DrmModeCardRes).vm_write(res)?;
+            }
+            DRM_IOCTL_MODE_GETCRTC => {
+                let mut crtc = (arg as *const DrmModeCrtc).vm_read()?;
+                if crtc.crtc_id != CRTC_ID {
+                    return Err(LinuxError::ENOENT);
+                }
+                let scanout = self.state.lock().scanout;
+                crtc.fb_id = scanout.unwrap_or(0);
+                (crtc.x, crtc.y) = (0, 0);
+                crtc.gamma_size = 0;
+                crtc.mode_valid = scanout.is_some() as _;
+                crtc.mode = display_mode();
+                (arg as *mut DrmModeCrtc).vm_write(crtc)?;
+            }
+            DRM_IOCTL_MODE_SETCRTC => {
+                let crtc = (arg as *const DrmModeCrtc).vm_read()?;
+                if crtc.crtc_id != CRTC_ID {
+                    return Err(LinuxError::ENOENT);
+                }
+                let mut state = self.state.lock();
+                if crtc.mode_valid == 0 {
+                    state.scanout = None;
+                    return Ok(0);
+                }
+                let mode = display_mode();
+                if (crtc.mode.hdisplay, crtc.mode.vdisplay) != (mode.hdisplay, mode.vdisplay) {
+                    return Err(LinuxError::EINVAL);
+                }
+                // Zero keeps the framebuffer shown.
+                let fb_id = match crtc.fb_id {
+                    0 => state.scanout.ok_or(LinuxError::EINVAL)?,
+                    id => id,
+                };
+                state.show(fb_id, crtc.x, crtc.y)?;
+                state.scanout = Some(fb_id);
+            }
+            DRM_IOCTL_MODE_GETENCODER => {
+                let mut encoder = (arg as *const DrmModeGetEncoder).vm_read()?;
+                if encoder.encoder_id != ENCODER_ID {
+                    return Err(LinuxError::ENOENT);
+                }
+                encoder.encoder_type = DRM_MODE_ENCODER_VIRTUAL;
+                encoder.crtc_id = CRTC_ID;
+                encoder.possible_crtcs = 1;
+                encoder.possible_clones = 0;
+                (arg as *mut DrmModeGetEncoder).vm_write(encoder)?;
+            }
+            DRM_IOCTL_MODE_GETCONNECTOR => {
+                let mut conn = (arg as *const DrmModeGetConnector).vm_read()?;
+                if conn.connector_id != CONNECTOR_ID {
+                    return Err(LinuxError::ENOENT);
+                }
+                if conn.count_modes >= 1 {
+                    (conn.modes_ptr as *mut DrmModeModeInfo).vm_write(display_mode())?;
+                }
+                write_ids(conn.encoders_ptr, conn.count_encoders, &[ENCODER_ID])?;
+                conn.count_modes = 1;
+                conn.count_props = 0;
+                conn.count_encoders = 1;
+                conn.encoder_id = ENCODER_ID;
+                conn.connector_type = DRM_MODE_CONNECTOR_VIRTUAL;
+                conn.connector_type_id = 1;
+                conn.connection = DRM_MODE_CONNECTED;
+                (conn.mm_width, conn.mm_height) = (0, 0);
+                conn.subpixel = DRM_MODE_SUBPIXEL_UNKNOWN;
+                (arg as *mut DrmModeGetConnector).vm_write(conn)?;
+            }
+            DRM_IOCTL_MODE_ADDFB => {
+                let mut cmd = (arg as *const DrmModeFbCmd).vm_read()?;
+                if cmd.bpp != 32 || !matches!(cmd.depth, 24 | 32) {
+                    return Err(LinuxError::EINVAL);
+                }
+                cmd.fb_id = self.state.lock().add_fb(Framebuffer {
+                    handle: cmd.handle,
+                    width: cmd.width,
+                    height: cmd.height,
+                    pitch: cmd.pitch,
+                    offset: 0,
+                })?;
+                (arg as *mut DrmModeFbCmd).vm_write(cmd)?;
+            }
+            DRM_IOCTL_MODE_ADDFB2 => {
+                let mut cmd = (arg as *const DrmModeFbCmd2).vm_read()?;
+                if !matches!(cmd.pixel_format, DRM_FORMAT_XRGB8888 | DRM_FORMAT_ARGB8888)
+                    || cmd.flags != 0
+                {
+                    return Err(LinuxError::EINVAL);
+                }
+                cmd.fb_id = self.state.lock().add_fb(Framebuffer {
+                    handle: cmd.handles[0],
+                    width: cmd.width,
+                    height: cmd.height,
+                    pitch: cmd.pitches[0],
+                    offset: cmd.offsets[0],
+                })?;
+                (arg as *mut DrmModeFbCmd2).vm_write(cmd)?;
+            }
+            DRM_IOCTL_MODE_RMFB => {
+                let fb_id = (arg as *const u32).vm_read()?;
+                let mut state = self.state.lock();
+                state.fbs.remove(&fb_id).ok_or(LinuxError::ENOENT)?;
+                if state.scanout == Some(fb_id) {
+                    state.scanout = None;
+                }
+            }
+            DRM_IOCTL_MODE_PAGE_FLIP => {
+                let flip = (arg as *const DrmModeCrtcPageFlip).vm_read()?;
+                if flip.crtc_id != CRTC_ID {
+                    return Err(LinuxError::ENOENT);
+                }
+                if flip.flags & !(DRM_MODE_PAGE_FLIP_EVENT | DRM_MODE_PAGE_FLIP_ASYNC) != 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                let mut state = self.state.lock();
+                // Flipping needs the CRTC to be set up.
+                if state.scanout.is_none() {
+                    return Err(LinuxError::EINVAL);
+                }
+                if flip.flags & DRM_MODE_PAGE_FLIP_EVENT != 0
+                    && (state.events.len() + 1) * size_of::<DrmEventVblank>() > EVENT_SPACE
+                {
+                    return Err(LinuxError::ENOMEM);
+                }
+                state.show(flip.fb_id, 0, 0)?;
+                state.scanout = Some(flip.fb_id);
+                state.sequence = state.sequence.wrapping_add(1);
+                if flip.flags & DRM_MODE_PAGE_FLIP_EVENT != 0 {
+                    let now = monotonic_time();
+                    let event = DrmEventVblank {
+                        type_: DRM_EVENT_FLIP_COMPLETE,
+                        length: size_of::<DrmEventVblank>() as _,
+                        user_data: flip.user_data,
+                        tv_sec: now.as_secs() as _,
+                        tv_usec: now.subsec_micros(),
+                        sequence: state.sequence,
+                        crtc_id: CRTC_ID,
+                    };
+                    state.events.push_back(event);
+                    drop(state);
+                    self.poll_event.wake();
+                }
+            }
+            DRM_IOCTL_MODE_DIRTYFB => {
+                let dirty = (arg as *const DrmModeFbDirtyCmd).vm_read()?;
+                let state = self.state.lock();
+                if !state.fbs.contains_key(&dirty.fb_id) {
+                    return Err(LinuxError::ENOENT);
+                }
+                // The clips are ignored: all of it is copied again.
+                if state.scanout == Some(dirty.fb_id) {
+                    state.show(dirty.fb_id, 0, 0)?;
+                }
+            }
+            DRM_IOCTL_MODE_CREATE_DUMB => {
+                let mut dumb = (arg as *const DrmModeCreateDumb).vm_read()?;
+                if dumb.width == 0 || dumb.height == 0 || !matches!(dumb.bpp, 8 | 16 | 32) {
+                    return Err(LinuxError::EINVAL);
+                }
+                let pitch = (dumb.width as usize * dumb.bpp as usize / 8).next_multiple_of(64);
+                let size = pitch
+                    .checked_mul(dumb.height as usize)
+                    .filter(|it| *it <= DrmState::pool_size())
+                    .ok_or(LinuxError::EINVAL)?;
+                let size = align_up_4k(size);
+                dumb.pitch = pitch.try_into().map_err(|_| LinuxError::EINVAL)?;
+                self.release_retired();
+                let mut state = self.state.lock();
+                let offset = state.alloc(size)?;
+                let handle = state.next_handle;
+                state.next_handle += 1;
+                state.buffers.insert(handle, DumbBuffer { offset, size });
+                dumb.handle = handle;
+                dumb.size = size as _;
+                (arg as *mut DrmModeCreateDumb).vm_write(dumb)?;
+            }
+            DRM_IOCTL_MODE_MAP_DUMB => {
+                let mut map = (arg as *const DrmModeMapDumb).vm_read()?;
+                let state = self.state.lock();
+                let buffer = state.buffers.get(&map.handle).ok_or(LinuxError::ENOENT)?;
+                // The offset to `mmap` the device at.
+                map.offset = buffer.offset as _;
+                (arg as *mut DrmModeMapDumb).vm_write(map)?;
+            }
+            DRM_IOCTL_MODE_DESTROY_DUMB | DRM_IOCTL_GEM_CLOSE => {
+                let handle = (arg as *const u32).vm_read()?;
+                let mut state = self.state.lock();
+                let buffer = state.buffers.remove(&handle).ok_or(LinuxError::ENOENT)?;
+                // It may still be mapped, see `release_retired`.
+                state.retired.push(buffer);
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn mmap(&self) -> DeviceMmap {
+        match self.state.lock().pool {
+            Some(pool) => DeviceMmap::Physical(PhysAddrRange::from_start_size(
+                virt_to_phys(pool),
+                DrmState::pool_size(),
+            )),
+            None => DeviceMmap::None,
+        }
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for Drm {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, !self.state.lock().events.is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_event.register(context.waker());
+        }
+    }
+}
@@ -1,68 +1,205 @@
-use alloc::{format, sync::Arc};
-use core::{any::Any, task::Context, time::Duration};
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU32, Ordering},
+    task::Context,
+};
 
 #[allow(unused_imports)]
 use axdriver::prelude::{
     AxInputDevice, BaseDriverOps, DevError, Event, EventType, InputDeviceId, InputDriverOps,
 };
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsError, VfsResult};
 use axhal::time::wall_time;
 use axio::{IoEvents, Pollable};
 use axsync::Mutex;
 use bitmaps::Bitmap;
+use bytemuck::AnyBitPattern;
+use kspin::SpinNoIrq;
 use linux_raw_sys::{
     general::{__kernel_old_time_t, __kernel_suseconds_t},
     ioctl::{EVIOCGID, EVIOCGRAB, EVIOCGVERSION},
 };
-use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
+use starry_core::vfs::{Device, DeviceOps, DirMapping, NodeOpsMux, SimpleDirOps, SimpleFs};
 use zerocopy::{FromBytes, Immutable, IntoBytes};
 
-use crate::mm::UserPtr;
-const KEY_CNT: usize = EventType::Key.bits_count();
+use super::uinput::VirtualInput;
+use crate::{
+    logging::RateLimit,
+    mm::UserPtr,
+    vfs::sysfs::{self, SysDevice},
+};
+pub(super) const KEY_CNT: usize = EventType::Key.bits_count();
+pub(super) const ABS_CNT: usize = EventType::Absolute.bits_count();
+
+/// How many events an open file holds before it drops them, the smallest
+/// buffer Linux gives a client.
+const QUEUE_LEN: usize = 64;
+
+const EV_SYN: u16 = 0;
+const SYN_DROPPED: u16 = 3;
+
+/// The range of the absolute axes, which the driver does not report. This is
+/// the one of the virtio tablet of QEMU.
+const ABS_RANGE: (i32, i32) = (0, 0x7fff);
+
+/// The number of the next `eventN` device.
+static NEXT_EVENT: AtomicU32 = AtomicU32::new(0);
+
+/// The devices created through `/dev/uinput`, by name.
+static VIRTUAL_DEVICES: SpinNoIrq<BTreeMap<String, Arc<Device>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Where the events of an input device come from.
+enum Source {
+    Driver(AxInputDevice),
+    Virtual(Arc<VirtualInput>),
+}
+
+impl Source {
+    fn name(&self) -> &str {
+        match self {
+            Self::Driver(device) => device.device_name(),
+            Self::Virtual(input) => input.name(),
+        }
+    }
+
+    fn physical_location(&self) -> &str {
+        match self {
+            Self::Driver(device) => device.physical_location(),
+            Self::Virtual(_) => "",
+        }
+    }
+
+    fn unique_id(&self) -> &str {
+        match self {
+            Self::Driver(device) => device.unique_id(),
+            Self::Virtual(_) => "",
+        }
+    }
+
+    fn get_event_bits(&mut self, ty: EventType, out: &mut [u8]) -> Result<bool, DevError> {
+        match self {
+            Self::Driver(device) => device.get_event_bits(ty, out),
+            Self::Virtual(input) => Ok(input.get_event_bits(ty, out)),
+        }
+    }
+
+    /// Returns the type, code and value of the next event.
+    fn read_event(&mut self) -> Result<(u16, u16, i32), DevError> {
+        match self {
+            Self::Driver(device) => device
+                .read_event()
+                .map(|event| (event.event_type, event.code, event.value as _)),
+            Self::Virtual(input) => input.read_event().ok_or(DevError::Again),
+        }
+    }
+}
 
 struct Inner {
-    device: AxInputDevice,
-    read_ahead: Option<(Duration, Event)>,
+    source: Source,
     key_state: Bitmap<KEY_CNT>,
+    /// The last value of each absolute axis.
+    abs_value: [i32; ABS_CNT],
+    /// The queues of the files that have the device open.
+    clients: Vec<Weak<Mutex<Queue>>>,
 }
 impl Inner {
-    fn has_event(&mut self) -> bool {
-        if self.read_ahead.is_none() {
-            match self.device.read_event() {
-                Ok(event) => {
-                    if event.event_type == EventType::Key as u16 {
-                        if event.value == 0 {
-                            self.key_state.set(event.code as usize, false);
-                        } else if event.value == 1 {
-                            self.key_state.set(event.code as usize, true);
-                        }
-                    }
-                    self.read_ahead = Some((wall_time(), event));
-                }
-                Err(DevError::Again) => {}
+    /// Reads the pending events of the driver into the queue of every open
+    /// file.
+    fn pump(&mut self) {
+        self.clients.retain(|it| it.strong_count() > 0);
+        loop {
+            let (event_type, code, value) = match self.source.read_event() {
+                Ok(event) => event,
+                Err(DevError::Again) => break,
                 Err(err) => {
                     warn!("Failed to read event: {:?}", err);
+                    break;
                 }
+            };
+            if event_type == EventType::Key as u16 && (code as usize) < KEY_CNT {
+                if value == 0 {
+                    self.key_state.set(code as usize, false);
+                } else if value == 1 {
+                    self.key_state.set(code as usize, true);
+                }
+            } else if event_type == EventType::Absolute as u16 && (code as usize) < ABS_CNT {
+                self.abs_value[code as usize] = value;
+            }
+            let time = wall_time();
+            let event = InputEvent {
+                time: KernelTimeval {
+                    tv_sec: time.as_secs() as _,
+                    tv_usec: time.subsec_micros() as _,
+                },
+                event_type,
+                code,
+                value,
+            };
+            for client in self.clients.iter().filter_map(Weak::upgrade) {
+                client.lock().push(event);
             }
         }
-        self.read_ahead.is_some()
     }
 }
 
-pub struct EventDev {
+/// The events an open file has yet to read.
+#[derive(Default)]
+struct Queue(VecDeque<InputEvent>);
+
+impl Queue {
+    fn push(&mut self, event: InputEvent) {
+        if self.0.len() >= QUEUE_LEN {
+            // Like Linux, drop what was not read and tell the reader, who has
+            // to query the state of the device again.
+            self.0.clear();
+            self.0.push_back(InputEvent {
+                event_type: EV_SYN,
+                code: SYN_DROPPED,
+                value: 0,
+                ..event
+            });
+        }
+        self.0.push_back(event);
+    }
+}
+
+/// What the open files of an input device share.
+struct Shared {
     inner: Mutex<Inner>,
     ev_bits: Bitmap<{ EventType::COUNT as usize }>,
+    abs_bits: Bitmap<ABS_CNT>,
+    fs: Arc<SimpleFs>,
+    device_id: DeviceId,
+}
+
+/// An input device, as an open file sees it.
+///
+/// The one of the device node is never read: opening it goes through
+/// [`EventDev::open`], which gives each open file a queue of its own, so that
+/// every reader sees every event.
+pub struct EventDev {
+    shared: Arc<Shared>,
+    queue: Arc<Mutex<Queue>>,
 }
 
 impl EventDev {
-    pub fn new(mut device: AxInputDevice) -> Self {
+    fn new(fs: Arc<SimpleFs>, device_id: DeviceId, mut source: Source) -> Self {
         let mut ev_bits = Bitmap::new();
         for i in 0..EventType::COUNT {
             let Some(ty) = EventType::from_repr(i) else {
                 continue;
             };
-            if device
+            if source
                 .get_event_bits(ty, &mut [])
                 .is_ok_and(|success| success)
             {
@@ -82,23 +219,85 @@ impl EventDev {
         // } else {
         //     warn!("failure");
         // }
+        let mut abs_bits = Bitmap::new();
+        let mut bytes = [0; ABS_CNT / 8];
+        if source
+            .get_event_bits(EventType::Absolute, &mut bytes)
+            .is_ok_and(|success| success)
+        {
+            for axis in 0..ABS_CNT {
+                abs_bits.set(axis, bytes[axis / 8] & (1 << (axis % 8)) != 0);
+            }
+        }
         Self {
-            inner: Mutex::new(Inner {
-                device,
-                read_ahead: None,
-                key_state: Bitmap::new(),
+            shared: Arc::new(Shared {
+                inner: Mutex::new(Inner {
+                    source,
+                    key_state: Bitmap::new(),
+                    abs_value: [0; ABS_CNT],
+                    clients: Vec::new(),
+                }),
+                ev_bits,
+                abs_bits,
+                fs,
+                device_id,
+            }),
+            queue: Arc::new(Mutex::new(Queue::default())),
+        }
+    }
+
+    /// Opens the device, with a queue that gets every event from now on.
+    pub fn open(&self) -> Arc<Device> {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        self.shared
+            .inner
+            .lock()
+            .clients
+            .push(Arc::downgrade(&queue));
+        Device::new(
+            self.shared.fs.clone(),
+            NodeType::CharacterDevice,
+            self.shared.device_id,
+            Arc::new(Self {
+                shared: self.shared.clone(),
+                queue,
             }),
-            ev_bits,
+        )
+    }
+
+    fn get_abs_info(&self, arg: usize, size: usize, axis: u8) -> LinuxResult<usize> {
+        if !self.shared.abs_bits.get(axis as usize) {
+            return Err(LinuxError::EINVAL);
         }
+        let inner = self.shared.inner.lock();
+        let value = inner.abs_value[axis as usize];
+        let info = match &inner.source {
+            Source::Virtual(input) => InputAbsInfo {
+                value,
+                ..input.abs_info(axis as usize)
+            },
+            Source::Driver(_) => InputAbsInfo {
+                value,
+                minimum: ABS_RANGE.0,
+                maximum: ABS_RANGE.1,
+                fuzz: 0,
+                flat: 0,
+                resolution: 0,
+            },
+        };
+        drop(inner);
+        let out = UserPtr::<u8>::from(arg).get_as_mut_slice(size)?;
+        copy_bytes(info.as_bytes(), out);
+        Ok(0)
     }
 
     fn get_event_bits(&self, arg: usize, size: usize, ty: u8) -> LinuxResult<usize> {
         let bits = UserPtr::<u8>::from(arg).get_as_mut_slice(size)?;
         if ty == 0 {
-            Ok(copy_bytes(self.ev_bits.as_bytes(), bits))
+            Ok(copy_bytes(self.shared.ev_bits.as_bytes(), bits))
         } else {
             let ty = EventType::from_repr(ty).ok_or(LinuxError::EINVAL)?;
-            match self.inner.lock().device.get_event_bits(ty, bits) {
+            match self.shared.inner.lock().source.get_event_bits(ty, bits) {
                 Ok(true) => {}
                 Ok(false) => {
                     debug!("No events for {ty:?}");
@@ -130,19 +329,40 @@ fn return_zero_bits(arg: usize, size: usize, bits: usize) -> LinuxResult<usize>
 }
 
 #[repr(C)]
-#[derive(FromBytes, IntoBytes, Immutable)]
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable)]
 pub struct KernelTimeval {
     pub tv_sec: __kernel_old_time_t,
     pub tv_usec: __kernel_suseconds_t,
 }
 
 #[repr(C)]
-#[derive(FromBytes, IntoBytes, Immutable)]
-struct InputEvent {
-    time: KernelTimeval,
-    event_type: u16,
-    code: u16,
-    value: i32,
+#[derive(Clone, Copy, FromBytes, IntoBytes, Immutable)]
+pub(super) struct InputEvent {
+    pub time: KernelTimeval,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, AnyBitPattern, FromBytes, IntoBytes, Immutable)]
+pub(super) struct InputAbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+/// `struct input_id`.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+pub(super) struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
 }
 
 #[unsafe(no_mangle)]
@@ -159,25 +379,14 @@ impl DeviceOps for EventDev {
         if buf.len() < size_of::<InputEvent>() {
             return Err(LinuxError::EINVAL);
         }
+        self.shared.inner.lock().pump();
         let mut read = 0;
-        let mut inner = self.inner.lock();
+        let mut queue = self.queue.lock();
         for out in buf.chunks_exact_mut(size_of::<InputEvent>()) {
-            if !inner.has_event() {
-                break;
-            }
-            let Some((time, event)) = inner.read_ahead.take() else {
+            let Some(event) = queue.0.pop_front() else {
                 break;
             };
-            let input_event = InputEvent {
-                time: KernelTimeval {
-                    tv_sec: time.as_secs() as _,
-                    tv_usec: time.subsec_micros() as _,
-                },
-                event_type: event.event_type,
-                code: event.code,
-                value: event.value as _,
-            };
-            out.copy_from_slice(input_event.as_bytes());
+            out.copy_from_slice(event.as_bytes());
             read += out.len();
         }
         if read == 0 {
@@ -210,8 +419,14 @@ impl DeviceOps for EventDev {
                 Ok(0)
             }
             EVIOCGID => {
-                *UserPtr::<InputDeviceId>::from(arg).get_as_mut()? =
-                    self.inner.lock().device.device_id();
+                match &self.shared.inner.lock().source {
+                    Source::Driver(device) => {
+                        *UserPtr::<InputDeviceId>::from(arg).get_as_mut()? = device.device_id();
+                    }
+                    Source::Virtual(input) => {
+                        *UserPtr::<InputId>::from(arg).get_as_mut()? = input.id();
+                    }
+                }
                 Ok(0)
             }
             EVIOCGRAB => Ok(0),
@@ -227,7 +442,10 @@ impl DeviceOps for EventDev {
                 let dir = tmp & 0x3;
 
                 if ty != b'E' {
-                    warn!("unknown ioctl for evdev: {} {}", cmd, arg);
+                    static UNKNOWN_IOCTL: RateLimit = RateLimit::new("evdev");
+                    if UNKNOWN_IOCTL.allow() {
+                        warn!("unknown ioctl for evdev: {} {}", cmd, arg);
+                    }
                     return Err(LinuxError::EINVAL);
                 }
 
@@ -243,7 +461,7 @@ impl DeviceOps for EventDev {
                                 return return_str(
                                     arg,
                                     size,
-                                    self.inner.lock().device.device_name(),
+                                    self.shared.inner.lock().source.name(),
                                 );
                             }
                             // EVIOCGPHYS
@@ -251,12 +469,16 @@ impl DeviceOps for EventDev {
                                 return return_str(
                                     arg,
                                     size,
-                                    self.inner.lock().device.physical_location(),
+                                    self.shared.inner.lock().source.physical_location(),
                                 );
                             }
                             // EVIOCGUNIQ
                             0x08 => {
-                                return return_str(arg, size, self.inner.lock().device.unique_id());
+                                return return_str(
+                                    arg,
+                                    size,
+                                    self.shared.inner.lock().source.unique_id(),
+                                );
                             }
                             // EVIOCGPROP
                             0x09 => {
@@ -268,7 +490,7 @@ impl DeviceOps for EventDev {
                             0x18 => {
                                 let bits = UserPtr::<u8>::from(arg).get_as_mut_slice(size)?;
                                 return Ok(copy_bytes(
-                                    self.inner.lock().key_state.as_bytes(),
+                                    self.shared.inner.lock().key_state.as_bytes(),
                                     bits,
                                 ));
                             }
@@ -289,10 +511,9 @@ impl DeviceOps for EventDev {
                         if nr & !EventType::MAX == EventType::COUNT {
                             return self.get_event_bits(arg, size, nr & EventType::MAX);
                         }
-                        const ABS_CNT: u8 = 0x40;
-                        if nr & !(ABS_CNT - 1) == ABS_CNT {
-                            // TODO: abs info
-                            return Ok(0);
+                        // EVIOCGABS
+                        if nr & !(ABS_CNT as u8 - 1) == ABS_CNT as u8 {
+                            return self.get_abs_info(arg, size, nr & (ABS_CNT as u8 - 1));
                         }
                         return Err(LinuxError::EINVAL);
                     }
@@ -308,7 +529,8 @@ impl DeviceOps for EventDev {
 impl Pollable for EventDev {
     fn poll(&self) -> IoEvents {
         let mut events = IoEvents::empty();
-        events.set(IoEvents::IN, self.inner.lock().has_event());
+        self.shared.inner.lock().pump();
+        events.set(IoEvents::IN, !self.queue.lock().0.is_empty());
         events
     }
 
@@ -319,7 +541,9 @@ impl Pollable for EventDev {
     }
 }
 
-pub fn input_devices(fs: Arc<SimpleFs>) -> DirMapping {
+/// Returns `/dev/input`: the devices of drivers, followed by the ones created
+/// through `/dev/uinput`.
+pub fn input_devices(fs: Arc<SimpleFs>) -> impl SimpleDirOps {
     let mut inputs = DirMapping::new();
     let mut input_id = 0;
     let input_devices = axinput::take_inputs();
@@ -327,21 +551,79 @@ pub fn input_devices(fs: Arc<SimpleFs>) -> DirMapping {
     for (i, mut device) in input_devices.into_iter().enumerate() {
         assert!(device.get_event_bits(EventType::Key, &mut keys).unwrap());
 
+        let dev_id = DeviceId::new(13, (i + 1) as _);
         let dev = Device::new(
             fs.clone(),
             NodeType::CharacterDevice,
-            DeviceId::new(13, (i + 1) as _),
-            Arc::new(EventDev::new(device)),
+            dev_id,
+            Arc::new(EventDev::new(fs.clone(), dev_id, Source::Driver(device))),
         );
 
         const BTN_MOUSE: usize = 0x110;
-        if keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0 {
+        let name = if keys[BTN_MOUSE / 8] & (1 << (BTN_MOUSE % 8)) != 0 {
             // Mouse
-            inputs.add("mice", dev);
+            "mice".into()
         } else {
-            inputs.add(format!("event{input_id}"), dev);
+            let name = format!("event{input_id}");
             input_id += 1;
-        }
+            name
+        };
+        sysfs::register(SysDevice::new("input", format!("input/{name}"), dev_id));
+        inputs.add(name, dev);
+    }
+    NEXT_EVENT.store(input_id, Ordering::Release);
+    inputs.chain(VirtualInputDir)
+}
+
+/// Adds a device created through `/dev/uinput` as the next `eventN`, and
+/// returns its name.
+pub(super) fn add_virtual(fs: Arc<SimpleFs>, input: Arc<VirtualInput>) -> String {
+    let number = NEXT_EVENT.fetch_add(1, Ordering::AcqRel);
+    let name = format!("event{number}");
+    // The minor Linux gives `eventN`.
+    let dev_id = DeviceId::new(13, 64 + number);
+    let dev = Device::new(
+        fs.clone(),
+        NodeType::CharacterDevice,
+        dev_id,
+        Arc::new(EventDev::new(fs, dev_id, Source::Virtual(input))),
+    );
+    VIRTUAL_DEVICES.lock().insert(name.clone(), dev);
+    sysfs::register(SysDevice::new("input", format!("input/{name}"), dev_id));
+    name
+}
+
+/// Removes a device added by [`add_virtual`]. Files that have it open keep
+/// it, but get no more events.
+pub(super) fn remove_virtual(name: &str) {
+    if VIRTUAL_DEVICES.lock().remove(name).is_some() {
+        sysfs::unregister("input", name);
+    }
+}
+
+/// The devices created through `/dev/uinput`.
+struct VirtualInputDir;
+
+impl SimpleDirOps for VirtualInputDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = VIRTUAL_DEVICES
+            .lock()
+            .keys()
+            .map(|it| Cow::Owned(it.clone()))
+            .collect::<Vec<_>>();
+        Box::new(names.into_iter())
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let dev = VIRTUAL_DEVICES
+            .lock()
+            .get(name)
+            .ok_or(VfsError::ENOENT)?
+            .clone();
+        Ok(NodeOpsMux::File(dev))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
     }
-    inputs
 }
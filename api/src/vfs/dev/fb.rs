@@ -1,13 +1,16 @@
+use alloc::sync::Arc;
 use core::{any::Any, slice};
 
+use axalloc::global_allocator;
 #[allow(unused_imports)]
 use axdriver::prelude::DisplayDriverOps;
 use axerrno::LinuxError;
-use axfs_ng_vfs::{NodeFlags, VfsError, VfsResult};
+use axfs_ng_vfs::{NodeFlags, NodeType, VfsError, VfsResult};
 use axhal::mem::virt_to_phys;
-use memory_addr::{PhysAddrRange, VirtAddr};
-use starry_core::vfs::{DeviceMmap, DeviceOps};
-use starry_vm::VmMutPtr;
+use axsync::Mutex;
+use memory_addr::{PAGE_SIZE_4K, PhysAddrRange, VirtAddr};
+use starry_core::vfs::{DeviceMmap, DeviceOps, DirMapping, SimpleDir, SimpleFile, SimpleFs};
+use starry_vm::{VmMutPtr, VmPtr};
 
 // Types from https://github.com/Tangzh33/asterinas
 
@@ -87,9 +90,24 @@ async fn refresh_task() {
     }
 }
 
+/// The panning state of the framebuffer.
+struct Panning {
+    /// The memory user space draws into, twice the size of the screen, once
+    /// double buffering has been asked for with `yres_virtual`.
+    ///
+    /// It is never freed, since it may still be mapped.
+    back: Option<VirtAddr>,
+    /// Whether the virtual screen is [`Panning::back`], rather than the
+    /// screen itself.
+    double: bool,
+    /// The first line of the virtual screen that is shown.
+    yoffset: u32,
+}
+
 pub struct FrameBuffer {
     base: VirtAddr,
     size: usize,
+    panning: Mutex<Panning>,
 }
 impl FrameBuffer {
     pub fn new() -> Self {
@@ -101,6 +119,11 @@ impl FrameBuffer {
         Self {
             base: VirtAddr::from(info.fb_base_vaddr),
             size: info.fb_size,
+            panning: Mutex::new(Panning {
+                back: None,
+                double: false,
+                yoffset: 0,
+            }),
         }
     }
 
@@ -108,24 +131,164 @@ impl FrameBuffer {
     fn as_mut_slice(&self) -> &mut [u8] {
         unsafe { slice::from_raw_parts_mut(self.base.as_mut_ptr(), self.size) }
     }
+
+    /// Returns the memory user space draws into: the virtual screen.
+    #[allow(clippy::mut_from_ref)]
+    fn virtual_screen(&self, panning: &Panning) -> &mut [u8] {
+        match panning.back {
+            Some(back) if panning.double => unsafe {
+                slice::from_raw_parts_mut(back.as_mut_ptr(), self.size * 2)
+            },
+            _ => self.as_mut_slice(),
+        }
+    }
+
+    /// Returns the physical memory of the virtual screen.
+    fn screen_range(&self, panning: &Panning) -> PhysAddrRange {
+        let screen = self.virtual_screen(panning);
+        PhysAddrRange::from_start_size(
+            virt_to_phys(VirtAddr::from_ptr_of(screen.as_ptr())),
+            screen.len(),
+        )
+    }
+
+    fn yres_virtual(&self, panning: &Panning) -> u32 {
+        let height = axdisplay::main_display().info().height;
+        if panning.double { height * 2 } else { height }
+    }
+
+    fn var_screen_info(&self, panning: &Panning) -> VarScreenInfo {
+        let info = axdisplay::main_display().info();
+        let line_length = (info.fb_size / info.height as usize) as u32;
+        let bpp = line_length / info.width;
+        VarScreenInfo {
+            xres: info.width,
+            yres: info.height,
+            xres_virtual: info.width,
+            yres_virtual: self.yres_virtual(panning),
+            xoffset: 0,
+            yoffset: panning.yoffset,
+            bits_per_pixel: bpp * 8,
+            grayscale: 0,
+            red: FrameBufferBitfield {
+                offset: 16,
+                length: 8,
+                msb_right: 0,
+            },
+            green: FrameBufferBitfield {
+                offset: 8,
+                length: 8,
+                msb_right: 0,
+            },
+            blue: FrameBufferBitfield {
+                offset: 0,
+                length: 8,
+                msb_right: 0,
+            },
+            transp: FrameBufferBitfield {
+                offset: 24,
+                length: 8,
+                msb_right: 0,
+            },
+            nonstd: 0,
+            activate: 0,
+            height: 0,
+            width: 0,
+            accel_flags: 0,
+            pixclock: 10000000 / info.width * 1000 / info.height,
+            left_margin: (info.width / 8) & 0xf8,
+            right_margin: 32,
+            upper_margin: 16,
+            lower_margin: 4,
+            hsync_len: (info.width / 8) & 0xf8,
+            vsync_len: 4,
+            sync: 0,
+            vmode: 0,
+            rotate: 0,
+            colorspace: 0,
+            reserved: [0; 4],
+        }
+    }
+
+    /// Shows the lines of the virtual screen from `yoffset` on.
+    fn pan(&self, panning: &mut Panning, yoffset: u32) -> VfsResult<()> {
+        let height = axdisplay::main_display().info().height;
+        if yoffset
+            .checked_add(height)
+            .is_none_or(|end| end > self.yres_virtual(panning))
+        {
+            return Err(VfsError::EINVAL);
+        }
+        panning.yoffset = yoffset;
+        if panning.double {
+            let start = self.size / height as usize * yoffset as usize;
+            let shown = &self.virtual_screen(panning)[start..start + self.size];
+            self.as_mut_slice().copy_from_slice(shown);
+            if let Err(err) = axdisplay::main_display().flush() {
+                warn!("Failed to refresh framebuffer: {err:?}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches between drawing into the screen and into a virtual screen of
+    /// twice its height, in which `FBIOPAN_DISPLAY` selects what is shown.
+    fn set_double(&self, panning: &mut Panning, double: bool) -> VfsResult<()> {
+        if panning.double == double {
+            return Ok(());
+        }
+        if double && panning.back.is_none() {
+            let pages = (self.size * 2).div_ceil(PAGE_SIZE_4K);
+            let back = global_allocator()
+                .alloc_pages(pages, PAGE_SIZE_4K)
+                .map_err(|_| VfsError::ENOMEM)?;
+            // SAFETY: the pages were just allocated
+            unsafe { core::ptr::write_bytes(back as *mut u8, 0, pages * PAGE_SIZE_4K) };
+            panning.back = Some(VirtAddr::from(back));
+        }
+        // What is shown stays, in the first page of the new virtual screen.
+        let shown = self.as_mut_slice();
+        panning.double = double;
+        panning.yoffset = 0;
+        if double {
+            self.virtual_screen(panning)[..self.size].copy_from_slice(shown);
+        }
+        Ok(())
+    }
+}
+
+/// The attributes of `/sys/class/graphics/fb0`. Programs looking for a
+/// framebuffer check that it has a parent device with a subsystem.
+pub fn sysfs_attrs(fs: Arc<SimpleFs>) -> DirMapping {
+    let mut device = DirMapping::new();
+    device.add(
+        "subsystem",
+        SimpleFile::new(fs.clone(), NodeType::Symlink, || Ok("/sys/bus/platform")),
+    );
+    let mut attrs = DirMapping::new();
+    attrs.add("device", SimpleDir::new_maker(fs, Arc::new(device)));
+    attrs
 }
+
 impl DeviceOps for FrameBuffer {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
-        let slice = self.as_mut_slice();
-        let len = buf
-            .len()
-            .min((slice.len() as u64).saturating_sub(offset) as usize);
-        buf[..len].copy_from_slice(&slice[..len]);
+        let panning = self.panning.lock();
+        let slice = self.virtual_screen(&panning);
+        let offset = offset.min(slice.len() as u64) as usize;
+        let len = buf.len().min(slice.len() - offset);
+        buf[..len].copy_from_slice(&slice[offset..offset + len]);
         Ok(len)
     }
 
     fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
-        let slice = self.as_mut_slice();
+        let panning = self.panning.lock();
+        let slice = self.virtual_screen(&panning);
         if offset >= slice.len() as u64 {
             return Err(VfsError::ENOSPC);
         }
-        let len = buf.len().min(slice.len() - offset as usize);
-        slice[..len].copy_from_slice(&buf[..len]);
+        let offset = offset as usize;
+        let len = buf.len().min(slice.len() - offset);
+        slice[offset..offset + len].copy_from_slice(&buf[..len]);
         Ok(len)
     }
 
@@ -133,72 +296,51 @@ impl DeviceOps for FrameBuffer {
         match cmd {
             // FBIOGET_VSCREENINFO
             0x4600 => {
-                let info = axdisplay::main_display().info();
-                let line_length = (info.fb_size / info.height as usize) as u32;
-                let bpp = line_length / info.width;
-                (arg as *mut VarScreenInfo).vm_write(VarScreenInfo {
-                    xres: info.width,
-                    yres: info.height,
-                    xres_virtual: info.width,
-                    yres_virtual: info.height,
-                    xoffset: 0,
-                    yoffset: 0,
-                    bits_per_pixel: bpp * 8,
-                    grayscale: 0,
-                    red: FrameBufferBitfield {
-                        offset: 16,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    green: FrameBufferBitfield {
-                        offset: 8,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    blue: FrameBufferBitfield {
-                        offset: 0,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    transp: FrameBufferBitfield {
-                        offset: 24,
-                        length: 8,
-                        msb_right: 0,
-                    },
-                    nonstd: 0,
-                    activate: 0,
-                    height: 0,
-                    width: 0,
-                    accel_flags: 0,
-                    pixclock: 10000000 / info.width * 1000 / info.height,
-                    left_margin: (info.width / 8) & 0xf8,
-                    right_margin: 32,
-                    upper_margin: 16,
-                    lower_margin: 4,
-                    hsync_len: (info.width / 8) & 0xf8,
-                    vsync_len: 4,
-                    sync: 0,
-                    vmode: 0,
-                    rotate: 0,
-                    colorspace: 0,
-                    reserved: [0; 4],
-                })?;
+                let panning = self.panning.lock();
+                (arg as *mut VarScreenInfo).vm_write(self.var_screen_info(&panning))?;
                 Ok(0)
             }
             // FBIOPUT_VSCREENINFO
-            0x4601 => Ok(0),
+            0x4601 => {
+                // FIXME: AnyBitPattern
+                let var = unsafe {
+                    (arg as *const VarScreenInfo)
+                        .vm_read_uninit()?
+                        .assume_init()
+                };
+                let mut panning = self.panning.lock();
+                let current = self.var_screen_info(&panning);
+                // Only the virtual height and the panning can be changed.
+                if (var.xres, var.yres) != (current.xres, current.yres)
+                    || var.xres_virtual != current.xres_virtual
+                    || var.bits_per_pixel != current.bits_per_pixel
+                    || var.xoffset != 0
+                {
+                    return Err(LinuxError::EINVAL);
+                }
+                let double = match var.yres_virtual {
+                    yres if yres == current.yres => false,
+                    yres if yres == current.yres * 2 => true,
+                    _ => return Err(LinuxError::EINVAL),
+                };
+                self.set_double(&mut panning, double)?;
+                self.pan(&mut panning, var.yoffset)?;
+                (arg as *mut VarScreenInfo).vm_write(self.var_screen_info(&panning))?;
+                Ok(0)
+            }
             // FBIOGET_FSCREENINFO
             0x4602 => {
                 let info = axdisplay::main_display().info();
+                let screen = self.screen_range(&self.panning.lock());
                 (arg as *mut FixScreenInfo).vm_write(FixScreenInfo {
                     id: *b"Virtio Framebuf\0",
-                    smem_start: info.fb_base_vaddr as u64,
-                    smem_len: info.fb_size as u32,
+                    smem_start: screen.start.as_usize() as u64,
+                    smem_len: screen.size() as u32,
                     type_: 0,
                     type_aux: 0,
                     visual: 2, // FB_VISUAL_TRUECOLOR
                     xpanstep: 0,
-                    ypanstep: 0,
+                    ypanstep: 1,
                     ywrapstep: 0,
                     line_length: (info.fb_size / info.height as usize) as u32,
                     mmio_start: 0,
@@ -214,7 +356,19 @@ impl DeviceOps for FrameBuffer {
             // FBIOPUTCMAP
             0x4605 => Ok(0),
             // FBIOPAN_DISPLAY
-            0x4606 => Err(LinuxError::EINVAL),
+            0x4606 => {
+                // FIXME: AnyBitPattern
+                let var = unsafe {
+                    (arg as *const VarScreenInfo)
+                        .vm_read_uninit()?
+                        .assume_init()
+                };
+                if var.xoffset != 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                self.pan(&mut self.panning.lock(), var.yoffset)?;
+                Ok(0)
+            }
             // FBIOBLANK
             0x4611 => Err(LinuxError::EINVAL),
             _ => Err(LinuxError::ENOTTY),
@@ -226,10 +380,10 @@ impl DeviceOps for FrameBuffer {
     }
 
     fn mmap(&self) -> DeviceMmap {
-        DeviceMmap::Physical(PhysAddrRange::from_start_size(
-            virt_to_phys(self.base),
-            self.size,
-        ))
+        // Mapped with the attributes of the linear mapping the kernel draws
+        // through, as mismatched aliases are not coherent on ARM. The display
+        // sees the memory once `flush` has been called.
+        DeviceMmap::Physical(self.screen_range(&self.panning.lock()))
     }
 
     fn flags(&self) -> NodeFlags {
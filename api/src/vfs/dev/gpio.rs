@@ -0,0 +1,613 @@
+//! The GPIO banks of the RK3588, as the `/dev/gpiochipN` character devices of
+//! the Linux GPIO uAPI, both v1 and v2.
+//!
+//! Each of the five banks has 32 lines, which are requested through the chip
+//! and then driven through the file that comes back. Lines can be inputs or
+//! outputs, and active low. Pin multiplexing, bias and drive are left as the
+//! firmware set them, since they are in the pin controller rather than the
+//! GPIO banks, and there is no edge detection.
+
+#[cfg(not(target_arch = "aarch64"))]
+compile_error!("GPIO is only supported on the RK3588");
+
+use alloc::{borrow::Cow, string::String, sync::Arc, vec::Vec};
+use core::{any::Any, ffi::CStr, task::Context};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+use axhal::{mem::phys_to_virt, paging::MappingFlags};
+use axio::{IoEvents, Pollable};
+use axsync::Mutex;
+use bytemuck::{AnyBitPattern, Zeroable};
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use starry_core::vfs::DeviceOps;
+use starry_vm::{VmMutPtr, VmPtr};
+
+use crate::file::{FileLike, Kstat, SealedBuf, SealedBufMut};
+
+/// The major number of the chips.
+pub const GPIO_MAJOR: u32 = 254;
+
+/// The physical addresses of the banks.
+pub const BANKS: [usize; 5] = [
+    0xfd8a_0000,
+    0xfec2_0000,
+    0xfec3_0000,
+    0xfec4_0000,
+    0xfec5_0000,
+];
+const LINES: u32 = 32;
+
+// Each of these is split into a register for lines 0-15 and one for lines
+// 16-31, whose upper half says which bits of the lower half to write.
+const SWPORT_DR: usize = 0x00;
+const SWPORT_DDR: usize = 0x08;
+const EXT_PORT: usize = 0x70;
+
+const GPIO_GET_CHIPINFO_IOCTL: u32 = 0x8044_b401;
+const GPIO_GET_LINEINFO_IOCTL: u32 = 0xc048_b402;
+const GPIO_GET_LINEHANDLE_IOCTL: u32 = 0xc16c_b403;
+const GPIO_V2_GET_LINEINFO_IOCTL: u32 = 0xc100_b405;
+const GPIO_V2_GET_LINE_IOCTL: u32 = 0xc250_b407;
+const GPIOHANDLE_GET_LINE_VALUES_IOCTL: u32 = 0xc040_b408;
+const GPIOHANDLE_SET_LINE_VALUES_IOCTL: u32 = 0xc040_b409;
+const GPIO_V2_LINE_SET_CONFIG_IOCTL: u32 = 0xc110_b40d;
+const GPIO_V2_LINE_GET_VALUES_IOCTL: u32 = 0xc010_b40e;
+const GPIO_V2_LINE_SET_VALUES_IOCTL: u32 = 0xc010_b40f;
+
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_USED: u64 = 1 << 0;
+const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+
+const GPIO_V2_LINE_ATTR_ID_FLAGS: u32 = 1;
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+const GPIOLINE_FLAG_KERNEL: u32 = 1 << 0;
+const GPIOLINE_FLAG_IS_OUT: u32 = 1 << 1;
+const GPIOLINE_FLAG_ACTIVE_LOW: u32 = 1 << 2;
+
+const GPIOHANDLE_REQUEST_INPUT: u32 = 1 << 0;
+const GPIOHANDLE_REQUEST_OUTPUT: u32 = 1 << 1;
+const GPIOHANDLE_REQUEST_ACTIVE_LOW: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct ChipInfo {
+    name: [u8; 32],
+    label: [u8; 32],
+    lines: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct LineAttribute {
+    id: u32,
+    _padding: u32,
+    /// The flags, output values or debounce period, depending on `id`.
+    value: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct LineConfigAttribute {
+    attr: LineAttribute,
+    /// The lines of the request the attribute is for.
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct LineConfig {
+    flags: u64,
+    num_attrs: u32,
+    _padding: [u32; 5],
+    attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct LineRequestArgs {
+    offsets: [u32; GPIO_V2_LINES_MAX],
+    consumer: [u8; 32],
+    config: LineConfig,
+    num_lines: u32,
+    event_buffer_size: u32,
+    _padding: [u32; 5],
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct LineInfo {
+    name: [u8; 32],
+    consumer: [u8; 32],
+    offset: u32,
+    num_attrs: u32,
+    flags: u64,
+    attrs: [LineAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+    _padding: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct LineValues {
+    bits: u64,
+    mask: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct LineInfoV1 {
+    line_offset: u32,
+    flags: u32,
+    name: [u8; 32],
+    consumer: [u8; 32],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct HandleRequest {
+    lineoffsets: [u32; GPIO_V2_LINES_MAX],
+    flags: u32,
+    default_values: [u8; GPIO_V2_LINES_MAX],
+    consumer_label: [u8; 32],
+    lines: u32,
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct HandleData {
+    values: [u8; GPIO_V2_LINES_MAX],
+}
+
+// The sizes are part of the ioctl numbers above.
+const _: () = {
+    assert!(size_of::<ChipInfo>() == 68);
+    assert!(size_of::<LineConfig>() == 272);
+    assert!(size_of::<LineRequestArgs>() == 592);
+    assert!(size_of::<LineInfo>() == 256);
+    assert!(size_of::<LineInfoV1>() == 72);
+    assert!(size_of::<HandleRequest>() == 364);
+};
+
+/// Copies as much of `s` as fits into a NUL-terminated string field.
+fn set_str<const N: usize>(field: &mut [u8; N], s: &str) {
+    let len = s.len().min(N - 1);
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn get_str(field: &[u8]) -> String {
+    CStr::from_bytes_until_nul(field)
+        .ok()
+        .and_then(|it| it.to_str().ok())
+        .unwrap_or_default()
+        .into()
+}
+
+/// A line that has been requested.
+struct Requested {
+    consumer: String,
+    active_low: bool,
+}
+
+struct Bank {
+    base: VirtAddr,
+    lines: Mutex<[Option<Requested>; LINES as usize]>,
+}
+
+impl Bank {
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset).as_mut_ptr_of()
+    }
+
+    /// Sets bit `line` of one of the split registers.
+    fn write_bit(&self, reg: usize, line: u32, value: bool) {
+        let reg = reg + (line as usize / 16) * 4;
+        let bit = line % 16;
+        // SAFETY: the bank is mapped by `GpioChip::new`
+        unsafe {
+            self.reg(reg)
+                .write_volatile((1 << (bit + 16)) | ((value as u32) << bit))
+        };
+    }
+
+    fn read_bit(&self, reg: usize, line: u32) -> bool {
+        let reg = reg + (line as usize / 16) * 4;
+        // SAFETY: see above
+        unsafe { self.reg(reg).read_volatile() & (1 << (line % 16)) != 0 }
+    }
+
+    fn is_output(&self, line: u32) -> bool {
+        self.read_bit(SWPORT_DDR, line)
+    }
+
+    /// The level of the pin.
+    fn get(&self, line: u32) -> bool {
+        // SAFETY: see above
+        unsafe { self.reg(EXT_PORT).read_volatile() & (1 << line) != 0 }
+    }
+
+    fn set(&self, line: u32, value: bool) {
+        self.write_bit(SWPORT_DR, line, value);
+    }
+
+    fn set_direction(&self, line: u32, output: bool) {
+        self.write_bit(SWPORT_DDR, line, output);
+    }
+}
+
+/// A GPIO bank.
+pub struct GpioChip {
+    index: usize,
+    bank: Arc<Bank>,
+}
+
+impl GpioChip {
+    pub fn new(index: usize) -> Self {
+        let paddr = PhysAddr::from(BANKS[index]);
+        if let Err(err) = axmm::kernel_aspace().lock().map_linear(
+            phys_to_virt(paddr),
+            paddr,
+            PAGE_SIZE_4K,
+            MappingFlags::READ | MappingFlags::WRITE | MappingFlags::DEVICE,
+        ) {
+            // Already mapped if the platform lists it among its MMIO regions.
+            debug!("Failed to map GPIO bank {}: {:?}", index, err);
+        }
+        Self {
+            index,
+            bank: Arc::new(Bank {
+                base: phys_to_virt(paddr),
+                lines: Mutex::new([const { None }; LINES as usize]),
+            }),
+        }
+    }
+
+    /// Returns the v2 flags of `line`, and its consumer if requested.
+    fn line_flags(&self, line: u32) -> VfsResult<(u64, Option<String>)> {
+        if line >= LINES {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut flags = if self.bank.is_output(line) {
+            GPIO_V2_LINE_FLAG_OUTPUT
+        } else {
+            GPIO_V2_LINE_FLAG_INPUT
+        };
+        let consumer = self.bank.lines.lock()[line as usize].as_ref().map(|req| {
+            flags |= GPIO_V2_LINE_FLAG_USED;
+            if req.active_low {
+                flags |= GPIO_V2_LINE_FLAG_ACTIVE_LOW;
+            }
+            req.consumer.clone()
+        });
+        Ok((flags, consumer))
+    }
+
+    /// Requests `offsets` for `consumer`, and returns the file to drive them
+    /// through, as a new file descriptor.
+    fn request(
+        &self,
+        offsets: &[u32],
+        consumer: String,
+        config: &LineConfig,
+        v2: bool,
+    ) -> VfsResult<i32> {
+        if offsets.is_empty() || offsets.len() > GPIO_V2_LINES_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        let configs = line_configs(config, offsets.len())?;
+        let mut lines = self.bank.lines.lock();
+        for (i, &line) in offsets.iter().enumerate() {
+            if line >= LINES || offsets[..i].contains(&line) {
+                return Err(LinuxError::EINVAL);
+            }
+            if lines[line as usize].is_some() {
+                return Err(LinuxError::EBUSY);
+            }
+        }
+        let request = LineRequest {
+            bank: self.bank.clone(),
+            offsets: offsets.to_vec(),
+            active_low: Mutex::new(Vec::new()),
+            v2,
+        };
+        for (&line, config) in offsets.iter().zip(&configs) {
+            lines[line as usize] = Some(Requested {
+                consumer: consumer.clone(),
+                active_low: config.active_low,
+            });
+        }
+        drop(lines);
+        request.configure(&configs);
+        request.add_to_fd_table(true)
+    }
+}
+
+impl DeviceOps for GpioChip {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            GPIO_GET_CHIPINFO_IOCTL => {
+                let mut info = ChipInfo::zeroed();
+                set_str(&mut info.name, &alloc::format!("gpiochip{}", self.index));
+                set_str(&mut info.label, &alloc::format!("gpio{}", self.index));
+                info.lines = LINES;
+                (arg as *mut ChipInfo).vm_write(info)?;
+            }
+            GPIO_V2_GET_LINEINFO_IOCTL => {
+                let offset = (arg as *const LineInfo).vm_read()?.offset;
+                let (flags, consumer) = self.line_flags(offset)?;
+                let mut info = LineInfo::zeroed();
+                info.offset = offset;
+                info.flags = flags;
+                set_str(&mut info.consumer, consumer.as_deref().unwrap_or_default());
+                (arg as *mut LineInfo).vm_write(info)?;
+            }
+            GPIO_GET_LINEINFO_IOCTL => {
+                let line_offset = (arg as *const LineInfoV1).vm_read()?.line_offset;
+                let (flags, consumer) = self.line_flags(line_offset)?;
+                let mut info = LineInfoV1::zeroed();
+                info.line_offset = line_offset;
+                for (v2, v1) in [
+                    (GPIO_V2_LINE_FLAG_USED, GPIOLINE_FLAG_KERNEL),
+                    (GPIO_V2_LINE_FLAG_OUTPUT, GPIOLINE_FLAG_IS_OUT),
+                    (GPIO_V2_LINE_FLAG_ACTIVE_LOW, GPIOLINE_FLAG_ACTIVE_LOW),
+                ] {
+                    if flags & v2 != 0 {
+                        info.flags |= v1;
+                    }
+                }
+                set_str(&mut info.consumer, consumer.as_deref().unwrap_or_default());
+                (arg as *mut LineInfoV1).vm_write(info)?;
+            }
+            GPIO_V2_GET_LINE_IOCTL => {
+                let mut req = (arg as *const LineRequestArgs).vm_read()?;
+                let num_lines = (req.num_lines as usize).min(GPIO_V2_LINES_MAX + 1);
+                let offsets = req.offsets.get(..num_lines).ok_or(LinuxError::EINVAL)?;
+                req.fd = self.request(offsets, get_str(&req.consumer), &req.config, true)?;
+                (arg as *mut LineRequestArgs).vm_write(req)?;
+            }
+            GPIO_GET_LINEHANDLE_IOCTL => {
+                let mut req = (arg as *const HandleRequest).vm_read()?;
+                let num_lines = (req.lines as usize).min(GPIO_V2_LINES_MAX + 1);
+                let offsets = req.lineoffsets.get(..num_lines).ok_or(LinuxError::EINVAL)?;
+                let config = handle_config(&req, num_lines)?;
+                req.fd = self.request(offsets, get_str(&req.consumer_label), &config, false)?;
+                (arg as *mut HandleRequest).vm_write(req)?;
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+/// How a requested line is to be set up.
+#[derive(Clone, Copy)]
+struct Config {
+    active_low: bool,
+    /// Whether the line is an output, or `None` to leave it as it is.
+    output: Option<bool>,
+    value: bool,
+}
+
+/// Returns the configuration of each of the `num_lines` lines of a request.
+fn line_configs(config: &LineConfig, num_lines: usize) -> VfsResult<Vec<Config>> {
+    let attrs = config
+        .attrs
+        .get(..config.num_attrs as usize)
+        .ok_or(LinuxError::EINVAL)?;
+    (0..num_lines)
+        .map(|i| {
+            let for_line = |id| {
+                attrs
+                    .iter()
+                    .find(|it| it.attr.id == id && it.mask & (1 << i) != 0)
+                    .map(|it| it.attr.value)
+            };
+            let flags = for_line(GPIO_V2_LINE_ATTR_ID_FLAGS).unwrap_or(config.flags);
+            let supported =
+                GPIO_V2_LINE_FLAG_ACTIVE_LOW | GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_OUTPUT;
+            if flags & !supported != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            let output = match (
+                flags & GPIO_V2_LINE_FLAG_INPUT != 0,
+                flags & GPIO_V2_LINE_FLAG_OUTPUT != 0,
+            ) {
+                (true, true) => return Err(LinuxError::EINVAL),
+                (true, false) => Some(false),
+                (false, true) => Some(true),
+                (false, false) => None,
+            };
+            let values = for_line(GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES).unwrap_or(0);
+            Ok(Config {
+                active_low: flags & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0,
+                output,
+                value: values & (1 << i) != 0,
+            })
+        })
+        .collect()
+}
+
+/// Translates a v1 handle request into the v2 configuration of its lines.
+fn handle_config(req: &HandleRequest, num_lines: usize) -> VfsResult<LineConfig> {
+    let supported =
+        GPIOHANDLE_REQUEST_INPUT | GPIOHANDLE_REQUEST_OUTPUT | GPIOHANDLE_REQUEST_ACTIVE_LOW;
+    if req.flags & !supported != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut flags = 0;
+    for (v1, v2) in [
+        (GPIOHANDLE_REQUEST_INPUT, GPIO_V2_LINE_FLAG_INPUT),
+        (GPIOHANDLE_REQUEST_OUTPUT, GPIO_V2_LINE_FLAG_OUTPUT),
+        (GPIOHANDLE_REQUEST_ACTIVE_LOW, GPIO_V2_LINE_FLAG_ACTIVE_LOW),
+    ] {
+        if req.flags & v1 != 0 {
+            flags |= v2;
+        }
+    }
+    let mut config = LineConfig::zeroed();
+    config.flags = flags;
+    if flags & GPIO_V2_LINE_FLAG_OUTPUT != 0 {
+        config.num_attrs = 1;
+        config.attrs[0].attr.id = GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES;
+        for (i, &value) in req.default_values[..num_lines].iter().enumerate() {
+            if value != 0 {
+                config.attrs[0].attr.value |= 1 << i;
+            }
+        }
+        config.attrs[0].mask = u64::MAX;
+    }
+    Ok(config)
+}
+
+/// The lines of a bank requested together, as returned by
+/// `GPIO_V2_GET_LINE_IOCTL` or `GPIO_GET_LINEHANDLE_IOCTL`.
+struct LineRequest {
+    bank: Arc<Bank>,
+    offsets: Vec<u32>,
+    /// Whether each line is active low.
+    active_low: Mutex<Vec<bool>>,
+    /// Whether this came from the v2 uAPI, which decides the ioctls it
+    /// answers.
+    v2: bool,
+}
+
+impl LineRequest {
+    fn configure(&self, configs: &[Config]) {
+        let mut active_low = self.active_low.lock();
+        active_low.clear();
+        for (&line, config) in self.offsets.iter().zip(configs) {
+            match config.output {
+                Some(true) => {
+                    self.bank.set(line, config.value != config.active_low);
+                    self.bank.set_direction(line, true);
+                }
+                Some(false) => self.bank.set_direction(line, false),
+                None => {}
+            }
+            active_low.push(config.active_low);
+            if let Some(req) = &mut self.bank.lines.lock()[line as usize] {
+                req.active_low = config.active_low;
+            }
+        }
+    }
+
+    /// The logical value of the `i`-th line.
+    fn get(&self, i: usize) -> bool {
+        self.bank.get(self.offsets[i]) != self.active_low.lock()[i]
+    }
+
+    /// Sets the logical value of the `i`-th line, which must be an output.
+    fn set(&self, i: usize, value: bool) -> LinuxResult<()> {
+        let line = self.offsets[i];
+        if !self.bank.is_output(line) {
+            return Err(LinuxError::EPERM);
+        }
+        self.bank.set(line, value != self.active_low.lock()[i]);
+        Ok(())
+    }
+}
+
+impl Drop for LineRequest {
+    fn drop(&mut self) {
+        let mut lines = self.bank.lines.lock();
+        for &line in &self.offsets {
+            lines[line as usize] = None;
+        }
+    }
+}
+
+impl FileLike for LineRequest {
+    fn read(&self, _dst: &mut SealedBufMut) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write(&self, _src: &mut SealedBuf) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<Kstat> {
+        Ok(Kstat::default())
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn path(&self) -> Cow<str> {
+        "anon_inode:gpio-line".into()
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
+        let num_lines = self.offsets.len();
+        match (cmd, self.v2) {
+            (GPIO_V2_LINE_GET_VALUES_IOCTL, true) => {
+                let mut values = (arg as *const LineValues).vm_read()?;
+                values.bits = 0;
+                for i in (0..num_lines).filter(|i| values.mask & (1 << i) != 0) {
+                    values.bits |= (self.get(i) as u64) << i;
+                }
+                (arg as *mut LineValues).vm_write(values)?;
+            }
+            (GPIO_V2_LINE_SET_VALUES_IOCTL, true) => {
+                let values = (arg as *const LineValues).vm_read()?;
+                for i in (0..num_lines).filter(|i| values.mask & (1 << i) != 0) {
+                    self.set(i, values.bits & (1 << i) != 0)?;
+                }
+            }
+            (GPIO_V2_LINE_SET_CONFIG_IOCTL, true) => {
+                let config = (arg as *const LineConfig).vm_read()?;
+                self.configure(&line_configs(&config, num_lines)?);
+            }
+            (GPIOHANDLE_GET_LINE_VALUES_IOCTL, false) => {
+                let mut data = HandleData::zeroed();
+                for i in 0..num_lines {
+                    data.values[i] = self.get(i) as u8;
+                }
+                (arg as *mut HandleData).vm_write(data)?;
+            }
+            (GPIOHANDLE_SET_LINE_VALUES_IOCTL, false) => {
+                let data = (arg as *const HandleData).vm_read()?;
+                for i in 0..num_lines {
+                    self.set(i, data.values[i] != 0)?;
+                }
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+impl Pollable for LineRequest {
+    fn poll(&self) -> IoEvents {
+        IoEvents::empty()
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
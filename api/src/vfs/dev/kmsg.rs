@@ -0,0 +1,157 @@
+//! `/dev/kmsg`, the kernel log as structured records.
+//!
+//! Every open file reads the records from the oldest one kept, one record
+//! per `read`, formatted as `prio,seq,usec,-;text`. A reader that falls behind
+//! the ring buffer gets `EPIPE` once and continues from the oldest record.
+//! Writes add a record, with the priority from a leading `<N>` if any.
+
+use alloc::{format, sync::Arc};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
+
+use axerrno::LinuxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use axio::{IoEvents, Pollable};
+use linux_raw_sys::general::{SEEK_DATA, SEEK_END, SEEK_SET};
+use starry_core::vfs::{Device, DeviceOps, SimpleFs};
+
+use crate::logging::{add_record, read_record, record_range, register_record_waker};
+
+pub const KMSG_DEVICE_ID: DeviceId = DeviceId::new(1, 11);
+
+/// The facility of records written by user space.
+const LOG_USER: u8 = 1 << 3;
+/// The level of records written by user space without one.
+const DEFAULT_LEVEL: u8 = 4;
+/// The longest record that can be written.
+const LOG_LINE_MAX: usize = 1024;
+
+/// `/dev/kmsg`, or a file opened on it.
+pub struct Kmsg {
+    fs: Arc<SimpleFs>,
+    /// The next record to read.
+    seq: AtomicU64,
+}
+
+impl Kmsg {
+    pub fn new(fs: Arc<SimpleFs>) -> Self {
+        let (first, ..) = record_range();
+        Self {
+            fs,
+            seq: AtomicU64::new(first),
+        }
+    }
+
+    /// Opens the device, with a reader of its own starting at the oldest
+    /// record.
+    pub fn open(&self) -> Arc<Device> {
+        Device::new(
+            self.fs.clone(),
+            NodeType::CharacterDevice,
+            KMSG_DEVICE_ID,
+            Arc::new(Self::new(self.fs.clone())),
+        )
+    }
+
+    /// Moves the reader, for `lseek`: `SEEK_SET` to the oldest record,
+    /// `SEEK_END` past the newest and `SEEK_DATA` to the first one after the
+    /// last clear. Only offset 0 is allowed.
+    pub fn seek(&self, offset: i64, whence: u32) -> VfsResult<u64> {
+        if offset != 0 {
+            return Err(LinuxError::ESPIPE);
+        }
+        let (first, cleared, next) = record_range();
+        let seq = match whence {
+            SEEK_SET => first,
+            SEEK_END => next,
+            SEEK_DATA => cleared,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        self.seq.store(seq, Ordering::Relaxed);
+        Ok(0)
+    }
+}
+
+impl DeviceOps for Kmsg {
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        let seq = self.seq.load(Ordering::Relaxed);
+        let record = match read_record(seq) {
+            Ok(Some(record)) => record,
+            Ok(None) => return Err(LinuxError::EAGAIN),
+            Err(first) => {
+                self.seq.store(first, Ordering::Relaxed);
+                return Err(LinuxError::EPIPE);
+            }
+        };
+        let mut line = format!(
+            "{},{},{},-;",
+            record.prio,
+            record.seq,
+            record.time.as_micros()
+        );
+        for ch in record.text.chars() {
+            if ch.is_control() {
+                line.push_str(&format!("\\x{:02x}", ch as u32));
+            } else {
+                line.push(ch);
+            }
+        }
+        line.push('\n');
+        if buf.len() < line.len() {
+            return Err(LinuxError::EINVAL);
+        }
+        buf[..line.len()].copy_from_slice(line.as_bytes());
+        self.seq.store(seq + 1, Ordering::Relaxed);
+        Ok(line.len())
+    }
+
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        if buf.len() > LOG_LINE_MAX {
+            return Err(LinuxError::EINVAL);
+        }
+        let text = str::from_utf8(buf).map_err(|_| LinuxError::EINVAL)?;
+        let (prio, text) = text
+            .strip_prefix('<')
+            .and_then(|rest| rest.split_once('>'))
+            .and_then(|(prio, text)| Some((prio.parse::<u8>().ok()?, text)))
+            .unwrap_or((LOG_USER | DEFAULT_LEVEL, text));
+        // Only user facilities may be written, as on Linux.
+        let prio = if prio >> 3 == 0 {
+            prio | LOG_USER
+        } else {
+            prio
+        };
+        add_record(prio, text);
+        Ok(buf.len())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for Kmsg {
+    fn poll(&self) -> IoEvents {
+        let (.., next) = record_range();
+        let mut events = IoEvents::OUT;
+        events.set(IoEvents::IN, self.seq.load(Ordering::Relaxed) < next);
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            register_record_waker(context.waker());
+        }
+    }
+}
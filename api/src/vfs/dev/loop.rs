@@ -1,3 +1,4 @@
+use alloc::format;
 use core::{
     any::Any,
     sync::atomic::{AtomicBool, AtomicU32, Ordering},
@@ -8,24 +9,88 @@ use axfs_ng::FileBackend;
 use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
 use axsync::Mutex;
 use linux_raw_sys::{
-    ioctl::{BLKGETSIZE, BLKGETSIZE64, BLKRAGET, BLKRASET, BLKROGET, BLKROSET},
-    loop_device::{LOOP_CLR_FD, LOOP_GET_STATUS, LOOP_SET_FD, LOOP_SET_STATUS, loop_info},
+    ioctl::{
+        BLKDISCARD, BLKDISCARDZEROES, BLKGETSIZE, BLKGETSIZE64, BLKRAGET, BLKRASET, BLKROGET,
+        BLKROSET, BLKZEROOUT,
+    },
+    loop_device::{
+        LO_CRYPT_NONE, LO_FLAGS_AUTOCLEAR, LO_FLAGS_DIRECT_IO, LO_FLAGS_PARTSCAN,
+        LO_FLAGS_READ_ONLY, LOOP_CLR_FD, LOOP_CONFIGURE, LOOP_GET_STATUS, LOOP_GET_STATUS64,
+        LOOP_SET_CAPACITY, LOOP_SET_FD, LOOP_SET_STATUS, LOOP_SET_STATUS64, loop_config, loop_info,
+        loop_info64,
+    },
+};
+use starry_core::{
+    task::offload,
+    vfs::{DeviceMmap, DeviceOps},
 };
-use starry_core::vfs::{DeviceMmap, DeviceOps};
 use starry_vm::{VmMutPtr, VmPtr};
 
-use crate::file::get_file_like;
+use super::uevent;
+use crate::{file::get_file_like, logging::RateLimit, vfs};
+
+/// Flags that `LOOP_SET_STATUS*` may change.
+const SETTABLE_FLAGS: u32 = LO_FLAGS_AUTOCLEAR as u32 | LO_FLAGS_PARTSCAN as u32;
+/// Flags that `LOOP_CONFIGURE` may set.
+const CONFIGURE_FLAGS: u32 = SETTABLE_FLAGS | LO_FLAGS_READ_ONLY as u32 | LO_FLAGS_DIRECT_IO as u32;
+
+/// The backing file of a loop device and how it is mapped.
+struct Binding {
+    file: FileBackend,
+    /// Offset of the device in the file, in bytes.
+    offset: u64,
+    /// Size of the device in bytes, or 0 for up to the end of the file.
+    size_limit: u64,
+    /// `LO_FLAGS_*`
+    flags: u32,
+    file_name: [u8; 64],
+}
+
+impl Binding {
+    fn new(file: FileBackend) -> Self {
+        Self {
+            file,
+            offset: 0,
+            size_limit: 0,
+            flags: 0,
+            file_name: [0; 64],
+        }
+    }
+
+    /// Size of the device in bytes.
+    fn size(&self) -> VfsResult<u64> {
+        let size = self.file.location().len()?.saturating_sub(self.offset);
+        Ok(match self.size_limit {
+            0 => size,
+            limit => size.min(limit),
+        })
+    }
+
+    /// Applies the parameters of `LOOP_SET_STATUS*` or `LOOP_CONFIGURE`,
+    /// where the flags in `settable` may be changed.
+    fn set_info(&mut self, info: &loop_info64, settable: u32) -> LinuxResult<()> {
+        if info.lo_encrypt_type != LO_CRYPT_NONE || info.lo_encrypt_key_size != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        self.offset = info.lo_offset;
+        self.size_limit = info.lo_sizelimit;
+        self.flags = (self.flags & !settable) | (info.lo_flags & settable);
+        self.file_name = info.lo_file_name;
+        self.file_name[63] = 0;
+        Ok(())
+    }
+}
 
 /// /dev/loopX devices
 pub struct LoopDevice {
     number: u32,
     dev_id: DeviceId,
     /// Underlying file for the loop device, if any.
-    pub file: Mutex<Option<FileBackend>>,
-    /// Read-only flag for the loop device.
-    pub ro: AtomicBool,
+    binding: Mutex<Option<Binding>>,
+    /// Read-only flag set with `BLKROSET`.
+    ro: AtomicBool,
     /// Read-ahead size for the loop device, in bytes.
-    pub ra: AtomicU32,
+    ra: AtomicU32,
 }
 
 impl LoopDevice {
@@ -33,94 +98,264 @@ impl LoopDevice {
         Self {
             number,
             dev_id,
-            file: Mutex::new(None),
+            binding: Mutex::new(None),
             ro: AtomicBool::new(false),
             ra: AtomicU32::new(512),
         }
     }
 
-    /// Get information about the loop device.
-    pub fn get_info(&self) -> LinuxResult<loop_info> {
-        if self.file.lock().is_none() {
-            return Err(LinuxError::ENXIO);
+    /// Size of the device in bytes, which is 0 while it has no backing file.
+    pub(crate) fn size(&self) -> u64 {
+        let guard = self.binding.lock();
+        guard
+            .as_ref()
+            .and_then(|binding| binding.size().ok())
+            .unwrap_or(0)
+    }
+
+    /// Tells device managers that the backing file was set or cleared.
+    fn emit_change(&self) {
+        let name = format!("loop{}", self.number);
+        uevent::emit(uevent::Action::Change, "block", &name, self.dev_id);
+    }
+
+    /// Binds the file opened as `fd` to the loop device.
+    fn bind(
+        &self,
+        fd: i32,
+        configure: impl FnOnce(&mut Binding) -> LinuxResult<()>,
+    ) -> LinuxResult<()> {
+        if fd < 0 {
+            return Err(LinuxError::EBADF);
         }
-        let mut res: loop_info = unsafe { core::mem::zeroed() };
-        res.lo_number = self.number as _;
-        res.lo_rdevice = self.dev_id.0 as _;
+        let f = get_file_like(fd)?;
+        let Ok(file) = f.into_any().downcast::<crate::file::File>() else {
+            return Err(LinuxError::EINVAL);
+        };
+        let backend = file.inner().backend()?.clone();
+        // Getting the size may have to go through a slow filesystem, which
+        // should not keep `losetup` from being interrupted.
+        let probe = backend.clone();
+        offload("loop-set-fd", move |_| probe.location().len())?;
+
+        let mut guard = self.binding.lock();
+        if guard.is_some() {
+            return Err(LinuxError::EBUSY);
+        }
+        // Configured before being made visible, so that the device is never
+        // seen half set up, e.g. by a mount right after `losetup`.
+        let mut binding = Binding::new(backend);
+        configure(&mut binding)?;
+        *guard = Some(binding);
+        drop(guard);
+        self.emit_change();
+        Ok(())
+    }
+
+    /// Get information about the loop device.
+    pub fn get_info(&self) -> LinuxResult<loop_info64> {
+        let guard = self.binding.lock();
+        let binding = guard.as_ref().ok_or(LinuxError::ENXIO)?;
+        let metadata = binding.file.location().metadata()?;
+        let mut res: loop_info64 = unsafe { core::mem::zeroed() };
+        res.lo_device = metadata.device;
+        res.lo_inode = metadata.inode;
+        res.lo_rdevice = self.dev_id.0;
+        res.lo_offset = binding.offset;
+        res.lo_sizelimit = binding.size_limit;
+        res.lo_number = self.number;
+        res.lo_flags = binding.flags;
+        res.lo_file_name = binding.file_name;
         Ok(res)
     }
 
     /// Set information for the loop device.
-    pub fn set_info(&self, _src: loop_info) -> LinuxResult<()> {
-        Ok(())
+    pub fn set_info(&self, info: &loop_info64) -> LinuxResult<()> {
+        let mut guard = self.binding.lock();
+        guard
+            .as_mut()
+            .ok_or(LinuxError::ENXIO)?
+            .set_info(info, SETTABLE_FLAGS)
+    }
+
+    /// Checks that `len` bytes at `offset` may be discarded or zeroed, and
+    /// returns the backing file with where they are in it.
+    fn block_range(&self, offset: u64, len: u64) -> LinuxResult<(FileBackend, u64, u64)> {
+        if offset % 512 != 0 || len % 512 != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        if self.is_read_only() {
+            return Err(LinuxError::EROFS);
+        }
+        let (file, base, size) = {
+            let guard = self.binding.lock();
+            let binding = guard.as_ref().ok_or(LinuxError::ENXIO)?;
+            (binding.file.clone(), binding.offset, binding.size()?)
+        };
+        let end = offset.checked_add(len).ok_or(LinuxError::EINVAL)?;
+        if end > size {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok((file, base + offset, base + end))
+    }
+
+    /// Zeroes `len` bytes at `offset`, for `BLKZEROOUT`.
+    fn zero_range(&self, offset: u64, len: u64) -> LinuxResult<()> {
+        const ZEROES: [u8; 4096] = [0; 4096];
+
+        let (file, start, end) = self.block_range(offset, len)?;
+        // Zeroing a whole device takes a while, so it runs off the caller,
+        // which may give up with a signal. The zeroing then stops too.
+        offload("loop-zeroout", move |cancellation| {
+            let mut pos = start;
+            while pos < end {
+                cancellation.check()?;
+                let chunk = (end - pos).min(ZEROES.len() as u64) as usize;
+                pos += file.write_at(&mut &ZEROES[..chunk], pos)? as u64;
+            }
+            Ok(())
+        })
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.ro.load(Ordering::Relaxed)
+            || self
+                .binding
+                .lock()
+                .as_ref()
+                .is_some_and(|it| it.flags & LO_FLAGS_READ_ONLY as u32 != 0)
+    }
+}
+
+fn info_to_old(info: &loop_info64) -> loop_info {
+    let mut res: loop_info = unsafe { core::mem::zeroed() };
+    res.lo_number = info.lo_number as _;
+    res.lo_device = info.lo_device as _;
+    res.lo_inode = info.lo_inode as _;
+    res.lo_rdevice = info.lo_rdevice as _;
+    res.lo_offset = info.lo_offset as _;
+    res.lo_flags = info.lo_flags as _;
+    for (dst, src) in res.lo_name.iter_mut().zip(info.lo_file_name) {
+        *dst = src as _;
     }
+    res
+}
 
-    /// Clone the underlying file of the loop device.
-    pub fn clone_file(&self) -> VfsResult<FileBackend> {
-        let file = self.file.lock().clone();
-        file.ok_or(LinuxError::ENXIO)
+fn info_from_old(info: &loop_info) -> loop_info64 {
+    let mut res: loop_info64 = unsafe { core::mem::zeroed() };
+    res.lo_number = info.lo_number as _;
+    res.lo_device = info.lo_device as _;
+    res.lo_inode = info.lo_inode as _;
+    res.lo_rdevice = info.lo_rdevice as _;
+    res.lo_offset = info.lo_offset as u32 as _;
+    res.lo_encrypt_type = info.lo_encrypt_type as _;
+    res.lo_encrypt_key_size = info.lo_encrypt_key_size as _;
+    res.lo_flags = info.lo_flags as _;
+    for (dst, src) in res.lo_file_name.iter_mut().zip(info.lo_name) {
+        *dst = src as _;
     }
+    res
 }
 
 impl DeviceOps for LoopDevice {
-    fn read_at(&self, mut buf: &mut [u8], offset: u64) -> VfsResult<usize> {
-        let file = self.file.lock().clone();
-        file.ok_or(LinuxError::EPERM)?.read_at(&mut buf, offset)
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        let guard = self.binding.lock();
+        let binding = guard.as_ref().ok_or(LinuxError::EPERM)?;
+        let len = binding.size()?.saturating_sub(offset).min(buf.len() as u64) as usize;
+        let file = binding.file.clone();
+        let offset = binding.offset + offset;
+        drop(guard);
+        file.read_at(&mut &mut buf[..len], offset)
     }
 
-    fn write_at(&self, mut buf: &[u8], offset: u64) -> VfsResult<usize> {
-        if self.ro.load(Ordering::Relaxed) {
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        if self.is_read_only() {
             return Err(LinuxError::EROFS);
         }
-        let file = self.file.lock().clone();
-        file.ok_or(LinuxError::EPERM)?.write_at(&mut buf, offset)
+        let guard = self.binding.lock();
+        let binding = guard.as_ref().ok_or(LinuxError::EPERM)?;
+        let len = binding.size()?.saturating_sub(offset).min(buf.len() as u64) as usize;
+        if len == 0 && !buf.is_empty() {
+            return Err(LinuxError::ENOSPC);
+        }
+        let file = binding.file.clone();
+        let offset = binding.offset + offset;
+        drop(guard);
+        file.write_at(&mut &buf[..len], offset)
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
         match cmd {
-            LOOP_SET_FD => {
-                let fd = arg as i32;
-                if fd < 0 {
-                    return Err(LinuxError::EBADF);
-                }
-                let f = get_file_like(fd)?;
-                let Ok(file) = f.into_any().downcast::<crate::file::File>() else {
+            LOOP_SET_FD => self.bind(arg as i32, |_| Ok(()))?,
+            LOOP_CONFIGURE => {
+                // FIXME: AnyBitPattern
+                let config = unsafe { (arg as *const loop_config).vm_read_uninit()?.assume_init() };
+                if config.block_size != 0
+                    && (!config.block_size.is_power_of_two()
+                        || !(512..=4096).contains(&config.block_size))
+                {
                     return Err(LinuxError::EINVAL);
-                };
-                let mut guard = self.file.lock();
-                if guard.is_some() {
-                    return Err(LinuxError::EBUSY);
                 }
-
-                *guard = Some(file.inner().backend()?.clone());
+                self.bind(config.fd as i32, |binding| {
+                    binding.set_info(&config.info, CONFIGURE_FLAGS)
+                })?;
             }
             LOOP_CLR_FD => {
-                let mut guard = self.file.lock();
+                let mut guard = self.binding.lock();
                 if guard.is_none() {
                     return Err(LinuxError::ENXIO);
                 }
                 *guard = None;
+                drop(guard);
+                self.ro.store(false, Ordering::Relaxed);
+                self.emit_change();
             }
             LOOP_GET_STATUS => {
-                (arg as *mut loop_info).vm_write(self.get_info()?)?;
+                (arg as *mut loop_info).vm_write(info_to_old(&self.get_info()?))?;
             }
             LOOP_SET_STATUS => {
                 // FIXME: AnyBitPattern
                 let info = unsafe { (arg as *const loop_info).vm_read_uninit()?.assume_init() };
-                self.set_info(info)?;
+                self.set_info(&info_from_old(&info))?;
+            }
+            LOOP_GET_STATUS64 => {
+                (arg as *mut loop_info64).vm_write(self.get_info()?)?;
+            }
+            LOOP_SET_STATUS64 => {
+                // FIXME: AnyBitPattern
+                let info = unsafe { (arg as *const loop_info64).vm_read_uninit()?.assume_init() };
+                self.set_info(&info)?;
+            }
+            LOOP_SET_CAPACITY => {
+                // The size is always computed from the backing file.
+                if self.binding.lock().is_none() {
+                    return Err(LinuxError::ENXIO);
+                }
             }
             // TODO: the following should apply to any block devices
             BLKGETSIZE | BLKGETSIZE64 => {
-                let file = self.clone_file()?;
-                let sectors = file.location().len()? / 512;
+                let guard = self.binding.lock();
+                let size = guard.as_ref().ok_or(LinuxError::ENXIO)?.size()?;
+                drop(guard);
                 if cmd == BLKGETSIZE {
-                    (arg as *mut u32).vm_write(sectors as _)?;
+                    (arg as *mut u32).vm_write((size / 512) as _)?;
                 } else {
-                    (arg as *mut u64).vm_write(sectors * 512)?;
+                    (arg as *mut u64).vm_write(size)?;
                 }
             }
+            BLKDISCARD => {
+                let [offset, len] = (arg as *const [u64; 2]).vm_read()?;
+                self.discard(offset, len)?;
+            }
+            BLKZEROOUT => {
+                let [offset, len] = (arg as *const [u64; 2]).vm_read()?;
+                self.zero_range(offset, len)?;
+            }
+            BLKDISCARDZEROES => {
+                (arg as *mut u32).vm_write(1)?;
+            }
             BLKROGET => {
-                (arg as *mut u32).vm_write(self.ro.load(Ordering::Relaxed) as u32)?;
+                (arg as *mut u32).vm_write(self.is_read_only() as u32)?;
             }
             BLKROSET => {
                 let ro = (arg as *const u32).vm_read()?;
@@ -137,7 +372,10 @@ impl DeviceOps for LoopDevice {
                     .store((arg as *const u32).vm_read()? as _, Ordering::Relaxed);
             }
             _ => {
-                warn!("unknown ioctl for loop device: {cmd}");
+                static UNKNOWN_IOCTL: RateLimit = RateLimit::new("loop");
+                if UNKNOWN_IOCTL.allow() {
+                    warn!("unknown ioctl for loop device: {cmd}");
+                }
                 return Err(LinuxError::ENOTTY);
             }
         }
@@ -149,14 +387,37 @@ impl DeviceOps for LoopDevice {
     }
 
     fn mmap(&self) -> DeviceMmap {
-        if let Some(FileBackend::Cached(cache)) = self.file.lock().as_ref() {
-            DeviceMmap::Cache(cache.clone())
-        } else {
-            DeviceMmap::None
+        match self.binding.lock().as_ref() {
+            Some(Binding {
+                file: FileBackend::Cached(cache),
+                offset: 0,
+                ..
+            }) => DeviceMmap::Cache(cache.clone()),
+            _ => DeviceMmap::None,
         }
     }
 
     fn flags(&self) -> NodeFlags {
         NodeFlags::NON_CACHEABLE
     }
+
+    fn sync(&self) -> VfsResult<()> {
+        let file = self.binding.lock().as_ref().map(|it| it.file.clone());
+        match file {
+            Some(file) => file.sync(false),
+            None => Ok(()),
+        }
+    }
+
+    fn read_only(&self) -> bool {
+        self.is_read_only()
+    }
+
+    fn discard(&self, offset: u64, len: u64) -> VfsResult<()> {
+        let (file, start, end) = self.block_range(offset, len)?;
+        // This punches a hole into the backing file, which fails if its
+        // filesystem cannot store holes. What the range held still has to be
+        // zeroed, so it runs off the caller as zeroing does.
+        offload("loop-discard", move |_| vfs::punch_hole(&file, start, end))
+    }
 }
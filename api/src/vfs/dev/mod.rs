@@ -1,15 +1,25 @@
 //! Special devices
 
+mod drm;
 #[cfg(feature = "input")]
-mod event;
+pub mod event;
 mod fb;
+#[cfg(feature = "gpio")]
+pub mod gpio;
+pub mod kmsg;
 #[cfg(feature = "dev-log")]
 mod log;
 mod r#loop;
 #[cfg(feature = "memtrack")]
 mod memtrack;
 mod rtc;
+#[cfg(feature = "sound")]
+mod snd;
 pub mod tty;
+pub mod uevent;
+#[cfg(feature = "input")]
+pub mod uinput;
+pub mod uring_lite;
 
 use alloc::{format, sync::Arc};
 use core::any::Any;
@@ -17,15 +27,24 @@ use core::any::Any;
 use axerrno::LinuxError;
 use axfs_ng_vfs::{DeviceId, Filesystem, NodeFlags, NodeType, VfsResult};
 use axsync::Mutex;
+use linux_raw_sys::general::TMPFS_MAGIC;
 #[cfg(feature = "dev-log")]
 pub use log::bind_dev_log;
 use rand::{RngCore, SeedableRng, rngs::SmallRng};
-use starry_core::vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs};
+use starry_core::{
+    task::deterministic_seed,
+    vfs::{Device, DeviceOps, DirMaker, DirMapping, SimpleDir, SimpleFs},
+};
+
+use super::{
+    power,
+    sysfs::{self, SysDevice},
+};
 
 const RANDOM_SEED: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
 
 pub(crate) fn new_devfs() -> Filesystem {
-    SimpleFs::new_with("devfs".into(), 0x01021994, builder)
+    SimpleFs::new_with("devfs".into(), TMPFS_MAGIC, builder)
 }
 
 struct Null;
@@ -76,7 +95,10 @@ struct Random {
 impl Random {
     pub fn new() -> Self {
         Self {
-            rng: Mutex::new(SmallRng::from_seed(*RANDOM_SEED)),
+            rng: Mutex::new(match deterministic_seed() {
+                Some(seed) => SmallRng::seed_from_u64(seed),
+                None => SmallRng::from_seed(*RANDOM_SEED),
+            }),
         }
     }
 }
@@ -188,6 +210,15 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             Arc::new(Random::new()),
         ),
     );
+    root.add(
+        "kmsg",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            kmsg::KMSG_DEVICE_ID,
+            Arc::new(kmsg::Kmsg::new(fs.clone())),
+        ),
+    );
     root.add(
         "rtc0",
         Device::new(
@@ -198,15 +229,32 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         ),
     );
     if axdisplay::has_display() {
+        let fb0 = DeviceId::new(29, 0);
+        let graphics = SysDevice::new("graphics", "fb0", fb0);
+        sysfs::register(graphics.with_attrs(fb::sysfs_attrs));
+        sysfs::register(SysDevice::new("drm", "dri/card0", DeviceId::new(226, 0)));
         root.add(
             "fb0",
             Device::new(
                 fs.clone(),
                 NodeType::CharacterDevice,
-                DeviceId::new(29, 0),
+                fb0,
                 Arc::new(fb::FrameBuffer::new()),
             ),
         );
+        root.add("dri", {
+            let mut dri = DirMapping::new();
+            dri.add(
+                "card0",
+                Device::new(
+                    fs.clone(),
+                    NodeType::CharacterDevice,
+                    DeviceId::new(226, 0),
+                    Arc::new(drm::Drm::new()),
+                ),
+            );
+            SimpleDir::new_maker(fs.clone(), Arc::new(dri))
+        });
     }
 
     root.add(
@@ -224,9 +272,31 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             fs.clone(),
             NodeType::CharacterDevice,
             DeviceId::new(5, 1),
+            tty::console_device(),
+        ),
+    );
+    root.add(
+        "ttyS0",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            DeviceId::new(4, 64),
             tty::N_TTY.clone(),
         ),
     );
+    #[cfg(feature = "hvc")]
+    if let Some(hvc) = tty::HVC.clone() {
+        sysfs::register(SysDevice::new("tty", "hvc0", DeviceId::new(229, 0)));
+        root.add(
+            "hvc0",
+            Device::new(
+                fs.clone(),
+                NodeType::CharacterDevice,
+                DeviceId::new(229, 0),
+                hvc,
+            ),
+        );
+    }
 
     root.add(
         "ptmx",
@@ -267,24 +337,55 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             Arc::new(CpuDmaLatency),
         ),
     );
+    root.add(
+        "uring_lite",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            uring_lite::URING_LITE_DEVICE_ID,
+            Arc::new(uring_lite::UringLite::new(fs.clone())),
+        ),
+    );
 
     // This is mounted to a tmpfs in `new_procfs`
     root.add(
         "shm",
         SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
     );
+    // And this to the mqueue filesystem
+    root.add(
+        "mqueue",
+        SimpleDir::new_maker(fs.clone(), Arc::new(DirMapping::new())),
+    );
+
+    // The other devices shown in /sys/class
+    for (class, name, dev_id) in [
+        ("mem", "null", DeviceId::new(1, 3)),
+        ("mem", "zero", DeviceId::new(1, 5)),
+        ("mem", "full", DeviceId::new(1, 7)),
+        ("mem", "random", DeviceId::new(1, 8)),
+        ("mem", "urandom", DeviceId::new(1, 9)),
+        ("mem", "kmsg", kmsg::KMSG_DEVICE_ID),
+        ("tty", "tty", DeviceId::new(5, 0)),
+        ("tty", "console", DeviceId::new(5, 1)),
+        ("tty", "ptmx", DeviceId::new(5, 2)),
+        ("tty", "ttyS0", DeviceId::new(4, 64)),
+    ] {
+        sysfs::register(SysDevice::new(class, name, dev_id));
+    }
+    let rtc0 = SysDevice::new("rtc", "rtc0", rtc::RTC0_DEVICE_ID);
+    sysfs::register(rtc0.with_attrs(power::rtc_attrs));
 
     // Loop devices
     for i in 0..16 {
-        let dev_id = DeviceId::new(7, 0);
+        let dev_id = DeviceId::new(7, i);
+        let dev = Arc::new(r#loop::LoopDevice::new(i, dev_id));
+        let loop_dev = dev.clone();
+        let block = SysDevice::new("block", format!("loop{i}"), dev_id);
+        sysfs::register(block.with_size(move || loop_dev.size()));
         root.add(
             format!("loop{i}"),
-            Device::new(
-                fs.clone(),
-                NodeType::BlockDevice,
-                dev_id,
-                Arc::new(r#loop::LoopDevice::new(i, dev_id)),
-            ),
+            Device::new(fs.clone(), NodeType::BlockDevice, dev_id, dev),
         );
     }
 
@@ -294,6 +395,55 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
         "input",
         SimpleDir::new_maker(fs.clone(), Arc::new(event::input_devices(fs.clone()))),
     );
+    #[cfg(feature = "input")]
+    root.add(
+        "uinput",
+        Device::new(
+            fs.clone(),
+            NodeType::CharacterDevice,
+            uinput::UINPUT_DEVICE_ID,
+            Arc::new(uinput::Uinput::new(fs.clone())),
+        ),
+    );
+
+    // GPIO banks
+    #[cfg(feature = "gpio")]
+    for bank in 0..gpio::BANKS.len() {
+        root.add(
+            format!("gpiochip{bank}"),
+            Device::new(
+                fs.clone(),
+                NodeType::CharacterDevice,
+                DeviceId::new(gpio::GPIO_MAJOR, bank as u32),
+                Arc::new(gpio::GpioChip::new(bank)),
+            ),
+        );
+    }
+
+    // Sound
+    #[cfg(feature = "sound")]
+    root.add("snd", {
+        let mut snd = DirMapping::new();
+        snd.add(
+            "controlC0",
+            Device::new(
+                fs.clone(),
+                NodeType::CharacterDevice,
+                DeviceId::new(116, 0),
+                Arc::new(snd::Control),
+            ),
+        );
+        snd.add(
+            "pcmC0D0p",
+            Device::new(
+                fs.clone(),
+                NodeType::CharacterDevice,
+                DeviceId::new(116, 16),
+                Arc::new(snd::Pcm::new()),
+            ),
+        );
+        SimpleDir::new_maker(fs.clone(), Arc::new(snd))
+    });
 
     SimpleDir::new_maker(fs, Arc::new(root))
 }
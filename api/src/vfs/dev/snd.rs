@@ -0,0 +1,866 @@
+//! `/dev/snd`: a sound card with one playback-only ALSA PCM device.
+//!
+//! The PCM device, `pcmC0D0p`, takes interleaved frames through
+//! `SNDRV_PCM_IOCTL_WRITEI_FRAMES`, but cannot be mapped. There is no audio
+//! driver to play them to, so they are consumed at the configured rate and
+//! dropped. This still keeps the timing of real hardware: writes block while
+//! the buffer is full, the delay drains as time goes by, and an application
+//! that does not keep up gets an underrun.
+//!
+//! `controlC0` answers the few control ioctls alsa-lib needs to find the PCM
+//! device. It has no mixer controls.
+
+use alloc::sync::Arc;
+use core::{any::Any, task::Context, time::Duration};
+
+use axerrno::LinuxError;
+use axfs_ng_vfs::{NodeFlags, VfsResult};
+use axhal::time::monotonic_time;
+use axio::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use axtask::future::{block_on, block_on_interruptible, sleep};
+use bytemuck::{AnyBitPattern, Zeroable};
+use event_listener::{Event, listener};
+use starry_core::vfs::DeviceOps;
+use starry_vm::{VmMutPtr, VmPtr};
+
+use crate::mm::UserConstPtr;
+
+/// `SNDRV_CTL_IOCTL_PVERSION`, `_IOR('U', 0x00, int)`.
+const CTL_PVERSION: u32 = 0x8004_5500;
+/// `SNDRV_CTL_IOCTL_CARD_INFO`, `_IOR('U', 0x01, struct snd_ctl_card_info)`.
+const CTL_CARD_INFO: u32 = 0x8178_5501;
+/// `SNDRV_CTL_IOCTL_PCM_NEXT_DEVICE`, `_IOR('U', 0x30, int)`.
+const CTL_PCM_NEXT_DEVICE: u32 = 0x8004_5530;
+/// `SNDRV_CTL_IOCTL_PCM_INFO`, `_IOWR('U', 0x31, struct snd_pcm_info)`.
+const CTL_PCM_INFO: u32 = 0xc120_5531;
+/// `SNDRV_CTL_IOCTL_PCM_PREFER_SUBDEVICE`, `_IOW('U', 0x32, int)`.
+const CTL_PCM_PREFER_SUBDEVICE: u32 = 0x4004_5532;
+
+const PCM_PVERSION: u32 = 0x8004_4100;
+const PCM_INFO: u32 = 0x8120_4101;
+const PCM_TTSTAMP: u32 = 0x4004_4103;
+const PCM_USER_PVERSION: u32 = 0x4004_4104;
+const PCM_HW_REFINE: u32 = 0xc260_4110;
+const PCM_HW_PARAMS: u32 = 0xc260_4111;
+const PCM_HW_FREE: u32 = 0x4112;
+const PCM_SW_PARAMS: u32 = 0xc088_4113;
+const PCM_DELAY: u32 = 0x8008_4121;
+const PCM_HWSYNC: u32 = 0x4122;
+const PCM_SYNC_PTR: u32 = 0xc088_4123;
+const PCM_PREPARE: u32 = 0x4140;
+const PCM_RESET: u32 = 0x4141;
+const PCM_START: u32 = 0x4142;
+const PCM_DROP: u32 = 0x4143;
+const PCM_DRAIN: u32 = 0x4144;
+const PCM_WRITEI_FRAMES: u32 = 0x4018_4150;
+
+const CTL_VERSION: u32 = 0x0002_0008;
+const PCM_VERSION: u32 = 0x0002_000f;
+
+const SNDRV_PCM_ACCESS_RW_INTERLEAVED: u32 = 3;
+const SNDRV_PCM_INFO_INTERLEAVED: u32 = 0x100;
+const SNDRV_PCM_SYNC_PTR_AVAIL_MIN: u32 = 4;
+
+/// The formats supported, as `(format, physical bits, significant bits)`.
+const FORMATS: &[(u32, u32, u32)] = &[
+    (0, 8, 8),    // S8
+    (1, 8, 8),    // U8
+    (2, 16, 16),  // S16_LE
+    (4, 16, 16),  // U16_LE
+    (6, 32, 24),  // S24_LE
+    (10, 32, 32), // S32_LE
+    (14, 32, 32), // FLOAT_LE
+];
+
+// The indices of the masks and intervals in `HwParams`, counted from the
+// first of each.
+const ACCESS: usize = 0;
+const FORMAT: usize = 1;
+const SUBFORMAT: usize = 2;
+const SAMPLE_BITS: usize = 0;
+const FRAME_BITS: usize = 1;
+const CHANNELS: usize = 2;
+const RATE: usize = 3;
+const PERIOD_TIME: usize = 4;
+const PERIOD_SIZE: usize = 5;
+const PERIOD_BYTES: usize = 6;
+const PERIODS: usize = 7;
+const BUFFER_TIME: usize = 8;
+const BUFFER_SIZE: usize = 9;
+const BUFFER_BYTES: usize = 10;
+
+const INTERVAL_OPENMIN: u32 = 1 << 0;
+const INTERVAL_OPENMAX: u32 = 1 << 1;
+const INTERVAL_INTEGER: u32 = 1 << 2;
+const INTERVAL_EMPTY: u32 = 1 << 3;
+
+const USEC_PER_SEC: u64 = 1_000_000;
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct CardInfo {
+    card: i32,
+    _pad: i32,
+    id: [u8; 16],
+    driver: [u8; 16],
+    name: [u8; 32],
+    longname: [u8; 80],
+    _reserved: [u8; 16],
+    mixername: [u8; 80],
+    components: [u8; 128],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct PcmInfo {
+    device: u32,
+    subdevice: u32,
+    stream: i32,
+    card: i32,
+    id: [u8; 64],
+    name: [u8; 80],
+    subname: [u8; 32],
+    dev_class: i32,
+    dev_subclass: i32,
+    subdevices_count: u32,
+    subdevices_avail: u32,
+    sync: [u8; 16],
+    _reserved: [u8; 64],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct Interval {
+    min: u32,
+    max: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct HwParams {
+    flags: u32,
+    masks: [[u32; 8]; 3],
+    _mres: [[u32; 8]; 5],
+    intervals: [Interval; 12],
+    _ires: [Interval; 9],
+    rmask: u32,
+    cmask: u32,
+    info: u32,
+    msbits: u32,
+    rate_num: u32,
+    rate_den: u32,
+    fifo_size: u64,
+    _reserved: [u8; 64],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct SwParams {
+    tstamp_mode: i32,
+    period_step: u32,
+    sleep_min: u32,
+    _pad: u32,
+    avail_min: u64,
+    xfer_align: u64,
+    start_threshold: u64,
+    stop_threshold: u64,
+    silence_threshold: u64,
+    silence_size: u64,
+    boundary: u64,
+    proto: u32,
+    tstamp_type: u32,
+    _reserved: [u8; 56],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct SyncPtr {
+    flags: u32,
+    _pad: u32,
+    state: i32,
+    _pad1: i32,
+    hw_ptr: u64,
+    _status: [u8; 48],
+    appl_ptr: u64,
+    avail_min: u64,
+    _control: [u8; 48],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+#[allow(dead_code)]
+struct Xferi {
+    result: i64,
+    buf: usize,
+    frames: u64,
+}
+
+// The sizes are part of the ioctl numbers above.
+const _: () = {
+    assert!(size_of::<CardInfo>() == 376);
+    assert!(size_of::<PcmInfo>() == 288);
+    assert!(size_of::<HwParams>() == 608);
+    assert!(size_of::<SwParams>() == 136);
+    assert!(size_of::<SyncPtr>() == 136);
+    assert!(size_of::<Xferi>() == 24);
+};
+
+/// Copies `s` into a NUL-terminated string field.
+fn set_str<const N: usize>(field: &mut [u8; N], s: &str) {
+    field[..s.len()].copy_from_slice(s.as_bytes());
+}
+
+fn pcm_info() -> PcmInfo {
+    let mut info = PcmInfo::zeroed();
+    set_str(&mut info.id, "Starry PCM");
+    set_str(&mut info.name, "Starry PCM");
+    set_str(&mut info.subname, "subdevice #0");
+    info.subdevices_count = 1;
+    info.subdevices_avail = 1;
+    info
+}
+
+/// The control device of the card.
+pub struct Control;
+
+impl DeviceOps for Control {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            CTL_PVERSION => (arg as *mut u32).vm_write(CTL_VERSION)?,
+            CTL_CARD_INFO => {
+                let mut info = CardInfo::zeroed();
+                set_str(&mut info.id, "Starry");
+                set_str(&mut info.driver, "Starry");
+                set_str(&mut info.name, "Starry");
+                set_str(&mut info.longname, "Starry sound card");
+                (arg as *mut CardInfo).vm_write(info)?;
+            }
+            CTL_PCM_NEXT_DEVICE => {
+                let device = (arg as *const i32).vm_read()?;
+                (arg as *mut i32).vm_write(if device < 0 { 0 } else { -1 })?;
+            }
+            CTL_PCM_INFO => {
+                let query = (arg as *const PcmInfo).vm_read()?;
+                if query.device != 0 || query.subdevice != 0 || query.stream != 0 {
+                    return Err(LinuxError::ENXIO);
+                }
+                (arg as *mut PcmInfo).vm_write(pcm_info())?;
+            }
+            CTL_PCM_PREFER_SUBDEVICE => {}
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+impl Interval {
+    fn new(min: u64, max: u64) -> Self {
+        Self {
+            min: min.min(u32::MAX as u64) as u32,
+            max: max.min(u32::MAX as u64) as u32,
+            flags: INTERVAL_INTEGER,
+        }
+    }
+
+    /// Narrows the interval to `other`, failing if nothing is left.
+    fn refine(&mut self, other: Interval) -> VfsResult<()> {
+        self.min = self.min.max(other.min);
+        self.max = self.max.min(other.max);
+        if self.min > self.max {
+            return Err(LinuxError::EINVAL);
+        }
+        Ok(())
+    }
+
+    /// `a * b / k`.
+    fn mul_div(a: Interval, b: Interval, k: u64) -> Interval {
+        Interval::new(
+            a.min as u64 * b.min as u64 / k,
+            (a.max as u64 * b.max as u64).div_ceil(k),
+        )
+    }
+
+    /// `a * k / b`.
+    fn div_mul(a: Interval, k: u64, b: Interval) -> Interval {
+        Interval::new(
+            a.min as u64 * k / (b.max as u64).max(1),
+            (a.max as u64 * k).div_ceil((b.min as u64).max(1)),
+        )
+    }
+}
+
+impl HwParams {
+    fn mask_first(&self, mask: usize) -> Option<u32> {
+        let bits = &self.masks[mask];
+        (0..256).find(|&bit| bits[bit as usize / 32] & (1 << (bit % 32)) != 0)
+    }
+
+    fn set_mask(&mut self, mask: usize, bit: u32) {
+        self.masks[mask] = [0; 8];
+        self.masks[mask][bit as usize / 32] = 1 << (bit % 32);
+    }
+
+    fn fix(&mut self, interval: usize, value: u32) {
+        self.intervals[interval] = Interval::new(value as u64, value as u64);
+    }
+
+    /// Narrows the parameters to what the device supports and to what
+    /// follows from each other, as `SNDRV_PCM_IOCTL_HW_REFINE` does.
+    fn refine(&mut self) -> VfsResult<()> {
+        let formats = FORMATS
+            .iter()
+            .fold(0, |mask, &(format, ..)| mask | (1 << format));
+        self.masks[ACCESS][0] &= 1 << SNDRV_PCM_ACCESS_RW_INTERLEAVED;
+        self.masks[ACCESS][1..].fill(0);
+        self.masks[FORMAT][0] &= formats;
+        self.masks[FORMAT][1..].fill(0);
+        self.masks[SUBFORMAT][0] &= 1;
+        self.masks[SUBFORMAT][1..].fill(0);
+        if self.masks.iter().any(|mask| mask[0] == 0) {
+            return Err(LinuxError::EINVAL);
+        }
+
+        for interval in &mut self.intervals {
+            if interval.flags & INTERVAL_EMPTY != 0 {
+                return Err(LinuxError::EINVAL);
+            }
+            if interval.flags & INTERVAL_OPENMIN != 0 {
+                interval.min = interval.min.saturating_add(1);
+            }
+            if interval.flags & INTERVAL_OPENMAX != 0 {
+                interval.max = interval.max.saturating_sub(1);
+            }
+            interval.flags = INTERVAL_INTEGER;
+        }
+        let (min_bits, max_bits) = FORMATS
+            .iter()
+            .filter(|&&(format, ..)| self.masks[FORMAT][0] & (1 << format) != 0)
+            .fold((u32::MAX, 0), |(min, max), &(_, bits, _)| {
+                (min.min(bits), max.max(bits))
+            });
+        let limits = [
+            (SAMPLE_BITS, Interval::new(min_bits as _, max_bits as _)),
+            (CHANNELS, Interval::new(1, 8)),
+            (RATE, Interval::new(8000, 192000)),
+            (PERIODS, Interval::new(2, 1024)),
+            (PERIOD_BYTES, Interval::new(64, 256 * 1024)),
+            (BUFFER_BYTES, Interval::new(128, 1024 * 1024)),
+        ];
+        for (interval, limit) in limits {
+            self.intervals[interval].refine(limit)?;
+        }
+
+        // The relations between the parameters, twice over so that each of
+        // them can narrow the ones before it.
+        let rules: [(usize, fn(&[Interval; 12]) -> Interval); 12] = [
+            (FRAME_BITS, |i| {
+                Interval::mul_div(i[SAMPLE_BITS], i[CHANNELS], 1)
+            }),
+            (SAMPLE_BITS, |i| {
+                Interval::div_mul(i[FRAME_BITS], 1, i[CHANNELS])
+            }),
+            (CHANNELS, |i| {
+                Interval::div_mul(i[FRAME_BITS], 1, i[SAMPLE_BITS])
+            }),
+            (PERIOD_BYTES, |i| {
+                Interval::mul_div(i[PERIOD_SIZE], i[FRAME_BITS], 8)
+            }),
+            (PERIOD_SIZE, |i| {
+                Interval::div_mul(i[PERIOD_BYTES], 8, i[FRAME_BITS])
+            }),
+            (PERIOD_TIME, |i| {
+                Interval::div_mul(i[PERIOD_SIZE], USEC_PER_SEC, i[RATE])
+            }),
+            (PERIOD_SIZE, |i| {
+                Interval::mul_div(i[PERIOD_TIME], i[RATE], USEC_PER_SEC)
+            }),
+            (BUFFER_SIZE, |i| {
+                Interval::mul_div(i[PERIOD_SIZE], i[PERIODS], 1)
+            }),
+            (PERIODS, |i| {
+                Interval::div_mul(i[BUFFER_SIZE], 1, i[PERIOD_SIZE])
+            }),
+            (BUFFER_BYTES, |i| {
+                Interval::mul_div(i[BUFFER_SIZE], i[FRAME_BITS], 8)
+            }),
+            (BUFFER_SIZE, |i| {
+                Interval::div_mul(i[BUFFER_BYTES], 8, i[FRAME_BITS])
+            }),
+            (BUFFER_TIME, |i| {
+                Interval::div_mul(i[BUFFER_SIZE], USEC_PER_SEC, i[RATE])
+            }),
+        ];
+        for _ in 0..2 {
+            for (interval, rule) in rules {
+                let derived = rule(&self.intervals);
+                self.intervals[interval].refine(derived)?;
+            }
+        }
+
+        self.cmask = self.rmask;
+        self.info = SNDRV_PCM_INFO_INTERLEAVED;
+        let format = self.mask_first(FORMAT);
+        self.msbits = match format {
+            Some(format) if self.masks[FORMAT][0] == 1 << format => FORMATS
+                .iter()
+                .find(|&&(it, ..)| it == format)
+                .map_or(0, |&(.., msbits)| msbits),
+            _ => 0,
+        };
+        let rate = self.intervals[RATE];
+        (self.rate_num, self.rate_den) = if rate.min == rate.max {
+            (rate.min, 1)
+        } else {
+            (0, 0)
+        };
+        self.fifo_size = 0;
+        Ok(())
+    }
+
+    /// Fixes every parameter to one value, choosing as
+    /// `SNDRV_PCM_IOCTL_HW_PARAMS` does: the first of each, except for the
+    /// largest buffer.
+    fn choose(&mut self) -> VfsResult<()> {
+        self.refine()?;
+        for mask in [ACCESS, FORMAT, SUBFORMAT] {
+            let bit = self.mask_first(mask).ok_or(LinuxError::EINVAL)?;
+            self.set_mask(mask, bit);
+        }
+        for interval in [
+            CHANNELS,
+            RATE,
+            PERIOD_TIME,
+            BUFFER_SIZE,
+            PERIOD_SIZE,
+            PERIODS,
+        ] {
+            self.refine()?;
+            let value = &self.intervals[interval];
+            let value = if interval == BUFFER_SIZE {
+                value.max
+            } else {
+                value.min
+            };
+            self.fix(interval, value);
+        }
+        self.refine()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+enum State {
+    Open     = 0,
+    Setup    = 1,
+    Prepared = 2,
+    Running  = 3,
+    Xrun     = 4,
+    Draining = 5,
+}
+
+/// The configuration set with `SNDRV_PCM_IOCTL_HW_PARAMS` and
+/// `SNDRV_PCM_IOCTL_SW_PARAMS`, in frames.
+#[derive(Clone, Copy)]
+struct Config {
+    rate: u64,
+    frame_bytes: usize,
+    period_size: u64,
+    buffer_size: u64,
+    avail_min: u64,
+    start_threshold: u64,
+    stop_threshold: u64,
+}
+
+impl Config {
+    fn frames_to_duration(&self, frames: u64) -> Duration {
+        Duration::from_nanos(frames * NANOS_PER_SEC / self.rate)
+    }
+}
+
+struct Stream {
+    state: State,
+    config: Option<Config>,
+    /// The frames written so far.
+    appl_ptr: u64,
+    /// The frames played so far.
+    hw_ptr: u64,
+    /// When playback was at `base_ptr`.
+    base_time: Duration,
+    base_ptr: u64,
+}
+
+impl Stream {
+    fn config(&self) -> VfsResult<Config> {
+        self.config.ok_or(LinuxError::EBADFD)
+    }
+
+    /// The frames written but not played yet.
+    fn delay(&self) -> u64 {
+        self.appl_ptr - self.hw_ptr
+    }
+
+    /// Room in the buffer for writing.
+    fn avail(&self, config: &Config) -> u64 {
+        config.buffer_size - self.delay()
+    }
+
+    fn start(&mut self) {
+        self.state = State::Running;
+        self.base_time = monotonic_time();
+        self.base_ptr = self.hw_ptr;
+    }
+
+    /// Moves the playback position on to the current time.
+    fn update(&mut self) {
+        let (State::Running | State::Draining, Some(config)) = (self.state, self.config) else {
+            return;
+        };
+        let now = monotonic_time();
+        let played = (now - self.base_time).as_nanos() as u64 * config.rate / NANOS_PER_SEC;
+        self.hw_ptr = (self.base_ptr + played).min(self.appl_ptr);
+        if self.hw_ptr < self.appl_ptr {
+            return;
+        }
+        if self.state == State::Draining {
+            self.state = State::Setup;
+        } else if config.stop_threshold <= config.buffer_size {
+            self.state = State::Xrun;
+        } else {
+            // Play silence until there is more to play.
+            self.base_time = now;
+            self.base_ptr = self.hw_ptr;
+        }
+    }
+}
+
+struct PcmInner {
+    stream: Mutex<Stream>,
+    poll_out: PollSet,
+    started: Event,
+}
+
+/// Wakes up those polling the device at every period, while it plays.
+async fn period_task(pcm: Arc<PcmInner>) {
+    loop {
+        let period = {
+            let mut stream = pcm.stream.lock();
+            stream.update();
+            match (stream.state, stream.config) {
+                (State::Running | State::Draining, Some(config)) => {
+                    Some(config.frames_to_duration(config.period_size))
+                }
+                _ => None,
+            }
+        };
+        pcm.poll_out.wake();
+        match period {
+            Some(period) => sleep(period).await,
+            None => {
+                listener!(pcm.started => listener);
+                if matches!(pcm.stream.lock().state, State::Running | State::Draining) {
+                    continue;
+                }
+                listener.await;
+            }
+        }
+    }
+}
+
+/// The playback PCM device of the card.
+pub struct Pcm(Arc<PcmInner>);
+
+impl Pcm {
+    pub fn new() -> Self {
+        let inner = Arc::new(PcmInner {
+            stream: Mutex::new(Stream {
+                state: State::Open,
+                config: None,
+                appl_ptr: 0,
+                hw_ptr: 0,
+                base_time: Duration::ZERO,
+                base_ptr: 0,
+            }),
+            poll_out: PollSet::new(),
+            started: Event::new(),
+        });
+        let task = inner.clone();
+        axtask::spawn(move || block_on(period_task(task)), "pcm-period".into());
+        Self(inner)
+    }
+
+    fn start(&self, stream: &mut Stream) {
+        stream.start();
+        self.0.started.notify(1);
+    }
+
+    fn hw_params(&self, arg: usize) -> VfsResult<()> {
+        let mut stream = self.0.stream.lock();
+        if !matches!(stream.state, State::Open | State::Setup | State::Prepared) {
+            return Err(LinuxError::EBADFD);
+        }
+        let mut params = (arg as *const HwParams).vm_read()?;
+        params.choose()?;
+        let interval = |i: usize| params.intervals[i].min as u64;
+        let buffer_size = interval(BUFFER_SIZE);
+        stream.config = Some(Config {
+            rate: interval(RATE),
+            frame_bytes: interval(FRAME_BITS) as usize / 8,
+            period_size: interval(PERIOD_SIZE),
+            buffer_size,
+            avail_min: interval(PERIOD_SIZE),
+            start_threshold: 1,
+            stop_threshold: buffer_size,
+        });
+        stream.state = State::Setup;
+        (arg as *mut HwParams).vm_write(params)?;
+        Ok(())
+    }
+
+    fn sw_params(&self, arg: usize) -> VfsResult<()> {
+        let params = (arg as *const SwParams).vm_read()?;
+        if params.avail_min == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut stream = self.0.stream.lock();
+        let config = stream.config.as_mut().ok_or(LinuxError::EBADFD)?;
+        config.avail_min = params.avail_min;
+        config.start_threshold = params.start_threshold;
+        config.stop_threshold = params.stop_threshold;
+        Ok(())
+    }
+
+    /// Writes `frames` frames from `buf`, waiting for room as needed.
+    /// Returns how many were written.
+    fn write_frames(&self, buf: usize, frames: u64) -> VfsResult<u64> {
+        let mut written = 0;
+        while written < frames {
+            let mut stream = self.0.stream.lock();
+            stream.update();
+            let config = stream.config()?;
+            match stream.state {
+                State::Prepared | State::Running => {}
+                State::Xrun => return Err(LinuxError::EPIPE),
+                _ => return Err(LinuxError::EBADFD),
+            }
+            let avail = stream.avail(&config);
+            if avail == 0 {
+                drop(stream);
+                let wait = config.frames_to_duration((frames - written).min(config.avail_min));
+                let slept = block_on_interruptible(async {
+                    sleep(wait.max(Duration::from_micros(100))).await;
+                    Ok(())
+                });
+                if let Err(err) = slept {
+                    return if written > 0 { Ok(written) } else { Err(err) };
+                }
+                continue;
+            }
+
+            let count = avail.min(frames - written);
+            let offset = written as usize * config.frame_bytes;
+            // There is nothing to play the frames to, so they only have to
+            // be readable.
+            UserConstPtr::<u8>::from(buf + offset)
+                .get_as_slice(count as usize * config.frame_bytes)?;
+            stream.appl_ptr += count;
+            written += count;
+            if stream.state == State::Prepared && stream.delay() >= config.start_threshold {
+                self.start(&mut stream);
+            }
+        }
+        Ok(written)
+    }
+
+    fn drain(&self) -> VfsResult<()> {
+        loop {
+            let mut stream = self.0.stream.lock();
+            stream.update();
+            match stream.state {
+                State::Open => return Err(LinuxError::EBADFD),
+                State::Setup => return Ok(()),
+                State::Prepared if stream.delay() == 0 => {
+                    stream.state = State::Setup;
+                    return Ok(());
+                }
+                State::Prepared => {
+                    self.start(&mut stream);
+                    stream.state = State::Draining;
+                }
+                State::Running => stream.state = State::Draining,
+                State::Xrun => {
+                    stream.state = State::Setup;
+                    return Ok(());
+                }
+                State::Draining => {}
+            }
+            let wait = stream.config()?.frames_to_duration(stream.delay());
+            drop(stream);
+            block_on_interruptible(async {
+                sleep(wait).await;
+                Ok(())
+            })?;
+        }
+    }
+}
+
+impl DeviceOps for Pcm {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            PCM_PVERSION => (arg as *mut u32).vm_write(PCM_VERSION)?,
+            PCM_INFO => (arg as *mut PcmInfo).vm_write(pcm_info())?,
+            PCM_TTSTAMP | PCM_USER_PVERSION => {}
+            PCM_HW_REFINE => {
+                let mut params = (arg as *const HwParams).vm_read()?;
+                params.refine()?;
+                (arg as *mut HwParams).vm_write(params)?;
+            }
+            PCM_HW_PARAMS => self.hw_params(arg)?,
+            PCM_HW_FREE => {
+                let mut stream = self.0.stream.lock();
+                if !matches!(stream.state, State::Open | State::Setup | State::Prepared) {
+                    return Err(LinuxError::EBADFD);
+                }
+                stream.state = State::Open;
+                stream.config = None;
+            }
+            PCM_SW_PARAMS => self.sw_params(arg)?,
+            PCM_DELAY => {
+                let mut stream = self.0.stream.lock();
+                stream.update();
+                if stream.state == State::Xrun {
+                    return Err(LinuxError::EPIPE);
+                }
+                (arg as *mut i64).vm_write(stream.delay() as i64)?;
+            }
+            PCM_HWSYNC => {
+                let mut stream = self.0.stream.lock();
+                stream.update();
+                if stream.state == State::Xrun {
+                    return Err(LinuxError::EPIPE);
+                }
+            }
+            PCM_SYNC_PTR => {
+                let mut sync = (arg as *const SyncPtr).vm_read()?;
+                let mut stream = self.0.stream.lock();
+                stream.update();
+                if let Some(config) = &mut stream.config {
+                    if sync.flags & SNDRV_PCM_SYNC_PTR_AVAIL_MIN == 0 && sync.avail_min > 0 {
+                        config.avail_min = sync.avail_min;
+                    }
+                    sync.avail_min = config.avail_min;
+                }
+                sync.state = stream.state as i32;
+                sync.hw_ptr = stream.hw_ptr;
+                sync.appl_ptr = stream.appl_ptr;
+                drop(stream);
+                (arg as *mut SyncPtr).vm_write(sync)?;
+            }
+            PCM_PREPARE => {
+                let mut stream = self.0.stream.lock();
+                stream.update();
+                if matches!(stream.state, State::Open | State::Running | State::Draining) {
+                    return Err(LinuxError::EBADFD);
+                }
+                stream.state = State::Prepared;
+                stream.appl_ptr = 0;
+                stream.hw_ptr = 0;
+            }
+            PCM_RESET => {
+                let mut stream = self.0.stream.lock();
+                stream.update();
+                stream.appl_ptr = stream.hw_ptr;
+            }
+            PCM_START => {
+                let mut stream = self.0.stream.lock();
+                if stream.state != State::Prepared {
+                    return Err(LinuxError::EBADFD);
+                }
+                self.start(&mut stream);
+            }
+            PCM_DROP => {
+                let mut stream = self.0.stream.lock();
+                if stream.state == State::Open {
+                    return Err(LinuxError::EBADFD);
+                }
+                stream.state = State::Setup;
+            }
+            PCM_DRAIN => self.drain()?,
+            PCM_WRITEI_FRAMES => {
+                let xferi = (arg as *const Xferi).vm_read()?;
+                let written = self.write_frames(xferi.buf, xferi.frames)?;
+                (arg as *mut Xferi).vm_write(Xferi {
+                    result: written as i64,
+                    ..xferi
+                })?;
+            }
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for Pcm {
+    fn poll(&self) -> IoEvents {
+        let mut stream = self.0.stream.lock();
+        stream.update();
+        match (stream.state, stream.config) {
+            (State::Prepared | State::Running, Some(config)) => {
+                let mut events = IoEvents::empty();
+                events.set(IoEvents::OUT, stream.avail(&config) >= config.avail_min);
+                events
+            }
+            (State::Draining, _) => IoEvents::empty(),
+            _ => IoEvents::OUT | IoEvents::ERR,
+        }
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::OUT) {
+            self.0.poll_out.register(context.waker());
+        }
+    }
+}
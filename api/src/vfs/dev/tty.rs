@@ -1,13 +1,28 @@
-use alloc::sync::{Arc, Weak};
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+};
 use core::{any::Any, ops::Deref, sync::atomic::Ordering, task::Context};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng_vfs::NodeFlags;
+use axfs_ng_vfs::{DeviceId, NodeFlags};
 use axio::{IoEvents, Pollable};
 use axsync::Mutex;
 use axtask::{current, future::Poller};
-use starry_core::{task::AsThread, vfs::SimpleFs};
-use starry_process::Process;
+use linux_raw_sys::general::{
+    IXON, TCIFLUSH, TCIOFF, TCIOFLUSH, TCION, TCOFLUSH, TCOOFF, TCOON, TIOCPKT_DATA,
+    TIOCPKT_DOSTOP, TIOCPKT_FLUSHREAD, TIOCPKT_FLUSHWRITE, TIOCPKT_NOSTOP,
+};
+use starry_core::{
+    task::{
+        AsThread, current_pid_ns, get_process_data, get_process_group, send_signal_to_process,
+        send_signal_to_process_group,
+    },
+    vfs::SimpleFs,
+};
+use starry_process::{Process, Session};
+use starry_signal::{SignalInfo, Signo};
 use starry_vm::{VmMutPtr, VmPtr};
 
 use crate::{
@@ -19,11 +34,17 @@ use crate::{
     vfs::DeviceOps,
 };
 
+#[cfg(feature = "hvc")]
+mod hvc;
 mod ntty;
 mod ptm;
 mod pts;
 mod pty;
+#[cfg(feature = "uart-speed")]
+mod uart;
 
+#[cfg(feature = "hvc")]
+pub use hvc::{HVC, HvcDriver};
 pub use ntty::{N_TTY, NTtyDriver};
 pub use ptm::Ptmx;
 pub use pts::PtsDir;
@@ -35,6 +56,196 @@ pub fn create_pty_master(fs: Arc<SimpleFs>) -> LinuxResult<Arc<PtyDriver>> {
     Ok(master)
 }
 
+/// Returns the name of the device selected as the system console.
+///
+/// The last `console=` on the kernel command line wins, as on Linux, over the
+/// `CONSOLE` the kernel was built with. Options after the name, like the
+/// speed in `console=ttyS0,115200`, are ignored.
+#[cfg(feature = "hvc")]
+fn console_name() -> Option<&'static str> {
+    starry_core::cmdline::args()
+        .filter_map(|it| it.strip_prefix("console="))
+        .last()
+        .map(|it| it.split(',').next().unwrap_or(it))
+        .or(option_env!("CONSOLE"))
+}
+
+/// Returns the virtio console if it was selected as the system console.
+#[cfg(feature = "hvc")]
+fn hvc_console() -> Option<Arc<HvcDriver>> {
+    HVC.clone().filter(|_| console_name() == Some("hvc0"))
+}
+
+/// Returns the device behind `/dev/console`: the virtio console if selected
+/// and present, or the UART otherwise.
+pub fn console_device() -> Arc<dyn DeviceOps> {
+    #[cfg(feature = "hvc")]
+    if let Some(hvc) = hvc_console() {
+        return hvc;
+    }
+    N_TTY.clone()
+}
+
+/// Makes the system console the controlling terminal of `proc`.
+pub fn bind_console(proc: &Process) -> LinuxResult<()> {
+    #[cfg(feature = "hvc")]
+    if let Some(hvc) = hvc_console() {
+        return hvc.bind_to(proc, false);
+    }
+    N_TTY.bind_to(proc, false)
+}
+
+/// Returns the path of the device node for the terminal `term`.
+pub fn terminal_path(term: &(dyn Any + Send + Sync)) -> Option<String> {
+    if term.is::<NTtyDriver>() {
+        return Some("/dev/ttyS0".to_string());
+    }
+    #[cfg(feature = "hvc")]
+    if term.is::<HvcDriver>() {
+        return Some("/dev/hvc0".to_string());
+    }
+    term.downcast_ref::<PtyDriver>()
+        .map(|pts| format!("/dev/pts/{}", pts.pty_number()))
+}
+
+/// Returns the device ID of the terminal `term`.
+fn terminal_device_id(term: &(dyn Any + Send + Sync)) -> Option<DeviceId> {
+    if term.is::<NTtyDriver>() {
+        return Some(DeviceId::new(4, 64));
+    }
+    #[cfg(feature = "hvc")]
+    if term.is::<HvcDriver>() {
+        return Some(DeviceId::new(229, 0));
+    }
+    term.downcast_ref::<PtyDriver>()
+        .map(|pts| DeviceId::new(136, pts.pty_number()))
+}
+
+/// Returns the `tty_nr` and `tpgid` fields of `/proc/[pid]/stat` for a
+/// process in `session`: the device number of the controlling terminal and
+/// its foreground process group, or 0 and -1 without one.
+pub fn stat_terminal(session: &Session) -> (u32, i32) {
+    let Some(term) = session.terminal() else {
+        return (0, -1);
+    };
+    let tty_nr = terminal_device_id(term.as_ref()).map_or(0, |dev| {
+        // The encoding of `new_encode_dev`.
+        let (major, minor) = (dev.major(), dev.minor());
+        (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12)
+    });
+    let tpgid = terminal_of(term.as_ref())
+        .and_then(|terminal| terminal.job_control.foreground())
+        .map_or(-1, |pg| pg.pgid() as i32);
+    (tty_nr, tpgid)
+}
+
+fn terminal_of(term: &(dyn Any + Send + Sync)) -> Option<&Arc<Terminal>> {
+    if let Some(tty) = term.downcast_ref::<NTtyDriver>() {
+        return Some(&tty.terminal);
+    }
+    #[cfg(feature = "hvc")]
+    if let Some(tty) = term.downcast_ref::<HvcDriver>() {
+        return Some(&tty.terminal);
+    }
+    term.downcast_ref::<PtyDriver>().map(|tty| &tty.terminal)
+}
+
+/// Drops the controlling terminal of `session`.
+///
+/// The foreground process group receives SIGHUP followed by SIGCONT, and the
+/// terminal becomes free to be acquired by another session.
+fn hangup_session(session: &Session) {
+    let Some(term) = session.terminal() else {
+        return;
+    };
+    if !session.unset_terminal(&term) {
+        return;
+    }
+    let Some(terminal) = terminal_of(term.as_ref()) else {
+        return;
+    };
+    if let Some(pg) = terminal.job_control.detach() {
+        for signo in [Signo::SIGHUP, Signo::SIGCONT] {
+            let _ = send_signal_to_process_group(pg.pgid(), Some(SignalInfo::new_kernel(signo)));
+        }
+    }
+}
+
+/// Hangs up `terminal` once its other end is gone, like the slave of a pty
+/// whose master is closed.
+///
+/// Besides the foreground process group, the leader of the session it
+/// controls receives SIGHUP and SIGCONT.
+fn hangup_terminal(terminal: &Terminal) {
+    let Some(session) = terminal.job_control.session() else {
+        return;
+    };
+    hangup_session(&session);
+    for signo in [Signo::SIGHUP, Signo::SIGCONT] {
+        let _ = send_signal_to_process(session.sid(), Some(SignalInfo::new_kernel(signo)));
+    }
+}
+
+/// Makes the terminal behind `ops`, if it is one, the controlling terminal of
+/// the calling process, as opening it without `O_NOCTTY` does.
+///
+/// Nothing happens unless the process leads a session without a controlling
+/// terminal and the terminal controls no session yet.
+pub fn acquire_on_open(ops: &dyn DeviceOps) {
+    fn acquire<R: TtyRead, W: TtyWrite>(tty: &Tty<R, W>) {
+        // The master of a pty never becomes a controlling terminal.
+        if tty.is_ptm {
+            return;
+        }
+        if let Some(tty) = tty.this.upgrade() {
+            let _ = tty.bind_to(&current().as_thread().proc_data.proc, false);
+        }
+    }
+
+    let ops = ops.as_any();
+    if let Some(tty) = ops.downcast_ref::<NTtyDriver>() {
+        acquire(tty);
+    }
+    #[cfg(feature = "hvc")]
+    if let Some(tty) = ops.downcast_ref::<HvcDriver>() {
+        acquire(tty);
+    }
+    if let Some(tty) = ops.downcast_ref::<PtyDriver>() {
+        acquire(tty);
+    }
+}
+
+/// Cleans up terminal job control state when `proc` exits.
+///
+/// A session leader hangs up its controlling terminal. Otherwise, if the
+/// foreground process group is left without live members, the foreground is
+/// handed back to the session leader's group so that the console does not
+/// stay owned by a dead job.
+pub fn release_terminal(proc: &Process) {
+    let pg = proc.group();
+    let session = pg.session();
+    if session.sid() == proc.pid() {
+        hangup_session(&session);
+        return;
+    }
+
+    let Some(term) = session.terminal() else {
+        return;
+    };
+    let Some(terminal) = terminal_of(term.as_ref()) else {
+        return;
+    };
+    let job_control = &terminal.job_control;
+    if job_control
+        .foreground()
+        .is_some_and(|fg| Arc::ptr_eq(&fg, &pg))
+        && pg.processes().iter().all(|it| it.is_zombie())
+        && let Ok(leader) = get_process_data(session.sid())
+    {
+        let _ = job_control.set_foreground(&leader.proc.group());
+    }
+}
+
 /// Tty device
 pub struct Tty<R, W> {
     this: Weak<Self>,
@@ -59,30 +270,126 @@ impl<R: TtyRead, W: TtyWrite + Clone> Tty<R, W> {
     }
 }
 
+impl<R, W> Drop for Tty<R, W> {
+    fn drop(&mut self) {
+        if self.is_ptm {
+            hangup_terminal(&self.terminal);
+            let pty_number = self.terminal.pty_number.load(Ordering::Acquire);
+            pts::remove_slave(pty_number, &self.terminal);
+        }
+    }
+}
+
 impl<R: TtyRead, W: TtyWrite> Tty<R, W> {
-    pub fn bind_to(self: &Arc<Self>, proc: &Process) -> LinuxResult<()> {
+    /// Makes this the controlling terminal of the session `proc` leads.
+    ///
+    /// The session must not have another one. If this controls another
+    /// session, it is only taken away from it if `steal` is set.
+    pub fn bind_to(self: &Arc<Self>, proc: &Process, steal: bool) -> LinuxResult<()> {
         let pg = proc.group();
-        if pg.session().sid() != proc.pid() {
+        let session = pg.session();
+        if session.sid() != proc.pid() {
             return Err(LinuxError::EPERM);
         }
-        assert!(pg.session().set_terminal_with(|| {
-            self.terminal.job_control.set_session(&pg.session());
+        if let Some(term) = session.terminal() {
+            return if self.is_terminal(term.as_ref()) {
+                Ok(())
+            } else {
+                Err(LinuxError::EPERM)
+            };
+        }
+        if let Some(other) = self.terminal.job_control.session() {
+            if !steal {
+                return Err(LinuxError::EPERM);
+            }
+            if let Some(term) = other.terminal() {
+                other.unset_terminal(&term);
+            }
+            self.terminal.job_control.detach();
+        }
+        if !session.set_terminal_with(|| {
+            self.terminal.job_control.set_session(&session);
             self.clone()
-        }));
+        }) {
+            return Err(LinuxError::EPERM);
+        }
 
-        self.terminal.job_control.set_foreground(&pg).unwrap();
-        Ok(())
+        self.terminal.job_control.set_foreground(&pg)
+    }
+
+    /// Whether `term` is this terminal, or the other side of the pty.
+    fn is_terminal(&self, term: &(dyn Any + Send + Sync)) -> bool {
+        terminal_of(term).is_some_and(|it| Arc::ptr_eq(it, &self.terminal))
+    }
+
+    /// Whether this is the controlling terminal of the calling process.
+    fn is_current_ctty(&self) -> bool {
+        let session = current().as_thread().proc_data.proc.group().session();
+        session
+            .terminal()
+            .is_some_and(|term| self.is_terminal(term.as_ref()))
     }
 
     pub fn pty_number(&self) -> u32 {
         self.terminal.pty_number.load(Ordering::Acquire)
     }
+
+    /// Installs new settings, switching the line to their output speed if the
+    /// device has one.
+    ///
+    /// Such a device has a single clock for both directions, so the input
+    /// speed is reported as the speed set, like Linux does for UARTs. B0, which
+    /// would hang up a modem line, leaves the speed as it is. A speed the
+    /// device cannot take fails the call, leaving the settings unchanged.
+    fn set_termios(&self, mut termios: Termios2) -> LinuxResult<()> {
+        let speed = termios.output_speed();
+        if speed != 0
+            && let Some(speed) = self.writer.set_speed(speed)?
+        {
+            termios.set_speeds(speed, speed);
+        }
+        let ixon = termios.has_iflag(IXON);
+        let old = core::mem::replace(&mut *self.terminal.termios.lock(), Arc::new(termios));
+        if old.has_iflag(IXON) != ixon {
+            let status = if ixon { TIOCPKT_DOSTOP } else { TIOCPKT_NOSTOP };
+            self.terminal.packet.report(status);
+        }
+        Ok(())
+    }
+
+    /// Discards the input not read yet, as `TCSETSF` and `TCFLSH` do.
+    fn flush_input(&self) {
+        self.ldisc.lock().drain_input();
+        self.terminal.packet.report(TIOCPKT_FLUSHREAD);
+    }
+
+    /// Reads from the master, which waits in [`FileLike`] rather than here.
+    ///
+    /// [`FileLike`]: crate::file::FileLike
+    fn read_master(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        if !self.terminal.packet.enabled() {
+            return self.ldisc.lock().read(buf);
+        }
+        let Some((first, data)) = buf.split_first_mut() else {
+            return Ok(0);
+        };
+        if let Some(status) = self.terminal.packet.take_status() {
+            *first = status;
+            return Ok(1);
+        }
+        let read = self.ldisc.lock().read(data)?;
+        *first = TIOCPKT_DATA as u8;
+        Ok(read + 1)
+    }
 }
 
 impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     fn read_at(&self, buf: &mut [u8], _offset: u64) -> LinuxResult<usize> {
+        if self.is_ptm {
+            return self.read_master(buf);
+        }
         Poller::new(&self.terminal.job_control, IoEvents::IN).poll(|| {
-            if self.is_ptm || self.terminal.job_control.current_in_foreground() {
+            if self.terminal.job_control.current_in_foreground() {
                 self.ldisc.lock().read(buf)
             } else {
                 Err(LinuxError::EAGAIN)
@@ -91,8 +398,11 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
     }
 
     fn write_at(&self, buf: &[u8], _offset: u64) -> LinuxResult<usize> {
-        self.writer.write(buf);
-        Ok(buf.len())
+        // Fails with `EAGAIN` when full or stopped, for the file to wait.
+        if self.terminal.output_stopped() {
+            return Err(LinuxError::EAGAIN);
+        }
+        self.writer.try_write(buf)
     }
 
     fn ioctl(&self, cmd: u32, arg: usize) -> LinuxResult<usize> {
@@ -106,63 +416,108 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
             }
             TCSETS | TCSETSF | TCSETSW => {
                 // TODO: drain output?
-                *self.terminal.termios.lock() =
-                    Arc::new(Termios2::new((arg as *const Termios).vm_read()?));
+                let termios = (arg as *const Termios).vm_read()?;
+                self.set_termios(self.terminal.load_termios().with_termios(termios))?;
                 if cmd == TCSETSF {
-                    self.ldisc.lock().drain_input();
+                    self.flush_input();
                 }
             }
             TCSETS2 | TCSETSF2 | TCSETSW2 => {
                 // TODO: drain output?
-                *self.terminal.termios.lock() = Arc::new((arg as *const Termios2).vm_read()?);
+                let mut termios = (arg as *const Termios2).vm_read()?;
+                termios.encode_speeds();
+                self.set_termios(termios)?;
                 if cmd == TCSETSF2 {
-                    self.ldisc.lock().drain_input();
+                    self.flush_input();
+                }
+            }
+            TCXONC => match arg as u32 {
+                TCOOFF => self.terminal.set_output_stopped(true),
+                TCOON => self.terminal.set_output_stopped(false),
+                // Nothing is sent to stop or restart the input.
+                TCIOFF | TCION => {}
+                _ => return Err(LinuxError::EINVAL),
+            },
+            TCFLSH => {
+                // Output is handed to the device at once, so there is none
+                // to discard.
+                match arg as u32 {
+                    TCIFLUSH | TCIOFLUSH => self.flush_input(),
+                    TCOFLUSH => {}
+                    _ => return Err(LinuxError::EINVAL),
+                }
+                if arg as u32 != TCIFLUSH {
+                    self.terminal.packet.report(TIOCPKT_FLUSHWRITE);
                 }
             }
             TIOCGPGRP => {
+                // The master tells about the slave, whoever asks.
+                if !self.is_ptm && !self.is_current_ctty() {
+                    return Err(LinuxError::ENOTTY);
+                }
                 let foreground = self
                     .terminal
                     .job_control
                     .foreground()
                     .ok_or(LinuxError::ESRCH)?;
-                (arg as *mut u32).vm_write(foreground.pgid())?;
+                let pgid = current_pid_ns().local_pid(foreground.pgid()).unwrap_or(0);
+                (arg as *mut u32).vm_write(pgid)?;
             }
             TIOCSPGRP => {
-                let curr = current();
-                self.terminal
-                    .job_control
-                    .set_foreground(&curr.as_thread().proc_data.proc.group())?;
+                if !self.is_current_ctty() {
+                    return Err(LinuxError::ENOTTY);
+                }
+                let pgid = (arg as *const i32).vm_read()?;
+                if pgid < 0 {
+                    return Err(LinuxError::EINVAL);
+                }
+                let pg = get_process_group(current_pid_ns().global_pid(pgid as _)?)?;
+                // `set_foreground` checks that it is in the session.
+                self.terminal.job_control.set_foreground(&pg)?;
+            }
+            TIOCSTI if !self.is_ptm => {
+                // Only the controlling terminal takes input this way.
+                if !self.is_current_ctty() {
+                    return Err(LinuxError::EPERM);
+                }
+                let ch = (arg as *const u8).vm_read()?;
+                self.ldisc.lock().push_input(ch);
             }
             TIOCGWINSZ => {
                 (arg as *mut WindowSize).vm_write(*self.terminal.window_size.lock())?;
             }
             TIOCSWINSZ => {
-                *self.terminal.window_size.lock() = (arg as *const WindowSize).vm_read()?;
+                self.terminal
+                    .set_window_size((arg as *const WindowSize).vm_read()?);
             }
             TIOCSPTLCK => {}
             TIOCGPTN => {
                 (arg as *mut u32).vm_write(self.pty_number())?;
             }
+            TIOCPKT if self.is_ptm => {
+                let enabled = (arg as *const i32).vm_read()? != 0;
+                self.terminal.packet.set_enabled(enabled);
+            }
+            TIOCGPKT if self.is_ptm => {
+                (arg as *mut i32).vm_write(self.terminal.packet.enabled() as _)?;
+            }
             TIOCSCTTY => {
+                // Everyone may take it over, as there are no capabilities.
                 self.this
                     .upgrade()
                     .unwrap()
-                    .bind_to(&current().as_thread().proc_data.proc)?;
+                    .bind_to(&current().as_thread().proc_data.proc, arg == 1)?;
             }
             TIOCNOTTY => {
-                if current()
-                    .as_thread()
-                    .proc_data
-                    .proc
-                    .group()
-                    .session()
-                    .unset_terminal(&(self.this.upgrade().unwrap() as _))
-                {
-                    // TODO: If the process was session leader, send SIGHUP and
-                    // SIGCONT to the foreground process group and all processes
-                    // in the current session lose their
-                    // controlling terminal.
-                } else {
+                if !self.is_current_ctty() {
+                    return Err(LinuxError::ENOTTY);
+                }
+                let curr = current();
+                let proc = &curr.as_thread().proc_data.proc;
+                let session = proc.group().session();
+                if session.sid() == proc.pid() {
+                    hangup_session(&session);
+                } else if !session.unset_terminal(&(self.this.upgrade().unwrap() as _)) {
                     warn!("Failed to unset terminal");
                 }
             }
@@ -187,9 +542,14 @@ impl<R: TtyRead, W: TtyWrite> DeviceOps for Tty<R, W> {
 
 impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
     fn poll(&self) -> IoEvents {
-        let mut events = IoEvents::OUT | self.terminal.job_control.poll();
+        let mut events = self.terminal.job_control.poll();
+        events.set(
+            IoEvents::OUT,
+            !self.terminal.output_stopped() && self.writer.writable(),
+        );
         if self.is_ptm || events.contains(IoEvents::IN) {
-            events.set(IoEvents::IN, self.ldisc.lock().poll_read());
+            let status = self.is_ptm && self.terminal.packet.has_status();
+            events.set(IoEvents::IN, status || self.ldisc.lock().poll_read());
         }
         events
     }
@@ -200,6 +560,13 @@ impl<R: TtyRead, W: TtyWrite> Pollable for Tty<R, W> {
         }
         if events.contains(IoEvents::IN) {
             self.ldisc.lock().register_rx_waker(context.waker());
+            if self.is_ptm {
+                self.terminal.packet.register(context.waker());
+            }
+        }
+        if events.contains(IoEvents::OUT) {
+            self.terminal.register_output(context.waker());
+            self.writer.register_writable(context.waker());
         }
     }
 }
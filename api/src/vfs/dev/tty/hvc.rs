@@ -0,0 +1,129 @@
+use alloc::sync::Arc;
+use core::ptr::NonNull;
+
+use axalloc::global_allocator;
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+use memory_addr::PAGE_SIZE_4K;
+use virtio_drivers::{
+    BufferDirection, Hal, PhysAddr,
+    device::console::VirtIOConsole,
+    transport::{
+        DeviceType, Transport,
+        mmio::{MmioTransport, VirtIOHeader},
+    },
+};
+
+use super::Tty;
+use crate::terminal::ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite};
+
+pub type HvcDriver = Tty<Hvc, Hvc>;
+
+struct HalImpl;
+
+unsafe impl Hal for HalImpl {
+    fn dma_alloc(pages: usize, _direction: BufferDirection) -> (PhysAddr, NonNull<u8>) {
+        let Ok(vaddr) = global_allocator().alloc_pages(pages, PAGE_SIZE_4K) else {
+            return (0, NonNull::dangling());
+        };
+        // SAFETY: the pages were just allocated
+        unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, pages * PAGE_SIZE_4K) };
+        let paddr = virt_to_phys(vaddr.into());
+        (paddr.as_usize(), NonNull::new(vaddr as *mut u8).unwrap())
+    }
+
+    unsafe fn dma_dealloc(_paddr: PhysAddr, vaddr: NonNull<u8>, pages: usize) -> i32 {
+        global_allocator().dealloc_pages(vaddr.as_ptr() as usize, pages);
+        0
+    }
+
+    unsafe fn mmio_phys_to_virt(paddr: PhysAddr, _size: usize) -> NonNull<u8> {
+        NonNull::new(phys_to_virt(paddr.into()).as_mut_ptr()).unwrap()
+    }
+
+    unsafe fn share(buffer: NonNull<[u8]>, _direction: BufferDirection) -> PhysAddr {
+        virt_to_phys((buffer.as_ptr() as *mut u8 as usize).into()).as_usize()
+    }
+
+    unsafe fn unshare(_paddr: PhysAddr, _buffer: NonNull<[u8]>, _direction: BufferDirection) {}
+}
+
+struct Console(VirtIOConsole<HalImpl, MmioTransport>);
+
+// SAFETY: the device is only accessed with the lock held
+unsafe impl Send for Console {}
+
+/// The virtio console, if QEMU provides one.
+#[derive(Clone)]
+pub struct Hvc(Arc<SpinNoIrq<Console>>);
+
+impl TtyRead for Hvc {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut console = self.0.lock();
+        let mut read = 0;
+        while read < buf.len() {
+            match console.0.recv(true) {
+                Ok(Some(ch)) => {
+                    buf[read] = ch;
+                    read += 1;
+                }
+                _ => break,
+            }
+        }
+        read
+    }
+}
+
+impl TtyWrite for Hvc {
+    fn write(&self, buf: &[u8]) {
+        let mut console = self.0.lock();
+        for &ch in buf {
+            if let Err(err) = console.0.send(ch) {
+                warn!("Failed to write to hvc0: {:?}", err);
+                break;
+            }
+        }
+    }
+}
+
+/// Looks for a virtio console among the virtio-mmio slots.
+///
+/// Only the MMIO transport is probed, which is what the QEMU `virt` machines
+/// use with `-device virtio-serial-device -device virtconsole`.
+fn probe() -> Option<Hvc> {
+    for &(base, _size) in axconfig::devices::VIRTIO_MMIO_REGIONS {
+        let header = NonNull::new(phys_to_virt(base.into()).as_mut_ptr() as *mut VirtIOHeader)?;
+        // SAFETY: the region is a virtio-mmio slot mapped by the platform
+        let Ok(transport) = (unsafe { MmioTransport::new(header) }) else {
+            continue;
+        };
+        if transport.device_type() != DeviceType::Console {
+            continue;
+        }
+        match VirtIOConsole::new(transport) {
+            Ok(console) => {
+                info!("Found virtio console at {:#x}", base);
+                return Some(Hvc(Arc::new(SpinNoIrq::new(Console(console)))));
+            }
+            Err(err) => warn!("Failed to initialize virtio console: {:?}", err),
+        }
+    }
+    None
+}
+
+lazy_static! {
+    /// The virtio console TTY device, `/dev/hvc0`.
+    pub static ref HVC: Option<Arc<HvcDriver>> = probe().map(new_hvc);
+}
+
+fn new_hvc(hvc: Hvc) -> Arc<HvcDriver> {
+    Tty::new(
+        Arc::default(),
+        TtyConfig {
+            reader: hvc.clone(),
+            writer: hvc,
+            process_mode: ProcessMode::Manual,
+        },
+    )
+}
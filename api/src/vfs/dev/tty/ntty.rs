@@ -1,27 +1,83 @@
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, sync::Arc, task::Wake};
+use core::task::Waker;
 
 use axhal::irq::register_irq_waker;
+use kspin::SpinNoIrq;
 use lazy_static::lazy_static;
+use ringbuf::{
+    StaticRb,
+    traits::{Consumer, Observer, Producer},
+};
 
 use super::Tty;
 use crate::terminal::ldisc::{ProcessMode, TtyConfig, TtyRead, TtyWrite};
 
 pub type NTtyDriver = Tty<Console, Console>;
 
+/// How many received bytes are kept until the reader task gets to run.
+const RX_BUF_SIZE: usize = 4096;
+
+type RxBuf = StaticRb<u8, RX_BUF_SIZE>;
+
+/// Moves what the UART has received into `rx`, as long as there is room.
+fn drain_uart(rx: &mut RxBuf) {
+    let mut buf = [0; 64];
+    loop {
+        let room = rx.vacant_len().min(buf.len());
+        if room == 0 {
+            break;
+        }
+        let read = axhal::console::read_bytes(&mut buf[..room]);
+        if read == 0 {
+            break;
+        }
+        rx.push_slice(&buf[..read]);
+    }
+}
+
+/// Wakes the reader task on a console interrupt.
+///
+/// The input is first taken out of the UART, whose FIFO would overrun if the
+/// task does not get to run soon, e.g. under load.
+struct RxWaker(Waker);
+
+impl Wake for RxWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        drain_uart(&mut RX_BUF.lock());
+        crate::vfs::report_wakeup();
+        self.0.wake_by_ref();
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Console;
 impl TtyRead for Console {
     fn read(&mut self, buf: &mut [u8]) -> usize {
-        axhal::console::read_bytes(buf)
+        // Held while reading the UART too, so that the interrupt cannot slip
+        // newer bytes in between.
+        let mut rx = RX_BUF.lock();
+        let read = rx.pop_slice(buf);
+        read + axhal::console::read_bytes(&mut buf[read..])
     }
 }
 impl TtyWrite for Console {
     fn write(&self, buf: &[u8]) {
         axhal::console::write_bytes(buf);
     }
+
+    #[cfg(feature = "uart-speed")]
+    fn set_speed(&self, baud: u32) -> axerrno::LinuxResult<Option<u32>> {
+        super::uart::set_speed(baud).map(Some)
+    }
 }
 
 lazy_static! {
+    /// The input drained from the UART by its interrupt.
+    static ref RX_BUF: SpinNoIrq<RxBuf> = SpinNoIrq::new(RxBuf::default());
     /// The default TTY device.
     pub static ref N_TTY: Arc<NTtyDriver> = new_n_tty();
 }
@@ -33,9 +89,10 @@ fn new_n_tty() -> Arc<NTtyDriver> {
             reader: Console,
             writer: Console,
             process_mode: if let Some(irq) = axhal::console::get_console_irq() {
-                ProcessMode::External(
-                    Box::new(move |waker| register_irq_waker(irq as _, &waker)) as _
-                )
+                ProcessMode::External(Box::new(move |waker| {
+                    let waker = Waker::from(Arc::new(RxWaker(waker)));
+                    register_irq_waker(irq as _, &waker);
+                }) as _)
             } else {
                 ProcessMode::Manual
             },
@@ -1,13 +1,23 @@
-use alloc::{borrow::Cow, boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{borrow::Cow, boxed::Box, format, string::ToString, sync::Arc, vec::Vec};
 use core::sync::atomic::Ordering;
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng_vfs::{DeviceId, NodeType, VfsResult};
+use axfs_ng_vfs::{DeviceId, MetadataUpdate, NodeOps, NodePermission, NodeType, VfsResult};
 use flatten_objects::FlattenObjects;
 use kspin::SpinNoIrq;
 use starry_core::vfs::{Device, NodeOpsMux, SimpleDirOps, SimpleFs};
 
-use crate::vfs::dev::tty::pty::PtyDriver;
+use crate::{
+    terminal::Terminal,
+    vfs::dev::{
+        tty::pty::PtyDriver,
+        uevent::{self, Action},
+    },
+};
+
+/// The `tty` group, which owns the slaves as with the `gid=5,mode=620`
+/// options distributions mount devpts with.
+const TTY_GID: u32 = 5;
 
 static PTS_TABLE: SpinNoIrq<FlattenObjects<Arc<Device>, 16>> =
     SpinNoIrq::new(FlattenObjects::new());
@@ -24,13 +34,41 @@ pub fn add_slave(fs: Arc<SimpleFs>, pty: Arc<PtyDriver>) -> LinuxResult<u32> {
         ))
         .map_err(|_| LinuxError::EMFILE)? as u32;
     terminal.pty_number.store(pty_number, Ordering::Release);
-    table
-        .get(pty_number as usize)
-        .unwrap()
-        .set_device_id(DeviceId::new(136, pty_number));
+    let dev_id = DeviceId::new(136, pty_number);
+    let slave = table.get(pty_number as usize).unwrap().clone();
+    drop(table);
+    slave.set_device_id(dev_id);
+    // Owned by whoever opened the master, which is always root for now.
+    slave.update_metadata(MetadataUpdate {
+        owner: Some((0, TTY_GID)),
+        mode: Some(NodePermission::from_bits_truncate(0o620)),
+        ..Default::default()
+    })?;
+    uevent::emit(Action::Add, "tty", &format!("pts/{pty_number}"), dev_id);
     Ok(pty_number)
 }
 
+/// Removes slave `pty_number` once the master of `terminal` is closed.
+pub fn remove_slave(pty_number: u32, terminal: &Arc<Terminal>) {
+    let mut table = PTS_TABLE.lock();
+    // The number is only set once the slave is added, so it may belong to
+    // another pair if adding failed.
+    let ours = table.get(pty_number as usize).is_some_and(|slave| {
+        slave
+            .inner()
+            .as_any()
+            .downcast_ref::<PtyDriver>()
+            .is_some_and(|pty| Arc::ptr_eq(&pty.terminal, terminal))
+    });
+    if !ours {
+        return;
+    }
+    table.remove(pty_number as usize);
+    drop(table);
+    let dev_id = DeviceId::new(136, pty_number);
+    uevent::emit(Action::Remove, "tty", &format!("pts/{pty_number}"), dev_id);
+}
+
 /// /dev/pts directory
 pub struct PtsDir;
 
@@ -1,10 +1,12 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::task::Waker;
 
+use axerrno::{LinuxError, LinuxResult};
 use axio::PollSet;
 use kspin::SpinNoPreempt;
 use ringbuf::{
     Cons, HeapRb, Prod,
-    traits::{Consumer, Producer},
+    traits::{Consumer, Observer, Producer},
 };
 
 use super::Tty;
@@ -19,37 +21,69 @@ pub type PtyDriver = Tty<PtyReader, PtyWriter>;
 
 type Buffer = Arc<HeapRb<u8>>;
 
-pub struct PtyReader(Cons<Buffer>);
+/// Reads from a buffer, waking the [`PollSet`] of its writer as room is made.
+pub struct PtyReader(Cons<Buffer>, Arc<PollSet>);
 
 impl PtyReader {
-    pub fn new(buffer: Buffer) -> Self {
-        Self(Cons::new(buffer))
+    pub fn new(buffer: Buffer, poll_space: Arc<PollSet>) -> Self {
+        Self(Cons::new(buffer), poll_space)
     }
 }
 
 impl TtyRead for PtyReader {
     fn read(&mut self, buf: &mut [u8]) -> usize {
-        self.0.pop_slice(buf)
+        let read = self.0.pop_slice(buf);
+        if read > 0 {
+            self.1.wake();
+        }
+        read
     }
 }
 
+/// Writes to a buffer, waking the [`PollSet`] of its reader. Writes through
+/// the device wait for room, while echoes drop what does not fit.
 #[derive(Clone)]
-pub struct PtyWriter(Arc<SpinNoPreempt<Prod<Buffer>>>, Arc<PollSet>);
+pub struct PtyWriter {
+    buffer: Arc<SpinNoPreempt<Prod<Buffer>>>,
+    poll_rx: Arc<PollSet>,
+    poll_space: Arc<PollSet>,
+}
 
 impl PtyWriter {
-    pub fn new(buffer: Buffer, poll_rx: Arc<PollSet>) -> Self {
-        Self(Arc::new(SpinNoPreempt::new(Prod::new(buffer))), poll_rx)
+    pub fn new(buffer: Buffer, poll_rx: Arc<PollSet>, poll_space: Arc<PollSet>) -> Self {
+        Self {
+            buffer: Arc::new(SpinNoPreempt::new(Prod::new(buffer))),
+            poll_rx,
+            poll_space,
+        }
     }
 }
 
 impl TtyWrite for PtyWriter {
     fn write(&self, buf: &[u8]) {
-        let read = self.0.lock().push_slice(buf);
-        self.1.wake();
+        let read = self.buffer.lock().push_slice(buf);
+        self.poll_rx.wake();
         if read < buf.len() {
             warn!("Discarding {} bytes written to pty", buf.len() - read);
         }
     }
+
+    fn try_write(&self, buf: &[u8]) -> LinuxResult<usize> {
+        let written = self.buffer.lock().push_slice(buf);
+        if written == 0 && !buf.is_empty() {
+            return Err(LinuxError::EAGAIN);
+        }
+        self.poll_rx.wake();
+        Ok(written)
+    }
+
+    fn writable(&self) -> bool {
+        !self.buffer.lock().is_full()
+    }
+
+    fn register_writable(&self, waker: &Waker) {
+        self.poll_space.register(waker);
+    }
 }
 
 pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
@@ -57,14 +91,20 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
     let slave_to_master = Arc::new(HeapRb::new(PTY_BUF_SIZE));
     let poll_rx_slave = Arc::new(PollSet::new());
     let poll_rx_master = Arc::new(PollSet::new());
+    let poll_space_slave = Arc::new(PollSet::new());
+    let poll_space_master = Arc::new(PollSet::new());
 
     let terminal = Arc::new(Terminal::default());
 
     let master = Tty::new(
         terminal.clone(),
         TtyConfig {
-            reader: PtyReader::new(slave_to_master.clone()),
-            writer: PtyWriter::new(master_to_slave.clone(), poll_rx_slave.clone()),
+            reader: PtyReader::new(slave_to_master.clone(), poll_space_slave.clone()),
+            writer: PtyWriter::new(
+                master_to_slave.clone(),
+                poll_rx_slave.clone(),
+                poll_space_master.clone(),
+            ),
             process_mode: ProcessMode::None(poll_rx_master.clone()),
         },
     );
@@ -72,8 +112,8 @@ pub(crate) fn create_pty_pair() -> (Arc<PtyDriver>, Arc<PtyDriver>) {
     let slave = Tty::new(
         terminal,
         TtyConfig {
-            reader: PtyReader::new(master_to_slave),
-            writer: PtyWriter::new(slave_to_master, poll_rx_master),
+            reader: PtyReader::new(master_to_slave, poll_space_master),
+            writer: PtyWriter::new(slave_to_master, poll_rx_master, poll_space_slave),
             process_mode: ProcessMode::External(Box::new(move |waker| {
                 poll_rx_slave.register(&waker)
             })),
@@ -0,0 +1,73 @@
+//! Line speed of the RK3588 debug UART (UART2), which backs the console.
+//!
+//! The UART is a DesignWare 8250 clocked at 24 MHz, whose speed is set by a
+//! divisor of the clock over 16. Other settings are left to the firmware.
+
+#[cfg(not(target_arch = "aarch64"))]
+compile_error!("UART speed control is only supported on the RK3588");
+
+use core::hint::spin_loop;
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::mem::phys_to_virt;
+use kspin::SpinNoIrq;
+use memory_addr::PhysAddr;
+
+const UART_BASE: usize = 0xfeb5_0000;
+/// The UART input clock, in Hz.
+const UART_CLOCK: u32 = 24_000_000;
+
+const UART_DLL: usize = 0x00;
+const UART_DLH: usize = 0x04;
+const UART_LCR: usize = 0x0c;
+const UART_LSR: usize = 0x14;
+const UART_USR: usize = 0x7c;
+
+const LCR_DLAB: u32 = 1 << 7;
+const LSR_TEMT: u32 = 1 << 6;
+const USR_BUSY: u32 = 1 << 0;
+
+/// Keeps interrupts, and with them the kernel log, away while the divisor is
+/// being changed.
+static LOCK: SpinNoIrq<()> = SpinNoIrq::new(());
+
+fn uart_reg(offset: usize) -> *mut u32 {
+    phys_to_virt(PhysAddr::from(UART_BASE + offset)).as_mut_ptr() as *mut u32
+}
+
+fn uart_read(offset: usize) -> u32 {
+    // SAFETY: the console UART is mapped as device memory by the platform
+    unsafe { uart_reg(offset).read_volatile() }
+}
+
+fn uart_write(offset: usize, value: u32) {
+    // SAFETY: see above
+    unsafe { uart_reg(offset).write_volatile(value) }
+}
+
+/// Switches the UART to the speed closest to `baud` it can generate, which is
+/// returned.
+///
+/// Fails with `EINVAL` for a zero speed or one above what the clock can
+/// generate.
+pub fn set_speed(baud: u32) -> LinuxResult<u32> {
+    let (clock, baud) = (UART_CLOCK as u64, baud as u64);
+    if baud == 0 || baud > clock / 16 {
+        return Err(LinuxError::EINVAL);
+    }
+    let divisor = ((clock + 8 * baud) / (16 * baud)).min(0xffff) as u32;
+
+    let _guard = LOCK.lock();
+    // Let what was written go out at the old speed, and the UART become idle,
+    // as LCR cannot be written while it is busy.
+    while uart_read(UART_LSR) & LSR_TEMT == 0 || uart_read(UART_USR) & USR_BUSY != 0 {
+        spin_loop();
+    }
+    let lcr = uart_read(UART_LCR);
+    uart_write(UART_LCR, lcr | LCR_DLAB);
+    uart_write(UART_DLL, divisor & 0xff);
+    uart_write(UART_DLH, divisor >> 8);
+    uart_write(UART_LCR, lcr & !LCR_DLAB);
+
+    Ok(UART_CLOCK / (16 * divisor))
+}
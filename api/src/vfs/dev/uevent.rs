@@ -0,0 +1,142 @@
+//! Device events for user space device managers.
+//!
+//! Linux broadcasts these on `NETLINK_KOBJECT_UEVENT` sockets, which the
+//! network stack does not provide. They are queued for `/proc/uevents`
+//! instead, each as `KEY=value` lines followed by an empty line, which a
+//! hotplug loop turns into the environment `mdev` expects:
+//!
+//! ```sh
+//! while read -r line; do
+//!     if [ -n "$line" ]; then export "$line"; else mdev; fi
+//! done </proc/uevents
+//! ```
+//!
+//! The devices present at boot are queued as added, so that such a loop
+//! started by init does the job of `mdev -s` as well. Devices that come and
+//! go later, like pty slaves and `uinput` devices, are queued as added and
+//! then as removed. Every event is read by
+//! a single reader, and the oldest are dropped when nobody reads them.
+
+use alloc::{collections::VecDeque, format, vec::Vec};
+use core::{
+    any::Any,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
+
+use axerrno::LinuxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, VfsResult};
+use axio::{IoEvents, PollSet, Pollable};
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+use starry_core::vfs::DeviceOps;
+
+/// How many events are kept for readers.
+const MAX_QUEUED: usize = 256;
+
+/// What happened to a device.
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    /// The device appeared.
+    Add,
+    /// The device changed, e.g. a loop device got or lost its backing file.
+    Change,
+    /// The device went away.
+    Remove,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Change => "change",
+            Action::Remove => "remove",
+        }
+    }
+}
+
+static QUEUE: SpinNoIrq<VecDeque<Vec<u8>>> = SpinNoIrq::new(VecDeque::new());
+static SEQNUM: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref POLL_EVENT: PollSet = PollSet::new();
+}
+
+/// Queues an event about the device node `/dev/{devname}`.
+pub fn emit(action: Action, subsystem: &str, devname: &str, dev: DeviceId) {
+    let seqnum = SEQNUM.fetch_add(1, Ordering::Relaxed) + 1;
+    let name = devname.rsplit('/').next().unwrap_or(devname);
+    let event = format!(
+        concat!(
+            "ACTION={action}\nDEVPATH=/devices/virtual/{subsystem}/{name}\n",
+            "SUBSYSTEM={subsystem}\nMAJOR={major}\nMINOR={minor}\n",
+            "DEVNAME={devname}\nSEQNUM={seqnum}\n\n",
+        ),
+        action = action.as_str(),
+        subsystem = subsystem,
+        name = name,
+        major = dev.major(),
+        minor = dev.minor(),
+        devname = devname,
+        seqnum = seqnum,
+    );
+
+    let mut queue = QUEUE.lock();
+    if queue.len() == MAX_QUEUED {
+        queue.pop_front();
+    }
+    queue.push_back(event.into_bytes());
+    drop(queue);
+    POLL_EVENT.wake();
+}
+
+/// `/proc/uevents`
+pub struct Uevents;
+
+impl DeviceOps for Uevents {
+    /// Reads the rest of the oldest event, as much as fits into `buf`.
+    fn read_at(&self, buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut queue = QUEUE.lock();
+        let event = queue.front_mut().ok_or(LinuxError::EAGAIN)?;
+        let len = buf.len().min(event.len());
+        buf[..len].copy_from_slice(&event[..len]);
+        event.drain(..len);
+        if event.is_empty() {
+            queue.pop_front();
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EPERM)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Pollable for Uevents {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::empty();
+        events.set(IoEvents::IN, !QUEUE.lock().is_empty());
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            POLL_EVENT.register(context.waker());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! `/dev/uinput`, which lets user space create input devices and inject
+//! events into them.
+//!
+//! The device is described with the `UI_SET_*BIT` ioctls, `UI_DEV_SETUP` and
+//! `UI_ABS_SETUP`, and then created with `UI_DEV_CREATE`, after which it
+//! appears as the next `/dev/input/eventN`. Every `struct input_event`
+//! written to the file is then read from there. The device goes away with
+//! `UI_DEV_DESTROY` or when the file is closed. Every open file describes and
+//! creates a device of its own.
+
+use alloc::{
+    collections::VecDeque,
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::{any::Any, ffi::CStr};
+
+use axdriver::prelude::EventType;
+use axerrno::LinuxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use axsync::Mutex;
+use bitmaps::Bitmap;
+use bytemuck::AnyBitPattern;
+use kspin::SpinNoIrq;
+use starry_core::vfs::{Device, DeviceOps, SimpleFs};
+use starry_vm::{VmMutPtr, VmPtr};
+use zerocopy::FromBytes;
+
+use super::event::{self, ABS_CNT, InputAbsInfo, InputEvent, InputId, KEY_CNT};
+
+pub const UINPUT_DEVICE_ID: DeviceId = DeviceId::new(10, 223);
+
+const EV_REL: u8 = 2;
+const REL_CNT: usize = 0x10;
+
+const UI_DEV_CREATE: u32 = 0x5501;
+const UI_DEV_DESTROY: u32 = 0x5502;
+/// `_IOW('U', 3, struct uinput_setup)`.
+const UI_DEV_SETUP: u32 = 0x405c_5503;
+/// `_IOW('U', 4, struct uinput_abs_setup)`.
+const UI_ABS_SETUP: u32 = 0x401c_5504;
+/// `_IOR('U', 45, unsigned int)`.
+const UI_GET_VERSION: u32 = 0x8004_552d;
+const UI_SET_EVBIT: u32 = 0x4004_5564;
+const UI_SET_KEYBIT: u32 = 0x4004_5565;
+const UI_SET_RELBIT: u32 = 0x4004_5566;
+const UI_SET_ABSBIT: u32 = 0x4004_5567;
+
+const UINPUT_VERSION: u32 = 5;
+/// The events a created device holds before it drops the oldest.
+const MAX_PENDING: usize = 1024;
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct UinputAbsSetup {
+    code: u16,
+    _pad: u16,
+    absinfo: InputAbsInfo,
+}
+
+/// What a device is created with.
+#[derive(Clone)]
+struct Setup {
+    id: InputId,
+    name: String,
+    ev_bits: Bitmap<{ EventType::COUNT as usize }>,
+    key_bits: Bitmap<KEY_CNT>,
+    rel_bits: Bitmap<REL_CNT>,
+    abs_bits: Bitmap<ABS_CNT>,
+    abs_info: [InputAbsInfo; ABS_CNT],
+}
+
+/// An input device created through `/dev/uinput`.
+pub struct VirtualInput {
+    setup: Setup,
+    events: SpinNoIrq<VecDeque<(u16, u16, i32)>>,
+}
+
+impl VirtualInput {
+    pub(super) fn id(&self) -> InputId {
+        self.setup.id
+    }
+
+    pub(super) fn name(&self) -> &str {
+        &self.setup.name
+    }
+
+    pub(super) fn abs_info(&self, axis: usize) -> InputAbsInfo {
+        self.setup.abs_info[axis]
+    }
+
+    pub(super) fn get_event_bits(&self, ty: EventType, out: &mut [u8]) -> bool {
+        let setup = &self.setup;
+        if !setup.ev_bits.get(ty as usize) {
+            return false;
+        }
+        let bits: &[u8] = match ty {
+            EventType::Key => setup.key_bits.as_bytes(),
+            EventType::Absolute => setup.abs_bits.as_bytes(),
+            _ if ty as u8 == EV_REL => setup.rel_bits.as_bytes(),
+            _ => &[],
+        };
+        let len = bits.len().min(out.len());
+        out[..len].copy_from_slice(&bits[..len]);
+        true
+    }
+
+    pub(super) fn read_event(&self) -> Option<(u16, u16, i32)> {
+        self.events.lock().pop_front()
+    }
+}
+
+struct Inner {
+    setup: Setup,
+    /// The device and its name, once created.
+    created: Option<(Arc<VirtualInput>, String)>,
+}
+
+/// `/dev/uinput`, or a file opened on it.
+pub struct Uinput {
+    fs: Arc<SimpleFs>,
+    inner: Mutex<Inner>,
+}
+
+impl Uinput {
+    pub fn new(fs: Arc<SimpleFs>) -> Self {
+        Self {
+            fs,
+            inner: Mutex::new(Inner {
+                setup: Setup {
+                    id: InputId {
+                        bustype: 0x06, // BUS_VIRTUAL
+                        vendor: 0,
+                        product: 0,
+                        version: 0,
+                    },
+                    name: String::new(),
+                    ev_bits: Bitmap::new(),
+                    key_bits: Bitmap::new(),
+                    rel_bits: Bitmap::new(),
+                    abs_bits: Bitmap::new(),
+                    abs_info: [InputAbsInfo::default(); ABS_CNT],
+                },
+                created: None,
+            }),
+        }
+    }
+
+    /// Opens the device, with a device of its own to describe and create.
+    pub fn open(&self) -> Arc<Device> {
+        Device::new(
+            self.fs.clone(),
+            NodeType::CharacterDevice,
+            UINPUT_DEVICE_ID,
+            Arc::new(Self::new(self.fs.clone())),
+        )
+    }
+
+    /// Sets bit `arg` of one of the capabilities, which can only be changed
+    /// before the device is created.
+    fn set_bit(&self, cmd: u32, arg: usize) -> VfsResult<()> {
+        let mut inner = self.inner.lock();
+        if inner.created.is_some() {
+            return Err(LinuxError::EINVAL);
+        }
+        let setup = &mut inner.setup;
+        match cmd {
+            UI_SET_EVBIT if arg < EventType::COUNT as usize => setup.ev_bits.set(arg, true),
+            UI_SET_KEYBIT if arg < KEY_CNT => setup.key_bits.set(arg, true),
+            UI_SET_RELBIT if arg < REL_CNT => setup.rel_bits.set(arg, true),
+            UI_SET_ABSBIT if arg < ABS_CNT => setup.abs_bits.set(arg, true),
+            _ => return Err(LinuxError::EINVAL),
+        };
+        Ok(())
+    }
+
+    fn create(&self) -> VfsResult<()> {
+        let mut inner = self.inner.lock();
+        if inner.created.is_some() || inner.setup.name.is_empty() {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut setup = inner.setup.clone();
+        // Every device reports EV_SYN.
+        setup.ev_bits.set(0, true);
+        let input = Arc::new(VirtualInput {
+            setup,
+            events: SpinNoIrq::new(VecDeque::new()),
+        });
+        let name = event::add_virtual(self.fs.clone(), input.clone());
+        inner.created = Some((input, name));
+        Ok(())
+    }
+
+    fn destroy(&self) -> VfsResult<()> {
+        let (_, name) = self.inner.lock().created.take().ok_or(LinuxError::EINVAL)?;
+        event::remove_virtual(&name);
+        Ok(())
+    }
+}
+
+impl Drop for Uinput {
+    fn drop(&mut self) {
+        if let Some((_, name)) = self.inner.get_mut().created.take() {
+            event::remove_virtual(&name);
+        }
+    }
+}
+
+impl DeviceOps for Uinput {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    /// Injects the events in `buf` into the created device.
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        let inner = self.inner.lock();
+        let (input, _) = inner.created.as_ref().ok_or(LinuxError::EINVAL)?;
+        if buf.len() < size_of::<InputEvent>() {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut events = input.events.lock();
+        let mut written = 0;
+        for chunk in buf.chunks_exact(size_of::<InputEvent>()) {
+            let event = InputEvent::read_from_bytes(chunk).map_err(|_| LinuxError::EINVAL)?;
+            if events.len() >= MAX_PENDING {
+                events.pop_front();
+            }
+            events.push_back((event.event_type, event.code, event.value));
+            written += chunk.len();
+        }
+        Ok(written)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            UI_GET_VERSION => {
+                (arg as *mut u32).vm_write(UINPUT_VERSION)?;
+            }
+            UI_SET_EVBIT | UI_SET_KEYBIT | UI_SET_RELBIT | UI_SET_ABSBIT => {
+                self.set_bit(cmd, arg)?;
+            }
+            UI_DEV_SETUP => {
+                let setup = (arg as *const UinputSetup).vm_read()?;
+                let name = CStr::from_bytes_until_nul(&setup.name)
+                    .ok()
+                    .and_then(|it| it.to_str().ok())
+                    .ok_or(LinuxError::EINVAL)?;
+                let mut inner = self.inner.lock();
+                if inner.created.is_some() {
+                    return Err(LinuxError::EINVAL);
+                }
+                inner.setup.id = setup.id;
+                inner.setup.name = name.to_string();
+            }
+            UI_ABS_SETUP => {
+                let abs = (arg as *const UinputAbsSetup).vm_read()?;
+                if abs.code as usize >= ABS_CNT {
+                    return Err(LinuxError::EINVAL);
+                }
+                let mut inner = self.inner.lock();
+                if inner.created.is_some() {
+                    return Err(LinuxError::EINVAL);
+                }
+                inner.setup.abs_info[abs.code as usize] = abs.absinfo;
+            }
+            UI_DEV_CREATE => self.create()?,
+            UI_DEV_DESTROY => self.destroy()?,
+            _ => return Err(LinuxError::ENOTTY),
+        }
+        Ok(0)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
@@ -0,0 +1,286 @@
+//! `/dev/uring_lite`, a ring shared with the kernel for measuring latencies.
+//!
+//! After `URING_LITE_SETUP`, user space maps the device: a header page, then
+//! the submission queue and the completion queue, both of
+//! [`RingParams::entries`] [`RingEntry`]s. It queues entries by advancing
+//! `sq_tail`, and rings the doorbell by writing anything to the device. The
+//! kernel then moves them to the completion queue, stamped with the time it
+//! saw them, and makes the device readable for `poll`.
+//!
+//! The indices are free-running and only the owner of each writes it: user
+//! space `sq_tail` and `cq_head`, the kernel `sq_head` and `cq_tail`. Every
+//! open file has a ring of its own; setting it up again starts it over.
+//!
+//! The ring is mapped as normal, cacheable memory, the same as the kernel's
+//! linear mapping it is accessed through, so both sides see the same data.
+//! The device is [`NodeFlags::NON_CACHEABLE`] only in that it bypasses the
+//! page cache.
+
+use alloc::sync::Arc;
+use core::{
+    any::Any,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Context,
+};
+
+use axalloc::global_allocator;
+use axerrno::LinuxError;
+use axfs_ng_vfs::{DeviceId, NodeFlags, NodeType, VfsResult};
+use axhal::{mem::virt_to_phys, time::monotonic_time};
+use axio::{IoEvents, PollSet, Pollable};
+use axsync::Mutex;
+use bytemuck::AnyBitPattern;
+use memory_addr::{PAGE_SIZE_4K, PhysAddrRange, VirtAddr};
+use starry_core::vfs::{Device, DeviceMmap, DeviceOps, SimpleFs};
+use starry_vm::{VmMutPtr, VmPtr};
+
+pub const URING_LITE_DEVICE_ID: DeviceId = DeviceId::new(10, 1025);
+
+/// `_IOWR('R', 1, struct RingParams)`.
+const URING_LITE_SETUP: u32 = 0xc018_5201;
+
+/// The most entries a queue can have.
+const MAX_ENTRIES: u32 = 4096;
+const SQ_OFFSET: usize = PAGE_SIZE_4K;
+const CQ_OFFSET: usize = SQ_OFFSET + MAX_ENTRIES as usize * size_of::<RingEntry>();
+const RING_SIZE: usize = CQ_OFFSET + MAX_ENTRIES as usize * size_of::<RingEntry>();
+
+/// The argument of `URING_LITE_SETUP`.
+#[repr(C)]
+#[derive(Clone, Copy, AnyBitPattern)]
+struct RingParams {
+    /// The size of each queue, a power of two. Set by the caller.
+    entries: u32,
+    flags: u32,
+    sq_off: u32,
+    cq_off: u32,
+    /// How much to map.
+    ring_size: u64,
+}
+
+/// The header page of the ring.
+#[repr(C)]
+struct RingHeader {
+    sq_head: AtomicU32,
+    sq_tail: AtomicU32,
+    cq_head: AtomicU32,
+    cq_tail: AtomicU32,
+    entries: AtomicU32,
+}
+
+/// An entry of either queue.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RingEntry {
+    /// Passed through untouched.
+    user_data: u64,
+    /// When user space queued it, by its clock.
+    user_ns: u64,
+    /// When the kernel saw it, on the monotonic clock.
+    kernel_ns: u64,
+    /// Passed through untouched.
+    code: u32,
+    flags: u32,
+}
+
+struct Ring {
+    base: VirtAddr,
+    entries: u32,
+    /// The copies of the indices the kernel owns, since the ones in the
+    /// header can be overwritten by user space.
+    sq_head: u32,
+    cq_tail: u32,
+}
+
+impl Ring {
+    fn header(&self) -> &RingHeader {
+        // SAFETY: the header page lives as long as the device owning the ring
+        unsafe { &*self.base.as_ptr_of::<RingHeader>() }
+    }
+
+    fn entry(&self, queue: usize, index: u32) -> *mut RingEntry {
+        let index = (index & (self.entries - 1)) as usize;
+        (self.base + queue + index * size_of::<RingEntry>()).as_mut_ptr_of()
+    }
+
+    /// Moves what was queued to the completion queue, as far as there is
+    /// room. Returns how many entries were completed.
+    fn complete(&mut self) -> VfsResult<u32> {
+        let header = self.header();
+        let sq_tail = header.sq_tail.load(Ordering::Acquire);
+        if sq_tail.wrapping_sub(self.sq_head) > self.entries {
+            return Err(LinuxError::EINVAL);
+        }
+        let now = monotonic_time().as_nanos() as u64;
+        let mut completed = 0;
+        while self.sq_head != sq_tail {
+            let cq_head = self.header().cq_head.load(Ordering::Acquire);
+            if self.cq_tail.wrapping_sub(cq_head) >= self.entries {
+                break;
+            }
+            // SAFETY: the entries are in the ring, which user space may
+            // change at any time but only as plain data
+            unsafe {
+                let mut entry = self.entry(SQ_OFFSET, self.sq_head).read_volatile();
+                entry.kernel_ns = now;
+                self.entry(CQ_OFFSET, self.cq_tail).write_volatile(entry);
+            }
+            self.sq_head = self.sq_head.wrapping_add(1);
+            self.cq_tail = self.cq_tail.wrapping_add(1);
+            completed += 1;
+        }
+        let header = self.header();
+        header.sq_head.store(self.sq_head, Ordering::Release);
+        header.cq_tail.store(self.cq_tail, Ordering::Release);
+        Ok(completed)
+    }
+}
+
+/// `/dev/uring_lite`, or a file opened on it.
+pub struct UringLite {
+    fs: Arc<SimpleFs>,
+    /// The memory of the ring, allocated on the first setup.
+    base: Mutex<Option<VirtAddr>>,
+    /// Whether the ring was handed out to be mapped, in which case it is
+    /// never freed, as the mapping may outlive the file.
+    mapped: AtomicBool,
+    ring: Mutex<Option<Ring>>,
+    poll_cq: PollSet,
+}
+
+impl UringLite {
+    pub fn new(fs: Arc<SimpleFs>) -> Self {
+        Self {
+            fs,
+            base: Mutex::new(None),
+            mapped: AtomicBool::new(false),
+            ring: Mutex::new(None),
+            poll_cq: PollSet::new(),
+        }
+    }
+
+    /// Opens the device, with a ring of its own.
+    pub fn open(&self) -> Arc<Device> {
+        Device::new(
+            self.fs.clone(),
+            NodeType::CharacterDevice,
+            URING_LITE_DEVICE_ID,
+            Arc::new(Self::new(self.fs.clone())),
+        )
+    }
+
+    fn setup(&self, entries: u32) -> VfsResult<()> {
+        if !entries.is_power_of_two() || entries > MAX_ENTRIES {
+            return Err(LinuxError::EINVAL);
+        }
+        let mut base = self.base.lock();
+        let base = match *base {
+            Some(base) => base,
+            None => {
+                let pages = RING_SIZE / PAGE_SIZE_4K;
+                let vaddr = global_allocator()
+                    .alloc_pages(pages, PAGE_SIZE_4K)
+                    .map_err(|_| LinuxError::ENOMEM)?;
+                *base = Some(VirtAddr::from(vaddr));
+                VirtAddr::from(vaddr)
+            }
+        };
+        // SAFETY: the ring was allocated above
+        unsafe { core::ptr::write_bytes(base.as_mut_ptr(), 0, RING_SIZE) };
+        let ring = Ring {
+            base,
+            entries,
+            sq_head: 0,
+            cq_tail: 0,
+        };
+        ring.header().entries.store(entries, Ordering::Release);
+        *self.ring.lock() = Some(ring);
+        Ok(())
+    }
+}
+
+impl DeviceOps for UringLite {
+    fn read_at(&self, _buf: &mut [u8], _offset: u64) -> VfsResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    /// Rings the doorbell.
+    fn write_at(&self, buf: &[u8], _offset: u64) -> VfsResult<usize> {
+        let mut ring = self.ring.lock();
+        let ring = ring.as_mut().ok_or(LinuxError::ENXIO)?;
+        if ring.complete()? > 0 {
+            self.poll_cq.wake();
+        }
+        Ok(buf.len())
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            URING_LITE_SETUP => {
+                let mut params = (arg as *const RingParams).vm_read()?;
+                self.setup(params.entries)?;
+                params.sq_off = SQ_OFFSET as _;
+                params.cq_off = CQ_OFFSET as _;
+                params.ring_size = RING_SIZE as _;
+                (arg as *mut RingParams).vm_write(params)?;
+                Ok(0)
+            }
+            _ => Err(LinuxError::ENOTTY),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_pollable(&self) -> Option<&dyn Pollable> {
+        Some(self)
+    }
+
+    fn mmap(&self) -> DeviceMmap {
+        match *self.base.lock() {
+            Some(base) => {
+                self.mapped.store(true, Ordering::Release);
+                DeviceMmap::Physical(PhysAddrRange::from_start_size(
+                    virt_to_phys(base),
+                    RING_SIZE,
+                ))
+            }
+            None => DeviceMmap::None,
+        }
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE | NodeFlags::STREAM
+    }
+}
+
+impl Drop for UringLite {
+    fn drop(&mut self) {
+        if let Some(base) = *self.base.lock()
+            && !self.mapped.load(Ordering::Acquire)
+        {
+            global_allocator().dealloc_pages(base.as_usize(), RING_SIZE / PAGE_SIZE_4K);
+        }
+    }
+}
+
+impl Pollable for UringLite {
+    fn poll(&self) -> IoEvents {
+        let mut events = IoEvents::OUT;
+        if let Some(ring) = self.ring.lock().as_ref() {
+            let header = ring.header();
+            events.set(
+                IoEvents::IN,
+                header.cq_head.load(Ordering::Acquire) != ring.cq_tail,
+            );
+        }
+        events
+    }
+
+    fn register(&self, context: &mut Context<'_>, events: IoEvents) {
+        if events.contains(IoEvents::IN) {
+            self.poll_cq.register(context.waker());
+        }
+    }
+}
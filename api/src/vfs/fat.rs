@@ -0,0 +1,761 @@
+//! FAT filesystems on block devices, e.g. an image attached to a loop device.
+//!
+//! FAT has no inodes, so nodes are identified by their path, and get inode
+//! numbers assigned on first use which stay stable while the filesystem is
+//! mounted. Nodes are not cacheable: writes go straight through to the
+//! device, and the device is flushed when the filesystem is unmounted.
+
+use alloc::{
+    borrow::ToOwned,
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::{any::Any, task::Context, time::Duration};
+
+use axerrno::LinuxError;
+use axfs_ng_vfs::{
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Location, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission,
+    NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
+};
+use axio::{IoEvents, Pollable};
+use axsync::Mutex;
+use chrono::NaiveDate;
+use fatfs::{
+    DefaultTimeProvider, FileSystem, FsOptions, IoBase, LossyOemCpConverter, Read, Seek, SeekFrom,
+    Write,
+};
+use hashbrown::HashMap;
+use linux_raw_sys::general::MSDOS_SUPER_MAGIC;
+use starry_core::vfs::DeviceOps;
+
+const ROOT_INO: u64 = 1;
+
+/// Error of the underlying device, as seen by `fatfs`.
+#[derive(Debug)]
+struct DiskError(LinuxError);
+
+impl fatfs::IoError for DiskError {
+    fn is_interrupted(&self) -> bool {
+        self.0 == LinuxError::EINTR
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        Self(LinuxError::EIO)
+    }
+
+    fn new_write_zero_error() -> Self {
+        Self(LinuxError::ENOSPC)
+    }
+}
+
+/// A block device accessed as a seekable stream.
+struct BlockDisk {
+    dev: Arc<dyn DeviceOps>,
+    pos: u64,
+}
+
+impl IoBase for BlockDisk {
+    type Error = DiskError;
+}
+
+impl Read for BlockDisk {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, DiskError> {
+        let read = self.dev.read_at(buf, self.pos).map_err(DiskError)?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for BlockDisk {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, DiskError> {
+        let written = self.dev.write_at(buf, self.pos).map_err(DiskError)?;
+        self.pos += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), DiskError> {
+        self.dev.sync().map_err(DiskError)
+    }
+}
+
+impl Seek for BlockDisk {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, DiskError> {
+        self.pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            // `fatfs` never seeks relative to the end of the device.
+            SeekFrom::End(_) => None,
+        }
+        .ok_or(DiskError(LinuxError::EINVAL))?;
+        Ok(self.pos)
+    }
+}
+
+impl Drop for BlockDisk {
+    fn drop(&mut self) {
+        if let Err(err) = self.dev.sync() {
+            warn!("Failed to flush FAT filesystem: {:?}", err);
+        }
+    }
+}
+
+type FatDir<'a> = fatfs::Dir<'a, BlockDisk, DefaultTimeProvider, LossyOemCpConverter>;
+type FatFile<'a> = fatfs::File<'a, BlockDisk, DefaultTimeProvider, LossyOemCpConverter>;
+type FatEntry<'a> = fatfs::DirEntry<'a, BlockDisk, DefaultTimeProvider, LossyOemCpConverter>;
+
+fn fat_error(err: fatfs::Error<DiskError>) -> VfsError {
+    match err {
+        fatfs::Error::Io(DiskError(err)) => err,
+        fatfs::Error::NotFound => VfsError::ENOENT,
+        fatfs::Error::AlreadyExists => VfsError::EEXIST,
+        fatfs::Error::DirectoryIsNotEmpty => VfsError::ENOTEMPTY,
+        fatfs::Error::NotEnoughSpace => VfsError::ENOSPC,
+        fatfs::Error::InvalidFileNameLength => VfsError::ENAMETOOLONG,
+        fatfs::Error::InvalidInput | fatfs::Error::UnsupportedFileNameCharacter => VfsError::EINVAL,
+        _ => VfsError::EIO,
+    }
+}
+
+fn fat_time(date: fatfs::Date, time: Option<fatfs::Time>) -> Duration {
+    let (hour, min, sec, millis) =
+        time.map_or((0, 0, 0, 0), |it| (it.hour, it.min, it.sec, it.millis));
+    NaiveDate::from_ymd_opt(date.year as _, date.month as _, date.day as _)
+        .and_then(|it| it.and_hms_milli_opt(hour as _, min as _, sec as _, millis as _))
+        .map_or(Duration::ZERO, |it| {
+            Duration::from_millis(it.and_utc().timestamp_millis().max(0) as _)
+        })
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+fn open_dir<'a>(root: &FatDir<'a>, path: &str) -> VfsResult<FatDir<'a>> {
+    if path.is_empty() {
+        Ok(root.clone())
+    } else {
+        root.open_dir(path).map_err(fat_error)
+    }
+}
+
+fn find_entry<'a>(dir: &FatDir<'a>, name: &str) -> VfsResult<FatEntry<'a>> {
+    for entry in dir.iter() {
+        let entry = entry.map_err(fat_error)?;
+        if entry.file_name().eq_ignore_ascii_case(name) {
+            return Ok(entry);
+        }
+    }
+    Err(VfsError::ENOENT)
+}
+
+/// Reads `buf.len()` bytes of `dev` at `offset`.
+fn read_exact(dev: &dyn DeviceOps, buf: &mut [u8], offset: u64) -> VfsResult<()> {
+    let mut read = 0;
+    while read < buf.len() {
+        match dev.read_at(&mut buf[read..], offset + read as u64)? {
+            0 => return Err(VfsError::EIO),
+            n => read += n,
+        }
+    }
+    Ok(())
+}
+
+/// Where the allocation table and the clusters of a FAT volume are, from its
+/// boot sector. `fatfs` keeps this to itself, but trimming needs to know
+/// which clusters are free.
+struct FatLayout {
+    /// Bits per table entry: 12, 16 or 32.
+    entry_bits: u64,
+    fat_offset: u64,
+    fat_size: u64,
+    data_offset: u64,
+    cluster_size: u64,
+    clusters: u64,
+}
+
+impl FatLayout {
+    fn read(dev: &dyn DeviceOps) -> VfsResult<Self> {
+        let mut bpb = [0; 40];
+        read_exact(dev, &mut bpb, 0)?;
+        let u16_at = |at: usize| u16::from_le_bytes([bpb[at], bpb[at + 1]]) as u64;
+        let u32_at = |at: usize| u32::from_le_bytes(bpb[at..at + 4].try_into().unwrap()) as u64;
+
+        let sector_size = u16_at(11);
+        let sectors_per_cluster = bpb[13] as u64;
+        let reserved_sectors = u16_at(14);
+        let fats = bpb[16] as u64;
+        let root_entries = u16_at(17);
+        let total_sectors = match u16_at(19) {
+            0 => u32_at(32),
+            n => n,
+        };
+        let fat_sectors = match u16_at(22) {
+            0 => u32_at(36),
+            n => n,
+        };
+        if sector_size == 0 || sectors_per_cluster == 0 {
+            return Err(VfsError::EIO);
+        }
+        let root_sectors = (root_entries * 32).div_ceil(sector_size);
+        let data_sector = reserved_sectors + fats * fat_sectors + root_sectors;
+        let clusters = total_sectors.saturating_sub(data_sector) / sectors_per_cluster;
+        // The FAT type follows from the number of clusters alone.
+        let entry_bits = match clusters {
+            0..4085 => 12,
+            4085..65525 => 16,
+            _ => 32,
+        };
+        let fat_size = fat_sectors * sector_size;
+        Ok(Self {
+            entry_bits,
+            fat_offset: reserved_sectors * sector_size,
+            fat_size,
+            data_offset: data_sector * sector_size,
+            cluster_size: sectors_per_cluster * sector_size,
+            // Clusters are numbered from 2, and the table may be too small
+            // for all of them.
+            clusters: clusters.min((fat_size * 8 / entry_bits).saturating_sub(2)),
+        })
+    }
+
+    /// Whether `cluster` is free in the allocation table `fat`.
+    fn is_free(&self, fat: &[u8], cluster: u64) -> bool {
+        let at = (cluster * self.entry_bits / 8) as usize;
+        let pair = || u16::from_le_bytes([fat[at], fat[at + 1]]);
+        let entry = match self.entry_bits {
+            // Two 12-bit entries share three bytes.
+            12 if cluster % 2 == 0 => (pair() & 0xfff).into(),
+            12 => (pair() >> 4).into(),
+            16 => pair().into(),
+            _ => u32::from_le_bytes(fat[at..at + 4].try_into().unwrap()) & 0x0fff_ffff,
+        };
+        entry == 0
+    }
+
+    /// Returns where `cluster` starts on the device.
+    fn cluster_offset(&self, cluster: u64) -> u64 {
+        self.data_offset + (cluster - 2) * self.cluster_size
+    }
+}
+
+/// The mounted FAT filesystems, to find the one a `FITRIM` is for.
+static FILESYSTEMS: Mutex<Vec<Weak<FatFs>>> = Mutex::new(Vec::new());
+
+/// Discards the free space from `start` to `end` of the filesystem `loc` is
+/// on, in runs of at least `min_len` bytes, for `FITRIM`. Returns how many
+/// bytes were discarded.
+///
+/// Only FAT filesystems can tell their free space; the others fail with
+/// `EOPNOTSUPP`.
+pub fn trim(loc: &Location, start: u64, end: u64, min_len: u64) -> VfsResult<u64> {
+    let fs: &dyn FilesystemOps = &**loc.filesystem();
+    let key = fs as *const dyn FilesystemOps as *const ();
+    let fat = FILESYSTEMS
+        .lock()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|it| Arc::as_ptr(it) as *const () == key)
+        .ok_or(VfsError::EOPNOTSUPP)?;
+    fat.trim(start, end, min_len)
+}
+
+/// Inode numbers assigned to paths.
+#[derive(Default)]
+struct InodeMap {
+    last: u64,
+    by_path: HashMap<String, u64>,
+    paths: HashMap<u64, String>,
+}
+
+impl InodeMap {
+    fn get_or_insert(&mut self, path: &str) -> u64 {
+        if let Some(ino) = self.by_path.get(path) {
+            return *ino;
+        }
+        self.last += 1;
+        let ino = self.last;
+        self.by_path.insert(path.to_owned(), ino);
+        self.paths.insert(ino, path.to_owned());
+        ino
+    }
+
+    /// Applies `rename` to `path` and everything below it.
+    fn rename(&mut self, path: &str, rename: impl Fn(&str) -> Option<String>) {
+        let prefix = format!("{path}/");
+        let moved = self
+            .by_path
+            .keys()
+            .filter(|it| *it == path || it.starts_with(&prefix))
+            .cloned()
+            .collect::<Vec<_>>();
+        for old in moved {
+            let ino = self.by_path.remove(&old).unwrap();
+            match rename(&old) {
+                Some(new) => {
+                    self.by_path.insert(new.clone(), ino);
+                    self.paths.insert(ino, new);
+                }
+                None => {
+                    self.paths.remove(&ino);
+                }
+            }
+        }
+    }
+}
+
+/// A FAT filesystem.
+pub struct FatFs {
+    this: Weak<FatFs>,
+    inner: Mutex<FileSystem<BlockDisk>>,
+    dev: Arc<dyn DeviceOps>,
+    cluster_size: u64,
+    read_only: bool,
+    inodes: Mutex<InodeMap>,
+    /// The root directory. Its node refers back to the filesystem, so only
+    /// the mount keeps it alive: this way the filesystem is dropped, and the
+    /// device flushed, once unmounted.
+    root: Mutex<Option<WeakDirEntry>>,
+}
+
+impl FatFs {
+    /// Opens the FAT filesystem on `dev`.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(dev: Arc<dyn DeviceOps>, read_only: bool) -> VfsResult<Filesystem> {
+        let disk = BlockDisk {
+            dev: dev.clone(),
+            pos: 0,
+        };
+        let inner = FileSystem::new(disk, FsOptions::new()).map_err(fat_error)?;
+        let cluster_size = inner.stats().map_err(fat_error)?.cluster_size() as u64;
+        let mut inodes = InodeMap::default();
+        assert_eq!(inodes.get_or_insert(""), ROOT_INO);
+        let fs = Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            inner: Mutex::new(inner),
+            dev,
+            cluster_size,
+            read_only,
+            inodes: Mutex::new(inodes),
+            root: Mutex::default(),
+        });
+        let mut filesystems = FILESYSTEMS.lock();
+        filesystems.retain(|it| it.strong_count() > 0);
+        filesystems.push(Arc::downgrade(&fs));
+        drop(filesystems);
+        Ok(Filesystem::new(fs))
+    }
+
+    fn check_writable(&self) -> VfsResult<()> {
+        if self.read_only {
+            Err(VfsError::EROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Discards the runs of free clusters from `start` to `end` of the
+    /// device, skipping those shorter than `min_len` bytes.
+    fn trim(&self, start: u64, end: u64, min_len: u64) -> VfsResult<u64> {
+        self.check_writable()?;
+        // Keeps `fatfs` from allocating clusters meanwhile.
+        let _fs = self.inner.lock();
+        let layout = FatLayout::read(&*self.dev)?;
+        let mut fat = vec![0; layout.fat_size as usize];
+        read_exact(&*self.dev, &mut fat, layout.fat_offset)?;
+
+        let mut trimmed = 0;
+        let mut run = None;
+        // One past the last cluster ends the last run.
+        for cluster in 2..layout.clusters + 3 {
+            let free = cluster < layout.clusters + 2 && layout.is_free(&fat, cluster);
+            match (free, run) {
+                (true, None) => run = Some(cluster),
+                (false, Some(first)) => {
+                    run = None;
+                    let from = layout.cluster_offset(first).max(start);
+                    let to = layout.cluster_offset(cluster).min(end);
+                    if to > from && to - from >= min_len {
+                        self.dev.discard(from, to - from)?;
+                        trimmed += to - from;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(trimmed)
+    }
+}
+
+impl FilesystemOps for FatFs {
+    fn name(&self) -> &str {
+        "vfat"
+    }
+
+    fn root_dir(&self) -> DirEntry {
+        let mut root = self.root.lock();
+        if let Some(entry) = root.as_ref().and_then(WeakDirEntry::upgrade) {
+            return entry;
+        }
+        let fs = self.this.upgrade().unwrap();
+        DirEntry::new_dir(
+            |this| {
+                *root = Some(this.clone());
+                DirNode::new(FatNode::new(fs, ROOT_INO, Some(this)))
+            },
+            Reference::root(),
+        )
+    }
+
+    fn stat(&self) -> VfsResult<StatFs> {
+        let stats = self.inner.lock().stats().map_err(fat_error)?;
+        Ok(StatFs {
+            fs_type: MSDOS_SUPER_MAGIC,
+            block_size: stats.cluster_size() as _,
+            blocks: stats.total_clusters() as _,
+            blocks_free: stats.free_clusters() as _,
+            blocks_available: stats.free_clusters() as _,
+
+            file_count: 0,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: stats.cluster_size() as _,
+            mount_flags: 0,
+        })
+    }
+}
+
+struct FatNode {
+    fs: Arc<FatFs>,
+    ino: u64,
+    this: Option<WeakDirEntry>,
+}
+
+impl FatNode {
+    fn new(fs: Arc<FatFs>, ino: u64, this: Option<WeakDirEntry>) -> Arc<Self> {
+        Arc::new(Self { fs, ino, this })
+    }
+
+    fn path(&self) -> VfsResult<String> {
+        // The path is gone once the node has been unlinked.
+        self.fs
+            .inodes
+            .lock()
+            .paths
+            .get(&self.ino)
+            .cloned()
+            .ok_or(VfsError::ENOENT)
+    }
+
+    fn with_dir<R>(&self, f: impl FnOnce(&FatDir) -> VfsResult<R>) -> VfsResult<R> {
+        let path = self.path()?;
+        let fs = self.fs.inner.lock();
+        f(&open_dir(&fs.root_dir(), &path)?)
+    }
+
+    fn with_file<R>(&self, f: impl FnOnce(&mut FatFile) -> VfsResult<R>) -> VfsResult<R> {
+        let path = self.path()?;
+        let fs = self.fs.inner.lock();
+        let mut file = fs.root_dir().open_file(&path).map_err(fat_error)?;
+        f(&mut file)
+    }
+
+    fn new_entry(&self, name: &str, node_type: NodeType) -> VfsResult<DirEntry> {
+        let fs = self.fs.clone();
+        let ino = fs.inodes.lock().get_or_insert(&join(&self.path()?, name));
+        let reference = Reference::new(
+            self.this.as_ref().and_then(WeakDirEntry::upgrade),
+            name.to_owned(),
+        );
+        Ok(if node_type == NodeType::Directory {
+            DirEntry::new_dir(
+                |this| DirNode::new(FatNode::new(fs, ino, Some(this))),
+                reference,
+            )
+        } else {
+            DirEntry::new_file(
+                FileNode::new(FatNode::new(fs, ino, None)),
+                node_type,
+                reference,
+            )
+        })
+    }
+}
+
+fn node_type(entry: &FatEntry) -> NodeType {
+    if entry.is_dir() {
+        NodeType::Directory
+    } else {
+        NodeType::RegularFile
+    }
+}
+
+/// Extends `file` with zeros up to `len` bytes.
+fn extend(file: &mut FatFile, len: u64) -> VfsResult<()> {
+    let end = file.seek(SeekFrom::End(0)).map_err(fat_error)?;
+    let zeros = [0; 512];
+    let mut remaining = len.saturating_sub(end);
+    while remaining > 0 {
+        let chunk = remaining.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk]).map_err(fat_error)?;
+        remaining -= chunk as u64;
+    }
+    Ok(())
+}
+
+impl NodeOps for FatNode {
+    fn inode(&self) -> u64 {
+        self.ino
+    }
+
+    fn metadata(&self) -> VfsResult<Metadata> {
+        let path = self.path()?;
+        let cluster_size = self.fs.cluster_size;
+        let mut metadata = Metadata {
+            device: 0,
+            inode: self.ino,
+            nlink: 1,
+            mode: NodePermission::from_bits_truncate(0o755),
+            node_type: NodeType::Directory,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            block_size: cluster_size,
+            blocks: 0,
+            rdev: DeviceId::default(),
+            atime: Duration::default(),
+            mtime: Duration::default(),
+            ctime: Duration::default(),
+        };
+        if path.is_empty() {
+            return Ok(metadata);
+        }
+
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", &path));
+        let fs = self.fs.inner.lock();
+        let entry = find_entry(&open_dir(&fs.root_dir(), parent)?, name)?;
+        metadata.node_type = node_type(&entry);
+        if entry.is_file() {
+            metadata.mode = NodePermission::from_bits_truncate(0o644);
+            metadata.size = entry.len();
+            metadata.blocks = entry.len().div_ceil(cluster_size) * (cluster_size / 512);
+        }
+        let modified = entry.modified();
+        metadata.atime = fat_time(entry.accessed(), None);
+        metadata.mtime = fat_time(modified.date, Some(modified.time));
+        metadata.ctime = metadata.mtime;
+        Ok(metadata)
+    }
+
+    fn update_metadata(&self, _update: MetadataUpdate) -> VfsResult<()> {
+        // FAT has no owners or permissions to store.
+        Ok(())
+    }
+
+    fn filesystem(&self) -> &dyn FilesystemOps {
+        self.fs.as_ref()
+    }
+
+    fn sync(&self, _data_only: bool) -> VfsResult<()> {
+        self.fs.dev.sync()
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn flags(&self) -> NodeFlags {
+        NodeFlags::NON_CACHEABLE
+    }
+}
+
+impl FileNodeOps for FatNode {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
+        self.with_file(|file| {
+            file.seek(SeekFrom::Start(offset)).map_err(fat_error)?;
+            let mut read = 0;
+            while read < buf.len() {
+                match file.read(&mut buf[read..]).map_err(fat_error)? {
+                    0 => break,
+                    n => read += n,
+                }
+            }
+            Ok(read)
+        })
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> VfsResult<usize> {
+        self.fs.check_writable()?;
+        self.with_file(|file| {
+            // Seeking past the end is clamped to the end, so fill the gap first.
+            extend(file, offset)?;
+            file.seek(SeekFrom::Start(offset)).map_err(fat_error)?;
+            file.write_all(buf).map_err(fat_error)?;
+            Ok(buf.len())
+        })
+    }
+
+    fn append(&self, buf: &[u8]) -> VfsResult<(usize, u64)> {
+        self.fs.check_writable()?;
+        self.with_file(|file| {
+            file.seek(SeekFrom::End(0)).map_err(fat_error)?;
+            file.write_all(buf).map_err(fat_error)?;
+            let len = file.seek(SeekFrom::Current(0)).map_err(fat_error)?;
+            Ok((buf.len(), len))
+        })
+    }
+
+    fn set_len(&self, len: u64) -> VfsResult<()> {
+        self.fs.check_writable()?;
+        self.with_file(|file| {
+            extend(file, len)?;
+            file.seek(SeekFrom::Start(len)).map_err(fat_error)?;
+            file.truncate().map_err(fat_error)
+        })
+    }
+
+    fn set_symlink(&self, _target: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+}
+
+impl Pollable for FatNode {
+    fn poll(&self) -> IoEvents {
+        IoEvents::IN | IoEvents::OUT
+    }
+
+    fn register(&self, _context: &mut Context<'_>, _events: IoEvents) {}
+}
+
+impl DirNodeOps for FatNode {
+    fn read_dir(&self, offset: u64, sink: &mut dyn DirEntrySink) -> VfsResult<usize> {
+        let path = self.path()?;
+        let fs = self.fs.inner.lock();
+        let dir = open_dir(&fs.root_dir(), &path)?;
+
+        // The root directory has no `.` and `..` entries on disk, so they are
+        // made up for every directory.
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let mut inodes = self.fs.inodes.lock();
+        let mut entries = vec![
+            (".".to_string(), self.ino, NodeType::Directory),
+            (
+                "..".to_string(),
+                inodes.get_or_insert(parent),
+                NodeType::Directory,
+            ),
+        ];
+        for entry in dir.iter() {
+            let entry = entry.map_err(fat_error)?;
+            let name = entry.file_name();
+            if name == "." || name == ".." {
+                continue;
+            }
+            let ino = inodes.get_or_insert(&join(&path, &name));
+            entries.push((name, ino, node_type(&entry)));
+        }
+        drop(inodes);
+
+        let mut count = 0;
+        for (i, (name, ino, node_type)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if !sink.accept(&name, ino, node_type, i as u64 + 1) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<DirEntry> {
+        let (name, node_type) = self.with_dir(|dir| {
+            let entry = find_entry(dir, name)?;
+            Ok((entry.file_name(), node_type(&entry)))
+        })?;
+        self.new_entry(&name, node_type)
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        node_type: NodeType,
+        _permission: NodePermission,
+    ) -> VfsResult<DirEntry> {
+        self.fs.check_writable()?;
+        self.with_dir(|dir| {
+            if find_entry(dir, name).is_ok() {
+                return Err(VfsError::EEXIST);
+            }
+            match node_type {
+                NodeType::Directory => dir.create_dir(name).map(drop),
+                NodeType::RegularFile => dir.create_file(name).map(drop),
+                _ => return Err(VfsError::EPERM),
+            }
+            .map_err(fat_error)
+        })?;
+        self.new_entry(name, node_type)
+    }
+
+    fn link(&self, _name: &str, _target: &DirEntry) -> VfsResult<DirEntry> {
+        Err(VfsError::EPERM)
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        self.fs.check_writable()?;
+        let name = self.with_dir(|dir| {
+            let name = find_entry(dir, name)?.file_name();
+            dir.remove(&name).map_err(fat_error)?;
+            Ok(name)
+        })?;
+        let path = join(&self.path()?, &name);
+        self.fs.inodes.lock().rename(&path, |_| None);
+        Ok(())
+    }
+
+    fn rename(&self, src_name: &str, dst_dir: &DirNode, dst_name: &str) -> VfsResult<()> {
+        self.fs.check_writable()?;
+        let dst_node = dst_dir.downcast::<Self>()?;
+        let src_dir_path = self.path()?;
+        let dst_dir_path = dst_node.path()?;
+
+        let fs = self.fs.inner.lock();
+        let root = fs.root_dir();
+        let src_dir = open_dir(&root, &src_dir_path)?;
+        let dst_dir = open_dir(&root, &dst_dir_path)?;
+        let src = find_entry(&src_dir, src_name)?;
+        let src_name = src.file_name();
+        if let Ok(dst) = find_entry(&dst_dir, dst_name) {
+            if src_dir_path == dst_dir_path && dst.file_name() == src_name {
+                return Ok(());
+            }
+            if dst.is_dir() != src.is_dir() {
+                return Err(if dst.is_dir() {
+                    VfsError::EISDIR
+                } else {
+                    VfsError::ENOTDIR
+                });
+            }
+            dst_dir.remove(&dst.file_name()).map_err(fat_error)?;
+        }
+        src_dir
+            .rename(&src_name, &dst_dir, dst_name)
+            .map_err(fat_error)?;
+        drop(fs);
+
+        let src_path = join(&src_dir_path, &src_name);
+        let dst_path = join(&dst_dir_path, dst_name);
+        let mut inodes = self.fs.inodes.lock();
+        inodes.rename(&dst_path, |_| None);
+        inodes.rename(&src_path, |old| {
+            Some(format!("{dst_path}{}", &old[src_path.len()..]))
+        });
+        Ok(())
+    }
+}
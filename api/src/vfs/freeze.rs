@@ -0,0 +1,163 @@
+//! Filesystem freezing, for `FIFREEZE` and `FITHAW`.
+//!
+//! Freezing a filesystem waits for the changes in progress, writes its dirty
+//! data back and then holds off any further change: writes, truncations and
+//! changes to its directories wait until it is thawed, while reads go on.
+//! The disk then holds a consistent image that can be copied without
+//! unmounting the filesystem.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{future::poll_fn, task::Poll};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::{FilesystemOps, Location};
+use axio::PollSet;
+use axsync::Mutex;
+use axtask::future::try_block_on;
+use lazy_static::lazy_static;
+use starry_core::task::{WaitChannel, processes, wait_on};
+
+use crate::file::{FD_TABLE, File};
+
+#[derive(Default)]
+struct FreezeState {
+    frozen: bool,
+    /// The changes in progress.
+    writers: usize,
+}
+
+/// The filesystems being frozen or changed, by [`fs_key`].
+static STATES: Mutex<BTreeMap<usize, FreezeState>> = Mutex::new(BTreeMap::new());
+
+lazy_static! {
+    /// Woken when a filesystem is thawed or its last change in progress ends.
+    static ref POLL_FREEZE: PollSet = PollSet::new();
+}
+
+/// Identifies the filesystem `loc` is on.
+fn fs_key(loc: &Location) -> usize {
+    let fs: &dyn FilesystemOps = &**loc.filesystem();
+    fs as *const dyn FilesystemOps as *const () as usize
+}
+
+/// Blocks until `check` returns a result, retrying whenever a filesystem is
+/// thawed or a change ends.
+fn wait_for<T>(mut check: impl FnMut() -> Option<T>) -> LinuxResult<T> {
+    if let Some(result) = check() {
+        return Ok(result);
+    }
+    let _wait = wait_on(WaitChannel::Syscall("freeze"));
+    let result = try_block_on(poll_fn(|cx| {
+        if let Some(result) = check() {
+            return Poll::Ready(Ok(result));
+        }
+        POLL_FREEZE.register(cx.waker());
+        match check() {
+            Some(result) => Poll::Ready(Ok(result)),
+            None => Poll::Pending,
+        }
+    }))?;
+    result.ok_or(LinuxError::EINTR)
+}
+
+/// A change in progress to a filesystem, which keeps it from being frozen
+/// until dropped.
+pub struct WriteGuard {
+    key: usize,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        let mut states = STATES.lock();
+        let Some(state) = states.get_mut(&self.key) else {
+            return;
+        };
+        state.writers -= 1;
+        if state.writers == 0 {
+            if !state.frozen {
+                states.remove(&self.key);
+            }
+            POLL_FREEZE.wake();
+        }
+    }
+}
+
+/// Starts a change to the filesystem `loc` is on, waiting until it is thawed
+/// if it is frozen.
+pub fn start_write(loc: &Location) -> LinuxResult<WriteGuard> {
+    let key = fs_key(loc);
+    wait_for(|| {
+        let mut states = STATES.lock();
+        let state = states.entry(key).or_default();
+        if state.frozen {
+            return None;
+        }
+        state.writers += 1;
+        Some(WriteGuard { key })
+    })
+}
+
+/// Freezes the filesystem `loc` is on, for `FIFREEZE`.
+pub fn freeze(loc: &Location) -> LinuxResult<()> {
+    let key = fs_key(loc);
+    {
+        let mut states = STATES.lock();
+        let state = states.entry(key).or_default();
+        if state.frozen {
+            return Err(LinuxError::EBUSY);
+        }
+        state.frozen = true;
+    }
+    let result = wait_for(|| (STATES.lock()[&key].writers == 0).then_some(()))
+        .and_then(|()| write_back(loc, key));
+    if result.is_err() {
+        thaw(loc)?;
+    }
+    result
+}
+
+/// Thaws the filesystem `loc` is on, for `FITHAW`.
+pub fn thaw(loc: &Location) -> LinuxResult<()> {
+    let key = fs_key(loc);
+    let mut states = STATES.lock();
+    match states.get_mut(&key) {
+        Some(state) if state.frozen => {
+            state.frozen = false;
+            if state.writers == 0 {
+                states.remove(&key);
+            }
+        }
+        _ => return Err(LinuxError::EINVAL),
+    }
+    drop(states);
+    POLL_FREEZE.wake();
+    Ok(())
+}
+
+/// Writes back the files open on the filesystem `key`, then the filesystem
+/// itself.
+///
+/// The files are synced without taking their write-back errors, which are
+/// left for the `fsync` of their owners.
+fn write_back(loc: &Location, key: usize) -> LinuxResult<()> {
+    let mut files = Vec::new();
+    for proc_data in processes() {
+        let scope = proc_data.scope.read();
+        let table = FD_TABLE.scope(&scope).read();
+        files.extend(table.ids().filter_map(|fd| {
+            let file = table
+                .get(fd)?
+                .inner
+                .clone()
+                .into_any()
+                .downcast::<File>()
+                .ok()?;
+            (fs_key(file.inner().location()) == key).then_some(file)
+        }));
+    }
+    for file in files {
+        file.inner().sync(false)?;
+    }
+    loc.filesystem().flush()?;
+    Ok(())
+}
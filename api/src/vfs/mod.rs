@@ -1,47 +1,297 @@
 //! Virtual filesystems
 
+#[cfg(feature = "cpu-topology")]
+mod cpu;
+#[cfg(feature = "cpufreq")]
+mod cpufreq;
+mod cpuinfo;
 pub mod dev;
+mod fat;
+pub mod freeze;
+pub mod mount;
+mod mqueue;
+mod power;
 mod proc;
+pub mod stats;
+pub mod sysfs;
 mod tmp;
 
-use axerrno::LinuxResult;
-use axfs_ng::{FS_CONTEXT, FsContext};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::ops::Range;
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng::{FS_CONTEXT, FileBackend, FsContext};
 use axfs_ng_vfs::{
-    Filesystem, NodePermission,
+    DeviceId, Filesystem, Location, NodePermission, NodeType,
     path::{Path, PathBuf},
 };
+#[cfg(feature = "cpu-topology")]
+pub use cpu::cpu_capacity;
+pub use fat::{FatFs, trim};
+use linux_raw_sys::general::SYSFS_MAGIC;
+pub use mqueue::new_mqueuefs;
+pub use power::report_wakeup;
 pub use starry_core::vfs::{Device, DeviceOps, DirMapping, SimpleFs};
+use starry_core::vfs::{SimpleFile, XattrNode};
 pub use tmp::MemoryFs;
 
+use self::{
+    mount::{MOUNT_TABLE, Mount},
+    tmp::MemoryNode,
+};
+
 const DIR_PERMISSION: NodePermission = NodePermission::from_bits_truncate(0o755);
 
-fn mount_at(fs: &FsContext, path: &str, mount_fs: Filesystem) -> LinuxResult<()> {
+/// How many times an operation of the teardown is tried before giving up.
+const TEARDOWN_ATTEMPTS: usize = 3;
+
+fn mount_at(
+    fs: &FsContext,
+    path: &str,
+    mount_fs: Filesystem,
+    source: &str,
+    fs_type: &str,
+    options: &'static str,
+) -> LinuxResult<()> {
     if fs.resolve(path).is_err() {
         fs.create_dir(path, DIR_PERMISSION)?;
     }
     fs.resolve(path)?.mount(&mount_fs)?;
+    MOUNT_TABLE
+        .write()
+        .add(Mount::new(source, fs_type, options, fs.resolve(path)?)?);
     info!("Mounted {} at {}", mount_fs.name(), path);
     Ok(())
 }
 
+fn create_dir_all(fs: &FsContext, path: &str) -> LinuxResult<()> {
+    let mut current = PathBuf::new();
+    for comp in Path::new(path).components() {
+        current.push(comp.as_str());
+        if fs.resolve(&current).is_err() {
+            fs.create_dir(&current, DIR_PERMISSION)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the node at `loc` if its filesystem keeps extended attributes,
+/// which only the in-memory filesystems do.
+pub fn xattr_node(loc: &Location) -> LinuxResult<Arc<dyn XattrNode + Send + Sync>> {
+    let entry = loc.entry();
+    if let Ok(node) = entry.downcast::<MemoryNode>() {
+        Ok(node)
+    } else if let Ok(node) = entry.downcast::<SimpleFile>() {
+        Ok(node)
+    } else if let Ok(node) = entry.downcast::<Device>() {
+        Ok(node)
+    } else {
+        Err(LinuxError::EOPNOTSUPP)
+    }
+}
+
+/// Sets the length of the file at `loc` with `set_len`, waiting while its
+/// filesystem is frozen. On the in-memory filesystems, any extension is a
+/// hole taking no memory.
+pub fn truncate(loc: &Location, set_len: impl FnOnce() -> LinuxResult<()>) -> LinuxResult<()> {
+    let _write = freeze::start_write(loc)?;
+    set_len()
+}
+
+/// Allocates memory for the file at `loc` from `offset` to `end`, if it is on
+/// an in-memory filesystem, as `fallocate` does and writes do before filling
+/// holes. The other filesystems allocate on writes.
+pub fn allocate(loc: &Location, offset: u64, end: u64) -> LinuxResult<()> {
+    let _write = freeze::start_write(loc)?;
+    match loc.entry().downcast::<MemoryNode>() {
+        Ok(node) => node.allocate(offset, end),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Punches a hole from `offset` to `end` into `file`, as
+/// `FALLOC_FL_PUNCH_HOLE` does, without changing its length. Only the
+/// in-memory filesystems can store holes; the others fail with `EOPNOTSUPP`.
+///
+/// The page cache cannot drop pages from the middle of a file, so the data
+/// left in the range is zeroed through it. Its whole pages then no longer
+/// count as allocated, against the size limit or in `st_blocks`.
+pub fn punch_hole(file: &FileBackend, offset: u64, end: u64) -> LinuxResult<()> {
+    const ZEROES: [u8; 4096] = [0; 4096];
+
+    let loc = file.location();
+    let node = loc
+        .entry()
+        .downcast::<MemoryNode>()
+        .map_err(|_| LinuxError::EOPNOTSUPP)?;
+    let _write = freeze::start_write(loc)?;
+    let end = end.min(loc.len()?);
+    // Holes already read back as zeroes.
+    for range in node.extents()? {
+        let mut pos = range.start.max(offset);
+        let stop = range.end.min(end);
+        while pos < stop {
+            let chunk = (stop - pos).min(ZEROES.len() as u64) as usize;
+            pos += file.write_at(&mut &ZEROES[..chunk], pos)? as u64;
+        }
+    }
+    node.punch_hole(offset, end)?;
+    Ok(())
+}
+
+/// Returns the byte ranges of the file at `loc` that hold data, in order, if
+/// it is on an in-memory filesystem. The others do not tell where their holes
+/// are.
+pub fn extents(loc: &Location) -> LinuxResult<Option<Vec<Range<u64>>>> {
+    match loc.entry().downcast::<MemoryNode>() {
+        Ok(node) => Ok(Some(node.extents()?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Creates a device node for the device `rdev` in `dir`, which only the
+/// in-memory filesystems can store.
+pub fn create_device_node(
+    dir: &Location,
+    name: &str,
+    node_type: NodeType,
+    mode: NodePermission,
+    rdev: DeviceId,
+) -> LinuxResult<()> {
+    if dir.entry().downcast::<MemoryNode>().is_err() {
+        return Err(LinuxError::EPERM);
+    }
+    let loc = dir.create(name, node_type, mode)?;
+    let node = loc
+        .entry()
+        .downcast::<MemoryNode>()
+        .map_err(|_| LinuxError::EPERM)?;
+    node.set_rdev(rdev);
+    Ok(())
+}
+
 /// Mount all filesystems
 pub fn mount_all() -> LinuxResult<()> {
     let fs = FS_CONTEXT.lock();
-    mount_at(&fs, "/dev", dev::new_devfs())?;
-    mount_at(&fs, "/dev/shm", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/tmp", tmp::MemoryFs::new())?;
-    mount_at(&fs, "/proc", proc::new_procfs())?;
-
-    mount_at(&fs, "/sys", tmp::MemoryFs::new())?;
-    let mut path = PathBuf::new();
-    for comp in Path::new("/sys/class/graphics/fb0/device").components() {
-        path.push(comp.as_str());
-        if fs.resolve(&path).is_err() {
-            fs.create_dir(&path, DIR_PERMISSION)?;
-        }
+    let root = fs.root_dir().clone();
+    let root_type = root.filesystem().name().to_string();
+    MOUNT_TABLE
+        .write()
+        .add(Mount::new("rootfs", root_type, "rw", root)?);
+
+    mount_at(
+        &fs,
+        "/dev",
+        dev::new_devfs(),
+        "udev",
+        "devtmpfs",
+        "rw,nosuid",
+    )?;
+    mount_at(
+        &fs,
+        "/dev/shm",
+        tmp::MemoryFs::new(),
+        "tmpfs",
+        "tmpfs",
+        "rw,nosuid,nodev",
+    )?;
+    mount_at(
+        &fs,
+        "/dev/mqueue",
+        mqueue::new_mqueuefs(),
+        "mqueue",
+        "mqueue",
+        "rw,nosuid,nodev",
+    )?;
+    mount_at(&fs, "/tmp", tmp::MemoryFs::new(), "tmpfs", "tmpfs", "rw")?;
+    mount_at(
+        &fs,
+        "/proc",
+        proc::new_procfs(),
+        "proc",
+        "proc",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+
+    mount_at(
+        &fs,
+        "/sys",
+        tmp::MemoryFs::new_with(SYSFS_MAGIC, None),
+        "sysfs",
+        "sysfs",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+    mount_at(
+        &fs,
+        "/sys/class",
+        sysfs::new_classfs(),
+        "sysfs",
+        "sysfs",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+    mount_at(
+        &fs,
+        "/sys/block",
+        sysfs::new_blockfs(),
+        "sysfs",
+        "sysfs",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+    create_dir_all(&fs, "/sys/dev")?;
+    mount_at(
+        &fs,
+        "/sys/dev/block",
+        sysfs::new_devblockfs(),
+        "sysfs",
+        "sysfs",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+    mount_at(
+        &fs,
+        "/sys/power",
+        power::new_powerfs(),
+        "sysfs",
+        "sysfs",
+        "rw,nosuid,nodev,noexec,relatime",
+    )?;
+    #[cfg(feature = "cpu-topology")]
+    {
+        create_dir_all(&fs, "/sys/devices/system")?;
+        mount_at(
+            &fs,
+            "/sys/devices/system/cpu",
+            cpu::new_cpufs(),
+            "sysfs",
+            "sysfs",
+            "rw,nosuid,nodev,noexec,relatime",
+        )?;
+    }
+    #[cfg(feature = "cpufreq")]
+    if let Some(cpufreq) = cpufreq::new_cpufreqfs() {
+        #[cfg(not(feature = "cpu-topology"))]
+        create_dir_all(&fs, "/sys/devices/system/cpu/cpu0")?;
+        mount_at(
+            &fs,
+            "/sys/devices/system/cpu/cpu0/cpufreq",
+            cpufreq,
+            "sysfs",
+            "sysfs",
+            "rw,nosuid,nodev,noexec,relatime",
+        )?;
+    }
+    // libgpiod tells GPIO chips apart from other character devices by their
+    // subsystem.
+    #[cfg(feature = "gpio")]
+    for bank in 0..dev::gpio::BANKS.len() {
+        let dir = alloc::format!("/sys/dev/char/{}:{}", dev::gpio::GPIO_MAJOR, bank);
+        create_dir_all(&fs, &dir)?;
+        create_dir_all(&fs, "/sys/bus/gpio")?;
+        fs.symlink("/sys/bus/gpio", alloc::format!("{dir}/subsystem").as_str())?;
     }
-    path.push("subsystem");
-    fs.symlink("whatever", &path)?;
     drop(fs);
 
     #[cfg(feature = "dev-log")]
@@ -49,3 +299,52 @@ pub fn mount_all() -> LinuxResult<()> {
 
     Ok(())
 }
+
+/// Runs `op` on the filesystem mounted at `target` until it succeeds, up to
+/// [`TEARDOWN_ATTEMPTS`] times.
+fn retry(what: &str, target: &str, mut op: impl FnMut() -> LinuxResult<()>) -> LinuxResult<()> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < TEARDOWN_ATTEMPTS => {
+                warn!(
+                    "Failed to {} {} (attempt {}): {:?}",
+                    what, target, attempt, err
+                );
+                attempt += 1;
+            }
+            Err(err) => {
+                error!("Failed to {} {}: {:?}", what, target, err);
+                return Err(err);
+            }
+        }
+    }
+}
+
+/// Flushes and unmounts all filesystems before powering off.
+///
+/// Unlike [`mount_all`], this never fails: every step is retried a few times,
+/// then its error is logged and the teardown goes on, so that the system still
+/// powers off. Returns the mount points whose data may not have reached the
+/// disk.
+pub fn unmount_all() -> Vec<String> {
+    let mut failed = Vec::new();
+    // The root filesystem comes last, once nothing is mounted on it.
+    for mount in mount::mounts().iter().rev() {
+        if mount.target() == "/" {
+            continue;
+        }
+        let fs = mount.root().filesystem();
+        if retry("flush", mount.target(), || fs.flush()).is_err() {
+            failed.push(mount.target().to_string());
+        }
+    }
+
+    let root = FS_CONTEXT.lock().root_dir().clone();
+    let _ = retry("unmount everything under", "/", || root.unmount_all());
+    if retry("flush", "/", || root.filesystem().flush()).is_err() {
+        failed.push("/".to_string());
+    }
+    failed
+}
@@ -0,0 +1,225 @@
+//! The mount table.
+//!
+//! Mounts are attached to the directory tree every process shares, so there
+//! is a single [`MountTable`] listing them, for `/proc/mounts`, `umount` and
+//! the mount flags of `statfs`. There are no mount namespaces: `CLONE_NEWNS`
+//! is accepted, but the new namespace keeps sharing every mount.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
+use spin::RwLock;
+
+use super::stats::mount_stats;
+
+/// The `ST_*` flags of `statfs`, by mount option.
+const STATFS_FLAGS: &[(&str, u32)] = &[
+    ("ro", 0x0001),
+    ("nosuid", 0x0002),
+    ("nodev", 0x0004),
+    ("noexec", 0x0008),
+    ("sync", 0x0010),
+    ("mand", 0x0040),
+    ("noatime", 0x0400),
+    ("nodiratime", 0x0800),
+    ("relatime", 0x1000),
+];
+/// Set by Linux in every `statfs` result.
+const ST_VALID: u32 = 0x0020;
+
+/// The ID of the next mount.
+static NEXT_MOUNT_ID: AtomicU32 = AtomicU32::new(1);
+
+/// A mounted filesystem.
+pub struct Mount {
+    id: u32,
+    source: String,
+    target: String,
+    fs_type: String,
+    options: &'static str,
+    root: Location,
+}
+
+impl Mount {
+    /// Creates a new mount record for the filesystem mounted at `root`.
+    pub fn new(
+        source: impl Into<String>,
+        fs_type: impl Into<String>,
+        options: &'static str,
+        root: Location,
+    ) -> LinuxResult<Arc<Self>> {
+        Ok(Arc::new(Self {
+            id: NEXT_MOUNT_ID.fetch_add(1, Ordering::Relaxed),
+            source: source.into(),
+            target: root.absolute_path()?.to_string(),
+            fs_type: fs_type.into(),
+            options,
+            root,
+        }))
+    }
+
+    /// Returns the unique ID of the mount, the `mnt_id` of Linux.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns where the filesystem is mounted.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Returns the root of the mounted filesystem.
+    pub fn root(&self) -> &Location {
+        &self.root
+    }
+
+    /// Returns the flags of the mount as `statfs` reports them.
+    pub fn statfs_flags(&self) -> u32 {
+        self.options
+            .split(',')
+            .filter_map(|option| STATFS_FLAGS.iter().find(|(name, _)| *name == option))
+            .fold(ST_VALID, |flags, (_, flag)| flags | flag)
+    }
+}
+
+/// The mounted filesystems.
+pub static MOUNT_TABLE: RwLock<MountTable> = RwLock::new(MountTable::new());
+
+/// Returns the mounts, the latest last.
+pub(crate) fn mounts() -> Vec<Arc<Mount>> {
+    MOUNT_TABLE.read().mounts.clone()
+}
+
+/// The mounted filesystems, in the order they were mounted.
+pub struct MountTable {
+    mounts: Vec<Arc<Mount>>,
+}
+
+impl MountTable {
+    const fn new() -> Self {
+        Self { mounts: Vec::new() }
+    }
+
+    /// Adds a mount to the table.
+    pub fn add(&mut self, mount: Arc<Mount>) {
+        self.mounts.push(mount);
+    }
+
+    /// Unmounts the topmost mount at `target`.
+    pub fn remove(&mut self, target: &str) -> LinuxResult<()> {
+        let index = self
+            .mounts
+            .iter()
+            .rposition(|it| it.target == target)
+            .ok_or(LinuxError::EINVAL)?;
+        self.mounts[index].root.unmount()?;
+        self.mounts.remove(index);
+        Ok(())
+    }
+
+    /// Returns the topmost mount of the filesystem `loc` is on.
+    pub fn find(&self, loc: &Location) -> Option<&Arc<Mount>> {
+        let device = loc.mountpoint().device();
+        self.mounts
+            .iter()
+            .rev()
+            .find(|it| it.root.mountpoint().device() == device)
+    }
+
+    /// Formats the table in the format of `/proc/mounts`.
+    pub fn render(&self) -> String {
+        let mut result = String::new();
+        for mount in &self.mounts {
+            let _ = writeln!(
+                result,
+                "{} {} {} {} 0 0",
+                escape(&mount.source),
+                escape(&mount.target),
+                mount.fs_type,
+                mount.options
+            );
+        }
+        result
+    }
+
+    /// Formats the table in the format of `/proc/[pid]/mountinfo`.
+    ///
+    /// Every mount shows the root of its filesystem, and has no propagation
+    /// fields as there is no mount propagation.
+    pub fn render_info(&self) -> String {
+        let mut result = String::new();
+        for (index, mount) in self.mounts.iter().enumerate() {
+            let _ = writeln!(
+                result,
+                "{} {} 0:{} / {} {} - {} {} {}",
+                mount.id,
+                self.parent_id(index),
+                mount.root.mountpoint().device(),
+                escape(&mount.target),
+                mount.options,
+                mount.fs_type,
+                escape(&mount.source),
+                mount.options
+            );
+        }
+        result
+    }
+
+    /// Returns the ID of the mount the one at `index` is mounted on, the
+    /// latest of those mounted before it on the longest prefix of its target,
+    /// which may be the target itself. The root mount is its own parent.
+    fn parent_id(&self, index: usize) -> u32 {
+        let target = &self.mounts[index].target;
+        self.mounts[..index]
+            .iter()
+            .filter(|it| {
+                it.target == "/"
+                    || target
+                        .strip_prefix(it.target.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|it| it.target.len())
+            .map_or(self.mounts[index].id, |it| it.id)
+    }
+
+    /// Formats the VFS statistics of the mounts for `/proc/fs/stats`.
+    pub fn render_stats(&self) -> String {
+        let mut result = String::new();
+        for mount in &self.mounts {
+            let Some(stats) = mount_stats(mount.root.mountpoint().device() as u64) else {
+                continue;
+            };
+            let _ = writeln!(
+                result,
+                "{} {} {}",
+                mount.source, mount.target, mount.fs_type
+            );
+            stats.render(&mut result);
+        }
+        result
+    }
+}
+
+/// Escapes the characters separating the fields of `/proc/mounts` as octal,
+/// like Linux.
+fn escape(field: &str) -> String {
+    let mut result = String::new();
+    for ch in field.chars() {
+        match ch {
+            ' ' | '\t' | '\n' | '\\' => {
+                let _ = write!(result, "\\{:03o}", ch as u32);
+            }
+            _ => result.push(ch),
+        }
+    }
+    result
+}
@@ -0,0 +1,43 @@
+//! The mqueue filesystem, showing the POSIX message queues as files.
+//!
+//! Reading a queue's file gives its status line as on Linux, and unlinking it
+//! is the same as `mq_unlink`. Queues cannot be created through the
+//! filesystem.
+
+use alloc::{borrow::Cow, boxed::Box, sync::Arc};
+
+use axfs_ng_vfs::{Filesystem, VfsError, VfsResult};
+use starry_core::vfs::{DirMaker, NodeOpsMux, SimpleDir, SimpleDirOps, SimpleFile, SimpleFs};
+
+use crate::file::mqueue;
+
+const MQUEUE_MAGIC: u32 = 0x1980_0202;
+
+pub fn new_mqueuefs() -> Filesystem {
+    SimpleFs::new_with("mqueue".into(), MQUEUE_MAGIC, builder)
+}
+
+struct MqueueDir(Arc<SimpleFs>);
+
+impl SimpleDirOps for MqueueDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(mqueue::names().into_iter().map(Cow::Owned))
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let queue = mqueue::lookup(name).ok_or(VfsError::ENOENT)?;
+        Ok(SimpleFile::new_regular(self.0.clone(), move || Ok(queue.status())).into())
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+
+    fn unlink_child(&self, name: &str) -> VfsResult<()> {
+        mqueue::unlink(name)
+    }
+}
+
+fn builder(fs: Arc<SimpleFs>) -> DirMaker {
+    SimpleDir::new_maker(fs.clone(), Arc::new(MqueueDir(fs)))
+}
@@ -0,0 +1,272 @@
+//! System sleep, exposed as `/sys/power`, and the RTC wake alarm, exposed as
+//! `/sys/class/rtc/rtc0/wakealarm`.
+//!
+//! Only suspend-to-idle is provided, and nothing is frozen: the task entering
+//! it waits for the next wakeup event, which console input and the RTC alarm
+//! report. The `wakeup_count` handshake keeps a suspend from missing events
+//! that happened after user space decided to suspend.
+
+use alloc::{format, string::String, sync::Arc};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use axfs_ng_vfs::{Filesystem, VfsError, VfsResult};
+use axio::PollSet;
+use axtask::future::{block_on, timeout_opt};
+use kspin::SpinNoIrq;
+use lazy_static::lazy_static;
+use linux_raw_sys::general::SYSFS_MAGIC;
+use starry_core::vfs::{
+    DirMaker, DirMapping, RwFile, SimpleDir, SimpleFile, SimpleFileOperation, SimpleFs,
+};
+
+use super::proc::parse_sysctl as parse;
+
+/// How long the system must go without wakeup events before autosleep
+/// suspends it.
+const AUTOSLEEP_IDLE: Duration = Duration::from_secs(2);
+
+/// The number of wakeup events so far.
+static WAKEUP_COUNT: AtomicU64 = AtomicU64::new(0);
+/// The count written to `wakeup_count`, checked by the next suspend.
+static SAVED_COUNT: SpinNoIrq<Option<u64>> = SpinNoIrq::new(None);
+
+static AUTOSLEEP: AtomicBool = AtomicBool::new(false);
+/// Whether the autosleep task is running.
+static AUTOSLEEP_TASK: AtomicBool = AtomicBool::new(false);
+
+/// The wall time of the RTC alarm in seconds, if it is set.
+static WAKE_ALARM: SpinNoIrq<Option<u64>> = SpinNoIrq::new(None);
+/// Whether the task firing the RTC alarm is running.
+static ALARM_TASK: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Woken on every wakeup event.
+    static ref WAKEUP: PollSet = PollSet::new();
+    /// Woken when the RTC alarm is set or cleared.
+    static ref ALARM_CHANGED: PollSet = PollSet::new();
+}
+
+/// Records a wakeup event, which ends suspend-to-idle.
+pub fn report_wakeup() {
+    WAKEUP_COUNT.fetch_add(1, Ordering::SeqCst);
+    WAKEUP.wake();
+}
+
+/// Suspends to idle until the next wakeup event.
+///
+/// Fails with `EBUSY` if events happened since the count was written to
+/// `wakeup_count`.
+fn suspend() -> VfsResult<()> {
+    let count = WAKEUP_COUNT.load(Ordering::SeqCst);
+    if SAVED_COUNT
+        .lock()
+        .take()
+        .is_some_and(|saved| saved != count)
+    {
+        return Err(VfsError::EBUSY);
+    }
+
+    info!("Entering s2idle");
+    block_on(poll_fn(|cx| {
+        WAKEUP.register(cx.waker());
+        if WAKEUP_COUNT.load(Ordering::SeqCst) != count {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }));
+    info!("Resumed from s2idle");
+    Ok(())
+}
+
+fn autosleep_task() {
+    while AUTOSLEEP.load(Ordering::Acquire) {
+        let count = WAKEUP_COUNT.load(Ordering::SeqCst);
+        axtask::sleep(AUTOSLEEP_IDLE);
+        if AUTOSLEEP.load(Ordering::Acquire) && WAKEUP_COUNT.load(Ordering::SeqCst) == count {
+            let _ = suspend();
+        }
+    }
+    AUTOSLEEP_TASK.store(false, Ordering::Release);
+}
+
+fn set_autosleep(enabled: bool) {
+    AUTOSLEEP.store(enabled, Ordering::Release);
+    if enabled && !AUTOSLEEP_TASK.swap(true, Ordering::AcqRel) {
+        axtask::spawn(autosleep_task, "autosleep".into());
+    }
+}
+
+fn wall_secs() -> u64 {
+    axhal::time::wall_time_nanos() / 1_000_000_000
+}
+
+/// Waits for the RTC alarm to go off, starting over whenever it changes.
+fn alarm_task() {
+    loop {
+        let alarm = *WAKE_ALARM.lock();
+        let delay = alarm.map(|at| Duration::from_secs(at.saturating_sub(wall_secs())));
+        let changed = block_on(timeout_opt(
+            poll_fn(|cx| {
+                ALARM_CHANGED.register(cx.waker());
+                if *WAKE_ALARM.lock() != alarm {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }),
+            delay,
+        ));
+        if changed.is_some() {
+            continue;
+        }
+        let mut current = WAKE_ALARM.lock();
+        // Unless it was changed in the meantime.
+        if *current == alarm {
+            *current = None;
+            drop(current);
+            report_wakeup();
+        }
+    }
+}
+
+/// Sets the RTC alarm to go off at `at`, in seconds since the epoch, or
+/// clears it.
+fn set_wake_alarm(at: Option<u64>) {
+    *WAKE_ALARM.lock() = at;
+    ALARM_CHANGED.wake();
+    if at.is_some() && !ALARM_TASK.swap(true, Ordering::AcqRel) {
+        axtask::spawn(alarm_task, "rtc-alarm".into());
+    }
+}
+
+fn power_builder(fs: Arc<SimpleFs>) -> DirMaker {
+    let mut root = DirMapping::new();
+    root.add(
+        "state",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some("freeze\n")),
+                SimpleFileOperation::Write(data) => match data.trim_ascii() {
+                    b"" => Ok(None),
+                    b"freeze" => suspend().map(|_| None),
+                    _ => Err(VfsError::EINVAL),
+                },
+            }),
+        ),
+    );
+    root.add(
+        "mem_sleep",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some("[s2idle]\n")),
+                SimpleFileOperation::Write(data) => match data.trim_ascii() {
+                    b"" | b"s2idle" => Ok(None),
+                    _ => Err(VfsError::EINVAL),
+                },
+            }),
+        ),
+    );
+    root.add(
+        "wakeup_count",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => {
+                    Ok(Some(format!("{}\n", WAKEUP_COUNT.load(Ordering::SeqCst))))
+                }
+                SimpleFileOperation::Write(data) => {
+                    if data.is_empty() {
+                        return Ok(None);
+                    }
+                    let mut saved = SAVED_COUNT.lock();
+                    let count = parse(data)?;
+                    if count != WAKEUP_COUNT.load(Ordering::SeqCst) {
+                        // Events happened since it was read.
+                        *saved = None;
+                        return Err(VfsError::EINVAL);
+                    }
+                    *saved = Some(count);
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    root.add(
+        "autosleep",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(if AUTOSLEEP.load(Ordering::Acquire) {
+                    "freeze\n"
+                } else {
+                    "off\n"
+                })),
+                SimpleFileOperation::Write(data) => {
+                    match data.trim_ascii() {
+                        b"" => {}
+                        b"off" => set_autosleep(false),
+                        b"freeze" => set_autosleep(true),
+                        _ => return Err(VfsError::EINVAL),
+                    }
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+
+    SimpleDir::new_maker(fs, Arc::new(root))
+}
+
+/// The attributes of `/sys/class/rtc/rtc0`.
+pub fn rtc_attrs(fs: Arc<SimpleFs>) -> DirMapping {
+    let mut root = DirMapping::new();
+    root.add(
+        "since_epoch",
+        SimpleFile::new_regular(fs.clone(), || Ok(format!("{}\n", wall_secs()))),
+    );
+    root.add(
+        "wakealarm",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(match *WAKE_ALARM.lock() {
+                    Some(at) => format!("{at}\n"),
+                    None => String::new(),
+                })),
+                SimpleFileOperation::Write(data) => {
+                    let data = data.trim_ascii();
+                    if data.is_empty() {
+                        return Ok(None);
+                    }
+                    let at = if let Some(delay) = data.strip_prefix(b"+") {
+                        // A relative alarm does not replace a pending one.
+                        if WAKE_ALARM.lock().is_some() {
+                            return Err(VfsError::EBUSY);
+                        }
+                        wall_secs()
+                            .checked_add(parse(delay)?)
+                            .ok_or(VfsError::EINVAL)?
+                    } else {
+                        parse(data)?
+                    };
+                    set_wake_alarm((at != 0).then_some(at));
+                    Ok(None)
+                }
+            }),
+        ),
+    );
+    root
+}
+
+/// Creates the `/sys/power` directory.
+pub fn new_powerfs() -> Filesystem {
+    SimpleFs::new_with(String::from("sysfs"), SYSFS_MAGIC, power_builder)
+}
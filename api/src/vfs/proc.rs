@@ -7,21 +7,51 @@ use alloc::{
     vec,
     vec::Vec,
 };
-use core::{ffi::CStr, iter};
+use core::{
+    ffi::CStr,
+    fmt::Write,
+    iter,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
-use axfs_ng_vfs::{Filesystem, NodeType, VfsError, VfsResult};
-use axtask::{AxTaskRef, WeakAxTaskRef, current};
+use axfs_ng_vfs::{DeviceId, Filesystem, Location, NodeType, VfsError, VfsResult};
+use axhal::{
+    paging::MappingFlags,
+    time::{monotonic_time, wall_time},
+};
+use axio::{Seek, SeekFrom};
+use axmm::backend::Backend;
+use axtask::{AxTaskRef, TaskState, WeakAxTaskRef, current};
 use indoc::indoc;
+use linux_raw_sys::general::{O_CLOEXEC, PROC_SUPER_MAGIC};
+use memory_addr::{PAGE_SIZE_4K, VirtAddr};
 use starry_core::{
-    task::{AsThread, TaskStat, get_task, tasks},
+    mm::{
+        render_vmstat, total_forks, total_pages,
+        vdso::{VDSO_BASE, VDSO_DATA},
+    },
+    shm::shared_memory_usage,
+    sysctl,
+    task::{AsThread, TaskStat, current_pid_ns, get_process_data, get_task, processes, tasks},
     vfs::{
-        DirMaker, DirMapping, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps, SimpleFile,
-        SimpleFileOperation, SimpleFs,
+        Device, DirMaker, DirMapping, MagicLink, NodeOpsMux, RwFile, SimpleDir, SimpleDirOps,
+        SimpleFile, SimpleFileOperation, SimpleFs,
     },
 };
-use starry_process::Process;
+use starry_process::{Pid, Process};
 
-use crate::file::FD_TABLE;
+use crate::{
+    file::{Directory, FD_TABLE, File, FileDescriptor, FileLike, status_flags},
+    time::{context_switches, cpu_times, irq_cnt},
+    vfs::{
+        cpuinfo,
+        dev::{tty, uevent::Uevents},
+        mount::MOUNT_TABLE,
+        stats::{set_slow_threshold_ms, slow_threshold_ms},
+    },
+};
 
 const DUMMY_MEMINFO: &str = indoc! {"
     MemTotal:       32536204 kB
@@ -84,7 +114,7 @@ const DUMMY_MEMINFO: &str = indoc! {"
 "};
 
 pub fn new_procfs() -> Filesystem {
-    SimpleFs::new_with("proc".into(), 0x9fa0, builder)
+    SimpleFs::new_with("proc".into(), PROC_SUPER_MAGIC, builder)
 }
 
 struct ProcessTaskDir {
@@ -97,10 +127,12 @@ impl SimpleDirOps for ProcessTaskDir {
         let Some(process) = self.process.upgrade() else {
             return Box::new(iter::empty());
         };
+        let pid_ns = current_pid_ns();
         Box::new(
             process
                 .threads()
                 .into_iter()
+                .filter_map(move |tid| pid_ns.local_pid(tid))
                 .map(|tid| tid.to_string().into()),
         )
     }
@@ -108,7 +140,10 @@ impl SimpleDirOps for ProcessTaskDir {
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let process = self.process.upgrade().ok_or(VfsError::ENOENT)?;
         let tid = name.parse::<u32>().map_err(|_| VfsError::ENOENT)?;
-        let task = get_task(tid).map_err(|_| VfsError::ENOENT)?;
+        let task = current_pid_ns()
+            .global_pid(tid)
+            .and_then(get_task)
+            .map_err(|_| VfsError::ENOENT)?;
         if task.as_thread().proc_data.proc.pid() != process.pid() {
             return Err(VfsError::ENOENT);
         }
@@ -129,6 +164,7 @@ impl SimpleDirOps for ProcessTaskDir {
 
 #[rustfmt::skip]
 fn task_status(task: &AxTaskRef) -> String {
+    let pid_ns = current_pid_ns();
     format!(
         "Tgid:\t{}\n\
         Pid:\t{}\n\
@@ -138,11 +174,51 @@ fn task_status(task: &AxTaskRef) -> String {
         Cpus_allowed_list:\t0\n\
         Mems_allowed:\t1\n\
         Mems_allowed_list:\t0",
-        task.as_thread().proc_data.proc.pid(),
-        task.id().as_u64()
+        pid_ns.local_pid(task.as_thread().proc_data.proc.pid()).unwrap_or_default(),
+        pid_ns.local_pid(task.id().as_u64() as Pid).unwrap_or_default()
     )
 }
 
+/// The file descriptors open in the process of `task`, as entry names.
+fn fd_names<'a>(task: &WeakAxTaskRef) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+    let Some(task) = task.upgrade() else {
+        return Box::new(iter::empty());
+    };
+    let ids = FD_TABLE
+        .scope(&task.as_thread().proc_data.scope.read())
+        .read()
+        .ids()
+        .map(|id| Cow::Owned(id.to_string()))
+        .collect::<Vec<_>>();
+    Box::new(ids.into_iter())
+}
+
+/// The file descriptor named `name` in the process of `task`.
+fn fd_lookup(task: &AxTaskRef, name: &str) -> VfsResult<FileDescriptor> {
+    fd_get(task, name.parse().map_err(|_| VfsError::ENOENT)?)
+}
+
+/// The file descriptor `fd` in the process of `task`.
+fn fd_get(task: &AxTaskRef, fd: u32) -> VfsResult<FileDescriptor> {
+    FD_TABLE
+        .scope(&task.as_thread().proc_data.scope.read())
+        .read()
+        .get(fd as _)
+        .cloned()
+        .ok_or(VfsError::ENOENT)
+}
+
+/// The location a file is open at, if it is in the file system.
+fn fd_location(file_like: &Arc<dyn FileLike>) -> Option<Location> {
+    let any = file_like.clone().into_any();
+    if let Some(file) = any.downcast_ref::<File>() {
+        Some(file.inner().location().clone())
+    } else {
+        any.downcast_ref::<Directory>()
+            .map(|dir| dir.inner().clone())
+    }
+}
+
 /// The /proc/[pid]/fd directory
 struct ThreadFdDir {
     fs: Arc<SimpleFs>,
@@ -151,31 +227,25 @@ struct ThreadFdDir {
 
 impl SimpleDirOps for ThreadFdDir {
     fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
-        let Some(task) = self.task.upgrade() else {
-            return Box::new(iter::empty());
-        };
-        let ids = FD_TABLE
-            .scope(&task.as_thread().proc_data.scope.read())
-            .read()
-            .ids()
-            .map(|id| Cow::Owned(id.to_string()))
-            .collect::<Vec<_>>();
-        Box::new(ids.into_iter())
+        fd_names(&self.task)
     }
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
         let fs = self.fs.clone();
         let task = self.task.upgrade().ok_or(VfsError::ENOENT)?;
-        let fd = name.parse::<u32>().map_err(|_| VfsError::ENOENT)?;
-        let path = FD_TABLE
-            .scope(&task.as_thread().proc_data.scope.read())
-            .read()
-            .get(fd as _)
-            .ok_or(VfsError::ENOENT)?
-            .inner
-            .path()
-            .into_owned();
-        Ok(SimpleFile::new(fs, NodeType::Symlink, move || Ok(path.clone())).into())
+        let file_like = fd_lookup(&task, name)?.inner;
+        Ok(match fd_location(&file_like) {
+            Some(loc) => SimpleFile::new(
+                fs,
+                NodeType::Symlink,
+                MagicLink::new(move || Ok(loc.clone())),
+            ),
+            None => {
+                let path = file_like.path().into_owned();
+                SimpleFile::new(fs, NodeType::Symlink, move || Ok(path.clone()))
+            }
+        }
+        .into())
     }
 
     fn is_cacheable(&self) -> bool {
@@ -183,6 +253,62 @@ impl SimpleDirOps for ThreadFdDir {
     }
 }
 
+/// The /proc/[pid]/fdinfo directory
+struct ThreadFdInfoDir {
+    fs: Arc<SimpleFs>,
+    task: WeakAxTaskRef,
+}
+
+impl SimpleDirOps for ThreadFdInfoDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        fd_names(&self.task)
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let fd = name.parse().map_err(|_| VfsError::ENOENT)?;
+        fd_get(&self.task.upgrade().ok_or(VfsError::ENOENT)?, fd)?;
+        // The descriptor is looked up again on every read, so that the file
+        // shows what the descriptor refers to then and does not keep the
+        // open file or the task alive.
+        let task = self.task.clone();
+        Ok(SimpleFile::new_regular(self.fs.clone(), move || {
+            let task = task.upgrade().ok_or(VfsError::ENOENT)?;
+            let desc = fd_get(&task, fd)?;
+            let f = &desc.inner;
+            let pos = match f.clone().into_any().downcast_ref::<File>() {
+                Some(file) => file.inner().seek(SeekFrom::Current(0)).unwrap_or(0),
+                None => 0,
+            };
+            let mut flags = status_flags(f)?;
+            if desc.cloexec {
+                flags |= O_CLOEXEC;
+            }
+            let mnt_id = fd_location(f)
+                .and_then(|loc| MOUNT_TABLE.read().find(&loc).map(|mount| mount.id()))
+                .unwrap_or(0);
+            let ino = f.stat()?.ino;
+            Ok(format!(
+                "pos:\t{pos}\nflags:\t0{flags:o}\nmnt_id:\t{mnt_id}\nino:\t{ino}\n"
+            ))
+        })
+        .into())
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Joins `strings` with NUL terminators, as in `/proc/[pid]/cmdline`.
+fn nul_separated(strings: &[String]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for string in strings {
+        buf.extend_from_slice(string.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
 /// The /proc/[pid] directory
 struct ThreadDir {
     fs: Arc<SimpleFs>,
@@ -195,14 +321,21 @@ impl SimpleDirOps for ThreadDir {
             [
                 "stat",
                 "status",
+                "wchan",
                 "oom_score_adj",
                 "task",
                 "maps",
                 "mounts",
+                "mountinfo",
                 "cmdline",
+                "environ",
                 "comm",
                 "exe",
+                "cwd",
+                "root",
                 "fd",
+                "fdinfo",
+                "io",
             ]
             .into_iter()
             .map(Cow::Borrowed),
@@ -214,10 +347,23 @@ impl SimpleDirOps for ThreadDir {
         let task = self.task.upgrade().ok_or(VfsError::ENOENT)?;
         Ok(match name {
             "stat" => SimpleFile::new_regular(fs, move || {
-                Ok(format!("{}", TaskStat::from_thread(&task)?).into_bytes())
+                let mut stat = TaskStat::from_thread(&task)?;
+                let session = task.as_thread().proc_data.proc.group().session();
+                (stat.tty_nr, stat.tpgid) = tty::stat_terminal(&session);
+                Ok(format!("{stat}").into_bytes())
             })
             .into(),
             "status" => SimpleFile::new_regular(fs, move || Ok(task_status(&task))).into(),
+            // What a blocked thread waits for, or "0" like on Linux otherwise.
+            "wchan" => SimpleFile::new_regular(fs, move || {
+                let chan = task.as_thread().wait_channel();
+                Ok(match chan {
+                    Some(chan) if matches!(task.state(), TaskState::Blocked) => chan.to_string(),
+                    _ => "0".to_string(),
+                }
+                .into_bytes())
+            })
+            .into(),
             "oom_score_adj" => SimpleFile::new_regular(
                 fs,
                 RwFile::new(move |req| match req {
@@ -245,27 +391,17 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
-            "maps" => SimpleFile::new_regular(fs, move || {
-                Ok(indoc! {"
-                    7f000000-7f001000 r--p 00000000 00:00 0          [vdso]
-                    7f001000-7f003000 r-xp 00001000 00:00 0          [vdso]
-                    7f003000-7f005000 r--p 00003000 00:00 0          [vdso]
-                    7f005000-7f007000 rw-p 00005000 00:00 0          [vdso]
-                "})
+            "maps" => SimpleFile::new_regular(fs, move || Ok(task_maps(&task))).into(),
+            "mounts" => SimpleFile::new_regular(fs, || Ok(MOUNT_TABLE.read().render())).into(),
+            "mountinfo" => {
+                SimpleFile::new_regular(fs, || Ok(MOUNT_TABLE.read().render_info())).into()
+            }
+            "cmdline" => SimpleFile::new_regular(fs, move || {
+                Ok(nul_separated(&task.as_thread().proc_data.cmdline.read()))
             })
             .into(),
-            "mounts" => SimpleFile::new_regular(fs, move || {
-                Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-            })
-            .into(),
-            "cmdline" => SimpleFile::new_regular(fs, move || {
-                let cmdline = task.as_thread().proc_data.cmdline.read();
-                let mut buf = Vec::new();
-                for arg in cmdline.iter() {
-                    buf.extend_from_slice(arg.as_bytes());
-                    buf.push(0);
-                }
-                Ok(buf)
+            "environ" => SimpleFile::new_regular(fs, move || {
+                Ok(nul_separated(&task.as_thread().proc_data.environ.read()))
             })
             .into(),
             "comm" => SimpleFile::new_regular(
@@ -296,9 +432,32 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
-            "exe" => SimpleFile::new(fs, NodeType::Symlink, move || {
-                Ok(task.as_thread().proc_data.exe_path.read().clone())
-            })
+            "exe" => SimpleFile::new(
+                fs,
+                NodeType::Symlink,
+                MagicLink::new(move || {
+                    let exe = task.as_thread().proc_data.exe.read().clone();
+                    exe.ok_or(VfsError::ENOENT)
+                }),
+            )
+            .into(),
+            "cwd" => SimpleFile::new(
+                fs,
+                NodeType::Symlink,
+                MagicLink::new(move || {
+                    let context = task.as_thread().fs().context();
+                    Ok(context.lock().current_dir().clone())
+                }),
+            )
+            .into(),
+            "root" => SimpleFile::new(
+                fs,
+                NodeType::Symlink,
+                MagicLink::new(move || {
+                    let context = task.as_thread().fs().context();
+                    Ok(context.lock().root_dir().clone())
+                }),
+            )
             .into(),
             "fd" => SimpleDir::new_maker(
                 fs.clone(),
@@ -308,6 +467,16 @@ impl SimpleDirOps for ThreadDir {
                 }),
             )
             .into(),
+            "fdinfo" => SimpleDir::new_maker(
+                fs.clone(),
+                Arc::new(ThreadFdInfoDir {
+                    fs,
+                    task: Arc::downgrade(&task),
+                }),
+            )
+            .into(),
+            "io" => SimpleFile::new_regular(fs, move || Ok(task.as_thread().proc_data.io.render()))
+                .into(),
             _ => return Err(VfsError::ENOENT),
         })
     }
@@ -317,25 +486,45 @@ impl SimpleDirOps for ThreadDir {
     }
 }
 
-/// Handles /proc/[pid] & /proc/self
+/// Finds the task shown as /proc/[pid]: the thread `tid`, or any thread of
+/// process `tid` if its leader has already exited.
+fn lookup_task(tid: Pid) -> VfsResult<AxTaskRef> {
+    get_task(tid).or_else(|_| {
+        let proc_data = get_process_data(tid)?;
+        let tid = *proc_data.proc.threads().first().ok_or(VfsError::ESRCH)?;
+        get_task(tid)
+    })
+}
+
+/// Handles /proc/[pid], /proc/self & /proc/thread-self
+///
+/// As on Linux, only processes are listed, but the threads other than the
+/// leaders can be looked up by their TIDs as well.
 struct ProcFsHandler(Arc<SimpleFs>);
 
 impl SimpleDirOps for ProcFsHandler {
     fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let pid_ns = current_pid_ns();
         Box::new(
-            tasks()
+            processes()
                 .into_iter()
-                .map(|task| task.id().as_u64().to_string().into())
-                .chain([Cow::Borrowed("self")]),
+                .filter_map(move |proc_data| pid_ns.local_pid(proc_data.proc.pid()))
+                .map(|pid| pid.to_string().into())
+                .chain([Cow::Borrowed("self"), Cow::Borrowed("thread-self")]),
         )
     }
 
     fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
-        let task = if name == "self" {
-            current().clone()
-        } else {
-            let tid = name.parse::<u32>().map_err(|_| VfsError::ENOENT)?;
-            get_task(tid).map_err(|_| VfsError::ENOENT)?
+        let task = match name {
+            "self" => lookup_task(current().as_thread().proc_data.proc.pid())?,
+            "thread-self" => current().clone(),
+            _ => {
+                let tid = name.parse::<u32>().map_err(|_| VfsError::ENOENT)?;
+                current_pid_ns()
+                    .global_pid(tid)
+                    .and_then(lookup_task)
+                    .map_err(|_| VfsError::ENOENT)?
+            }
         };
         let node = NodeOpsMux::Dir(SimpleDir::new_maker(
             self.0.clone(),
@@ -352,17 +541,203 @@ impl SimpleDirOps for ProcFsHandler {
     }
 }
 
+fn task_maps(task: &AxTaskRef) -> String {
+    let mut result = String::new();
+    let mut write_area = |range: (VirtAddr, VirtAddr), flags: MappingFlags, shared, name: &str| {
+        let perm = |flag, ch| if flags.contains(flag) { ch } else { '-' };
+        let _ = writeln!(
+            result,
+            "{:08x}-{:08x} {}{}{}{shared} 00000000 00:00 0          {name}",
+            range.0,
+            range.1,
+            perm(MappingFlags::READ, 'r'),
+            perm(MappingFlags::WRITE, 'w'),
+            perm(MappingFlags::EXECUTE, 'x'),
+        );
+    };
+
+    let proc_data = &task.as_thread().proc_data;
+    let aspace = proc_data.aspace.lock();
+    for (start, name) in [(VDSO_DATA, "[vvar]"), (VDSO_BASE, "[vdso]")] {
+        if let Some(area) = aspace.find_area(VirtAddr::from(start)) {
+            write_area((area.start(), area.end()), area.flags(), 'p', name);
+        }
+    }
+    // Forget the names of regions that have been unmapped since.
+    proc_data.mapping_names.lock().retain(|start, name| {
+        let Some(area) = aspace.find_area(VirtAddr::from(*start)) else {
+            return false;
+        };
+        if area.start().as_usize() != *start || !matches!(area.backend(), Backend::Shared(_)) {
+            return false;
+        }
+        write_area((area.start(), area.end()), area.flags(), 's', name.as_str());
+        true
+    });
+    result
+}
+
+fn meminfo() -> String {
+    // These must agree with `sysinfo`, from which `sysconf` takes the number
+    // of pages.
+    let total = total_pages() * PAGE_SIZE_4K / 1024;
+    let free = axalloc::global_allocator().available_pages() * PAGE_SIZE_4K / 1024;
+    let shmem = shared_memory_usage() / 1024;
+    DUMMY_MEMINFO
+        .lines()
+        .map(|line| {
+            let (key, value) = match line.split_once(':') {
+                Some(("MemTotal", _)) => ("MemTotal", total),
+                Some((key @ ("MemFree" | "MemAvailable"), _)) => (key, free),
+                Some(("Shmem", _)) => ("Shmem", shmem),
+                _ => return format!("{line}\n"),
+            };
+            format!("{:<16}{value:>8} kB\n", format!("{key}:"))
+        })
+        .collect()
+}
+
+/// Formats `/proc/stat` from the times accounted on timer ticks, in jiffies
+/// of `USER_HZ`.
+fn stat() -> String {
+    const NANOS_PER_JIFFY: u64 = 10_000_000;
+    let jiffies = |counter: &AtomicU64| counter.load(Ordering::Relaxed) / NANOS_PER_JIFFY;
+
+    let cpus = (0..axconfig::plat::CPU_NUM)
+        .map(|cpu| {
+            let times = cpu_times(cpu);
+            [&times.user, &times.system, &times.idle].map(jiffies)
+        })
+        .collect::<Vec<_>>();
+    let total = cpus.iter().fold([0; 3], |acc, it| {
+        [acc[0] + it[0], acc[1] + it[1], acc[2] + it[2]]
+    });
+
+    let mut result = String::new();
+    let mut write_cpu = |name: &str, [user, system, idle]: [u64; 3]| {
+        let _ = writeln!(result, "{name} {user} 0 {system} {idle} 0 0 0 0 0 0");
+    };
+    write_cpu("cpu ", total);
+    for (cpu, times) in cpus.into_iter().enumerate() {
+        write_cpu(&format!("cpu{cpu}"), times);
+    }
+
+    let tasks = tasks();
+    let count = |state| tasks.iter().filter(|it| it.state() == state).count();
+    let running = count(TaskState::Running) + count(TaskState::Ready);
+    let boot_time = (wall_time() - monotonic_time()).as_secs();
+    let _ = write!(
+        result,
+        "intr {}\nctxt {}\nbtime {boot_time}\nprocesses {}\nprocs_running \
+         {running}\nprocs_blocked {}\n",
+        irq_cnt(),
+        context_switches(),
+        total_forks(),
+        count(TaskState::Blocked),
+    );
+    result
+}
+
+/// Formats `/proc/uptime`: the time since boot and the idle time of all CPUs.
+fn uptime() -> String {
+    let idle = (0..axconfig::plat::CPU_NUM)
+        .map(|cpu| cpu_times(cpu).idle.load(Ordering::Relaxed))
+        .sum::<u64>();
+    let idle = Duration::from_nanos(idle);
+    let up = monotonic_time();
+    format!(
+        "{}.{:02} {}.{:02}\n",
+        up.as_secs(),
+        up.subsec_millis() / 10,
+        idle.as_secs(),
+        idle.subsec_millis() / 10
+    )
+}
+
+/// A directory of `/proc/sys`, holding the sysctls under `prefix`.
+struct SysctlDir {
+    fs: Arc<SimpleFs>,
+    prefix: String,
+}
+
+impl SimpleDirOps for SysctlDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        Box::new(sysctl::children(&self.prefix).into_iter().map(Cow::Owned))
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        if name.contains('.') {
+            return Err(VfsError::ENOENT);
+        }
+        let name = if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{name}", self.prefix)
+        };
+        if sysctl::exists(&name) {
+            let file = SimpleFile::new_regular(
+                self.fs.clone(),
+                RwFile::new(move |req| match req {
+                    SimpleFileOperation::Read => {
+                        Ok(Some(format!("{}\n", sysctl::read(&name)?).into_bytes()))
+                    }
+                    SimpleFileOperation::Write(data) => {
+                        if !data.is_empty() {
+                            let value = str::from_utf8(data).map_err(|_| VfsError::EINVAL)?;
+                            sysctl::write(&name, value)?;
+                        }
+                        Ok(None)
+                    }
+                }),
+            );
+            return Ok(file.into());
+        }
+        if sysctl::children(&name).is_empty() {
+            return Err(VfsError::ENOENT);
+        }
+        Ok(NodeOpsMux::Dir(SimpleDir::new_maker(
+            self.fs.clone(),
+            Arc::new(SysctlDir {
+                fs: self.fs.clone(),
+                prefix: name,
+            }),
+        )))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+pub(crate) fn parse_sysctl<T: FromStr>(data: &[u8]) -> VfsResult<T> {
+    str::from_utf8(data)
+        .ok()
+        .and_then(|it| it.trim().parse().ok())
+        .ok_or(VfsError::EINVAL)
+}
+
 fn builder(fs: Arc<SimpleFs>) -> DirMaker {
     let mut root = DirMapping::new();
     root.add(
         "mounts",
-        SimpleFile::new_regular(fs.clone(), || {
-            Ok("proc /proc proc rw,nosuid,nodev,noexec,relatime 0 0\n")
-        }),
+        SimpleFile::new_regular(fs.clone(), || Ok(MOUNT_TABLE.read().render())),
     );
     root.add(
         "meminfo",
-        SimpleFile::new_regular(fs.clone(), || Ok(DUMMY_MEMINFO)),
+        SimpleFile::new_regular(fs.clone(), || Ok(meminfo())),
+    );
+    root.add(
+        "vmstat",
+        SimpleFile::new_regular(fs.clone(), || Ok(render_vmstat())),
+    );
+    root.add(
+        "cpuinfo",
+        SimpleFile::new_regular(fs.clone(), || Ok(cpuinfo::render())),
+    );
+    root.add("stat", SimpleFile::new_regular(fs.clone(), || Ok(stat())));
+    root.add(
+        "uptime",
+        SimpleFile::new_regular(fs.clone(), || Ok(uptime())),
     );
     root.add(
         "meminfo2",
@@ -384,28 +759,73 @@ fn builder(fs: Arc<SimpleFs>) -> DirMaker {
             }
         }),
     );
+    root.add(
+        "uevents",
+        Device::new(
+            fs.clone(),
+            NodeType::RegularFile,
+            DeviceId::default(),
+            Arc::new(Uevents),
+        ),
+    );
     root.add(
         "interrupts",
-        SimpleFile::new_regular(fs.clone(), || Ok(format!("0: {}", crate::time::irq_cnt()))),
+        SimpleFile::new_regular(fs.clone(), || Ok(format!("0: {}", irq_cnt()))),
+    );
+    #[cfg(feature = "profile")]
+    root.add(
+        "profile",
+        SimpleFile::new_regular(
+            fs.clone(),
+            RwFile::new(|req| match req {
+                SimpleFileOperation::Read => Ok(Some(crate::profile::render().into_bytes())),
+                SimpleFileOperation::Write(_) => {
+                    crate::profile::reset();
+                    Ok(None)
+                }
+            }),
+        ),
     );
 
-    root.add("sys", {
-        let mut sys = DirMapping::new();
-
-        sys.add("kernel", {
-            let mut kernel = DirMapping::new();
-
-            kernel.add(
-                "pid_max",
-                SimpleFile::new_regular(fs.clone(), || Ok("32768\n")),
-            );
+    root.add("fs", {
+        let mut fs_dir = DirMapping::new();
 
-            SimpleDir::new_maker(fs.clone(), Arc::new(kernel))
-        });
+        fs_dir.add(
+            "stats",
+            SimpleFile::new_regular(fs.clone(), || Ok(MOUNT_TABLE.read().render_stats())),
+        );
+        fs_dir.add(
+            "slow_threshold_ms",
+            SimpleFile::new_regular(
+                fs.clone(),
+                RwFile::new(|req| match req {
+                    SimpleFileOperation::Read => {
+                        Ok(Some(format!("{}\n", slow_threshold_ms()).into_bytes()))
+                    }
+                    SimpleFileOperation::Write(data) => {
+                        if !data.is_empty() {
+                            set_slow_threshold_ms(parse_sysctl(data)?);
+                        }
+                        Ok(None)
+                    }
+                }),
+            ),
+        );
 
-        SimpleDir::new_maker(fs.clone(), Arc::new(sys))
+        SimpleDir::new_maker(fs.clone(), Arc::new(fs_dir))
     });
 
+    root.add(
+        "sys",
+        SimpleDir::new_maker(
+            fs.clone(),
+            Arc::new(SysctlDir {
+                fs: fs.clone(),
+                prefix: String::new(),
+            }),
+        ),
+    );
+
     let proc_dir = ProcFsHandler(fs.clone());
     SimpleDir::new_maker(fs, Arc::new(proc_dir.chain(root)))
 }
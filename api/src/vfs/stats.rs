@@ -0,0 +1,166 @@
+//! Per-mount statistics of VFS operations, shown in `/proc/fs/stats`.
+//!
+//! Lookups are counted when paths are resolved by `open` and the `stat`
+//! family, creates when `mknod` or `open(O_CREAT | O_EXCL)` add a node, and
+//! reads and writes for regular files only, as those of devices and pipes may
+//! block for as long as they like.
+//!
+//! Operations taking longer than `/proc/fs/slow_threshold_ms` are logged, which
+//! is disabled when it is 0.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axerrno::LinuxResult;
+use axfs_ng_vfs::{Location, NodeType};
+use axhal::time::monotonic_time;
+use axtask::current;
+use spin::RwLock;
+use starry_core::task::AsThread;
+
+use super::freeze;
+use crate::file::path_for;
+
+/// A kind of VFS operation.
+#[derive(Debug, Clone, Copy)]
+pub enum VfsOp {
+    Lookup,
+    Create,
+    Read,
+    Write,
+}
+
+impl VfsOp {
+    const ALL: [VfsOp; 4] = [VfsOp::Lookup, VfsOp::Create, VfsOp::Read, VfsOp::Write];
+
+    fn name(self) -> &'static str {
+        match self {
+            VfsOp::Lookup => "lookups",
+            VfsOp::Create => "creates",
+            VfsOp::Read => "reads",
+            VfsOp::Write => "writes",
+        }
+    }
+}
+
+#[derive(Default)]
+struct OpStats {
+    count: AtomicU64,
+    bytes: AtomicU64,
+    nanos: AtomicU64,
+}
+
+/// Counters of the operations on one mount.
+#[derive(Default)]
+pub struct MountStats {
+    ops: [OpStats; 4],
+}
+
+impl MountStats {
+    /// Formats the counters as `name count bytes avg_us` lines, indented
+    /// under the mount they belong to.
+    pub fn render(&self, out: &mut String) {
+        for op in VfsOp::ALL {
+            let stats = &self.ops[op as usize];
+            let count = stats.count.load(Ordering::Relaxed);
+            let avg_us = match count {
+                0 => 0,
+                _ => stats.nanos.load(Ordering::Relaxed) / count / 1000,
+            };
+            let _ = writeln!(
+                out,
+                "\t{} {} {} {}",
+                op.name(),
+                count,
+                stats.bytes.load(Ordering::Relaxed),
+                avg_us
+            );
+        }
+    }
+}
+
+/// Counters by the device number of the mount.
+static STATS: RwLock<BTreeMap<u64, Arc<MountStats>>> = RwLock::new(BTreeMap::new());
+
+static SLOW_THRESHOLD_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the threshold above which operations are logged, in milliseconds.
+pub fn slow_threshold_ms() -> u64 {
+    SLOW_THRESHOLD_MS.load(Ordering::Relaxed)
+}
+
+/// Sets the threshold above which operations are logged, 0 to disable it.
+pub fn set_slow_threshold_ms(ms: u64) {
+    SLOW_THRESHOLD_MS.store(ms, Ordering::Relaxed);
+}
+
+/// Returns the counters of the mount with device number `device`, if it has
+/// seen any operation.
+pub fn mount_stats(device: u64) -> Option<Arc<MountStats>> {
+    STATS.read().get(&device).cloned()
+}
+
+fn stats_for(device: u64) -> Arc<MountStats> {
+    if let Some(stats) = mount_stats(device) {
+        return stats;
+    }
+    STATS.write().entry(device).or_default().clone()
+}
+
+/// Records an operation on `loc` that started at `start` and moved `bytes`
+/// bytes.
+pub fn record(loc: &Location, op: VfsOp, bytes: usize, start: Duration) {
+    let elapsed = monotonic_time().saturating_sub(start);
+    let stats = stats_for(loc.mountpoint().device() as u64);
+    let stats = &stats.ops[op as usize];
+    stats.count.fetch_add(1, Ordering::Relaxed);
+    stats.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    stats
+        .nanos
+        .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+
+    let threshold = slow_threshold_ms();
+    if threshold != 0 && elapsed.as_millis() as u64 >= threshold {
+        warn!(
+            "slow {:?} of {} bytes on {}: {} ms",
+            op,
+            bytes,
+            path_for(loc),
+            elapsed.as_millis()
+        );
+    }
+}
+
+/// Runs a read or write on `loc`, recording the bytes it transferred, also
+/// as the `read_bytes` or `write_bytes` of the current process. A write
+/// waits first while the filesystem is frozen.
+pub fn track_io(
+    loc: &Location,
+    op: VfsOp,
+    f: impl FnOnce() -> LinuxResult<usize>,
+) -> LinuxResult<usize> {
+    if loc.node_type() != NodeType::RegularFile {
+        return f();
+    }
+    let _write = match op {
+        VfsOp::Write => Some(freeze::start_write(loc)?),
+        _ => None,
+    };
+    let start = monotonic_time();
+    let result = f();
+    if let Ok(n) = result {
+        record(loc, op, n, start);
+        if let Some(thr) = current().try_as_thread() {
+            match op {
+                VfsOp::Read => thr.proc_data.io.add_read_bytes(n),
+                VfsOp::Write => thr.proc_data.io.add_write_bytes(n),
+                _ => {}
+            }
+        }
+    }
+    result
+}
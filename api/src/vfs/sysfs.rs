@@ -0,0 +1,267 @@
+//! `/sys/class`, `/sys/block` and `/sys/dev/block`, mirrored from the devices
+//! registered with [`register`].
+//!
+//! Every device gets a directory with its `dev` number, its `uevent` and a
+//! `subsystem` link, and block devices their `size` in 512-byte sectors,
+//! which is what `lsblk` and scripts without udev read. Registering and
+//! unregistering a device also queues its uevent.
+
+use alloc::{
+    borrow::Cow,
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+
+use axfs_ng_vfs::{DeviceId, Filesystem, NodeType, VfsError, VfsResult};
+use kspin::SpinNoIrq;
+use linux_raw_sys::general::SYSFS_MAGIC;
+use starry_core::vfs::{
+    DirMaker, DirMapping, NodeOpsMux, SimpleDir, SimpleDirOps, SimpleFile, SimpleFs,
+};
+
+use super::dev::uevent::{self, Action};
+
+/// A device shown in sysfs.
+#[derive(Clone)]
+pub struct SysDevice {
+    class: &'static str,
+    devname: String,
+    dev: DeviceId,
+    /// The size in bytes of a block device.
+    size: Option<Arc<dyn Fn() -> u64 + Send + Sync>>,
+    /// Attributes the device has of its own.
+    attrs: Option<fn(Arc<SimpleFs>) -> DirMapping>,
+}
+
+impl SysDevice {
+    /// A device of `class` whose node is `/dev/{devname}`.
+    pub fn new(class: &'static str, devname: impl Into<String>, dev: DeviceId) -> Self {
+        Self {
+            class,
+            devname: devname.into(),
+            dev,
+            size: None,
+            attrs: None,
+        }
+    }
+
+    /// Shows the size of a block device, as given by `size` in bytes.
+    pub fn with_size(mut self, size: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        self.size = Some(Arc::new(size));
+        self
+    }
+
+    /// Adds the attributes made by `attrs` to the directory of the device.
+    pub fn with_attrs(mut self, attrs: fn(Arc<SimpleFs>) -> DirMapping) -> Self {
+        self.attrs = Some(attrs);
+        self
+    }
+
+    /// The name of the device in sysfs, which is that of its node without
+    /// the directories.
+    fn name(&self) -> &str {
+        name_of(&self.devname)
+    }
+}
+
+fn name_of(devname: &str) -> &str {
+    devname.rsplit('/').next().unwrap_or(devname)
+}
+
+/// The registered devices, by class and name.
+static DEVICES: SpinNoIrq<BTreeMap<(&'static str, String), SysDevice>> =
+    SpinNoIrq::new(BTreeMap::new());
+
+/// Adds `device` to sysfs and tells device managers about it.
+pub fn register(device: SysDevice) {
+    let (class, devname, dev) = (device.class, device.devname.clone(), device.dev);
+    DEVICES.lock().insert((class, device.name().into()), device);
+    uevent::emit(Action::Add, class, &devname, dev);
+}
+
+/// Removes the device `/dev/{devname}` of `class` from sysfs and tells device
+/// managers that it is gone.
+pub fn unregister(class: &'static str, devname: &str) {
+    let device = DEVICES.lock().remove(&(class, name_of(devname).into()));
+    if let Some(device) = device {
+        uevent::emit(Action::Remove, class, &device.devname, device.dev);
+    }
+}
+
+/// The devices of `class`.
+fn devices_of(class: &str) -> Vec<SysDevice> {
+    DEVICES
+        .lock()
+        .values()
+        .filter(|device| device.class == class)
+        .cloned()
+        .collect()
+}
+
+/// The number of `dev`, as `major:minor`.
+fn dev_name(dev: DeviceId) -> String {
+    format!("{}:{}", dev.major(), dev.minor())
+}
+
+fn symlink(fs: &Arc<SimpleFs>, target: String) -> NodeOpsMux {
+    NodeOpsMux::File(SimpleFile::new(fs.clone(), NodeType::Symlink, move || {
+        Ok(target.clone())
+    }))
+}
+
+/// The directory of `device`.
+fn device_dir(fs: &Arc<SimpleFs>, device: SysDevice) -> NodeOpsMux {
+    let mut dir = match device.attrs {
+        Some(attrs) => attrs(fs.clone()),
+        None => DirMapping::new(),
+    };
+    let dev = device.dev;
+    dir.add(
+        "dev",
+        SimpleFile::new_regular(fs.clone(), move || Ok(format!("{}\n", dev_name(dev)))),
+    );
+    let devname = device.devname.clone();
+    dir.add(
+        "uevent",
+        SimpleFile::new_regular(fs.clone(), move || {
+            Ok(format!(
+                "MAJOR={}\nMINOR={}\nDEVNAME={devname}\n",
+                dev.major(),
+                dev.minor()
+            ))
+        }),
+    );
+    dir.add(
+        "subsystem",
+        symlink(fs, format!("/sys/class/{}", device.class)),
+    );
+    if let Some(size) = device.size {
+        dir.add(
+            "size",
+            SimpleFile::new_regular(fs.clone(), move || Ok(format!("{}\n", size() / 512))),
+        );
+    }
+    SimpleDir::new_maker(fs.clone(), Arc::new(dir)).into()
+}
+
+/// `/sys/class`
+struct ClassesDir {
+    fs: Arc<SimpleFs>,
+}
+
+impl SimpleDirOps for ClassesDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let classes = DEVICES
+            .lock()
+            .keys()
+            .map(|(class, _)| *class)
+            .collect::<BTreeSet<_>>();
+        Box::new(classes.into_iter().map(Cow::Borrowed))
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let class = DEVICES
+            .lock()
+            .keys()
+            .map(|(class, _)| *class)
+            .find(|class| *class == name)
+            .ok_or(VfsError::ENOENT)?;
+        Ok(NodeOpsMux::Dir(SimpleDir::new_maker(
+            self.fs.clone(),
+            Arc::new(ClassDir {
+                fs: self.fs.clone(),
+                class,
+            }),
+        )))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// `/sys/class/{class}`, and `/sys/block` for the class `block`
+struct ClassDir {
+    fs: Arc<SimpleFs>,
+    class: &'static str,
+}
+
+impl SimpleDirOps for ClassDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = devices_of(self.class)
+            .into_iter()
+            .map(|device| Cow::Owned(device.name().into()))
+            .collect::<Vec<_>>();
+        Box::new(names.into_iter())
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let device = DEVICES
+            .lock()
+            .get(&(self.class, name.into()))
+            .cloned()
+            .ok_or(VfsError::ENOENT)?;
+        Ok(device_dir(&self.fs, device))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// `/sys/dev/block`, which links the numbers of block devices to their
+/// directories.
+struct DevBlockDir {
+    fs: Arc<SimpleFs>,
+}
+
+impl SimpleDirOps for DevBlockDir {
+    fn child_names<'a>(&'a self) -> Box<dyn Iterator<Item = Cow<'a, str>> + 'a> {
+        let names = devices_of("block")
+            .into_iter()
+            .map(|device| Cow::Owned(dev_name(device.dev)))
+            .collect::<Vec<_>>();
+        Box::new(names.into_iter())
+    }
+
+    fn lookup_child(&self, name: &str) -> VfsResult<NodeOpsMux> {
+        let device = devices_of("block")
+            .into_iter()
+            .find(|device| dev_name(device.dev) == name)
+            .ok_or(VfsError::ENOENT)?;
+        Ok(symlink(&self.fs, format!("../../block/{}", device.name())))
+    }
+
+    fn is_cacheable(&self) -> bool {
+        false
+    }
+}
+
+fn new_sysfs(root: impl FnOnce(Arc<SimpleFs>) -> DirMaker) -> Filesystem {
+    SimpleFs::new_with(String::from("sysfs"), SYSFS_MAGIC, root)
+}
+
+/// Creates the `/sys/class` directory.
+pub fn new_classfs() -> Filesystem {
+    new_sysfs(|fs| SimpleDir::new_maker(fs.clone(), Arc::new(ClassesDir { fs })))
+}
+
+/// Creates the `/sys/block` directory.
+pub fn new_blockfs() -> Filesystem {
+    new_sysfs(|fs| {
+        let dir = ClassDir {
+            fs: fs.clone(),
+            class: "block",
+        };
+        SimpleDir::new_maker(fs, Arc::new(dir))
+    })
+}
+
+/// Creates the `/sys/dev/block` directory.
+pub fn new_devblockfs() -> Filesystem {
+    new_sysfs(|fs| SimpleDir::new_maker(fs.clone(), Arc::new(DevBlockDir { fs })))
+}
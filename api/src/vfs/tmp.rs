@@ -1,26 +1,38 @@
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
-use core::{any::Any, borrow::Borrow, cmp::Ordering, task::Context, time::Duration};
+use alloc::{borrow::ToOwned, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    any::Any,
+    borrow::Borrow,
+    cmp,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Context,
+};
 
 use axfs_ng_vfs::{
-    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, Reference, StatFs, VfsError, VfsResult, WeakDirEntry
+    DeviceId, DirEntry, DirEntrySink, DirNode, DirNodeOps, FileNode, FileNodeOps, Filesystem,
+    FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType,
+    Reference, StatFs, VfsError, VfsResult, WeakDirEntry, path::MAX_NAME_LEN,
 };
+use axhal::time::wall_time;
 use axio::{IoEvents, Pollable};
 use axsync::Mutex;
 use hashbrown::HashMap;
+use linux_raw_sys::general::TMPFS_MAGIC;
+use memory_addr::PAGE_SIZE_4K;
 use slab::Slab;
-use starry_core::vfs::dummy_stat_fs;
+use starry_core::vfs::{XattrNode, XattrStore};
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 struct FileName(String);
 
 impl PartialOrd for FileName {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
 
 impl Ord for FileName {
-    fn cmp(&self, other: &Self) -> Ordering {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
         fn index(s: &str) -> u8 {
             match s {
                 "." => 0,
@@ -49,6 +61,9 @@ impl Borrow<str> for FileName {
 
 /// A simple in-memory filesystem that supports basic file operations.
 pub struct MemoryFs {
+    fs_type: u32,
+    /// The size limit in bytes, or `None` for half of the physical memory.
+    size: Option<u64>,
     inodes: Mutex<Slab<Arc<Inode>>>,
     root: Mutex<Option<DirEntry>>,
 }
@@ -57,7 +72,15 @@ impl MemoryFs {
     /// Creates a new empty memory filesystem.
     #[allow(clippy::new_ret_no_self)]
     pub fn new() -> Filesystem {
+        Self::new_with(TMPFS_MAGIC, None)
+    }
+
+    /// Creates a new empty memory filesystem reporting `fs_type` as its magic
+    /// number and limited to `size` bytes.
+    pub fn new_with(fs_type: u32, size: Option<u64>) -> Filesystem {
         let fs = Arc::new(Self {
+            fs_type,
+            size,
             inodes: Mutex::new(Slab::new()),
             root: Mutex::default(),
         });
@@ -77,6 +100,39 @@ impl MemoryFs {
     fn get(&self, ino: u64) -> Arc<Inode> {
         self.inodes.lock()[ino as usize - 1].clone()
     }
+
+    /// Returns the number of pages occupied by file contents.
+    fn used_blocks(&self) -> u64 {
+        self.inodes
+            .lock()
+            .iter()
+            .map(|(_, inode)| match &inode.content {
+                NodeContent::File(file) => file.size.allocated.load(Ordering::Relaxed),
+                NodeContent::Dir(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Returns the size limit in pages. Same default as Linux: half of the
+    /// memory.
+    fn max_blocks(&self) -> u64 {
+        self.size.map_or_else(
+            || {
+                let allocator = axalloc::global_allocator();
+                (allocator.used_pages() + allocator.available_pages()) as u64 / 2
+            },
+            |size| size.div_ceil(PAGE_SIZE_4K as u64),
+        )
+    }
+
+    /// Checks that `blocks` more pages can be allocated without exceeding the
+    /// size limit.
+    fn reserve(&self, blocks: u64) -> VfsResult<()> {
+        if blocks > 0 && self.used_blocks() + blocks > self.max_blocks() {
+            return Err(VfsError::ENOSPC);
+        }
+        Ok(())
+    }
 }
 
 impl FilesystemOps for MemoryFs {
@@ -89,7 +145,30 @@ impl FilesystemOps for MemoryFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(0x01021994))
+        let allocator = axalloc::global_allocator();
+        let available = allocator.available_pages() as u64;
+        let total = allocator.used_pages() as u64 + available;
+
+        let blocks = self.max_blocks();
+        let blocks_free = blocks.saturating_sub(self.used_blocks()).min(available);
+        // Same default as Linux: half as many inodes as pages of memory.
+        let file_count = total / 2;
+        let used_files = self.inodes.lock().len() as u64;
+
+        Ok(StatFs {
+            fs_type: self.fs_type,
+            block_size: PAGE_SIZE_4K as _,
+            blocks: blocks as _,
+            blocks_free: blocks_free as _,
+            blocks_available: blocks_free as _,
+
+            file_count: file_count as _,
+            free_file_count: file_count.saturating_sub(used_files) as _,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: PAGE_SIZE_4K as _,
+            mount_flags: 0,
+        })
     }
 }
 
@@ -102,13 +181,145 @@ fn release_inode(fs: &MemoryFs, inode: &Arc<Inode>, nlink: u64) {
     }
 }
 
+/// Allocated page ranges, as disjoint `start..end` page indices keyed by
+/// their start.
 #[derive(Default)]
-struct FileContent {
+struct Extents(BTreeMap<u64, u64>);
+
+impl Extents {
+    /// Returns how many pages from `start` to `end` are allocated.
+    fn covered(&self, start: u64, end: u64) -> u64 {
+        self.0
+            .range(..end)
+            .rev()
+            .take_while(|(_, e)| **e > start)
+            .map(|(s, e)| (*e).min(end) - (*s).max(start))
+            .sum()
+    }
+
+    /// Adds the pages from `start` to `end`, merging them with the extents
+    /// they overlap or touch.
+    fn insert(&mut self, mut start: u64, mut end: u64) {
+        let merged = self
+            .0
+            .range(..=end)
+            .rev()
+            .take_while(|(_, e)| **e >= start)
+            .map(|(s, _)| *s)
+            .collect::<Vec<_>>();
+        for s in merged {
+            let e = self.0.remove(&s).unwrap();
+            start = start.min(s);
+            end = end.max(e);
+        }
+        self.0.insert(start, end);
+    }
+
+    /// Removes the pages from `start` to `end`.
+    fn remove(&mut self, start: u64, end: u64) {
+        let cut = self
+            .0
+            .range(..end)
+            .rev()
+            .take_while(|(_, e)| **e > start)
+            .map(|(s, e)| (*s, *e))
+            .collect::<Vec<_>>();
+        for (s, e) in cut {
+            self.0.remove(&s);
+            if s < start {
+                self.0.insert(s, start);
+            }
+            if e > end {
+                self.0.insert(end, e);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct SparseData {
     /// The length of the file content.
-    ///
-    /// We only need to store the length here because we delegate the actual
+    length: u64,
+    /// The pages allocated to the content. Those up to the length that are
+    /// missing are holes.
+    extents: Extents,
+}
+
+/// The size of a file, which may have holes taking no memory.
+///
+/// Growing the length leaves a hole, as for `ftruncate`. Pages are only
+/// allocated by [`SparseFile::allocate`], which writes do for the range they
+/// cover before filling it.
+#[derive(Default)]
+struct SparseFile {
+    data: Mutex<SparseData>,
+    /// The number of pages in `data.extents`, kept apart so that the usage of
+    /// the filesystem can be summed up without taking the lock of every file.
+    allocated: AtomicU64,
+}
+
+impl SparseFile {
+    fn len(&self) -> u64 {
+        self.data.lock().length
+    }
+
+    fn set_len(&self, len: u64) {
+        let mut data = self.data.lock();
+        if len < data.length {
+            let first = len.div_ceil(PAGE_SIZE_4K as u64);
+            let freed = data.extents.covered(first, u64::MAX);
+            data.extents.remove(first, u64::MAX);
+            self.allocated.fetch_sub(freed, Ordering::Relaxed);
+        }
+        data.length = len;
+    }
+
+    /// Allocates the pages from `offset` to `end`, failing with `ENOSPC` if
+    /// those missing do not fit in the size limit of `fs`.
+    fn allocate(&self, fs: &MemoryFs, offset: u64, end: u64) -> VfsResult<()> {
+        let start = offset / PAGE_SIZE_4K as u64;
+        let end = end.div_ceil(PAGE_SIZE_4K as u64);
+        if start >= end {
+            return Ok(());
+        }
+        let mut data = self.data.lock();
+        let missing = (end - start) - data.extents.covered(start, end);
+        fs.reserve(missing)?;
+        data.extents.insert(start, end);
+        self.allocated.fetch_add(missing, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Frees the whole pages from `offset` to `end`.
+    fn punch_hole(&self, offset: u64, end: u64) {
+        let start = offset.div_ceil(PAGE_SIZE_4K as u64);
+        let end = end / PAGE_SIZE_4K as u64;
+        if start >= end {
+            return;
+        }
+        let mut data = self.data.lock();
+        let freed = data.extents.covered(start, end);
+        data.extents.remove(start, end);
+        self.allocated.fetch_sub(freed, Ordering::Relaxed);
+    }
+
+    /// Returns the allocated byte ranges, in order.
+    fn extents(&self) -> Vec<Range<u64>> {
+        let page = PAGE_SIZE_4K as u64;
+        let data = self.data.lock();
+        data.extents
+            .0
+            .iter()
+            .map(|(s, e)| s * page..e * page)
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct FileContent {
+    /// We only need to store the size here because we delegate the actual
     /// content management to page cache.
-    length: Mutex<u64>,
+    size: SparseFile,
     symlink: Mutex<Option<String>>,
 }
 
@@ -126,6 +337,7 @@ struct Inode {
     ino: u64,
     metadata: Mutex<Metadata>,
     content: NodeContent,
+    xattrs: XattrStore,
 }
 
 impl Inode {
@@ -138,6 +350,7 @@ impl Inode {
         let mut inodes = fs.inodes.lock();
         let entry = inodes.vacant_entry();
         let ino = entry.key() as u64 + 1;
+        let now = wall_time();
         let metadata = Metadata {
             device: 0,
             inode: ino,
@@ -150,9 +363,9 @@ impl Inode {
             block_size: 0,
             blocks: 0,
             rdev: DeviceId::default(),
-            atime: Duration::default(),
-            mtime: Duration::default(),
-            ctime: Duration::default(),
+            atime: now,
+            mtime: now,
+            ctime: now,
         };
         let content = match node_type {
             NodeType::Directory => NodeContent::Dir(DirContent::default()),
@@ -162,6 +375,7 @@ impl Inode {
             ino,
             metadata: Mutex::new(metadata),
             content,
+            xattrs: XattrStore::default(),
         });
         entry.insert(result.clone());
         drop(inodes);
@@ -213,7 +427,7 @@ impl Drop for InodeRef {
     }
 }
 
-struct MemoryNode {
+pub(crate) struct MemoryNode {
     fs: Arc<MemoryFs>,
     inode: Arc<Inode>,
     this: Option<WeakDirEntry>,
@@ -224,6 +438,29 @@ impl MemoryNode {
         Arc::new(Self { fs, inode, this })
     }
 
+    /// Sets the device ID of a device node.
+    pub fn set_rdev(&self, rdev: DeviceId) {
+        self.inode.metadata.lock().rdev = rdev;
+    }
+
+    /// Allocates the pages from `offset` to `end`, as `fallocate` does. The
+    /// length is not changed.
+    pub fn allocate(&self, offset: u64, end: u64) -> VfsResult<()> {
+        self.inode.as_file()?.size.allocate(&self.fs, offset, end)
+    }
+
+    /// Frees the whole pages from `offset` to `end`, whose content has been
+    /// zeroed, leaving a hole.
+    pub fn punch_hole(&self, offset: u64, end: u64) -> VfsResult<()> {
+        self.inode.as_file()?.size.punch_hole(offset, end);
+        Ok(())
+    }
+
+    /// Returns the byte ranges of this file that are not holes, in order.
+    pub fn extents(&self) -> VfsResult<Vec<Range<u64>>> {
+        Ok(self.inode.as_file()?.size.extents())
+    }
+
     fn new_entry(&self, name: &str, node_type: NodeType, inode: Arc<Inode>) -> VfsResult<DirEntry> {
         let fs = self.fs.clone();
         let reference = Reference::new(
@@ -245,6 +482,12 @@ impl MemoryNode {
     }
 }
 
+impl XattrNode for MemoryNode {
+    fn xattrs(&self) -> &XattrStore {
+        &self.inode.xattrs
+    }
+}
+
 impl NodeOps for MemoryNode {
     fn inode(&self) -> u64 {
         self.inode.ino
@@ -254,7 +497,10 @@ impl NodeOps for MemoryNode {
         let mut metadata = self.inode.metadata.lock().clone();
         match &self.inode.content {
             NodeContent::File(content) => {
-                metadata.size = *content.length.lock();
+                let allocated = content.size.allocated.load(Ordering::Relaxed);
+                metadata.size = content.size.len();
+                metadata.block_size = PAGE_SIZE_4K as _;
+                metadata.blocks = allocated * (PAGE_SIZE_4K as u64 / 512);
             }
             NodeContent::Dir(dir) => {
                 metadata.size = dir.entries.lock().len() as u64;
@@ -278,6 +524,8 @@ impl NodeOps for MemoryNode {
         if let Some(mtime) = update.mtime {
             metadata.mtime = mtime;
         }
+        // Any change of the metadata is a change of the inode.
+        metadata.ctime = wall_time();
         Ok(())
     }
 
@@ -319,13 +567,21 @@ impl FileNodeOps for MemoryNode {
     }
 
     fn set_len(&self, len: u64) -> VfsResult<()> {
-        *self.inode.as_file()?.length.lock() = len;
+        let size = &self.inode.as_file()?.size;
+        if size.len() == len {
+            return Ok(());
+        }
+        size.set_len(len);
+        let now = wall_time();
+        let mut metadata = self.inode.metadata.lock();
+        metadata.mtime = now;
+        metadata.ctime = now;
         Ok(())
     }
 
     fn set_symlink(&self, target: &str) -> VfsResult<()> {
         let file = self.inode.as_file()?;
-        *file.length.lock() = target.len() as u64;
+        file.size.set_len(target.len() as u64);
         *file.symlink.lock() = Some(target.to_owned());
         Ok(())
     }
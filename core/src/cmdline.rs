@@ -0,0 +1,31 @@
+//! The kernel command line, and the device tree it comes from.
+
+use axhal::mem::{PhysAddr, phys_to_virt};
+use fdt::Fdt;
+use spin::Once;
+
+/// Returns the device tree the kernel was booted with, if any.
+pub fn device_tree() -> Option<Fdt<'static>> {
+    let dtb = phys_to_virt(PhysAddr::from(axhal::dtb::get_bootarg()));
+    // SAFETY: the boot argument is either a device tree, which stays mapped
+    // for the lifetime of the kernel, or something else, which fails the
+    // header check
+    unsafe { Fdt::from_ptr(dtb.as_ptr()) }.ok()
+}
+
+/// Returns the kernel command line: the `bootargs` of the `/chosen` node of
+/// the device tree the kernel was booted with, or an empty one without
+/// either.
+pub fn cmdline() -> &'static str {
+    static CMDLINE: Once<&'static str> = Once::new();
+    CMDLINE.call_once(|| {
+        device_tree()
+            .and_then(|fdt| fdt.find_node("/chosen")?.property("bootargs")?.as_str())
+            .unwrap_or_default()
+    })
+}
+
+/// Returns the words of the kernel command line.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    cmdline().split_whitespace()
+}
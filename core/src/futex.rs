@@ -1,23 +1,17 @@
 //! Futex implementation.
 
-use alloc::{
-    collections::vec_deque::VecDeque,
-    sync::{Arc, Weak},
-    vec::Vec,
-};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
 use core::{
     future::poll_fn,
     ops::Deref,
-    sync::atomic::AtomicBool,
+    sync::atomic::{AtomicBool, Ordering},
     task::{Poll, Waker},
     time::Duration,
 };
 
 use axerrno::{LinuxError, LinuxResult};
-use axmm::{
-    AddrSpace,
-    backend::{Backend, SharedPages},
-};
+use axhal::paging::MappingFlags;
+use axmm::{AddrSpace, backend::Backend};
 use axsync::Mutex;
 use axtask::{
     current,
@@ -26,14 +20,36 @@ use axtask::{
 use futures::FutureExt;
 use hashbrown::HashMap;
 use kspin::SpinNoIrq;
-use memory_addr::VirtAddr;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 
 use crate::task::AsThread;
 
+/// A task waiting on a futex.
+struct Waiter {
+    waker: SpinNoIrq<Waker>,
+    bitset: u32,
+    /// Set once the waiter has been woken up or has given up waiting, after
+    /// which it no longer takes part in wakeups.
+    done: AtomicBool,
+}
+
+impl Waiter {
+    /// Marks the waiter as done, returning `false` if it already was.
+    fn finish(&self) -> bool {
+        !self.done.swap(true, Ordering::AcqRel)
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+}
+
 /// Wait queue used by futex.
+///
+/// Waiters are woken up in the order they started waiting.
 #[derive(Default)]
 pub struct WaitQueue {
-    queue: SpinNoIrq<VecDeque<(Waker, u32)>>,
+    queue: SpinNoIrq<VecDeque<Arc<Waiter>>>,
 }
 impl WaitQueue {
     /// Creates a new `WaitQueue`.
@@ -44,45 +60,66 @@ impl WaitQueue {
     /// Waits if the given condition is met.
     ///
     /// Returns `false` if the condition is not met and no actual waiting
-    /// occurs.
+    /// occurs. Only an explicit wakeup ends the wait successfully; a wakeup
+    /// racing with the timeout or a signal takes precedence over them, so
+    /// that it is not lost.
     pub fn wait_if(
         &self,
         bitset: u32,
         timeout: Option<Duration>,
         condition: impl FnOnce() -> bool,
     ) -> LinuxResult<bool> {
+        let waiter = Arc::new(Waiter {
+            waker: SpinNoIrq::new(Waker::noop().clone()),
+            bitset,
+            done: AtomicBool::new(false),
+        });
         let mut condition = Some(condition);
-        block_on_interruptible(
+        let result = block_on_interruptible(
             timeout_opt(
                 poll_fn(|cx| {
                     if let Some(cond) = condition.take() {
                         let mut queue = self.queue.lock();
                         if !cond() {
-                            Poll::Ready(Ok(false))
-                        } else {
-                            queue.push_back((cx.waker().clone(), bitset));
-                            Poll::Pending
+                            return Poll::Ready(Ok(false));
                         }
-                    } else {
+                        *waiter.waker.lock() = cx.waker().clone();
+                        queue.push_back(waiter.clone());
+                        Poll::Pending
+                    } else if waiter.is_done() {
                         Poll::Ready(Ok(true))
+                    } else {
+                        // Spurious wakeup
+                        *waiter.waker.lock() = cx.waker().clone();
+                        Poll::Pending
                     }
                 }),
                 timeout,
             )
             .map(|opt| opt.ok_or(LinuxError::ETIMEDOUT)?),
-        )
+        );
+        match result {
+            Ok(false) => Ok(false),
+            // If it is not done yet, the waiter is removed lazily by `wake`.
+            _ if !waiter.finish() => Ok(true),
+            result => result,
+        }
     }
 
     /// Wakes up at most `count` tasks whose bitset intersects with the given
     /// bitmask.
     pub fn wake(&self, count: usize, mask: u32) -> usize {
         let mut woke = 0;
-        self.queue.lock().retain(|(waker, bitset)| {
-            if woke >= count || (bitset & mask) == 0 {
+        self.queue.lock().retain(|waiter| {
+            if waiter.is_done() {
+                false
+            } else if woke >= count || (waiter.bitset & mask) == 0 {
                 true
             } else {
-                waker.wake_by_ref();
-                woke += 1;
+                if waiter.finish() {
+                    waiter.waker.lock().wake_by_ref();
+                    woke += 1;
+                }
                 false
             }
         });
@@ -91,16 +128,20 @@ impl WaitQueue {
 
     /// Checks if the wait queue is empty.
     pub fn is_empty(&self) -> bool {
-        self.queue.lock().is_empty()
+        let mut queue = self.queue.lock();
+        queue.retain(|waiter| !waiter.is_done());
+        queue.is_empty()
     }
 
     /// Requeue at most `count` tasks to the target wait queue.
-    pub fn requeue(&self, mut count: usize, target: &WaitQueue) -> usize {
+    pub fn requeue(&self, count: usize, target: &WaitQueue) -> usize {
         let tasks: Vec<_> = {
             let mut wq = self.queue.lock();
-            count = count.min(wq.len());
+            wq.retain(|waiter| !waiter.is_done());
+            let count = count.min(wq.len());
             wq.drain(..count).collect()
         };
+        let count = tasks.len();
         if !tasks.is_empty() {
             let mut wq = target.queue.lock();
             wq.extend(tasks);
@@ -117,47 +158,51 @@ pub enum FutexKey {
         address: usize,
     },
 
-    /// A futex in a shared memory region.
+    /// A futex in a shared mapping, such as shared anonymous memory, a SysV
+    /// shared memory segment or a shared file mapping.
+    ///
+    /// Processes may map the memory at different addresses, and a process may
+    /// map it more than once, so the futex is identified by the physical
+    /// address of its word, which is the same in all of them.
     Shared {
-        /// The offset of the futex within the shared memory region.
-        offset: usize,
-        /// The shared memory region.
-        region: Result<Weak<SharedPages>, Weak<()>>,
+        /// The physical address of the futex.
+        paddr: usize,
     },
 }
 
 impl FutexKey {
     /// Creates a new `FutexKey`.
-    pub fn new(aspace: &AddrSpace, address: usize) -> Self {
-        if let Some(area) = aspace.find_area(VirtAddr::from_usize(address)) {
-            match area.backend() {
-                Backend::Shared(backend) => {
-                    return Self::Shared {
-                        offset: address - area.start().as_usize(),
-                        region: Ok(Arc::downgrade(backend.pages())),
-                    };
-                }
-                Backend::File(file) => {
-                    return Self::Shared {
-                        offset: address - area.start().as_usize(),
-                        region: Err(file.futex_handle()),
-                    };
-                }
-                _ => {}
-            }
+    ///
+    /// The page of a futex in a shared mapping is populated first, so that it
+    /// has a physical address. If that fails, the address is not accessible
+    /// and the key is of no use anyway.
+    pub fn new(aspace: &mut AddrSpace, address: usize) -> Self {
+        let vaddr = VirtAddr::from_usize(address);
+        let shared = aspace
+            .find_area(vaddr)
+            .is_some_and(|area| matches!(area.backend(), Backend::Shared(_) | Backend::File(_)));
+        if shared
+            && aspace
+                .populate_area(vaddr.align_down_4k(), PAGE_SIZE_4K, MappingFlags::READ)
+                .is_ok()
+            && let Ok((paddr, ..)) = aspace.page_table().query(vaddr)
+        {
+            return Self::Shared {
+                paddr: paddr.as_usize(),
+            };
         }
         Self::Private { address }
     }
 
     /// Shortcut to create a `FutexKey` for the current task's address space.
     pub fn new_current(address: usize) -> Self {
-        Self::new(&current().as_thread().proc_data.aspace.lock(), address)
+        Self::new(&mut current().as_thread().proc_data.aspace.lock(), address)
     }
 
     fn as_usize(&self) -> usize {
         match self {
             FutexKey::Private { address } => *address,
-            FutexKey::Shared { offset, .. } => *offset,
+            FutexKey::Shared { paddr } => *paddr,
         }
     }
 }
@@ -166,16 +211,12 @@ impl FutexKey {
 pub struct FutexEntry {
     /// The wait queue associated with this futex.
     pub wq: WaitQueue,
-
-    /// Used by robust list, indicates if the owner of this futex is dead.
-    pub owner_dead: AtomicBool,
 }
 
 impl FutexEntry {
     fn new() -> Self {
         Self {
             wq: WaitQueue::new(),
-            owner_dead: AtomicBool::new(false),
         }
     }
 }
@@ -195,6 +236,16 @@ impl FutexTable {
         self.0.lock().is_empty()
     }
 
+    /// Wakes up every waiter and drops all wait queues, returning how many
+    /// were woken up.
+    pub fn clear(&self) -> usize {
+        let entries = core::mem::take(&mut *self.0.lock());
+        entries
+            .values()
+            .map(|entry| entry.wq.wake(usize::MAX, u32::MAX))
+            .sum()
+    }
+
     /// Gets the wait queue associated with the given address.
     pub fn get(&self, key: &FutexKey) -> Option<FutexGuard> {
         let key = key.as_usize();
@@ -10,11 +10,13 @@ extern crate alloc;
 #[macro_use]
 extern crate axlog;
 
+pub mod cmdline;
 pub mod config;
 pub mod futex;
 pub mod mm;
 pub mod resources;
 pub mod shm;
+pub mod sysctl;
 pub mod task;
 pub mod time;
 pub mod vfs;
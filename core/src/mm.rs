@@ -1,16 +1,17 @@
 //! User address space management.
 
+pub mod aslr;
+mod commit;
+mod pin;
+mod stats;
+pub mod vdso;
+mod zero;
+
 use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
-use core::{
-    ffi::CStr,
-    hint::unlikely,
-    iter,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::{ffi::CStr, hint::unlikely, iter, mem::MaybeUninit, sync::atomic::Ordering};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs_ng::{CachedFile, FS_CONTEXT, FileBackend};
+use axfs_ng::{CachedFile, FileBackend};
 use axfs_ng_vfs::Location;
 use axhal::{
     asm::user_copy,
@@ -19,15 +20,33 @@ use axhal::{
 };
 use axmm::{AddrSpace, backend::Backend};
 use axsync::Mutex;
+use axtask::current;
 use extern_trait::extern_trait;
-use kernel_elf_parser::{AuxEntry, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region};
+use kernel_elf_parser::{
+    AuxEntry, AuxType, ELFHeaders, ELFHeadersBuilder, ELFParser, app_stack_region,
+};
 use kernel_guard::IrqSave;
 use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use ouroboros::self_referencing;
 use starry_vm::{VmError, VmIo, VmResult};
 use uluru::LRUCache;
 
-use crate::config::{USER_SPACE_BASE, USER_SPACE_SIZE};
+pub use self::{
+    commit::{
+        CommitMap, OvercommitPolicy, commit_limit, committed_pages, overcommit_policy,
+        overcommit_ratio, set_overcommit_policy, set_overcommit_ratio, total_pages,
+    },
+    stats::{
+        check_fork_memory, count_cow_fault, count_fork, fork_stress, render_vmstat,
+        set_fork_stress, total_forks,
+    },
+    pin::{PinGuard, UserPins},
+    zero::{ZeroPages, zero_page},
+};
+use crate::{
+    config::{USER_SPACE_BASE, USER_SPACE_SIZE},
+    task::{AsThread, fs_context},
+};
 
 /// Creates a new empty user address space.
 pub fn new_user_aspace_empty() -> LinuxResult<AddrSpace> {
@@ -63,6 +82,42 @@ pub fn map_trampoline(aspace: &mut AddrSpace) -> LinuxResult {
     Ok(())
 }
 
+/// Resolves a page fault in a user address space, accounting it if it
+/// copies a page shared on fork.
+pub fn resolve_page_fault(
+    aspace: &mut AddrSpace,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool {
+    // A write to a page that is mapped in a copy-on-write area must be a
+    // write to a page still shared with the parent or a child.
+    let cow = access_flags.contains(MappingFlags::WRITE)
+        && aspace
+            .find_area(vaddr)
+            .is_some_and(|area| matches!(area.backend(), Backend::Cow(_)))
+        && aspace.page_table().query(vaddr).is_ok();
+    let resolved = aspace.handle_page_fault(vaddr, access_flags);
+    if resolved && cow {
+        count_cow_fault();
+    }
+    resolved
+}
+
+/// Returns how many of the pages from `start` to `start + size` are mapped
+/// in `aspace`, and so are resident. The zero page is not counted, as on
+/// Linux.
+pub fn resident_pages(aspace: &AddrSpace, start: VirtAddr, size: usize) -> usize {
+    let zero = zero_page();
+    (0..size / PAGE_SIZE_4K)
+        .filter(|i| {
+            aspace
+                .page_table()
+                .query(start + i * PAGE_SIZE_4K)
+                .is_ok_and(|(paddr, ..)| paddr != zero)
+        })
+        .count()
+}
+
 fn mapping_flags(flags: xmas_elf::program::Flags) -> MappingFlags {
     let mut mapping_flags = MappingFlags::USER;
     if flags.is_read() {
@@ -82,6 +137,7 @@ fn mapping_flags(flags: xmas_elf::program::Flags) -> MappingFlags {
 /// # Arguments
 /// - `uspace`: The address space of the user app.
 /// - `elf`: The elf file.
+/// - `resident`: Counts the pages mapped.
 ///
 /// # Returns
 /// - The entry point of the user app.
@@ -89,6 +145,7 @@ fn map_elf<'a>(
     uspace: &mut AddrSpace,
     base: usize,
     entry: &'a ElfCacheEntry,
+    resident: &mut usize,
 ) -> LinuxResult<ELFParser<'a>> {
     let elf_parser = ELFParser::new(entry.borrow_elf(), base).map_err(|_| LinuxError::EINVAL)?;
     let cache = entry.borrow_cache();
@@ -134,6 +191,7 @@ fn map_elf<'a>(
             true,
             backend,
         )?;
+        *resident += seg_align_size / PAGE_SIZE_4K;
 
         // TDOO: flush the I-cache
     }
@@ -141,6 +199,20 @@ fn map_elf<'a>(
     Ok(elf_parser)
 }
 
+/// Returns where the program headers of a mapped ELF are, from its
+/// `PT_PHDR`.
+///
+/// The offset of the headers in the file is only their address when the
+/// first segment is loaded from offset 0 at address 0, which static PIEs do
+/// not always do.
+fn phdr_addr(elf: &ELFParser) -> Option<usize> {
+    elf.headers()
+        .ph
+        .iter()
+        .find(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Phdr))
+        .map(|ph| ph.virtual_addr as usize + elf.base())
+}
+
 fn map_elf_error(err: &'static str) -> LinuxError {
     debug!("Failed to parse ELF file: {err}");
     LinuxError::ENOEXEC
@@ -182,16 +254,14 @@ impl ElfCacheEntry {
 
 struct ElfLoader(LRUCache<ElfCacheEntry, 32>);
 
-type LoadResult = Result<(VirtAddr, Vec<AuxEntry>), Vec<u8>>;
+type LoadResult = Result<(VirtAddr, Vec<AuxEntry>, usize), Vec<u8>>;
 
 impl ElfLoader {
     const fn new() -> Self {
         Self(LRUCache::new())
     }
 
-    fn load(&mut self, uspace: &mut AddrSpace, path: &str) -> LinuxResult<LoadResult> {
-        let loc = FS_CONTEXT.lock().resolve(path)?;
-
+    fn load(&mut self, uspace: &mut AddrSpace, loc: Location) -> LinuxResult<LoadResult> {
         if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
             match ElfCacheEntry::load(loc)? {
                 Ok(e) => {
@@ -205,6 +275,7 @@ impl ElfLoader {
 
         uspace.clear();
         map_trampoline(uspace)?;
+        vdso::map_vdso(uspace)?;
 
         let entry = self.0.front().unwrap();
         let ldso = if let Some(header) = entry
@@ -229,7 +300,7 @@ impl ElfLoader {
         };
 
         let (elf, ldso) = if let Some(ldso) = ldso {
-            let loc = FS_CONTEXT.lock().resolve(ldso)?;
+            let loc = fs_context().lock().resolve(ldso)?;
             if !self.0.touch(|e| e.borrow_cache().location().ptr_eq(&loc)) {
                 let e = ElfCacheEntry::load(loc)?.map_err(|_| LinuxError::EINVAL)?;
                 self.0.insert(e);
@@ -243,20 +314,31 @@ impl ElfLoader {
             (entry, None)
         };
 
-        let elf = map_elf(uspace, crate::config::USER_SPACE_BASE, elf)?;
+        // Executables that are not position independent ignore the base.
+        let mut resident = 0;
+        let elf = map_elf(uspace, aslr::pie_base(), elf, &mut resident)?;
         let ldso = ldso
-            .map(|elf| map_elf(uspace, crate::config::USER_INTERP_BASE, elf))
+            .map(|elf| map_elf(uspace, aslr::interp_base(), elf, &mut resident))
             .transpose()?;
 
         let entry = VirtAddr::from_usize(
             ldso.as_ref()
                 .map_or_else(|| elf.entry(), |ldso| ldso.entry()),
         );
-        let auxv = elf
-            .aux_vector(PAGE_SIZE_4K, ldso.map(|elf| elf.base()))
+        let phdr = phdr_addr(&elf);
+        let auxv = iter::once(AuxEntry::new(AuxType::SYSINFO_EHDR, vdso::VDSO_BASE))
+            .chain(
+                elf.aux_vector(PAGE_SIZE_4K, ldso.map(|elf| elf.base()))
+                    .map(|aux| match phdr {
+                        Some(addr) if aux.get_type() == AuxType::PHDR => {
+                            AuxEntry::new(AuxType::PHDR, addr)
+                        }
+                        _ => aux,
+                    }),
+            )
             .collect::<Vec<_>>();
 
-        Ok(Ok((entry, auxv)))
+        Ok(Ok((entry, auxv, resident)))
     }
 }
 
@@ -280,12 +362,13 @@ pub fn clear_elf_cache() {
 /// # Returns
 /// - The entry point of the user app.
 /// - The stack pointer of the user app.
+/// - The number of pages mapped, which make up the resident set.
 pub fn load_user_app(
     uspace: &mut AddrSpace,
     path: Option<&str>,
     args: &[String],
     envs: &[String],
-) -> LinuxResult<(VirtAddr, VirtAddr)> {
+) -> LinuxResult<(VirtAddr, VirtAddr, usize)> {
     let path = path
         .or_else(|| args.first().map(String::as_str))
         .ok_or(LinuxError::EINVAL)?;
@@ -298,8 +381,24 @@ pub fn load_user_app(
         return load_user_app(uspace, None, &new_args, envs);
     }
 
-    let (entry, auxv) = match { ELF_LOADER.lock().load(uspace, path)? } {
-        Ok((entry, auxv)) => (entry, auxv),
+    let loc = fs_context().lock().resolve(path)?;
+    load_user_app_at(uspace, loc, path, args, envs)
+}
+
+/// Load the user app at `loc`, which was named `path`, to the user address
+/// space.
+///
+/// This is [`load_user_app`] for a file that is already open, which need not
+/// have a path any more, as for `fexecve`.
+pub fn load_user_app_at(
+    uspace: &mut AddrSpace,
+    loc: Location,
+    path: &str,
+    args: &[String],
+    envs: &[String],
+) -> LinuxResult<(VirtAddr, VirtAddr, usize)> {
+    let (entry, auxv, mut resident) = match { ELF_LOADER.lock().load(uspace, loc)? } {
+        Ok(loaded) => loaded,
         Err(data) => {
             if data.starts_with(b"#!") {
                 let head = &data[2..data.len().min(256)];
@@ -319,7 +418,7 @@ pub fn load_user_app(
         }
     };
 
-    let ustack_top = VirtAddr::from_usize(crate::config::USER_STACK_TOP);
+    let ustack_top = VirtAddr::from_usize(aslr::stack_top());
     let ustack_size = crate::config::USER_STACK_SIZE;
     let ustack_start = ustack_top - ustack_size;
     debug!(
@@ -338,11 +437,13 @@ pub fn load_user_app(
     let stack_data = app_stack_region(args, envs, &auxv, ustack_top.into());
     let user_sp = ustack_top - stack_data.len();
     let user_sp_aligned = user_sp.align_down_4k();
+    let stack_populated = (ustack_top - user_sp_aligned).align_up_4k();
     uspace.populate_area(
         user_sp_aligned,
-        (ustack_top - user_sp_aligned).align_up_4k(),
+        stack_populated,
         MappingFlags::READ | MappingFlags::WRITE,
     )?;
+    resident += stack_populated / PAGE_SIZE_4K;
     uspace.write(user_sp, stack_data.as_slice())?;
 
     let heap_start = VirtAddr::from_usize(crate::config::USER_HEAP_BASE);
@@ -354,24 +455,32 @@ pub fn load_user_app(
         true,
         Backend::new_alloc(heap_start, PageSize::Size4K),
     )?;
+    resident += heap_size / PAGE_SIZE_4K;
 
-    Ok((entry, user_sp))
+    Ok((entry, user_sp, resident))
 }
 
-static ACCESSING_USER_MEM: AtomicBool = AtomicBool::new(false);
-
 /// Enables scoped access into user memory, allowing page faults to occur inside
 /// kernel.
+///
+/// This is a property of the current thread, which other CPUs do not share
+/// and which stays with the thread if it moves to another CPU.
 pub fn access_user_memory<R>(f: impl FnOnce() -> R) -> R {
-    ACCESSING_USER_MEM.store(true, Ordering::Release);
+    let curr = current();
+    let Some(thr) = curr.try_as_thread() else {
+        return f();
+    };
+    let outer = thr.accessing_user_memory.swap(true, Ordering::AcqRel);
     let result = f();
-    ACCESSING_USER_MEM.store(false, Ordering::Release);
+    thr.accessing_user_memory.store(outer, Ordering::Release);
     result
 }
 
 /// Check if the current thread is accessing user memory.
 pub fn is_accessing_user_memory() -> bool {
-    ACCESSING_USER_MEM.load(Ordering::Acquire)
+    current()
+        .try_as_thread()
+        .is_some_and(|thr| thr.accessing_user_memory.load(Ordering::Acquire))
 }
 
 #[allow(dead_code)]
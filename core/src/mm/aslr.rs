@@ -0,0 +1,87 @@
+//! Address space layout randomization.
+//!
+//! Controlled by `/proc/sys/kernel/randomize_va_space` as on Linux, except
+//! that it is off by default to keep addresses reproducible between runs. When
+//! on, position independent executables, the dynamic linker, the stack and the
+//! search for `mmap` addresses are shifted by random numbers of pages. The heap
+//! stays where it is, so 2 means the same as 1.
+//!
+//! In the deterministic mode the shifts come from its seed instead, so they
+//! are the same in every run.
+
+use core::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::time::monotonic_time_nanos;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::{
+    config::{USER_INTERP_BASE, USER_SPACE_BASE, USER_STACK_TOP},
+    task::deterministic_seed,
+};
+
+/// How far a PIE or the dynamic linker may be shifted. This keeps the main
+/// object below the dynamic linker and the latter below the heap.
+const LOAD_RANGE: usize = 0x100_0000;
+/// How far the stack top may be moved down.
+const STACK_RANGE: usize = 0x400_0000;
+/// How far the search for `mmap` addresses may start above the lowest one.
+const MMAP_RANGE: usize = 0x4000_0000;
+
+static RANDOMIZE: AtomicU8 = AtomicU8::new(0);
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the value of `kernel.randomize_va_space`.
+pub fn randomize_va_space() -> u8 {
+    RANDOMIZE.load(Ordering::Relaxed)
+}
+
+/// Sets `kernel.randomize_va_space`, which must be 0, 1 or 2.
+pub fn set_randomize_va_space(value: u8) -> LinuxResult<()> {
+    if value > 2 {
+        return Err(LinuxError::EINVAL);
+    }
+    RANDOMIZE.store(value, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Returns a random page aligned offset below `range`, or 0 with
+/// randomization off.
+///
+/// The numbers come from splitmix64 seeded by the time they are asked for,
+/// which is plenty to keep layouts from repeating but no protection against a
+/// determined attacker.
+fn random_offset(range: usize) -> usize {
+    if randomize_va_space() == 0 {
+        return 0;
+    }
+    let seed = match deterministic_seed() {
+        Some(seed) => seed.wrapping_add(STATE.fetch_add(1, Ordering::Relaxed)),
+        None => STATE.fetch_add(monotonic_time_nanos() | 1, Ordering::Relaxed),
+    };
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z as usize % (range / PAGE_SIZE_4K)) * PAGE_SIZE_4K
+}
+
+/// Returns where a position independent executable is loaded.
+pub fn pie_base() -> usize {
+    USER_SPACE_BASE + random_offset(LOAD_RANGE)
+}
+
+/// Returns where the dynamic linker is loaded.
+pub fn interp_base() -> usize {
+    USER_INTERP_BASE + random_offset(LOAD_RANGE)
+}
+
+/// Returns the top of the user stack.
+pub fn stack_top() -> usize {
+    USER_STACK_TOP - random_offset(STACK_RANGE)
+}
+
+/// Returns where the search for addresses of `mmap` without a hint starts.
+pub fn mmap_base() -> usize {
+    USER_SPACE_BASE + random_offset(MMAP_RANGE)
+}
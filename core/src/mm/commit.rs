@@ -0,0 +1,205 @@
+//! Overcommit policy and commit accounting.
+//!
+//! Private writable and shared anonymous mappings are charged to a global
+//! commit counter when they are created, which is checked against the policy
+//! set in `/proc/sys/vm/overcommit_memory`.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
+
+/// The policy of `vm.overcommit_memory`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OvercommitPolicy {
+    /// Refuse obvious overcommits, i.e. single allocations larger than the
+    /// total memory.
+    Heuristic = 0,
+    /// Always overcommit.
+    Always    = 1,
+    /// Never commit more than the commit limit.
+    Never     = 2,
+}
+
+impl TryFrom<u8> for OvercommitPolicy {
+    type Error = LinuxError;
+
+    fn try_from(value: u8) -> LinuxResult<Self> {
+        match value {
+            0 => Ok(Self::Heuristic),
+            1 => Ok(Self::Always),
+            2 => Ok(Self::Never),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(OvercommitPolicy::Heuristic as u8);
+static RATIO: AtomicUsize = AtomicUsize::new(50);
+static COMMITTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the current overcommit policy.
+pub fn overcommit_policy() -> OvercommitPolicy {
+    OvercommitPolicy::try_from(POLICY.load(Ordering::Relaxed)).unwrap()
+}
+
+/// Sets the overcommit policy.
+pub fn set_overcommit_policy(policy: OvercommitPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the percentage of memory that may be committed under
+/// [`OvercommitPolicy::Never`].
+pub fn overcommit_ratio() -> usize {
+    RATIO.load(Ordering::Relaxed)
+}
+
+/// Sets the overcommit ratio.
+pub fn set_overcommit_ratio(ratio: usize) {
+    RATIO.store(ratio, Ordering::Relaxed);
+}
+
+/// Returns the number of pages of memory, which `sysinfo` and
+/// `/proc/meminfo` both report.
+pub fn total_pages() -> usize {
+    let allocator = axalloc::global_allocator();
+    allocator.used_pages() + allocator.available_pages()
+}
+
+/// Returns the maximum number of pages that can be committed under
+/// [`OvercommitPolicy::Never`].
+pub fn commit_limit() -> usize {
+    total_pages() * overcommit_ratio() / 100
+}
+
+/// Returns the number of pages currently committed.
+pub fn committed_pages() -> usize {
+    COMMITTED.load(Ordering::Relaxed)
+}
+
+fn charge(pages: usize) -> LinuxResult<()> {
+    match overcommit_policy() {
+        OvercommitPolicy::Always => {}
+        OvercommitPolicy::Heuristic => {
+            if pages > total_pages() {
+                return Err(LinuxError::ENOMEM);
+            }
+        }
+        OvercommitPolicy::Never => {
+            let limit = commit_limit();
+            return COMMITTED
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |committed| {
+                    committed.checked_add(pages).filter(|it| *it <= limit)
+                })
+                .map(|_| ())
+                .map_err(|_| LinuxError::ENOMEM);
+        }
+    }
+    COMMITTED.fetch_add(pages, Ordering::Relaxed);
+    Ok(())
+}
+
+fn uncharge(pages: usize) {
+    COMMITTED.fetch_sub(pages, Ordering::Relaxed);
+}
+
+/// The committed ranges of an address space.
+#[derive(Default)]
+pub struct CommitMap {
+    /// Page-aligned `start -> end` ranges.
+    ranges: Mutex<BTreeMap<usize, usize>>,
+}
+
+impl CommitMap {
+    /// Charges the range `[start, start + len)`, which must not be committed
+    /// yet.
+    pub fn charge(&self, start: usize, len: usize) -> LinuxResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        charge(len / PAGE_SIZE_4K)?;
+        self.ranges.lock().insert(start, start + len);
+        Ok(())
+    }
+
+    /// Charges the parts of the range `[start, start + len)` that are not
+    /// committed yet, e.g. when private memory is made writable.
+    ///
+    /// Returns the ranges charged, for [`CommitMap::release`] to undo.
+    pub fn charge_uncommitted(&self, start: usize, len: usize) -> LinuxResult<Vec<(usize, usize)>> {
+        let end = start + len;
+        let mut ranges = self.ranges.lock();
+        let mut gaps = Vec::new();
+        let mut addr = start;
+        // The range ending after `start` may begin before it.
+        let first = ranges
+            .range(..=start)
+            .next_back()
+            .filter(|(_, range_end)| **range_end > start)
+            .map(|(range_start, _)| *range_start);
+        for (range_start, range_end) in ranges.range(first.unwrap_or(start)..end) {
+            if *range_start > addr {
+                gaps.push((addr, *range_start));
+            }
+            addr = addr.max(*range_end);
+        }
+        if addr < end {
+            gaps.push((addr, end));
+        }
+        charge(gaps.iter().map(|(start, end)| end - start).sum::<usize>() / PAGE_SIZE_4K)?;
+        ranges.extend(gaps.iter().copied());
+        Ok(gaps)
+    }
+
+    /// Releases the committed parts of the range `[start, start + len)`.
+    pub fn release(&self, start: usize, len: usize) {
+        let released = remove_range(&mut self.ranges.lock(), start, start + len);
+        uncharge(released / PAGE_SIZE_4K);
+    }
+
+    /// Releases everything, e.g. when the address space is replaced by
+    /// `execve`.
+    pub fn clear(&self) {
+        let ranges = core::mem::take(&mut *self.ranges.lock());
+        uncharge(ranges.iter().map(|(start, end)| end - start).sum::<usize>() / PAGE_SIZE_4K);
+    }
+
+    /// Charges a copy of `parent` for a forked address space.
+    pub fn inherit(&self, parent: &CommitMap) -> LinuxResult<()> {
+        let ranges = parent.ranges.lock().clone();
+        charge(ranges.iter().map(|(start, end)| end - start).sum::<usize>() / PAGE_SIZE_4K)?;
+        *self.ranges.lock() = ranges;
+        Ok(())
+    }
+}
+
+/// Removes `[start, end)` from a map of `start -> end` ranges, splitting the
+/// ranges it overlaps, and returns the size removed.
+pub(super) fn remove_range(ranges: &mut BTreeMap<usize, usize>, start: usize, end: usize) -> usize {
+    let overlapping = ranges
+        .range(..end)
+        .filter(|(_, range_end)| **range_end > start)
+        .map(|(range_start, range_end)| (*range_start, *range_end))
+        .collect::<Vec<_>>();
+    let mut removed = 0;
+    for (range_start, range_end) in overlapping {
+        ranges.remove(&range_start);
+        if range_start < start {
+            ranges.insert(range_start, start);
+        }
+        if range_end > end {
+            ranges.insert(end, range_end);
+        }
+        removed += range_end.min(end) - range_start.max(start);
+    }
+    removed
+}
+
+impl Drop for CommitMap {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
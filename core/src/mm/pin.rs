@@ -0,0 +1,85 @@
+//! Pinning of user memory for transfers that run without the address space
+//! locked.
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{future::poll_fn, task::Poll};
+
+use axio::PollSet;
+use axmm::AddrSpace;
+use axsync::{Mutex, MutexGuard};
+use axtask::future::block_on;
+use memory_addr::VirtAddr;
+
+/// The ranges of an address space pinned by transfers in progress, which
+/// must not be unmapped, remapped or have their protection changed until the
+/// transfers are done.
+#[derive(Default)]
+pub struct UserPins {
+    /// Pinned `(start, end)` ranges, once per pin.
+    ranges: Mutex<Vec<(usize, usize)>>,
+    /// Triggered whenever a pin is released.
+    released: PollSet,
+}
+
+impl UserPins {
+    /// Pins the range `[start, start + len)` until the returned guard is
+    /// dropped. The address space must be locked, so that the pages cannot
+    /// go away before they are pinned.
+    pub fn pin(self: &Arc<Self>, start: VirtAddr, len: usize) -> PinGuard {
+        let range = (start.as_usize(), start.as_usize() + len);
+        self.ranges.lock().push(range);
+        PinGuard {
+            pins: self.clone(),
+            range,
+        }
+    }
+
+    fn is_pinned(&self, start: usize, end: usize) -> bool {
+        self.ranges
+            .lock()
+            .iter()
+            .any(|(pin_start, pin_end)| *pin_start < end && start < *pin_end)
+    }
+
+    /// Locks `aspace` once nothing in `[start, start + len)` is pinned, before
+    /// the range is modified.
+    pub fn lock_unpinned<'a>(
+        &self,
+        aspace: &'a Mutex<AddrSpace>,
+        start: VirtAddr,
+        len: usize,
+    ) -> MutexGuard<'a, AddrSpace> {
+        let (start, end) = (start.as_usize(), start.as_usize() + len);
+        block_on(poll_fn(|cx| {
+            let guard = aspace.lock();
+            if !self.is_pinned(start, end) {
+                return Poll::Ready(guard);
+            }
+            drop(guard);
+            self.released.register(cx.waker());
+            let guard = aspace.lock();
+            if self.is_pinned(start, end) {
+                Poll::Pending
+            } else {
+                Poll::Ready(guard)
+            }
+        }))
+    }
+}
+
+/// A pin of user memory, released when dropped.
+pub struct PinGuard {
+    pins: Arc<UserPins>,
+    range: (usize, usize),
+}
+
+impl Drop for PinGuard {
+    fn drop(&mut self) {
+        let mut ranges = self.pins.ranges.lock();
+        if let Some(index) = ranges.iter().position(|it| *it == self.range) {
+            ranges.swap_remove(index);
+        }
+        drop(ranges);
+        self.pins.released.wake();
+    }
+}
@@ -0,0 +1,87 @@
+//! Fork and copy-on-write statistics, and the fork stress mode.
+//!
+//! The statistics are shown in `/proc/vmstat`. The stress mode is switched
+//! in `/proc/sys/vm/fork_stress`: while it is on, a fork fails with `EAGAIN`
+//! once free memory falls below a reserve, rather than taking the pages that
+//! the parents need to reap their children. Switching it on also resets the
+//! statistics, so that a stress test reads figures of its own.
+
+use alloc::{format, string::String};
+use core::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+
+use super::total_pages;
+
+static FORKS: AtomicU64 = AtomicU64::new(0);
+/// The forks since boot, which the stress mode does not reset.
+static TOTAL_FORKS: AtomicU64 = AtomicU64::new(0);
+static FORK_NANOS: AtomicU64 = AtomicU64::new(0);
+static COW_FAULTS: AtomicU64 = AtomicU64::new(0);
+static FORK_STRESS: AtomicBool = AtomicBool::new(false);
+
+/// The share of memory kept from new tasks in stress mode, as a divisor of
+/// the total.
+const STRESS_RESERVE_DIVISOR: usize = 16;
+
+/// Accounts a fork that took `elapsed`.
+pub fn count_fork(elapsed: Duration) {
+    FORKS.fetch_add(1, Ordering::Relaxed);
+    TOTAL_FORKS.fetch_add(1, Ordering::Relaxed);
+    FORK_NANOS.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// Returns the number of forks since boot, the `processes` of `/proc/stat`.
+pub fn total_forks() -> u64 {
+    TOTAL_FORKS.load(Ordering::Relaxed)
+}
+
+/// Accounts a write fault that copied a page shared on fork.
+pub fn count_cow_fault() {
+    COW_FAULTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns whether the fork stress mode is on.
+pub fn fork_stress() -> bool {
+    FORK_STRESS.load(Ordering::Relaxed)
+}
+
+/// Switches the fork stress mode, resetting the statistics when it is
+/// switched on.
+pub fn set_fork_stress(enabled: bool) {
+    if enabled && !FORK_STRESS.swap(true, Ordering::Relaxed) {
+        FORKS.store(0, Ordering::Relaxed);
+        FORK_NANOS.store(0, Ordering::Relaxed);
+        COW_FAULTS.store(0, Ordering::Relaxed);
+    } else if !enabled {
+        FORK_STRESS.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Checks that there is memory left for a new task in stress mode.
+pub fn check_fork_memory() -> LinuxResult {
+    let reserve = total_pages() / STRESS_RESERVE_DIVISOR;
+    if fork_stress() && axalloc::global_allocator().available_pages() < reserve {
+        return Err(LinuxError::EAGAIN);
+    }
+    Ok(())
+}
+
+/// Formats the statistics in the format of `/proc/vmstat`.
+pub fn render_vmstat() -> String {
+    let forks = FORKS.load(Ordering::Relaxed);
+    let latency = FORK_NANOS
+        .load(Ordering::Relaxed)
+        .checked_div(forks)
+        .unwrap_or(0);
+    format!(
+        "nr_free_pages {}\nnr_forks {}\ncow_faults {}\nfork_latency_avg_ns {}\n",
+        axalloc::global_allocator().available_pages(),
+        forks,
+        COW_FAULTS.load(Ordering::Relaxed),
+        latency,
+    )
+}
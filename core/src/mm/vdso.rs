@@ -0,0 +1,677 @@
+//! The vDSO, a small shared object mapped into every process.
+//!
+//! It exports `clock_gettime`, `gettimeofday` and, except on aarch64, `getcpu`
+//! under the names the C libraries look for. The first two then need no
+//! system call.
+//! The functions read the timer counter directly, which is made accessible to
+//! user space, and turn it into nanoseconds with the factors published on a
+//! read-only data page. The timer callback refreshes those factors along with
+//! the offset of the wall clock, so a change of the latter shows up within a
+//! tick. Clocks that are not covered fall back to the system call.
+//!
+//! The object is built at boot instead of being linked separately: an ELF
+//! header, a dynamic section and a symbol table are written in front of the
+//! code, which comes from the `global_asm!` below. It is mapped right after
+//! the signal trampoline at a fixed address, and the code finds the data page
+//! at a fixed address too.
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::LinuxResult;
+use axhal::{
+    mem::virt_to_phys,
+    paging::MappingFlags,
+    time::{current_ticks, monotonic_time_nanos, ticks_to_nanos, wall_time},
+};
+use axmm::AddrSpace;
+use lazy_static::lazy_static;
+use memory_addr::PAGE_SIZE_4K;
+
+use crate::config::SIGNAL_TRAMPOLINE;
+
+/// The address of the data page in user space.
+pub const VDSO_DATA: usize = SIGNAL_TRAMPOLINE + PAGE_SIZE_4K;
+/// The address of the vDSO image in user space, passed as `AT_SYSINFO_EHDR`.
+pub const VDSO_BASE: usize = VDSO_DATA + PAGE_SIZE_4K;
+
+/// Where the code starts in the image; everything before is ELF metadata.
+const TEXT_OFFSET: usize = 0x800;
+
+/// The data page read by the vDSO functions.
+///
+/// The field offsets are hard-coded in the assembly. Nanoseconds since boot
+/// are `((counter * mult) >> 32) + mono_bias`, and adding `real_offset` gives
+/// nanoseconds since the epoch. The fields are not updated together, but they
+/// only ever change by tiny amounts, so a mix of old and new values is as good
+/// as either.
+#[repr(C, align(4096))]
+struct VdsoData {
+    /// Non-zero once the counter may be read from user space.
+    enabled: AtomicU64,
+    mult: AtomicU64,
+    mono_bias: AtomicU64,
+    real_offset: AtomicU64,
+}
+
+static DATA: VdsoData = VdsoData {
+    enabled: AtomicU64::new(0),
+    mult: AtomicU64::new(0),
+    mono_bias: AtomicU64::new(0),
+    real_offset: AtomicU64::new(0),
+};
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE_4K]);
+
+lazy_static! {
+    static ref IMAGE: Box<Page> = build_image();
+}
+
+unsafe extern "C" {
+    fn starry_vdso_start();
+    fn starry_vdso_clock_gettime();
+    fn starry_vdso_gettimeofday();
+    fn starry_vdso_getcpu();
+    fn starry_vdso_end();
+}
+
+#[cfg(target_arch = "aarch64")]
+const SYMBOLS: &[(&str, unsafe extern "C" fn())] = &[
+    ("__kernel_clock_gettime", starry_vdso_clock_gettime),
+    ("__kernel_gettimeofday", starry_vdso_gettimeofday),
+];
+#[cfg(not(target_arch = "aarch64"))]
+const SYMBOLS: &[(&str, unsafe extern "C" fn())] = &[
+    ("__vdso_clock_gettime", starry_vdso_clock_gettime),
+    ("__vdso_gettimeofday", starry_vdso_gettimeofday),
+    ("__vdso_getcpu", starry_vdso_getcpu),
+];
+
+const SONAME: &str = "linux-vdso.so.1";
+
+const DT_NULL: u64 = 0;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_STRSZ: u64 = 10;
+const DT_SYMENT: u64 = 11;
+const DT_SONAME: u64 = 14;
+
+#[cfg(target_arch = "aarch64")]
+const EM_CURRENT: u16 = 183;
+#[cfg(target_arch = "riscv64")]
+const EM_CURRENT: u16 = 243;
+#[cfg(target_arch = "loongarch64")]
+const EM_CURRENT: u16 = 258;
+#[cfg(target_arch = "x86_64")]
+const EM_CURRENT: u16 = 62;
+
+struct Writer<'a>(&'a mut [u8]);
+
+impl Writer<'_> {
+    fn bytes(&mut self, offset: usize, data: &[u8]) {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn u16(&mut self, offset: usize, value: u16) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+
+    fn u32(&mut self, offset: usize, value: u32) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+
+    fn u64(&mut self, offset: usize, value: u64) {
+        self.bytes(offset, &value.to_le_bytes());
+    }
+}
+
+/// Builds the vDSO image: an ELF shared object of a single page, loaded at
+/// virtual address 0, so that offsets and addresses coincide.
+fn build_image() -> Box<Page> {
+    const EHDR: usize = 0;
+    const PHDR: usize = 64;
+    const DYNAMIC: usize = PHDR + 2 * 56;
+    const DYNAMIC_LEN: usize = 7;
+    const HASH: usize = DYNAMIC + DYNAMIC_LEN * 16;
+    const SYMTAB: usize = HASH + 32;
+    const STRTAB: usize = SYMTAB + 4 * 24;
+
+    let mut page = Box::new(Page([0; PAGE_SIZE_4K]));
+    let mut w = Writer(&mut page.0);
+
+    let start = starry_vdso_start as usize;
+    let text_len = starry_vdso_end as usize - start;
+    assert!(TEXT_OFFSET + text_len <= PAGE_SIZE_4K);
+    // SAFETY: the code lies between the two symbols
+    let text = unsafe { core::slice::from_raw_parts(start as *const u8, text_len) };
+    w.bytes(TEXT_OFFSET, text);
+
+    // Strings: the empty one, the soname and the symbol names.
+    let mut strtab_len = 1;
+    let mut add_string = |w: &mut Writer, s: &str| {
+        let offset = strtab_len;
+        w.bytes(STRTAB + offset, s.as_bytes());
+        strtab_len += s.len() + 1;
+        offset
+    };
+    let soname = add_string(&mut w, SONAME);
+
+    // Symbol 0 is the null symbol.
+    let nsyms = SYMBOLS.len() + 1;
+    assert!(SYMTAB + nsyms * 24 <= STRTAB);
+    for (i, (name, func)) in SYMBOLS.iter().enumerate() {
+        let sym = SYMTAB + (i + 1) * 24;
+        let name = add_string(&mut w, name);
+        w.u32(sym, name as u32);
+        // STB_GLOBAL, STT_FUNC
+        w.bytes(sym + 4, &[0x12, 0]);
+        // Any defined section will do, there are no section headers.
+        w.u16(sym + 6, 1);
+        w.u64(sym + 8, (TEXT_OFFSET + *func as usize - start) as u64);
+    }
+    assert!(STRTAB + strtab_len <= TEXT_OFFSET);
+
+    // A hash table with a single bucket chaining all the symbols.
+    w.u32(HASH, 1);
+    w.u32(HASH + 4, nsyms as u32);
+    w.u32(HASH + 8, (nsyms - 1) as u32);
+    for i in 1..nsyms {
+        w.u32(HASH + 12 + i * 4, (i - 1) as u32);
+    }
+
+    let dynamic = [
+        (DT_HASH, HASH),
+        (DT_STRTAB, STRTAB),
+        (DT_SYMTAB, SYMTAB),
+        (DT_STRSZ, strtab_len),
+        (DT_SYMENT, 24),
+        (DT_SONAME, soname),
+        (DT_NULL, 0),
+    ];
+    for (i, (tag, value)) in dynamic.into_iter().enumerate() {
+        w.u64(DYNAMIC + i * 16, tag);
+        w.u64(DYNAMIC + i * 16 + 8, value as u64);
+    }
+
+    // ELF header: 64-bit, little endian, current version, shared object.
+    w.bytes(EHDR, b"\x7fELF\x02\x01\x01");
+    w.u16(EHDR + 16, 3);
+    w.u16(EHDR + 18, EM_CURRENT);
+    w.u32(EHDR + 20, 1);
+    w.u64(EHDR + 32, PHDR as u64);
+    w.u16(EHDR + 52, 64);
+    w.u16(EHDR + 54, 56);
+    w.u16(EHDR + 56, 2);
+    w.u16(EHDR + 58, 64);
+
+    // PT_LOAD covering the whole page, readable and executable.
+    w.u32(PHDR, 1);
+    w.u32(PHDR + 4, 5);
+    w.u64(PHDR + 32, PAGE_SIZE_4K as u64);
+    w.u64(PHDR + 40, PAGE_SIZE_4K as u64);
+    w.u64(PHDR + 48, PAGE_SIZE_4K as u64);
+    // PT_DYNAMIC, readable.
+    let ph = PHDR + 56;
+    w.u32(ph, 2);
+    w.u32(ph + 4, 4);
+    for field in [8, 16, 24] {
+        w.u64(ph + field, DYNAMIC as u64);
+    }
+    for field in [32, 40] {
+        w.u64(ph + field, (DYNAMIC_LEN * 16) as u64);
+    }
+    w.u64(ph + 48, 8);
+
+    sync_icache(page.0.as_ptr() as usize + TEXT_OFFSET, text_len);
+    page
+}
+
+/// Makes code just written through the data cache visible to instruction
+/// fetches.
+fn sync_icache(_start: usize, _len: usize) {
+    // SAFETY: only cleans and invalidates caches
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        for line in (_start & !63.._start + _len).step_by(64) {
+            core::arch::asm!("dc cvau, {}", in(reg) line);
+        }
+        core::arch::asm!("dsb ish", "ic iallu", "dsb ish", "isb");
+    }
+    // SAFETY: only invalidates caches
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("fence.i");
+    }
+    // SAFETY: only orders instruction fetches
+    #[cfg(target_arch = "loongarch64")]
+    unsafe {
+        core::arch::asm!("ibar 0");
+    }
+}
+
+/// Lets user space read the timer counter.
+fn enable_user_counter() {
+    // SAFETY: only sets EL0PCTEN, which grants EL0 access to CNTPCT_EL0
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        core::arch::asm!(
+            "mrs {tmp}, cntkctl_el1",
+            "orr {tmp}, {tmp}, #1",
+            "msr cntkctl_el1, {tmp}",
+            tmp = out(reg) _,
+        );
+    }
+    // SAFETY: only sets the TM bit, which grants U-mode access to `time`
+    #[cfg(target_arch = "riscv64")]
+    unsafe {
+        core::arch::asm!("csrs scounteren, {}", in(reg) 2);
+    }
+    // `rdtsc` and `rdtime.d` are available to user space by default.
+}
+
+/// Initializes the vDSO; processes started before use system calls.
+pub fn init() {
+    let _ = &*IMAGE;
+    enable_user_counter();
+    DATA.mult.store(ticks_to_nanos(1 << 32), Ordering::Relaxed);
+    update();
+    DATA.enabled.store(1, Ordering::Release);
+}
+
+/// Refreshes the data page, called on every timer tick.
+pub fn update() {
+    let mult = DATA.mult.load(Ordering::Relaxed);
+    let ticks = current_ticks();
+    let mono = monotonic_time_nanos();
+    let scaled = ((ticks as u128 * mult as u128) >> 32) as u64;
+    DATA.mono_bias
+        .store(mono.wrapping_sub(scaled), Ordering::Relaxed);
+    let real = wall_time().as_nanos() as u64;
+    DATA.real_offset
+        .store(real.wrapping_sub(mono), Ordering::Relaxed);
+}
+
+/// Maps the vDSO and its data page to the user address space.
+pub fn map_vdso(aspace: &mut AddrSpace) -> LinuxResult {
+    aspace.map_linear(
+        VDSO_DATA.into(),
+        virt_to_phys((&DATA as *const VdsoData as usize).into()),
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::USER,
+    )?;
+    aspace.map_linear(
+        VDSO_BASE.into(),
+        virt_to_phys((IMAGE.0.as_ptr() as usize).into()),
+        PAGE_SIZE_4K,
+        MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER,
+    )?;
+    Ok(())
+}
+
+// The functions follow the C calling convention. `clock_gettime` serves
+// CLOCK_REALTIME (0) and CLOCK_REALTIME_COARSE (5) from the wall clock, and
+// CLOCK_MONOTONIC (1), CLOCK_MONOTONIC_RAW (4), CLOCK_MONOTONIC_COARSE (6) and
+// CLOCK_BOOTTIME (7) from the monotonic one: bit `id` of 0x21 and 0xd2
+// respectively is set for them. `gettimeofday` ignores the time zone, as the
+// system call does. `getcpu` is a plain system call, as the CPU a thread
+// runs on is only known to the kernel.
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    ".pushsection .text.vdso, \"ax\"",
+    ".balign 16",
+    ".globl starry_vdso_start",
+    "starry_vdso_start:",
+    ".globl starry_vdso_clock_gettime",
+    "starry_vdso_clock_gettime:",
+    "cmp w0, #8",
+    "b.hs .Lvdso_cgt_fallback",
+    "mov x9, #0x21",
+    "lsr x9, x9, x0",
+    "mov x10, #0xd2",
+    "lsr x10, x10, x0",
+    "orr x11, x9, x10",
+    "tbz x11, #0, .Lvdso_cgt_fallback",
+    "movz x12, #{data_lo}",
+    "movk x12, #{data_hi}, lsl #16",
+    "ldr x13, [x12]",
+    "cbz x13, .Lvdso_cgt_fallback",
+    "isb",
+    "mrs x13, cntpct_el0",
+    "ldr x14, [x12, #8]",
+    "mul x15, x13, x14",
+    "umulh x16, x13, x14",
+    "extr x15, x16, x15, #32",
+    "ldr x14, [x12, #16]",
+    "add x15, x15, x14",
+    "ldr x14, [x12, #24]",
+    "and x9, x9, #1",
+    "neg x9, x9",
+    "and x14, x14, x9",
+    "add x15, x15, x14",
+    "movz x14, #0xca00",
+    "movk x14, #0x3b9a, lsl #16",
+    "udiv x16, x15, x14",
+    "msub x17, x16, x14, x15",
+    "stp x16, x17, [x1]",
+    "mov x0, #0",
+    "ret",
+    ".Lvdso_cgt_fallback:",
+    "mov x8, #{nr_clock_gettime}",
+    "svc #0",
+    "ret",
+    ".globl starry_vdso_gettimeofday",
+    "starry_vdso_gettimeofday:",
+    "movz x12, #{data_lo}",
+    "movk x12, #{data_hi}, lsl #16",
+    "ldr x13, [x12]",
+    "cbz x13, .Lvdso_gtod_fallback",
+    "cbz x0, .Lvdso_gtod_done",
+    "isb",
+    "mrs x13, cntpct_el0",
+    "ldr x14, [x12, #8]",
+    "mul x15, x13, x14",
+    "umulh x16, x13, x14",
+    "extr x15, x16, x15, #32",
+    "ldr x14, [x12, #16]",
+    "add x15, x15, x14",
+    "ldr x14, [x12, #24]",
+    "add x15, x15, x14",
+    "movz x14, #0xca00",
+    "movk x14, #0x3b9a, lsl #16",
+    "udiv x16, x15, x14",
+    "msub x17, x16, x14, x15",
+    "mov x14, #1000",
+    "udiv x17, x17, x14",
+    "stp x16, x17, [x0]",
+    ".Lvdso_gtod_done:",
+    "mov x0, #0",
+    "ret",
+    ".Lvdso_gtod_fallback:",
+    "mov x8, #{nr_gettimeofday}",
+    "svc #0",
+    "ret",
+    ".globl starry_vdso_getcpu",
+    "starry_vdso_getcpu:",
+    "mov x8, #{nr_getcpu}",
+    "svc #0",
+    "ret",
+    ".globl starry_vdso_end",
+    "starry_vdso_end:",
+    ".popsection",
+    data_lo = const VDSO_DATA & 0xffff,
+    data_hi = const VDSO_DATA >> 16,
+    nr_clock_gettime = const linux_raw_sys::general::__NR_clock_gettime,
+    nr_gettimeofday = const linux_raw_sys::general::__NR_gettimeofday,
+    nr_getcpu = const linux_raw_sys::general::__NR_getcpu,
+);
+
+#[cfg(target_arch = "riscv64")]
+core::arch::global_asm!(
+    ".pushsection .text.vdso, \"ax\"",
+    ".balign 16",
+    ".globl starry_vdso_start",
+    "starry_vdso_start:",
+    ".globl starry_vdso_clock_gettime",
+    "starry_vdso_clock_gettime:",
+    "li t0, 8",
+    "bgeu a0, t0, .Lvdso_cgt_fallback",
+    "li t1, 0x21",
+    "srl t1, t1, a0",
+    "li t2, 0xd2",
+    "srl t2, t2, a0",
+    "or t3, t1, t2",
+    "andi t3, t3, 1",
+    "beqz t3, .Lvdso_cgt_fallback",
+    "li t4, {data}",
+    "ld t5, 0(t4)",
+    "beqz t5, .Lvdso_cgt_fallback",
+    "csrr t5, time",
+    "ld t6, 8(t4)",
+    "mul a2, t5, t6",
+    "mulhu a3, t5, t6",
+    "srli a2, a2, 32",
+    "slli a3, a3, 32",
+    "or a2, a2, a3",
+    "ld t6, 16(t4)",
+    "add a2, a2, t6",
+    "ld t6, 24(t4)",
+    "andi t1, t1, 1",
+    "neg t1, t1",
+    "and t6, t6, t1",
+    "add a2, a2, t6",
+    "li t6, 1000000000",
+    "divu a3, a2, t6",
+    "remu a4, a2, t6",
+    "sd a3, 0(a1)",
+    "sd a4, 8(a1)",
+    "li a0, 0",
+    "ret",
+    ".Lvdso_cgt_fallback:",
+    "li a7, {nr_clock_gettime}",
+    "ecall",
+    "ret",
+    ".globl starry_vdso_gettimeofday",
+    "starry_vdso_gettimeofday:",
+    "li t4, {data}",
+    "ld t5, 0(t4)",
+    "beqz t5, .Lvdso_gtod_fallback",
+    "beqz a0, .Lvdso_gtod_done",
+    "csrr t5, time",
+    "ld t6, 8(t4)",
+    "mul a2, t5, t6",
+    "mulhu a3, t5, t6",
+    "srli a2, a2, 32",
+    "slli a3, a3, 32",
+    "or a2, a2, a3",
+    "ld t6, 16(t4)",
+    "add a2, a2, t6",
+    "ld t6, 24(t4)",
+    "add a2, a2, t6",
+    "li t6, 1000000000",
+    "divu a3, a2, t6",
+    "remu a4, a2, t6",
+    "li t6, 1000",
+    "divu a4, a4, t6",
+    "sd a3, 0(a0)",
+    "sd a4, 8(a0)",
+    ".Lvdso_gtod_done:",
+    "li a0, 0",
+    "ret",
+    ".Lvdso_gtod_fallback:",
+    "li a7, {nr_gettimeofday}",
+    "ecall",
+    "ret",
+    ".globl starry_vdso_getcpu",
+    "starry_vdso_getcpu:",
+    "li a7, {nr_getcpu}",
+    "ecall",
+    "ret",
+    ".globl starry_vdso_end",
+    "starry_vdso_end:",
+    ".popsection",
+    data = const VDSO_DATA,
+    nr_clock_gettime = const linux_raw_sys::general::__NR_clock_gettime,
+    nr_gettimeofday = const linux_raw_sys::general::__NR_gettimeofday,
+    nr_getcpu = const linux_raw_sys::general::__NR_getcpu,
+);
+
+#[cfg(target_arch = "loongarch64")]
+core::arch::global_asm!(
+    ".pushsection .text.vdso, \"ax\"",
+    ".balign 16",
+    ".globl starry_vdso_start",
+    "starry_vdso_start:",
+    ".globl starry_vdso_clock_gettime",
+    "starry_vdso_clock_gettime:",
+    "li.d $t0, 8",
+    "bgeu $a0, $t0, .Lvdso_cgt_fallback",
+    "li.d $t1, 0x21",
+    "srl.d $t1, $t1, $a0",
+    "li.d $t2, 0xd2",
+    "srl.d $t2, $t2, $a0",
+    "or $t3, $t1, $t2",
+    "andi $t3, $t3, 1",
+    "beqz $t3, .Lvdso_cgt_fallback",
+    "li.d $t4, {data}",
+    "ld.d $t5, $t4, 0",
+    "beqz $t5, .Lvdso_cgt_fallback",
+    "rdtime.d $t5, $zero",
+    "ld.d $t6, $t4, 8",
+    "mul.d $a2, $t5, $t6",
+    "mulh.du $a3, $t5, $t6",
+    "srli.d $a2, $a2, 32",
+    "slli.d $a3, $a3, 32",
+    "or $a2, $a2, $a3",
+    "ld.d $t6, $t4, 16",
+    "add.d $a2, $a2, $t6",
+    "ld.d $t6, $t4, 24",
+    "andi $t1, $t1, 1",
+    "sub.d $t1, $zero, $t1",
+    "and $t6, $t6, $t1",
+    "add.d $a2, $a2, $t6",
+    "li.d $t6, 1000000000",
+    "div.du $a3, $a2, $t6",
+    "mod.du $a4, $a2, $t6",
+    "st.d $a3, $a1, 0",
+    "st.d $a4, $a1, 8",
+    "move $a0, $zero",
+    "jr $ra",
+    ".Lvdso_cgt_fallback:",
+    "li.d $a7, {nr_clock_gettime}",
+    "syscall 0",
+    "jr $ra",
+    ".globl starry_vdso_gettimeofday",
+    "starry_vdso_gettimeofday:",
+    "li.d $t4, {data}",
+    "ld.d $t5, $t4, 0",
+    "beqz $t5, .Lvdso_gtod_fallback",
+    "beqz $a0, .Lvdso_gtod_done",
+    "rdtime.d $t5, $zero",
+    "ld.d $t6, $t4, 8",
+    "mul.d $a2, $t5, $t6",
+    "mulh.du $a3, $t5, $t6",
+    "srli.d $a2, $a2, 32",
+    "slli.d $a3, $a3, 32",
+    "or $a2, $a2, $a3",
+    "ld.d $t6, $t4, 16",
+    "add.d $a2, $a2, $t6",
+    "ld.d $t6, $t4, 24",
+    "add.d $a2, $a2, $t6",
+    "li.d $t6, 1000000000",
+    "div.du $a3, $a2, $t6",
+    "mod.du $a4, $a2, $t6",
+    "li.d $t6, 1000",
+    "div.du $a4, $a4, $t6",
+    "st.d $a3, $a0, 0",
+    "st.d $a4, $a0, 8",
+    ".Lvdso_gtod_done:",
+    "move $a0, $zero",
+    "jr $ra",
+    ".Lvdso_gtod_fallback:",
+    "li.d $a7, {nr_gettimeofday}",
+    "syscall 0",
+    "jr $ra",
+    ".globl starry_vdso_getcpu",
+    "starry_vdso_getcpu:",
+    "li.d $a7, {nr_getcpu}",
+    "syscall 0",
+    "jr $ra",
+    ".globl starry_vdso_end",
+    "starry_vdso_end:",
+    ".popsection",
+    data = const VDSO_DATA,
+    nr_clock_gettime = const linux_raw_sys::general::__NR_clock_gettime,
+    nr_gettimeofday = const linux_raw_sys::general::__NR_gettimeofday,
+    nr_getcpu = const linux_raw_sys::general::__NR_getcpu,
+);
+
+#[cfg(target_arch = "x86_64")]
+core::arch::global_asm!(
+    ".pushsection .text.vdso, \"ax\"",
+    ".balign 16",
+    ".globl starry_vdso_start",
+    "starry_vdso_start:",
+    ".globl starry_vdso_clock_gettime",
+    "starry_vdso_clock_gettime:",
+    "cmp edi, 8",
+    "jae .Lvdso_cgt_fallback",
+    "mov ecx, edi",
+    "mov r8d, 0x21",
+    "shr r8d, cl",
+    "mov r9d, 0xd2",
+    "shr r9d, cl",
+    "mov eax, r8d",
+    "or eax, r9d",
+    "test eax, 1",
+    "jz .Lvdso_cgt_fallback",
+    "mov r10, {data}",
+    "cmp qword ptr [r10], 0",
+    "je .Lvdso_cgt_fallback",
+    "lfence",
+    "rdtsc",
+    "shl rdx, 32",
+    "or rax, rdx",
+    "mul qword ptr [r10 + 8]",
+    "shrd rax, rdx, 32",
+    "add rax, [r10 + 16]",
+    "and r8d, 1",
+    "neg r8",
+    "and r8, [r10 + 24]",
+    "add rax, r8",
+    "xor edx, edx",
+    "mov ecx, 1000000000",
+    "div rcx",
+    "mov [rsi], rax",
+    "mov [rsi + 8], rdx",
+    "xor eax, eax",
+    "ret",
+    ".Lvdso_cgt_fallback:",
+    "mov eax, {nr_clock_gettime}",
+    "syscall",
+    "ret",
+    ".globl starry_vdso_gettimeofday",
+    "starry_vdso_gettimeofday:",
+    "mov r10, {data}",
+    "cmp qword ptr [r10], 0",
+    "je .Lvdso_gtod_fallback",
+    "test rdi, rdi",
+    "jz .Lvdso_gtod_done",
+    "lfence",
+    "rdtsc",
+    "shl rdx, 32",
+    "or rax, rdx",
+    "mul qword ptr [r10 + 8]",
+    "shrd rax, rdx, 32",
+    "add rax, [r10 + 16]",
+    "add rax, [r10 + 24]",
+    "xor edx, edx",
+    "mov ecx, 1000000000",
+    "div rcx",
+    "mov [rdi], rax",
+    "mov rax, rdx",
+    "xor edx, edx",
+    "mov ecx, 1000",
+    "div rcx",
+    "mov [rdi + 8], rax",
+    ".Lvdso_gtod_done:",
+    "xor eax, eax",
+    "ret",
+    ".Lvdso_gtod_fallback:",
+    "mov eax, {nr_gettimeofday}",
+    "syscall",
+    "ret",
+    ".globl starry_vdso_getcpu",
+    "starry_vdso_getcpu:",
+    "mov eax, {nr_getcpu}",
+    "syscall",
+    "ret",
+    ".globl starry_vdso_end",
+    "starry_vdso_end:",
+    ".popsection",
+    data = const VDSO_DATA,
+    nr_clock_gettime = const linux_raw_sys::general::__NR_clock_gettime,
+    nr_gettimeofday = const linux_raw_sys::general::__NR_gettimeofday,
+    nr_getcpu = const linux_raw_sys::general::__NR_getcpu,
+);
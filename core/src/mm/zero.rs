@@ -0,0 +1,134 @@
+//! Zero-page sharing for private anonymous memory.
+//!
+//! A read fault in private anonymous memory maps a single page of zeros
+//! read-only instead of allocating a frame, and the first write replaces it
+//! with a private page as usual. Such pages are linear areas of their own,
+//! whose frame is never freed, so anything that could make them writable has
+//! to turn them back into plain anonymous pages with [`ZeroPages::strip`]
+//! first.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use axerrno::LinuxResult;
+use axhal::{
+    mem::virt_to_phys,
+    paging::{MappingFlags, PageSize},
+};
+use axmm::{AddrSpace, backend::Backend};
+use axsync::Mutex;
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
+
+use super::commit::remove_range;
+
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE_4K]);
+
+static ZERO_PAGE: Page = Page([0; PAGE_SIZE_4K]);
+
+/// Returns the physical address of the zero page.
+pub fn zero_page() -> PhysAddr {
+    virt_to_phys((&ZERO_PAGE as *const Page as usize).into())
+}
+
+/// The private anonymous memory of an address space, and which of its pages
+/// are mapped to the zero page.
+#[derive(Default)]
+pub struct ZeroPages {
+    /// Page-aligned `start -> end` ranges of private anonymous memory.
+    anon: Mutex<BTreeMap<usize, usize>>,
+    /// Pages mapped to the zero page, with the flags of their mapping.
+    pages: Mutex<BTreeMap<usize, MappingFlags>>,
+}
+
+impl ZeroPages {
+    /// Records a new private anonymous mapping of `[start, start + len)`.
+    pub fn add_anon(&self, start: usize, len: usize) {
+        self.anon.lock().insert(start, start + len);
+    }
+
+    /// Forgets the range `[start, start + len)` once it has been unmapped,
+    /// along with the zero pages in it.
+    pub fn remove(&self, start: usize, len: usize) {
+        let end = start + len;
+        remove_range(&mut self.anon.lock(), start, end);
+        let mut pages = self.pages.lock();
+        let mut removed = pages.split_off(&start);
+        pages.append(&mut removed.split_off(&end));
+    }
+
+    /// Forgets everything, e.g. when the address space is replaced by
+    /// `execve`.
+    pub fn clear(&self) {
+        self.anon.lock().clear();
+        self.pages.lock().clear();
+    }
+
+    /// Copies the records of `parent` for a forked address space, which maps
+    /// the same zero pages.
+    pub fn inherit(&self, parent: &ZeroPages) {
+        *self.anon.lock() = parent.anon.lock().clone();
+        *self.pages.lock() = parent.pages.lock().clone();
+    }
+
+    fn is_anon(&self, addr: usize) -> bool {
+        self.anon
+            .lock()
+            .range(..=addr)
+            .next_back()
+            .is_some_and(|(_, end)| addr < *end)
+    }
+
+    /// Resolves a read fault at `vaddr` by mapping the zero page, if it is in
+    /// private anonymous memory that has not been populated yet.
+    ///
+    /// Returns whether the fault is resolved.
+    pub fn map(&self, aspace: &mut AddrSpace, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        let page = vaddr.align_down_4k();
+        if access_flags.contains(MappingFlags::WRITE)
+            || !self.is_anon(page.as_usize())
+            || aspace.page_table().query(page).is_ok()
+        {
+            return false;
+        }
+        let Some(flags) = aspace.find_area(page).map(|area| area.flags()) else {
+            return false;
+        };
+        if !flags.contains(access_flags) || aspace.unmap(page, PAGE_SIZE_4K).is_err() {
+            return false;
+        }
+        let zero_flags = flags - MappingFlags::WRITE;
+        if aspace
+            .map_linear(page, zero_page(), PAGE_SIZE_4K, zero_flags)
+            .is_err()
+        {
+            let _ = map_anon(aspace, page, flags);
+            return false;
+        }
+        self.pages.lock().insert(page.as_usize(), flags);
+        true
+    }
+
+    /// Turns the zero pages in `[start, start + len)` back into anonymous
+    /// pages that are not populated yet, before the range is written to or
+    /// its protection changes.
+    pub fn strip(&self, aspace: &mut AddrSpace, start: VirtAddr, len: usize) -> LinuxResult<()> {
+        let mut pages = self.pages.lock();
+        let stripped = pages
+            .range(start.as_usize()..start.as_usize() + len)
+            .map(|(page, flags)| (*page, *flags))
+            .collect::<Vec<_>>();
+        for (page, flags) in stripped {
+            pages.remove(&page);
+            let page = VirtAddr::from(page);
+            aspace.unmap(page, PAGE_SIZE_4K)?;
+            map_anon(aspace, page, flags)?;
+        }
+        Ok(())
+    }
+}
+
+fn map_anon(aspace: &mut AddrSpace, page: VirtAddr, flags: MappingFlags) -> LinuxResult<()> {
+    let backend = Backend::new_alloc(page, PageSize::Size4K);
+    aspace.map(page, PAGE_SIZE_4K, flags, false, backend)?;
+    Ok(())
+}
@@ -1,14 +1,25 @@
-//! Resource limits.
+//! Resource limits and usage.
 
-use core::ops::{Index, IndexMut};
+use alloc::{format, string::String};
+use core::{
+    ops::{Index, IndexMut},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use linux_raw_sys::general::{RLIM_NLIMITS, RLIMIT_NOFILE, RLIMIT_STACK};
+use axhal::time::TimeValue;
+use linux_raw_sys::general::{
+    __kernel_old_timeval, RLIM_NLIMITS, RLIMIT_CORE, RLIMIT_NOFILE, RLIMIT_STACK, rusage,
+};
+use memory_addr::PAGE_SIZE_4K;
 
 /// The maximum number of open files
 pub const AX_FILE_LIMIT: usize = 1024;
 
+/// The value of an unlimited resource
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
 /// The limit for a specific resource
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Rlimit {
     /// The current limit for the resource (soft)
     pub current: u64,
@@ -36,13 +47,15 @@ impl From<u64> for Rlimit {
 }
 
 /// Process resource limits
+#[derive(Clone)]
 pub struct Rlimits([Rlimit; RLIM_NLIMITS as usize]);
 
 impl Default for Rlimits {
     fn default() -> Self {
-        let mut result = Self(Default::default());
+        let mut result = Self(core::array::from_fn(|_| RLIM_INFINITY.into()));
         result[RLIMIT_STACK] = (crate::config::USER_STACK_SIZE as u64).into();
         result[RLIMIT_NOFILE] = (AX_FILE_LIMIT as u64).into();
+        result[RLIMIT_CORE] = Rlimit::new(0, RLIM_INFINITY);
         result
     }
 }
@@ -60,3 +73,103 @@ impl IndexMut<u32> for Rlimits {
         &mut self.0[index as usize]
     }
 }
+
+/// Resource usage of a thread, a process or a set of processes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ResourceUsage {
+    /// Time spent in user mode
+    pub utime: TimeValue,
+    /// Time spent in kernel mode
+    pub stime: TimeValue,
+    /// Peak resident set size in pages
+    pub maxrss: usize,
+    /// Page faults serviced without I/O
+    pub minflt: u64,
+    /// Page faults that required I/O
+    pub majflt: u64,
+}
+
+impl ResourceUsage {
+    /// Combines two usages into one.
+    ///
+    /// Times and fault counts add up, while the peak resident set size is the
+    /// larger of the two.
+    pub fn collate(mut self, other: ResourceUsage) -> Self {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.maxrss = self.maxrss.max(other.maxrss);
+        self.minflt += other.minflt;
+        self.majflt += other.majflt;
+        self
+    }
+}
+
+fn to_timeval(tv: TimeValue) -> __kernel_old_timeval {
+    __kernel_old_timeval {
+        tv_sec: tv.as_secs() as _,
+        tv_usec: tv.subsec_micros() as _,
+    }
+}
+
+impl From<ResourceUsage> for rusage {
+    fn from(value: ResourceUsage) -> Self {
+        // FIXME: Zeroable
+        let mut usage: rusage = unsafe { core::mem::zeroed() };
+        usage.ru_utime = to_timeval(value.utime);
+        usage.ru_stime = to_timeval(value.stime);
+        usage.ru_maxrss = (value.maxrss * PAGE_SIZE_4K / 1024) as _;
+        usage.ru_minflt = value.minflt as _;
+        usage.ru_majflt = value.majflt as _;
+        usage
+    }
+}
+
+/// The I/O counters of a process, shown in `/proc/[pid]/io`.
+#[derive(Default)]
+pub struct IoAccounting {
+    rchar: AtomicU64,
+    wchar: AtomicU64,
+    syscr: AtomicU64,
+    syscw: AtomicU64,
+    read_bytes: AtomicU64,
+    write_bytes: AtomicU64,
+}
+
+impl IoAccounting {
+    /// Accounts a read syscall that returned `bytes`.
+    pub fn add_read(&self, bytes: usize) {
+        self.syscr.fetch_add(1, Ordering::Relaxed);
+        self.rchar.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accounts a write syscall that returned `bytes`.
+    pub fn add_write(&self, bytes: usize) {
+        self.syscw.fetch_add(1, Ordering::Relaxed);
+        self.wchar.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accounts `bytes` read from a file on a filesystem.
+    pub fn add_read_bytes(&self, bytes: usize) {
+        self.read_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Accounts `bytes` written to a file on a filesystem.
+    pub fn add_write_bytes(&self, bytes: usize) {
+        self.write_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Formats the counters in the format of `/proc/[pid]/io`.
+    pub fn render(&self) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        format!(
+            "rchar: {}\nwchar: {}\nsyscr: {}\nsyscw: {}\nread_bytes: {}\nwrite_bytes: \
+             {}\ncancelled_write_bytes: 0\n",
+            get(&self.rchar),
+            get(&self.wchar),
+            get(&self.syscr),
+            get(&self.syscw),
+            get(&self.read_bytes),
+            get(&self.write_bytes),
+        )
+    }
+}
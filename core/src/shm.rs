@@ -1,6 +1,10 @@
 //! Shared memory management.
 
-use alloc::{collections::btree_map::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 
 use axerrno::{LinuxError, LinuxResult};
 use axhal::{paging::MappingFlags, time::monotonic_time_nanos};
@@ -135,6 +139,11 @@ impl ShmInner {
         self.phys_pages = Some(phys_pages);
     }
 
+    /// Returns the key the segment was created with.
+    pub fn key(&self) -> i32 {
+        self.shmid_ds.shm_perm.key
+    }
+
     /// Returns the number of processes currently attached to this shared memory
     /// segment.
     pub fn attach_count(&self) -> usize {
@@ -369,3 +378,24 @@ impl ShmManager {
 
 /// Global shared memory manager.
 pub static SHM_MANAGER: Mutex<ShmManager> = Mutex::new(ShmManager::new());
+
+/// Shared anonymous memory still alive, with its size in bytes.
+static SHARED_PAGES: Mutex<Vec<(Weak<SharedPages>, usize)>> = Mutex::new(Vec::new());
+
+/// Accounts `pages` of `size` bytes in [`shared_memory_usage`] until they are
+/// freed.
+pub fn track_shared_pages(pages: &Arc<SharedPages>, size: usize) {
+    let mut shared = SHARED_PAGES.lock();
+    // Drop the pages freed since, which would otherwise pile up as long as
+    // nothing asks for the usage.
+    shared.retain(|(pages, _)| pages.strong_count() > 0);
+    shared.push((Arc::downgrade(pages), size));
+}
+
+/// Returns the number of bytes used by shared anonymous memory, both
+/// `MAP_SHARED | MAP_ANONYMOUS` mappings and System V segments.
+pub fn shared_memory_usage() -> usize {
+    let mut pages = SHARED_PAGES.lock();
+    pages.retain(|(pages, _)| pages.strong_count() > 0);
+    pages.iter().map(|(_, size)| size).sum()
+}
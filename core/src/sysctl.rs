@@ -0,0 +1,178 @@
+//! The sysctl registry.
+//!
+//! Subsystems register their knobs here under dotted names like
+//! `kernel.pid_max`, which `/proc/sys` shows as `/proc/sys/kernel/pid_max`.
+//! A knob is either kept by its subsystem, which reads and writes it through
+//! the functions it registered, or stored in the registry itself, for knobs
+//! that only have to be remembered. Both kinds can be read and written from
+//! kernel code with [`read`] and [`write`] as well.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{fmt::Display, str::FromStr};
+
+use axerrno::{LinuxError, LinuxResult};
+use kspin::SpinNoIrq;
+use spin::RwLock;
+
+use crate::{
+    mm::{
+        aslr, fork_stress, overcommit_policy, overcommit_ratio, set_fork_stress,
+        set_overcommit_policy, set_overcommit_ratio,
+    },
+    resources::AX_FILE_LIMIT,
+    task::{pid_max, set_pid_max, set_zombie_max, zombie_max},
+};
+
+type ReadFn = Box<dyn Fn() -> String + Send + Sync>;
+type WriteFn = Box<dyn Fn(&str) -> LinuxResult<()> + Send + Sync>;
+
+struct Knob {
+    read: ReadFn,
+    /// Absent for read-only knobs.
+    write: Option<WriteFn>,
+}
+
+static KNOBS: RwLock<BTreeMap<String, Knob>> = RwLock::new(BTreeMap::new());
+
+fn insert(name: &str, read: ReadFn, write: Option<WriteFn>) {
+    if KNOBS
+        .write()
+        .insert(name.to_string(), Knob { read, write })
+        .is_some()
+    {
+        warn!("sysctl {} registered twice", name);
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> LinuxResult<T> {
+    value.trim().parse().map_err(|_| LinuxError::EINVAL)
+}
+
+/// Registers the read-only knob `name`, whose value is given by `read`.
+pub fn register(name: &str, read: impl Fn() -> String + Send + Sync + 'static) {
+    insert(name, Box::new(read), None);
+}
+
+/// Registers the knob `name`, whose value is given by `read` and set with
+/// `write`.
+pub fn register_writable(
+    name: &str,
+    read: impl Fn() -> String + Send + Sync + 'static,
+    write: impl Fn(&str) -> LinuxResult<()> + Send + Sync + 'static,
+) {
+    insert(name, Box::new(read), Some(Box::new(write)));
+}
+
+/// Registers the knob `name` holding a number kept by its subsystem, which
+/// `set` may refuse.
+pub fn register_int<T>(name: &str, get: fn() -> T, set: fn(T) -> LinuxResult<()>)
+where
+    T: Display + FromStr + 'static,
+{
+    register_writable(
+        name,
+        move || get().to_string(),
+        move |value| set(parse(value)?),
+    );
+}
+
+/// Registers the knob `name` storing a string of at most `max_len` bytes.
+/// Written values lose their final newline.
+pub fn register_string_value(name: &str, initial: &str, max_len: usize) {
+    let value = Arc::new(SpinNoIrq::new(initial.to_string()));
+    let stored = value.clone();
+    register_writable(
+        name,
+        move || stored.lock().clone(),
+        move |new| {
+            let new = new.strip_suffix('\n').unwrap_or(new);
+            if new.len() > max_len {
+                return Err(LinuxError::EINVAL);
+            }
+            *value.lock() = new.to_string();
+            Ok(())
+        },
+    );
+}
+
+/// Returns whether the knob `name` exists.
+pub fn exists(name: &str) -> bool {
+    KNOBS.read().contains_key(name)
+}
+
+/// Reads the knob `name`.
+pub fn read(name: &str) -> LinuxResult<String> {
+    let knobs = KNOBS.read();
+    let knob = knobs.get(name).ok_or(LinuxError::ENOENT)?;
+    Ok((knob.read)())
+}
+
+/// Writes `value` to the knob `name`.
+pub fn write(name: &str, value: &str) -> LinuxResult<()> {
+    let knobs = KNOBS.read();
+    let knob = knobs.get(name).ok_or(LinuxError::ENOENT)?;
+    let write = knob.write.as_ref().ok_or(LinuxError::EPERM)?;
+    write(value)
+}
+
+/// Returns the names of the knobs and groups directly under `prefix`, or at
+/// the top level if it is empty.
+pub fn children(prefix: &str) -> Vec<String> {
+    let prefix = if prefix.is_empty() {
+        String::new()
+    } else {
+        format!("{prefix}.")
+    };
+    KNOBS
+        .read()
+        .keys()
+        .filter_map(|name| name.strip_prefix(prefix.as_str()))
+        .map(|rest| rest.split('.').next().unwrap_or(rest).to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Registers the knobs of the core subsystems.
+pub fn init() {
+    // There is no system-wide limit on open files, only the per-process one,
+    // which cannot be changed.
+    register("fs.file-max", || AX_FILE_LIMIT.to_string());
+    register_int("kernel.pid_max", pid_max, set_pid_max);
+    register_int("kernel.zombie_max", zombie_max, |max| {
+        set_zombie_max(max);
+        Ok(())
+    });
+    register_int(
+        "kernel.randomize_va_space",
+        aslr::randomize_va_space,
+        aslr::set_randomize_va_space,
+    );
+    register_int(
+        "vm.overcommit_memory",
+        || overcommit_policy() as u8,
+        |policy| {
+            set_overcommit_policy(policy.try_into().map_err(|_| LinuxError::EINVAL)?);
+            Ok(())
+        },
+    );
+    register_int("vm.overcommit_ratio", overcommit_ratio, |ratio| {
+        set_overcommit_ratio(ratio);
+        Ok(())
+    });
+    register_int(
+        "vm.fork_stress",
+        || fork_stress() as u8,
+        |enabled| {
+            set_fork_stress(enabled != 0);
+            Ok(())
+        },
+    );
+}
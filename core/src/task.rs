@@ -1,9 +1,15 @@
 //! User task management.
 
+mod fs;
+mod offload;
+mod pid_ns;
+mod sched;
 mod stat;
+mod wchan;
 
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     string::String,
     sync::{Arc, Weak},
     vec::Vec,
@@ -11,30 +17,45 @@ use alloc::{
 use core::{
     cell::RefCell,
     ops::Deref,
-    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicI32, AtomicU32, AtomicU64, AtomicUsize, Ordering},
 };
 
 use axerrno::{LinuxError, LinuxResult};
+use axfs_ng_vfs::Location;
+use axhal::paging::MappingFlags;
 use axio::PollSet;
 use axmm::AddrSpace;
-use axsync::{Mutex, spin::SpinNoIrq};
+use axsync::{Mutex, MutexGuard, spin::SpinNoIrq};
 use axtask::{AxTaskRef, TaskExt, TaskInner, WeakAxTaskRef, current};
 use extern_trait::extern_trait;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use lazy_static::lazy_static;
+use linux_raw_sys::general::{CLD_CONTINUED, CLD_STOPPED, SA_NOCLDSTOP, SA_NOCLDWAIT};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, VirtAddr};
 use scope_local::{ActiveScope, Scope};
 use spin::RwLock;
 use starry_process::{Pid, Process, ProcessGroup, Session};
 use starry_signal::{
-    SignalInfo, Signo,
+    SignalDisposition, SignalInfo, SignalSet, Signo,
     api::{ProcessSignalManager, SignalActions, ThreadSignalManager},
 };
 use weak_map::WeakMap;
 
-pub use self::stat::TaskStat;
+pub use self::{
+    fs::{FsState, fs_context},
+    offload::{Cancellation, offload},
+    pid_ns::{PidNamespace, current_pid_ns, pid_max, release_pid, set_pid_max},
+    sched::{
+        SchedParams, SchedPolicy, allowed_cpus, defer_idle_work, deterministic_seed,
+        set_cpu_capacity, spawn_background, spawn_idle_worker, start_cpus,
+    },
+    stat::TaskStat,
+    wchan::{WaitChannel, WaitGuard, wait_on},
+};
 use crate::{
     futex::{FutexKey, FutexTable},
-    resources::Rlimits,
+    mm::{CommitMap, UserPins, ZeroPages, resolve_page_fault},
+    resources::{IoAccounting, ResourceUsage, Rlimits},
     time::{TimeManager, TimerState},
 };
 
@@ -57,6 +78,9 @@ pub struct ThreadInner {
     /// The process data shared by all threads in the process.
     pub proc_data: Arc<ProcessData>,
 
+    /// The ID of the thread.
+    tid: Pid,
+
     /// The clear thread tid field
     ///
     /// See <https://manpages.debian.org/unstable/manpages-dev/set_tid_address.2.en.html#clear_child_tid>
@@ -80,6 +104,30 @@ pub struct ThreadInner {
     /// The OOM score adjustment value.
     oom_score_adj: AtomicI32,
 
+    /// The address of the last unresolved page fault, used for diagnostics.
+    fault_addr: AtomicUsize,
+
+    /// Whether the thread is accessing user memory, where page faults are
+    /// expected. See [`access_user_memory`](crate::mm::access_user_memory).
+    pub(crate) accessing_user_memory: AtomicBool,
+
+    /// The number of page faults resolved for this thread.
+    minflt: AtomicU64,
+
+    /// The scheduling policy and priority.
+    sched: SpinNoIrq<SchedParams>,
+
+    /// What the thread waits for in a blocking syscall.
+    wait_channel: SpinNoIrq<Option<WaitChannel>>,
+
+    /// Whether the thread treats every file as nonblocking, for the syscall
+    /// it is in.
+    nowait: AtomicBool,
+
+    /// The filesystem information of the thread, if it does not share that
+    /// of its process, see [`ThreadInner::fs`].
+    fs: SpinNoIrq<Option<Arc<FsState>>>,
+
     /// Ready to exit
     exit: AtomicBool,
 }
@@ -87,13 +135,22 @@ pub struct ThreadInner {
 impl ThreadInner {
     /// Create a new [`ThreadInner`].
     pub fn new(tid: u32, proc_data: Arc<ProcessData>) -> Self {
+        THREAD_COUNT.fetch_add(1, Ordering::Relaxed);
         ThreadInner {
             signal: ThreadSignalManager::new(tid, proc_data.signal.clone()),
             proc_data,
+            tid,
             clear_child_tid: AtomicUsize::new(0),
             robust_list_head: AtomicUsize::new(0),
             time: AssumeSync(RefCell::new(TimeManager::new())),
             oom_score_adj: AtomicI32::new(200),
+            fault_addr: AtomicUsize::new(0),
+            accessing_user_memory: AtomicBool::new(false),
+            minflt: AtomicU64::new(0),
+            sched: SpinNoIrq::new(SchedParams::default()),
+            wait_channel: SpinNoIrq::new(None),
+            nowait: AtomicBool::new(false),
+            fs: SpinNoIrq::new(None),
             exit: AtomicBool::new(false),
         }
     }
@@ -130,6 +187,94 @@ impl ThreadInner {
         self.oom_score_adj.store(value, Ordering::SeqCst);
     }
 
+    /// Get the resource usage of this thread.
+    pub fn usage(&self) -> ResourceUsage {
+        let (utime, stime) = self.time.borrow().output();
+        ResourceUsage {
+            utime,
+            stime,
+            maxrss: self.proc_data.max_rss(),
+            minflt: self.minflt.load(Ordering::Relaxed),
+            majflt: 0,
+        }
+    }
+
+    /// Accounts a page fault that has been resolved.
+    pub fn count_page_fault(&self) {
+        self.minflt.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Get the address of the last unresolved page fault.
+    pub fn fault_addr(&self) -> usize {
+        self.fault_addr.load(Ordering::Relaxed)
+    }
+
+    /// Record the address of an unresolved page fault.
+    pub fn set_fault_addr(&self, addr: usize) {
+        self.fault_addr.store(addr, Ordering::Relaxed);
+    }
+
+    /// Get the scheduling parameters.
+    pub fn sched(&self) -> SchedParams {
+        *self.sched.lock()
+    }
+
+    /// Set the scheduling parameters.
+    pub fn set_sched(&self, params: SchedParams) {
+        *self.sched.lock() = params;
+    }
+
+    /// Get what the thread waits for, if it is in a blocking syscall.
+    ///
+    /// This is set for the whole syscall, so the thread is only actually
+    /// waiting if it is also blocked.
+    pub fn wait_channel(&self) -> Option<WaitChannel> {
+        *self.wait_channel.lock()
+    }
+
+    /// Set what the thread waits for, returning the previous value.
+    pub(crate) fn set_wait_channel(&self, chan: Option<WaitChannel>) -> Option<WaitChannel> {
+        core::mem::replace(&mut *self.wait_channel.lock(), chan)
+    }
+
+    /// Whether the thread treats every file as nonblocking.
+    pub fn nowait(&self) -> bool {
+        self.nowait.load(Ordering::Acquire)
+    }
+
+    /// Set whether the thread treats every file as nonblocking, returning
+    /// the previous value.
+    pub fn set_nowait(&self, nowait: bool) -> bool {
+        self.nowait.swap(nowait, Ordering::AcqRel)
+    }
+
+    /// Get the filesystem information of the thread.
+    ///
+    /// This is that of its process, unless the thread was created without
+    /// `CLONE_FS` or has called `unshare(CLONE_FS)`, which gives it its own
+    /// working directory, root and umask.
+    pub fn fs(&self) -> Arc<FsState> {
+        match &*self.fs.lock() {
+            Some(fs) => fs.clone(),
+            None => self.proc_data.fs.clone(),
+        }
+    }
+
+    /// Gives the thread filesystem information of its own.
+    pub fn set_fs(&self, fs: Arc<FsState>) {
+        *self.fs.lock() = Some(fs);
+    }
+
+    /// Get the umask.
+    pub fn umask(&self) -> u32 {
+        self.fs().umask()
+    }
+
+    /// Set the umask and return the old value.
+    pub fn replace_umask(&self, umask: u32) -> u32 {
+        self.fs().replace_umask(umask)
+    }
+
     /// Check if the thread is ready to exit.
     pub fn pending_exit(&self) -> bool {
         self.exit.load(Ordering::Acquire)
@@ -141,6 +286,27 @@ impl ThreadInner {
     }
 }
 
+impl Drop for ThreadInner {
+    fn drop(&mut self) {
+        THREAD_COUNT.fetch_sub(1, Ordering::Relaxed);
+        // The ID of the leader is the ID of the process, which is released
+        // when the process is reaped. The other IDs can go once the task
+        // cannot be looked up anymore.
+        if self.tid != self.proc_data.proc.pid() {
+            release_pid(self.tid);
+        }
+    }
+}
+
+/// The number of threads whose tasks have not been dropped yet.
+static THREAD_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of threads, counting the ones that have exited until
+/// their tasks are dropped.
+pub fn thread_count() -> usize {
+    THREAD_COUNT.load(Ordering::Relaxed)
+}
+
 /// Extended thread data for the monolithic kernel.
 pub struct Thread(Box<ThreadInner>);
 
@@ -196,8 +362,13 @@ pub struct ProcessData {
     pub proc: Arc<Process>,
     /// The executable path
     pub exe_path: RwLock<String>,
+    /// The executable file, which `/proc/[pid]/exe` leads to even after it is
+    /// unlinked
+    pub exe: RwLock<Option<Location>>,
     /// The command line arguments
     pub cmdline: RwLock<Arc<Vec<String>>>,
+    /// The environment given to `execve`
+    pub environ: RwLock<Arc<Vec<String>>>,
     /// The virtual memory address space.
     // TODO: scopify
     pub aspace: Arc<Mutex<AddrSpace>>,
@@ -207,6 +378,11 @@ pub struct ProcessData {
     heap_bottom: AtomicUsize,
     /// The user heap top
     heap_top: AtomicUsize,
+    /// Where the search for `mmap` addresses without a hint starts
+    mmap_base: AtomicUsize,
+    /// Whether the low 2 GiB of the address space are kept for executable
+    /// mappings, see [`ProcessData::low_code`].
+    low_code: AtomicBool,
 
     /// The resource limits
     pub rlim: RwLock<Rlimits>,
@@ -224,8 +400,56 @@ pub struct ProcessData {
     /// The futex table.
     futex_table: Arc<FutexTable>,
 
-    /// The default mask for file permissions.
-    umask: AtomicU32,
+    /// The filesystem information, shared with `CLONE_FS`. A thread may have
+    /// its own instead, see [`ThreadInner::fs`].
+    pub fs: Arc<FsState>,
+    /// The memory committed in the address space, see [`CommitMap`], shared
+    /// along with it.
+    pub commit: RwLock<Arc<CommitMap>>,
+    /// The anonymous memory of the address space mapped to the zero page,
+    /// shared along with the address space.
+    pub zero_pages: RwLock<Arc<ZeroPages>>,
+    /// The user memory pinned by transfers in progress, shared along with the
+    /// address space.
+    pub pins: RwLock<Arc<UserPins>>,
+    /// Names of shared anonymous mappings by start address, shown in
+    /// `/proc/[pid]/maps`.
+    pub mapping_names: Mutex<BTreeMap<usize, String>>,
+    /// The PID namespace of the process.
+    pub pid_ns: RwLock<Arc<PidNamespace>>,
+    /// The PID namespace children are created in, which differs from
+    /// [`ProcessData::pid_ns`] after `unshare(CLONE_NEWPID)`.
+    pub children_pid_ns: RwLock<Arc<PidNamespace>>,
+
+    /// Pages mapped in the address space of the process, i.e. its resident
+    /// set.
+    rss: AtomicUsize,
+    /// The peak of [`ProcessData::rss`].
+    max_rss: AtomicUsize,
+    /// The I/O counters of all threads of the process.
+    pub io: IoAccounting,
+    /// Resource usage of the threads that have exited.
+    exited_usage: Mutex<ResourceUsage>,
+    /// Resource usage of the children that have been reaped, including that
+    /// of their own reaped children.
+    children_usage: Mutex<ResourceUsage>,
+    /// Resource usage of zombie children, kept until they are reaped.
+    zombie_usage: Mutex<HashMap<Pid, ResourceUsage>>,
+    /// Children inherited from exited processes.
+    adopted: Mutex<HashSet<Pid>>,
+    /// Held while reaping a zombie child, so that `wait` and the orphan
+    /// reaper never free the same child twice.
+    reap_lock: Mutex<()>,
+
+    /// Whether the process is stopped by a job control signal.
+    stopped: AtomicBool,
+    /// The job control state change not yet reported to the parent.
+    job_event: SpinNoIrq<Option<JobEvent>>,
+    /// Event triggered when the process leaves the stopped state.
+    pub cont_event: Arc<PollSet>,
+    /// The `sa_flags` the `SIGCHLD` action was last set with, for
+    /// `SA_NOCLDSTOP` and `SA_NOCLDWAIT`.
+    sigchld_flags: AtomicU32,
 }
 
 impl ProcessData {
@@ -237,15 +461,20 @@ impl ProcessData {
         aspace: Arc<Mutex<AddrSpace>>,
         signal_actions: Arc<SpinNoIrq<SignalActions>>,
         exit_signal: Option<Signo>,
+        fs: Arc<FsState>,
     ) -> Arc<Self> {
         Arc::new(Self {
             proc,
             exe_path: RwLock::new(exe_path),
+            exe: RwLock::new(None),
             cmdline: RwLock::new(cmdline),
+            environ: RwLock::default(),
             aspace,
             scope: RwLock::new(Scope::new()),
             heap_bottom: AtomicUsize::new(crate::config::USER_HEAP_BASE),
             heap_top: AtomicUsize::new(crate::config::USER_HEAP_BASE),
+            mmap_base: AtomicUsize::new(crate::config::USER_SPACE_BASE),
+            low_code: AtomicBool::new(false),
 
             rlim: RwLock::default(),
 
@@ -260,7 +489,27 @@ impl ProcessData {
 
             futex_table: Arc::new(FutexTable::new()),
 
-            umask: AtomicU32::new(0o022),
+            fs,
+            commit: RwLock::default(),
+            zero_pages: RwLock::default(),
+            pins: RwLock::default(),
+            mapping_names: Mutex::new(BTreeMap::new()),
+            pid_ns: RwLock::new(PidNamespace::root()),
+            children_pid_ns: RwLock::new(PidNamespace::root()),
+
+            rss: AtomicUsize::new(0),
+            max_rss: AtomicUsize::new(0),
+            io: IoAccounting::default(),
+            exited_usage: Mutex::new(ResourceUsage::default()),
+            children_usage: Mutex::new(ResourceUsage::default()),
+            zombie_usage: Mutex::new(HashMap::new()),
+            adopted: Mutex::new(HashSet::new()),
+            reap_lock: Mutex::new(()),
+
+            stopped: AtomicBool::new(false),
+            job_event: SpinNoIrq::new(None),
+            cont_event: Arc::default(),
+            sigchld_flags: AtomicU32::new(0),
         })
     }
 
@@ -284,6 +533,54 @@ impl ProcessData {
         self.heap_top.store(top, Ordering::Release)
     }
 
+    /// Get the address the search for `mmap` addresses starts at.
+    pub fn get_mmap_base(&self) -> usize {
+        self.mmap_base.load(Ordering::Acquire)
+    }
+
+    /// Set the address the search for `mmap` addresses starts at.
+    pub fn set_mmap_base(&self, base: usize) {
+        self.mmap_base.store(base, Ordering::Release)
+    }
+
+    /// Whether `mmap` places executable mappings in the low 2 GiB of the
+    /// address space, and other mappings above, so that code generated at
+    /// run time stays within reach of relative calls.
+    pub fn low_code(&self) -> bool {
+        self.low_code.load(Ordering::Acquire)
+    }
+
+    /// Set whether the low 2 GiB of the address space are kept for code.
+    pub fn set_low_code(&self, low_code: bool) {
+        self.low_code.store(low_code, Ordering::Release)
+    }
+
+    /// Releases what the process holds once its last thread has exited.
+    ///
+    /// The data itself lives on while the process is a zombie, or while
+    /// `/proc` or a pidfd refers to it, but it has no use for any of this.
+    /// With the `track` feature, this also checks that no thread was left
+    /// waiting on a private futex.
+    pub fn teardown(&self) {
+        let _waiters = self.futex_table.clear();
+        #[cfg(feature = "track")]
+        assert_eq!(_waiters, 0, "{:?} exited with futex waiters", self.proc);
+
+        self.exe.write().take();
+        *self.cmdline.write() = Arc::default();
+        *self.environ.write() = Arc::default();
+        self.mapping_names.lock().clear();
+        self.adopted.lock().clear();
+    }
+
+    /// Forgets the names of the mappings starting from `start` to
+    /// `start + len`, once that range has been unmapped.
+    pub fn remove_mapping_names(&self, start: usize, len: usize) {
+        let mut names = self.mapping_names.lock();
+        let mut removed = names.split_off(&start);
+        names.append(&mut removed.split_off(&(start + len)));
+    }
+
     /// Linux manual: A "clone" child is one which delivers no signal, or a
     /// signal other than SIGCHLD to its parent upon termination.
     pub fn is_clone_child(&self) -> bool {
@@ -294,60 +591,334 @@ impl ProcessData {
     pub fn futex_table_for(&self, key: &FutexKey) -> Arc<FutexTable> {
         match key {
             FutexKey::Private { .. } => self.futex_table.clone(),
-            FutexKey::Shared { region, .. } => {
-                let ptr = match region {
-                    Ok(pages) => Weak::as_ptr(pages) as usize,
-                    Err(key) => Weak::as_ptr(key) as usize,
-                };
-                SHARED_FUTEX_TABLES.lock().get_or_insert(ptr)
+            FutexKey::Shared { .. } => SHARED_FUTEX_TABLE.clone(),
+        }
+    }
+
+    /// Get the peak resident set size of the process in pages.
+    pub fn max_rss(&self) -> usize {
+        self.max_rss.load(Ordering::Relaxed)
+    }
+
+    /// Accounts `pages` newly mapped into the address space.
+    pub fn add_rss(&self, pages: usize) {
+        let rss = self.rss.fetch_add(pages, Ordering::Relaxed) + pages;
+        self.max_rss.fetch_max(rss, Ordering::Relaxed);
+    }
+
+    /// Accounts `pages` about to be unmapped from the address space.
+    pub fn sub_rss(&self, pages: usize) {
+        let _ = self
+            .rss
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |rss| {
+                Some(rss.saturating_sub(pages))
+            });
+    }
+
+    /// Starts over with `pages` resident, after `execve` replaced the
+    /// address space. The peak is kept, as on Linux.
+    pub fn reset_rss(&self, pages: usize) {
+        self.rss.store(pages, Ordering::Relaxed);
+        self.max_rss.fetch_max(pages, Ordering::Relaxed);
+    }
+
+    /// Starts the resident set of a forked child from that of its parent,
+    /// whose pages it shares.
+    pub fn inherit_rss(&self, parent: &ProcessData) {
+        let rss = parent.rss.load(Ordering::Relaxed);
+        self.rss.store(rss, Ordering::Relaxed);
+        self.max_rss.store(rss, Ordering::Relaxed);
+    }
+
+    /// Locks the address space once no transfer has memory from `start` to
+    /// `start + len` pinned, before that range is unmapped or its protection
+    /// changes.
+    pub fn lock_unpinned(&self, start: VirtAddr, len: usize) -> MutexGuard<'_, AddrSpace> {
+        let pins = self.pins.read().clone();
+        pins.lock_unpinned(&self.aspace, start, len)
+    }
+
+    /// Locks the whole address space once no transfer has memory pinned in
+    /// it, before it is copied or replaced.
+    pub fn lock_all_unpinned(&self) -> MutexGuard<'_, AddrSpace> {
+        self.lock_unpinned(
+            VirtAddr::from(crate::config::USER_SPACE_BASE),
+            crate::config::USER_SPACE_SIZE,
+        )
+    }
+
+    /// Resolves a page fault in the address space, accounting the page to
+    /// the resident set if it was not mapped before.
+    ///
+    /// Read faults in anonymous memory map the zero page, which is not
+    /// resident, and a write to it maps a private page instead.
+    pub fn handle_page_fault(&self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        let mut aspace = self.aspace.lock();
+        let zero_pages = self.zero_pages.read().clone();
+        if zero_pages.map(&mut aspace, vaddr, access_flags) {
+            return true;
+        }
+        if access_flags.contains(MappingFlags::WRITE)
+            && zero_pages
+                .strip(&mut aspace, vaddr.align_down_4k(), PAGE_SIZE_4K)
+                .is_err()
+        {
+            return false;
+        }
+        let mapped = aspace.page_table().query(vaddr).is_ok();
+        let resolved = resolve_page_fault(&mut aspace, vaddr, access_flags);
+        if resolved && !mapped {
+            self.add_rss(1);
+        }
+        resolved
+    }
+
+    /// Accounts the usage of an exited thread to this process, returning the
+    /// total usage of the exited threads so far.
+    pub fn add_exited_usage(&self, usage: ResourceUsage) -> ResourceUsage {
+        let mut exited = self.exited_usage.lock();
+        *exited = exited.collate(usage);
+        *exited
+    }
+
+    /// Records the final usage of a child that has become a zombie.
+    ///
+    /// Warns once the process has more unreaped zombies than
+    /// [`zombie_max`] allows, which usually means that it does not handle
+    /// `SIGCHLD`.
+    pub fn set_zombie_usage(&self, pid: Pid, usage: ResourceUsage) {
+        let mut zombies = self.zombie_usage.lock();
+        zombies.insert(pid, usage);
+        if zombies.len() == zombie_max() + 1 {
+            warn!(
+                "Process {} has more than {} unreaped children; it should wait for them on SIGCHLD",
+                self.proc.pid(),
+                zombie_max()
+            );
+        }
+    }
+
+    /// Get the usage of a zombie child without reaping it.
+    pub fn zombie_usage(&self, pid: Pid) -> ResourceUsage {
+        self.zombie_usage
+            .lock()
+            .get(&pid)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn take_zombie_usage(&self, pid: Pid) -> ResourceUsage {
+        self.adopted.lock().remove(&pid);
+        self.zombie_usage.lock().remove(&pid).unwrap_or_default()
+    }
+
+    /// Removes and returns the usage of a zombie child being reaped, which is
+    /// added to the usage of the children.
+    pub fn reap_zombie_usage(&self, pid: Pid) -> ResourceUsage {
+        let usage = self.take_zombie_usage(pid);
+        let mut children = self.children_usage.lock();
+        *children = children.collate(usage);
+        usage
+    }
+
+    /// Get the usage of the children that have been reaped.
+    pub fn children_usage(&self) -> ResourceUsage {
+        *self.children_usage.lock()
+    }
+
+    /// Frees a zombie child and returns its usage, or `None` if it is not a
+    /// zombie child of the process anymore.
+    pub fn reap_child(&self, child: &Process) -> Option<ResourceUsage> {
+        let _guard = self.reap_lock.lock();
+        let is_child = self
+            .proc
+            .children()
+            .iter()
+            .any(|it| it.pid() == child.pid());
+        if !child.is_zombie() || !is_child {
+            return None;
+        }
+        let usage = self.reap_zombie_usage(child.pid());
+        child.free();
+        release_pid(child.pid());
+        Some(usage)
+    }
+
+    /// Whether the children of the process are freed as soon as they exit,
+    /// because it ignores `SIGCHLD` or has set `SA_NOCLDWAIT`.
+    pub fn autoreaps_children(&self) -> bool {
+        self.sigchld_flags() & SA_NOCLDWAIT != 0
+            || matches!(
+                self.signal.actions.lock()[Signo::SIGCHLD].disposition,
+                SignalDisposition::Ignore
+            )
+    }
+
+    /// Frees the zombie children inherited from exited processes, if the
+    /// process would not be able to wait for them anyway.
+    ///
+    /// Returns the number of children reaped.
+    pub fn reap_orphans(&self) -> usize {
+        if !self.autoreaps_children() {
+            return 0;
+        }
+        let mut count = 0;
+        for child in self.proc.children() {
+            if self.adopted.lock().contains(&child.pid()) && self.reap_child(&child).is_some() {
+                count += 1;
             }
         }
+        count
     }
 
-    /// Get the umask.
-    pub fn umask(&self) -> u32 {
-        self.umask.load(Ordering::SeqCst)
+    /// Check if the process is stopped by a job control signal.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
     }
 
-    /// Set the umask.
-    pub fn set_umask(&self, umask: u32) {
-        self.umask.store(umask, Ordering::SeqCst);
+    /// Puts the process into the stopped state and notifies the parent.
+    ///
+    /// Returns `false` if the process was already stopped.
+    pub fn stop(&self, signo: Signo) -> bool {
+        if self.stopped.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        *self.job_event.lock() = Some(JobEvent::Stopped(signo));
+        self.notify_parent(CLD_STOPPED);
+        true
     }
 
-    /// Set the umask and return the old value.
-    pub fn replace_umask(&self, umask: u32) -> u32 {
-        self.umask.swap(umask, Ordering::SeqCst)
+    /// Resumes a stopped process and notifies the parent.
+    ///
+    /// Returns `false` if the process was not stopped.
+    pub fn cont(&self) -> bool {
+        if !self.stopped.swap(false, Ordering::AcqRel) {
+            return false;
+        }
+        *self.job_event.lock() = Some(JobEvent::Continued);
+        self.cont_event.wake();
+        self.notify_parent(CLD_CONTINUED);
+        true
     }
-}
 
-struct FutexTables {
-    map: HashMap<usize, Arc<FutexTable>>,
-    operations: usize,
+    /// Wakes up a stopped process without reporting it, e.g. to let it handle
+    /// `SIGKILL`.
+    pub fn wake_stopped(&self) {
+        if self.stopped.swap(false, Ordering::AcqRel) {
+            self.cont_event.wake();
+        }
+    }
+
+    /// Get the job control state change not yet reported to the parent.
+    pub fn job_event(&self) -> Option<JobEvent> {
+        *self.job_event.lock()
+    }
+
+    /// Marks the pending job control state change as reported.
+    pub fn clear_job_event(&self) {
+        self.job_event.lock().take();
+    }
+
+    /// Get the `sa_flags` of the `SIGCHLD` action.
+    pub fn sigchld_flags(&self) -> u32 {
+        self.sigchld_flags.load(Ordering::Acquire)
+    }
+
+    /// Set the `sa_flags` of the `SIGCHLD` action.
+    pub fn set_sigchld_flags(&self, flags: u32) {
+        self.sigchld_flags.store(flags, Ordering::Release)
+    }
+
+    fn notify_parent(&self, code: u32) {
+        let Some(parent) = self.proc.parent() else {
+            return;
+        };
+        let Ok(data) = get_process_data(parent.pid()) else {
+            return;
+        };
+        // With SA_NOCLDSTOP, only the children that terminate raise SIGCHLD,
+        // though `wait` still reports the others.
+        if data.sigchld_flags() & SA_NOCLDSTOP == 0 {
+            let sig = SignalInfo::new_user(Signo::SIGCHLD, code as _, self.proc.pid());
+            let _ = send_signal_to_process(parent.pid(), Some(sig));
+        }
+        data.child_exit_event.wake();
+    }
 }
-impl FutexTables {
-    fn new() -> Self {
-        Self {
-            map: HashMap::new(),
-            operations: 0,
+
+/// Exits the process, handing its children over to init.
+///
+/// The usage of children that have already exited follows them to the new
+/// parent, which is sent SIGCHLD so that it gets a chance to reap them.
+pub fn exit_process(proc_data: &ProcessData) {
+    let proc = &proc_data.proc;
+    let children = proc.children();
+    proc.exit();
+
+    let mut reaper = None;
+    let mut has_zombie = false;
+    for child in children {
+        let Some(parent) = child.parent() else {
+            continue;
+        };
+        if Arc::ptr_eq(&parent, proc) {
+            continue;
+        }
+        let Ok(data) = get_process_data(parent.pid()) else {
+            continue;
+        };
+        data.adopted.lock().insert(child.pid());
+        if child.is_zombie() {
+            data.set_zombie_usage(child.pid(), proc_data.take_zombie_usage(child.pid()));
+            has_zombie = true;
         }
+        reaper = Some(data);
     }
 
-    fn get_or_insert(&mut self, key: usize) -> Arc<FutexTable> {
-        self.operations += 1;
-        if self.operations == 100 {
-            self.operations = 0;
-            self.map
-                .retain(|_, table| Arc::strong_count(table) > 1 || !table.is_empty());
+    if has_zombie && let Some(reaper) = reaper {
+        let sig = SignalInfo::new_kernel(Signo::SIGCHLD);
+        let _ = send_signal_to_process(reaper.proc.pid(), Some(sig));
+        reaper.child_exit_event.wake();
+    }
+}
+
+/// A job control state change of a process, reported by `wait` with
+/// `WUNTRACED` or `WCONTINUED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobEvent {
+    /// The process was stopped by the given signal.
+    Stopped(Signo),
+    /// The process was resumed by `SIGCONT`.
+    Continued,
+}
+
+impl JobEvent {
+    /// Encodes the event as a `wait` status.
+    pub fn wait_status(&self) -> i32 {
+        match self {
+            JobEvent::Stopped(signo) => ((*signo as i32) << 8) | 0x7f,
+            JobEvent::Continued => 0xffff,
         }
-        self.map
-            .entry(key)
-            .or_insert_with(|| Arc::new(FutexTable::new()))
-            .clone()
     }
 }
 
 lazy_static! {
-    static ref SHARED_FUTEX_TABLES: Mutex<FutexTables> = Mutex::new(FutexTables::new());
+    /// The futexes in shared mappings, keyed by physical address.
+    static ref SHARED_FUTEX_TABLE: Arc<FutexTable> = Arc::new(FutexTable::new());
+}
+
+static ZOMBIE_MAX: AtomicUsize = AtomicUsize::new(1024);
+
+/// Returns the number of unreaped zombie children a process may have before
+/// a warning is logged.
+pub fn zombie_max() -> usize {
+    ZOMBIE_MAX.load(Ordering::Relaxed)
+}
+
+/// Sets the number of unreaped zombie children a process may have before a
+/// warning is logged.
+pub fn set_zombie_max(max: usize) {
+    ZOMBIE_MAX.store(max, Ordering::Relaxed);
 }
 
 static TASK_TABLE: RwLock<WeakMap<Pid, WeakAxTaskRef>> = RwLock::new(WeakMap::new());
@@ -476,6 +1047,50 @@ fn send_signal_thread_inner(task: &TaskInner, thr: &Thread, sig: SignalInfo) {
     }
 }
 
+/// The signals that stop a process by default.
+const STOP_SIGNALS: [Signo; 4] = [
+    Signo::SIGSTOP,
+    Signo::SIGTSTP,
+    Signo::SIGTTIN,
+    Signo::SIGTTOU,
+];
+
+/// Applies the side effects a job control signal has as soon as it is
+/// generated, regardless of whether it is blocked.
+///
+/// As POSIX requires, a stop signal discards a pending `SIGCONT`, and
+/// `SIGCONT` discards the pending stop signals besides resuming the process.
+fn handle_job_control_signal(proc_data: &ProcessData, signo: Signo) {
+    match signo {
+        Signo::SIGCONT => {
+            discard_pending_signals(proc_data, &STOP_SIGNALS);
+            proc_data.cont();
+        }
+        _ if STOP_SIGNALS.contains(&signo) => {
+            discard_pending_signals(proc_data, &[Signo::SIGCONT]);
+        }
+        Signo::SIGKILL => proc_data.wake_stopped(),
+        _ => {}
+    }
+}
+
+/// Discards the signals in `signals` that are pending for the process or for
+/// any of its threads.
+fn discard_pending_signals(proc_data: &ProcessData, signals: &[Signo]) {
+    let mut set = SignalSet::default();
+    for signo in signals {
+        set.add(*signo);
+    }
+    while proc_data.signal.dequeue_signal(&set).is_some() {}
+    for tid in proc_data.proc.threads() {
+        if let Ok(task) = get_task(tid)
+            && let Some(thr) = task.try_as_thread()
+        {
+            while thr.signal.dequeue_signal(&set).is_some() {}
+        }
+    }
+}
+
 /// Sends a signal to a thread.
 pub fn send_signal_to_thread(
     tgid: Option<Pid>,
@@ -490,6 +1105,7 @@ pub fn send_signal_to_thread(
 
     if let Some(sig) = sig {
         info!("Send signal {:?} to thread {}", sig.signo(), tid);
+        handle_job_control_signal(&thread.proc_data, sig.signo());
         send_signal_thread_inner(&task, thread, sig);
     }
 
@@ -503,6 +1119,7 @@ pub fn send_signal_to_process(pid: Pid, sig: Option<SignalInfo>) -> LinuxResult<
     if let Some(sig) = sig {
         let signo = sig.signo();
         info!("Send signal {:?} to process {}", signo, pid);
+        handle_job_control_signal(&proc_data, signo);
         if let Some(tid) = proc_data.signal.send_signal(sig)
             && let Ok(task) = get_task(tid)
         {
@@ -0,0 +1,74 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axfs_ng::{FS_CONTEXT, FsContext};
+use axsync::Mutex;
+use axtask::current;
+use lazy_static::lazy_static;
+
+use super::AsThread;
+
+/// Filesystem information of a process or thread: the root and working
+/// directories and the file mode creation mask.
+///
+/// It is shared between processes and threads created with `CLONE_FS`, and
+/// copied otherwise.
+pub struct FsState {
+    context: Arc<Mutex<FsContext>>,
+    umask: AtomicU32,
+}
+
+impl FsState {
+    /// Creates a new [`FsState`] with the given context and the default umask.
+    pub fn new(context: FsContext) -> Arc<Self> {
+        Arc::new(Self {
+            context: Arc::new(Mutex::new(context)),
+            umask: AtomicU32::new(0o022),
+        })
+    }
+
+    /// Creates an independent copy of this [`FsState`].
+    pub fn fork(&self) -> Arc<Self> {
+        Arc::new(Self {
+            context: Arc::new(Mutex::new(self.context.lock().clone())),
+            umask: AtomicU32::new(self.umask()),
+        })
+    }
+
+    /// Get the filesystem context.
+    pub fn context(&self) -> Arc<Mutex<FsContext>> {
+        self.context.clone()
+    }
+
+    /// Get the umask.
+    pub fn umask(&self) -> u32 {
+        self.umask.load(Ordering::SeqCst)
+    }
+
+    /// Set the umask.
+    pub fn set_umask(&self, umask: u32) {
+        self.umask.store(umask, Ordering::SeqCst);
+    }
+
+    /// Set the umask and return the old value.
+    pub fn replace_umask(&self, umask: u32) -> u32 {
+        self.umask.swap(umask, Ordering::SeqCst)
+    }
+}
+
+lazy_static! {
+    static ref KERNEL_FS_CONTEXT: Arc<Mutex<FsContext>> =
+        Arc::new(Mutex::new(FS_CONTEXT.lock().clone()));
+}
+
+/// Get the filesystem context of the current task.
+///
+/// User tasks use the context of their thread, which is usually owned by
+/// their process, while kernel tasks always use the context of the root
+/// filesystem.
+pub fn fs_context() -> Arc<Mutex<FsContext>> {
+    match current().try_as_thread() {
+        Some(thr) => thr.fs().context(),
+        None => KERNEL_FS_CONTEXT.clone(),
+    }
+}
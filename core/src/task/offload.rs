@@ -0,0 +1,89 @@
+//! Off-loading of slow operations to kernel tasks.
+//!
+//! Device operations that may block for long, like those going through a
+//! backing file on a slow filesystem, run on a kernel task of their own while
+//! the caller sleeps interruptibly. A signal wakes the caller up with `EINTR`,
+//! so that Ctrl-C gets the shell back even if the operation is stuck.
+
+use alloc::{string::String, sync::Arc};
+use core::{
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::Poll,
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axio::PollSet;
+use axsync::Mutex;
+use axtask::future::try_block_on;
+
+use super::spawn_background;
+
+/// Tells off-loaded work whether its caller has given up on it.
+pub struct Cancellation(AtomicBool);
+
+impl Cancellation {
+    /// Fails with `EINTR` once the caller was interrupted, so that work made
+    /// of many steps can stop between two of them.
+    pub fn check(&self) -> LinuxResult<()> {
+        if self.0.load(Ordering::Acquire) {
+            Err(LinuxError::EINTR)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+struct Completion<T> {
+    result: Mutex<Option<LinuxResult<T>>>,
+    done: PollSet,
+    cancellation: Cancellation,
+}
+
+/// Runs `work` on a new kernel task named `name` and waits for its result.
+///
+/// If the caller is interrupted by a signal, `EINTR` is returned at once. The
+/// task still runs `work`, whose result is thrown away, as it cannot be
+/// stopped halfway; `work` should check the [`Cancellation`] it is given
+/// between steps to end early. `work` runs outside of any process, so it must
+/// not touch user memory or the file descriptor table: both are to be read by
+/// the caller beforehand.
+pub fn offload<T: Send + 'static>(
+    name: impl Into<String>,
+    work: impl FnOnce(&Cancellation) -> LinuxResult<T> + Send + 'static,
+) -> LinuxResult<T> {
+    let completion = Arc::new(Completion {
+        result: Mutex::new(None),
+        done: PollSet::new(),
+        cancellation: Cancellation(AtomicBool::new(false)),
+    });
+
+    let worker = completion.clone();
+    spawn_background(
+        move || {
+            let result = work(&worker.cancellation);
+            *worker.result.lock() = Some(result);
+            worker.done.wake();
+        },
+        name.into(),
+    );
+
+    let result = try_block_on(poll_fn(|cx| {
+        if let Some(result) = completion.result.lock().take() {
+            return Poll::Ready(result);
+        }
+        completion.done.register(cx.waker());
+        match completion.result.lock().take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }));
+    match result {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => {
+            completion.cancellation.0.store(true, Ordering::Release);
+            Err(LinuxError::EINTR)
+        }
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,220 @@
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    ops::RangeInclusive,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use axerrno::{LinuxError, LinuxResult};
+use axtask::current;
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use spin::RwLock;
+use starry_process::Pid;
+
+use super::{AsThread, thread_count};
+
+/// The maximum nesting depth of PID namespaces, as in Linux.
+const MAX_PID_NS_LEVEL: u32 = 32;
+
+/// The range `kernel.pid_max` may be set to, as in Linux on 64-bit systems.
+const PID_MAX_RANGE: RangeInclusive<Pid> = 301..=4 * 1024 * 1024;
+
+static PID_MAX: AtomicU32 = AtomicU32::new(32768);
+
+/// Returns `kernel.pid_max`.
+///
+/// IDs in nested namespaces stay below it, wrapping around. Global IDs are
+/// task IDs, which are never reused and cannot be chosen, so there it only
+/// bounds the number of tasks.
+pub fn pid_max() -> Pid {
+    PID_MAX.load(Ordering::Relaxed)
+}
+
+/// Sets `kernel.pid_max`.
+pub fn set_pid_max(max: Pid) -> LinuxResult {
+    if !PID_MAX_RANGE.contains(&max) {
+        return Err(LinuxError::EINVAL);
+    }
+    PID_MAX.store(max, Ordering::Relaxed);
+    Ok(())
+}
+
+#[derive(Default)]
+struct PidMap {
+    last: Pid,
+    /// Global ID -> ID in the namespace.
+    local: HashMap<Pid, Pid>,
+    /// ID in the namespace -> global ID.
+    global: HashMap<Pid, Pid>,
+}
+
+impl PidMap {
+    /// Takes the first free ID after the last one taken, wrapping around at
+    /// `pid_max`.
+    ///
+    /// An ID is only freed when its task is reaped, or dropped for threads
+    /// other than the leader, so that it is not reused while anything may
+    /// still look the task up by it.
+    fn take_next(&mut self, pid_max: Pid) -> Option<Pid> {
+        let mut local = self.last;
+        for _ in 1..pid_max {
+            local = if local + 1 >= pid_max { 1 } else { local + 1 };
+            if !self.global.contains_key(&local) {
+                self.last = local;
+                return Some(local);
+            }
+        }
+        None
+    }
+}
+
+/// A PID namespace.
+///
+/// Tasks are identified by their global ID everywhere in the kernel, and
+/// namespaces only translate IDs at the user boundary. A task created in a
+/// namespace gets an ID there and in each of its ancestors, starting from 1
+/// for the init of the namespace, and is invisible from the namespaces below.
+/// The root namespace maps every ID to itself.
+pub struct PidNamespace {
+    parent: Option<Arc<PidNamespace>>,
+    level: u32,
+    map: RwLock<PidMap>,
+    children: RwLock<Vec<Weak<PidNamespace>>>,
+    /// Set once the init of the namespace has exited.
+    dead: AtomicBool,
+}
+
+lazy_static! {
+    static ref ROOT_PID_NS: Arc<PidNamespace> = Arc::new(PidNamespace {
+        parent: None,
+        level: 0,
+        map: RwLock::default(),
+        children: RwLock::default(),
+        dead: AtomicBool::new(false),
+    });
+}
+
+impl PidNamespace {
+    /// Returns the root namespace.
+    pub fn root() -> Arc<Self> {
+        ROOT_PID_NS.clone()
+    }
+
+    /// Whether this is the root namespace.
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// Creates a namespace nested in this one.
+    pub fn new_child(self: &Arc<Self>) -> LinuxResult<Arc<Self>> {
+        if self.level >= MAX_PID_NS_LEVEL {
+            return Err(LinuxError::ENOSPC);
+        }
+        let child = Arc::new(Self {
+            parent: Some(self.clone()),
+            level: self.level + 1,
+            map: RwLock::default(),
+            children: RwLock::default(),
+            dead: AtomicBool::new(false),
+        });
+        let mut children = self.children.write();
+        children.retain(|it| it.strong_count() > 0);
+        children.push(Arc::downgrade(&child));
+        Ok(child)
+    }
+
+    fn ancestors(&self) -> impl Iterator<Item = &PidNamespace> {
+        core::iter::successors(Some(self), |ns| ns.parent.as_deref())
+    }
+
+    /// Gives the task `global` an ID in this namespace and its ancestors, and
+    /// returns the ID in this namespace.
+    ///
+    /// Fails with `ENOMEM` once the init of the namespace has exited, and
+    /// with `EAGAIN` when some namespace has no ID left below `pid_max`.
+    pub fn alloc(&self, global: Pid) -> LinuxResult<Pid> {
+        if self.dead.load(Ordering::Acquire) {
+            return Err(LinuxError::ENOMEM);
+        }
+        let pid_max = pid_max();
+        if thread_count() >= pid_max as usize {
+            return Err(LinuxError::EAGAIN);
+        }
+        for ns in self.ancestors().filter(|ns| !ns.is_root()) {
+            let mut map = ns.map.write();
+            let Some(local) = map.take_next(pid_max) else {
+                drop(map);
+                release_pid(global);
+                return Err(LinuxError::EAGAIN);
+            };
+            map.local.insert(global, local);
+            map.global.insert(local, global);
+        }
+        Ok(self.local_pid(global).unwrap())
+    }
+
+    fn release(&self, global: Pid) {
+        let mut map = self.map.write();
+        if let Some(local) = map.local.remove(&global) {
+            map.global.remove(&local);
+        }
+        drop(map);
+        for child in self.children.read().iter().filter_map(Weak::upgrade) {
+            child.release(global);
+        }
+    }
+
+    /// Returns the ID of the task `global` in this namespace, or `None` if it
+    /// is not visible from here.
+    pub fn local_pid(&self, global: Pid) -> Option<Pid> {
+        if self.is_root() {
+            return Some(global);
+        }
+        self.map.read().local.get(&global).copied()
+    }
+
+    /// Returns the global ID of the task known as `local` in this namespace.
+    ///
+    /// `0`, which syscalls take to mean the caller, is passed through.
+    pub fn global_pid(&self, local: Pid) -> LinuxResult<Pid> {
+        if self.is_root() || local == 0 {
+            return Ok(local);
+        }
+        self.map
+            .read()
+            .global
+            .get(&local)
+            .copied()
+            .ok_or(LinuxError::ESRCH)
+    }
+
+    /// Returns the global ID of the init of this namespace, if it is a nested
+    /// namespace whose init is still alive.
+    pub fn init_pid(&self) -> Option<Pid> {
+        if self.is_root() || self.dead.load(Ordering::Acquire) {
+            return None;
+        }
+        self.global_pid(1).ok()
+    }
+
+    /// Marks the namespace as dead after its init has exited, and returns the
+    /// global IDs of the tasks left in it, which are to be killed.
+    pub fn kill(&self) -> Vec<Pid> {
+        self.dead.store(true, Ordering::Release);
+        self.map.read().local.keys().copied().collect()
+    }
+}
+
+/// Releases the IDs of the task `global` in all namespaces, once it has been
+/// reaped, or dropped for threads other than the leader.
+pub fn release_pid(global: Pid) {
+    ROOT_PID_NS.release(global);
+}
+
+/// Returns the PID namespace of the current process.
+pub fn current_pid_ns() -> Arc<PidNamespace> {
+    current().as_thread().proc_data.pid_ns.read().clone()
+}
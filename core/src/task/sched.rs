@@ -0,0 +1,209 @@
+use alloc::{boxed::Box, collections::VecDeque, string::String, vec::Vec};
+use core::time::Duration;
+
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use axtask::{AxCpuMask, AxTaskRef, TaskInner};
+use linux_raw_sys::general::{SCHED_BATCH, SCHED_FIFO, SCHED_IDLE, SCHED_NORMAL, SCHED_RR};
+use spin::Once;
+
+use super::cleanup_task_tables;
+
+/// A scheduling policy of `sched_setscheduler`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy.
+    #[default]
+    Normal = SCHED_NORMAL,
+    /// First-in, first-out real-time policy.
+    Fifo   = SCHED_FIFO,
+    /// Round-robin real-time policy.
+    Rr     = SCHED_RR,
+    /// For CPU-intensive, non-interactive tasks.
+    Batch  = SCHED_BATCH,
+    /// For tasks that should only run when nothing else wants the CPU.
+    ///
+    /// The scheduler has no idle class, so such threads only start on the
+    /// little cores and give up the CPU every time they enter the kernel.
+    Idle   = SCHED_IDLE,
+}
+
+impl TryFrom<u32> for SchedPolicy {
+    type Error = LinuxError;
+
+    fn try_from(value: u32) -> LinuxResult<Self> {
+        match value {
+            SCHED_NORMAL => Ok(Self::Normal),
+            SCHED_FIFO => Ok(Self::Fifo),
+            SCHED_RR => Ok(Self::Rr),
+            SCHED_BATCH => Ok(Self::Batch),
+            SCHED_IDLE => Ok(Self::Idle),
+            _ => Err(LinuxError::EINVAL),
+        }
+    }
+}
+
+impl SchedPolicy {
+    /// Returns the range of static priorities valid for the policy.
+    pub fn priority_range(self) -> (u32, u32) {
+        match self {
+            Self::Fifo | Self::Rr => (1, 99),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// The scheduling parameters of a thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedParams {
+    /// The scheduling policy.
+    pub policy: SchedPolicy,
+    /// The static priority, only meaningful for real-time policies.
+    pub priority: u32,
+    /// Whether children start with the default parameters
+    /// (`SCHED_RESET_ON_FORK`).
+    pub reset_on_fork: bool,
+}
+
+impl SchedParams {
+    /// Returns the parameters a child created by `clone` starts with.
+    pub fn fork(self) -> Self {
+        if self.reset_on_fork {
+            Self::default()
+        } else {
+            self
+        }
+    }
+}
+
+/// The seed used without one on the command line.
+const DEFAULT_SEED: u64 = 0x5eed;
+
+/// Returns the seed of the deterministic mode, or `None` if it is off.
+///
+/// The mode is turned on by `deterministic` or `deterministic=<seed>` on the
+/// kernel command line, to reproduce failures that depend on timing. Every
+/// task then runs on CPU 0 and the random numbers handed out by the kernel
+/// come from the seed. The time slice of the scheduler is a fixed number of
+/// timer ticks anyway, but the ticks and device interrupts still come when
+/// they come, so runs are only as repeatable as the timing of those.
+pub fn deterministic_seed() -> Option<u64> {
+    static SEED: Once<Option<u64>> = Once::new();
+    *SEED.call_once(|| {
+        let seed = crate::cmdline::args()
+            .filter_map(|it| it.strip_prefix("deterministic"))
+            .last()?;
+        match seed.strip_prefix('=') {
+            Some(seed) => seed.parse().ok(),
+            None => seed.is_empty().then_some(DEFAULT_SEED),
+        }
+    })
+}
+
+/// Restricts `mask` to the CPUs tasks may run on, which is only CPU 0 in the
+/// deterministic mode.
+pub fn allowed_cpus(mask: AxCpuMask) -> AxCpuMask {
+    if deterministic_seed().is_none() {
+        return mask;
+    }
+    let mut first = AxCpuMask::new();
+    first.set(0, true);
+    mask & first
+}
+
+/// The capacity of every CPU, from 0 to 1024 like `cpu_capacity` on Linux.
+static CPU_CAPACITY: Once<Vec<u32>> = Once::new();
+
+/// Records the capacity of every CPU, for placing tasks on systems whose CPUs
+/// are not all alike.
+pub fn set_cpu_capacity(capacity: Vec<u32>) {
+    CPU_CAPACITY.call_once(|| capacity);
+}
+
+/// Returns the CPUs of the highest capacity if `big`, otherwise those of the
+/// lowest, or `None` if all CPUs are alike.
+fn cpus_by_capacity(big: bool) -> Option<AxCpuMask> {
+    let capacity = CPU_CAPACITY.get()?;
+    let target = if big {
+        capacity.iter().max()?
+    } else {
+        capacity.iter().min()?
+    };
+    if capacity.iter().all(|it| it == target) {
+        return None;
+    }
+    let mut mask = AxCpuMask::new();
+    for (cpu, _) in capacity.iter().enumerate().filter(|(_, it)| *it == target) {
+        mask.set(cpu, true);
+    }
+    Some(mask)
+}
+
+/// Returns the CPUs a new user task allowed on `allowed` should start on.
+///
+/// The scheduler only looks at the affinity of a task when placing it, so
+/// starting CPU-bound tasks on the big cores and widening their affinity
+/// afterwards keeps them there until they sleep, without preventing them from
+/// using the little cores when the big ones are busy.
+pub fn start_cpus(allowed: AxCpuMask, params: SchedParams) -> AxCpuMask {
+    let allowed = allowed_cpus(allowed);
+    let big = params.policy != SchedPolicy::Idle;
+    match cpus_by_capacity(big) {
+        Some(preferred) if !(preferred & allowed).is_empty() => preferred & allowed,
+        _ => allowed,
+    }
+}
+
+/// Spawns a kernel task doing background work, which runs on the little cores
+/// to leave the big ones to user tasks.
+pub fn spawn_background<F>(f: F, name: String) -> AxTaskRef
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = TaskInner::new(f, name, axconfig::TASK_STACK_SIZE);
+    if deterministic_seed().is_some() {
+        task.set_cpumask(allowed_cpus(task.cpumask()));
+    } else if let Some(little) = cpus_by_capacity(false) {
+        task.set_cpumask(little);
+    }
+    axtask::spawn_task(task)
+}
+
+/// The interval at which the idle worker looks for work.
+const IDLE_WORK_INTERVAL: Duration = Duration::from_secs(1);
+
+type IdleWork = Box<dyn FnOnce() + Send>;
+
+static IDLE_WORK: Mutex<VecDeque<IdleWork>> = Mutex::new(VecDeque::new());
+
+/// Queues `work` to be run by the idle worker, e.g. writeback or freeing of
+/// resources that nobody waits for.
+pub fn defer_idle_work(work: impl FnOnce() + Send + 'static) {
+    IDLE_WORK.lock().push_back(Box::new(work));
+}
+
+/// Spawns the kernel task running deferred maintenance work.
+///
+/// The scheduler has no notion of priority, so this is not a real idle class:
+/// the worker wakes up periodically and gives up the CPU before every unit of
+/// work, which only lets it run once the tasks that were runnable have had
+/// their turn, but it competes with them like any other task while it runs.
+pub fn spawn_idle_worker() {
+    spawn_background(
+        || {
+            loop {
+                axtask::sleep(IDLE_WORK_INTERVAL);
+                loop {
+                    axtask::yield_now();
+                    let Some(work) = IDLE_WORK.lock().pop_front() else {
+                        break;
+                    };
+                    work();
+                }
+                cleanup_task_tables();
+            }
+        },
+        "idle-worker".into(),
+    );
+}
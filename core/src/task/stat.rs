@@ -19,7 +19,7 @@ pub struct TaskStat {
     pub pgrp: u32,
     pub session: u32,
     pub tty_nr: u32,
-    pub tpgid: u32,
+    pub tpgid: i32,
     pub flags: u32,
     pub minflt: u64,
     pub cminflt: u64,
@@ -77,9 +77,10 @@ impl TaskStat {
         let comm = task.name();
         let comm = comm[..comm.len().min(16)].to_owned();
         let state = match task.state() {
+            TaskState::Exited => 'Z',
+            _ if proc_data.is_stopped() => 'T',
             TaskState::Running | TaskState::Ready => 'R',
             TaskState::Blocked => 'S',
-            TaskState::Exited => 'Z',
         };
         let ppid = proc.parent().map_or(0, |p| p.pid());
         let pgrp = proc.group().pgid();
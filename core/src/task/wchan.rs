@@ -0,0 +1,60 @@
+//! What blocked threads are waiting for, shown in `/proc/[pid]/wchan`.
+//!
+//! Linux names the kernel function a task sleeps in. There is no such symbol
+//! to show here, so the blocking syscalls record what they wait for instead,
+//! which says more when looking for a deadlock anyway.
+
+use core::fmt;
+
+use axtask::current;
+
+use super::AsThread;
+
+/// What a thread waits for in a blocking syscall.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitChannel {
+    /// A futex at a user address.
+    Futex(usize),
+    /// A timer, in the named syscall.
+    Sleep(&'static str),
+    /// A signal, in the named syscall.
+    Signal(&'static str),
+    /// A file, in the named syscall.
+    File(&'static str, i32),
+    /// Anything else, in the named syscall.
+    Syscall(&'static str),
+}
+
+impl fmt::Display for WaitChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Futex(addr) => write!(f, "waiting on futex {addr:#x}"),
+            Self::Sleep(name) => write!(f, "sleeping in {name}"),
+            Self::Signal(name) => write!(f, "waiting for a signal in {name}"),
+            Self::File(name, fd) => write!(f, "blocked in {name}(fd={fd})"),
+            Self::Syscall(name) => write!(f, "blocked in {name}"),
+        }
+    }
+}
+
+/// Restores the wait channel of the current thread when dropped.
+pub struct WaitGuard {
+    prev: Option<WaitChannel>,
+}
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        if let Some(thr) = current().try_as_thread() {
+            thr.set_wait_channel(self.prev);
+        }
+    }
+}
+
+/// Records that the current thread may block on `chan` until the returned
+/// guard is dropped.
+pub fn wait_on(chan: WaitChannel) -> WaitGuard {
+    let prev = current()
+        .try_as_thread()
+        .and_then(|thr| thr.set_wait_channel(Some(chan)));
+    WaitGuard { prev }
+}
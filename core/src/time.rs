@@ -185,6 +185,11 @@ impl TimeManager {
         self.last_wall_ns = now_ns;
     }
 
+    /// Whether the thread is running in user space.
+    pub fn in_user(&self) -> bool {
+        matches!(self.state, TimerState::User)
+    }
+
     /// Updates the timer state.
     pub fn set_state(&mut self, state: TimerState) {
         self.state = state;
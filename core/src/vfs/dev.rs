@@ -1,4 +1,7 @@
-use alloc::sync::Arc;
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use core::{any::Any, task::Context};
 
 use axfs_ng::CachedFile;
@@ -6,10 +9,11 @@ use axfs_ng_vfs::{
     DeviceId, FileNodeOps, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission, NodeType, VfsError, VfsResult
 };
 use axio::{IoEvents, Pollable};
+use axsync::Mutex;
 use inherit_methods_macro::inherit_methods;
 use memory_addr::PhysAddrRange;
 
-use super::{SimpleFs, SimpleFsNode};
+use super::{SimpleFs, SimpleFsNode, XattrNode, XattrStore};
 
 /// Mmap behavior for devices.
 pub enum DeviceMmap {
@@ -51,6 +55,23 @@ pub trait DeviceOps: Send + Sync {
     fn flags(&self) -> NodeFlags {
         NodeFlags::empty()
     }
+
+    /// Flushes data written to the device to its backing storage.
+    fn sync(&self) -> VfsResult<()> {
+        Ok(())
+    }
+
+    /// Discards `len` bytes at `offset`, which then read back as zeroes, for
+    /// `BLKDISCARD` and filesystems trimming their free space.
+    fn discard(&self, _offset: u64, _len: u64) -> VfsResult<()> {
+        Err(VfsError::EOPNOTSUPP)
+    }
+
+    /// Whether the device refuses writes, in which case filesystems on it are
+    /// mounted read-only.
+    fn read_only(&self) -> bool {
+        false
+    }
 }
 
 /// A device node in the filesystem.
@@ -69,7 +90,11 @@ impl Device {
     ) -> Arc<Self> {
         let node = SimpleFsNode::new(fs, node_type, NodePermission::default());
         node.metadata.lock().rdev = device_id;
-        Arc::new(Self { node, ops })
+        let device = Arc::new(Self { node, ops });
+        let mut devices = DEVICES.lock();
+        devices.retain(|it| it.strong_count() > 0);
+        devices.push(Arc::downgrade(&device));
+        device
     }
 
     /// Returns the inner device operations.
@@ -88,6 +113,24 @@ impl Device {
     }
 }
 
+/// All live devices, to resolve device nodes created with `mknod` outside of
+/// devfs.
+static DEVICES: Mutex<Vec<Weak<Device>>> = Mutex::new(Vec::new());
+
+/// Finds the device of type `node_type` with the device ID `device_id`.
+pub fn find_device(node_type: NodeType, device_id: DeviceId) -> Option<Arc<Device>> {
+    DEVICES.lock().iter().filter_map(Weak::upgrade).find(|it| {
+        let metadata = it.node.metadata.lock();
+        metadata.node_type == node_type && metadata.rdev == device_id
+    })
+}
+
+impl XattrNode for Device {
+    fn xattrs(&self) -> &XattrStore {
+        self.node.xattrs()
+    }
+}
+
 #[inherit_methods(from = "self.node")]
 impl NodeOps for Device {
     fn inode(&self) -> u64;
@@ -99,7 +142,7 @@ impl NodeOps for Device {
     fn filesystem(&self) -> &dyn FilesystemOps;
 
     fn sync(&self, _data_only: bool) -> VfsResult<()> {
-        Err(VfsError::EINVAL)
+        self.ops.sync()
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
@@ -30,6 +30,11 @@ pub trait SimpleDirOps: Send + Sync + 'static {
         true
     }
 
+    /// Removes a child, for directories whose children can be unlinked.
+    fn unlink_child(&self, _name: &str) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
     /// Combines two directories into one.
     fn chain<N: SimpleDirOps>(self, other: N) -> ChainedDirOps<Self, N>
     where
@@ -200,8 +205,8 @@ impl<O: SimpleDirOps> DirNodeOps for SimpleDir<O> {
         Err(VfsError::EPERM)
     }
 
-    fn unlink(&self, _name: &str) -> VfsResult<()> {
-        Err(VfsError::EPERM)
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        self.ops.unlink_child(name)
     }
 
     fn rename(&self, _src_name: &str, _dst_dir: &DirNode, _dst_name: &str) -> VfsResult<()> {
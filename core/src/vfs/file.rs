@@ -1,14 +1,17 @@
-use alloc::{borrow::Cow, sync::Arc, vec::Vec};
+use alloc::{borrow::Cow, string::ToString, sync::Arc, vec::Vec};
 use core::{any::Any, cmp::Ordering, task::Context};
 
 use axfs_ng_vfs::{
-    FileNodeOps, FilesystemOps, Metadata, MetadataUpdate, NodeFlags, NodeOps, NodePermission,
-    NodeType, VfsError, VfsResult,
+    FileNodeOps, FilesystemOps, Location, Metadata, MetadataUpdate, NodeFlags, NodeOps,
+    NodePermission, NodeType, VfsError, VfsResult,
 };
 use axio::{IoEvents, Pollable};
 use inherit_methods_macro::inherit_methods;
 
-use super::fs::{SimpleFs, SimpleFsNode};
+use super::{
+    XattrNode, XattrStore,
+    fs::{SimpleFs, SimpleFsNode},
+};
 
 /// Operations for a simple file.
 pub trait SimpleFileOps: Send + Sync + 'static {
@@ -16,6 +19,10 @@ pub trait SimpleFileOps: Send + Sync + 'static {
     fn read_all(&self) -> VfsResult<Cow<[u8]>>;
     /// Replaces the file's content with `data`.
     fn write_all(&self, data: &[u8]) -> VfsResult<()>;
+    /// Returns the file a magic link leads to, or `None` if this is not one.
+    fn link_target(&self) -> Option<VfsResult<Location>> {
+        None
+    }
 }
 
 /// Type representing operation applied to a simple file.
@@ -69,6 +76,46 @@ where
     }
 }
 
+/// A wrapper that implements [`SimpleFileOps`] for a magic link, like
+/// `/proc/[pid]/exe`, from `Fn() -> VfsResult<Location>`.
+///
+/// Reading the link gives the path of its target, followed by ` (deleted)`
+/// once the target has been unlinked. Opening it leads to the target itself
+/// through [`SimpleFile::link_target`], without resolving that path again.
+pub struct MagicLink<F>(F);
+
+impl<F> MagicLink<F>
+where
+    F: Fn() -> VfsResult<Location> + Send + Sync,
+{
+    /// Creates a new `MagicLink`.
+    pub fn new(target: F) -> Self {
+        Self(target)
+    }
+}
+
+impl<F> SimpleFileOps for MagicLink<F>
+where
+    F: Fn() -> VfsResult<Location> + Send + Sync + 'static,
+{
+    fn read_all(&self) -> VfsResult<Cow<[u8]>> {
+        let target = (self.0)()?;
+        let mut path = target.absolute_path()?.to_string();
+        if target.metadata()?.nlink == 0 {
+            path.push_str(" (deleted)");
+        }
+        Ok(Cow::Owned(path.into_bytes()))
+    }
+
+    fn write_all(&self, _data: &[u8]) -> VfsResult<()> {
+        Err(VfsError::EPERM)
+    }
+
+    fn link_target(&self) -> Option<VfsResult<Location>> {
+        Some((self.0)())
+    }
+}
+
 /// A simple file.
 pub struct SimpleFile {
     node: SimpleFsNode,
@@ -89,6 +136,11 @@ impl SimpleFile {
     pub fn new_regular(fs: Arc<SimpleFs>, ops: impl SimpleFileOps) -> Arc<Self> {
         Self::new(fs, NodeType::RegularFile, ops)
     }
+
+    /// Returns the file this leads to if it is a [`MagicLink`].
+    pub fn link_target(&self) -> Option<VfsResult<Location>> {
+        self.ops.link_target()
+    }
 }
 
 #[inherit_methods(from = "self.node")]
@@ -116,6 +168,12 @@ impl NodeOps for SimpleFile {
     }
 }
 
+impl XattrNode for SimpleFile {
+    fn xattrs(&self) -> &XattrStore {
+        self.node.xattrs()
+    }
+}
+
 impl FileNodeOps for SimpleFile {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> VfsResult<usize> {
         let data = self.ops.read_all()?;
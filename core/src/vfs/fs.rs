@@ -1,32 +1,16 @@
 use alloc::{string::String, sync::Arc};
-use core::{any::Any, time::Duration};
+use core::any::Any;
 
 use axfs_ng_vfs::{
     DeviceId, DirEntry, DirNode, Filesystem, FilesystemOps, Metadata, MetadataUpdate, NodeOps,
     NodePermission, NodeType, Reference, StatFs, VfsResult, path::MAX_NAME_LEN,
 };
+use axhal::time::wall_time;
 use axsync::Mutex;
+use memory_addr::PAGE_SIZE_4K;
 use slab::Slab;
 
-use super::DirMaker;
-
-/// Returns a dummy filesystem statistics.
-pub fn dummy_stat_fs(fs_type: u32) -> StatFs {
-    StatFs {
-        fs_type,
-        block_size: 512,
-        blocks: 100,
-        blocks_free: 100,
-        blocks_available: 100,
-
-        file_count: 0,
-        free_file_count: 0,
-
-        name_length: MAX_NAME_LEN as _,
-        fragment_size: 0,
-        mount_flags: 0,
-    }
-}
+use super::{DirMaker, XattrNode, XattrStore};
 
 /// A simple filesystem implementation that uses a slab allocator for inodes.
 pub struct SimpleFs {
@@ -80,7 +64,22 @@ impl FilesystemOps for SimpleFs {
     }
 
     fn stat(&self) -> VfsResult<StatFs> {
-        Ok(dummy_stat_fs(self.fs_type))
+        // Like procfs on Linux, nodes are generated on demand and take no
+        // storage, so only the inode count is meaningful.
+        Ok(StatFs {
+            fs_type: self.fs_type,
+            block_size: PAGE_SIZE_4K as _,
+            blocks: 0,
+            blocks_free: 0,
+            blocks_available: 0,
+
+            file_count: self.inodes.lock().len() as _,
+            free_file_count: 0,
+
+            name_length: MAX_NAME_LEN as _,
+            fragment_size: PAGE_SIZE_4K as _,
+            mount_flags: 0,
+        })
     }
 }
 
@@ -89,12 +88,14 @@ pub struct SimpleFsNode {
     fs: Arc<SimpleFs>,
     ino: u64,
     pub(crate) metadata: Mutex<Metadata>,
+    xattrs: XattrStore,
 }
 
 impl SimpleFsNode {
     /// Creates a new filesystem node.
     pub fn new(fs: Arc<SimpleFs>, node_type: NodeType, mode: NodePermission) -> Self {
         let ino = fs.alloc_inode();
+        let now = wall_time();
         let metadata = Metadata {
             device: 0,
             inode: ino,
@@ -107,18 +108,25 @@ impl SimpleFsNode {
             block_size: 0,
             blocks: 0,
             rdev: DeviceId::default(),
-            atime: Duration::default(),
-            mtime: Duration::default(),
-            ctime: Duration::default(),
+            atime: now,
+            mtime: now,
+            ctime: now,
         };
         Self {
             fs,
             ino,
             metadata: Mutex::new(metadata),
+            xattrs: XattrStore::default(),
         }
     }
 }
 
+impl XattrNode for SimpleFsNode {
+    fn xattrs(&self) -> &XattrStore {
+        &self.xattrs
+    }
+}
+
 impl Drop for SimpleFsNode {
     fn drop(&mut self) {
         self.fs.release_inode(self.ino);
@@ -155,6 +163,7 @@ impl NodeOps for SimpleFsNode {
         if let Some(mtime) = update.mtime {
             metadata.mtime = mtime;
         }
+        metadata.ctime = wall_time();
         Ok(())
     }
 
@@ -0,0 +1,106 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+use axerrno::{LinuxError, LinuxResult};
+use axsync::Mutex;
+use bitflags::bitflags;
+use linux_raw_sys::general::{
+    XATTR_CREATE, XATTR_LIST_MAX, XATTR_NAME_MAX, XATTR_REPLACE, XATTR_SIZE_MAX,
+};
+
+bitflags! {
+    /// Flags for `setxattr`.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct XattrFlags: u32 {
+        /// Fail if the attribute already exists.
+        const CREATE = XATTR_CREATE;
+        /// Fail if the attribute does not exist.
+        const REPLACE = XATTR_REPLACE;
+    }
+}
+
+/// Namespaces in which attributes can be stored. `system.` attributes, i.e.
+/// POSIX ACLs, would need support from the permission checks and are
+/// rejected.
+const XATTR_NAMESPACES: [&str; 3] = ["user.", "trusted.", "security."];
+
+fn check_name(name: &str) -> LinuxResult<()> {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX as usize {
+        return Err(LinuxError::ERANGE);
+    }
+    if !XATTR_NAMESPACES
+        .iter()
+        .any(|ns| name.len() > ns.len() && name.starts_with(ns))
+    {
+        return Err(LinuxError::EOPNOTSUPP);
+    }
+    Ok(())
+}
+
+/// The extended attributes of a filesystem node.
+#[derive(Default)]
+pub struct XattrStore {
+    attrs: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl XattrStore {
+    /// Returns the value of the attribute `name`.
+    pub fn get(&self, name: &str) -> LinuxResult<Vec<u8>> {
+        check_name(name)?;
+        self.attrs
+            .lock()
+            .get(name)
+            .cloned()
+            .ok_or(LinuxError::ENODATA)
+    }
+
+    /// Sets the attribute `name` to `value`.
+    pub fn set(&self, name: &str, value: &[u8], flags: XattrFlags) -> LinuxResult<()> {
+        check_name(name)?;
+        if value.len() > XATTR_SIZE_MAX as usize {
+            return Err(LinuxError::E2BIG);
+        }
+        let mut attrs = self.attrs.lock();
+        let exists = attrs.contains_key(name);
+        if exists && flags.contains(XattrFlags::CREATE) {
+            return Err(LinuxError::EEXIST);
+        }
+        if !exists {
+            if flags.contains(XattrFlags::REPLACE) {
+                return Err(LinuxError::ENODATA);
+            }
+            let list_len = attrs.keys().map(|it| it.len() + 1).sum::<usize>();
+            if list_len + name.len() + 1 > XATTR_LIST_MAX as usize {
+                return Err(LinuxError::ENOSPC);
+            }
+        }
+        attrs.insert(name.into(), value.into());
+        Ok(())
+    }
+
+    /// Returns the names of all attributes, each terminated by a null byte, as
+    /// returned by `listxattr`.
+    pub fn list(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        for name in self.attrs.lock().keys() {
+            result.extend_from_slice(name.as_bytes());
+            result.push(0);
+        }
+        result
+    }
+
+    /// Removes the attribute `name`.
+    pub fn remove(&self, name: &str) -> LinuxResult<()> {
+        check_name(name)?;
+        self.attrs
+            .lock()
+            .remove(name)
+            .map(|_| ())
+            .ok_or(LinuxError::ENODATA)
+    }
+}
+
+/// A filesystem node keeping extended attributes.
+pub trait XattrNode {
+    /// Returns the extended attributes of the node.
+    fn xattrs(&self) -> &XattrStore;
+}
@@ -2,15 +2,19 @@ use alloc::{
     string::{String, ToString},
     sync::Arc,
 };
+use core::{future::poll_fn, task::Poll};
 
 use axfs_ng::FS_CONTEXT;
 use axhal::uspace::UserContext;
 use axsync::Mutex;
-use axtask::{TaskExtProxy, spawn_task};
-use starry_api::{file::FD_TABLE, task::new_user_task, vfs::dev::tty::N_TTY};
+use axtask::{TaskExtProxy, future::block_on, spawn_task};
+use starry_api::{file::FD_TABLE, task::new_user_task, vfs::dev::tty};
 use starry_core::{
-    mm::{copy_from_kernel, load_user_app, new_user_aspace_empty},
-    task::{ProcessData, Thread, add_task_to_table},
+    mm::{aslr, copy_from_kernel, load_user_app, new_user_aspace_empty},
+    task::{
+        FsState, ProcessData, Thread, add_task_to_table, allowed_cpus, deterministic_seed,
+        spawn_background, spawn_idle_worker,
+    },
 };
 use starry_process::{Pid, Process};
 
@@ -31,7 +35,7 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         .expect("Failed to get executable absolute path");
     let name = loc.name();
 
-    let (entry, ustack_top) = load_user_app(&mut uspace, None, args, envs)
+    let (entry, ustack_top, resident) = load_user_app(&mut uspace, None, args, envs)
         .unwrap_or_else(|e| panic!("Failed to load user app: {}", e));
 
     
@@ -60,7 +64,9 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
     let proc = Process::new_init(pid);
     proc.add_thread(pid);
 
-    N_TTY.bind_to(&proc).expect("Failed to bind ntty");
+    if let Err(err) = tty::bind_console(&proc) {
+        warn!("Failed to bind console: {:?}", err);
+    }
 
     let proc_data = ProcessData::new(
         proc,
@@ -69,19 +75,58 @@ pub fn run_initproc(args: &[String], envs: &[String]) -> i32 {
         Arc::new(Mutex::new(uspace)),
         Arc::default(),
         None,
+        FsState::new(FS_CONTEXT.lock().clone()),
     );
+    proc_data.set_mmap_base(aslr::mmap_base());
+    proc_data.reset_rss(resident);
+    *proc_data.exe.write() = Some(loc.clone());
+    *proc_data.environ.write() = Arc::new(envs.to_vec());
     {
         let mut scope = proc_data.scope.write();
-        starry_api::file::add_stdio(&mut FD_TABLE.scope_mut(&mut scope).write())
-            .expect("Failed to add stdio");
+        let result = starry_api::file::add_stdio(&mut FD_TABLE.scope_mut(&mut scope).write());
+        if let Err(err) = result {
+            error!("Failed to open stdio: {:?}", err);
+        }
     }
+    spawn_orphan_reaper(proc_data.clone());
+    spawn_idle_worker();
     let thr = Thread::new(pid, proc_data);
 
     *task.task_ext_mut() = Some(unsafe { TaskExtProxy::from_impl(thr) });
 
+    // Everything else is forked from init and inherits its affinity.
+    if let Some(seed) = deterministic_seed() {
+        info!("Deterministic mode, seed {}", seed);
+        task.set_cpumask(allowed_cpus(task.cpumask()));
+    }
+
     let task = spawn_task(task);
     add_task_to_table(&task);
 
     // TODO: wait for all processes to finish
     task.join()
 }
+
+/// Spawns a kernel task reaping the orphans adopted by init.
+///
+/// The init program is not necessarily a real init and may never wait for
+/// processes it did not fork itself. Their zombies are freed here instead, as
+/// long as init ignores `SIGCHLD` or has set `SA_NOCLDWAIT`, so that they are
+/// never taken away from a `wait` of init.
+fn spawn_orphan_reaper(init: Arc<ProcessData>) {
+    spawn_background(
+        move || {
+            block_on(poll_fn(|cx| {
+                init.reap_orphans();
+                init.child_exit_event.register(cx.waker());
+                init.reap_orphans();
+                if init.proc.is_zombie() {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            }))
+        },
+        "orphan-reaper".into(),
+    );
+}
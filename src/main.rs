@@ -10,8 +10,6 @@ extern crate axruntime;
 
 use alloc::{borrow::ToOwned, format, vec::Vec};
 
-use axfs_ng::FS_CONTEXT;
-
 mod entry;
 mod test;
 
@@ -32,14 +30,10 @@ fn main() {
     let exit_code = entry::run_initproc(&args, &envs);
     info!("Init process exited with code: {:?}", exit_code);
 
-    let cx = FS_CONTEXT.lock();
-    cx.root_dir()
-        .unmount_all()
-        .expect("Failed to unmount all filesystems");
-    cx.root_dir()
-        .filesystem()
-        .flush()
-        .expect("Failed to flush rootfs");
+    let failed = starry_api::vfs::unmount_all();
+    if !failed.is_empty() {
+        error!("Data may have been lost on {}", failed.join(", "));
+    }
 }
 
 #[cfg(feature = "vf2")]
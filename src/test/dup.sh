@@ -0,0 +1,80 @@
+# dup(2) and lseek(2) interactions.
+#
+# The file offset belongs to the open file description, so descriptors
+# created by dup, by redirections and by fork all move it together, as
+# `(head; cat) <file`-style pipelines in coreutils scripts expect. Reads use
+# `dd bs=1` so that no more than asked for is consumed.
+
+dup_pass=0
+dup_fail=0
+
+# expect_dup <name> <expected> <actual>
+expect_dup() {
+    if [ "$2" = "$3" ]; then
+        dup_pass=$((dup_pass + 1))
+        echo "DUP PASS $1"
+    else
+        dup_fail=$((dup_fail + 1))
+        echo "DUP FAIL $1 (got '$3', expected '$2')"
+    fi
+}
+
+# take <fd> <count>: reads <count> bytes from <fd>.
+take() {
+    dd bs=1 count=$2 <&$1 2>/dev/null
+}
+
+run_dup() {
+    echo "#### OS COMP TEST GROUP START dup ####"
+
+    file=/tmp/dup.$$
+    printf 'abcdefghij' >$file
+
+    # Duplicates share the offset.
+    exec 3<$file
+    exec 4<&3
+    expect_dup "read through original" ab "$(take 3 2)"
+    expect_dup "duplicate continues" cd "$(take 4 2)"
+    expect_dup "original continues" ef "$(take 3 2)"
+
+    # So does a duplicate at a chosen descriptor.
+    exec 9<&3
+    expect_dup "dup2 continues" gh "$(take 9 2)"
+    exec 9<&-
+
+    # Seeking through one descriptor moves the other.
+    dd bs=1 skip=1 count=0 <&4 2>/dev/null
+    expect_dup "seek through duplicate" j "$(take 3 1)"
+    expect_dup "end of file for both" "" "$(take 4 1)"
+    exec 3<&- 4<&-
+
+    # A forked child advances its parent's offset.
+    exec 3<$file
+    (take 3 3 >/dev/null)
+    expect_dup "child moved offset" de "$(take 3 2)"
+
+    # Closing the original keeps the duplicate's offset.
+    exec 4<&3
+    exec 3<&-
+    expect_dup "offset kept after close" fg "$(take 4 2)"
+    exec 4<&-
+
+    # Separate opens have their own offsets.
+    exec 3<$file 4<$file
+    take 3 4 >/dev/null
+    expect_dup "separate open is independent" ab "$(take 4 2)"
+    exec 3<&- 4<&-
+
+    # Writes through a duplicate land after those through the original.
+    exec 3>$file
+    exec 4>&3
+    printf '12' >&3
+    printf '34' >&4
+    printf '56' >&3
+    exec 3>&- 4>&-
+    expect_dup "interleaved writes" 123456 "$(cat $file)"
+
+    rm -f $file
+    echo "dup: $dup_pass passed, $dup_fail failed"
+    echo "#### OS COMP TEST GROUP END dup ####"
+}
@@ -0,0 +1,83 @@
+# Error-code conformance matrix.
+#
+# Every case runs a command that is expected to fail and checks that the
+# reported error matches the strerror() text of the errno Linux returns in
+# the same situation, as spelled by musl (busybox is linked against it). Only
+# busybox is required, so this runs before any of the libc-specific suites.
+
+errno_pass=0
+errno_fail=0
+
+# expect_errno <errno name> <strerror text> <command...>
+expect_errno() {
+    name=$1
+    text=$2
+    shift 2
+    out=$("$@" 2>&1 </dev/null)
+    status=$?
+    if [ $status -ne 0 ] && echo "$out" | grep -q -F "$text"; then
+        errno_pass=$((errno_pass + 1))
+        echo "ERRNO PASS $name: $*"
+    else
+        errno_fail=$((errno_fail + 1))
+        echo "ERRNO FAIL $name: $* (status $status): $out"
+    fi
+}
+
+run_errno() {
+    echo "#### OS COMP TEST GROUP START errno ####"
+
+    base=/tmp/errno.$$
+    rm -rf $base
+    mkdir -p $base/dir/sub
+    echo data >$base/file
+    ln -s loop2 $base/loop1
+    ln -s loop1 $base/loop2
+    long=$(printf '%0300d' 0)
+
+    # fs: path resolution
+    expect_errno ENOENT "No such file or directory" cat $base/missing
+    expect_errno ENOENT "No such file or directory" ls $base/missing/child
+    expect_errno ENOTDIR "Not a directory" cat $base/file/child
+    expect_errno ENOTDIR "Not a directory" ls $base/file/
+    expect_errno ELOOP "Symbolic link loop" cat $base/loop1
+    expect_errno ENAMETOOLONG "Filename too long" touch $base/$long
+
+    # fs: namespace operations
+    expect_errno EEXIST "File exists" mkdir $base/dir
+    expect_errno EEXIST "File exists" ln -s $base/file $base/file
+    expect_errno ENOTEMPTY "Directory not empty" rmdir $base/dir
+    expect_errno ENOTDIR "Not a directory" rmdir $base/file
+    expect_errno ENOENT "No such file or directory" rmdir $base/missing
+    expect_errno ENOENT "No such file or directory" mv $base/missing $base/other
+    expect_errno EINVAL "Invalid argument" mv $base/dir $base/dir/sub/inner
+
+    # fs: file I/O
+    expect_errno EISDIR "Is a directory" cat $base/dir
+    expect_errno EISDIR "Is a directory" sh -c "echo x >$base/dir"
+    expect_errno EISDIR "Is a directory" truncate -s 0 $base/dir
+    expect_errno EBADF "Bad file descriptor" sh -c "cat <&9"
+    expect_errno EBADF "Bad file descriptor" sh -c "echo x >&9"
+
+    # fs: chdir
+    expect_errno ENOTDIR "Not a directory" sh -c "cd $base/file"
+    expect_errno ENOENT "No such file or directory" sh -c "cd $base/missing"
+
+    # devices and ioctl
+    expect_errno ENOTTY "Not a tty" stty -F $base/file
+    expect_errno ENOTTY "Not a tty" stty -F /dev/null
+    expect_errno ENOSPC "No space left on device" sh -c "echo x >/dev/full"
+
+    # exec
+    chmod -x $base/file
+    expect_errno EACCES "Permission denied" $base/file
+    expect_errno ENOENT "not found" $base/missing
+
+    # process and signals
+    expect_errno ESRCH "No such process" kill -0 99999
+    expect_errno ESRCH "No such process" kill -TERM -99999
+
+    rm -rf $base
+    echo "errno matrix: $errno_pass passed, $errno_fail failed"
+    echo "#### OS COMP TEST GROUP END errno ####"
+}
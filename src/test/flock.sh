@@ -0,0 +1,63 @@
+# flock(2) semantics.
+#
+# Locks belong to the open file description, so a child forked with the
+# descriptor holds the same lock as its parent, and the lock goes away only
+# when the last descriptor referring to it is closed.
+
+flock_pass=0
+flock_fail=0
+
+# expect_flock <name> <expected status> <command...>
+expect_flock() {
+    name=$1
+    expected=$2
+    shift 2
+    "$@" </dev/null >/dev/null 2>&1
+    status=$?
+    if [ $status -eq $expected ]; then
+        flock_pass=$((flock_pass + 1))
+        echo "FLOCK PASS $name"
+    else
+        flock_fail=$((flock_fail + 1))
+        echo "FLOCK FAIL $name (status $status, expected $expected)"
+    fi
+}
+
+run_flock() {
+    echo "#### OS COMP TEST GROUP START flock ####"
+
+    lock=/tmp/flock.$$
+    rm -f $lock
+    touch $lock
+
+    # A lock taken by a child outlives it while the parent keeps the file
+    # description open.
+    exec 9>$lock
+    (flock -x 9)
+    expect_flock "held after child exits" 1 flock -n -x $lock -c true
+    expect_flock "same description relocks" 0 flock -n -x 9
+    (flock -u 9)
+    expect_flock "child unlocks for parent" 0 flock -n -x $lock -c true
+    exec 9>&-
+
+    # A lock held across fork is released with the last descriptor.
+    exec 9>$lock
+    flock -x 9
+    sleep 1 &
+    child=$!
+    exec 9>&-
+    expect_flock "held by forked child" 1 flock -n -x $lock -c true
+    wait $child
+    expect_flock "released on last close" 0 flock -n -x $lock -c true
+
+    # Shared locks coexist, but exclude exclusive ones.
+    exec 9>$lock
+    flock -s 9
+    expect_flock "shared with shared" 0 flock -n -s $lock -c true
+    expect_flock "shared excludes exclusive" 1 flock -n -x $lock -c true
+    exec 9>&-
+
+    rm -f $lock
+    echo "flock: $flock_pass passed, $flock_fail failed"
+    echo "#### OS COMP TEST GROUP END flock ####"
+}
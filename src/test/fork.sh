@@ -0,0 +1,56 @@
+# Fork resilience.
+#
+# With the fork stress mode of /proc/sys/vm/fork_stress on, 10000 rapid forks
+# must all be accounted in /proc/vmstat, and the memory they took must come
+# back once the children are reaped.
+
+fork_pass=0
+fork_fail=0
+
+# vmstat <key>
+vmstat() {
+    grep "^$1 " /proc/vmstat | cut -d' ' -f2
+}
+
+# expect_fork <name> <condition...>
+expect_fork() {
+    name=$1
+    shift
+    if [ "$@" ]; then
+        fork_pass=$((fork_pass + 1))
+        echo "FORK PASS $name"
+    else
+        fork_fail=$((fork_fail + 1))
+        echo "FORK FAIL $name ($*)"
+    fi
+}
+
+run_fork() {
+    echo "#### OS COMP TEST GROUP START fork ####"
+
+    echo 1 >/proc/sys/vm/fork_stress
+    free_before=$(vmstat nr_free_pages)
+
+    # Forks in batches of 100, so that there are never more children than
+    # the task limit allows.
+    i=0
+    while [ $i -lt 100 ]; do
+        j=0
+        while [ $j -lt 100 ]; do
+            (exit 0) &
+            j=$((j + 1))
+        done
+        wait
+        i=$((i + 1))
+    done
+
+    free_after=$(vmstat nr_free_pages)
+    expect_fork "all forks counted" "$(vmstat nr_forks)" -ge 10000
+    expect_fork "fork latency measured" "$(vmstat fork_latency_avg_ns)" -gt 0
+    # Allow for caches filled meanwhile, but not for leaked children.
+    expect_fork "memory reclaimed" $((free_after * 10)) -ge $((free_before * 9))
+    echo 0 >/proc/sys/vm/fork_stress
+
+    echo "fork: $fork_pass passed, $fork_fail failed"
+    echo "#### OS COMP TEST GROUP END fork ####"
+}
@@ -2,7 +2,18 @@
 
 cfg_if::cfg_if! {
     if #[cfg(test = "pre")] {
-        pub const CMDLINE: &[&str] = &["/musl/busybox", "sh", "-c", include_str!("pre.sh")];
+        pub const CMDLINE: &[&str] = &[
+            "/musl/busybox",
+            "sh",
+            "-c",
+            concat!(
+                include_str!("errno.sh"),
+                include_str!("flock.sh"),
+                include_str!("dup.sh"),
+                include_str!("fork.sh"),
+                include_str!("pre.sh")
+            ),
+        ];
     } else if #[cfg(test = "final")] {
         pub const CMDLINE: &[&str] = &["/musl/busybox", "sh", "-c", include_str!("final.sh")];
     } else if #[cfg(test = "on-site")] {
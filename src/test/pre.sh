@@ -41,6 +41,11 @@ echo @@@@@@@@@@ env @@@@@@@@@@
 env
 echo
 
+run_errno
+run_flock
+run_dup
+run_fork
+
 run_ltp() {
     echo "#### OS COMP TEST GROUP START ltp-$1 ####"
 